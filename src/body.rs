@@ -14,17 +14,23 @@
 //!  and returned by hyper as a "receive stream" (so, for server requests and
 //!  client responses). It is also a decent default implementation if you don't
 //!  have very custom needs of your send streams.
+use std::any::Any;
 use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures::{Async, Future, Poll, Stream};
 use futures::sync::{mpsc, oneshot};
-use h2;
+use futures::task::{self, AtomicTask, Task};
+#[cfg(feature = "http2")] use h2;
 use http::HeaderMap;
 
 use common::Never;
 pub use chunk::Chunk;
+#[cfg(feature = "mmap")] pub use mmap::{MmapBody, MmapChunk};
 
 use self::internal::{FullDataArg, FullDataRet};
 
@@ -84,6 +90,36 @@ pub trait Payload: Send + 'static {
         None
     }
 
+    /// Return the HTTP/1 chunk extensions to send with a `Data` buffer
+    /// from `poll_data`.
+    ///
+    /// When the body is sent with chunked transfer-encoding, a buffer with
+    /// extensions is written as its own chunk, with them after its size,
+    /// such as `5;name=value`, and an empty buffer with extensions gives
+    /// the ones of the last chunk, that ends the body. Otherwise, they
+    /// aren't sent.
+    ///
+    /// `Body` returns the ones set with
+    /// [`Chunk::with_extensions`](::Chunk::with_extensions).
+    fn chunk_extensions(&self, data: &Self::Data) -> Option<Bytes> {
+        let _ = data;
+        None
+    }
+
+    /// Return a new body that yields all the same data, if this body can be
+    /// sent again.
+    ///
+    /// This is called before the body is polled, and allows the `Client` to
+    /// send a request again, after it was written to a connection that then
+    /// closed without responding. Bodies that can only be streamed once
+    /// return `None`, and their requests aren't sent again.
+    fn replay(&self) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
     // This API is unstable, and is impossible to use outside of hyper. Some
     // form of it may become stable in a later version.
     //
@@ -111,9 +147,17 @@ impl<E: Payload> Payload for Box<E> {
         (**self).is_end_stream()
     }
 
+    fn replay(&self) -> Option<Self> {
+        (**self).replay().map(Box::new)
+    }
+
     fn content_length(&self) -> Option<u64> {
         (**self).content_length()
     }
+
+    fn chunk_extensions(&self, data: &Self::Data) -> Option<Bytes> {
+        (**self).chunk_extensions(data)
+    }
 }
 
 
@@ -134,6 +178,17 @@ pub struct Body {
     /// a brand new connection, since the pool didn't know about the idle
     /// connection yet.
     delayed_eof: Option<DelayEof>,
+    /// Held on to until the body ends or is dropped, such as the `Client`'s
+    /// count of requests in flight.
+    eof_guard: Option<Box<Any + Send>>,
+    /// Split or coalesce received chunks, if configured on the connection.
+    rechunk: Option<Box<Rechunk>>,
+    /// The most bytes this body may yield, and how many it yielded so far.
+    size_limit: Option<(u64, u64)>,
+    /// Hands the stream back to the HTTP/2 connection, if the body is
+    /// rejected.
+    #[cfg(feature = "http2")]
+    on_reject: Option<oneshot::Sender<h2::RecvStream>>,
 }
 
 enum Kind {
@@ -141,15 +196,21 @@ enum Kind {
     Chan {
         _close_tx: oneshot::Sender<()>,
         rx: mpsc::Receiver<Result<Chunk, ::Error>>,
+        buffered: Option<Arc<Buffered>>,
     },
+    #[cfg(feature = "http2")]
+    #[cfg_attr(not(any(feature = "client", feature = "server")), allow(dead_code))]
     H2(h2::RecvStream),
     Wrapped(Box<Stream<Item=Chunk, Error=Box<::std::error::Error + Send + Sync>> + Send>),
+    Tee(Tee),
+    Trailers(Option<HeaderMap>),
 }
 
 type DelayEofUntil = oneshot::Receiver<Never>;
 
 enum DelayEof {
     /// Initial state, stream hasn't seen EOF yet.
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
     NotEof(DelayEofUntil),
     /// Transitions to this state once we've seen `poll` try to
     /// return EOF (`None`). This future is then polled, and
@@ -157,6 +218,95 @@ enum DelayEof {
     Eof(DelayEofUntil),
 }
 
+/// Limits on the size of the chunks yielded by a received `Body`.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ChunkSize {
+    pub(crate) max: Option<usize>,
+    pub(crate) min: Option<usize>,
+}
+
+/// Limits on how many bytes a received `Body` may hold, that weren't polled
+/// by the application yet.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct BufferLimit {
+    /// Larger chunks are split, so that no more than `max` bytes are held.
+    pub(crate) max: Option<usize>,
+    /// The `Sender` isn't ready for more once `stop_at` bytes are held.
+    pub(crate) stop_at: Option<usize>,
+}
+
+impl BufferLimit {
+    fn is_limited(&self) -> bool {
+        self.max.is_some() || self.stop_at.is_some()
+    }
+
+    fn stop_at(&self) -> usize {
+        match (self.max, self.stop_at) {
+            (Some(max), Some(stop_at)) => ::std::cmp::min(max, stop_at),
+            (Some(n), None) | (None, Some(n)) => n,
+            (None, None) => usize::max_value(),
+        }
+    }
+}
+
+// The bytes held by a limited channel, shared by its `Sender` and `Body`.
+#[derive(Debug)]
+struct Buffered {
+    bytes: AtomicUsize,
+    // The task of the `Sender`, waiting for bytes to be polled.
+    tx_task: AtomicTask,
+}
+
+struct Rechunk {
+    size: ChunkSize,
+    buf: Bytes,
+    eof: bool,
+}
+
+/// How far apart the halves of `Body::tee` may get by default.
+const DEFAULT_TEE_MAX_SKEW: usize = 64 * 1024;
+
+/// What the halves of a [`Body::tee_with`](Body::tee_with) do when one of
+/// them falls too far behind the other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TeePolicy {
+    /// The faster half waits for the slower one to catch up, so the slowest
+    /// reader applies backpressure to the body.
+    Wait,
+    /// The slower half is dropped: the chunks it hasn't read are discarded
+    /// and it yields an error, while the faster half carries on alone.
+    DropSlowest,
+    /// Both halves yield an error.
+    Error,
+}
+
+// One half of a teed body.
+struct Tee {
+    shared: Arc<Mutex<TeeShared>>,
+    side: usize,
+}
+
+// Whichever half is polled while its queue is empty polls the source, and
+// queues a copy of the chunk for the other half.
+struct TeeShared {
+    source: Body,
+    max_skew: usize,
+    policy: TeePolicy,
+    halves: [TeeHalf; 2],
+    // Once the source ends, the description of the error it ended with, if
+    // any, since only the half that polled it gets the error itself.
+    ended: Option<Option<String>>,
+}
+
+#[derive(Default)]
+struct TeeHalf {
+    queue: VecDeque<Chunk>,
+    queued: usize,
+    task: Option<Task>,
+    closed: bool,
+    failed: Option<&'static str>,
+}
+
 /// A sender half used with `Body::channel()`.
 ///
 /// Useful when wanting to stream chunks from another thread. See
@@ -166,6 +316,8 @@ enum DelayEof {
 pub struct Sender {
     close_rx: oneshot::Receiver<()>,
     tx: BodySender,
+    buffered: Option<Arc<Buffered>>,
+    limit: BufferLimit,
 }
 
 impl Body {
@@ -189,16 +341,36 @@ impl Body {
     /// Useful when wanting to stream chunks from another thread.
     #[inline]
     pub fn channel() -> (Sender, Body) {
-        let (tx, rx) = mpsc::channel(0);
+        Body::channel_with_limit(BufferLimit::default())
+    }
+
+    /// Create a channel that holds up to `limit` bytes, instead of a single
+    /// chunk.
+    pub(crate) fn channel_with_limit(limit: BufferLimit) -> (Sender, Body) {
+        let (buffered, cap) = if limit.is_limited() {
+            let buffered = Arc::new(Buffered {
+                bytes: AtomicUsize::new(0),
+                tx_task: AtomicTask::new(),
+            });
+            // Chunks are never empty, so the byte limit is reached before
+            // the channel runs out of slots.
+            (Some(buffered), limit.stop_at())
+        } else {
+            (None, 0)
+        };
+        let (tx, rx) = mpsc::channel(cap);
         let (close_tx, close_rx) = oneshot::channel();
 
         let tx = Sender {
             close_rx: close_rx,
             tx: tx,
+            buffered: buffered.clone(),
+            limit: limit,
         };
         let rx = Body::new(Kind::Chan {
             _close_tx: close_tx,
             rx: rx,
+            buffered: buffered,
         });
 
         (tx, rx)
@@ -236,17 +408,161 @@ impl Body {
         Body::new(Kind::Wrapped(Box::new(mapped)))
     }
 
+    /// Create a `Body` of no data, ended by the given trailers.
+    ///
+    /// Over HTTP/2, the message is sent as its HEADERS, and then the
+    /// trailers in a HEADERS frame ending the stream, without any DATA
+    /// frames. This is how a gRPC client ends a stream it has nothing left
+    /// to send on, or a server responds with just a status.
+    ///
+    /// HTTP/1 connections don't send trailers, so there the body is
+    /// empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate hyper;
+    /// # use hyper::{Body, HeaderMap};
+    /// # fn main() {
+    /// let mut trailers = HeaderMap::new();
+    /// trailers.insert("grpc-status", "0".parse().unwrap());
+    ///
+    /// let body = Body::trailers_only(trailers);
+    /// # }
+    /// ```
+    pub fn trailers_only(trailers: HeaderMap) -> Body {
+        Body::new(Kind::Trailers(Some(trailers)))
+    }
+
+    /// Split this body into two, each yielding all of its chunks.
+    ///
+    /// The halves can be read at different speeds, such as by a proxy
+    /// serving a response while storing it in a cache. The faster half
+    /// waits once it is 64KB ahead of the slower one, so the body is never
+    /// read faster than its slowest reader. Dropping a half lets the other
+    /// one carry on alone.
+    ///
+    /// Trailers aren't copied to the halves.
+    pub fn tee(self) -> (Body, Body) {
+        self.tee_with(DEFAULT_TEE_MAX_SKEW, TeePolicy::Wait)
+    }
+
+    /// Split this body into two, allowing one to get `max_skew` bytes ahead
+    /// of the other before applying `policy`.
+    ///
+    /// See [`Body::tee`](Body::tee) for more.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the passed `max_skew` is 0.
+    pub fn tee_with(self, max_skew: usize, policy: TeePolicy) -> (Body, Body) {
+        assert!(max_skew > 0, "the tee max_skew cannot be 0");
+        if let Some(replay) = self.replay() {
+            return (self, replay);
+        }
+        let shared = Arc::new(Mutex::new(TeeShared {
+            source: self,
+            max_skew: max_skew,
+            policy: policy,
+            halves: [TeeHalf::default(), TeeHalf::default()],
+            ended: None,
+        }));
+        let first = Body::new(Kind::Tee(Tee {
+            shared: shared.clone(),
+            side: 0,
+        }));
+        let second = Body::new(Kind::Tee(Tee {
+            shared: shared,
+            side: 1,
+        }));
+        (first, second)
+    }
+
     fn new(kind: Kind) -> Body {
         Body {
             kind: kind,
             delayed_eof: None,
+            eof_guard: None,
+            rechunk: None,
+            size_limit: None,
+            #[cfg(feature = "http2")]
+            on_reject: None,
+        }
+    }
+
+    #[cfg(all(feature = "http2", any(feature = "client", feature = "server")))]
+    pub(crate) fn h2(recv: h2::RecvStream, size: ChunkSize) -> Self {
+        let mut body = Body::new(Kind::H2(recv));
+        body.set_chunk_size(size);
+        body
+    }
+
+    /// Stop receiving this body, without reading the rest of it.
+    ///
+    /// This allows a server `Service` to answer a request, such as with a
+    /// `413 Payload Too Large`, without waiting for a possibly huge body to
+    /// be sent first.
+    ///
+    /// - On an HTTP/1 connection, no more of the body is read, and the
+    ///   response is sent with `Connection: close`, after which the
+    ///   connection is closed instead of being reused.
+    /// - On an HTTP/2 connection, the rest of the body is discarded as it
+    ///   arrives, so it doesn't use up the connection's flow control, and
+    ///   once the response is sent the stream is reset, telling the client
+    ///   to stop sending.
+    ///
+    /// Simply dropping the body works the same for HTTP/1, but leaves an
+    /// HTTP/2 stream holding on to the data it receives until the response
+    /// ends. Holding on to the body without reading it, such as by moving
+    /// it into the response future, makes the connection wait for it to be
+    /// read instead, which may leave the client hanging.
+    pub fn reject(self) {
+        trace!("body rejected");
+        self.hand_back();
+    }
+
+    #[cfg(feature = "http2")]
+    fn hand_back(mut self) {
+        if let Some(tx) = self.on_reject.take() {
+            if let Kind::H2(recv) = ::std::mem::replace(&mut self.kind, Kind::Once(None)) {
+                let _ = tx.send(recv);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "http2"))]
+    fn hand_back(self) {}
+
+    /// Send the stream to `tx` if the body is rejected.
+    #[cfg(all(feature = "http2", feature = "server"))]
+    pub(crate) fn on_reject(&mut self, tx: oneshot::Sender<h2::RecvStream>) {
+        self.on_reject = Some(tx);
+    }
+
+    #[cfg(any(feature = "client", feature = "server"))]
+    pub(crate) fn set_chunk_size(&mut self, size: ChunkSize) {
+        if size.max.is_none() && size.min.is_none() {
+            return;
         }
+        self.rechunk = Some(Box::new(Rechunk {
+            size,
+            buf: Bytes::new(),
+            eof: false,
+        }));
     }
 
-    pub(crate) fn h2(recv: h2::RecvStream) -> Self {
-        Body::new(Kind::H2(recv))
+    /// Error the body once it yields more than `max` bytes.
+    #[cfg(feature = "client")]
+    pub(crate) fn set_max_size(&mut self, max: u64) {
+        self.size_limit = Some((max, 0));
     }
 
+    #[cfg(feature = "client")]
+    pub(crate) fn release_on_eof<T: Send + 'static>(&mut self, guard: T) {
+        self.eof_guard = Some(Box::new(guard));
+    }
+
+    #[cfg(feature = "client")]
     pub(crate) fn delayed_eof(&mut self, fut: DelayEofUntil) {
         self.delayed_eof = Some(DelayEof::NotEof(fut));
     }
@@ -289,15 +605,53 @@ impl Body {
         }
     }
 
+    fn poll_rechunk(&mut self) -> Poll<Option<Chunk>, ::Error> {
+        let mut rechunk = match self.rechunk.take() {
+            Some(rechunk) => rechunk,
+            None => return self.poll_eof(),
+        };
+        let ret = rechunk.poll(|| self.poll_eof());
+        self.rechunk = Some(rechunk);
+        ret
+    }
+
+    fn poll_limited(&mut self) -> Poll<Option<Chunk>, ::Error> {
+        let ret = self.poll_rechunk();
+        let exceeded = match (self.size_limit.as_mut(), &ret) {
+            (Some(&mut (max, ref mut seen)), &Ok(Async::Ready(Some(ref chunk)))) => {
+                *seen += chunk.len() as u64;
+                *seen > max
+            },
+            _ => false,
+        };
+        if exceeded {
+            debug!("body exceeded its maximum size");
+            // Dropping the stream closes the HTTP/1 connection, or resets
+            // the HTTP/2 stream, instead of reading the rest.
+            self.kind = Kind::Once(None);
+            self.delayed_eof = None;
+            self.rechunk = None;
+            return Err(::Error::new_body_too_large());
+        }
+        ret
+    }
+
     fn poll_inner(&mut self) -> Poll<Option<Chunk>, ::Error> {
         match self.kind {
             Kind::Once(ref mut val) => Ok(Async::Ready(val.take())),
-            Kind::Chan { ref mut rx, .. } => match rx.poll().expect("mpsc cannot error") {
-                Async::Ready(Some(Ok(chunk))) => Ok(Async::Ready(Some(chunk))),
+            Kind::Chan { ref mut rx, ref buffered, .. } => match rx.poll().expect("mpsc cannot error") {
+                Async::Ready(Some(Ok(chunk))) => {
+                    if let Some(ref buffered) = *buffered {
+                        buffered.bytes.fetch_sub(chunk.len(), Ordering::SeqCst);
+                        buffered.tx_task.notify();
+                    }
+                    Ok(Async::Ready(Some(chunk)))
+                },
                 Async::Ready(Some(Err(err))) => Err(err),
                 Async::Ready(None) => Ok(Async::Ready(None)),
                 Async::NotReady => Ok(Async::NotReady),
             },
+            #[cfg(feature = "http2")]
             Kind::H2(ref mut h2) => {
                 h2.poll()
                     .map(|async| {
@@ -311,6 +665,8 @@ impl Body {
                     .map_err(::Error::new_body)
             },
             Kind::Wrapped(ref mut s) => s.poll().map_err(::Error::new_body),
+            Kind::Tee(ref mut tee) => tee.poll(),
+            Kind::Trailers(..) => Ok(Async::Ready(None)),
         }
     }
 }
@@ -328,22 +684,37 @@ impl Payload for Body {
     type Error = ::Error;
 
     fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
-        self.poll_eof()
+        let ret = self.poll_limited();
+        match ret {
+            Ok(Async::Ready(None)) | Err(_) => self.eof_guard = None,
+            _ => (),
+        }
+        ret
     }
 
     fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
         match self.kind {
+            #[cfg(feature = "http2")]
             Kind::H2(ref mut h2) => h2.poll_trailers().map_err(::Error::new_h2),
+            Kind::Trailers(ref mut trailers) => Ok(Async::Ready(trailers.take())),
             _ => Ok(Async::Ready(None)),
         }
     }
 
     fn is_end_stream(&self) -> bool {
+        if let Some(ref rechunk) = self.rechunk {
+            if !rechunk.buf.is_empty() {
+                return false;
+            }
+        }
         match self.kind {
             Kind::Once(ref val) => val.is_none(),
             Kind::Chan { .. } => false,
+            #[cfg(feature = "http2")]
             Kind::H2(ref h2) => h2.is_end_stream(),
             Kind::Wrapped(..) => false,
+            Kind::Tee(..) => false,
+            Kind::Trailers(ref trailers) => trailers.is_none(),
         }
     }
 
@@ -352,8 +723,28 @@ impl Payload for Body {
             Kind::Once(Some(ref val)) => Some(val.len() as u64),
             Kind::Once(None) => Some(0),
             Kind::Chan { .. } => None,
+            #[cfg(feature = "http2")]
+            Kind::H2(..) => None,
+            Kind::Wrapped(..) => None,
+            Kind::Tee(..) => None,
+            Kind::Trailers(..) => None,
+        }
+    }
+
+    #[inline]
+    fn chunk_extensions(&self, data: &Chunk) -> Option<Bytes> {
+        data.extensions_bytes()
+    }
+
+    fn replay(&self) -> Option<Body> {
+        match self.kind {
+            Kind::Once(ref val) => Some(Body::new(Kind::Once(val.as_ref().map(Chunk::share)))),
+            Kind::Chan { .. } => None,
+            #[cfg(feature = "http2")]
             Kind::H2(..) => None,
             Kind::Wrapped(..) => None,
+            Kind::Tee(..) => None,
+            Kind::Trailers(ref trailers) => Some(Body::new(Kind::Trailers(trailers.clone()))),
         }
     }
 
@@ -382,6 +773,148 @@ impl fmt::Debug for Body {
     }
 }
 
+impl Rechunk {
+    fn poll<F>(&mut self, mut poll_chunk: F) -> Poll<Option<Chunk>, ::Error>
+    where
+        F: FnMut() -> Poll<Option<Chunk>, ::Error>,
+    {
+        let max = self.size.max.unwrap_or(::std::usize::MAX);
+        let min = self.size.min.unwrap_or(0);
+        loop {
+            if self.buf.len() > max {
+                return Ok(Async::Ready(Some(Chunk::from(self.buf.split_to(max)))));
+            }
+            let is_full = !self.buf.is_empty() && self.buf.len() >= min;
+            if is_full || self.eof {
+                if self.buf.is_empty() {
+                    return Ok(Async::Ready(None));
+                }
+                let buf = ::std::mem::replace(&mut self.buf, Bytes::new());
+                return Ok(Async::Ready(Some(Chunk::from(buf))));
+            }
+
+            match try_ready!(poll_chunk()) {
+                Some(chunk) => {
+                    if self.buf.is_empty() {
+                        self.buf = chunk.into_bytes();
+                    } else {
+                        let mut buf = BytesMut::with_capacity(self.buf.len() + chunk.len());
+                        buf.put_slice(&self.buf);
+                        buf.put_slice(&chunk);
+                        self.buf = buf.freeze();
+                    }
+                },
+                None => self.eof = true,
+            }
+        }
+    }
+}
+
+impl Tee {
+    fn poll(&mut self) -> Poll<Option<Chunk>, ::Error> {
+        let mut shared = self.shared.lock().expect("tee lock");
+        shared.poll_half(self.side)
+    }
+}
+
+impl Drop for Tee {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.halves[self.side].close();
+            shared.halves[1 - self.side].notify();
+        }
+    }
+}
+
+impl TeeShared {
+    fn poll_half(&mut self, side: usize) -> Poll<Option<Chunk>, ::Error> {
+        let other = 1 - side;
+        if let Some(reason) = self.halves[side].failed {
+            return Err(::Error::new_body(reason));
+        }
+        if let Some(chunk) = self.halves[side].queue.pop_front() {
+            self.halves[side].queued -= chunk.len();
+            self.halves[other].notify();
+            return Ok(Async::Ready(Some(chunk)));
+        }
+        match self.ended {
+            Some(None) => return Ok(Async::Ready(None)),
+            Some(Some(ref reason)) => return Err(::Error::new_body(reason.clone())),
+            None => (),
+        }
+
+        if !self.halves[other].closed && self.halves[other].queued >= self.max_skew {
+            match self.policy {
+                TeePolicy::Wait => {
+                    trace!("body tee waiting for slower half");
+                    self.halves[side].task = Some(task::current());
+                    return Ok(Async::NotReady);
+                },
+                TeePolicy::DropSlowest => {
+                    debug!("body tee dropping slower half");
+                    self.halves[other].fail("body tee half fell behind");
+                },
+                TeePolicy::Error => {
+                    debug!("body tee halves too far apart");
+                    self.halves[side].fail("body tee halves too far apart");
+                    self.halves[other].fail("body tee halves too far apart");
+                    return Err(::Error::new_body("body tee halves too far apart"));
+                },
+            }
+        }
+
+        match self.source.poll_data() {
+            Ok(Async::Ready(Some(chunk))) => {
+                self.halves[other].push(chunk.share());
+                Ok(Async::Ready(Some(chunk)))
+            },
+            Ok(Async::Ready(None)) => {
+                self.ended = Some(None);
+                self.halves[other].notify();
+                Ok(Async::Ready(None))
+            },
+            Ok(Async::NotReady) => {
+                self.halves[side].task = Some(task::current());
+                Ok(Async::NotReady)
+            },
+            Err(err) => {
+                self.ended = Some(Some(err.to_string()));
+                self.halves[other].notify();
+                Err(err)
+            },
+        }
+    }
+}
+
+impl TeeHalf {
+    fn push(&mut self, chunk: Chunk) {
+        if self.closed || self.failed.is_some() {
+            return;
+        }
+        self.queued += chunk.len();
+        self.queue.push_back(chunk);
+        self.notify();
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        self.queue.clear();
+        self.queued = 0;
+    }
+
+    fn fail(&mut self, reason: &'static str) {
+        self.failed = Some(reason);
+        self.close();
+        self.notify();
+    }
+
+    fn notify(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.notify();
+        }
+    }
+}
+
 impl Sender {
     /// Check to see if this `Sender` can send more data.
     pub fn poll_ready(&mut self) -> Poll<(), ::Error> {
@@ -390,6 +923,13 @@ impl Sender {
             Ok(Async::NotReady) => (),
         }
 
+        if let Some(ref buffered) = self.buffered {
+            buffered.tx_task.register();
+            if buffered.bytes.load(Ordering::SeqCst) >= self.limit.stop_at() {
+                return Ok(Async::NotReady);
+            }
+        }
+
         self.tx.poll_ready().map_err(|_| ::Error::new_closed())
     }
 
@@ -401,10 +941,33 @@ impl Sender {
     /// Returns `Err(Chunk)` if the channel could not (currently) accept
     /// another `Chunk`.
     pub fn send_data(&mut self, chunk: Chunk) -> Result<(), Chunk> {
+        // Counted before sending, so the `Body` never takes away more than
+        // was added.
+        let len = chunk.len();
+        if let Some(ref buffered) = self.buffered {
+            buffered.bytes.fetch_add(len, Ordering::SeqCst);
+        }
         self.tx.try_send(Ok(chunk))
-            .map_err(|err| err.into_inner().expect("just sent Ok"))
+            .map_err(|err| {
+                if let Some(ref buffered) = self.buffered {
+                    buffered.bytes.fetch_sub(len, Ordering::SeqCst);
+                }
+                err.into_inner().expect("just sent Ok")
+            })
+    }
+
+    /// How many more bytes may be sent, if the channel has a maximum.
+    #[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
+    pub(crate) fn room(&self) -> Option<usize> {
+        match (self.limit.max, self.buffered.as_ref()) {
+            (Some(max), Some(buffered)) => {
+                Some(max.saturating_sub(buffered.bytes.load(Ordering::SeqCst)))
+            },
+            _ => None,
+        }
     }
 
+    #[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
     pub(crate) fn send_error(&mut self, err: ::Error) {
         let _ = self.tx.try_send(Err(err));
     }
@@ -515,6 +1078,68 @@ fn _assert_send_sync() {
     _assert_sync::<Chunk>();
 }
 
+#[cfg(any(feature = "client", feature = "server"))]
+#[test]
+fn test_body_rechunk() {
+    use futures::{Stream, Future};
+
+    fn collect(chunks: Vec<&'static str>, max: Option<usize>, min: Option<usize>) -> Vec<String> {
+        let mut body = Body::wrap_stream(::futures::stream::iter_ok::<_, ::Error>(chunks));
+        body.set_chunk_size(ChunkSize { max, min });
+        body
+            .map(|chunk| String::from_utf8(chunk.to_vec()).unwrap())
+            .collect()
+            .wait()
+            .unwrap()
+    }
+
+    assert_eq!(collect(vec!["hello", " world"], Some(4), None), vec!["hell", "o", " wor", "ld"]);
+    assert_eq!(collect(vec!["he", "llo", " ", "world"], None, Some(4)), vec!["hello", " world"]);
+    assert_eq!(collect(vec!["he", "llo", " ", "wor", "ld"], Some(4), Some(3)), vec!["hell", "o wo", "rld"]);
+}
+
+#[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
+#[test]
+fn test_body_channel_with_limit() {
+    use futures::{future, Future};
+
+    future::lazy(|| {
+        let (mut tx, mut body) = Body::channel_with_limit(BufferLimit {
+            max: Some(8),
+            stop_at: Some(6),
+        });
+
+        assert!(tx.poll_ready().unwrap().is_ready());
+        assert_eq!(tx.room(), Some(8));
+        tx.send_data(Chunk::from("hello")).unwrap();
+        assert!(tx.poll_ready().unwrap().is_ready());
+        assert_eq!(tx.room(), Some(3));
+        tx.send_data(Chunk::from("!")).unwrap();
+        assert!(tx.poll_ready().unwrap().is_not_ready(), "threshold reached");
+
+        match body.poll_data() {
+            Ok(Async::Ready(Some(chunk))) => assert_eq!(chunk.as_ref(), b"hello"),
+            other => panic!("unexpected poll: {:?}", other),
+        }
+        assert!(tx.poll_ready().unwrap().is_ready());
+        assert_eq!(tx.room(), Some(7));
+        Ok::<(), ()>(())
+    }).wait().unwrap();
+}
+
+#[test]
+fn test_body_replay() {
+    use futures::{Stream, Future};
+
+    let body = Body::from("hello");
+    let replay = body.replay().expect("once body is replayable");
+    assert_eq!(body.concat2().wait().unwrap().as_ref(), b"hello");
+    assert_eq!(replay.concat2().wait().unwrap().as_ref(), b"hello");
+
+    assert!(Body::empty().replay().is_some());
+    assert!(Body::channel().1.replay().is_none());
+}
+
 #[test]
 fn test_body_stream_concat() {
     use futures::{Stream, Future};
@@ -528,3 +1153,99 @@ fn test_body_stream_concat() {
     assert_eq!(total.as_ref(), b"hello world");
 }
 
+
+#[test]
+fn test_body_tee() {
+    use futures::{Stream, Future};
+
+    let body = Body::wrap_stream(::futures::stream::iter_ok::<_, ::Error>(vec!["hello", " ", "world"]));
+    let (first, second) = body.tee();
+    assert_eq!(first.concat2().wait().unwrap().as_ref(), b"hello world");
+    assert_eq!(second.concat2().wait().unwrap().as_ref(), b"hello world");
+
+    let (first, second) = Body::from("hello").tee();
+    assert_eq!(first.concat2().wait().unwrap().as_ref(), b"hello");
+    assert_eq!(second.concat2().wait().unwrap().as_ref(), b"hello");
+}
+
+#[test]
+fn test_body_tee_skew() {
+    use futures::{future, Future};
+
+    fn poll_str(body: &mut Body) -> Poll<Option<String>, ::Error> {
+        body.poll_data().map(|async| async.map(|opt| opt.map(|chunk| {
+            String::from_utf8(chunk.to_vec()).unwrap()
+        })))
+    }
+
+    future::lazy(|| {
+        let (mut tx, body) = Body::channel();
+        let (mut first, mut second) = body.tee_with(4, TeePolicy::Wait);
+
+        tx.send_data(Chunk::from("hello")).unwrap();
+        assert_eq!(poll_str(&mut first).unwrap(), Async::Ready(Some("hello".to_owned())));
+        tx.send_data(Chunk::from("world")).unwrap();
+        assert!(poll_str(&mut first).unwrap().is_not_ready(), "second is behind");
+        assert_eq!(poll_str(&mut second).unwrap(), Async::Ready(Some("hello".to_owned())));
+        assert_eq!(poll_str(&mut first).unwrap(), Async::Ready(Some("world".to_owned())));
+
+        // Dropping the slower half lets the other one carry on.
+        drop(second);
+        tx.send_data(Chunk::from("again")).unwrap();
+        assert_eq!(poll_str(&mut first).unwrap(), Async::Ready(Some("again".to_owned())));
+        Ok::<(), ()>(())
+    }).wait().unwrap();
+
+    future::lazy(|| {
+        let (mut tx, body) = Body::channel();
+        let (mut first, mut second) = body.tee_with(4, TeePolicy::DropSlowest);
+
+        tx.send_data(Chunk::from("hello")).unwrap();
+        assert_eq!(poll_str(&mut first).unwrap(), Async::Ready(Some("hello".to_owned())));
+        tx.send_data(Chunk::from("world")).unwrap();
+        assert_eq!(poll_str(&mut first).unwrap(), Async::Ready(Some("world".to_owned())));
+        assert!(poll_str(&mut second).is_err(), "second was dropped");
+        Ok::<(), ()>(())
+    }).wait().unwrap();
+
+    future::lazy(|| {
+        let (mut tx, body) = Body::channel();
+        let (mut first, mut second) = body.tee_with(4, TeePolicy::Error);
+
+        tx.send_data(Chunk::from("hello")).unwrap();
+        assert_eq!(poll_str(&mut first).unwrap(), Async::Ready(Some("hello".to_owned())));
+        tx.send_data(Chunk::from("world")).unwrap();
+        assert!(poll_str(&mut first).is_err());
+        assert!(poll_str(&mut second).is_err());
+        Ok::<(), ()>(())
+    }).wait().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "max_skew cannot be 0")]
+fn test_body_tee_zero_skew() {
+    let (_tx, body) = Body::channel();
+    let _ = body.tee_with(0, TeePolicy::Wait);
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn test_body_max_size() {
+    use futures::{future, Future};
+
+    future::lazy(|| {
+        let (mut tx, mut body) = Body::channel();
+        body.set_max_size(8);
+
+        tx.send_data(Chunk::from("hello")).unwrap();
+        match body.poll_data().unwrap() {
+            Async::Ready(Some(chunk)) => assert_eq!(chunk.as_ref(), b"hello"),
+            other => panic!("unexpected poll_data: {:?}", other),
+        }
+        tx.send_data(Chunk::from("world")).unwrap();
+        let err = body.poll_data().unwrap_err();
+        assert!(err.is_body_too_large(), "{:?}", err);
+        assert!(tx.send_data(Chunk::from("again")).is_err(), "receiver dropped");
+        Ok::<(), ()>(())
+    }).wait().unwrap();
+}
@@ -11,6 +11,9 @@ use bytes::{Buf, Bytes};
 pub struct Chunk {
     /// The buffer of bytes making up this body.
     bytes: Bytes,
+    /// The HTTP/1 chunk extensions received or to send with the bytes,
+    /// boxed since hardly any `Chunk` has them.
+    extensions: Option<Box<Bytes>>,
 }
 
 // An unexported type to prevent locking `Chunk::into_iter()` to `Bytes::into_iter()`.
@@ -29,6 +32,71 @@ impl Chunk {
     pub fn into_bytes(self) -> Bytes {
         self.into()
     }
+
+    /// Set the chunk extensions to send with this `Chunk`.
+    ///
+    /// When a body is sent with HTTP/1 chunked transfer-encoding, a `Chunk`
+    /// with extensions is written as its own chunk, with `;` and then the
+    /// `extensions` as they are after its size, such as `5;name=value`.
+    /// Several extensions are separated with `;` as well. An empty `Chunk`
+    /// with extensions gives the ones of the last chunk, `0;name=value`,
+    /// that ends the body. Otherwise, they aren't sent.
+    ///
+    /// They are sent by [`Body`](::Body), other bodies give them with
+    /// [`Payload::chunk_extensions`](::body::Payload::chunk_extensions).
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `extensions` contains a CR or LF.
+    pub fn with_extensions<E: Into<Bytes>>(mut self, extensions: E) -> Chunk {
+        let extensions = extensions.into();
+        assert!(
+            !extensions.iter().any(|&b| b == b'\r' || b == b'\n'),
+            "chunk extensions cannot contain CR or LF"
+        );
+        self.extensions = Some(Box::new(extensions));
+        self
+    }
+
+    /// Get the chunk extensions of this `Chunk`.
+    ///
+    /// These are the ones set with `with_extensions`, or, on a connection
+    /// keeping the extensions of received chunks, the ones its HTTP/1 chunk
+    /// arrived with, after the first `;`. Only the first `Chunk` of a
+    /// received chunk has them, and the extensions of the last chunk come
+    /// in an empty `Chunk` at the end of the body.
+    pub fn extensions(&self) -> Option<&[u8]> {
+        self.extensions.as_ref().map(|ext| ext.as_ref().as_ref())
+    }
+
+    #[inline]
+    pub(crate) fn extensions_bytes(&self) -> Option<Bytes> {
+        self.extensions.as_ref().map(|ext| (**ext).clone())
+    }
+
+    // Received extensions were already checked by the decoder.
+    #[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
+    #[inline]
+    pub(crate) fn set_extensions(&mut self, extensions: Bytes) {
+        self.extensions = Some(Box::new(extensions));
+    }
+
+    /// Split off the bytes from `at` into another `Chunk`, leaving the
+    /// extensions with the first.
+    #[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
+    #[inline]
+    pub(crate) fn split_off(&mut self, at: usize) -> Chunk {
+        Chunk::from(self.bytes.split_off(at))
+    }
+
+    /// A copy of this `Chunk`, sharing the same buffer.
+    #[inline]
+    pub(crate) fn share(&self) -> Chunk {
+        Chunk {
+            bytes: self.bytes.clone(),
+            extensions: self.extensions.clone(),
+        }
+    }
 }
 
 impl Buf for Chunk {
@@ -81,6 +149,7 @@ impl From<Bytes> for Chunk {
     fn from(bytes: Bytes) -> Chunk {
         Chunk {
             bytes: bytes,
+            extensions: None,
         }
     }
 }
@@ -0,0 +1,144 @@
+//! A blocking Client API.
+//!
+//! The [`Client`](Client) in this module sends requests without the caller
+//! having to drive any futures. It starts its own runtime in the
+//! background, and blocks the current thread until a response is received.
+//!
+//! This is meant for programs that only send a few requests, such as
+//! command line tools or build scripts. Programs already using a runtime
+//! should use the asynchronous [`Client`](::Client) instead, since blocking
+//! a thread of the runtime can stall every other task on it.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # extern crate hyper;
+//! # fn run() -> hyper::Result<()> {
+//! use hyper::client::blocking::Client;
+//!
+//! let client = Client::new()?;
+//! let res = client.get("http://example.domain/hello")?;
+//! println!("status: {}", res.status());
+//! println!("body: {}", String::from_utf8_lossy(res.body()));
+//! # Ok(())
+//! # }
+//! # fn main() {}
+//! ```
+
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::{Future, Stream};
+use futures::stream::Wait;
+use http::{Request, Response, Uri};
+use tokio::runtime::Runtime;
+
+use body::Body;
+use error::Parse;
+use super::connect::HttpConnector;
+
+/// A Client that blocks the current thread for each request.
+///
+/// Cloning a `Client` shares its runtime and connection pool.
+#[derive(Clone)]
+pub struct Client {
+    inner: ::Client<HttpConnector, Body>,
+    // Keeps the connections driven, for as long as the `Client` or a
+    // `BodyReader` is around.
+    runtime: Arc<Runtime>,
+}
+
+impl Client {
+    /// Create a new `Client` with the default configuration, starting a
+    /// runtime in the background.
+    pub fn new() -> ::Result<Client> {
+        Client::configured(&::Client::builder())
+    }
+
+    /// Create a new `Client` with the configuration of a
+    /// [`Builder`](::client::Builder), starting a runtime in the
+    /// background.
+    ///
+    /// The `Builder`'s executor is replaced by the new runtime.
+    pub fn configured(builder: &::client::Builder) -> ::Result<Client> {
+        let runtime = Runtime::new().map_err(::Error::new_io)?;
+        let connector = HttpConnector::new_with_handle(1, runtime.reactor().clone());
+        let inner = builder.clone()
+            .executor(runtime.executor())
+            .build(connector);
+        Ok(Client {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Send a `GET` request to the `uri`, and read the whole response body.
+    pub fn get(&self, uri: &str) -> ::Result<Response<Vec<u8>>> {
+        let uri = uri.parse::<Uri>().map_err(|e| ::Error::from(Parse::from(e)))?;
+        let mut req = Request::new(Body::empty());
+        *req.uri_mut() = uri;
+        self.request(req)
+    }
+
+    /// Send a request, and read the whole response body.
+    pub fn request(&self, req: Request<Body>) -> ::Result<Response<Vec<u8>>> {
+        let res = self.inner.request(req).wait()?;
+        let (parts, body) = res.into_parts();
+        let body = body.concat2().wait()?;
+        Ok(Response::from_parts(parts, body.to_vec()))
+    }
+
+    /// Send a request, and return the response once its head is received.
+    ///
+    /// The body can then be read as it arrives, with the `io::Read` of the
+    /// [`BodyReader`](BodyReader).
+    pub fn send(&self, req: Request<Body>) -> ::Result<Response<BodyReader>> {
+        let res = self.inner.request(req).wait()?;
+        let runtime = self.runtime.clone();
+        Ok(res.map(move |body| BodyReader {
+            body: body.wait(),
+            buf: Bytes::new(),
+            _runtime: runtime,
+        }))
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Client")
+            .finish()
+    }
+}
+
+/// A response body that blocks the current thread on reads, until more data
+/// is received.
+pub struct BodyReader {
+    body: Wait<Body>,
+    // The rest of the last chunk, that didn't fit in the read buffer.
+    buf: Bytes,
+    _runtime: Arc<Runtime>,
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.buf.is_empty() {
+            match self.body.next() {
+                Some(Ok(chunk)) => self.buf = chunk.into_bytes(),
+                Some(Err(e)) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+                None => return Ok(0),
+            }
+        }
+        let n = ::std::cmp::min(buf.len(), self.buf.len());
+        buf[..n].copy_from_slice(&self.buf.split_to(n));
+        Ok(n)
+    }
+}
+
+impl fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BodyReader")
+            .finish()
+    }
+}
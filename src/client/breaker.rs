@@ -0,0 +1,231 @@
+//! A circuit breaker per host, see `Builder::circuit_breaker`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use http::Response;
+
+use body::Body;
+use error::Kind;
+
+// The most hosts whose failures are kept track of at once.
+const MAX_HOSTS: usize = 1024;
+
+pub(super) struct CircuitBreaker {
+    threshold: usize,
+    cool_down: Duration,
+    // Hosts whose latest requests failed. A host is removed once a request
+    // to it succeeds again.
+    hosts: Mutex<HashMap<String, State>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    // The number of consecutive failures so far.
+    Closed(usize),
+    // Requests fail fast until the cool-down ends.
+    Open(Instant),
+    // A single request was let through, to check if the host recovered.
+    Probing,
+}
+
+/// A request that was let through the breaker, whose outcome is recorded
+/// once it is known.
+pub(super) struct Attempt {
+    breaker: Arc<CircuitBreaker>,
+    host: String,
+    is_probe: bool,
+    is_done: bool,
+}
+
+impl CircuitBreaker {
+    pub(super) fn new(threshold: usize, cool_down: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            threshold,
+            cool_down,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check if a request to `host` may be sent.
+    pub(super) fn start(breaker: &Arc<CircuitBreaker>, host: &str) -> ::Result<Attempt> {
+        let mut hosts = breaker.hosts.lock().unwrap();
+        let is_probe = match hosts.get(host).cloned() {
+            None | Some(State::Closed(_)) => false,
+            Some(State::Open(until)) if until <= Instant::now() => true,
+            Some(State::Open(_)) | Some(State::Probing) => {
+                trace!("circuit open for {:?}", host);
+                return Err(::Error::new_circuit_open());
+            }
+        };
+        if is_probe {
+            debug!("circuit half-open for {:?}, probing", host);
+            hosts.insert(host.to_owned(), State::Probing);
+        }
+        Ok(Attempt {
+            breaker: breaker.clone(),
+            host: host.to_owned(),
+            is_probe,
+            is_done: false,
+        })
+    }
+
+    fn on_success(&self, host: &str, is_probe: bool) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let is_closed = match hosts.get(host) {
+            Some(&State::Closed(_)) => true,
+            _ => false,
+        };
+        // A request started before the circuit opened doesn't close it,
+        // only the probe does.
+        if is_closed || is_probe {
+            hosts.remove(host);
+        }
+    }
+
+    fn on_failure(&self, host: &str, is_probe: bool) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let open = State::Open(Instant::now() + self.cool_down);
+        let next = match hosts.get(host).cloned() {
+            _ if is_probe => open,
+            None => State::Closed(1),
+            Some(State::Closed(failures)) => State::Closed(failures + 1),
+            Some(state) => state,
+        };
+        let next = match next {
+            State::Closed(failures) if failures >= self.threshold => open,
+            next => next,
+        };
+        if let State::Open(_) = next {
+            debug!("circuit open for {:?}, after {} failures", host, self.threshold);
+        }
+        if hosts.len() >= MAX_HOSTS && !hosts.contains_key(host) {
+            // Failures that didn't open a circuit are forgotten first, and
+            // then circuits whose cool-down is over, which would let a
+            // request through either way. A host failing while every other
+            // circuit is still open isn't kept track of.
+            let now = Instant::now();
+            hosts.retain(|_, state| match *state {
+                State::Closed(_) => false,
+                State::Open(until) => until > now,
+                State::Probing => true,
+            });
+            if hosts.len() >= MAX_HOSTS {
+                trace!("too many failing hosts to keep track of {:?}", host);
+                return;
+            }
+        }
+        hosts.insert(host.to_owned(), next);
+    }
+
+    /// The probe's outcome isn't known, so the next request probes again.
+    fn on_abandoned(&self, host: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if hosts.get(host) == Some(&State::Probing) {
+            hosts.insert(host.to_owned(), State::Open(Instant::now()));
+        }
+    }
+}
+
+impl Attempt {
+    /// Record the outcome of the request.
+    ///
+    /// Connect errors and `5xx` responses are failures, other responses are
+    /// successes, and other errors don't count either way.
+    pub(super) fn finish(mut self, res: &::Result<Response<Body>>) {
+        self.is_done = true;
+        match *res {
+            Ok(ref res) if res.status().is_server_error() => {
+                self.breaker.on_failure(&self.host, self.is_probe);
+            },
+            Ok(_) => self.breaker.on_success(&self.host, self.is_probe),
+            Err(ref err) if *err.kind() == Kind::Connect => {
+                self.breaker.on_failure(&self.host, self.is_probe);
+            },
+            Err(_) => self.breaker.on_abandoned(&self.host),
+        }
+    }
+}
+
+impl Drop for Attempt {
+    fn drop(&mut self) {
+        if !self.is_done && self.is_probe {
+            self.breaker.on_abandoned(&self.host);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use http::{Response, StatusCode};
+
+    use body::Body;
+    use super::{CircuitBreaker, MAX_HOSTS};
+
+    fn response(status: u16) -> ::Result<Response<Body>> {
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = StatusCode::from_u16(status).unwrap();
+        Ok(res)
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures_and_probes_once() {
+        let breaker = Arc::new(CircuitBreaker::new(2, Duration::from_secs(0)));
+        let start = |host| CircuitBreaker::start(&breaker, host);
+
+        start("a").unwrap().finish(&response(503));
+        start("a").unwrap().finish(&response(200));
+        start("a").unwrap().finish(&response(500));
+        start("a").unwrap().finish(&Err(::Error::new_connect("refused")));
+
+        // The cool-down is over, so a single probe is let through.
+        let probe = start("a").unwrap();
+        assert!(start("a").err().expect("circuit open").is_circuit_open());
+        assert!(start("b").is_ok());
+
+        // An abandoned probe lets the next request probe again.
+        drop(probe);
+        let probe = start("a").unwrap();
+        assert!(start("a").err().expect("circuit open").is_circuit_open());
+        probe.finish(&response(404));
+
+        start("a").unwrap().finish(&response(502));
+        assert!(start("a").is_ok());
+    }
+
+    #[test]
+    fn forgets_failures_past_max_hosts() {
+        let breaker = Arc::new(CircuitBreaker::new(2, Duration::from_secs(60)));
+        let start = |host: &str| CircuitBreaker::start(&breaker, host);
+        for i in 0..MAX_HOSTS {
+            start(&i.to_string()).unwrap().finish(&response(500));
+        }
+        // Failures that didn't open a circuit make room for a new host.
+        start("a").unwrap().finish(&response(500));
+        start("a").unwrap().finish(&response(500));
+        assert!(start("a").err().expect("circuit open").is_circuit_open());
+        start("0").unwrap().finish(&response(500));
+        assert!(start("0").is_ok());
+
+        let breaker = Arc::new(CircuitBreaker::new(1, Duration::from_secs(60)));
+        let start = |host: &str| CircuitBreaker::start(&breaker, host);
+        for i in 0..MAX_HOSTS {
+            start(&i.to_string()).unwrap().finish(&response(500));
+        }
+        // Open circuits aren't forgotten to make room.
+        start("a").unwrap().finish(&response(500));
+        assert!(start("a").is_ok());
+        assert!(start("0").err().expect("circuit open").is_circuit_open());
+    }
+
+    #[test]
+    fn fails_fast_during_cool_down() {
+        let breaker = Arc::new(CircuitBreaker::new(1, Duration::from_secs(60)));
+        CircuitBreaker::start(&breaker, "a").unwrap().finish(&response(500));
+        assert!(CircuitBreaker::start(&breaker, "a").err().expect("circuit open").is_circuit_open());
+    }
+}
@@ -0,0 +1,417 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use bytes::{Bytes, BytesMut};
+use futures::{Async, Poll, Stream};
+use http::{HeaderMap, Response, StatusCode};
+use http::header::{self, HeaderName, HeaderValue};
+use httparse;
+
+use body::Body;
+use chunk::Chunk;
+
+// The most headers a single part may have.
+const MAX_PART_HEADERS: usize = 16;
+
+/// The parts of a `206 Partial Content` response to a request for several
+/// ranges, with a `multipart/byteranges` body.
+///
+/// This is a `Stream` of each [`ByteRange`](ByteRange) in the response, as
+/// soon as its headers are received. The body of a part streams out of the
+/// response body as it arrives, no matter how the boundaries fall across
+/// chunks. Parts are read in order: polling for the next part skips what is
+/// left of the body of the previous one.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// # use futures::{Future, Stream};
+/// # use hyper::{Body, Response};
+/// use hyper::client::ByteRanges;
+///
+/// # fn doc(res: Response<Body>) {
+/// match ByteRanges::from_response(res) {
+///     Ok(parts) => {
+///         let fut = parts.for_each(|part| {
+///             let (first, last) = (part.first_byte(), part.last_byte());
+///             part.into_body().concat2().map(move |bytes| {
+///                 println!("bytes {}-{}: {} bytes", first, last, bytes.len());
+///             })
+///         });
+///         # drop(fut);
+///     },
+///     Err(res) => println!("not a multipart response: {}", res.status()),
+/// }
+/// # }
+/// # fn main() {}
+/// ```
+pub struct ByteRanges {
+    shared: Arc<Mutex<Parser>>,
+}
+
+/// A part of a `multipart/byteranges` response, from
+/// [`ByteRanges`](ByteRanges).
+pub struct ByteRange {
+    first: u64,
+    last: u64,
+    complete_length: Option<u64>,
+    headers: HeaderMap,
+    body: Body,
+}
+
+struct Parser {
+    body: Body,
+    buf: BytesMut,
+    // `CRLF--boundary`, which ends the body of each part.
+    delimiter: Bytes,
+    state: State,
+    // Which part the body is being read for, counting from 1.
+    part: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    // Skipping anything before the first delimiter.
+    Preamble,
+    // After a delimiter, either ending the body, or starting a part.
+    Delimiter,
+    Headers,
+    Body,
+    Done,
+}
+
+// The body of a single `ByteRange`, read out of the shared `Parser`.
+struct PartBody {
+    shared: Arc<Mutex<Parser>>,
+    part: usize,
+}
+
+impl ByteRanges {
+    /// Get the parts of a `multipart/byteranges` response.
+    ///
+    /// If the response isn't a `206 Partial Content` with a
+    /// `multipart/byteranges` body, such as when only a single range was
+    /// sent, it is returned back instead.
+    pub fn from_response(res: Response<Body>) -> Result<ByteRanges, Response<Body>> {
+        if res.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(res);
+        }
+        let boundary = match res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(::headers::multipart_byteranges_boundary)
+        {
+            Some(boundary) => boundary,
+            None => return Err(res),
+        };
+        let mut delimiter = BytesMut::with_capacity(boundary.len() + 4);
+        delimiter.extend_from_slice(b"\r\n--");
+        delimiter.extend_from_slice(boundary.as_bytes());
+        // The first delimiter may be at the very start of the body, without
+        // the CRLF that comes before the others.
+        let mut buf = BytesMut::with_capacity(8192);
+        buf.extend_from_slice(b"\r\n");
+        Ok(ByteRanges {
+            shared: Arc::new(Mutex::new(Parser {
+                body: res.into_body(),
+                buf,
+                delimiter: delimiter.freeze(),
+                state: State::Preamble,
+                part: 0,
+            })),
+        })
+    }
+}
+
+impl Stream for ByteRanges {
+    type Item = ByteRange;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let (part, headers) = match try_ready!(self.shared.lock().unwrap().poll_part()) {
+            Some(next) => next,
+            None => return Ok(Async::Ready(None)),
+        };
+        let (first, last, complete_length) = match headers
+            .get(header::CONTENT_RANGE)
+            .and_then(::headers::content_range_parse)
+        {
+            Some(range) => range,
+            None => return Err(::Error::new_body("multipart/byteranges part without a valid Content-Range")),
+        };
+        let body = Body::wrap_stream(PartBody {
+            shared: self.shared.clone(),
+            part,
+        });
+        Ok(Async::Ready(Some(ByteRange {
+            first,
+            last,
+            complete_length,
+            headers,
+            body,
+        })))
+    }
+}
+
+impl fmt::Debug for ByteRanges {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parser = self.shared.lock().unwrap();
+        f.debug_struct("ByteRanges")
+            .field("state", &parser.state)
+            .field("part", &parser.part)
+            .finish()
+    }
+}
+
+impl ByteRange {
+    /// The position of the first byte of this part.
+    pub fn first_byte(&self) -> u64 {
+        self.first
+    }
+
+    /// The position of the last byte of this part, inclusive.
+    pub fn last_byte(&self) -> u64 {
+        self.last
+    }
+
+    /// The length of the whole representation, if the server knew it.
+    pub fn complete_length(&self) -> Option<u64> {
+        self.complete_length
+    }
+
+    /// The headers of this part, such as `Content-Range` and `Content-Type`.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Consume this part, returning its body.
+    pub fn into_body(self) -> Body {
+        self.body
+    }
+}
+
+impl fmt::Debug for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ByteRange")
+            .field("first", &self.first)
+            .field("last", &self.last)
+            .field("complete_length", &self.complete_length)
+            .field("headers", &self.headers)
+            .finish()
+    }
+}
+
+impl Stream for PartBody {
+    type Item = Chunk;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut parser = self.shared.lock().unwrap();
+        if parser.part != self.part {
+            return Ok(Async::Ready(None));
+        }
+        parser.poll_body()
+    }
+}
+
+impl Parser {
+    // Read until the headers of the next part, returning its number.
+    fn poll_part(&mut self) -> Poll<Option<(usize, HeaderMap)>, ::Error> {
+        loop {
+            match self.state {
+                State::Preamble => {
+                    match find(&self.buf, &self.delimiter) {
+                        Some(pos) => {
+                            self.buf.advance(pos + self.delimiter.len());
+                            self.state = State::Delimiter;
+                        },
+                        None => {
+                            self.keep_partial_delimiter();
+                            try_ready!(self.read());
+                        }
+                    }
+                },
+                State::Delimiter => {
+                    if self.buf.len() >= 2 && &self.buf[..2] == b"--" {
+                        trace!("multipart/byteranges closing delimiter");
+                        self.state = State::Done;
+                        continue;
+                    }
+                    // The rest of the delimiter line may have some padding.
+                    match find(&self.buf, b"\r\n") {
+                        Some(pos) => {
+                            self.buf.advance(pos + 2);
+                            self.state = State::Headers;
+                        },
+                        None => try_ready!(self.read()),
+                    }
+                },
+                State::Headers => {
+                    let mut parsed = [httparse::EMPTY_HEADER; MAX_PART_HEADERS];
+                    let status = httparse::parse_headers(&self.buf, &mut parsed)
+                        .map_err(::Error::new_body)?;
+                    match status {
+                        httparse::Status::Complete((len, parsed)) => {
+                            let mut headers = HeaderMap::with_capacity(parsed.len());
+                            for h in parsed {
+                                let name = HeaderName::from_bytes(h.name.as_bytes())
+                                    .map_err(::Error::new_body)?;
+                                let value = HeaderValue::from_bytes(h.value)
+                                    .map_err(::Error::new_body)?;
+                                headers.append(name, value);
+                            }
+                            self.buf.advance(len);
+                            self.state = State::Body;
+                            self.part += 1;
+                            return Ok(Async::Ready(Some((self.part, headers))));
+                        },
+                        httparse::Status::Partial => try_ready!(self.read()),
+                    }
+                },
+                State::Body => {
+                    // Skip what the previous part didn't read of its body.
+                    while try_ready!(self.poll_body()).is_some() {}
+                },
+                State::Done => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+
+    // Read the body of the current part, until the next delimiter.
+    fn poll_body(&mut self) -> Poll<Option<Chunk>, ::Error> {
+        loop {
+            if self.state != State::Body {
+                return Ok(Async::Ready(None));
+            }
+            if let Some(pos) = find(&self.buf, &self.delimiter) {
+                let data = self.buf.split_to(pos).freeze();
+                self.buf.advance(self.delimiter.len());
+                self.state = State::Delimiter;
+                if !data.is_empty() {
+                    return Ok(Async::Ready(Some(Chunk::from(data))));
+                }
+                continue;
+            }
+            // Anything before a possible start of the delimiter is data.
+            let keep = self.delimiter.len() - 1;
+            if self.buf.len() > keep {
+                let len = self.buf.len() - keep;
+                let data = self.buf.split_to(len).freeze();
+                return Ok(Async::Ready(Some(Chunk::from(data))));
+            }
+            try_ready!(self.read());
+        }
+    }
+
+    fn keep_partial_delimiter(&mut self) {
+        let keep = self.delimiter.len() - 1;
+        if self.buf.len() > keep {
+            let len = self.buf.len() - keep;
+            self.buf.advance(len);
+        }
+    }
+
+    fn read(&mut self) -> Poll<(), ::Error> {
+        match try_ready!(self.body.poll()) {
+            Some(chunk) => {
+                self.buf.extend_from_slice(&chunk);
+                Ok(Async::Ready(()))
+            },
+            None => {
+                debug!("multipart/byteranges body ended in {:?} state", self.state);
+                Err(::Error::new_incomplete())
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+    use http::{Response, StatusCode};
+    use http::header;
+
+    use body::Body;
+    use super::ByteRanges;
+
+    static MULTIPART: &str = "\
+        --THIS_SEPARATES\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Range: bytes 0-4/26\r\n\
+        \r\n\
+        abcde\r\n\
+        --THIS_SEPARATES\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Range: bytes 20-25/26\r\n\
+        \r\n\
+        uvwxyz\r\n\
+        --THIS_SEPARATES--\r\n\
+    ";
+
+    fn response(chunks: Vec<&'static [u8]>) -> Response<Body> {
+        let body = Body::wrap_stream(::futures::stream::iter_ok::<_, ::Error>(chunks));
+        Response::builder()
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_TYPE, "multipart/byteranges; boundary=\"THIS_SEPARATES\"")
+            .body(body)
+            .unwrap()
+    }
+
+    fn collect(res: Response<Body>) -> Vec<(u64, u64, Option<u64>, Vec<u8>)> {
+        ByteRanges::from_response(res)
+            .expect("multipart")
+            .and_then(|part| {
+                let range = (part.first_byte(), part.last_byte(), part.complete_length());
+                part.into_body().concat2().map(move |body| (range.0, range.1, range.2, body.to_vec()))
+            })
+            .collect()
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_parts() {
+        let parts = collect(response(vec![MULTIPART.as_bytes()]));
+        assert_eq!(parts, vec![
+            (0, 4, Some(26), b"abcde".to_vec()),
+            (20, 25, Some(26), b"uvwxyz".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn parses_parts_split_across_chunks() {
+        let bytes = MULTIPART.as_bytes();
+        for size in 1..bytes.len() {
+            let chunks = bytes.chunks(size).collect::<Vec<_>>();
+            let parts = collect(response(chunks));
+            assert_eq!(parts.len(), 2, "chunks of {}", size);
+            assert_eq!(parts[0].3, b"abcde", "chunks of {}", size);
+            assert_eq!(parts[1].3, b"uvwxyz", "chunks of {}", size);
+        }
+    }
+
+    #[test]
+    fn skips_unread_bodies() {
+        let parts = ByteRanges::from_response(response(vec![MULTIPART.as_bytes()]))
+            .expect("multipart")
+            .map(|part| part.last_byte())
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(parts, vec![4, 25]);
+    }
+
+    #[test]
+    fn rejects_other_responses() {
+        let res = Response::new(Body::empty());
+        assert!(ByteRanges::from_response(res).is_err());
+    }
+}
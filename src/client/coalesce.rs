@@ -0,0 +1,329 @@
+//! Sharing a single exchange among identical requests, see
+//! `Builder::coalesce_requests`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot;
+use http::{Method, Request, Response};
+use http::header::{self, HeaderName, HeaderValue};
+
+use body::{Body, Payload, TeePolicy};
+use common::CancelToken;
+use super::RequestConfig;
+
+// Headers carrying credentials, so a response to a request with any of them
+// is only shared when their values are part of the key.
+const CREDENTIALS: [HeaderName; 3] = [
+    header::AUTHORIZATION,
+    header::COOKIE,
+    header::PROXY_AUTHORIZATION,
+];
+
+type Waiters = Vec<oneshot::Sender<::Result<Response<Body>>>>;
+
+pub(super) struct Coalescer {
+    headers: Vec<HeaderName>,
+    max_buffer: usize,
+    // The requests in flight, with the requests waiting to share their
+    // response. A request is removed once its response head is received,
+    // so later requests don't miss the start of the body.
+    in_flight: Mutex<HashMap<Key, Waiters>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(super) struct Key {
+    method: Method,
+    uri: String,
+    headers: Vec<Vec<HeaderValue>>,
+}
+
+pub(super) enum Start {
+    /// No identical request is in flight, so this one is sent.
+    Lead(Leader),
+    /// The response of an identical request in flight is shared.
+    Wait(Waiter),
+}
+
+/// A request that is sent, whose response is shared with the identical
+/// requests made while it's in flight.
+pub(super) struct Leader {
+    coalescer: Arc<Coalescer>,
+    key: Key,
+    is_done: bool,
+}
+
+/// A future of the response shared by a `Leader`.
+pub(super) struct Waiter {
+    rx: oneshot::Receiver<::Result<Response<Body>>>,
+    // The waiting request's own token, since the leader's is the one that
+    // aborts the shared exchange.
+    token: Option<CancelToken>,
+}
+
+impl Coalescer {
+    pub(super) fn new(headers: Vec<HeaderName>, max_buffer: usize) -> Coalescer {
+        Coalescer {
+            headers,
+            max_buffer,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The key of a request that may share an exchange, if it is a `GET` or
+    /// `HEAD` without a body.
+    ///
+    /// Requests with credentials that aren't part of the key, or with their
+    /// own `RequestConfig`, are always sent.
+    pub(super) fn key<B: Payload>(&self, req: &Request<B>) -> Option<Key> {
+        match *req.method() {
+            Method::GET | Method::HEAD => (),
+            _ => return None,
+        }
+        if !req.body().is_end_stream() {
+            return None;
+        }
+        if req.extensions().get::<RequestConfig>().is_some() {
+            return None;
+        }
+        let has_credentials = CREDENTIALS
+            .iter()
+            .any(|name| req.headers().contains_key(name) && !self.headers.contains(name));
+        if has_credentials {
+            trace!("not coalescing request with credentials");
+            return None;
+        }
+        let headers = self.headers
+            .iter()
+            .map(|name| req.headers().get_all(name).iter().cloned().collect())
+            .collect();
+        Some(Key {
+            method: req.method().clone(),
+            uri: req.uri().to_string(),
+            headers,
+        })
+    }
+
+    /// Either wait for the response of an identical request in flight, or
+    /// send this one.
+    ///
+    /// A waiting request is still aborted by its own `token`.
+    pub(super) fn start(coalescer: &Arc<Coalescer>, key: Key, token: Option<CancelToken>) -> Start {
+        let mut in_flight = coalescer.in_flight.lock().unwrap();
+        if let Some(waiters) = in_flight.get_mut(&key) {
+            trace!("coalescing request {:?}", key);
+            let (tx, rx) = oneshot::channel();
+            waiters.push(tx);
+            return Start::Wait(Waiter {
+                rx,
+                token,
+            });
+        }
+        in_flight.insert(key.clone(), Vec::new());
+        Start::Lead(Leader {
+            coalescer: coalescer.clone(),
+            key,
+            is_done: false,
+        })
+    }
+}
+
+impl Leader {
+    /// Share the outcome of the request with the ones waiting for it.
+    ///
+    /// If there are any, the response body is teed for each of them, so it's
+    /// read as fast as the slowest of the responses.
+    pub(super) fn finish(mut self, res: ::Result<Response<Body>>) -> ::Result<Response<Body>> {
+        self.is_done = true;
+        let waiters = self.coalescer.in_flight
+            .lock()
+            .unwrap()
+            .remove(&self.key)
+            .unwrap_or_else(Vec::new);
+        let waiters = waiters
+            .into_iter()
+            .filter(|tx| !tx.is_canceled())
+            .collect::<Vec<_>>();
+        if waiters.is_empty() {
+            return res;
+        }
+        debug!("sharing response to {:?} with {} requests", self.key, waiters.len());
+
+        let res = match res {
+            Ok(res) => res,
+            Err(err) => {
+                let reason = err.to_string();
+                for tx in waiters {
+                    let _ = tx.send(Err(::Error::new_canceled(Some(reason.clone()))));
+                }
+                return Err(err);
+            }
+        };
+        let (parts, mut body) = res.into_parts();
+        for tx in waiters {
+            let (shared, rest) = body.tee_with(self.coalescer.max_buffer, TeePolicy::Wait);
+            body = rest;
+            let mut res = Response::new(shared);
+            *res.status_mut() = parts.status;
+            *res.version_mut() = parts.version;
+            *res.headers_mut() = parts.headers.clone();
+            let _ = tx.send(Ok(res));
+        }
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        // The request was dropped before its response arrived, so the
+        // waiting requests are canceled.
+        if !self.is_done {
+            self.coalescer.in_flight.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
+impl Future for Waiter {
+    type Item = Response<Body>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref token) = self.token {
+            if token.poll_canceled().is_ready() {
+                trace!("coalesced request canceled");
+                return Err(::Error::new_canceled(Some("request canceled")));
+            }
+        }
+        match self.rx.poll() {
+            Ok(Async::Ready(Ok(res))) => Ok(Async::Ready(res)),
+            Ok(Async::Ready(Err(err))) => Err(err),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_canceled) => Err(::Error::new_canceled(Some("coalesced request was dropped"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::{Future, Stream};
+    use http::{Request, Response};
+    use http::header::{self, HeaderValue};
+
+    use body::Body;
+    use common::CancelToken;
+    use super::{Coalescer, Start};
+
+    #[test]
+    fn key_includes_selected_headers() {
+        let coalescer = Coalescer::new(vec![header::ACCEPT], 1024);
+        let get = |accept| {
+            let mut req = Request::new(Body::empty());
+            *req.uri_mut() = "http://example.com/a".parse().unwrap();
+            req.headers_mut().insert(header::ACCEPT, HeaderValue::from_static(accept));
+            req.headers_mut().insert(header::USER_AGENT, HeaderValue::from_static(accept));
+            req
+        };
+        assert_eq!(coalescer.key(&get("text/html")), coalescer.key(&get("text/html")));
+        assert_ne!(coalescer.key(&get("text/html")), coalescer.key(&get("text/plain")));
+
+        let mut post = get("text/html");
+        *post.method_mut() = ::http::Method::POST;
+        assert_eq!(coalescer.key(&post), None);
+
+        let with_body = get("text/html").map(|_| Body::from("hi"));
+        assert_eq!(coalescer.key(&with_body), None);
+
+        let mut with_config = get("text/html");
+        with_config.extensions_mut().insert(::client::RequestConfig::new());
+        assert_eq!(coalescer.key(&with_config), None);
+    }
+
+    #[test]
+    fn key_excludes_requests_with_credentials() {
+        let get = |name, value| {
+            let mut req = Request::new(Body::empty());
+            req.headers_mut().insert(name, HeaderValue::from_static(value));
+            req
+        };
+
+        let coalescer = Coalescer::new(Vec::new(), 1024);
+        assert_eq!(coalescer.key(&get(header::AUTHORIZATION, "Basic YTpi")), None);
+        assert_eq!(coalescer.key(&get(header::COOKIE, "session=a")), None);
+        assert_eq!(coalescer.key(&get(header::PROXY_AUTHORIZATION, "Basic YTpi")), None);
+
+        // Unless the credentials are part of the key.
+        let coalescer = Coalescer::new(vec![header::COOKIE], 1024);
+        let a = coalescer.key(&get(header::COOKIE, "session=a"));
+        assert!(a.is_some());
+        assert_eq!(a, coalescer.key(&get(header::COOKIE, "session=a")));
+        assert_ne!(a, coalescer.key(&get(header::COOKIE, "session=b")));
+        assert_eq!(coalescer.key(&get(header::AUTHORIZATION, "Basic YTpi")), None);
+    }
+
+    #[test]
+    fn waiters_share_leader_response_until_head() {
+        let coalescer = Arc::new(Coalescer::new(Vec::new(), 1024));
+        let key = coalescer.key(&Request::new(Body::empty())).unwrap();
+
+        let leader = match Coalescer::start(&coalescer, key.clone(), None) {
+            Start::Lead(leader) => leader,
+            Start::Wait(_) => panic!("nothing in flight"),
+        };
+        let waiter = match Coalescer::start(&coalescer, key.clone(), None) {
+            Start::Wait(waiter) => waiter,
+            Start::Lead(_) => panic!("request in flight"),
+        };
+
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = ::http::StatusCode::ACCEPTED;
+        let res = leader.finish(Ok(res)).unwrap();
+        assert_eq!(res.status(), ::http::StatusCode::ACCEPTED);
+        assert_eq!(waiter.wait().unwrap().status(), ::http::StatusCode::ACCEPTED);
+
+        // Once the head arrived, identical requests are sent again.
+        let leader = match Coalescer::start(&coalescer, key.clone(), None) {
+            Start::Lead(leader) => leader,
+            Start::Wait(_) => panic!("nothing in flight"),
+        };
+
+        // A dropped leader cancels its waiters.
+        let waiter = match Coalescer::start(&coalescer, key, None) {
+            Start::Wait(waiter) => waiter,
+            Start::Lead(_) => panic!("request in flight"),
+        };
+        drop(leader);
+        assert!(waiter.wait().unwrap_err().is_canceled());
+    }
+
+    #[test]
+    fn waiter_canceled_by_own_token() {
+        let coalescer = Arc::new(Coalescer::new(Vec::new(), 1024));
+        let key = coalescer.key(&Request::new(Body::empty())).unwrap();
+
+        let leader = match Coalescer::start(&coalescer, key.clone(), None) {
+            Start::Lead(leader) => leader,
+            Start::Wait(_) => panic!("nothing in flight"),
+        };
+        let token = CancelToken::new();
+        let canceled = match Coalescer::start(&coalescer, key.clone(), Some(token.clone())) {
+            Start::Wait(waiter) => waiter,
+            Start::Lead(_) => panic!("request in flight"),
+        };
+        let waiter = match Coalescer::start(&coalescer, key, None) {
+            Start::Wait(waiter) => waiter,
+            Start::Lead(_) => panic!("request in flight"),
+        };
+
+        token.cancel();
+        assert!(canceled.wait().unwrap_err().is_canceled());
+
+        // The exchange and the other requests sharing it carry on.
+        let res = leader.finish(Ok(Response::new(Body::from("shared")))).unwrap();
+        let (a, b) = (res.into_body(), waiter.wait().unwrap().into_body());
+        assert_eq!(a.concat2().wait().unwrap().as_ref(), b"shared");
+        assert_eq!(b.concat2().wait().unwrap().as_ref(), b"shared");
+    }
+}
@@ -0,0 +1,227 @@
+//! Per-request configuration of the `Client`.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use http::Request;
+
+use super::{RequestTarget, Ver};
+
+/// Options for a single request sent with a `Client`.
+///
+/// Insert it into the extensions of a `Request`, and any option set on it
+/// is used instead of the one the `Client` was built with. Options that are
+/// not set keep using the `Client`'s configuration.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// # use std::time::Duration;
+/// use hyper::{Body, Request};
+/// use hyper::client::RequestConfig;
+///
+/// # fn main() {
+/// let mut req = Request::new(Body::empty());
+/// req.extensions_mut().insert(
+///     RequestConfig::new()
+///         .pool_timeout(Duration::from_secs(1))
+///         .retry_canceled_requests(false)
+/// );
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RequestConfig {
+    hedge_after: Option<Option<Duration>>,
+    http2_defer_stream: bool,
+    http2_only: Option<bool>,
+    max_response_body_size: Option<Option<u64>>,
+    pool: bool,
+    pool_timeout: Option<Option<Duration>>,
+    request_target: Option<RequestTarget>,
+    resolve: Option<SocketAddr>,
+    retry_canceled_requests: Option<bool>,
+}
+
+impl RequestConfig {
+    /// Create a new `RequestConfig`, with no options set.
+    pub fn new() -> RequestConfig {
+        RequestConfig {
+            hedge_after: None,
+            http2_defer_stream: false,
+            http2_only: None,
+            max_response_body_size: None,
+            pool: true,
+            pool_timeout: None,
+            request_target: None,
+            resolve: None,
+            retry_canceled_requests: None,
+        }
+    }
+
+    /// Set an optional delay after which this request is sent a second
+    /// time, if its response hasn't arrived yet.
+    ///
+    /// See `Builder::hedge_after`.
+    pub fn hedge_after<D>(mut self, val: D) -> Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.hedge_after = Some(val.into());
+        self
+    }
+
+    /// Set whether to wait for this request's body to have its first chunk
+    /// ready, before opening its HTTP/2 stream.
+    ///
+    /// An open stream counts against the server's `MAX_CONCURRENT_STREAMS`,
+    /// even while its body has nothing to send yet. With a body that is slow
+    /// to start producing, deferring the stream leaves room for other
+    /// requests on the connection. A body that is already at its end, such
+    /// as `Body::empty()`, is sent as headers only right away.
+    ///
+    /// This has no effect on HTTP/1 requests.
+    ///
+    /// Default is `false`.
+    pub fn http2_defer_stream(mut self, val: bool) -> Self {
+        self.http2_defer_stream = val;
+        self
+    }
+
+    /// Set whether this request **must** use HTTP/2, or HTTP/1.
+    ///
+    /// See `Builder::http2_only`.
+    pub fn http2_only(mut self, val: bool) -> Self {
+        self.http2_only = Some(val);
+        self
+    }
+
+    /// Set an optional maximum number of bytes of this request's response
+    /// body, or `None` for no maximum.
+    ///
+    /// See `Builder::max_response_body_size`.
+    pub fn max_response_body_size<N>(mut self, val: N) -> Self
+    where
+        N: Into<Option<u64>>,
+    {
+        self.max_response_body_size = Some(val.into());
+        self
+    }
+
+    /// Set whether this request may use the connection pool.
+    ///
+    /// If false, this request is always sent on a new connection, which is
+    /// closed afterwards instead of being put into the pool. It still
+    /// counts towards any connection limits.
+    ///
+    /// HTTP/1 requests with a `Connection: close` header are treated the same
+    /// way, without needing this option.
+    ///
+    /// Default is `true`.
+    pub fn pool(mut self, val: bool) -> Self {
+        self.pool = val;
+        self
+    }
+
+    /// Set an optional timeout for how long this request waits to get a
+    /// connection.
+    ///
+    /// See `Builder::pool_timeout`.
+    pub fn pool_timeout<D>(mut self, val: D) -> Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.pool_timeout = Some(val.into());
+        self
+    }
+
+    /// Set the form of the request-target of this request, if sent with
+    /// HTTP/1.
+    ///
+    /// See [`RequestTarget`](::client::RequestTarget). This also applies to
+    /// requests sent with `conn::SendRequest`.
+    pub fn request_target(mut self, target: RequestTarget) -> Self {
+        self.request_target = Some(target);
+        self
+    }
+
+    /// Set the address to connect to for this request, instead of resolving
+    /// the host of its `Uri`.
+    ///
+    /// The `Uri` is still used for everything else, such as the `Host`
+    /// header. Connections to the address are pooled apart from the other
+    /// connections to the host, so that a request pinned to one address is
+    /// never sent to another one. This is useful to check on a single
+    /// replica of a service, like curl's `--resolve` option.
+    ///
+    /// The connector has to support it, see `Destination::resolved_addr`.
+    pub fn resolve(mut self, addr: SocketAddr) -> Self {
+        self.resolve = Some(addr);
+        self
+    }
+
+    /// Set whether to retry this request if it gets disrupted before ever
+    /// starting to write.
+    ///
+    /// See `Builder::retry_canceled_requests`.
+    pub fn retry_canceled_requests(mut self, val: bool) -> Self {
+        self.retry_canceled_requests = Some(val);
+        self
+    }
+
+    pub(crate) fn get<B>(req: &Request<B>) -> RequestConfig {
+        req.extensions()
+            .get::<RequestConfig>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    #[cfg(feature = "http2")]
+    pub(crate) fn is_h2_stream_deferred(&self) -> bool {
+        self.http2_defer_stream
+    }
+
+    pub(super) fn is_pooled(&self) -> bool {
+        self.pool
+    }
+
+    pub(super) fn ver(&self, default: Ver) -> Ver {
+        match self.http2_only {
+            Some(true) => Ver::Http2,
+            Some(false) => Ver::Http1,
+            None => default,
+        }
+    }
+
+    #[cfg(feature = "runtime")]
+    pub(super) fn hedge_after_or(&self, default: Option<Duration>) -> Option<Duration> {
+        self.hedge_after.unwrap_or(default)
+    }
+
+    pub(super) fn max_response_body_size_or(&self, default: Option<u64>) -> Option<u64> {
+        self.max_response_body_size.unwrap_or(default)
+    }
+
+    #[cfg(feature = "runtime")]
+    pub(super) fn pool_timeout_or(&self, default: Option<Duration>) -> Option<Duration> {
+        self.pool_timeout.unwrap_or(default)
+    }
+
+    pub(crate) fn target_form(&self) -> Option<RequestTarget> {
+        self.request_target
+    }
+
+    pub(super) fn resolved_addr(&self) -> Option<SocketAddr> {
+        self.resolve
+    }
+
+    pub(super) fn retry_canceled_requests_or(&self, default: bool) -> bool {
+        self.retry_canceled_requests.unwrap_or(default)
+    }
+}
+
+impl Default for RequestConfig {
+    fn default() -> RequestConfig {
+        RequestConfig::new()
+    }
+}
@@ -9,17 +9,29 @@
 //! higher-level [Client](super) API.
 use std::fmt;
 use std::marker::PhantomData;
+#[cfg(feature = "runtime")] use std::time::Duration;
 
 use bytes::Bytes;
 use futures::{Async, Future, Poll};
 use futures::future::{self, Either};
 use tokio_io::{AsyncRead, AsyncWrite};
 
-use body::Payload;
+use body::{BufferLimit, ChunkSize, Payload};
 use common::Exec;
+#[cfg(feature = "runtime")] use common::WriteTimeout;
+#[cfg(feature = "unstable-completion-io")] use completion::{Completion, CompletionIo};
 use proto;
 use super::dispatch;
-use {Body, Request, Response, StatusCode};
+#[cfg(feature = "runtime")] use super::idle_timeout::IdleTimeout;
+#[cfg(feature = "http2")] use super::measure::PingRtt;
+
+pub use common::{CancelToken, CloseReason, ConnectionStats, ExchangeStats};
+pub use proto::{HeaderCaseMap, HeaderOrder, HeaderValuePolicy, InvalidReasonPhrase, ReasonPhrase};
+pub use super::RequestTarget;
+pub use super::settings::PeerSettings;
+use super::settings::SettingsSlot;
+use {Body, Request, Response};
+#[cfg(feature = "http1")] use StatusCode;
 
 /// Returns a `Handshake` future over some IO.
 ///
@@ -35,6 +47,7 @@ where
 /// The sender side of an established connection.
 pub struct SendRequest<B> {
     dispatch: dispatch::Sender<Request<B>, Response<Body>>,
+    peer_settings: SettingsSlot,
 }
 
 
@@ -48,27 +61,59 @@ where
     T: AsyncRead + AsyncWrite + Send + 'static,
     B: Payload + 'static,
 {
-    inner: Either<
-        proto::dispatch::Dispatcher<
-            proto::dispatch::Client<B>,
-            B,
-            T,
-            proto::ClientUpgradeTransaction,
-        >,
-        proto::h2::Client<T, B>,
-    >,
+    inner: Option<ProtoClient<T, B, UpgradeTransaction>>,
+    close_reason: Option<CloseReason>,
+    #[cfg(all(feature = "http1", feature = "http2"))]
+    h2c: Option<H2cUpgrade>,
+    #[cfg(feature = "runtime")]
+    idle_timeout: Option<IdleTimeout>,
+    #[cfg(feature = "runtime")]
+    write_timeout: Option<WriteTimeout>,
 }
 
 
+/// A future that runs a `Connection` until it is done with HTTP, and then
+/// yields its [`Parts`](Parts), without shutting down the IO object.
+///
+/// This is created by [`Connection::without_shutdown`](Connection::without_shutdown).
+#[must_use = "futures do nothing unless polled"]
+pub struct WithoutShutdown<T, B>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    B: Payload + 'static,
+{
+    conn: Option<Connection<T, B>>,
+}
+
 /// A builder to configure an HTTP connection.
 ///
 /// After setting options, the builder is used to create a `Handshake` future.
 #[derive(Clone, Debug)]
 pub struct Builder {
+    chunk_size: ChunkSize,
     exec: Exec,
+    h1_body_buffer: BufferLimit,
     h1_writev: bool,
     h1_title_case_headers: bool,
+    h1_header_value_policy: Option<HeaderValuePolicy>,
+    h1_chunk_extensions: bool,
+    h1_legacy_keep_alive: bool,
+    h1_lenient: proto::Lenient,
+    h1_max_buf_size: Option<usize>,
+    h1_max_headers: Option<usize>,
+    h1_preserve_header_case: bool,
+    h1_preserve_header_order: bool,
+    h1_queue_depth: usize,
+    h1_request_target: Option<RequestTarget>,
+    h1_h2c_upgrade: bool,
     http2: bool,
+    http2_peer_settings: bool,
+    #[cfg(feature = "http2")]
+    http2_ping_rtt: Option<PingRtt>,
+    #[cfg(feature = "runtime")]
+    idle_timeout: Option<Duration>,
+    #[cfg(feature = "runtime")]
+    write_timeout: Option<Duration>,
 }
 
 /// A future setting up HTTP over an IO object.
@@ -76,7 +121,9 @@ pub struct Builder {
 /// If successful, yields a `(SendRequest, Connection)` pair.
 #[must_use = "futures do nothing unless polled"]
 pub struct Handshake<T, B> {
-    inner: HandshakeInner<T, B, proto::ClientUpgradeTransaction>,
+    inner: HandshakeInner<T, B, UpgradeTransaction>,
+    #[cfg(feature = "unstable-completion-io")]
+    owned_io: Option<proto::h1::OwnedIo<T>>,
 }
 
 /// A future returned by `SendRequest::send_request`.
@@ -89,6 +136,15 @@ pub struct ResponseFuture {
     inner: Box<Future<Item=Response<Body>, Error=::Error> + Send>,
 }
 
+/// A future returned by `SendRequest::try_send_request`.
+///
+/// Yields a `Response` if successful, or an error along with the `Request`
+/// if it was never sent.
+#[must_use = "futures do nothing unless polled"]
+pub struct TryResponseFuture<B> {
+    inner: Box<Future<Item=Response<Body>, Error=(::Error, Option<Request<B>>)> + Send>,
+}
+
 /// Deconstructed parts of a `Connection`.
 ///
 /// This allows taking apart a `Connection` at a later time, in order to
@@ -109,13 +165,16 @@ pub struct Parts<T> {
     _inner: (),
 }
 
-// ========== internal client api
-
-/// A `Future` for when `SendRequest::poll_ready()` is ready.
-pub(super) struct WhenReady<B> {
+/// A future returned by `SendRequest::ready`.
+///
+/// Yields the `SendRequest` once it can send a request.
+#[must_use = "futures do nothing unless polled"]
+pub struct WhenReady<B> {
     tx: Option<SendRequest<B>>,
 }
 
+// ========== internal client api
+
 // A `SendRequest` that can be cloned to send HTTP2 requests.
 // private for now, probably not a great idea of a type...
 pub(super) struct Http2SendRequest<B> {
@@ -124,7 +183,7 @@ pub(super) struct Http2SendRequest<B> {
 
 #[must_use = "futures do nothing unless polled"]
 pub(super) struct HandshakeNoUpgrades<T, B> {
-    inner: HandshakeInner<T, B, proto::ClientTransaction>,
+    inner: HandshakeInner<T, B, NoUpgradeTransaction>,
 }
 
 struct HandshakeInner<T, B, R> {
@@ -133,23 +192,97 @@ struct HandshakeInner<T, B, R> {
     _marker: PhantomData<(B, R)>,
 }
 
+// What an HTTP/1 connection needs to go on as HTTP/2, once the server
+// accepted an offer to upgrade with `h2c`.
+#[cfg(all(feature = "http1", feature = "http2"))]
+struct H2cUpgrade {
+    chunk_size: ChunkSize,
+    exec: Exec,
+    settings: Option<SettingsSlot>,
+    ping_rtt: Option<PingRtt>,
+}
+
+// The protocol of a connection.
+pub(super) enum ProtoClient<T, B, R>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    B: Payload + 'static,
+{
+    #[cfg(feature = "http1")]
+    H1(proto::h1::Dispatcher<proto::h1::dispatch::Client<B>, B, T, R>),
+    // `R` is the HTTP/1 transaction, so it is unused here.
+    #[cfg(feature = "http2")]
+    H2(proto::h2::Client<T, B>, PhantomData<R>),
+}
+
+// The HTTP/1 transaction of a connection, with or without upgrades.
+#[cfg(feature = "http1")]
+pub(super) trait ClientTransaction: proto::h1::Http1Transaction<Incoming=StatusCode, Outgoing=proto::RequestLine> {}
+
+#[cfg(feature = "http1")]
+impl<R> ClientTransaction for R
+where
+    R: proto::h1::Http1Transaction<Incoming=StatusCode, Outgoing=proto::RequestLine>,
+{}
+
+#[cfg(feature = "http1")]
+type UpgradeTransaction = proto::ClientUpgradeTransaction;
+#[cfg(feature = "http1")]
+type NoUpgradeTransaction = proto::ClientTransaction;
+
+// Without HTTP/1, there is no transaction to choose.
+#[cfg(not(feature = "http1"))]
+pub(super) trait ClientTransaction {}
+
+#[cfg(not(feature = "http1"))]
+impl ClientTransaction for () {}
+
+#[cfg(not(feature = "http1"))]
+type UpgradeTransaction = ();
+#[cfg(not(feature = "http1"))]
+type NoUpgradeTransaction = ();
+
 // ===== impl SendRequest
 
 impl<B> SendRequest<B>
 {
     /// Polls to determine whether this sender can be used yet for a request.
     ///
+    /// An HTTP/1 connection is ready once it can write another request,
+    /// usually after the response to the previous one was received, unless
+    /// `Builder::http1_queue_depth` allows queueing more. An HTTP/2
+    /// connection is ready while it has fewer streams open than the
+    /// server's limit of concurrent streams.
+    ///
     /// If the associated connection is closed, this returns an Error.
     pub fn poll_ready(&mut self) -> Poll<(), ::Error> {
         self.dispatch.poll_ready()
     }
 
-    pub(super) fn when_ready(self) -> WhenReady<B> {
+    /// Wait until this sender can be used for a request.
+    ///
+    /// This is the same as `poll_ready`, as a future yielding back the
+    /// `SendRequest`, so that a request sent afterwards isn't canceled
+    /// because the connection wasn't ready. The future errors if the
+    /// associated connection is closed.
+    pub fn ready(self) -> WhenReady<B> {
         WhenReady {
             tx: Some(self),
         }
     }
 
+    /// The settings the server advertised, if this is an HTTP/2 connection
+    /// made with [`Builder::http2_peer_settings`](Builder::http2_peer_settings)
+    /// enabled.
+    ///
+    /// This is `None` for other connections, and until the server's first
+    /// `SETTINGS` frame was read and acknowledged, which is usually by the
+    /// time the response to the first request arrives. Later `SETTINGS`
+    /// frames update them.
+    pub fn peer_settings(&self) -> Option<PeerSettings> {
+        self.peer_settings.get()
+    }
+
     pub(super) fn is_ready(&self) -> bool {
         self.dispatch.is_ready()
     }
@@ -184,7 +317,8 @@ where
     ///   the `Host` header based on it. You must add a `Host` header yourself
     ///   before calling this method.
     /// - Since absolute-form `Uri`s are not required, if received, they will
-    ///   be serialized as-is.
+    ///   be serialized as-is, unless a [`RequestTarget`](RequestTarget) is
+    ///   set.
     ///
     /// # Example
     ///
@@ -240,8 +374,48 @@ where
         }
     }
 
-    //TODO: replace with `impl Future` when stable
-    pub(crate) fn send_request_retryable(&mut self, req: Request<B>) -> Box<Future<Item=Response<Body>, Error=(::Error, Option<Request<B>>)> + Send>
+    /// Sends a request, given as its head and body separately, on the
+    /// associated connection.
+    ///
+    /// This is the same as `send_request`, except that if the connection
+    /// was not ready to send a request, the head and body are given back,
+    /// instead of being dropped. This allows trying again on a different
+    /// connection without needing to reconstruct the body, such as when it
+    /// is a stream that cannot be cloned.
+    pub fn send_request_parts(&mut self, head: ::http::request::Parts, body: B) -> Result<ResponseFuture, (::Error, ::http::request::Parts, B)> {
+        match self.dispatch.send(Request::from_parts(head, body)) {
+            Ok(rx) => {
+                let inner = rx.then(move |res| {
+                    match res {
+                        Ok(Ok(res)) => Ok(res),
+                        Ok(Err(err)) => Err(err),
+                        // this is definite bug if it happens, but it shouldn't happen!
+                        Err(_) => panic!("dispatch dropped without returning error"),
+                    }
+                });
+                Ok(ResponseFuture {
+                    inner: Box::new(inner),
+                })
+            },
+            Err(req) => {
+                debug!("connection was not ready");
+                let err = ::Error::new_canceled(Some("connection was not ready"));
+                let (head, body) = req.into_parts();
+                Err((err, head, body))
+            }
+        }
+    }
+
+    /// Sends a request on the associated connection, giving it back if it
+    /// could not be sent.
+    ///
+    /// This is the same as `send_request`, except that if the request was
+    /// never written to the connection, such as when the connection was not
+    /// ready, or was closed before the request could be written, the error
+    /// comes with the `Request`. It can then be sent again on a different
+    /// connection. If the request may have been sent, the error comes with
+    /// `None` instead.
+    pub fn try_send_request(&mut self, req: Request<B>) -> TryResponseFuture<B>
     where
         B: Send,
     {
@@ -262,7 +436,9 @@ where
                 Either::B(future::err((err, Some(req))))
             }
         };
-        Box::new(inner)
+        TryResponseFuture {
+            inner: Box::new(inner),
+        }
     }
 }
 
@@ -352,22 +528,56 @@ where
 {
     /// Return the inner IO object, and additional information.
     ///
-    /// Only works for HTTP/1 connections. HTTP/2 connections will panic.
-    pub fn into_parts(self) -> Parts<T> {
-        let (io, read_buf, _) = match self.inner {
-            Either::A(h1) => h1.into_inner(),
-            Either::B(_h2) => {
-                panic!("http2 cannot into_inner");
-            }
-        };
+    /// Only works for HTTP/1 connections, HTTP/2 connections return `None`.
+    pub fn into_parts(self) -> Option<Parts<T>> {
+        match self.inner.unwrap() {
+            #[cfg(feature = "http1")]
+            ProtoClient::H1(h1) => {
+                let (io, read_buf, _) = h1.into_inner();
+                Some(Parts {
+                    io: io,
+                    read_buf: read_buf,
+                    _inner: (),
+                })
+            },
+            #[cfg(feature = "http2")]
+            ProtoClient::H2(..) => None,
+        }
+    }
+
+    /// Returns how many request header values containing `CR`, `LF`, or
+    /// `NUL` bytes have been found on this connection.
+    ///
+    /// Values are only checked if a
+    /// [`HeaderValuePolicy`](HeaderValuePolicy) was configured, and
+    /// HTTP/2 connections always return `0`.
+    pub fn invalid_header_values(&self) -> usize {
+        match *self.inner.as_ref().unwrap() {
+            #[cfg(feature = "http1")]
+            ProtoClient::H1(ref h1) => h1.invalid_header_values(),
+            #[cfg(feature = "http2")]
+            ProtoClient::H2(..) => 0,
+        }
+    }
 
-        Parts {
-            io: io,
-            read_buf: read_buf,
-            _inner: (),
+    /// Get the byte and request counters of this connection.
+    pub fn stats(&self) -> ConnectionStats {
+        match *self.inner.as_ref().unwrap() {
+            #[cfg(feature = "http1")]
+            ProtoClient::H1(ref h1) => h1.stats(),
+            #[cfg(feature = "http2")]
+            ProtoClient::H2(ref h2, _) => h2.stats().clone(),
         }
     }
 
+    /// Get why this connection closed, once it has finished.
+    ///
+    /// For an HTTP/2 connection, this includes the error code of a `GOAWAY`
+    /// from the peer.
+    pub fn close_reason(&self) -> Option<&CloseReason> {
+        self.close_reason.as_ref()
+    }
+
     /// Poll the connection for completion, but without calling `shutdown`
     /// on the underlying IO.
     ///
@@ -376,13 +586,40 @@ where
     /// but it is not desired to actally shutdown the IO object. Instead you
     /// would take it back using `into_parts`.
     pub fn poll_without_shutdown(&mut self) -> Poll<(), ::Error> {
-        match self.inner {
-            Either::A(ref mut h1) => {
+        let ret = match *self.inner.as_mut().unwrap() {
+            #[cfg(feature = "http1")]
+            ProtoClient::H1(ref mut h1) => {
                 h1.poll_without_shutdown()
             },
-            Either::B(ref mut h2) => {
+            #[cfg(feature = "http2")]
+            ProtoClient::H2(ref mut h2, _) => {
                 h2.poll()
             }
+        };
+        match ret? {
+            Async::Ready(()) => self.poll_h2c(),
+            Async::NotReady => self.poll_write_timeout(),
+        }
+    }
+
+    /// Run the connection until it is done with HTTP, and then yield its
+    /// `Parts`, without calling `shutdown` on the underlying IO.
+    ///
+    /// This combines `poll_without_shutdown` and `into_parts`, such as to
+    /// take back the IO object after an HTTP upgrade. The future fails
+    /// right away if the connection isn't HTTP/1.
+    pub fn without_shutdown(self) -> WithoutShutdown<T, B> {
+        WithoutShutdown {
+            conn: Some(self),
+        }
+    }
+
+    // Whether this is an HTTP/1 connection, which can be taken apart.
+    fn is_http1(&self) -> bool {
+        match self.inner {
+            #[cfg(feature = "http1")]
+            Some(ProtoClient::H1(..)) => true,
+            _ => false,
         }
     }
 }
@@ -396,7 +633,112 @@ where
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        self.inner.poll()
+        let res = self.poll_inner();
+        if let Some(reason) = CloseReason::after(&res) {
+            self.close_reason = Some(reason);
+        }
+        res
+    }
+}
+
+impl<T, B> Connection<T, B>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    B: Payload + 'static,
+{
+    fn poll_inner(&mut self) -> Poll<(), ::Error> {
+        match self.inner.poll()? {
+            Async::Ready(_) => self.poll_h2c(),
+            Async::NotReady => {
+                self.poll_write_timeout()?;
+                self.poll_idle_timeout()
+            },
+        }
+    }
+
+    #[cfg(feature = "runtime")]
+    fn poll_idle_timeout(&mut self) -> Poll<(), ::Error> {
+        let stats = self.stats();
+        let is_idle = match self.idle_timeout {
+            Some(ref mut timeout) => timeout.poll(&stats).is_ready(),
+            None => false,
+        };
+        if !is_idle {
+            return Ok(Async::NotReady);
+        }
+        self.idle_timeout = None;
+        match *self.inner.as_mut().unwrap() {
+            // Closing an idle HTTP/1 connection shuts down the IO.
+            #[cfg(feature = "http1")]
+            ProtoClient::H1(ref mut h1) => {
+                h1.disable_keep_alive();
+                h1.poll()
+            },
+            // The HTTP/2 connection task shuts down once this is dropped.
+            #[cfg(feature = "http2")]
+            ProtoClient::H2(..) => Ok(Async::Ready(())),
+        }
+    }
+
+    #[cfg(not(feature = "runtime"))]
+    fn poll_idle_timeout(&mut self) -> Poll<(), ::Error> {
+        Ok(Async::NotReady)
+    }
+
+    // Fails the connection, and the HTTP/1 request waiting on it, once
+    // writing has been stuck for too long.
+    #[cfg(feature = "runtime")]
+    fn poll_write_timeout(&mut self) -> Poll<(), ::Error> {
+        let stats = self.stats();
+        let res = match self.write_timeout {
+            Some(ref mut timeout) => timeout.poll(&stats),
+            None => Ok(Async::NotReady),
+        };
+        if res.is_err() {
+            self.write_timeout = None;
+            #[cfg(feature = "http1")]
+            {
+                if let Some(ProtoClient::H1(ref mut h1)) = self.inner {
+                    h1.dispatch_mut().fail_in_flight(::Error::new_write_timeout());
+                }
+            }
+        }
+        res
+    }
+
+    #[cfg(not(feature = "runtime"))]
+    fn poll_write_timeout(&mut self) -> Poll<(), ::Error> {
+        Ok(Async::NotReady)
+    }
+
+    // An HTTP/1 connection whose offer to upgrade was accepted goes on as
+    // HTTP/2, once the `101` was read.
+    #[cfg(all(feature = "http1", feature = "http2"))]
+    fn poll_h2c(&mut self) -> Poll<(), ::Error> {
+        let cb = match self.inner {
+            Some(ProtoClient::H1(ref mut h1)) => h1.dispatch_mut().take_h2c_callback(),
+            _ => None,
+        };
+        let (cb, h2c) = match (cb, self.h2c.take()) {
+            (Some(cb), Some(h2c)) => (cb, h2c),
+            _ => return Ok(Async::Ready(())),
+        };
+        let (io, read_buf, dispatch) = match self.inner.take() {
+            Some(ProtoClient::H1(h1)) => h1.into_inner(),
+            _ => unreachable!("h2c accepted by an HTTP/1 connection"),
+        };
+        trace!("upgrading connection to h2c");
+        let mut h2 = proto::h2::Client::upgraded(io, read_buf, dispatch.into_rx(), h2c.exec, h2c.chunk_size, h2c.settings, cb);
+        if let Some(ping_rtt) = h2c.ping_rtt {
+            h2.set_ping_rtt(ping_rtt);
+        }
+        self.inner = Some(ProtoClient::H2(h2, PhantomData));
+        self.inner.poll().map(|ready| ready.map(|_| ()))
+    }
+
+    #[cfg(not(all(feature = "http1", feature = "http2")))]
+    fn poll_h2c(&mut self) -> Poll<(), ::Error> {
+        Ok(Async::Ready(()))
     }
 }
 
@@ -411,6 +753,45 @@ where
     }
 }
 
+// ===== impl WithoutShutdown
+
+impl<T, B> Future for WithoutShutdown<T, B>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    B: Payload + 'static,
+{
+    type Item = Parts<T>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let conn = self.conn.as_mut().expect("polled after complete");
+            // Only HTTP/1 can be taken apart, so an HTTP/2 connection fails
+            // at once, instead of being driven until it closes.
+            if !conn.is_http1() {
+                return Err(::Error::new_user_unsupported_into_parts());
+            }
+            try_ready!(conn.poll_without_shutdown());
+        }
+        let conn = self.conn.take().expect("polled after complete");
+        match conn.into_parts() {
+            Some(parts) => Ok(Async::Ready(parts)),
+            None => Err(::Error::new_user_unsupported_into_parts()),
+        }
+    }
+}
+
+impl<T, B> fmt::Debug for WithoutShutdown<T, B>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    B: Payload + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WithoutShutdown")
+            .finish()
+    }
+}
+
 // ===== impl Builder
 
 impl Builder {
@@ -418,10 +799,30 @@ impl Builder {
     #[inline]
     pub fn new() -> Builder {
         Builder {
+            chunk_size: ChunkSize::default(),
             exec: Exec::Default,
+            h1_body_buffer: BufferLimit::default(),
             h1_writev: true,
             h1_title_case_headers: false,
+            h1_header_value_policy: None,
+            h1_chunk_extensions: false,
+            h1_legacy_keep_alive: true,
+            h1_lenient: proto::Lenient::default(),
+            h1_max_buf_size: None,
+            h1_max_headers: None,
+            h1_preserve_header_case: false,
+            h1_preserve_header_order: false,
+            h1_queue_depth: 0,
+            h1_request_target: None,
+            h1_h2c_upgrade: false,
             http2: false,
+            http2_peer_settings: false,
+            #[cfg(feature = "http2")]
+            http2_ping_rtt: None,
+            #[cfg(feature = "runtime")]
+            idle_timeout: None,
+            #[cfg(feature = "runtime")]
+            write_timeout: None,
         }
     }
 
@@ -440,6 +841,304 @@ impl Builder {
         self
     }
 
+    /// Set how HTTP/1 connections treat request header values that contain
+    /// `CR`, `LF`, or `NUL` bytes.
+    ///
+    /// Default is to not check header values.
+    pub fn http1_header_value_policy(&mut self, policy: HeaderValuePolicy) -> &mut Builder {
+        self.h1_header_value_policy = Some(policy);
+        self
+    }
+
+    pub(super) fn h1_header_value_policy(&mut self, policy: Option<HeaderValuePolicy>) -> &mut Builder {
+        self.h1_header_value_policy = policy;
+        self
+    }
+
+    /// Set the maximum buffer size of HTTP/1 connections.
+    ///
+    /// A response head that doesn't fit fails the request with an error
+    /// for which `is_parse_too_large` is true, so a server can't make the
+    /// connection buffer more than `max` bytes.
+    ///
+    /// Default is ~400kb.
+    ///
+    /// # Panics
+    ///
+    /// The minimum value allowed is 8192. This method panics if the passed `max` is less than the minimum.
+    pub fn http1_max_buf_size(&mut self, max: usize) -> &mut Builder {
+        assert!(
+            max >= proto::MINIMUM_MAX_BUFFER_SIZE,
+            "the http1_max_buf_size cannot be smaller than the minimum that h1 specifies."
+        );
+        self.h1_max_buf_size = Some(max);
+        self
+    }
+
+    pub(super) fn h1_max_buf_size(&mut self, max: Option<usize>) -> &mut Builder {
+        self.h1_max_buf_size = max;
+        self
+    }
+
+    /// Set the maximum number of headers of a response on HTTP/1
+    /// connections.
+    ///
+    /// A response with more headers fails the request with an error for
+    /// which `is_parse_too_large` is true. Up to 100 headers are parsed
+    /// on the stack; a larger maximum allocates room for them on every
+    /// response.
+    ///
+    /// Default is 100.
+    pub fn http1_max_headers(&mut self, max: usize) -> &mut Builder {
+        self.h1_max_headers = Some(max);
+        self
+    }
+
+    pub(super) fn h1_max_headers(&mut self, max: Option<usize>) -> &mut Builder {
+        self.h1_max_headers = max;
+        self
+    }
+
+    /// Set whether HTTP/1 connections keep the original casing of response
+    /// header names.
+    ///
+    /// When enabled, every `Response` has a [`HeaderCaseMap`](HeaderCaseMap)
+    /// in its extensions, with each header name as it was received.
+    ///
+    /// Whether enabled or not, a `HeaderCaseMap` in the extensions of a
+    /// `Request` sets the casing of its header names, taking precedence over
+    /// title case.
+    ///
+    /// Default is false.
+    pub fn http1_preserve_header_case(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_preserve_header_case = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections keep the original order of response
+    /// header lines.
+    ///
+    /// When enabled, every `Response` has a [`HeaderOrder`](HeaderOrder) in
+    /// its extensions, with each header line in the order it was received.
+    ///
+    /// Whether enabled or not, a `HeaderOrder` in the extensions of a
+    /// `Request` sets the order its header lines are written in.
+    ///
+    /// Default is false.
+    pub fn http1_preserve_header_order(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_preserve_header_order = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections offer to upgrade to HTTP/2 over
+    /// cleartext.
+    ///
+    /// When enabled, the first request offers `Upgrade: h2c`, with an
+    /// `HTTP2-Settings` header, unless it has a body, or an `Upgrade` of
+    /// its own. If the server answers `101 Switching Protocols`, the
+    /// connection goes on as HTTP/2, and the response to that request is
+    /// read from its first stream. Otherwise, it stays HTTP/1.
+    ///
+    /// This needs the `http2` feature, and has no effect without it, or
+    /// with `http2_only`.
+    ///
+    /// Default is false.
+    pub fn http1_h2c_upgrade(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_h2c_upgrade = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections keep the extensions of chunks of
+    /// response bodies sent with chunked transfer-encoding.
+    ///
+    /// When enabled, they are available from the body's
+    /// [`Chunk::extensions`](::Chunk::extensions). Otherwise, they are
+    /// skipped. Extensions set on the `Chunk`s of a request body are sent
+    /// either way.
+    ///
+    /// Default is false.
+    pub fn http1_chunk_extensions(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_chunk_extensions = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1.0 requests can keep connections alive.
+    ///
+    /// When enabled, an HTTP/1.0 request is sent with
+    /// `Connection: keep-alive`, unless it has a `Connection` header
+    /// already, and the connection can send another request if the
+    /// response has `Connection: keep-alive` too. Otherwise, connections
+    /// are closed after every HTTP/1.0 request.
+    ///
+    /// Requests are sent as HTTP/1.0 once the server has answered with an
+    /// HTTP/1.0 response.
+    ///
+    /// Default is true.
+    pub fn http1_legacy_keep_alive(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_legacy_keep_alive = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections accept header values folded over
+    /// several lines.
+    ///
+    /// Obsolete line folding continues a header value on a line starting
+    /// with a space or tab. When allowed, each line ending before such a
+    /// line is replaced with spaces, and the value includes the rest.
+    /// Otherwise, a response with folded headers fails the request with a
+    /// parse error.
+    ///
+    /// Default is false.
+    pub fn http1_allow_obsolete_line_folding(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_lenient.obsolete_line_folding = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections accept HTTP/0.9 responses.
+    ///
+    /// An HTTP/0.9 server, or a broken device, may answer with just the
+    /// body, without a status line or headers. When allowed, a response
+    /// that doesn't start with a status line is read as a `200 OK` of
+    /// version `HTTP/0.9`, without headers, whose body is everything
+    /// received until the connection closes. Otherwise, such a response
+    /// fails the request with a parse error.
+    ///
+    /// Default is false.
+    pub fn http1_allow_http09_responses(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_lenient.http09_responses = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections accept whitespace around header names.
+    ///
+    /// When allowed, whitespace between a header name and its colon, such
+    /// as in `Server : legacy`, or before the first header name of a
+    /// response, is ignored. Otherwise, such a response fails the request
+    /// with a parse error.
+    ///
+    /// A status line without a reason phrase, such as `HTTP/1.1 200`, is
+    /// always accepted.
+    ///
+    /// Default is false.
+    pub fn http1_allow_whitespace_around_header_names(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_lenient.whitespace_around_header_names = enabled;
+        self
+    }
+
+    /// Set the maximum number of response body bytes that an HTTP/1
+    /// connection holds, after reading them, until the `Body` is polled.
+    ///
+    /// By default, a connection holds a single chunk, of up to the size of
+    /// its read buffer. With a maximum, it keeps reading until `max` bytes
+    /// are held, splitting a chunk that doesn't fit, and so a slow consumer
+    /// costs no more than `max` bytes, apart from the read buffer.
+    ///
+    /// Default is no maximum.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the passed `max` is 0.
+    pub fn http1_max_body_buffer(&mut self, max: usize) -> &mut Builder {
+        assert!(max > 0, "the http1_max_body_buffer cannot be 0");
+        self.h1_body_buffer.max = Some(max);
+        self
+    }
+
+    /// Set how many held response body bytes make an HTTP/1 connection stop
+    /// reading from its transport.
+    ///
+    /// Reading resumes once the `Body` was polled below `bytes` again. If a
+    /// maximum is also set, and is smaller, the maximum is used.
+    ///
+    /// Default is the `http1_max_body_buffer`, if set.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the passed `bytes` is 0.
+    pub fn http1_body_read_threshold(&mut self, bytes: usize) -> &mut Builder {
+        assert!(bytes > 0, "the http1_body_read_threshold cannot be 0");
+        self.h1_body_buffer.stop_at = Some(bytes);
+        self
+    }
+
+    /// Set how many requests can wait to be written on an HTTP/1
+    /// connection, while it is busy with another one.
+    ///
+    /// The requests wait in a queue in the `Connection`, and are still
+    /// written one at a time, each once the previous response is complete.
+    /// A queue lets `SendRequest` accept a burst of requests without
+    /// waiting on `poll_ready` between each of them. A request sent before
+    /// the `Connection` has started on the previous one counts as waiting
+    /// as well. If the connection closes, the queued requests fail as
+    /// canceled.
+    ///
+    /// Default is 0, a request can only be sent once the connection is
+    /// ready for it.
+    pub fn http1_queue_depth(&mut self, depth: usize) -> &mut Builder {
+        self.h1_queue_depth = depth;
+        self
+    }
+
+    /// Set the form of the request-target of requests on HTTP/1
+    /// connections.
+    ///
+    /// See [`RequestTarget`](RequestTarget) for details. A request can
+    /// change this with a
+    /// [`RequestConfig`](::client::RequestConfig::request_target) in its
+    /// extensions.
+    ///
+    /// Default is to write the `Uri` of a request as it is.
+    pub fn http1_request_target(&mut self, target: RequestTarget) -> &mut Builder {
+        self.h1_request_target = Some(target);
+        self
+    }
+
+    pub(super) fn h1_request_target(&mut self, target: Option<RequestTarget>) -> &mut Builder {
+        self.h1_request_target = target;
+        self
+    }
+
+    pub(super) fn h1_body_buffer(&mut self, limit: BufferLimit) -> &mut Builder {
+        self.h1_body_buffer = limit;
+        self
+    }
+
+    /// Set the maximum size of each chunk of a response body.
+    ///
+    /// Larger reads from the connection are split into several chunks.
+    ///
+    /// Default is no maximum.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the passed `max` is 0.
+    pub fn max_chunk_size(&mut self, max: usize) -> &mut Builder {
+        assert!(max > 0, "the max_chunk_size cannot be 0");
+        self.chunk_size.max = Some(max);
+        self
+    }
+
+    /// Set the minimum size of each chunk of a response body.
+    ///
+    /// Smaller reads from the connection are held back, and joined with the
+    /// following ones, until there are at least `min` bytes, or the body
+    /// ends. Only the last chunk of a body can be smaller. If a maximum is
+    /// also set, and is smaller, the maximum wins.
+    ///
+    /// Note that this delays a chunk until more of the body is received, so
+    /// it shouldn't be used for bodies that are streamed interactively.
+    ///
+    /// Default is no minimum.
+    pub fn min_chunk_size(&mut self, min: usize) -> &mut Builder {
+        self.chunk_size.min = Some(min);
+        self
+    }
+
+    pub(super) fn chunk_size(&mut self, size: ChunkSize) -> &mut Builder {
+        self.chunk_size = size;
+        self
+    }
+
     /// Sets whether HTTP2 is required.
     ///
     /// Default is false.
@@ -448,6 +1147,64 @@ impl Builder {
         self
     }
 
+    /// Set whether HTTP/2 connections keep the settings the server
+    /// advertised, for [`SendRequest::peer_settings`](SendRequest::peer_settings).
+    ///
+    /// The HTTP/2 library hyper uses doesn't expose them, so when enabled,
+    /// the frames read and written are walked to find them, which costs a
+    /// little on every read and write.
+    ///
+    /// Default is false.
+    pub fn http2_peer_settings(&mut self, enabled: bool) -> &mut Builder {
+        self.http2_peer_settings = enabled;
+        self
+    }
+
+    // Ping HTTP/2 connections once their handshake is done, passing how
+    // long the server took to answer to `ping_rtt`.
+    #[cfg(feature = "http2")]
+    pub(super) fn http2_ping_rtt(&mut self, ping_rtt: PingRtt) -> &mut Builder {
+        self.http2_ping_rtt = Some(ping_rtt);
+        self
+    }
+
+    /// Set how long a connection may go unused before it is closed.
+    ///
+    /// A connection is unused while no request sent on it is waiting for
+    /// its response, and no bytes are read or written, such as of a
+    /// response body. Once the timeout is reached, the `Connection` shuts
+    /// down and resolves, so that connections managed outside of a `Client`
+    /// can expire without a timer of their own.
+    ///
+    /// This requires the `runtime` feature.
+    ///
+    /// Default is `None`, waiting indefinitely.
+    #[cfg(feature = "runtime")]
+    pub fn idle_timeout(&mut self, dur: Option<Duration>) -> &mut Builder {
+        self.idle_timeout = dur;
+        self
+    }
+
+    /// Set how long a connection may go without writing anything, while
+    /// it has bytes waiting to be written, before it is closed.
+    ///
+    /// A server that stops reading, such as one that went away without
+    /// closing the connection, shows up as a write that doesn't make
+    /// progress, rather than as a response that's late. Once the timeout
+    /// is reached, the `Connection` resolves with an error where
+    /// `Error::is_write_timeout` is true, and an HTTP/1 request waiting for
+    /// its response fails with the same error. Any bytes written start the
+    /// timeout over, so servers that read slowly aren't affected.
+    ///
+    /// This requires the `runtime` feature.
+    ///
+    /// Default is `None`, waiting indefinitely.
+    #[cfg(feature = "runtime")]
+    pub fn write_timeout(&mut self, dur: Option<Duration>) -> &mut Builder {
+        self.write_timeout = dur;
+        self
+    }
+
     /// Constructs a connection with the configured options and IO.
     #[inline]
     pub fn handshake<T, B>(&self, io: T) -> Handshake<T, B>
@@ -460,10 +1217,30 @@ impl Builder {
                 builder: self.clone(),
                 io: Some(io),
                 _marker: PhantomData,
-            }
+            },
+            #[cfg(feature = "unstable-completion-io")]
+            owned_io: None,
         }
     }
 
+    /// Constructs a connection with the configured options, on a
+    /// completion based IO.
+    ///
+    /// HTTP/1 connections hand their buffers to the IO, instead of lending
+    /// them, see the [`completion`](::completion) module.
+    ///
+    /// This requires the `unstable-completion-io` feature.
+    #[cfg(feature = "unstable-completion-io")]
+    pub fn handshake_completion<C, B>(&self, io: C) -> Handshake<Completion<C>, B>
+    where
+        C: CompletionIo + Send + 'static,
+        B: Payload + 'static,
+    {
+        let mut handshake = self.handshake(Completion::new(io));
+        handshake.owned_io = Some(Completion::owned_io());
+        handshake
+    }
+
     pub(super) fn handshake_no_upgrades<T, B>(&self, io: T) -> HandshakeNoUpgrades<T, B>
     where
         T: AsyncRead + AsyncWrite + Send + 'static,
@@ -490,12 +1267,44 @@ where
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        self.inner.poll()
-            .map(|async| {
-                async.map(|(tx, dispatch)| {
-                    (tx, Connection { inner: dispatch })
-                })
+        let (tx, dispatch) = try_ready!(self.inner.poll());
+        #[cfg(feature = "unstable-completion-io")]
+        let dispatch = {
+            let mut dispatch = dispatch;
+            if let (Some(owned_io), &mut ProtoClient::H1(ref mut h1)) = (self.owned_io, &mut dispatch) {
+                h1.set_owned_io(owned_io);
+            }
+            dispatch
+        };
+        #[cfg(feature = "runtime")]
+        let idle_timeout = self.inner.builder.idle_timeout.map(|dur| {
+            IdleTimeout::new(dur, tx.dispatch.in_flight())
+        });
+        #[cfg(all(feature = "http1", feature = "http2"))]
+        let h2c = if self.inner.builder.h1_h2c_upgrade && !self.inner.builder.http2 {
+            Some(H2cUpgrade {
+                chunk_size: self.inner.builder.chunk_size,
+                exec: self.inner.builder.exec.clone(),
+                settings: if self.inner.builder.http2_peer_settings {
+                    Some(tx.peer_settings.clone())
+                } else {
+                    None
+                },
+                ping_rtt: self.inner.builder.http2_ping_rtt.clone(),
             })
+        } else {
+            None
+        };
+        Ok(Async::Ready((tx, Connection {
+            inner: Some(dispatch),
+            close_reason: None,
+            #[cfg(all(feature = "http1", feature = "http2"))]
+            h2c,
+            #[cfg(feature = "runtime")]
+            idle_timeout,
+            #[cfg(feature = "runtime")]
+            write_timeout: self.inner.builder.write_timeout.map(WriteTimeout::new),
+        })))
     }
 }
 
@@ -511,15 +1320,7 @@ where
     T: AsyncRead + AsyncWrite + Send + 'static,
     B: Payload + 'static,
 {
-    type Item = (SendRequest<B>, Either<
-        proto::h1::Dispatcher<
-            proto::h1::dispatch::Client<B>,
-            B,
-            T,
-            proto::ClientTransaction,
-        >,
-        proto::h2::Client<T, B>,
-    >);
+    type Item = (SendRequest<B>, ProtoClient<T, B, NoUpgradeTransaction>);
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
@@ -530,51 +1331,134 @@ where
 impl<T, B, R> Future for HandshakeInner<T, B, R>
 where
     T: AsyncRead + AsyncWrite + Send + 'static,
-    B: Payload,
-    R: proto::h1::Http1Transaction<
-        Incoming=StatusCode,
-        Outgoing=proto::RequestLine,
-    >,
+    B: Payload + 'static,
+    R: ClientTransaction,
 {
-    type Item = (SendRequest<B>, Either<
-        proto::h1::Dispatcher<
-            proto::h1::dispatch::Client<B>,
-            B,
-            T,
-            R,
-        >,
-        proto::h2::Client<T, B>,
-    >);
+    type Item = (SendRequest<B>, ProtoClient<T, B, R>);
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let io = self.io.take().expect("polled more than once");
-        let (tx, rx) = dispatch::channel();
-        let either = if !self.builder.http2 {
-            let mut conn = proto::Conn::new(io);
-            if !self.builder.h1_writev {
-                conn.set_write_strategy_flatten();
-            }
-            if self.builder.h1_title_case_headers {
-                conn.set_title_case_headers();
-            }
-            let cd = proto::h1::dispatch::Client::new(rx);
-            let dispatch = proto::h1::Dispatcher::new(cd, conn);
-            Either::A(dispatch)
+        let (tx, rx) = if !self.builder.http2 {
+            dispatch::channel_with_depth(self.builder.h1_queue_depth)
         } else {
-            let h2 = proto::h2::Client::new(io, rx, self.builder.exec.clone());
-            Either::B(h2)
+            dispatch::channel()
+        };
+        let peer_settings = SettingsSlot::new();
+        let proto = if !self.builder.http2 {
+            self.http1(io, rx)?
+        } else {
+            let settings = if self.builder.http2_peer_settings {
+                Some(peer_settings.clone())
+            } else {
+                None
+            };
+            self.http2(io, rx, settings)?
         };
 
         Ok(Async::Ready((
             SendRequest {
                 dispatch: tx,
+                peer_settings,
             },
-            either,
+            proto,
         )))
     }
 }
 
+impl<T, B, R> HandshakeInner<T, B, R>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    B: Payload + 'static,
+    R: ClientTransaction,
+{
+    #[cfg(feature = "http1")]
+    fn http1(&self, io: T, rx: dispatch::Receiver<Request<B>, Response<Body>>) -> ::Result<ProtoClient<T, B, R>> {
+        let mut conn = proto::Conn::new(io);
+        if !self.builder.h1_writev {
+            conn.set_write_strategy_flatten();
+        }
+        if self.builder.h1_title_case_headers {
+            conn.set_title_case_headers();
+        }
+        if let Some(policy) = self.builder.h1_header_value_policy {
+            conn.set_header_value_policy(policy);
+        }
+        if let Some(max) = self.builder.h1_max_buf_size {
+            conn.set_max_buf_size(max);
+        }
+        if let Some(max) = self.builder.h1_max_headers {
+            conn.set_max_headers(max);
+        }
+        if self.builder.h1_preserve_header_case {
+            conn.set_preserve_header_case();
+        }
+        if self.builder.h1_preserve_header_order {
+            conn.set_preserve_header_order();
+        }
+        if !self.builder.h1_legacy_keep_alive {
+            conn.disable_legacy_keep_alive();
+        }
+        if self.builder.h1_chunk_extensions {
+            conn.set_keep_chunk_extensions();
+        }
+        conn.set_lenient(self.builder.h1_lenient);
+        let mut cd = proto::h1::dispatch::Client::new(rx);
+        if let Some(target) = self.builder.h1_request_target {
+            cd.set_request_target(target);
+        }
+        if cfg!(feature = "http2") && self.builder.h1_h2c_upgrade {
+            cd.set_h2c_offer();
+        }
+        let mut dispatch = proto::h1::Dispatcher::new(cd, conn);
+        dispatch.set_chunk_size(self.builder.chunk_size);
+        dispatch.set_body_buffer_limit(self.builder.h1_body_buffer);
+        Ok(ProtoClient::H1(dispatch))
+    }
+
+    #[cfg(not(feature = "http1"))]
+    fn http1(&self, _io: T, _rx: dispatch::Receiver<Request<B>, Response<Body>>) -> ::Result<ProtoClient<T, B, R>> {
+        debug!("HTTP/1 connection, but the http1 feature is disabled");
+        Err(::Error::new_user_unsupported_version())
+    }
+
+    #[cfg(feature = "http2")]
+    fn http2(&self, io: T, rx: dispatch::Receiver<Request<B>, Response<Body>>, settings: Option<SettingsSlot>) -> ::Result<ProtoClient<T, B, R>> {
+        let mut h2 = proto::h2::Client::new(io, rx, self.builder.exec.clone(), self.builder.chunk_size, settings);
+        if let Some(ref ping_rtt) = self.builder.http2_ping_rtt {
+            h2.set_ping_rtt(ping_rtt.clone());
+        }
+        Ok(ProtoClient::H2(h2, PhantomData))
+    }
+
+    #[cfg(not(feature = "http2"))]
+    fn http2(&self, _io: T, _rx: dispatch::Receiver<Request<B>, Response<Body>>, _settings: Option<SettingsSlot>) -> ::Result<ProtoClient<T, B, R>> {
+        debug!("HTTP/2 connection, but the http2 feature is disabled");
+        Err(::Error::new_user_unsupported_version())
+    }
+}
+
+// ===== impl ProtoClient
+
+impl<T, B, R> Future for ProtoClient<T, B, R>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    B: Payload + 'static,
+    R: ClientTransaction,
+{
+    type Item = ();
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match *self {
+            #[cfg(feature = "http1")]
+            ProtoClient::H1(ref mut h1) => h1.poll(),
+            #[cfg(feature = "http2")]
+            ProtoClient::H2(ref mut h2, _) => h2.poll(),
+        }
+    }
+}
+
 // ===== impl ResponseFuture
 
 impl Future for ResponseFuture {
@@ -594,8 +1478,34 @@ impl fmt::Debug for ResponseFuture {
     }
 }
 
+// ===== impl TryResponseFuture
+
+impl<B> Future for TryResponseFuture<B> {
+    type Item = Response<Body>;
+    type Error = (::Error, Option<Request<B>>);
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+impl<B> fmt::Debug for TryResponseFuture<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TryResponseFuture")
+            .finish()
+    }
+}
+
 // ===== impl WhenReady
 
+impl<B> fmt::Debug for WhenReady<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WhenReady")
+            .finish()
+    }
+}
+
 impl<B> Future for WhenReady<B> {
     type Item = SendRequest<B>;
     type Error = ::Error;
@@ -642,3 +1552,9 @@ impl AssertSendSync for Builder {}
 #[doc(hidden)]
 impl AssertSend for ResponseFuture {}
 
+#[doc(hidden)]
+impl<B> AssertSend for TryResponseFuture<B> {}
+
+#[doc(hidden)]
+impl<B: Send> AssertSendSync for WhenReady<B> {}
+
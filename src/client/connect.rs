@@ -6,9 +6,13 @@
 //!   establishes connections over TCP.
 //! - The [`Connect`](Connect) trait and related types to build custom connectors.
 use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
 
 use futures::Future;
-use http::Uri;
+use http::{Extensions, Uri};
 use tokio_io::{AsyncRead, AsyncWrite};
 
 #[cfg(feature = "runtime")] pub use self::http::HttpConnector;
@@ -34,6 +38,8 @@ pub trait Connect: Send + Sync {
 pub struct Destination {
     //pub(super) alpn: Alpn,
     pub(super) uri: Uri,
+    pub(super) addr: Option<SocketAddr>,
+    pub(super) early_data: bool,
 }
 
 /// Extra information about the connected transport.
@@ -42,18 +48,65 @@ pub struct Destination {
 /// was used, or if connected to an HTTP proxy.
 #[derive(Debug)]
 pub struct Connected {
-    //alpn: Alpn,
+    pub(super) addr: Option<SocketAddr>,
+    pub(super) alpn: Alpn,
+    pub(super) extra: Option<Extra>,
     pub(super) is_proxied: bool,
+    pub(super) rtt: Option<Duration>,
 }
 
-/*TODO: when HTTP1 Upgrades to H2 are added, this will be needed
-#[derive(Debug)]
+/// An error connecting to a destination, telling which step of connecting
+/// failed, and the address it was trying to reach.
+///
+/// The `Client` gives the errors of the HTTP handshake on a new connection
+/// as a `ConnectError`, and connectors can return one, or an `io::Error`
+/// wrapping one, for their own steps, as the `HttpConnector` and the TLS
+/// connectors do. It is available from
+/// [`Error::connect_error`](::Error::connect_error).
+///
+/// This helps with deciding what to do next: a failed DNS lookup may be
+/// worth trying again later, while a server whose TLS certificate isn't
+/// trusted won't be any different on a retry.
+pub struct ConnectError {
+    phase: ConnectPhase,
+    addr: Option<SocketAddr>,
+    cause: Box<StdError + Send + Sync>,
+}
+
+/// A step of connecting to a destination, that a
+/// [`ConnectError`](ConnectError) happened in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectPhase {
+    /// Resolving the host name into addresses.
+    Dns,
+    /// Opening a TCP connection to one of the addresses.
+    Tcp,
+    /// The TLS handshake, such as when the server's certificate isn't
+    /// trusted.
+    Tls,
+    /// The HTTP handshake on the connected transport, such as when the
+    /// server doesn't speak HTTP/2.
+    Handshake,
+}
+
+// Values set with `Connected::extra`, that are copied into the extensions of
+// every `Response` received on the connection.
+pub(super) struct Extra(Box<ExtraInner>);
+
+trait ExtraInner: Send + Sync {
+    fn clone_box(&self) -> Box<ExtraInner>;
+    fn set(&self, ext: &mut Extensions);
+}
+
+struct ExtraEnvelope<T>(T);
+
+struct ExtraChain<T>(Box<ExtraInner>, T);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub(super) enum Alpn {
     Http1,
-    //H2,
-    //Http1OrH2
+    H2,
 }
-*/
 
 impl Destination {
     /// Get the protocol scheme.
@@ -79,6 +132,30 @@ impl Destination {
         self.uri.port()
     }
 
+    /// Get the address to connect to, if it was set with
+    /// [`RequestConfig::resolve`](::client::RequestConfig::resolve).
+    ///
+    /// If set, the host should not be resolved, but it should still be used
+    /// for anything else, such as TLS server name indication.
+    #[inline]
+    pub fn resolved_addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// Whether the transport may be returned before its TLS handshake
+    /// completes, to send the first request as TLS 1.3 early data.
+    ///
+    /// This is only true if the request the connection is made for is
+    /// idempotent, and the `Client` has
+    /// [`http1_early_data`](::client::Builder::http1_early_data) enabled.
+    /// Nothing else is written to the transport until a response is read,
+    /// which needs the handshake to complete. If the server rejects the
+    /// early data, the transport must send it again afterwards.
+    #[inline]
+    pub fn early_data(&self) -> bool {
+        self.early_data
+    }
+
     /*
     /// Returns whether this connection must negotiate HTTP/2 via ALPN.
     pub fn must_h2(&self) -> bool {
@@ -94,11 +171,29 @@ impl Connected {
     /// Create new `Connected` type with empty metadata.
     pub fn new() -> Connected {
         Connected {
-            //alpn: Alpn::Http1,
+            addr: None,
+            alpn: Alpn::Http1,
+            extra: None,
             is_proxied: false,
+            rtt: None,
         }
     }
 
+    /// Set a value to be put in the extensions of every `Response` received
+    /// on the connection.
+    ///
+    /// This can be used by connectors to tell about things like the
+    /// certificate of a TLS peer, the proxy used, or how long connecting
+    /// took. Setting values of several types keeps all of them, and setting
+    /// a type again replaces the previous value.
+    pub fn extra<T: Clone + Send + Sync + 'static>(mut self, extra: T) -> Connected {
+        self.extra = Some(match self.extra.take() {
+            Some(prev) => Extra(Box::new(ExtraChain(prev.0, extra))),
+            None => Extra(Box::new(ExtraEnvelope(extra))),
+        });
+        self
+    }
+
     /// Set whether the connected transport is to an HTTP proxy.
     ///
     /// This setting will affect if HTTP/1 requests written on the transport
@@ -111,14 +206,164 @@ impl Connected {
         self
     }
 
-    /*
     /// Set that the connected transport negotiated HTTP/2 as it's
     /// next protocol.
+    ///
+    /// The `Client` then speaks HTTP/2 on the connection, and shares it
+    /// between requests, even if it wasn't configured with `http2_only`.
     pub fn h2(mut self) -> Connected {
         self.alpn = Alpn::H2;
         self
     }
-    */
+
+    /// Check if the connected transport negotiated HTTP/2.
+    pub fn is_h2(&self) -> bool {
+        self.alpn == Alpn::H2
+    }
+
+    /// Set how long a single round-trip to the destination took while
+    /// connecting, such as the TCP handshake.
+    ///
+    /// The `Client` uses it to estimate the round-trip time to the host,
+    /// see [`Client::origin_stats`](::Client::origin_stats). The
+    /// `HttpConnector` sets the time its TCP connect took, so connectors
+    /// wrapping it can pass on its `Connected`.
+    pub fn rtt(mut self, rtt: Duration) -> Connected {
+        self.rtt = Some(rtt);
+        self
+    }
+}
+
+// ===== impl ConnectError =====
+
+impl ConnectError {
+    /// Create a new `ConnectError` for a failed `phase`.
+    pub fn new<E>(phase: ConnectPhase, cause: E) -> ConnectError
+    where
+        E: Into<Box<StdError + Send + Sync>>,
+    {
+        ConnectError {
+            phase,
+            addr: None,
+            cause: cause.into(),
+        }
+    }
+
+    /// Set the address that connecting to failed.
+    pub fn with_addr<A: Into<Option<SocketAddr>>>(mut self, addr: A) -> ConnectError {
+        self.addr = addr.into();
+        self
+    }
+
+    /// Get the step of connecting that failed.
+    pub fn phase(&self) -> ConnectPhase {
+        self.phase
+    }
+
+    /// Get the address that connecting to failed, if known.
+    ///
+    /// A failed DNS lookup has no address.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+}
+
+impl fmt::Debug for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConnectError")
+            .field("phase", &self.phase)
+            .field("addr", &self.addr)
+            .field("cause", &self.cause)
+            .finish()
+    }
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.addr {
+            Some(addr) => write!(f, "{} ({}): {}", self.phase.as_str(), addr, self.cause),
+            None => write!(f, "{}: {}", self.phase.as_str(), self.cause),
+        }
+    }
+}
+
+impl StdError for ConnectError {
+    fn description(&self) -> &str {
+        self.phase.as_str()
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        Some(&*self.cause)
+    }
+}
+
+impl ConnectPhase {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            ConnectPhase::Dns => "dns error",
+            ConnectPhase::Tcp => "tcp connect error",
+            ConnectPhase::Tls => "tls handshake error",
+            ConnectPhase::Handshake => "http handshake error",
+        }
+    }
+}
+
+impl From<ConnectError> for io::Error {
+    fn from(err: ConnectError) -> io::Error {
+        let kind = match err.cause.downcast_ref::<io::Error>() {
+            Some(cause) => cause.kind(),
+            None => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+// ===== impl Extra =====
+
+impl Extra {
+    pub(super) fn set(&self, ext: &mut Extensions) {
+        self.0.set(ext);
+    }
+}
+
+impl Clone for Extra {
+    fn clone(&self) -> Extra {
+        Extra(self.0.clone_box())
+    }
+}
+
+impl fmt::Debug for Extra {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Extra")
+            .finish()
+    }
+}
+
+impl<T> ExtraInner for ExtraEnvelope<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    fn clone_box(&self) -> Box<ExtraInner> {
+        Box::new(ExtraEnvelope(self.0.clone()))
+    }
+
+    fn set(&self, ext: &mut Extensions) {
+        ext.insert(self.0.clone());
+    }
+}
+
+impl<T> ExtraInner for ExtraChain<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    fn clone_box(&self) -> Box<ExtraInner> {
+        Box::new(ExtraChain(self.0.clone_box(), self.1.clone()))
+    }
+
+    fn set(&self, ext: &mut Extensions) {
+        self.0.set(ext);
+        ext.insert(self.1.clone());
+    }
 }
 
 #[cfg(feature = "runtime")]
@@ -131,7 +376,8 @@ mod http {
     use std::mem;
     use std::net::{IpAddr, SocketAddr};
     use std::sync::Arc;
-    use std::time::Duration;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
 
     use futures::{Async, Poll};
     use futures::future::{Executor, ExecuteError};
@@ -189,6 +435,7 @@ mod http {
         keep_alive_timeout: Option<Duration>,
         nodelay: bool,
         local_address: Option<IpAddr>,
+        round_robin: Option<Arc<AtomicUsize>>,
     }
 
     impl HttpConnector {
@@ -226,6 +473,7 @@ mod http {
                 keep_alive_timeout: None,
                 nodelay: false,
                 local_address: None,
+                round_robin: None,
             }
         }
 
@@ -264,6 +512,24 @@ mod http {
         pub fn set_local_address(&mut self, addr: Option<IpAddr>) {
             self.local_address = addr;
         }
+
+        /// Set whether to rotate which resolved address is connected to first.
+        ///
+        /// When a host resolves to several addresses, each new connection
+        /// starts with the address after the one the previous connection
+        /// started with, spreading connections across all of them. The other
+        /// addresses are still tried if connecting fails. Clones of this
+        /// connector share the rotation.
+        ///
+        /// Default is `false`, always starting with the first address.
+        #[inline]
+        pub fn set_round_robin(&mut self, enabled: bool) {
+            if !enabled {
+                self.round_robin = None;
+            } else if self.round_robin.is_none() {
+                self.round_robin = Some(Arc::new(AtomicUsize::new(0)));
+            }
+        }
     }
 
     impl fmt::Debug for HttpConnector {
@@ -304,11 +570,21 @@ mod http {
                 None => if dst.uri.scheme_part() == Some(&Scheme::HTTPS) { 443 } else { 80 },
             };
 
+            let state = match dst.addr {
+                Some(addr) => State::Connecting(ConnectingTcp {
+                    addrs: dns::IpAddrs::new(vec![addr]),
+                    local_addr: self.local_address,
+                    current: None,
+                }),
+                None => State::Lazy(self.executor.clone(), host.into(), port, self.local_address),
+            };
+
             HttpConnecting {
-                state: State::Lazy(self.executor.clone(), host.into(), port, self.local_address),
+                state: state,
                 handle: self.handle.clone(),
                 keep_alive_timeout: self.keep_alive_timeout,
                 nodelay: self.nodelay,
+                round_robin: self.round_robin.clone(),
             }
         }
     }
@@ -320,6 +596,7 @@ mod http {
             handle: handle.clone(),
             keep_alive_timeout: None,
             nodelay: false,
+            round_robin: None,
         }
     }
 
@@ -352,6 +629,7 @@ mod http {
         handle: Option<Handle>,
         keep_alive_timeout: Option<Duration>,
         nodelay: bool,
+        round_robin: Option<Arc<AtomicUsize>>,
     }
 
     enum State {
@@ -385,9 +663,14 @@ mod http {
                         }
                     },
                     State::Resolving(ref mut future, local_addr) => {
-                        match try!(future.poll()) {
+                        let addrs = future.poll()
+                            .map_err(|e| ConnectError::new(ConnectPhase::Dns, e))?;
+                        match addrs {
                             Async::NotReady => return Ok(Async::NotReady),
-                            Async::Ready(addrs) => {
+                            Async::Ready(mut addrs) => {
+                                if let Some(ref next) = self.round_robin {
+                                    addrs.rotate(next.fetch_add(1, Ordering::Relaxed));
+                                }
                                 state = State::Connecting(ConnectingTcp {
                                     addrs: addrs,
                                     local_addr: local_addr,
@@ -397,7 +680,7 @@ mod http {
                         };
                     },
                     State::Connecting(ref mut c) => {
-                        let sock = try_ready!(c.poll(&self.handle));
+                        let (sock, rtt) = try_ready!(c.poll(&self.handle));
 
                         if let Some(dur) = self.keep_alive_timeout {
                             sock.set_keepalive(Some(dur))?;
@@ -405,7 +688,9 @@ mod http {
 
                         sock.set_nodelay(self.nodelay)?;
 
-                        return Ok(Async::Ready((sock, Connected::new())));
+                        let mut connected = Connected::new().rtt(rtt);
+                        connected.addr = sock.peer_addr().ok();
+                        return Ok(Async::Ready((sock, connected)));
                     },
                     State::Error(ref mut e) => return Err(e.take().expect("polled more than once")),
                 }
@@ -423,34 +708,36 @@ mod http {
     struct ConnectingTcp {
         addrs: dns::IpAddrs,
         local_addr: Option<IpAddr>,
-        current: Option<ConnectFuture>,
+        current: Option<(SocketAddr, Instant, ConnectFuture)>,
     }
 
     impl ConnectingTcp {
         // not a Future, since passing a &Handle to poll
-        fn poll(&mut self, handle: &Option<Handle>) -> Poll<TcpStream, io::Error> {
+        //
+        // The TCP handshake of the address that connected is a round-trip.
+        fn poll(&mut self, handle: &Option<Handle>) -> Poll<(TcpStream, Duration), io::Error> {
             let mut err = None;
             loop {
-                if let Some(ref mut current) = self.current {
+                if let Some((ref addr, started, ref mut current)) = self.current {
                     match current.poll() {
-                        Ok(ok) => return Ok(ok),
+                        Ok(Async::Ready(sock)) => return Ok(Async::Ready((sock, started.elapsed()))),
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
                         Err(e) => {
                             trace!("connect error {:?}", e);
-                            err = Some(e);
-                            if let Some(addr) = self.addrs.next() {
-                                debug!("connecting to {}", addr);
-                                *current = connect(&addr, &self.local_addr, handle)?;
-                                continue;
-                            }
+                            err = Some(ConnectError::new(ConnectPhase::Tcp, e).with_addr(*addr));
                         }
                     }
-                } else if let Some(addr) = self.addrs.next() {
-                    debug!("connecting to {}", addr);
-                    self.current = Some(connect(&addr, &self.local_addr, handle)?);
-                    continue;
                 }
 
-                return Err(err.take().expect("missing connect error"));
+                match self.addrs.next() {
+                    Some(addr) => {
+                        debug!("connecting to {}", addr);
+                        let current = connect(&addr, &self.local_addr, handle)
+                            .map_err(|e| ConnectError::new(ConnectPhase::Tcp, e).with_addr(addr))?;
+                        self.current = Some((addr, Instant::now(), current));
+                    },
+                    None => return Err(err.take().expect("missing connect error").into()),
+                }
             }
         }
     }
@@ -500,6 +787,8 @@ mod http {
             let uri = "/foo/bar?baz".parse().unwrap();
             let dst = Destination {
                 uri,
+                addr: None,
+                early_data: false,
             };
             let connector = HttpConnector::new(1);
 
@@ -511,6 +800,8 @@ mod http {
             let uri = "https://example.domain/foo/bar?baz".parse().unwrap();
             let dst = Destination {
                 uri,
+                addr: None,
+                early_data: false,
             };
             let connector = HttpConnector::new(1);
 
@@ -523,6 +814,8 @@ mod http {
             let uri = "example.domain".parse().unwrap();
             let dst = Destination {
                 uri,
+                addr: None,
+                early_data: false,
             };
             let connector = HttpConnector::new(1);
 
@@ -531,3 +824,49 @@ mod http {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use http::Extensions;
+
+    use super::{ConnectError, ConnectPhase, Connected};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Ex1(u8);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Ex2(&'static str);
+
+    #[test]
+    fn extra_keeps_every_type() {
+        let connected = Connected::new()
+            .extra(Ex1(1))
+            .extra(Ex2("two"))
+            .extra(Ex1(3));
+
+        let mut ext = Extensions::new();
+        connected.extra.as_ref().unwrap().clone().set(&mut ext);
+        assert_eq!(ext.get::<Ex1>(), Some(&Ex1(3)));
+        assert_eq!(ext.get::<Ex2>(), Some(&Ex2("two")));
+    }
+
+    #[test]
+    fn connect_error_survives_io_error() {
+        let addr = "127.0.0.1:8080".parse().unwrap();
+        let cause = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+        let err = io::Error::from(ConnectError::new(ConnectPhase::Tcp, cause).with_addr(addr));
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+
+        let err = ::Error::new_connect(err);
+        assert!(err.is_connect());
+        let connect_err = err.connect_error().expect("connect_error");
+        assert_eq!(connect_err.phase(), ConnectPhase::Tcp);
+        assert_eq!(connect_err.addr(), Some(addr));
+        assert_eq!(connect_err.to_string(), "tcp connect error (127.0.0.1:8080): refused");
+
+        let err = ::Error::new_connect(ConnectError::new(ConnectPhase::Dns, "no such host"));
+        assert_eq!(err.connect_error().expect("connect_error").phase(), ConnectPhase::Dns);
+        assert!(::Error::new_connect("other").connect_error().is_none());
+    }
+}
@@ -1,3 +1,6 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use futures::{Async, Poll, Stream};
 use futures::sync::{mpsc, oneshot};
 use want;
@@ -8,15 +11,29 @@ pub type RetryPromise<T, U> = oneshot::Receiver<Result<U, (::Error, Option<T>)>>
 pub type Promise<T> = oneshot::Receiver<Result<T, ::Error>>;
 
 pub fn channel<T, U>() -> (Sender<T, U>, Receiver<T, U>) {
+    channel_with_depth(0)
+}
+
+/// A channel where up to `depth` messages can wait for the Receiver, even
+/// if it hasn't asked for more.
+pub fn channel_with_depth<T, U>(depth: usize) -> (Sender<T, U>, Receiver<T, U>) {
     let (tx, rx) = mpsc::unbounded();
     let (giver, taker) = want::new();
+    let queued = Arc::new(AtomicUsize::new(0));
+    let in_flight = Arc::new(AtomicUsize::new(0));
     let tx = Sender {
         buffered_once: false,
+        depth: depth,
+        queued: queued.clone(),
+        #[cfg(feature = "runtime")]
+        in_flight: in_flight.clone(),
         giver: giver,
         inner: tx,
     };
     let rx = Receiver {
         inner: rx,
+        queued: queued,
+        in_flight: in_flight,
         taker: taker,
     };
     (tx, rx)
@@ -31,12 +48,20 @@ pub struct Sender<T, U> {
     /// for it yet. This boolean keeps track of whether we've sent one
     /// without notice.
     buffered_once: bool,
+    /// Messages are also allowed while fewer than `depth` are waiting in
+    /// the channel, so that they queue up behind the one the Receiver is
+    /// working on.
+    depth: usize,
+    queued: Arc<AtomicUsize>,
+    /// The messages taken by the Receiver, whose callback isn't done yet.
+    #[cfg(feature = "runtime")]
+    in_flight: Arc<AtomicUsize>,
     /// The Giver helps watch that the the Receiver side has been polled
     /// when the queue is empty. This helps us know when a request and
     /// response have been fully processed, and a connection is ready
     /// for more.
     giver: want::Giver,
-    /// Actually bounded by the Giver, plus `buffered_once`.
+    /// Actually bounded by the Giver, plus `buffered_once` and `depth`.
     inner: mpsc::UnboundedSender<Envelope<T, U>>,
 }
 
@@ -47,29 +72,47 @@ pub struct Sender<T, U> {
 pub struct UnboundedSender<T, U> {
     /// Only used for `is_closed`, since mpsc::UnboundedSender cannot be checked.
     giver: want::SharedGiver,
+    queued: Arc<AtomicUsize>,
     inner: mpsc::UnboundedSender<Envelope<T, U>>,
 }
 
 impl<T, U> Sender<T, U> {
     pub fn poll_ready(&mut self) -> Poll<(), ::Error> {
+        if self.can_queue() && !self.giver.is_canceled() {
+            return Ok(Async::Ready(()));
+        }
         self.giver.poll_want()
             .map_err(|_| ::Error::new_closed())
     }
 
     pub fn is_ready(&self) -> bool {
-        self.giver.is_wanting()
+        self.giver.is_wanting() || self.can_queue()
     }
 
     pub fn is_closed(&self) -> bool {
         self.giver.is_canceled()
     }
 
+    /// The number of messages taken by the Receiver, whose callback isn't
+    /// done yet.
+    #[cfg(feature = "runtime")]
+    pub fn in_flight(&self) -> Arc<AtomicUsize> {
+        self.in_flight.clone()
+    }
+
+    fn can_queue(&self) -> bool {
+        self.queued.load(Ordering::SeqCst) < self.depth
+    }
+
     fn can_send(&mut self) -> bool {
-        if self.giver.give() || !self.buffered_once {
+        if self.giver.give() || !self.buffered_once || self.can_queue() {
             // If the receiver is ready *now*, then of course we can send.
             //
             // If the receiver isn't ready yet, but we don't have anything
             // in the channel yet, then allow one message.
+            //
+            // Otherwise, there may still be room in the queue.
+            self.queued.fetch_add(1, Ordering::SeqCst);
             self.buffered_once = true;
             true
         } else {
@@ -82,7 +125,7 @@ impl<T, U> Sender<T, U> {
             return Err(val);
         }
         let (tx, rx) = oneshot::channel();
-        self.inner.unbounded_send(Envelope(Some((val, Callback::Retry(tx)))))
+        self.inner.unbounded_send(Envelope(Some((val, Callback::retry(tx)))))
             .map(move |_| rx)
             .map_err(|e| e.into_inner().0.take().expect("envelope not dropped").0)
     }
@@ -92,7 +135,7 @@ impl<T, U> Sender<T, U> {
             return Err(val);
         }
         let (tx, rx) = oneshot::channel();
-        self.inner.unbounded_send(Envelope(Some((val, Callback::NoRetry(tx)))))
+        self.inner.unbounded_send(Envelope(Some((val, Callback::no_retry(tx)))))
             .map(move |_| rx)
             .map_err(|e| e.into_inner().0.take().expect("envelope not dropped").0)
     }
@@ -100,6 +143,7 @@ impl<T, U> Sender<T, U> {
     pub fn unbound(self) -> UnboundedSender<T, U> {
         UnboundedSender {
             giver: self.giver.shared(),
+            queued: self.queued,
             inner: self.inner,
         }
     }
@@ -115,8 +159,9 @@ impl<T, U> UnboundedSender<T, U> {
     }
 
     pub fn try_send(&mut self, val: T) -> Result<RetryPromise<T, U>, T> {
+        self.queued.fetch_add(1, Ordering::SeqCst);
         let (tx, rx) = oneshot::channel();
-        self.inner.unbounded_send(Envelope(Some((val, Callback::Retry(tx)))))
+        self.inner.unbounded_send(Envelope(Some((val, Callback::retry(tx)))))
             .map(move |_| rx)
             .map_err(|e| e.into_inner().0.take().expect("envelope not dropped").0)
     }
@@ -126,6 +171,7 @@ impl<T, U> Clone for UnboundedSender<T, U> {
     fn clone(&self) -> Self {
         UnboundedSender {
             giver: self.giver.clone(),
+            queued: self.queued.clone(),
             inner: self.inner.clone(),
         }
     }
@@ -133,6 +179,8 @@ impl<T, U> Clone for UnboundedSender<T, U> {
 
 pub struct Receiver<T, U> {
     inner: mpsc::UnboundedReceiver<Envelope<T, U>>,
+    queued: Arc<AtomicUsize>,
+    in_flight: Arc<AtomicUsize>,
     taker: want::Taker,
 }
 
@@ -143,7 +191,10 @@ impl<T, U> Stream for Receiver<T, U> {
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         match self.inner.poll() {
             Ok(Async::Ready(item)) => Ok(Async::Ready(item.map(|mut env| {
-                env.0.take().expect("envelope not dropped")
+                self.queued.fetch_sub(1, Ordering::SeqCst);
+                let (val, mut cb) = env.0.take().expect("envelope not dropped");
+                cb.in_flight = Some(InFlight::new(self.in_flight.clone()));
+                (val, cb)
             }))),
             Ok(Async::NotReady) => {
                 self.taker.want();
@@ -172,38 +223,75 @@ impl<T, U> Drop for Envelope<T, U> {
     }
 }
 
-pub enum Callback<T, U> {
+pub struct Callback<T, U> {
+    tx: CallbackTx<T, U>,
+    in_flight: Option<InFlight>,
+}
+
+enum CallbackTx<T, U> {
     Retry(oneshot::Sender<Result<U, (::Error, Option<T>)>>),
     NoRetry(oneshot::Sender<Result<U, ::Error>>),
 }
 
+// Counts a message as in flight, until its callback is done.
+struct InFlight(Arc<AtomicUsize>);
+
 impl<T, U> Callback<T, U> {
+    fn retry(tx: oneshot::Sender<Result<U, (::Error, Option<T>)>>) -> Callback<T, U> {
+        Callback {
+            tx: CallbackTx::Retry(tx),
+            in_flight: None,
+        }
+    }
+
+    fn no_retry(tx: oneshot::Sender<Result<U, ::Error>>) -> Callback<T, U> {
+        Callback {
+            tx: CallbackTx::NoRetry(tx),
+            in_flight: None,
+        }
+    }
+
     pub fn poll_cancel(&mut self) -> Poll<(), ()> {
-        match *self {
-            Callback::Retry(ref mut tx) => tx.poll_cancel(),
-            Callback::NoRetry(ref mut tx) => tx.poll_cancel(),
+        match self.tx {
+            CallbackTx::Retry(ref mut tx) => tx.poll_cancel(),
+            CallbackTx::NoRetry(ref mut tx) => tx.poll_cancel(),
         }
     }
 
     pub fn send(self, val: Result<U, (::Error, Option<T>)>) {
-        match self {
-            Callback::Retry(tx) => {
+        match self.tx {
+            CallbackTx::Retry(tx) => {
                 let _ = tx.send(val);
             },
-            Callback::NoRetry(tx) => {
+            CallbackTx::NoRetry(tx) => {
                 let _ = tx.send(val.map_err(|e| e.0));
             }
         }
     }
 }
 
+impl InFlight {
+    fn new(count: Arc<AtomicUsize>) -> InFlight {
+        count.fetch_add(1, Ordering::SeqCst);
+        InFlight(count)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate pretty_env_logger;
     #[cfg(feature = "nightly")]
     extern crate test;
 
-    use futures::{future, Future, Stream};
+    use std::sync::atomic::Ordering;
+
+    use futures::{future, Async, Future, Stream};
 
 
     #[derive(Debug)]
@@ -257,6 +345,43 @@ mod tests {
         }).wait().unwrap();
     }
 
+    #[test]
+    fn sender_queues_up_to_depth() {
+        future::lazy(|| {
+            let (mut tx, mut rx) = super::channel_with_depth::<Custom, ()>(2);
+            let _ = tx.try_send(Custom(1)).expect("1 queued");
+            let _ = tx.try_send(Custom(2)).expect("2 queued");
+            assert!(!tx.is_ready());
+            tx.try_send(Custom(3)).expect_err("3 over depth");
+
+            assert!(rx.poll().expect("rx 1").is_ready());
+            assert!(tx.is_ready());
+            let _ = tx.try_send(Custom(3)).expect("3 queued");
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[cfg(feature = "runtime")]
+    #[test]
+    fn in_flight_counts_until_callback_done() {
+        future::lazy(|| {
+            let (mut tx, mut rx) = super::channel::<Custom, ()>();
+            let in_flight = tx.in_flight();
+            let _ = tx.try_send(Custom(1)).expect("1 buffered");
+            assert_eq!(in_flight.load(Ordering::SeqCst), 0);
+
+            let (_, cb) = match rx.poll().expect("rx 1") {
+                Async::Ready(Some(msg)) => msg,
+                _ => panic!("rx 1 not ready"),
+            };
+            assert_eq!(in_flight.load(Ordering::SeqCst), 1);
+            cb.send(Ok(()));
+            assert_eq!(in_flight.load(Ordering::SeqCst), 0);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
     #[test]
     fn unbounded_sender_doesnt_bound_on_want() {
         let (tx, rx) = super::channel::<Custom, ()>();
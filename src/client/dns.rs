@@ -35,6 +35,10 @@ pub struct IpAddrs {
 }
 
 impl IpAddrs {
+    pub fn new(addrs: Vec<SocketAddr>) -> IpAddrs {
+        IpAddrs { iter: addrs.into_iter() }
+    }
+
     pub fn try_parse(host: &str, port: u16) -> Option<IpAddrs> {
         if let Ok(addr) = host.parse::<Ipv4Addr>() {
             let addr = SocketAddrV4::new(addr, port);
@@ -46,6 +50,18 @@ impl IpAddrs {
         }
         None
     }
+
+    /// Start with the address `n` places in, trying the skipped addresses
+    /// after the rest.
+    pub fn rotate(&mut self, n: usize) {
+        let addrs = self.iter.as_slice().to_vec();
+        if addrs.is_empty() {
+            return;
+        }
+        let (first, rest) = addrs.split_at(n % addrs.len());
+        let rotated = rest.iter().chain(first).cloned().collect::<Vec<_>>();
+        self.iter = rotated.into_iter();
+    }
 }
 
 impl Iterator for IpAddrs {
@@ -55,3 +71,22 @@ impl Iterator for IpAddrs {
         self.iter.next()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::IpAddrs;
+
+    #[test]
+    fn rotate_wraps_around() {
+        let addrs = vec![
+            "127.0.0.1:80".parse::<SocketAddr>().unwrap(),
+            "127.0.0.2:80".parse().unwrap(),
+            "127.0.0.3:80".parse().unwrap(),
+        ];
+        let mut ips = IpAddrs { iter: addrs.clone().into_iter() };
+        ips.rotate(4);
+        assert_eq!(ips.collect::<Vec<_>>(), vec![addrs[1], addrs[2], addrs[0]]);
+    }
+}
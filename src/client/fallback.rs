@@ -0,0 +1,185 @@
+//! Detect if a server answers an HTTP/2 prior knowledge handshake.
+//!
+//! An HTTP/2 server must start its side of the connection with a `SETTINGS`
+//! frame. Servers that only speak HTTP/1 will instead reply with something
+//! like `HTTP/1.1 400 Bad Request`, or just close the connection. By watching
+//! the first frame header read from the transport, the `Client` can tell
+//! which happened, and fall back to HTTP/1.1.
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use bytes::Buf;
+use futures::{Future, Poll};
+#[cfg(feature = "runtime")]
+use futures::future::{self, Either};
+use futures::sync::oneshot;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+const FRAME_HEADER_LEN: usize = 9;
+const FRAME_TYPE_SETTINGS: u8 = 0x4;
+const FLAG_ACK: u8 = 0x1;
+
+/// Wrap an IO, so that the returned receiver will resolve with whether the
+/// server started the connection with an HTTP/2 `SETTINGS` frame.
+///
+/// If the IO is dropped before enough bytes were read, the receiver is
+/// canceled, which should be treated the same as not speaking HTTP/2.
+pub(super) fn sniff<T>(io: T) -> (Sniff<T>, oneshot::Receiver<bool>) {
+    let (tx, rx) = oneshot::channel();
+    let sniff = Sniff {
+        head: [0; FRAME_HEADER_LEN],
+        io,
+        read: 0,
+        tx: Some(tx),
+    };
+    (sniff, rx)
+}
+
+/// Wait for whether the server started with a `SETTINGS` frame, counting
+/// no answer within `timeout` as not speaking HTTP/2.
+///
+/// The timeout requires the `runtime` feature, and is ignored without it.
+pub(super) fn answered(rx: oneshot::Receiver<bool>, timeout: Option<Duration>) -> Box<Future<Item=bool, Error=::Error> + Send> {
+    // A canceled receiver means the connection closed before sending a
+    // frame header.
+    let answered = rx.then(|is_h2| Ok::<_, ::Error>(is_h2.unwrap_or(false)));
+    #[cfg(feature = "runtime")]
+    {
+        if let Some(dur) = timeout {
+            let delay = super::lazy_delay(dur);
+            return Box::new(answered.select2(delay).then(move |res| {
+                match res {
+                    Ok(Either::A((is_h2, _delay))) => Either::A(future::ok(is_h2)),
+                    Ok(Either::B(((), _answered))) => {
+                        debug!("no HTTP/2 SETTINGS frame within {:?}", dur);
+                        Either::A(future::ok(false))
+                    },
+                    Err(Either::A((e, _delay))) => Either::A(future::err(e)),
+                    Err(Either::B((timer_err, answered))) => {
+                        error!("couldn't time HTTP/2 SETTINGS, timer error: {}", timer_err);
+                        Either::B(answered)
+                    },
+                }
+            }));
+        }
+    }
+    #[cfg(not(feature = "runtime"))]
+    let _ = timeout;
+    Box::new(answered)
+}
+
+pub(super) struct Sniff<T> {
+    head: [u8; FRAME_HEADER_LEN],
+    io: T,
+    read: usize,
+    tx: Option<oneshot::Sender<bool>>,
+}
+
+impl<T> Sniff<T> {
+    fn observe(&mut self, buf: &[u8]) {
+        let tx = match self.tx.take() {
+            Some(tx) => tx,
+            None => return,
+        };
+
+        if buf.is_empty() {
+            trace!("EOF before HTTP/2 SETTINGS frame");
+            let _ = tx.send(false);
+            return;
+        }
+
+        let n = ::std::cmp::min(buf.len(), FRAME_HEADER_LEN - self.read);
+        self.head[self.read..self.read + n].copy_from_slice(&buf[..n]);
+        self.read += n;
+
+        if self.read == FRAME_HEADER_LEN {
+            let _ = tx.send(is_settings(&self.head));
+        } else {
+            self.tx = Some(tx);
+        }
+    }
+}
+
+/// Whether the frame header is that of a non-ACK `SETTINGS` frame on the
+/// connection stream.
+fn is_settings(head: &[u8; FRAME_HEADER_LEN]) -> bool {
+    head[3] == FRAME_TYPE_SETTINGS
+        && head[4] & FLAG_ACK == 0
+        && head[5..] == [0, 0, 0, 0]
+}
+
+impl<T: Read> Read for Sniff<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.io.read(buf)?;
+        self.observe(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Sniff<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Sniff<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Sniff<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        self.io.write_buf(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use futures::Future;
+
+    use mock::AsyncIo;
+    use super::sniff;
+
+    #[test]
+    fn settings_frame_is_http2() {
+        // SETTINGS, no flags, stream 0, with a single setting
+        let frame = b"\x00\x00\x06\x04\x00\x00\x00\x00\x00\x00\x03\x00\x00\x00\x64";
+        let (mut io, rx) = sniff(AsyncIo::new_buf(frame.to_vec(), 64));
+        let mut buf = [0; 32];
+
+        // frame header split across reads
+        assert_eq!(io.read(&mut buf[..4]).unwrap(), 4);
+        assert_eq!(io.read(&mut buf).unwrap(), frame.len() - 4);
+        assert_eq!(rx.wait(), Ok(true));
+    }
+
+    #[test]
+    fn http1_response_is_not_http2() {
+        let (mut io, rx) = sniff(AsyncIo::new_buf(b"HTTP/1.1 400 Bad Request\r\n\r\n".to_vec(), 64));
+        let mut buf = [0; 64];
+
+        io.read(&mut buf).unwrap();
+        assert_eq!(rx.wait(), Ok(false));
+    }
+
+    #[test]
+    fn eof_is_not_http2() {
+        let (mut io, rx) = sniff(AsyncIo::new_buf(Vec::new(), 64));
+        let mut buf = [0; 64];
+
+        assert_eq!(io.read(&mut buf).unwrap(), 0);
+        assert_eq!(rx.wait(), Ok(false));
+    }
+}
@@ -0,0 +1,214 @@
+//! Check that an idle HTTP/1 connection is still usable before reusing it.
+//!
+//! An idle connection only notices that the server closed it when its
+//! `Connection` task gets to read from the transport. The `Probe` asks that
+//! task to read once more, and waits until it has, so that a request isn't
+//! written into a connection that was already closed.
+
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use bytes::Buf;
+use futures::{Async, Poll};
+use futures::task::AtomicTask;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Wrap an IO, so that the returned `Probe` can check on it.
+///
+/// If not `enabled`, the IO is passed through untouched, and there is no
+/// `Probe`.
+pub(super) fn watch<T>(io: T, enabled: bool) -> (Watch<T>, Option<Probe>) {
+    if !enabled {
+        return (Watch { io, shared: None }, None);
+    }
+    let shared = Arc::new(Shared {
+        checker: AtomicTask::new(),
+        conn: AtomicTask::new(),
+        requested: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+        unhealthy: AtomicBool::new(false),
+    });
+    let probe = Probe {
+        pending: None,
+        shared: shared.clone(),
+    };
+    (Watch { io, shared: Some(shared) }, Some(probe))
+}
+
+struct Shared {
+    // The task waiting on the `Probe`.
+    checker: AtomicTask,
+    // The `Connection` task, which reads from the `Watch`.
+    conn: AtomicTask,
+    // The latest probe asked for, and the latest probe that was answered
+    // by a read.
+    requested: AtomicUsize,
+    read: AtomicUsize,
+    unhealthy: AtomicBool,
+}
+
+pub(super) struct Watch<T> {
+    io: T,
+    shared: Option<Arc<Shared>>,
+}
+
+impl<T> Watch<T> {
+    fn observe(&self, res: &io::Result<usize>) {
+        let shared = match self.shared {
+            Some(ref shared) => shared,
+            None => return,
+        };
+        shared.conn.register();
+
+        let is_closed = match *res {
+            Ok(0) => true,
+            Ok(_) => false,
+            Err(ref e) => e.kind() != io::ErrorKind::WouldBlock,
+        };
+        let requested = shared.requested.load(Ordering::SeqCst);
+        let is_probed = shared.read.load(Ordering::SeqCst) != requested;
+        // Any bytes read while probing are unexpected, since the connection
+        // is idle, and so the connection is about to close as well.
+        if is_closed || (is_probed && res.is_ok()) {
+            shared.unhealthy.store(true, Ordering::SeqCst);
+        }
+        if is_probed {
+            shared.read.store(requested, Ordering::SeqCst);
+            shared.checker.notify();
+        }
+    }
+}
+
+impl<T> Drop for Watch<T> {
+    fn drop(&mut self) {
+        // The `Connection` is done, don't leave a `Probe` waiting on it.
+        if let Some(ref shared) = self.shared {
+            shared.unhealthy.store(true, Ordering::SeqCst);
+            shared.checker.notify();
+        }
+    }
+}
+
+impl<T: Read> Read for Watch<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.io.read(buf);
+        self.observe(&res);
+        res
+    }
+}
+
+impl<T: Write> Write for Watch<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Watch<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Watch<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        self.io.write_buf(buf)
+    }
+}
+
+/// Asks the `Connection` task of a `Watch` to read, to see if the
+/// connection is still healthy.
+pub(super) struct Probe {
+    pending: Option<usize>,
+    shared: Arc<Shared>,
+}
+
+impl Probe {
+    pub(super) fn poll_healthy(&mut self) -> Async<bool> {
+        let shared = &self.shared;
+        shared.checker.register();
+        if shared.unhealthy.load(Ordering::SeqCst) {
+            return Async::Ready(false);
+        }
+
+        let probe = match self.pending {
+            Some(probe) => probe,
+            None => {
+                let probe = shared.requested.fetch_add(1, Ordering::SeqCst).wrapping_add(1);
+                trace!("probing idle connection");
+                self.pending = Some(probe);
+                shared.conn.notify();
+                probe
+            }
+        };
+
+        if shared.read.load(Ordering::SeqCst) == probe {
+            self.pending = None;
+            Async::Ready(!shared.unhealthy.load(Ordering::SeqCst))
+        } else {
+            Async::NotReady
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use futures::{Async, Future};
+    use futures::future;
+
+    use mock::AsyncIo;
+    use super::watch;
+
+    #[test]
+    fn probe_waits_for_a_read() {
+        future::lazy(|| {
+            let (mut io, probe) = watch(AsyncIo::new_buf(Vec::new(), 0), true);
+            let mut probe = probe.expect("enabled");
+            let mut buf = [0; 16];
+
+            // the response was read, and the connection is idle
+            assert!(io.read(&mut buf).is_err());
+            assert_eq!(probe.poll_healthy(), Async::NotReady);
+            assert!(io.read(&mut buf).is_err(), "still blocked");
+            assert_eq!(probe.poll_healthy(), Async::Ready(true));
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn probe_sees_eof() {
+        future::lazy(|| {
+            let (mut io, probe) = watch(AsyncIo::new_buf(Vec::new(), 64), true);
+            let mut probe = probe.expect("enabled");
+            let mut buf = [0; 16];
+
+            assert_eq!(probe.poll_healthy(), Async::NotReady);
+            assert_eq!(io.read(&mut buf).unwrap(), 0);
+            assert_eq!(probe.poll_healthy(), Async::Ready(false));
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn probe_sees_dropped_connection() {
+        future::lazy(|| {
+            let (io, probe) = watch(AsyncIo::new_buf(Vec::new(), 0), true);
+            let mut probe = probe.expect("enabled");
+
+            assert_eq!(probe.poll_healthy(), Async::NotReady);
+            drop(io);
+            assert_eq!(probe.poll_healthy(), Async::Ready(false));
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -0,0 +1,502 @@
+//! An HTTPS connector, using rustls.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use bytes::Buf;
+use futures::{Async, Future, Poll};
+use rustls::{ClientConfig, ClientSession, Session};
+use tokio_io::{AsyncRead, AsyncWrite};
+use webpki::DNSNameRef;
+use webpki_roots;
+
+use super::connect::{Connect, ConnectError, ConnectPhase, Connected, Destination, HttpConnector};
+
+/// A connector for the `https` and `http` schemes.
+///
+/// Connects with the wrapped connector, which is an `HttpConnector` by
+/// default, and then performs a TLS handshake for `https` destinations.
+///
+/// The handshake offers `h2` and `http/1.1` with ALPN. If the server picks
+/// `h2`, the `Connected` reports it, and the `Client` uses HTTP/2 on the
+/// connection.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// # fn main() {
+/// use hyper::Client;
+/// use hyper::client::HttpsConnector;
+///
+/// let client = Client::builder()
+///     .build::<_, hyper::Body>(HttpsConnector::new(4));
+/// # drop(client);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct HttpsConnector<T> {
+    enforce_https: bool,
+    http: T,
+    tls: Arc<ClientConfig>,
+}
+
+impl HttpsConnector<HttpConnector> {
+    /// Construct a new HttpsConnector, trusting the root certificates of
+    /// the `webpki-roots` crate.
+    ///
+    /// Takes number of DNS worker threads.
+    pub fn new(threads: usize) -> HttpsConnector<HttpConnector> {
+        let mut http = HttpConnector::new(threads);
+        http.enforce_http(false);
+        let mut tls = ClientConfig::new();
+        tls.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        HttpsConnector::with_connector(http, tls)
+    }
+}
+
+impl<T> HttpsConnector<T> {
+    /// Construct a new HttpsConnector, from a connector for the underlying
+    /// transport, and a TLS configuration.
+    ///
+    /// If the configuration doesn't set any ALPN protocols, `h2` and
+    /// `http/1.1` are offered. An `HttpConnector` needs `enforce_http`
+    /// disabled, to connect to `https` destinations.
+    ///
+    /// If the configuration has `enable_early_data` set, and only offers
+    /// `http/1.1`, a resumed session can send the first request as early
+    /// data, when the `Destination` allows it.
+    pub fn with_connector(http: T, mut tls: ClientConfig) -> HttpsConnector<T> {
+        if tls.alpn_protocols.is_empty() {
+            tls.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+        }
+        HttpsConnector {
+            enforce_https: false,
+            http,
+            tls: Arc::new(tls),
+        }
+    }
+
+    /// Option to enforce all `Uri`s have the `https` scheme.
+    ///
+    /// Default is `false`, `http` destinations are connected to without TLS.
+    #[inline]
+    pub fn enforce_https(&mut self, is_enforced: bool) {
+        self.enforce_https = is_enforced;
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for HttpsConnector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpsConnector")
+            .field("enforce_https", &self.enforce_https)
+            .field("http", &self.http)
+            .finish()
+    }
+}
+
+impl<T> Connect for HttpsConnector<T>
+where
+    T: Connect,
+{
+    type Transport = MaybeHttpsStream<T::Transport>;
+    type Error = io::Error;
+    type Future = HttpsConnecting<T>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let is_https = dst.scheme() == "https";
+        if !is_https && self.enforce_https {
+            return HttpsConnecting::error("invalid URL, scheme must be https");
+        }
+
+        // Session tickets don't remember the negotiated protocol, so the
+        // request written as early data could be in the wrong one.
+        let early_data = dst.early_data() && !self.tls.alpn_protocols.contains(&b"h2".to_vec());
+        let session = if is_https {
+            match DNSNameRef::try_from_ascii_str(dst.host()) {
+                Ok(name) => Some(ClientSession::new(&self.tls, name)),
+                Err(_) => return HttpsConnecting::error("invalid URL, host must be a DNS name"),
+            }
+        } else {
+            None
+        };
+
+        HttpsConnecting {
+            state: State::Connecting(self.http.connect(dst), session, early_data),
+        }
+    }
+}
+
+/// A Future representing work to connect to a URL, and perform a TLS
+/// handshake.
+#[must_use = "futures do nothing unless polled"]
+pub struct HttpsConnecting<T: Connect> {
+    state: State<T>,
+}
+
+enum State<T: Connect> {
+    Connecting(T::Future, Option<ClientSession>, bool),
+    Handshaking(Option<(TlsStream<T::Transport>, Connected)>),
+    Error(Option<io::Error>),
+}
+
+impl<T: Connect> HttpsConnecting<T> {
+    fn error(msg: &'static str) -> HttpsConnecting<T> {
+        HttpsConnecting {
+            state: State::Error(Some(io::Error::new(io::ErrorKind::InvalidInput, msg))),
+        }
+    }
+}
+
+impl<T: Connect> Future for HttpsConnecting<T> {
+    type Item = (MaybeHttpsStream<T::Transport>, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let state = match self.state {
+                State::Connecting(ref mut fut, ref mut session, early_data) => {
+                    let (io, connected) = try_ready!(fut.poll().map_err(into_io_error));
+                    match session.take() {
+                        Some(session) => {
+                            let tls = TlsStream::new(io, session, early_data);
+                            if tls.early_data.is_some() {
+                                // The handshake completes as the request
+                                // is written, and the response read.
+                                let io = MaybeHttpsStream {
+                                    inner: Inner::Https(tls),
+                                };
+                                return Ok(Async::Ready((io, connected)));
+                            }
+                            State::Handshaking(Some((tls, connected)))
+                        },
+                        None => {
+                            let io = MaybeHttpsStream {
+                                inner: Inner::Http(io),
+                            };
+                            return Ok(Async::Ready((io, connected)));
+                        }
+                    }
+                },
+                State::Handshaking(ref mut handshaking) => {
+                    {
+                        let &mut (ref mut tls, ref connected) = handshaking
+                            .as_mut()
+                            .expect("polled after complete");
+                        try_ready!(tls.poll_handshake().map_err(|e| {
+                            io::Error::from(ConnectError::new(ConnectPhase::Tls, e).with_addr(connected.addr))
+                        }));
+                    }
+                    let (tls, connected) = handshaking.take().expect("polled after complete");
+                    let connected = if tls.session.get_alpn_protocol() == Some(&b"h2"[..]) {
+                        connected.h2()
+                    } else {
+                        connected
+                    };
+                    let io = MaybeHttpsStream {
+                        inner: Inner::Https(tls),
+                    };
+                    return Ok(Async::Ready((io, connected)));
+                },
+                State::Error(ref mut e) => return Err(e.take().expect("polled more than once")),
+            };
+            self.state = state;
+        }
+    }
+}
+
+impl<T: Connect> fmt::Debug for HttpsConnecting<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("HttpsConnecting")
+    }
+}
+
+fn into_io_error<E: Into<Box<StdError + Send + Sync>>>(err: E) -> io::Error {
+    match err.into().downcast::<io::Error>() {
+        Ok(err) => *err,
+        Err(err) => io::Error::new(io::ErrorKind::Other, err),
+    }
+}
+
+/// A transport connected by an `HttpsConnector`, with TLS for `https`
+/// destinations.
+pub struct MaybeHttpsStream<T> {
+    inner: Inner<T>,
+}
+
+enum Inner<T> {
+    Http(T),
+    Https(TlsStream<T>),
+}
+
+impl<T> MaybeHttpsStream<T> {
+    /// Check if this transport uses TLS.
+    pub fn is_https(&self) -> bool {
+        match self.inner {
+            Inner::Http(_) => false,
+            Inner::Https(_) => true,
+        }
+    }
+}
+
+impl<T> fmt::Debug for MaybeHttpsStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.inner {
+            Inner::Http(_) => f.pad("Http(..)"),
+            Inner::Https(_) => f.pad("Https(..)"),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Read for MaybeHttpsStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner {
+            Inner::Http(ref mut io) => io.read(buf),
+            Inner::Https(ref mut tls) => tls.read(buf),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Write for MaybeHttpsStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner {
+            Inner::Http(ref mut io) => io.write(buf),
+            Inner::Https(ref mut tls) => tls.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner {
+            Inner::Http(ref mut io) => io.flush(),
+            Inner::Https(ref mut tls) => tls.flush(),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncRead for MaybeHttpsStream<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        match self.inner {
+            Inner::Http(ref io) => io.prepare_uninitialized_buffer(buf),
+            // rustls copies into the buffer, and never reads from it.
+            Inner::Https(_) => false,
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncWrite for MaybeHttpsStream<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self.inner {
+            Inner::Http(ref mut io) => io.shutdown(),
+            Inner::Https(ref mut tls) => tls.shutdown(),
+        }
+    }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        match self.inner {
+            Inner::Http(ref mut io) => io.write_buf(buf),
+            Inner::Https(ref mut tls) => {
+                let n = try_nb!(tls.write(buf.bytes()));
+                buf.advance(n);
+                Ok(Async::Ready(n))
+            }
+        }
+    }
+}
+
+// A TLS session over a non-blocking transport.
+struct TlsStream<T> {
+    io: T,
+    session: ClientSession,
+    // The plaintext sent as early data, while the handshake is incomplete,
+    // in case the server rejects it.
+    early_data: Option<Vec<u8>>,
+    eof: bool,
+    sent_close_notify: bool,
+}
+
+impl<T: Read + Write> TlsStream<T> {
+    fn new(io: T, mut session: ClientSession, early_data: bool) -> TlsStream<T> {
+        let early_data = if early_data && session.early_data().is_some() {
+            Some(Vec::new())
+        } else {
+            None
+        };
+        TlsStream {
+            io,
+            session,
+            early_data,
+            eof: false,
+            sent_close_notify: false,
+        }
+    }
+
+    fn poll_handshake(&mut self) -> Poll<(), io::Error> {
+        while self.session.is_handshaking() {
+            try_nb!(self.write_tls());
+            if !self.session.wants_read() {
+                break;
+            }
+            if try_nb!(self.read_tls()) == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed during TLS handshake",
+                ));
+            }
+        }
+        try_nb!(self.write_tls());
+        Ok(Async::Ready(()))
+    }
+
+    // Reads TLS records from the transport, returning 0 at EOF.
+    fn read_tls(&mut self) -> io::Result<usize> {
+        let n = self.session.read_tls(&mut self.io)?;
+        if n == 0 {
+            self.eof = true;
+            return Ok(0);
+        }
+        if let Err(err) = self.session.process_new_packets() {
+            // Try to send the alert about it to the server.
+            let _ = self.session.write_tls(&mut self.io);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+        Ok(n)
+    }
+
+    // Once the handshake completes, sends again any early data the server
+    // rejected.
+    fn finish_early_data(&mut self) -> io::Result<()> {
+        if self.session.is_handshaking() {
+            return Ok(());
+        }
+        if let Some(sent) = self.early_data.take() {
+            if !sent.is_empty() && !self.session.is_early_data_accepted() {
+                debug!("TLS early data rejected, sending {} bytes again", sent.len());
+                self.session.write_all(&sent)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Writes all pending TLS records to the transport.
+    fn write_tls(&mut self) -> io::Result<()> {
+        while self.session.wants_write() {
+            if self.session.write_tls(&mut self.io)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "TLS transport closed"));
+            }
+        }
+        Ok(())
+    }
+    // Writes pending TLS records, as far as the transport takes them now.
+    fn try_write_tls(&mut self) -> io::Result<()> {
+        match self.write_tls() {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<T: Read + Write> Read for TlsStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // A handshake completed during a read may have left records to
+        // send, and the response won't come until they are.
+        self.try_write_tls()?;
+        loop {
+            match self.session.read(buf) {
+                Ok(0) if !buf.is_empty() && !self.eof => {
+                    // No plaintext yet, so more records are needed.
+                    self.read_tls()?;
+                    if self.early_data.is_some() {
+                        self.finish_early_data()?;
+                        self.try_write_tls()?;
+                    }
+                },
+                Ok(n) => return Ok(n),
+                // The server sent a `close_notify`.
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionAborted => return Ok(0),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<T: Read + Write> Write for TlsStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Records already encrypted are written out first, so that the
+        // session doesn't buffer more than the transport takes.
+        self.write_tls()?;
+        if self.early_data.is_some() && self.session.is_handshaking() {
+            let n = match self.session.early_data() {
+                Some(mut early) => early.write(buf)?,
+                None => 0,
+            };
+            if n > 0 {
+                if let Some(ref mut sent) = self.early_data {
+                    sent.extend_from_slice(&buf[..n]);
+                }
+                self.try_write_tls()?;
+                return Ok(n);
+            }
+            // The early data limit was reached, the rest waits for the
+            // handshake.
+            if !self.poll_handshake()?.is_ready() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+        }
+        self.finish_early_data()?;
+        let n = self.session.write(buf)?;
+        self.try_write_tls()?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.session.flush()?;
+        self.write_tls()?;
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> TlsStream<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        if !self.sent_close_notify {
+            self.session.send_close_notify();
+            self.sent_close_notify = true;
+        }
+        try_nb!(self.write_tls());
+        self.io.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use futures::Future;
+    use rustls::ClientConfig;
+
+    use client::connect::{Connect, Destination, HttpConnector};
+    use super::HttpsConnector;
+
+    #[test]
+    fn errors_enforce_https() {
+        let uri = "http://example.domain/foo/bar?baz".parse().unwrap();
+        let dst = Destination {
+            uri,
+            addr: None,
+            early_data: false,
+        };
+        let mut connector = HttpsConnector::new(1);
+        connector.enforce_https(true);
+
+        assert_eq!(connector.connect(dst).wait().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn offers_h2_and_http1() {
+        let connector = HttpsConnector::with_connector(HttpConnector::new(1), ClientConfig::new());
+
+        assert_eq!(connector.tls.alpn_protocols, vec![b"h2".to_vec(), b"http/1.1".to_vec()]);
+    }
+}
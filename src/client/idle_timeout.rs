@@ -0,0 +1,63 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future};
+use tokio_timer::Delay;
+
+use common::ConnectionStats;
+
+/// Watches a connection for going unused, see `conn::Builder::idle_timeout`.
+#[derive(Debug)]
+pub(super) struct IdleTimeout {
+    dur: Duration,
+    // The requests sent on the connection that are waiting for a response.
+    in_flight: Arc<AtomicUsize>,
+    // The bytes read and written when the connection became idle, and when
+    // it may stay idle until.
+    idle: Option<(usize, usize, Delay)>,
+}
+
+impl IdleTimeout {
+    pub(super) fn new(dur: Duration, in_flight: Arc<AtomicUsize>) -> IdleTimeout {
+        IdleTimeout {
+            dur,
+            in_flight,
+            idle: None,
+        }
+    }
+
+    /// Check on a connection that isn't ready, returning `Ready` once it
+    /// has been idle for too long.
+    pub(super) fn poll(&mut self, stats: &ConnectionStats) -> Async<()> {
+        if self.in_flight.load(Ordering::SeqCst) > 0 {
+            self.idle = None;
+            return Async::NotReady;
+        }
+        // A response body still being read, or written, means the
+        // connection is in use, so the timeout starts over.
+        let read = stats.bytes_read();
+        let written = stats.bytes_written();
+        let is_progress = match self.idle {
+            Some((idle_read, idle_written, _)) => idle_read != read || idle_written != written,
+            None => true,
+        };
+        if is_progress {
+            self.idle = Some((read, written, Delay::new(Instant::now() + self.dur)));
+        }
+
+        let delay = &mut self.idle.as_mut().expect("just set").2;
+        match delay.poll() {
+            Ok(Async::Ready(())) => {
+                debug!("connection idle for {:?}, closing", self.dur);
+                Async::Ready(())
+            },
+            Ok(Async::NotReady) => Async::NotReady,
+            Err(timer_err) => {
+                error!("couldn't time connection idleness, timer error: {}", timer_err);
+                self.idle = None;
+                Async::NotReady
+            }
+        }
+    }
+}
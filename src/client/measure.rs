@@ -0,0 +1,322 @@
+//! Round-trip time and throughput estimates per host, see
+//! `Client::origin_stats`.
+
+use std::collections::HashMap;
+#[cfg(feature = "http2")] use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use common::ConnectionStats;
+
+// Responses reading fewer bytes than this mostly measure the latency of
+// the server, not the bandwidth of the path.
+const MIN_THROUGHPUT_BYTES: u64 = 16 * 1024;
+
+// Each sample moves an estimate an eighth of the way towards it, the same
+// smoothing as TCP's SRTT (RFC 6298).
+const WEIGHT: u32 = 8;
+
+// The most hosts with estimates. A new host replaces the one sampled least
+// recently, so a `Client` sending to ever more hosts doesn't keep growing.
+const MAX_HOSTS: usize = 1024;
+
+/// Called with how long an HTTP/2 `PING` took to be acknowledged.
+#[cfg(feature = "http2")]
+#[derive(Clone)]
+pub(crate) struct PingRtt(Arc<Fn(Duration) + Send + Sync>);
+
+pub(super) struct Measurements {
+    hosts: Mutex<Hosts>,
+}
+
+struct Hosts {
+    estimates: HashMap<String, Sampled>,
+    // Counts the samples, to tell which host was sampled least recently.
+    samples: u64,
+}
+
+struct Sampled {
+    estimate: OriginEstimate,
+    // The value of `Hosts::samples` when the host was last sampled.
+    at: u64,
+}
+
+/// Estimates of the network path to a host, from the `Client`'s own
+/// connections.
+///
+/// Each estimate is an exponentially weighted moving average, giving every
+/// new sample an eighth of the weight, like TCP's smoothed round-trip time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OriginEstimate {
+    rtt: Option<Duration>,
+    rtt_samples: u64,
+    throughput: Option<u64>,
+    throughput_samples: u64,
+    enhance_your_calm: u64,
+}
+
+/// A snapshot of a `Client`'s estimates of the network path to each host.
+#[derive(Clone, Debug, Default)]
+pub struct OriginStats {
+    hosts: HashMap<String, OriginEstimate>,
+}
+
+/// Samples the bytes a connection read while a response body was received,
+/// recording the throughput once the body is done or dropped.
+pub(super) struct ThroughputSample {
+    measurements: Arc<Measurements>,
+    host: String,
+    stats: ConnectionStats,
+    bytes_read: usize,
+    started: Instant,
+}
+
+impl Measurements {
+    pub(super) fn new() -> Measurements {
+        Measurements {
+            hosts: Mutex::new(Hosts {
+                estimates: HashMap::new(),
+                samples: 0,
+            }),
+        }
+    }
+
+    pub(super) fn rtt(&self, host: &str, rtt: Duration) {
+        trace!("rtt sample for {:?}: {:?}", host, rtt);
+        let mut hosts = self.hosts.lock().unwrap();
+        let estimate = hosts.sample(host);
+        estimate.rtt = Some(match estimate.rtt {
+            Some(srtt) => srtt - srtt / WEIGHT + rtt / WEIGHT,
+            None => rtt,
+        });
+        estimate.rtt_samples += 1;
+    }
+
+    pub(super) fn throughput(&self, host: &str, bytes: u64, elapsed: Duration) {
+        let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+        if bytes < MIN_THROUGHPUT_BYTES || nanos == 0 {
+            return;
+        }
+        let rate = (bytes as f64 * 1e9 / nanos as f64) as u64;
+        trace!("throughput sample for {:?}: {} bytes/s", host, rate);
+        let mut hosts = self.hosts.lock().unwrap();
+        let estimate = hosts.sample(host);
+        estimate.throughput = Some(match estimate.throughput {
+            Some(avg) => avg - avg / WEIGHT as u64 + rate / WEIGHT as u64,
+            None => rate,
+        });
+        estimate.throughput_samples += 1;
+    }
+
+    pub(super) fn enhance_your_calm(&self, host: &str) {
+        warn!("{:?} asked to be sent less (ENHANCE_YOUR_CALM)", host);
+        let mut hosts = self.hosts.lock().unwrap();
+        let estimate = hosts.sample(host);
+        estimate.enhance_your_calm += 1;
+    }
+
+    pub(super) fn stats(&self) -> OriginStats {
+        let hosts = self.hosts.lock().unwrap();
+        OriginStats {
+            hosts: hosts.estimates.iter()
+                .map(|(host, sampled)| (host.clone(), sampled.estimate))
+                .collect(),
+        }
+    }
+}
+
+impl Hosts {
+    // The estimate of `host`, to update with a new sample.
+    fn sample(&mut self, host: &str) -> &mut OriginEstimate {
+        if self.estimates.len() >= MAX_HOSTS && !self.estimates.contains_key(host) {
+            let oldest = self.estimates.iter()
+                .min_by_key(|&(_, sampled)| sampled.at)
+                .map(|(host, _)| host.clone());
+            if let Some(oldest) = oldest {
+                trace!("forgetting estimates of {:?}", oldest);
+                self.estimates.remove(&oldest);
+            }
+        }
+        self.samples += 1;
+        let sampled = self.estimates.entry(host.to_string()).or_insert_with(|| Sampled {
+            estimate: OriginEstimate::default(),
+            at: 0,
+        });
+        sampled.at = self.samples;
+        &mut sampled.estimate
+    }
+}
+
+#[cfg(feature = "http2")]
+impl PingRtt {
+    pub(super) fn new<F>(f: F) -> PingRtt
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        PingRtt(Arc::new(f))
+    }
+
+    pub(crate) fn call(&self, rtt: Duration) {
+        (self.0)(rtt)
+    }
+}
+
+#[cfg(feature = "http2")]
+impl fmt::Debug for PingRtt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PingRtt")
+            .finish()
+    }
+}
+
+impl OriginEstimate {
+    /// The smoothed round-trip time to the host, if any was measured.
+    ///
+    /// Samples are taken when connecting, from connectors that report how
+    /// long their transport's handshake took with
+    /// [`Connected::rtt`](::client::connect::Connected::rtt), as the
+    /// `HttpConnector` does for TCP. Each new HTTP/2 connection also sends
+    /// a `PING` once its handshake is done, and samples how long the server
+    /// took to acknowledge it.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// The number of round-trip times sampled.
+    pub fn rtt_samples(&self) -> u64 {
+        self.rtt_samples
+    }
+
+    /// The smoothed throughput from the host, in bytes per second, if any
+    /// was measured.
+    ///
+    /// Samples are the bytes read from a connection while a response body
+    /// was received, over the time from its headers until it ended or was
+    /// dropped. Bodies of less than 16KB aren't sampled. Since a body is
+    /// only read as fast as it's polled, an application that is slow to
+    /// read lowers the estimate.
+    pub fn throughput(&self) -> Option<u64> {
+        self.throughput
+    }
+
+    /// The number of throughputs sampled.
+    pub fn throughput_samples(&self) -> u64 {
+        self.throughput_samples
+    }
+
+    /// The bandwidth-delay product of the path, in bytes, if both the
+    /// round-trip time and the throughput were measured.
+    ///
+    /// This is about how many bytes are in flight when the path is kept
+    /// busy, which makes it a starting point for deciding how much to
+    /// request, or prefetch, at once.
+    pub fn bandwidth_delay_product(&self) -> Option<u64> {
+        match (self.rtt, self.throughput) {
+            (Some(rtt), Some(throughput)) => {
+                let micros = rtt.as_secs() * 1_000_000 + (rtt.subsec_nanos() / 1_000) as u64;
+                Some((throughput as f64 * micros as f64 / 1e6) as u64)
+            },
+            _ => None,
+        }
+    }
+
+    /// The number of requests to the host that failed with the HTTP/2
+    /// error code `ENHANCE_YOUR_CALM`.
+    ///
+    /// A server uses it, in a `RST_STREAM` or `GOAWAY`, to say it's being
+    /// sent more than it's willing to handle, such as too many requests,
+    /// streams reset too often, or too many pings. Anything but zero is a
+    /// sign that the `Client` should be configured to ask less of the host.
+    pub fn enhance_your_calm(&self) -> u64 {
+        self.enhance_your_calm
+    }
+}
+
+impl OriginStats {
+    /// The estimates for a host, such as `http://hyper.rs`.
+    pub fn get(&self, host: &str) -> Option<&OriginEstimate> {
+        self.hosts.get(host)
+    }
+
+    /// The estimates, keyed by host.
+    pub fn per_host(&self) -> &HashMap<String, OriginEstimate> {
+        &self.hosts
+    }
+}
+
+impl ThroughputSample {
+    pub(super) fn new(measurements: Arc<Measurements>, host: String, stats: ConnectionStats) -> ThroughputSample {
+        ThroughputSample {
+            measurements,
+            host,
+            bytes_read: stats.bytes_read(),
+            stats,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for ThroughputSample {
+    fn drop(&mut self) {
+        // On an HTTP/2 connection, this includes the bytes of any other
+        // streams received at the same time, which is still what the path
+        // delivered meanwhile.
+        let bytes = self.stats.bytes_read().wrapping_sub(self.bytes_read) as u64;
+        self.measurements.throughput(&self.host, bytes, self.started.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Measurements, MAX_HOSTS};
+
+    #[test]
+    fn smooths_samples() {
+        let measurements = Measurements::new();
+        measurements.rtt("http://hyper.rs", Duration::from_millis(80));
+        measurements.rtt("http://hyper.rs", Duration::from_millis(160));
+        measurements.throughput("http://hyper.rs", 1_000_000, Duration::from_secs(1));
+        // Too small to be sampled.
+        measurements.throughput("http://hyper.rs", 1_000, Duration::from_millis(1));
+
+        let stats = measurements.stats();
+        let estimate = stats.get("http://hyper.rs").unwrap();
+        assert_eq!(estimate.rtt(), Some(Duration::from_millis(90)));
+        assert_eq!(estimate.rtt_samples(), 2);
+        assert_eq!(estimate.throughput(), Some(1_000_000));
+        assert_eq!(estimate.throughput_samples(), 1);
+        assert_eq!(estimate.bandwidth_delay_product(), Some(90_000));
+        assert!(stats.get("http://example.com").is_none());
+    }
+
+    #[test]
+    fn counts_enhance_your_calm() {
+        let measurements = Measurements::new();
+        measurements.enhance_your_calm("http://hyper.rs");
+        measurements.enhance_your_calm("http://hyper.rs");
+
+        let stats = measurements.stats();
+        let estimate = stats.get("http://hyper.rs").unwrap();
+        assert_eq!(estimate.enhance_your_calm(), 2);
+        assert_eq!(estimate.rtt(), None);
+    }
+
+    #[test]
+    fn forgets_least_recently_sampled_host() {
+        let measurements = Measurements::new();
+        for i in 0..MAX_HOSTS {
+            measurements.enhance_your_calm(&format!("http://{}.hyper.rs", i));
+        }
+        // A sample keeps the first host from being the oldest.
+        measurements.enhance_your_calm("http://0.hyper.rs");
+        measurements.enhance_your_calm("http://example.com");
+
+        let stats = measurements.stats();
+        assert_eq!(stats.per_host().len(), MAX_HOSTS);
+        assert!(stats.get("http://example.com").is_some());
+        assert_eq!(stats.get("http://0.hyper.rs").unwrap().enhance_your_calm(), 2);
+        assert!(stats.get("http://1.hyper.rs").is_none());
+    }
+}
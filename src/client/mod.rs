@@ -19,7 +19,17 @@
 //!   server before any bytes have been written.
 //!
 //! Many of these features can configured, by making use of
-//! [`Client::builder`](Client::builder).
+//! [`Client::builder`](Client::builder). Some can also be changed for a
+//! single request, with a [`RequestConfig`](RequestConfig).
+//!
+//! ## Presets
+//!
+//! [`Builder::low_memory`](Builder::low_memory),
+//! [`Builder::high_throughput`](Builder::high_throughput) and
+//! [`Builder::low_latency`](Builder::low_latency) set a group of related
+//! options for one goal at once. Each preset sets the same options, so the
+//! last one called wins, and options outside that group are kept. Any
+//! option can still be changed after a preset, to adjust it.
 //!
 //! ## Example
 //!
@@ -74,41 +84,103 @@
 
 use std::fmt;
 use std::io;
+use std::mem;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures::{Async, Future, Poll};
 use futures::future::{self, Either, Executor};
 use futures::sync::oneshot;
-use http::{Method, Request, Response, Uri, Version};
-use http::header::{Entry, HeaderValue, HOST};
+use http::{Extensions, Method, Request, Response, Uri, Version};
+use http::HeaderMap;
+use http::header::{Entry, HeaderName, HeaderValue, CONNECTION, HOST};
 use http::uri::Scheme;
+use tokio_io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "runtime")]
+use tokio_timer::Delay;
 
-use body::{Body, Payload};
-use common::Exec;
-use self::connect::{Connect, Destination};
-use self::pool::{Pool, Poolable, Reservation};
+use body::{Body, BufferLimit, ChunkSize, Payload};
+use common::{ConnectionStats, Exec};
+use self::breaker::CircuitBreaker;
+use self::coalesce::{Coalescer, Start};
+use self::conn::HeaderValuePolicy;
+use self::connect::{Connect, ConnectError, ConnectPhase, Destination, Extra};
+use self::health::Probe;
+#[cfg(feature = "http2")] use self::measure::PingRtt;
+use self::measure::{Measurements, ThroughputSample};
+use self::pool::{EventHook, HealthHook, Limits, Permit, Pool, Poolable, Pooled, Reservation};
 
+pub use common::CancelToken;
+pub use self::byteranges::{ByteRange, ByteRanges};
+pub use self::config::RequestConfig;
+pub use self::measure::{OriginEstimate, OriginStats};
+pub use self::render::DebugRender;
 #[cfg(feature = "runtime")] pub use self::connect::HttpConnector;
+#[cfg(feature = "https-rustls")] pub use self::https::{HttpsConnector, MaybeHttpsStream};
+#[cfg(feature = "https-native-tls")] pub use self::tls::{MaybeNativeTlsStream, NativeTlsConnector};
+pub use self::target::RequestTarget;
+pub use self::pool::{CheckoutOutcome, CheckoutStrategy, EvictReason, IdleConnection, PoolEvent, PoolStats, ReuseStrategy};
+pub use common::SendWindow;
 
+#[cfg(feature = "blocking")] pub mod blocking;
+mod breaker;
+mod byteranges;
+mod coalesce;
+mod config;
 pub mod conn;
 pub mod connect;
 pub(crate) mod dispatch;
 #[cfg(feature = "runtime")] mod dns;
-mod pool;
+mod fallback;
+mod health;
+#[cfg(feature = "runtime")] mod idle_timeout;
+#[cfg(feature = "https-rustls")] mod https;
+pub(crate) mod measure;
+pub mod pool;
+mod render;
+pub(crate) mod settings;
+mod target;
 #[cfg(test)]
 mod tests;
+#[cfg(feature = "https-native-tls")] mod tls;
 
 /// A Client to make outgoing HTTP requests.
 pub struct Client<C, B = Body> {
+    chunk_size: ChunkSize,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    coalescer: Option<Arc<Coalescer>>,
     connector: Arc<C>,
     executor: Exec,
+    h1_body_buffer: BufferLimit,
+    h1_early_data: bool,
     h1_writev: bool,
     h1_title_case_headers: bool,
-    pool: Pool<PoolClient<B>>,
+    h1_allow_http09_responses: bool,
+    h1_header_value_policy: Option<HeaderValuePolicy>,
+    h1_chunk_extensions: bool,
+    h1_legacy_keep_alive: bool,
+    h1_max_buf_size: Option<usize>,
+    h1_max_headers: Option<usize>,
+    h1_preserve_header_case: bool,
+    h1_preserve_header_order: bool,
+    h1_request_target: Option<RequestTarget>,
+    h2_fallback: bool,
+    h2_fallback_expiry: Option<Duration>,
+    h2_fallback_timeout: Option<Duration>,
+    hedge_after: Option<Duration>,
+    max_response_body_size: Option<u64>,
+    measurements: Arc<Measurements>,
+    pool: Pool<PoolClient<B>, PoolKey>,
+    pool_checkout: CheckoutStrategy,
+    pool_health_check: bool,
+    pool_key_fn: Option<KeyFn>,
+    pool_timeout: Option<Duration>,
     retry_canceled_requests: bool,
+    retry_replayable_requests: bool,
     set_host: bool,
     ver: Ver,
+    write_timeout: Option<Duration>,
 }
 
 #[cfg(feature = "runtime")]
@@ -186,8 +258,192 @@ where C: Connect + Sync + 'static,
         self.request(req)
     }
 
+    /// Get a snapshot of the state of this `Client`'s connection pool.
+    ///
+    /// Clones of a `Client` share the same pool.
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.stats()
+    }
+
+    /// Get a snapshot of this `Client`'s estimates of the round-trip time
+    /// and throughput to each host, keyed like the pool.
+    ///
+    /// They are measured on the `Client`'s own connections, so that
+    /// applications can size their concurrency or prefetching from them
+    /// instead of measuring again. Round-trip times are sampled when
+    /// connecting, from connectors that report them, and with a `PING` on
+    /// each new HTTP/2 connection. Throughputs are sampled from the bytes read
+    /// while receiving response bodies. Requests that a host refused with
+    /// HTTP/2's `ENHANCE_YOUR_CALM` are counted as well, as a sign that the
+    /// `Client` is asking too much of it.
+    ///
+    /// Clones of a `Client` share the same estimates.
+    pub fn origin_stats(&self) -> OriginStats {
+        self.measurements.stats()
+    }
+
+    /// Connect to the host of a `Uri` ahead of time.
+    ///
+    /// The connection is made, and its handshake done, the same way as for
+    /// a request, and is then kept idle in the pool, for the next request to
+    /// that host. The returned future resolves once the connection is in the
+    /// pool. Connection limits and the pool timeout apply as for a request.
+    ///
+    /// If an HTTP/2 connection to the host is already being made, no other
+    /// one is. If keep-alive is disabled, the connection is closed again
+    /// right away.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate hyper;
+    /// # #[cfg(feature = "runtime")]
+    /// # fn run () {
+    /// use hyper::Client;
+    /// use hyper::rt::Future;
+    ///
+    /// let client = Client::new();
+    ///
+    /// let warm = client.prepare("http://example.com".parse().unwrap())
+    ///     .and_then(move |()| client.get("http://example.com/orders".parse().unwrap()));
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn prepare(&self, uri: Uri) -> Prepare {
+        let domain = match self.domain_of(&uri, &Extensions::new()) {
+            Ok(domain) => domain,
+            Err(err) => return Prepare::new(Box::new(future::err(err))),
+        };
+        let pool_key = self.pool_key(&domain, self.ver);
+        let connect = self.connect_to(uri, None, pool_key, true, false)
+            .map(|pooled| {
+                // Dropping it puts it into the pool, as an idle connection.
+                drop(pooled);
+            })
+            .or_else(|err| {
+                if err.is_canceled() {
+                    trace!("prepare canceled, another connection is in progress");
+                    Ok(())
+                } else {
+                    Err(err)
+                }
+            });
+
+        #[cfg(feature = "runtime")]
+        let connect = match self.pool_timeout {
+            Some(dur) => {
+                let timeout = lazy_delay(dur);
+                Either::A(connect.select2(timeout).then(|res| {
+                    match res {
+                        Ok(Either::A(((), _timeout))) => Either::A(future::ok(())),
+                        Ok(Either::B(((), _connect))) => {
+                            debug!("timed out preparing a connection");
+                            Either::A(future::err(::Error::new_pool_timeout()))
+                        },
+                        Err(Either::A((e, _timeout))) => Either::A(future::err(e)),
+                        Err(Either::B((timer_err, connect))) => {
+                            error!("couldn't time preparing a connection, timer error: {}", timer_err);
+                            Either::B(connect)
+                        },
+                    }
+                }))
+            },
+            None => Either::B(connect),
+        };
+
+        Prepare::new(Box::new(connect))
+    }
+
+    /// Render the bytes this `Client` would write for a request, without
+    /// connecting anywhere.
+    ///
+    /// The request goes through the same HTTP/1 connection code as if it
+    /// were sent, with the settings of this `Client`, so the bytes include
+    /// the headers added automatically, like `Host`, and the framing picked
+    /// for the body, like `transfer-encoding: chunked`. This is meant for
+    /// debugging, such as when a server disagrees with a request's
+    /// signature, and not to be sent elsewhere.
+    ///
+    /// The request-target is rendered as it's sent to an origin server. A
+    /// connector that goes through an HTTP proxy would have it sent in
+    /// absolute-form instead.
+    ///
+    /// The returned future resolves once the whole body was written, so a
+    /// streaming body has to end for it to resolve. Requests this `Client`
+    /// would send over HTTP/2 fail with an error, since only HTTP/1 is
+    /// rendered.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate hyper;
+    /// # #[cfg(feature = "runtime")]
+    /// # fn run () {
+    /// use hyper::{Body, Client, Request};
+    /// use hyper::rt::Future;
+    ///
+    /// let client = Client::new();
+    /// let req = Request::post("http://example.com/orders")
+    ///     .body(Body::from("{}"))
+    ///     .unwrap();
+    ///
+    /// let bytes = client.debug_render(req).wait().unwrap();
+    /// assert!(bytes.starts_with(b"POST /orders HTTP/1.1\r\n"));
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn debug_render(&self, mut req: Request<B>) -> DebugRender {
+        match req.version() {
+            Version::HTTP_10 |
+            Version::HTTP_11 => (),
+            other => {
+                debug!("can't render a request with version \"{:?}\"", other);
+                return DebugRender::new(Box::new(future::err(::Error::new_user_unsupported_version())));
+            }
+        }
+
+        if req.method() == &Method::CONNECT {
+            return DebugRender::new(Box::new(future::err(::Error::new_user_unsupported_request_method())));
+        }
+
+        let uri = req.uri().clone();
+        if let Err(err) = domain_of(&uri) {
+            return DebugRender::new(Box::new(future::err(err)));
+        }
+
+        let config = RequestConfig::get(&req);
+        if config.ver(self.ver) == Ver::Http2 {
+            debug!("can't render a request that would be sent over HTTP/2");
+            return DebugRender::new(Box::new(future::err(::Error::new_user_unsupported_version())));
+        }
+
+        if self.set_host {
+            set_host_header(req.headers_mut(), &uri);
+        }
+        if config.target_form().or(self.h1_request_target).is_none() {
+            set_relative_uri(req.uri_mut(), false);
+        }
+
+        DebugRender::new(render::render(&self.conn_builder(Ver::Http1), req))
+    }
+
+    /// Shut down this `Client`, and all of its clones.
+    ///
+    /// New requests are rejected with an error, for which
+    /// [`Error::is_pool_closed`](::Error::is_pool_closed) is `true`. Idle
+    /// connections are closed right away, and connections that are still in
+    /// use are closed once their requests complete.
+    ///
+    /// The returned future resolves once every connection has closed.
+    pub fn shutdown(&self) -> Shutdown {
+        self.pool.close();
+        Shutdown {
+            closed: self.pool.closed(),
+        }
+    }
+
     /// Send a constructed Request using this Client.
-    pub fn request(&self, mut req: Request<B>) -> ResponseFuture {
+    pub fn request(&self, req: Request<B>) -> ResponseFuture {
         match req.version() {
             Version::HTTP_10 |
             Version::HTTP_11 => (),
@@ -202,125 +458,478 @@ where C: Connect + Sync + 'static,
             return ResponseFuture::new(Box::new(future::err(::Error::new_user_unsupported_request_method())));
         }
 
+        let key = match self.coalescer {
+            Some(ref coalescer) => coalescer.key(&req),
+            None => None,
+        };
+        match key {
+            Some(key) => {
+                let token = req.extensions().get::<CancelToken>().cloned();
+                match Coalescer::start(self.coalescer.as_ref().unwrap(), key, token) {
+                    Start::Lead(leader) => {
+                        ResponseFuture::new(Box::new(self.send_coalesced(req).then(move |res| {
+                            leader.finish(res)
+                        })))
+                    },
+                    Start::Wait(waiter) => ResponseFuture::new(Box::new(waiter)),
+                }
+            },
+            None => ResponseFuture::new(self.send_coalesced(req)),
+        }
+    }
+
+    // Send a request that may be sharing its response with identical ones,
+    // see `Builder::coalesce_requests`.
+    fn send_coalesced(&self, mut req: Request<B>) -> Box<Future<Item=Response<Body>, Error=::Error> + Send> {
         let uri = req.uri().clone();
-        let domain = match (uri.scheme_part(), uri.authority_part()) {
-            (Some(scheme), Some(auth)) => {
-                format!("{}://{}", scheme, auth)
-            }
-            _ => {
-                //TODO: replace this with a proper variant
-                return ResponseFuture::new(Box::new(future::err(::Error::new_io(
-                    io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        "invalid URI for Client Request"
-                    )
-                ))));
-            }
+        let domain = match self.domain_of(&uri, req.extensions()) {
+            Ok(domain) => domain,
+            Err(err) => return Box::new(future::err(err)),
         };
 
-        if self.set_host && self.ver == Ver::Http1 {
-            if let Entry::Vacant(entry) = req.headers_mut().entry(HOST).expect("HOST is always valid header name") {
-                let hostname = uri.host().expect("authority implies host");
-                let host = if let Some(port) = uri.port() {
-                    let s = format!("{}:{}", hostname, port);
-                    HeaderValue::from_str(&s)
-                } else {
-                    HeaderValue::from_str(hostname)
-                }.expect("uri host is valid header value");
-                entry.insert(host);
-            }
+        let attempt = match self.circuit_breaker {
+            Some(ref breaker) => match CircuitBreaker::start(breaker, &domain) {
+                Ok(attempt) => Some(attempt),
+                Err(err) => return Box::new(future::err(err)),
+            },
+            None => None,
+        };
+
+        // When falling back from HTTP/2, it isn't known yet which version
+        // the request will be sent with, so the `Host` header is set once
+        // a connection has been picked.
+        if self.set_host && RequestConfig::get(&req).ver(self.ver) == Ver::Http1 {
+            set_host_header(req.headers_mut(), &uri);
         }
 
 
+        #[cfg(feature = "runtime")]
+        let hedge = self.hedge_request(&req);
+
         let client = self.clone();
         let uri = req.uri().clone();
         let fut = RetryableSendRequest {
             client: client,
             future: self.send_request(req, &domain),
-            domain: domain,
+            domain: domain.clone(),
             uri: uri,
         };
-        ResponseFuture::new(Box::new(fut))
+
+        let fut: Box<Future<Item=Response<Body>, Error=::Error> + Send> = {
+            #[cfg(feature = "runtime")]
+            {
+                match hedge {
+                    Some((dur, hedge)) => Box::new(Hedge::new(fut, self.clone(), domain, hedge, dur)),
+                    None => Box::new(fut),
+                }
+            }
+            #[cfg(not(feature = "runtime"))]
+            {
+                Box::new(fut)
+            }
+        };
+        match attempt {
+            Some(attempt) => Box::new(fut.then(move |res| {
+                attempt.finish(&res);
+                res
+            })),
+            None => fut,
+        }
+    }
+
+    // The copy of a request to send if it takes too long, see
+    // `Builder::hedge_after`.
+    #[cfg(feature = "runtime")]
+    fn hedge_request(&self, req: &Request<B>) -> Option<(Duration, Request<B>)> {
+        let config = RequestConfig::get(req);
+        let dur = config.hedge_after_or(self.hedge_after)?;
+        let mut hedge = replay_request(req)?;
+        if config.ver(self.ver) == Ver::Http2 {
+            // The original request would share its HTTP/2 connection with
+            // the copy, so the copy gets a connection of its own.
+            hedge.extensions_mut().insert(config.pool(false));
+        }
+        Some((dur, hedge))
+    }
+
+    fn domain_of(&self, uri: &Uri, extensions: &Extensions) -> ::Result<String> {
+        let domain = domain_of(uri)?;
+        let key = match self.pool_key_fn {
+            Some(ref key_fn) => key_fn(uri, extensions),
+            None => domain,
+        };
+        let addr = extensions
+            .get::<RequestConfig>()
+            .and_then(RequestConfig::resolved_addr);
+        match addr {
+            // Connections to a pinned address aren't shared with requests
+            // that may be sent to any of the host's.
+            Some(addr) => Ok(format!("{}@{}", key, addr)),
+            None => Ok(key),
+        }
+    }
+
+    fn pool_key(&self, domain: &str, ver: Ver) -> PoolKey {
+        let domain = Arc::new(domain.to_string());
+        let ver = if ver == Ver::Http2 && self.h2_fallback && self.pool.is_http1_only(&domain) {
+            Ver::Http1
+        } else if ver == Ver::Http1 && self.pool.is_h2_negotiated(&domain) {
+            Ver::Http2
+        } else {
+            ver
+        };
+        (domain, ver)
+    }
+
+    // A connection builder with this `Client`'s settings.
+    fn conn_builder(&self, ver: Ver) -> conn::Builder {
+        let mut builder = conn::Builder::new();
+        builder
+            .exec(self.executor.clone())
+            .chunk_size(self.chunk_size)
+            .h1_body_buffer(self.h1_body_buffer)
+            .h1_writev(self.h1_writev)
+            .h1_title_case_headers(self.h1_title_case_headers)
+            .http1_allow_http09_responses(self.h1_allow_http09_responses)
+            .h1_header_value_policy(self.h1_header_value_policy)
+            .http1_chunk_extensions(self.h1_chunk_extensions)
+            .http1_legacy_keep_alive(self.h1_legacy_keep_alive)
+            .h1_max_buf_size(self.h1_max_buf_size)
+            .h1_max_headers(self.h1_max_headers)
+            .http1_preserve_header_case(self.h1_preserve_header_case)
+            .http1_preserve_header_order(self.h1_preserve_header_order)
+            .h1_request_target(self.h1_request_target)
+            .http2_only(ver == Ver::Http2);
+        #[cfg(feature = "runtime")]
+        builder.write_timeout(self.write_timeout);
+        builder
     }
 
     //TODO: replace with `impl Future` when stable
-    fn send_request(&self, mut req: Request<B>, domain: &str) -> Box<Future<Item=Response<Body>, Error=ClientError<B>> + Send> {
-        let url = req.uri().clone();
-        let ver = self.ver;
-        let pool_key = (Arc::new(domain.to_string()), self.ver);
-        let checkout = self.pool.checkout(pool_key.clone());
-        let connect = {
-            let executor = self.executor.clone();
-            let pool = self.pool.clone();
-            let h1_writev = self.h1_writev;
-            let h1_title_case_headers = self.h1_title_case_headers;
-            let connector = self.connector.clone();
-            let dst = Destination {
-                uri: url,
+    fn connect_to(&self, url: Uri, addr: Option<SocketAddr>, pool_key: PoolKey, is_pooled: bool, early_data: bool) -> Box<Future<Item=Pooled<PoolClient<B>, PoolKey>, Error=::Error> + Send> {
+        let ver = pool_key.1;
+        let executor = self.executor.clone();
+        let pool = self.pool.clone();
+        let mut builder = self.conn_builder(ver);
+        let connector = self.connector.clone();
+        let measurements = self.measurements.clone();
+        #[cfg(feature = "http2")]
+        {
+            let measurements = measurements.clone();
+            let host = pool_key.0.clone();
+            builder.http2_ping_rtt(PingRtt::new(move |rtt| measurements.rtt(&host, rtt)));
+        }
+        let h2_fallback = self.h2_fallback && ver == Ver::Http2;
+        let h2_fallback_expiry = self.h2_fallback_expiry;
+        let h2_fallback_timeout = self.h2_fallback_timeout;
+        let health_check = self.pool_health_check;
+        Box::new(future::lazy(move || {
+            let connecting = if is_pooled {
+                pool.connecting(&pool_key)
+            } else {
+                Some(pool.connecting_unpooled(&pool_key))
             };
-            future::lazy(move || {
-                if let Some(connecting) = pool.connecting(&pool_key) {
-                    Either::A(connector.connect(dst)
-                        .map_err(::Error::new_connect)
-                        .and_then(move |(io, connected)| {
-                            conn::Builder::new()
-                                .exec(executor.clone())
-                                .h1_writev(h1_writev)
-                                .h1_title_case_headers(h1_title_case_headers)
-                                .http2_only(pool_key.1 == Ver::Http2)
-                                .handshake_no_upgrades(io)
-                                .and_then(move |(tx, conn)| {
-                                    executor.execute(conn.map_err(|e| {
-                                        debug!("client connection error: {}", e)
-                                    }));
-
-                                    // Wait for 'conn' to ready up before we
-                                    // declare this tx as usable
-                                    tx.when_ready()
-                                })
-                                .map(move |tx| {
-                                    pool.pooled(connecting, PoolClient {
-                                        is_proxied: connected.is_proxied,
-                                        tx: match ver {
-                                            Ver::Http1 => PoolTx::Http1(tx),
-                                            Ver::Http2 => PoolTx::Http2(tx.into_http2()),
-                                        },
-                                    })
+            if let Some(connecting) = connecting {
+                let dst = Destination {
+                    uri: url.clone(),
+                    addr: addr,
+                    early_data: early_data,
+                };
+                let connected = {
+                    let connector = connector.clone();
+                    let host = pool_key.0.clone();
+                    pool.permit(&pool_key.0)
+                        .and_then(move |permit| {
+                            connector.connect(dst)
+                                .map_err(::Error::new_connect)
+                                .map(move |(io, connected)| {
+                                    if let Some(rtt) = connected.rtt {
+                                        measurements.rtt(&host, rtt);
+                                    }
+                                    (io, connected, permit)
                                 })
-                        }))
+                        })
+                };
+                if h2_fallback {
+                    Either::A(Either::A(connected.and_then(move |(io, connected, permit)| {
+                        let (io, is_h2) = fallback::sniff(io);
+                        handshake(&builder, &executor, io, connected.addr, permit.clone())
+                            .and_then(move |tx| {
+                                fallback::answered(is_h2, h2_fallback_timeout)
+                                    .map(move |is_h2| (tx, is_h2))
+                            })
+                            .and_then(move |(tx, is_h2)| {
+                                if is_h2 {
+                                    let pooled = pool.pooled(connecting, PoolClient::new(tx, connected.is_proxied, connected.extra, Ver::Http2, None));
+                                    return Either::A(future::ok(pooled));
+                                }
+
+                                debug!("{:?} did not answer with HTTP/2, falling back to HTTP/1.1", pool_key.0);
+                                drop(tx);
+                                pool.set_http1_only(&pool_key.0, h2_fallback_expiry);
+                                // Release the HTTP/2 connecting lock, any
+                                // other requests waiting on it can connect
+                                // on their own.
+                                drop(connecting);
+                                let key = (pool_key.0, Ver::Http1);
+                                let connecting = if is_pooled {
+                                    pool.connecting(&key)
+                                        .expect("HTTP/1 connecting is never locked")
+                                } else {
+                                    pool.connecting_unpooled(&key)
+                                };
+                                builder.http2_only(false);
+                                let dst = Destination {
+                                    uri: url,
+                                    addr: addr,
+                                    early_data: false,
+                                };
+                                // The HTTP/2 connection is closing, so its
+                                // permit is handed over to the new one.
+                                Either::B(connector.connect(dst)
+                                    .map_err(::Error::new_connect)
+                                    .and_then(move |(io, connected)| {
+                                        let (io, probe) = health::watch(io, health_check);
+                                        handshake(&builder, &executor, io, connected.addr, permit)
+                                            .map(move |tx| {
+                                                pool.pooled(connecting, PoolClient::new(tx, connected.is_proxied, connected.extra, Ver::Http1, probe))
+                                            })
+                                    }))
+                            })
+                    })))
                 } else {
-                    let canceled = ::Error::new_canceled(Some("HTTP/2 connection in progress"));
-                    Either::B(future::err(canceled))
+                    let failed_pool = pool.clone();
+                    let failed_host = pool_key.0.clone();
+                    Either::A(Either::B(connected.and_then(move |(io, connected, permit)| {
+                        let mut builder = builder;
+                        if ver == Ver::Http2 && !connected.is_h2() {
+                            // The host may have stopped negotiating HTTP/2.
+                            pool.clear_h2_negotiated(&pool_key.0);
+                        }
+                        let (ver, connecting) = if ver == Ver::Http1 && connected.is_h2() {
+                            // The connector negotiated HTTP/2, so this
+                            // connection is shared with the host's other
+                            // requests, under its HTTP/2 key.
+                            debug!("{:?} negotiated HTTP/2", pool_key.0);
+                            pool.set_h2_negotiated(&pool_key.0);
+                            drop(connecting);
+                            let key = (pool_key.0, Ver::Http2);
+                            let connecting = if is_pooled {
+                                // Another HTTP/2 connection may already be
+                                // in progress, then this one isn't pooled.
+                                pool.connecting(&key)
+                                    .unwrap_or_else(|| pool.connecting_unpooled(&key))
+                            } else {
+                                pool.connecting_unpooled(&key)
+                            };
+                            builder.http2_only(true);
+                            (Ver::Http2, connecting)
+                        } else {
+                            (ver, connecting)
+                        };
+                        let (io, probe) = health::watch(io, health_check && ver == Ver::Http1);
+                        handshake(&builder, &executor, io, connected.addr, permit)
+                            .map(move |tx| {
+                                pool.pooled(connecting, PoolClient::new(tx, connected.is_proxied, connected.extra, ver, probe))
+                            })
+                    }).map_err(move |err| {
+                        if ver == Ver::Http2 {
+                            failed_pool.clear_h2_negotiated(&failed_host);
+                        }
+                        err
+                    })))
                 }
-            })
+            } else {
+                let canceled = ::Error::new_canceled(Some("HTTP/2 connection in progress"));
+                Either::B(future::err(canceled))
+            }
+        }))
+    }
+
+    //TODO: replace with `impl Future` when stable
+    fn send_request(&self, mut req: Request<B>, domain: &str) -> Box<Future<Item=Response<Body>, Error=ClientError<B>> + Send> {
+        let started = Instant::now();
+        let config = RequestConfig::get(&req);
+        let url = req.uri().clone();
+        let pool_key = self.pool_key(domain, config.ver(self.ver));
+        // A request that closes its connection shouldn't take an idle one
+        // from the pool, only to close it afterwards.
+        let is_pooled = config.is_pooled() && !(pool_key.1 == Ver::Http1 && req
+            .headers()
+            .get_all(CONNECTION)
+            .iter()
+            .any(::headers::connection_close));
+        let request_permit = self.pool.request_permit(&pool_key.0);
+        let checkout = self.pool.checkout(pool_key.clone());
+        let checkout = if self.pool_checkout == CheckoutStrategy::Connect {
+            checkout.without_waiting()
+        } else {
+            checkout
+        };
+        let prefer_idle = is_pooled
+            && self.pool_checkout == CheckoutStrategy::PreferIdle
+            && self.pool.is_busy(&pool_key);
+        // A new HTTP/1 connection is only used by the request it was made
+        // for, until a response was received, and so the handshake is done.
+        let early_data = self.h1_early_data
+            && pool_key.1 == Ver::Http1
+            && req.method().is_idempotent();
+        // Connecting a replacement for a reused connection near the end of
+        // its lifetime needs the client once the connection is known.
+        let refresher = if is_pooled && self.pool.refreshes() {
+            Some((self.clone(), url.clone(), config.resolved_addr(), pool_key.clone()))
+        } else {
+            None
         };
+        let connect = self.connect_to(url, config.resolved_addr(), pool_key, is_pooled, early_data);
 
-        let race = checkout.select(connect)
-            .map(|(pooled, _work)| pooled)
-            .or_else(|(e, other)| {
-                // Either checkout or connect could get canceled:
-                //
-                // 1. Connect is canceled if this is HTTP/2 and there is
-                //    an outstanding HTTP/2 connecting task.
-                // 2. Checkout is canceled if the pool cannot deliver an
-                //    idle connection reliably.
-                //
-                // In both cases, we should just wait for the other future.
-                if e.is_canceled() {
-                    //trace!("checkout/connect race canceled: {}", e);
-                    Either::A(other.map_err(ClientError::Normal))
-                } else {
-                    Either::B(future::err(ClientError::Normal(e)))
-                }
-            });
+        #[cfg(feature = "runtime")]
+        let connect = match self.pool_checkout {
+            CheckoutStrategy::RaceAfter(dur) if is_pooled => {
+                Either::A(lazy_delay(dur).then(move |res| {
+                    if let Err(timer_err) = res {
+                        error!("couldn't delay connecting, timer error: {}", timer_err);
+                    }
+                    connect
+                }))
+            },
+            _ => Either::B(connect),
+        };
+
+        // Requests that bypass the pool only wait on their own connection.
+        let race = if !is_pooled {
+            Either::A(connect.map_err(ClientError::Normal))
+        } else if prefer_idle {
+            // Only connect if the busy connections close instead of becoming
+            // idle, which cancels the checkout.
+            Either::B(Either::A(checkout.then(move |res| match res {
+                Ok(pooled) => Either::A(future::ok(pooled)),
+                Err(ref e) if e.is_canceled() => Either::B(connect.map_err(ClientError::Normal)),
+                Err(e) => Either::A(future::err(ClientError::Normal(e))),
+            })))
+        } else {
+            Either::B(Either::B(checkout.select(connect)
+                .map(|(pooled, _work)| pooled)
+                .or_else(|(e, other)| {
+                    // Either checkout or connect could get canceled:
+                    //
+                    // 1. Connect is canceled if this is HTTP/2 and there is
+                    //    an outstanding HTTP/2 connecting task.
+                    // 2. Checkout is canceled if the pool cannot deliver an
+                    //    idle connection reliably.
+                    //
+                    // In both cases, we should just wait for the other future.
+                    if e.is_canceled() {
+                        //trace!("checkout/connect race canceled: {}", e);
+                        Either::A(other.map_err(ClientError::Normal))
+                    } else {
+                        Either::B(future::err(ClientError::Normal(e)))
+                    }
+                })))
+        };
+        // Only once the request may be sent does it look for a connection.
+        let race = request_permit
+            .map_err(ClientError::Normal)
+            .and_then(move |permit| race.map(move |pooled| (pooled, permit)));
+
+        #[cfg(feature = "runtime")]
+        let race = match config.pool_timeout_or(self.pool_timeout) {
+            Some(dur) => {
+                let timeout = lazy_delay(dur);
+                Either::A(race.select2(timeout).then(|res| {
+                    match res {
+                        Ok(Either::A((pooled, _timeout))) => Either::A(future::ok(pooled)),
+                        Ok(Either::B(((), _race))) => {
+                            debug!("timed out waiting for a connection from the pool");
+                            Either::A(future::err(ClientError::Normal(::Error::new_pool_timeout())))
+                        },
+                        Err(Either::A((e, _timeout))) => Either::A(future::err(e)),
+                        Err(Either::B((timer_err, race))) => {
+                            error!("couldn't time pool checkout, timer error: {}", timer_err);
+                            Either::B(race)
+                        },
+                    }
+                }))
+            },
+            None => Either::B(race),
+        };
 
         let executor = self.executor.clone();
-        let resp = race.and_then(move |mut pooled| {
+        let pool = self.pool.clone();
+        let set_host = self.set_host && self.h2_fallback;
+        let replayable = self.retry_replayable_requests
+            && config.retry_canceled_requests_or(self.retry_canceled_requests);
+        // The connection writes the request-target in the form configured,
+        // otherwise it's made relative here, unless proxied.
+        let target_configured = config.target_form().or(self.h1_request_target).is_some();
+        let max_body_size = config.max_response_body_size_or(self.max_response_body_size);
+        let measurements = self.measurements.clone();
+        let calm_measurements = self.measurements.clone();
+        let host = domain.to_string();
+        let calm_host = host.clone();
+        let resp = race.and_then(move |(mut pooled, permit)| {
+            pool.checked_out(&pooled, started.elapsed());
+            if let Some((client, url, resolved_addr, pool_key)) = refresher {
+                if let Some(refresh) = pool.refresh(&pooled) {
+                    let replace = client.connect_to(url, resolved_addr, pool_key, true, false)
+                        .then(move |res| {
+                            drop(refresh);
+                            match res {
+                                // Dropping it puts it into the pool.
+                                Ok(pooled) => drop(pooled),
+                                Err(err) => debug!("replacing connection near its max lifetime failed: {}", err),
+                            }
+                            Ok(())
+                        });
+                    client.executor.execute(replace);
+                }
+            }
             let conn_reused = pooled.is_reused();
-            if ver == Ver::Http1 {
-                set_relative_uri(req.uri_mut(), pooled.is_proxied);
+            let is_http2 = pooled.is_http2();
+            if !is_http2 {
+                if set_host {
+                    let uri = req.uri().clone();
+                    set_host_header(req.headers_mut(), &uri);
+                }
+                if !target_configured {
+                    set_relative_uri(req.uri_mut(), pooled.is_proxied);
+                }
             }
-            let fut = pooled.send_request_retryable(req);
+            // Keep a copy of the request, in case the reused connection
+            // turns out to be closed only after the request was written.
+            let replay = if conn_reused && replayable {
+                replay_request(&req).map(|mut replay| {
+                    // The copy is the request sent again, so it keeps all
+                    // of the extensions, and the one sent now the ones the
+                    // connection reads.
+                    mem::swap(replay.extensions_mut(), req.extensions_mut());
+                    replay
+                })
+            } else {
+                None
+            };
+            let extra = pooled.extra.clone();
+            let fut = pooled.send_request_retryable(req)
+                .map(move |mut res| {
+                    if let Some(extra) = extra {
+                        extra.set(res.extensions_mut());
+                    }
+                    if let Some(max) = max_body_size {
+                        res.body_mut().set_max_size(max);
+                    }
+                    let sample = res.extensions()
+                        .get::<ConnectionStats>()
+                        .cloned()
+                        .map(|stats| ThroughputSample::new(measurements, host, stats));
+                    res.body_mut().release_on_eof((permit, sample));
+                    res
+                })
+                .map_err(move |(err, orig_req)| {
+                    if err.is_enhance_your_calm() {
+                        calm_measurements.enhance_your_calm(&calm_host);
+                    }
+                    (err, orig_req)
+                });
 
             // As of futures@0.1.21, there is a race condition in the mpsc
             // channel, such that sending when the receiver is closing can
@@ -333,31 +942,18 @@ where C: Connect + Sync + 'static,
                 drop(pooled);
                 let fut = fut
                     .map_err(move |(err, orig_req)| {
-                        if let Some(req) = orig_req {
-                            ClientError::Canceled {
-                                connection_reused: conn_reused,
-                                reason: err,
-                                req,
-                            }
-                        } else {
-                            ClientError::Normal(err)
-                        }
+                        ClientError::from_send(err, orig_req, replay, conn_reused)
                     });
                 Either::A(fut)
             } else {
                 let fut = fut
                     .map_err(move |(err, orig_req)| {
-                        if let Some(req) = orig_req {
-                            ClientError::Canceled {
-                                connection_reused: conn_reused,
-                                reason: err,
-                                req,
-                            }
-                        } else {
-                            ClientError::Normal(err)
-                        }
+                        ClientError::from_send(err, orig_req, replay, conn_reused)
                     })
                     .and_then(move |mut res| {
+                        if !is_http2 {
+                            pooled.keep_alive.on_response(res.headers());
+                        }
                         // If pooled is HTTP/2, we can toss this reference immediately.
                         //
                         // when pooled is dropped, it will try to insert back into the
@@ -368,7 +964,7 @@ where C: Connect + Sync + 'static,
                         // for a new request to start.
                         //
                         // It won't be ready if there is a body to stream.
-                        if ver == Ver::Http2 || !pooled.is_pool_enabled() || pooled.is_ready() {
+                        if is_http2 || !pooled.is_pool_enabled() || pooled.is_ready() {
                             drop(pooled);
                         } else if !res.body().is_end_stream() {
                             let (delayed_tx, delayed_rx) = oneshot::channel();
@@ -407,14 +1003,40 @@ where C: Connect + Sync + 'static,
 impl<C, B> Clone for Client<C, B> {
     fn clone(&self) -> Client<C, B> {
         Client {
+            chunk_size: self.chunk_size,
+            circuit_breaker: self.circuit_breaker.clone(),
+            coalescer: self.coalescer.clone(),
             connector: self.connector.clone(),
             executor: self.executor.clone(),
+            h1_body_buffer: self.h1_body_buffer,
+            h1_early_data: self.h1_early_data,
             h1_writev: self.h1_writev,
             h1_title_case_headers: self.h1_title_case_headers,
+            h1_allow_http09_responses: self.h1_allow_http09_responses,
+            h1_header_value_policy: self.h1_header_value_policy,
+            h1_chunk_extensions: self.h1_chunk_extensions,
+            h1_legacy_keep_alive: self.h1_legacy_keep_alive,
+            h1_max_buf_size: self.h1_max_buf_size,
+            h1_max_headers: self.h1_max_headers,
+            h1_preserve_header_case: self.h1_preserve_header_case,
+            h1_preserve_header_order: self.h1_preserve_header_order,
+            h1_request_target: self.h1_request_target,
+            h2_fallback: self.h2_fallback,
+            h2_fallback_expiry: self.h2_fallback_expiry,
+            h2_fallback_timeout: self.h2_fallback_timeout,
+            hedge_after: self.hedge_after,
+            max_response_body_size: self.max_response_body_size,
+            measurements: self.measurements.clone(),
             pool: self.pool.clone(),
+            pool_checkout: self.pool_checkout,
+            pool_health_check: self.pool_health_check,
+            pool_key_fn: self.pool_key_fn.clone(),
+            pool_timeout: self.pool_timeout,
             retry_canceled_requests: self.retry_canceled_requests,
+            retry_replayable_requests: self.retry_replayable_requests,
             set_host: self.set_host,
             ver: self.ver,
+            write_timeout: self.write_timeout,
         }
     }
 }
@@ -455,6 +1077,62 @@ impl Future for ResponseFuture {
     }
 }
 
+/// A `Future` that resolves once a connection made ahead of time is idle in
+/// the pool.
+///
+/// See [`Client::prepare`](Client::prepare).
+#[must_use = "futures do nothing unless polled"]
+pub struct Prepare {
+    inner: Box<Future<Item=(), Error=::Error> + Send>,
+}
+
+impl Prepare {
+    fn new(fut: Box<Future<Item=(), Error=::Error> + Send>) -> Self {
+        Self {
+            inner: fut,
+        }
+    }
+}
+
+impl fmt::Debug for Prepare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Future<Prepare>")
+    }
+}
+
+impl Future for Prepare {
+    type Item = ();
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+/// A `Future` that resolves once all connections of a shut down `Client`
+/// have closed.
+///
+/// See [`Client::shutdown`](Client::shutdown).
+#[must_use = "futures do nothing unless polled"]
+pub struct Shutdown {
+    closed: pool::Closed,
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Future<Shutdown>")
+    }
+}
+
+impl Future for Shutdown {
+    type Item = ();
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.closed.poll().map_err(|never| match never {})
+    }
+}
+
 struct RetryableSendRequest<C, B> {
     client: Client<C, B>,
     domain: String,
@@ -483,13 +1161,15 @@ where
                     mut req,
                     reason,
                 }) => {
-                    if !self.client.retry_canceled_requests || !connection_reused {
+                    let retry = RequestConfig::get(&req)
+                        .retry_canceled_requests_or(self.client.retry_canceled_requests);
+                    if !retry || !connection_reused {
                         // if client disabled, don't retry
                         // a fresh connection means we definitely can't retry
                         return Err(reason);
                     }
 
-                    trace!("unstarted request canceled, trying again (reason={:?})", reason);
+                    trace!("request canceled, trying again (reason={:?})", reason);
                     *req.uri_mut() = self.uri.clone();
                     self.future = self.client.send_request(req, &self.domain);
                 }
@@ -498,78 +1178,260 @@ where
     }
 }
 
-struct PoolClient<B> {
-    is_proxied: bool,
-    tx: PoolTx<B>,
-}
-
-enum PoolTx<B> {
-    Http1(conn::SendRequest<B>),
-    Http2(conn::Http2SendRequest<B>),
+// Sends a request a second time if its response takes too long, and takes
+// whichever response arrives first.
+#[cfg(feature = "runtime")]
+struct Hedge<C, B> {
+    client: Client<C, B>,
+    delay: Option<LazyDelay>,
+    domain: String,
+    dur: Duration,
+    hedge: Option<Request<B>>,
+    first: Option<RetryableSendRequest<C, B>>,
+    second: Option<RetryableSendRequest<C, B>>,
 }
 
-impl<B> PoolClient<B> {
-    fn poll_ready(&mut self) -> Poll<(), ::Error> {
-        match self.tx {
-            PoolTx::Http1(ref mut tx) => tx.poll_ready(),
-            PoolTx::Http2(_) => Ok(Async::Ready(())),
+#[cfg(feature = "runtime")]
+impl<C, B> Hedge<C, B> {
+    fn new(first: RetryableSendRequest<C, B>, client: Client<C, B>, domain: String, hedge: Request<B>, dur: Duration) -> Hedge<C, B> {
+        Hedge {
+            client,
+            delay: Some(lazy_delay(dur)),
+            domain,
+            dur,
+            hedge: Some(hedge),
+            first: Some(first),
+            second: None,
         }
     }
+}
 
-    fn is_ready(&self) -> bool {
-        match self.tx {
-            PoolTx::Http1(ref tx) => tx.is_ready(),
-            PoolTx::Http2(ref tx) => tx.is_ready(),
-        }
-    }
+#[cfg(feature = "runtime")]
+impl<C, B> Future for Hedge<C, B>
+where
+    C: Connect + 'static,
+    C::Future: 'static,
+    B: Payload + Send + 'static,
+    B::Data: Send,
+{
+    type Item = Response<Body>;
+    type Error = ::Error;
 
-    fn is_closed(&self) -> bool {
-        match self.tx {
-            PoolTx::Http1(ref tx) => tx.is_closed(),
-            PoolTx::Http2(ref tx) => tx.is_closed(),
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(mut delay) = self.delay.take() {
+            match delay.poll() {
+                Ok(Async::NotReady) => self.delay = Some(delay),
+                res => {
+                    if let Err(timer_err) = res {
+                        error!("couldn't delay hedged request, timer error: {}", timer_err);
+                    }
+                    let req = self.hedge.take().expect("hedge polled after delay");
+                    debug!("no response after {:?}, sending hedged request", self.dur);
+                    let uri = req.uri().clone();
+                    self.second = Some(RetryableSendRequest {
+                        client: self.client.clone(),
+                        future: self.client.send_request(req, &self.domain),
+                        domain: self.domain.clone(),
+                        uri: uri,
+                    });
+                }
+            }
         }
-    }
-}
 
-impl<B: Payload + 'static> PoolClient<B> {
-    //TODO: replace with `impl Future` when stable
-    fn send_request_retryable(&mut self, req: Request<B>) -> Box<Future<Item=Response<Body>, Error=(::Error, Option<Request<B>>)> + Send>
-    where
-        B: Send,
-    {
-        match self.tx {
-            PoolTx::Http1(ref mut tx) => tx.send_request_retryable(req),
-            PoolTx::Http2(ref mut tx) => tx.send_request_retryable(req),
+        // Whichever request responds first wins, and dropping the other one
+        // cancels it. An error is only returned once neither is left.
+        if let Some(res) = poll_hedged(&mut self.first, self.second.is_some()) {
+            return res;
+        }
+        if let Some(res) = poll_hedged(&mut self.second, self.first.is_some()) {
+            return res;
         }
+        Ok(Async::NotReady)
     }
 }
 
-impl<B> Poolable for PoolClient<B>
+// Poll one of the requests of a `Hedge`, returning `None` if it should keep
+// waiting on the other one.
+#[cfg(feature = "runtime")]
+fn poll_hedged<C, B>(fut: &mut Option<RetryableSendRequest<C, B>>, has_other: bool) -> Option<Poll<Response<Body>, ::Error>>
+where
+    C: Connect + 'static,
+    C::Future: 'static,
+    B: Payload + Send + 'static,
+    B::Data: Send,
+{
+    let res = match *fut {
+        Some(ref mut fut) => fut.poll(),
+        None => return None,
+    };
+    match res {
+        Ok(Async::NotReady) => None,
+        Ok(Async::Ready(res)) => Some(Ok(Async::Ready(res))),
+        Err(err) => {
+            *fut = None;
+            if has_other {
+                debug!("hedged request failed, waiting on the other: {}", err);
+                None
+            } else {
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+struct PoolClient<B> {
+    extra: Option<Extra>,
+    is_proxied: bool,
+    keep_alive: KeepAlive,
+    probe: Option<Probe>,
+    tx: PoolTx<B>,
+}
+
+// What the server said about reusing an HTTP/1 connection, in the legacy
+// `Keep-Alive` header of its responses, such as `timeout=5, max=100`.
+#[derive(Clone, Copy, Debug, Default)]
+struct KeepAlive {
+    // How many more requests the server accepts on the connection.
+    max: Option<u64>,
+    // How long the server keeps the connection open while idle.
+    timeout: Option<Duration>,
+}
+
+impl KeepAlive {
+    fn on_response(&mut self, headers: &HeaderMap) {
+        match headers.get("keep-alive") {
+            Some(value) => {
+                let (max, timeout) = ::headers::keep_alive_parse(value);
+                self.max = max;
+                // Retire the connection a second early, so a request sent
+                // just before the timeout doesn't race the server closing.
+                self.timeout = timeout.map(|secs| Duration::from_secs(secs.saturating_sub(1)));
+            },
+            None => {
+                // The request this answers used up one of those allowed.
+                self.max = self.max.map(|max| max.saturating_sub(1));
+            }
+        }
+        if self.max == Some(0) {
+            debug!("server allows no more requests on this connection");
+        }
+    }
+
+    fn allows_reuse(&self) -> bool {
+        self.max != Some(0)
+    }
+}
+
+enum PoolTx<B> {
+    Http1(conn::SendRequest<B>),
+    Http2(conn::Http2SendRequest<B>),
+}
+
+impl<B> PoolClient<B> {
+    fn new(tx: conn::SendRequest<B>, is_proxied: bool, extra: Option<Extra>, ver: Ver, probe: Option<Probe>) -> PoolClient<B> {
+        PoolClient {
+            extra,
+            is_proxied,
+            keep_alive: KeepAlive::default(),
+            probe,
+            tx: match ver {
+                Ver::Http1 => PoolTx::Http1(tx),
+                Ver::Http2 => PoolTx::Http2(tx.into_http2()),
+            },
+        }
+    }
+
+    fn is_http2(&self) -> bool {
+        match self.tx {
+            PoolTx::Http1(_) => false,
+            PoolTx::Http2(_) => true,
+        }
+    }
+
+    fn poll_ready(&mut self) -> Poll<(), ::Error> {
+        match self.tx {
+            PoolTx::Http1(ref mut tx) => tx.poll_ready(),
+            PoolTx::Http2(_) => Ok(Async::Ready(())),
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        match self.tx {
+            PoolTx::Http1(ref tx) => tx.is_ready(),
+            PoolTx::Http2(ref tx) => tx.is_ready(),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        match self.tx {
+            PoolTx::Http1(ref tx) => tx.is_closed(),
+            PoolTx::Http2(ref tx) => tx.is_closed(),
+        }
+    }
+}
+
+impl<B: Payload + 'static> PoolClient<B> {
+    //TODO: replace with `impl Future` when stable
+    fn send_request_retryable(&mut self, req: Request<B>) -> Box<Future<Item=Response<Body>, Error=(::Error, Option<Request<B>>)> + Send>
+    where
+        B: Send,
+    {
+        match self.tx {
+            PoolTx::Http1(ref mut tx) => Box::new(tx.try_send_request(req)),
+            PoolTx::Http2(ref mut tx) => tx.send_request_retryable(req),
+        }
+    }
+}
+
+impl<B> Poolable for PoolClient<B>
 where
     B: Send + 'static,
 {
     fn is_open(&self) -> bool {
         match self.tx {
-            PoolTx::Http1(ref tx) => tx.is_ready(),
+            PoolTx::Http1(ref tx) => tx.is_ready() && self.keep_alive.allows_reuse(),
             PoolTx::Http2(ref tx) => tx.is_ready(),
         }
     }
 
+    fn idle_timeout(&self) -> Option<Duration> {
+        self.keep_alive.timeout
+    }
+
+    fn poll_healthy(&mut self) -> Async<bool> {
+        if !self.is_open() {
+            return Async::Ready(false);
+        }
+        match self.probe {
+            Some(ref mut probe) => probe.poll_healthy(),
+            None => Async::Ready(true),
+        }
+    }
+
     fn reserve(self) -> Reservation<Self> {
         match self.tx {
             PoolTx::Http1(tx) => {
                 Reservation::Unique(PoolClient {
+                    extra: self.extra,
                     is_proxied: self.is_proxied,
+                    keep_alive: self.keep_alive,
+                    probe: self.probe,
                     tx: PoolTx::Http1(tx),
                 })
             },
             PoolTx::Http2(tx) => {
                 let b = PoolClient {
+                    extra: self.extra.clone(),
                     is_proxied: self.is_proxied,
+                    keep_alive: KeepAlive::default(),
+                    probe: None,
                     tx: PoolTx::Http2(tx.clone()),
                 };
                 let a = PoolClient {
+                    extra: self.extra,
                     is_proxied: self.is_proxied,
+                    keep_alive: KeepAlive::default(),
+                    probe: None,
                     tx: PoolTx::Http2(tx),
                 };
                 Reservation::Shared(a, b)
@@ -587,6 +1449,66 @@ enum ClientError<B> {
     }
 }
 
+impl<B> ClientError<B> {
+    fn from_send(err: ::Error, orig_req: Option<Request<B>>, replay: Option<Request<B>>, conn_reused: bool) -> ClientError<B> {
+        // A request that was never written can always be tried again. One
+        // that was written can only be if the connection closed before any
+        // response, and there is a copy to send instead. The copy is sent
+        // either way, since it has all of the extensions.
+        let req = match (orig_req, replay) {
+            (Some(_), Some(replay)) => replay,
+            (Some(req), None) => req,
+            (None, Some(replay)) if err.is_closed_before_response() => {
+                debug!("reused connection closed before responding: {}", err);
+                replay
+            },
+            (None, _) => return ClientError::Normal(err),
+        };
+        ClientError::Canceled {
+            connection_reused: conn_reused,
+            reason: err,
+            req,
+        }
+    }
+}
+
+// Copy an idempotent request whose body can be replayed, so that it can be
+// sent again on another connection. Only the extensions hyper reads are
+// copied, as others can't be cloned.
+fn replay_request<B: Payload>(req: &Request<B>) -> Option<Request<B>> {
+    if !req.method().is_idempotent() {
+        return None;
+    }
+    let body = req.body().replay()?;
+    let mut replay = Request::new(body);
+    *replay.method_mut() = req.method().clone();
+    *replay.uri_mut() = req.uri().clone();
+    *replay.version_mut() = req.version();
+    *replay.headers_mut() = req.headers().clone();
+    if let Some(config) = req.extensions().get::<RequestConfig>() {
+        replay.extensions_mut().insert(config.clone());
+    }
+    if let Some(token) = req.extensions().get::<CancelToken>() {
+        replay.extensions_mut().insert(token.clone());
+    }
+    if let Some(window) = req.extensions().get::<SendWindow>() {
+        replay.extensions_mut().insert(window.clone());
+    }
+    if let Some(header_case) = req.extensions().get::<::proto::HeaderCaseMap>() {
+        replay.extensions_mut().insert(header_case.clone());
+    }
+    if let Some(header_order) = req.extensions().get::<::proto::HeaderOrder>() {
+        replay.extensions_mut().insert(header_order.clone());
+    }
+    Some(replay)
+}
+
+/// The key of the `Client`'s pool: a connection's host and version.
+type PoolKey = (Arc<String>, Ver);
+
+/// Derives the host part of a request's `PoolKey`, see `Builder::pool_key`.
+type KeyFn = Arc<Fn(&Uri, &Extensions) -> String + Send + Sync>;
+
 /// A marker to identify what version a pooled connection is.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum Ver {
@@ -594,6 +1516,96 @@ enum Ver {
     Http2,
 }
 
+// Handshake a new connection, spawning the `Connection` task on the executor.
+//
+// The `Permit` is held until the `Connection` task is done.
+//
+//TODO: replace with `impl Future` when stable
+fn handshake<T, B>(builder: &conn::Builder, executor: &Exec, io: T, addr: Option<SocketAddr>, permit: Permit) -> Box<Future<Item=conn::SendRequest<B>, Error=::Error> + Send>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    B: Payload + Send + 'static,
+    B::Data: Send,
+{
+    let executor = executor.clone();
+    Box::new(builder.handshake_no_upgrades(io)
+        .and_then(move |(tx, conn)| {
+            executor.execute(conn.then(move |res| {
+                drop(permit);
+                res.map_err(|e| {
+                    debug!("client connection error: {}", e)
+                })
+            }));
+
+            // Wait for 'conn' to ready up before we
+            // declare this tx as usable
+            tx.ready()
+        })
+        .map_err(move |err| {
+            ::Error::new_connect(ConnectError::new(ConnectPhase::Handshake, err).with_addr(addr))
+        }))
+}
+
+// A `Delay` of `dur`, which is only created when first polled, so that it is
+// registered with the timer of the executor running it, instead of the one
+// of the thread building the future.
+#[cfg(feature = "runtime")]
+fn lazy_delay(dur: Duration) -> LazyDelay {
+    LazyDelay {
+        delay: None,
+        dur,
+    }
+}
+
+#[cfg(feature = "runtime")]
+struct LazyDelay {
+    delay: Option<Delay>,
+    dur: Duration,
+}
+
+#[cfg(feature = "runtime")]
+impl Future for LazyDelay {
+    type Item = ();
+    type Error = ::tokio_timer::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let dur = self.dur;
+        self.delay
+            .get_or_insert_with(|| Delay::new(Instant::now() + dur))
+            .poll()
+    }
+}
+
+fn domain_of(uri: &Uri) -> ::Result<String> {
+    match (uri.scheme_part(), uri.authority_part()) {
+        (Some(scheme), Some(auth)) => {
+            Ok(format!("{}://{}", scheme, auth))
+        }
+        _ => {
+            //TODO: replace this with a proper variant
+            Err(::Error::new_io(
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid URI for Client Request"
+                )
+            ))
+        }
+    }
+}
+
+fn set_host_header(headers: &mut HeaderMap, uri: &Uri) {
+    if let Entry::Vacant(entry) = headers.entry(HOST).expect("HOST is always valid header name") {
+        let hostname = uri.host().expect("authority implies host");
+        let host = if let Some(port) = uri.port() {
+            let s = format!("{}:{}", hostname, port);
+            HeaderValue::from_str(&s)
+        } else {
+            HeaderValue::from_str(hostname)
+        }.expect("uri host is valid header value");
+        entry.insert(host);
+    }
+}
+
 fn set_relative_uri(uri: &mut Uri, is_proxied: bool) {
     if is_proxied && uri.scheme_part() != Some(&Scheme::HTTPS) {
         return;
@@ -615,35 +1627,168 @@ fn set_relative_uri(uri: &mut Uri, is_proxied: bool) {
 #[derive(Clone)]
 pub struct Builder {
     //connect_timeout: Duration,
+    chunk_size: ChunkSize,
+    circuit_breaker: Option<(usize, Duration)>,
+    coalesce: bool,
+    coalesce_headers: Vec<HeaderName>,
+    coalesce_max_buffer: usize,
     exec: Exec,
     keep_alive: bool,
     keep_alive_timeout: Option<Duration>,
+    h1_body_buffer: BufferLimit,
+    h1_early_data: bool,
     h1_writev: bool,
     h1_title_case_headers: bool,
+    h1_allow_http09_responses: bool,
+    h1_header_value_policy: Option<HeaderValuePolicy>,
+    h1_chunk_extensions: bool,
+    h1_legacy_keep_alive: bool,
+    h1_max_buf_size: Option<usize>,
+    h1_max_headers: Option<usize>,
+    h1_preserve_header_case: bool,
+    h1_preserve_header_order: bool,
+    h1_request_target: Option<RequestTarget>,
+    h2_fallback: bool,
+    h2_fallback_expiry: Option<Duration>,
+    h2_fallback_timeout: Option<Duration>,
+    hedge_after: Option<Duration>,
     //TODO: make use of max_idle config
     max_idle: usize,
+    max_response_body_size: Option<u64>,
+    pool_checkout: CheckoutStrategy,
+    pool_health_check: bool,
+    pool_event_hook: Option<EventHook>,
+    pool_health_hook: Option<HealthHook>,
+    pool_key_fn: Option<KeyFn>,
+    pool_limits: Limits,
+    pool_max_lifetime: Option<Duration>,
+    pool_refresh: Option<Duration>,
+    pool_reuse: ReuseStrategy,
+    pool_timeout: Option<Duration>,
     retry_canceled_requests: bool,
+    retry_replayable_requests: bool,
     set_host: bool,
     ver: Ver,
+    write_timeout: Option<Duration>,
 }
 
 impl Default for Builder {
     fn default() -> Self {
         Self {
+            chunk_size: ChunkSize::default(),
+            circuit_breaker: None,
+            coalesce: false,
+            coalesce_headers: Vec::new(),
+            coalesce_max_buffer: 64 * 1024,
             exec: Exec::Default,
             keep_alive: true,
             keep_alive_timeout: Some(Duration::from_secs(90)),
+            h1_body_buffer: BufferLimit::default(),
+            h1_early_data: false,
             h1_writev: true,
             h1_title_case_headers: false,
+            h1_allow_http09_responses: false,
+            h1_header_value_policy: None,
+            h1_chunk_extensions: false,
+            h1_legacy_keep_alive: true,
+            h1_max_buf_size: None,
+            h1_max_headers: None,
+            h1_preserve_header_case: false,
+            h1_preserve_header_order: false,
+            h1_request_target: None,
+            h2_fallback: false,
+            h2_fallback_expiry: Some(Duration::from_secs(60 * 60)),
+            h2_fallback_timeout: Some(Duration::from_secs(5)),
+            hedge_after: None,
             max_idle: 5,
+            max_response_body_size: None,
+            pool_checkout: CheckoutStrategy::Race,
+            pool_health_check: false,
+            pool_event_hook: None,
+            pool_health_hook: None,
+            pool_key_fn: None,
+            pool_limits: Limits::default(),
+            pool_max_lifetime: None,
+            pool_refresh: None,
+            pool_reuse: ReuseStrategy::Lifo,
+            pool_timeout: None,
             retry_canceled_requests: true,
+            retry_replayable_requests: false,
             set_host: true,
             ver: Ver::Http1,
+            write_timeout: None,
         }
     }
 }
 
 impl Builder {
+    /// Configure the `Client` to use as little memory as possible.
+    ///
+    /// Idle connections are closed after 15 seconds. A request waits for a
+    /// connection in use to become idle, instead of opening another, and the
+    /// most recently used idle connection is reused, so that the others can
+    /// expire. Response body chunks are passed on as soon as they're read,
+    /// and bodies are written with vectored writes, instead of being copied
+    /// into a buffer. Requests may wait longer when the connections to a
+    /// host are busy.
+    ///
+    /// This sets `keep_alive`, `keep_alive_timeout`, `pool_checkout_strategy`,
+    /// `pool_reuse_strategy`, `pool_health_check`, `min_chunk_size` and
+    /// `http1_writev`, like the other [presets](::client#presets).
+    pub fn low_memory(&mut self) -> &mut Self {
+        self.keep_alive = true;
+        self.keep_alive_timeout = Some(Duration::from_secs(15));
+        self.pool_checkout = CheckoutStrategy::PreferIdle;
+        self.pool_reuse = ReuseStrategy::Lifo;
+        self.pool_health_check = false;
+        self.chunk_size.min = None;
+        self.h1_writev = true;
+        self
+    }
+
+    /// Configure the `Client` to transfer as many bytes as possible.
+    ///
+    /// A request races connecting with waiting for a connection in use,
+    /// and idle connections are reused in turn, spreading requests over all
+    /// of them. Small response body chunks are joined into chunks of at
+    /// least 16kb, which reach the application fewer and later.
+    ///
+    /// This sets `keep_alive`, `keep_alive_timeout`, `pool_checkout_strategy`,
+    /// `pool_reuse_strategy`, `pool_health_check`, `min_chunk_size` and
+    /// `http1_writev`, like the other [presets](::client#presets).
+    pub fn high_throughput(&mut self) -> &mut Self {
+        self.keep_alive = true;
+        self.keep_alive_timeout = Some(Duration::from_secs(90));
+        self.pool_checkout = CheckoutStrategy::Race;
+        self.pool_reuse = ReuseStrategy::Fifo;
+        self.pool_health_check = false;
+        self.chunk_size.min = Some(16 * 1024);
+        self.h1_writev = true;
+        self
+    }
+
+    /// Configure the `Client` to get responses as soon as possible.
+    ///
+    /// A request races connecting with waiting for a connection in use, and
+    /// idle connections are checked before being reused, so that a request
+    /// isn't first written to a connection the server already closed.
+    /// Response body chunks are passed on as soon as they're read. This can
+    /// open more connections than are needed.
+    ///
+    /// This sets `keep_alive`, `keep_alive_timeout`, `pool_checkout_strategy`,
+    /// `pool_reuse_strategy`, `pool_health_check`, `min_chunk_size` and
+    /// `http1_writev`, like the other [presets](::client#presets).
+    pub fn low_latency(&mut self) -> &mut Self {
+        self.keep_alive = true;
+        self.keep_alive_timeout = Some(Duration::from_secs(90));
+        self.pool_checkout = CheckoutStrategy::Race;
+        self.pool_reuse = ReuseStrategy::Lifo;
+        self.pool_health_check = true;
+        self.chunk_size.min = None;
+        self.h1_writev = true;
+        self
+    }
+
     /// Enable or disable keep-alive mechanics.
     ///
     /// Default is enabled.
@@ -657,6 +1802,11 @@ impl Builder {
     ///
     /// Pass `None` to disable timeout.
     ///
+    /// An HTTP/1 server can also say how long it keeps a connection open,
+    /// and how many more requests it accepts on it, with a `Keep-Alive`
+    /// header such as `timeout=5, max=100`. The connection then isn't reused
+    /// past either of those, whichever comes first with this timeout.
+    ///
     /// Default is 90 seconds.
     #[inline]
     pub fn keep_alive_timeout<D>(&mut self, val: D) -> &mut Self
@@ -667,6 +1817,430 @@ impl Builder {
         self
     }
 
+    /// Set an optional maximum lifetime for pooled connections.
+    ///
+    /// Once a connection has been open this long, it is no longer reused,
+    /// even if it has been busy the whole time. This helps with picking up
+    /// DNS changes, or with load balancers that rotate backends.
+    ///
+    /// Pass `None` to keep connections for as long as they're usable.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn pool_max_connection_lifetime<D>(&mut self, val: D) -> &mut Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.pool_max_lifetime = val.into();
+        self
+    }
+
+    /// Set how long before its maximum lifetime a pooled connection is
+    /// replaced.
+    ///
+    /// A request that reuses a connection this close to the end of its
+    /// [lifetime](Builder::pool_max_connection_lifetime) is still sent on
+    /// it right away, while a new connection to the same host is made in
+    /// the background, and put into the pool once ready. Requests then
+    /// don't have to wait for a connection to be made when an old one is
+    /// retired. Only one replacement is made for a host at a time, and
+    /// only for HTTP/1 connections, since an HTTP/2 connection is shared.
+    ///
+    /// This has no effect without a maximum lifetime.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn pool_refresh_before_lifetime<D>(&mut self, val: D) -> &mut Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.pool_refresh = val.into();
+        self
+    }
+
+    /// Set how to get a connection for a request, when there isn't an idle
+    /// one in the pool.
+    ///
+    /// By default, a new connection is started right away, racing with any
+    /// connection in use becoming idle. If the busy connection wins, the new
+    /// one is dropped. Against busy connections that are slow to be released,
+    /// this can create many more connections than are needed.
+    ///
+    /// Default is `CheckoutStrategy::Race`.
+    #[inline]
+    pub fn pool_checkout_strategy(&mut self, strategy: CheckoutStrategy) -> &mut Self {
+        self.pool_checkout = strategy;
+        self
+    }
+
+    /// Set which idle connection is reused, when there are several for a
+    /// host.
+    ///
+    /// `ReuseStrategy::Lifo` reuses the most recently used connection, so
+    /// that the fewest connections are kept busy. `ReuseStrategy::Fifo`
+    /// reuses the least recently used one, which keeps all of them warm,
+    /// so that they don't reach a server's idle timeout.
+    ///
+    /// Default is `ReuseStrategy::Lifo`.
+    #[inline]
+    pub fn pool_reuse_strategy(&mut self, strategy: ReuseStrategy) -> &mut Self {
+        self.pool_reuse = strategy;
+        self
+    }
+
+    /// Set whether idle HTTP/1 connections are checked before being reused.
+    ///
+    /// If true, an idle connection first reads from its transport once more,
+    /// and is only reused if the server hasn't closed it, nor sent any
+    /// unexpected bytes. Otherwise, closing is only noticed whenever the
+    /// connection gets to read, which may be after a request was already
+    /// written to it.
+    ///
+    /// HTTP/2 connections are always checked for having been closed,
+    /// including from a `GOAWAY`.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn pool_health_check(&mut self, val: bool) -> &mut Self {
+        self.pool_health_check = val;
+        self
+    }
+
+    /// Set a function to decide if an idle connection may be reused.
+    ///
+    /// The function is called with each idle connection before it's picked
+    /// from the pool. If it returns `false`, the connection is closed, and
+    /// another one is tried instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate hyper;
+    /// # use std::time::Duration;
+    /// use hyper::Client;
+    ///
+    /// # fn main() {
+    /// let mut builder = Client::builder();
+    /// builder.pool_health_check_hook(|conn| conn.idle_for() < Duration::from_secs(30));
+    /// # }
+    /// ```
+    pub fn pool_health_check_hook<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&IdleConnection) -> bool + Send + Sync + 'static,
+    {
+        self.pool_health_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Set a function to be told about what happens in the pool.
+    ///
+    /// The function is called with a [`PoolEvent`](PoolEvent) whenever a
+    /// request gets a connection, with how long it waited and whether the
+    /// connection was reused, and whenever an idle connection is evicted,
+    /// with the reason why. It's called while the pool may be locked, so it
+    /// should return quickly, such as by only updating some metrics.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate hyper;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use hyper::Client;
+    /// use hyper::client::{CheckoutOutcome, PoolEvent};
+    ///
+    /// # fn main() {
+    /// let misses = Arc::new(AtomicUsize::new(0));
+    /// let counter = misses.clone();
+    /// let mut builder = Client::builder();
+    /// builder.pool_event_hook(move |event| {
+    ///     if let PoolEvent::Checkout { outcome: CheckoutOutcome::Connected, .. } = *event {
+    ///         counter.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// });
+    /// # }
+    /// ```
+    pub fn pool_event_hook<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&PoolEvent) + Send + Sync + 'static,
+    {
+        self.pool_event_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Set a function to decide which pooled connections a request may use.
+    ///
+    /// By default, connections are shared by all requests to the same scheme
+    /// and authority. The function is called with the URI and extensions of
+    /// each request, which always has a scheme and authority, and only
+    /// requests for which it returns the same key share connections. This keeps apart connections that were made
+    /// differently for some requests, such as with another client
+    /// certificate, or through another proxy.
+    ///
+    /// Limits like `max_connections_per_host` count the connections of each
+    /// key. [`Client::prepare`](Client::prepare) calls the function with
+    /// empty extensions. Requests pinned to an address with
+    /// [`RequestConfig::resolve`](RequestConfig::resolve) are still pooled
+    /// apart, by the key and the address.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate hyper;
+    /// use hyper::Client;
+    ///
+    /// // A request extension, that picks the tenant to connect as.
+    /// #[derive(Clone)]
+    /// struct Tenant(String);
+    ///
+    /// # fn main() {
+    /// let mut builder = Client::builder();
+    /// builder.pool_key(|uri, extensions| {
+    ///     let origin = format!("{}://{}", uri.scheme_part().unwrap(), uri.authority_part().unwrap());
+    ///     match extensions.get::<Tenant>() {
+    ///         Some(tenant) => format!("{}#{}", origin, tenant.0),
+    ///         None => origin,
+    ///     }
+    /// });
+    /// # }
+    /// ```
+    pub fn pool_key<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&Uri, &Extensions) -> String + Send + Sync + 'static,
+    {
+        self.pool_key_fn = Some(Arc::new(f));
+        self
+    }
+
+    /// Set an optional maximum number of connections to a single host.
+    ///
+    /// This counts connections that are in use, idle in the pool, or still
+    /// connecting. When the limit is reached, requests to that host wait for
+    /// one of those connections, unless `pool_fail_fast` is set.
+    ///
+    /// Default is `None`, no limit.
+    #[inline]
+    pub fn max_connections_per_host<N>(&mut self, val: N) -> &mut Self
+    where
+        N: Into<Option<usize>>,
+    {
+        self.pool_limits.per_host = val.into();
+        self
+    }
+
+    /// Set an optional maximum number of requests in flight to a single host.
+    ///
+    /// A request is in flight from when it starts waiting for a connection,
+    /// until its response body ends or is dropped, whether it's sent on an
+    /// HTTP/1 connection or as an HTTP/2 stream. When the limit is reached,
+    /// requests to that host wait for one of the others to complete, unless
+    /// `pool_fail_fast` is set. That wait counts towards the `pool_timeout`.
+    ///
+    /// Hosts are told apart the same way as for `max_connections_per_host`.
+    ///
+    /// Default is `None`, no limit.
+    #[inline]
+    pub fn max_requests_per_host<N>(&mut self, val: N) -> &mut Self
+    where
+        N: Into<Option<usize>>,
+    {
+        self.pool_limits.requests_per_host = val.into();
+        self
+    }
+
+    /// Set an optional maximum number of connections to all hosts combined.
+    ///
+    /// This counts connections the same way as `max_connections_per_host`.
+    /// When the limit is reached, the connection idle the longest for another
+    /// host is closed to make room, and requests wait for a connection to
+    /// close, unless `pool_fail_fast` is set.
+    ///
+    /// Default is `None`, no limit.
+    #[inline]
+    pub fn max_total_connections<N>(&mut self, val: N) -> &mut Self
+    where
+        N: Into<Option<usize>>,
+    {
+        self.pool_limits.total = val.into();
+        self
+    }
+
+    /// Set whether requests fail right away when a connection or request
+    /// limit is reached, instead of waiting.
+    ///
+    /// If true, such a request fails with an error where
+    /// `Error::is_pool_full` is true. Waiting requests can be bounded
+    /// with `pool_timeout` instead.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn pool_fail_fast(&mut self, val: bool) -> &mut Self {
+        self.pool_limits.fail_fast = val;
+        self
+    }
+
+    /// Set an optional circuit breaker for each host.
+    ///
+    /// After `failures` requests to a host fail in a row, either failing to
+    /// connect or getting a `5xx` response, the following requests to it
+    /// fail right away for the `cool_down`, with an error where
+    /// `Error::is_circuit_open` is true. Once the `cool_down` is over, a
+    /// single request is sent to check on the host. If it succeeds, requests
+    /// are sent again as usual, otherwise they keep failing for another
+    /// `cool_down`.
+    ///
+    /// Other errors, such as a connection closing before a response, don't
+    /// count either way. Hosts are told apart the same way as for
+    /// `max_connections_per_host`.
+    ///
+    /// Default is `None`, requests are always sent.
+    #[inline]
+    pub fn circuit_breaker<N>(&mut self, val: N) -> &mut Self
+    where
+        N: Into<Option<(usize, Duration)>>,
+    {
+        self.circuit_breaker = val.into();
+        self
+    }
+
+    /// Set whether identical `GET` and `HEAD` requests in flight at the same
+    /// time share a single exchange.
+    ///
+    /// A request with the same method and URI as one waiting for its
+    /// response isn't sent, and gets a copy of that response instead, with
+    /// the same status, version, and headers, and with every chunk of the
+    /// body as it's read. Requests with a body are always sent.
+    ///
+    /// Requests with an `Authorization`, `Cookie`, or `Proxy-Authorization`
+    /// header are always sent as well, unless that header was added with
+    /// `coalesce_key_header`, so a response is never shared between
+    /// different credentials. So are requests with their own
+    /// `RequestConfig`, since they would be sent with another one's.
+    ///
+    /// If the request that was sent fails, is canceled with its
+    /// `CancelToken`, or is dropped before its response arrives, the ones
+    /// sharing it fail with an error where `Error::is_canceled` is true. A
+    /// request sharing another's response is still aborted by its own
+    /// `CancelToken`, but doesn't abort the exchange.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn coalesce_requests(&mut self, val: bool) -> &mut Self {
+        self.coalesce = val;
+        self
+    }
+
+    /// Add a header whose values must be the same for requests to share an
+    /// exchange, with `coalesce_requests`.
+    ///
+    /// This should include the headers a response may vary on, such as
+    /// `Accept`. Adding `Authorization`, `Cookie`, or `Proxy-Authorization`
+    /// lets requests with the very same credentials share an exchange.
+    ///
+    /// Default is that only the method and URI must match.
+    #[inline]
+    pub fn coalesce_key_header(&mut self, name: HeaderName) -> &mut Self {
+        self.coalesce_headers.push(name);
+        self
+    }
+
+    /// Set how many bytes of a shared response body may be held for each
+    /// request that hasn't read them yet.
+    ///
+    /// The body is read as fast as the slowest request reads its copy, once
+    /// it holds this many bytes.
+    ///
+    /// Default is 64kb.
+    #[inline]
+    pub fn coalesce_max_buffer(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "the coalesce_max_buffer cannot be 0");
+        self.coalesce_max_buffer = max;
+        self
+    }
+
+    /// Set the maximum number of bytes a response body may have.
+    ///
+    /// The body of a response that is larger yields an error where
+    /// `Error::is_body_too_large` is true, once more than `max` bytes were
+    /// received, and its connection is closed, or its HTTP/2 stream reset,
+    /// instead of receiving the rest. This applies no matter the
+    /// `Content-Length` of the response, so chunked and HTTP/2 bodies are
+    /// limited too.
+    ///
+    /// A request can change this with
+    /// [`RequestConfig::max_response_body_size`](RequestConfig::max_response_body_size).
+    ///
+    /// Default is no maximum.
+    #[inline]
+    pub fn max_response_body_size(&mut self, max: u64) -> &mut Self {
+        self.max_response_body_size = Some(max);
+        self
+    }
+
+    /// Set an optional timeout for how long a request waits to get a
+    /// connection, either an idle one from the pool or a newly connected one.
+    ///
+    /// If the timeout is reached, the request fails with an error where
+    /// `Error::is_pool_timeout` is true.
+    ///
+    /// This requires the `runtime` feature, and is ignored without it.
+    ///
+    /// Default is `None`, waiting indefinitely.
+    #[inline]
+    pub fn pool_timeout<D>(&mut self, val: D) -> &mut Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.pool_timeout = val.into();
+        self
+    }
+
+    /// Set an optional delay after which a request is sent a second time,
+    /// if its response hasn't arrived yet.
+    ///
+    /// The duplicate request is sent on another connection, and whichever
+    /// response arrives first is used, while the other request is canceled.
+    /// This trades some extra load for better tail latency, when a few
+    /// connections or servers are much slower than the rest.
+    ///
+    /// Only idempotent requests with bodies that can be sent again (see
+    /// [`Payload::replay`](::body::Payload::replay)) are hedged.
+    ///
+    /// This requires the `runtime` feature, and is ignored without it.
+    ///
+    /// Default is `None`, never hedging requests.
+    #[inline]
+    pub fn hedge_after<D>(&mut self, val: D) -> &mut Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.hedge_after = val.into();
+        self
+    }
+
+    /// Set how long a connection may go without writing anything, while
+    /// it has bytes waiting to be written, before it is closed.
+    ///
+    /// A server that stops reading shows up as a request that can't be
+    /// written, rather than as a response that's late, and so isn't caught
+    /// by timeouts on responses. A request on an HTTP/1 connection that
+    /// timed out fails with an error where `Error::is_write_timeout` is
+    /// true. See
+    /// [`conn::Builder::write_timeout`](conn::Builder::write_timeout) for
+    /// details.
+    ///
+    /// This requires the `runtime` feature, and is ignored without it.
+    ///
+    /// Default is `None`, waiting indefinitely.
+    #[inline]
+    pub fn write_timeout<D>(&mut self, val: D) -> &mut Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.write_timeout = val.into();
+        self
+    }
+
     /// Set whether HTTP/1 connections should try to use vectored writes,
     /// or always flatten into a single buffer.
     ///
@@ -692,6 +2266,201 @@ impl Builder {
         self
     }
 
+    /// Set how HTTP/1 connections treat request header values that contain
+    /// `CR`, `LF`, or `NUL` bytes.
+    ///
+    /// Note that setting this does not affect HTTP/2.
+    ///
+    /// Default is to not check header values.
+    pub fn http1_header_value_policy(&mut self, policy: HeaderValuePolicy) -> &mut Self {
+        self.h1_header_value_policy = Some(policy);
+        self
+    }
+
+    /// Set whether HTTP/1 connections accept HTTP/0.9 responses, which are
+    /// only a body, without a status line or headers.
+    ///
+    /// See [`conn::Builder::http1_allow_http09_responses`](conn::Builder::http1_allow_http09_responses)
+    /// for details.
+    ///
+    /// Default is false.
+    pub fn http1_allow_http09_responses(&mut self, val: bool) -> &mut Self {
+        self.h1_allow_http09_responses = val;
+        self
+    }
+
+    /// Set the form of the request-target of HTTP/1 requests.
+    ///
+    /// See [`RequestTarget`](RequestTarget) for details. A request can
+    /// change this with
+    /// [`RequestConfig::request_target`](RequestConfig::request_target).
+    ///
+    /// Default is origin-form, or absolute-form for `http` requests sent
+    /// through a proxy.
+    pub fn http1_request_target(&mut self, target: RequestTarget) -> &mut Self {
+        self.h1_request_target = Some(target);
+        self
+    }
+
+    /// Set whether HTTP/1 connections keep the extensions of chunks of
+    /// response bodies.
+    ///
+    /// See [`conn::Builder::http1_chunk_extensions`](conn::Builder::http1_chunk_extensions)
+    /// for details.
+    ///
+    /// Default is false.
+    pub fn http1_chunk_extensions(&mut self, enabled: bool) -> &mut Self {
+        self.h1_chunk_extensions = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1.0 requests can keep connections alive, to be
+    /// reused from the pool.
+    ///
+    /// See [`conn::Builder::http1_legacy_keep_alive`](conn::Builder::http1_legacy_keep_alive)
+    /// for details.
+    ///
+    /// Default is true.
+    pub fn http1_legacy_keep_alive(&mut self, enabled: bool) -> &mut Self {
+        self.h1_legacy_keep_alive = enabled;
+        self
+    }
+
+    /// Set the maximum buffer size of HTTP/1 connections.
+    ///
+    /// See [`conn::Builder::http1_max_buf_size`](conn::Builder::http1_max_buf_size)
+    /// for details.
+    ///
+    /// Default is ~400kb.
+    ///
+    /// # Panics
+    ///
+    /// The minimum value allowed is 8192. This method panics if the passed `max` is less than the minimum.
+    pub fn http1_max_buf_size(&mut self, max: usize) -> &mut Self {
+        assert!(
+            max >= ::proto::MINIMUM_MAX_BUFFER_SIZE,
+            "the http1_max_buf_size cannot be smaller than the minimum that h1 specifies."
+        );
+        self.h1_max_buf_size = Some(max);
+        self
+    }
+
+    /// Set the maximum number of headers of a response on HTTP/1
+    /// connections.
+    ///
+    /// See [`conn::Builder::http1_max_headers`](conn::Builder::http1_max_headers)
+    /// for details.
+    ///
+    /// Default is 100.
+    pub fn http1_max_headers(&mut self, max: usize) -> &mut Self {
+        self.h1_max_headers = Some(max);
+        self
+    }
+
+    /// Set whether HTTP/1 connections keep the original casing of response
+    /// header names.
+    ///
+    /// See [`conn::Builder::http1_preserve_header_case`](conn::Builder::http1_preserve_header_case)
+    /// for details.
+    ///
+    /// Default is false.
+    pub fn http1_preserve_header_case(&mut self, enabled: bool) -> &mut Self {
+        self.h1_preserve_header_case = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections keep the original order of response
+    /// header lines.
+    ///
+    /// See [`conn::Builder::http1_preserve_header_order`](conn::Builder::http1_preserve_header_order)
+    /// for details.
+    ///
+    /// Default is false.
+    pub fn http1_preserve_header_order(&mut self, enabled: bool) -> &mut Self {
+        self.h1_preserve_header_order = enabled;
+        self
+    }
+
+    /// Set the maximum number of response body bytes that an HTTP/1
+    /// connection holds, after reading them, until the `Body` is polled.
+    ///
+    /// See [`conn::Builder::http1_max_body_buffer`](conn::Builder::http1_max_body_buffer)
+    /// for details.
+    ///
+    /// Default is no maximum.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the passed `max` is 0.
+    pub fn http1_max_body_buffer(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "the http1_max_body_buffer cannot be 0");
+        self.h1_body_buffer.max = Some(max);
+        self
+    }
+
+    /// Set how many held response body bytes make an HTTP/1 connection stop
+    /// reading from its transport.
+    ///
+    /// Reading resumes once the `Body` was polled below `bytes` again. If a
+    /// maximum is also set, and is smaller, the maximum is used.
+    ///
+    /// Default is the `http1_max_body_buffer`, if set.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the passed `bytes` is 0.
+    pub fn http1_body_read_threshold(&mut self, bytes: usize) -> &mut Self {
+        assert!(bytes > 0, "the http1_body_read_threshold cannot be 0");
+        self.h1_body_buffer.stop_at = Some(bytes);
+        self
+    }
+
+    /// Set the maximum size of each chunk of a response body.
+    ///
+    /// Larger reads from the connection are split into several chunks.
+    ///
+    /// Default is no maximum.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the passed `max` is 0.
+    pub fn max_chunk_size(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "the max_chunk_size cannot be 0");
+        self.chunk_size.max = Some(max);
+        self
+    }
+
+    /// Set the minimum size of each chunk of a response body.
+    ///
+    /// See [`conn::Builder::min_chunk_size`](conn::Builder::min_chunk_size)
+    /// for details.
+    ///
+    /// Default is no minimum.
+    pub fn min_chunk_size(&mut self, min: usize) -> &mut Self {
+        self.chunk_size.min = Some(min);
+        self
+    }
+
+    /// Set whether idempotent requests may be sent as TLS 1.3 early data.
+    ///
+    /// When enabled, a new HTTP/1 connection for an idempotent request asks
+    /// the connector, with [`Destination::early_data`](connect::Destination::early_data),
+    /// to resolve before its TLS handshake completes, so that the request
+    /// is sent along with the handshake, saving a round trip. If the server
+    /// rejects the early data, the connector sends the request again once
+    /// the handshake completes.
+    ///
+    /// Early data can be replayed by an attacker, so requests that aren't
+    /// idempotent always wait for the handshake. It's only used if the
+    /// connector supports it, and the server allowed it on a previous
+    /// connection.
+    ///
+    /// Default is `false`.
+    pub fn http1_early_data(&mut self, val: bool) -> &mut Self {
+        self.h1_early_data = val;
+        self
+    }
+
     /// Set whether the connection **must** use HTTP/2.
     ///
     /// Note that setting this to true prevents HTTP/1 from being allowed.
@@ -706,6 +2475,55 @@ impl Builder {
         self
     }
 
+    /// Set whether HTTP/2 connections may fall back to HTTP/1.1.
+    ///
+    /// When enabled, connections are started with HTTP/2 prior knowledge,
+    /// just like `http2_only`. If a server doesn't answer the connection
+    /// preface with its HTTP/2 `SETTINGS`, the destination is connected to
+    /// again using HTTP/1.1, and the pool remembers to use HTTP/1.1 for that
+    /// host, for as long as set with
+    /// [`http2_fallback_expiry`](Builder::http2_fallback_expiry).
+    ///
+    /// Disabling it again uses the version set with `http2_only`.
+    ///
+    /// Default is false.
+    pub fn http2_fallback(&mut self, val: bool) -> &mut Self {
+        self.h2_fallback = val;
+        self
+    }
+
+    /// Set how long to wait for the HTTP/2 `SETTINGS` of a server, when
+    /// [`http2_fallback`](Builder::http2_fallback) is enabled.
+    ///
+    /// A server that doesn't answer in time is treated like one that
+    /// doesn't speak HTTP/2, and is connected to again using HTTP/1.1.
+    ///
+    /// This requires the `runtime` feature, and is ignored without it.
+    ///
+    /// Default is 5 seconds. Pass `None` to wait indefinitely.
+    #[inline]
+    pub fn http2_fallback_timeout<D>(&mut self, val: D) -> &mut Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.h2_fallback_timeout = val.into();
+        self
+    }
+
+    /// Set how long a host that fell back to HTTP/1.1 keeps being connected
+    /// to with HTTP/1.1, before trying HTTP/2 again.
+    ///
+    /// Default is 1 hour. Pass `None` to remember it for as long as the
+    /// `Client` lives.
+    #[inline]
+    pub fn http2_fallback_expiry<D>(&mut self, val: D) -> &mut Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.h2_fallback_expiry = val.into();
+        self
+    }
+
     /// Set whether to retry requests that get disrupted before ever starting
     /// to write.
     ///
@@ -723,6 +2541,22 @@ impl Builder {
         self
     }
 
+    /// Set whether to retry idempotent requests, such as `GET` or `PUT`,
+    /// when a reused connection closes after the request was written, but
+    /// before any response.
+    ///
+    /// Only requests whose body can be sent again are retried (see
+    /// [`Payload::replay`](::body::Payload::replay)), and only while
+    /// [retrying canceled requests](Builder::retry_canceled_requests) is
+    /// enabled. The request sent again keeps all its extensions.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn retry_replayable_requests(&mut self, val: bool) -> &mut Self {
+        self.retry_replayable_requests = val;
+        self
+    }
+
     /// Set whether to automatically add the `Host` header to requests.
     ///
     /// If true, and a request does not include a `Host` header, one will be
@@ -758,6 +2592,22 @@ impl Builder {
         self.build(connector)
     }
 
+    /// Build a client with this configuration, injecting
+    /// [`Faults`](::testing::Faults) into the connections of `connector`.
+    ///
+    /// This method is only available with the `testing` feature.
+    #[cfg(feature = "testing")]
+    pub fn build_with_faults<C, B>(&self, connector: C, faults: ::testing::Faults) -> Client<::testing::FaultyConnector<C>, B>
+    where
+        C: Connect,
+        C::Transport: 'static,
+        C::Future: 'static,
+        B: Payload + Send,
+        B::Data: Send,
+    {
+        self.build(faults.connector(connector))
+    }
+
     /// Combine the configuration of this builder with a connector to create a `Client`.
     pub fn build<C, B>(&self, connector: C) -> Client<C, B>
     where
@@ -767,15 +2617,52 @@ impl Builder {
         B: Payload + Send,
         B::Data: Send,
     {
+        let pool = Pool::with_config(self.keep_alive, self.keep_alive_timeout, self.pool_max_lifetime, self.pool_limits, &self.exec);
+        pool.set_health_hook(self.pool_health_hook.clone());
+        pool.set_event_hook(self.pool_event_hook.clone());
+        pool.set_reuse_strategy(self.pool_reuse);
+        pool.set_refresh(self.pool_refresh);
         Client {
+            chunk_size: self.chunk_size,
+            circuit_breaker: self.circuit_breaker.map(|(failures, cool_down)| {
+                Arc::new(CircuitBreaker::new(failures, cool_down))
+            }),
+            coalescer: if self.coalesce {
+                Some(Arc::new(Coalescer::new(self.coalesce_headers.clone(), self.coalesce_max_buffer)))
+            } else {
+                None
+            },
             connector: Arc::new(connector),
             executor: self.exec.clone(),
+            h1_body_buffer: self.h1_body_buffer,
+            h1_early_data: self.h1_early_data,
             h1_writev: self.h1_writev,
             h1_title_case_headers: self.h1_title_case_headers,
-            pool: Pool::new(self.keep_alive, self.keep_alive_timeout, &self.exec),
+            h1_allow_http09_responses: self.h1_allow_http09_responses,
+            h1_header_value_policy: self.h1_header_value_policy,
+            h1_chunk_extensions: self.h1_chunk_extensions,
+            h1_legacy_keep_alive: self.h1_legacy_keep_alive,
+            h1_max_buf_size: self.h1_max_buf_size,
+            h1_max_headers: self.h1_max_headers,
+            h1_preserve_header_case: self.h1_preserve_header_case,
+            h1_preserve_header_order: self.h1_preserve_header_order,
+            h1_request_target: self.h1_request_target,
+            h2_fallback: self.h2_fallback,
+            h2_fallback_expiry: self.h2_fallback_expiry,
+            h2_fallback_timeout: self.h2_fallback_timeout,
+            hedge_after: self.hedge_after,
+            max_response_body_size: self.max_response_body_size,
+            measurements: Arc::new(Measurements::new()),
+            pool,
+            pool_checkout: self.pool_checkout,
+            pool_health_check: self.pool_health_check,
+            pool_key_fn: self.pool_key_fn.clone(),
+            pool_timeout: self.pool_timeout,
             retry_canceled_requests: self.retry_canceled_requests,
+            retry_replayable_requests: self.retry_replayable_requests,
             set_host: self.set_host,
-            ver: self.ver,
+            ver: if self.h2_fallback { Ver::Http2 } else { self.ver },
+            write_timeout: self.write_timeout,
         }
     }
 }
@@ -787,6 +2674,10 @@ impl fmt::Debug for Builder {
             .field("keep_alive_timeout", &self.keep_alive_timeout)
             .field("http1_writev", &self.h1_writev)
             .field("max_idle", &self.max_idle)
+            .field("pool_checkout_strategy", &self.pool_checkout)
+            .field("pool_health_check", &self.pool_health_check)
+            .field("pool_limits", &self.pool_limits)
+            .field("pool_reuse_strategy", &self.pool_reuse)
             .field("set_host", &self.set_host)
             .field("version", &self.ver)
             .finish()
@@ -804,4 +2695,35 @@ mod unit_tests {
 
         assert_eq!(uri.to_string(), "/");
     }
+
+    #[test]
+    fn builder_presets_replace_each_other() {
+        let mut builder = Client::builder();
+        builder.low_memory();
+        assert_eq!(builder.pool_checkout, CheckoutStrategy::PreferIdle);
+        assert_eq!(builder.keep_alive_timeout, Some(Duration::from_secs(15)));
+
+        builder.high_throughput();
+        assert_eq!(builder.pool_checkout, CheckoutStrategy::Race);
+        assert_eq!(builder.chunk_size.min, Some(16 * 1024));
+
+        builder.low_latency().pool_reuse_strategy(ReuseStrategy::Fifo);
+        assert!(builder.pool_health_check);
+        assert_eq!(builder.chunk_size.min, None);
+        assert_eq!(builder.pool_reuse, ReuseStrategy::Fifo);
+    }
+
+    #[cfg(feature = "runtime")]
+    #[test]
+    fn http2_fallback_disabled_keeps_version() {
+        let mut builder = Client::builder();
+        builder.http2_fallback(true);
+        assert_eq!(builder.build_http::<Body>().ver, Ver::Http2);
+
+        builder.http2_fallback(false);
+        assert_eq!(builder.build_http::<Body>().ver, Ver::Http1);
+
+        builder.http2_only(true).http2_fallback(true).http2_fallback(false);
+        assert_eq!(builder.build_http::<Body>().ver, Ver::Http2);
+    }
 }
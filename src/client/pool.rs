@@ -1,19 +1,41 @@
+//! A pool of connections, grouped by key.
+//!
+//! This is the pool that a [`Client`](::client::Client) uses. It can also be
+//! used to pool connections of other protocols, such as tunnels made with
+//! `CONNECT` over [`client::conn`](::client::conn), to get the same
+//! checkout, idle timeout, and eviction behavior.
+//!
+//! A connection type implements [`Poolable`](Poolable), and connections are
+//! grouped by a type implementing [`Key`](Key). An idle connection is taken
+//! with [`Pool::checkout`](Pool::checkout), or a new one is made and then
+//! wrapped with [`Pool::pooled`](Pool::pooled). Either way, it returns to
+//! the pool once the [`Pooled`](Pooled) is dropped, if it's still open.
+use std::cmp;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
+use std::hash::Hash;
+use std::mem;
 use std::ops::{Deref, DerefMut};
-use std::sync::{Arc, Mutex, Weak};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
 use std::time::{Duration, Instant};
 
 use futures::{Future, Async, Poll};
 use futures::sync::oneshot;
+use futures::task::{self, Task};
 #[cfg(feature = "runtime")]
 use tokio_timer::Interval;
 
 use common::Exec;
 use super::Ver;
 
-pub(super) struct Pool<T> {
-    inner: Arc<PoolInner<T>>,
+// The most hosts remembered as HTTP/1 only, or as negotiating HTTP/2.
+const MAX_HOSTS: usize = 1024;
+
+/// A pool of connections of type `T`, grouped by keys of type `K`.
+///
+/// Clones share the same pool.
+pub struct Pool<T, K> {
+    inner: Arc<PoolInner<T, K>>,
 }
 
 // Before using a pooled connection, make sure the sender is not dead.
@@ -21,8 +43,22 @@ pub(super) struct Pool<T> {
 // This is a trait to allow the `client::pool::tests` to work for `i32`.
 //
 // See https://github.com/hyperium/hyper/issues/1429
-pub(super) trait Poolable: Send + Sized + 'static {
+/// A connection that can be kept in a `Pool`.
+pub trait Poolable: Send + Sized + 'static {
+    /// Whether the connection is still usable.
+    ///
+    /// Connections that aren't open are dropped instead of returning to
+    /// the pool, or being checked out of it.
     fn is_open(&self) -> bool;
+    /// Check that an idle connection is still healthy, before it's reused.
+    fn poll_healthy(&mut self) -> Async<bool> {
+        Async::Ready(self.is_open())
+    }
+    /// How long the connection may stay idle, if it's shorter than the
+    /// idle timeout of the pool, such as when the server said so.
+    fn idle_timeout(&self) -> Option<Duration> {
+        None
+    }
     /// Reserve this connection.
     ///
     /// Allows for HTTP/2 to return a shared reservation.
@@ -34,7 +70,8 @@ pub(super) trait Poolable: Send + Sized + 'static {
 ///
 /// Specifically, HTTP/1 requires a unique reservation, but HTTP/2 can be
 /// used for multiple requests.
-pub(super) enum Reservation<T> {
+#[derive(Debug)]
+pub enum Reservation<T> {
     /// This connection could be used multiple times, the first one will be
     /// reinserted into the `idle` pool, and the second will be given to
     /// the `Checkout`.
@@ -44,22 +81,97 @@ pub(super) enum Reservation<T> {
     Unique(T),
 }
 
-/// Simple type alias in case the key type needs to be adjusted.
-type Key = (Arc<String>, Ver);
+/// The key that connections in a `Pool` are grouped by.
+///
+/// A `Checkout` only yields connections with an equal key.
+pub trait Key: Clone + Eq + Hash + fmt::Debug + Send + Sync + 'static {
+    /// The host connections for this key are to.
+    ///
+    /// `PoolStats` count idle connections by host.
+    fn host(&self) -> &str;
+
+    /// Whether a single connection for this key can be used by many
+    /// checkouts at the same time, like an HTTP/2 connection.
+    ///
+    /// Only one connection for a shared key is kept in the pool, and only
+    /// one is connected at a time. The connection's `Poolable::reserve`
+    /// should return `Reservation::Shared` exactly when its key is shared.
+    ///
+    /// Default is `false`.
+    fn is_shared(&self) -> bool {
+        false
+    }
+}
+
+impl Key for (Arc<String>, Ver) {
+    fn host(&self) -> &str {
+        &self.0
+    }
+
+    fn is_shared(&self) -> bool {
+        self.1 == Ver::Http2
+    }
+}
 
-struct PoolInner<T> {
-    connections: Mutex<Connections<T>>,
+struct PoolInner<T, K> {
+    connections: Mutex<Connections<T, K>>,
     enabled: bool,
+    limits: Limits,
+    // Kept apart from `connections`, since a `Permit` may outlive the
+    // pool, and doesn't know the type of connection it is for.
+    open: Arc<Mutex<Open>>,
+}
+
+impl<T, K> PoolInner<T, K> {
+    // Lock the connections where idle ones may be evicted, or `None` if
+    // the lock is poisoned.
+    fn lock<'a>(&'a self) -> Option<Locked<'a, T, K>> {
+        self.connections.lock().ok().map(|guard| Locked {
+            guard: Some(guard),
+        })
+    }
+}
+
+/// Limits on how many connections may be open, and requests in flight, at
+/// the same time.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct Limits {
+    pub(super) per_host: Option<usize>,
+    pub(super) requests_per_host: Option<usize>,
+    pub(super) total: Option<usize>,
+    /// Whether to error instead of waiting when a limit is reached.
+    pub(super) fail_fast: bool,
+}
+
+// Counts of connections that are open, or are being connected, to enforce
+// the `Limits`, and to know when all of them have closed after the pool is
+// closed. Idle connections in the pool are still open, and so count.
+struct Open {
+    per_host: HashMap<Arc<String>, usize>,
+    // Requests in flight to each host, when `requests_per_host` is limited.
+    requests: HashMap<Arc<String>, usize>,
+    total: usize,
+    // Once closed, no more connections may be opened.
+    closed: bool,
+    // Tasks waiting for a `Permit` to be released, so they may try to open
+    // a connection of their own, or waiting for all of them to be released.
+    waiting: Vec<Task>,
 }
 
-struct Connections<T> {
+struct Connections<T, K> {
     // A flag that a connection is being estabilished, and the connection
     // should be shared. This prevents making multiple HTTP/2 connections
     // to the same host.
-    connecting: HashSet<Key>,
+    connecting: HashSet<K>,
+    // Hosts that failed to speak HTTP/2 with prior knowledge, and so
+    // should be connected to with HTTP/1 instead, until they expire.
+    http1_only: HashMap<Arc<String>, Option<Instant>>,
+    // Hosts whose connections negotiated HTTP/2, such as with ALPN, and so
+    // should share an HTTP/2 connection.
+    h2_negotiated: HashSet<Arc<String>>,
     // These are internal Conns sitting in the event loop in the KeepAlive
     // state, waiting to receive a new Request to send on the socket.
-    idle: HashMap<Key, Vec<Idle<T>>>,
+    idle: HashMap<K, Vec<Idle<T>>>,
     // These are outstanding Checkouts that are waiting for a socket to be
     // able to send a Request one. This is used when "racing" for a new
     // connection.
@@ -69,7 +181,23 @@ struct Connections<T> {
     // this list is checked for any parked Checkouts, and tries to notify
     // them that the Conn could be used instead of waiting for a brand new
     // connection.
-    waiters: HashMap<Key, VecDeque<oneshot::Sender<T>>>,
+    //
+    // Along with the connection is sent when it was first created.
+    waiters: HashMap<K, VecDeque<oneshot::Sender<(T, Instant)>>>,
+    // How many HTTP/1 connections are checked out of the pool, and in use
+    // by a request.
+    busy: HashMap<K, usize>,
+    // Keys for which a connection is being made to replace one close to
+    // its maximum lifetime, so that only one is made at a time.
+    refreshing: HashSet<K>,
+    // Idle connections that have been removed for being closed or expired.
+    events: Events,
+    // Once closed, connections are not checked out or put back anymore.
+    closed: bool,
+    // Lets the user decide if an idle connection may be reused.
+    health_hook: Option<HealthHook>,
+    // Which end of an `idle` list is reused first.
+    reuse: ReuseStrategy,
     // A oneshot channel is used to allow the interval to be notified when
     // the Pool completely drops. That way, the interval can cancel immediately.
     #[cfg(feature = "runtime")]
@@ -77,27 +205,309 @@ struct Connections<T> {
     #[cfg(feature = "runtime")]
     exec: Exec,
     timeout: Option<Duration>,
+    max_lifetime: Option<Duration>,
+    // How long before its maximum lifetime a reused connection is replaced.
+    refresh: Option<Duration>,
+}
+
+pub(super) type HealthHook = Arc<Fn(&IdleConnection) -> bool + Send + Sync>;
+
+pub(super) type EventHook = Arc<Fn(&PoolEvent) + Send + Sync>;
+
+/// Something that happened in a `Client`'s pool.
+///
+/// This is given to the hook set with
+/// [`Builder::pool_event_hook`](::client::Builder::pool_event_hook).
+#[derive(Debug)]
+pub enum PoolEvent<'a> {
+    /// A request got a connection to send on.
+    Checkout {
+        /// The host of the connection, such as `http://hyper.rs`.
+        host: &'a str,
+        /// Where the connection came from.
+        outcome: CheckoutOutcome,
+        /// How long the request waited for the connection.
+        waited: Duration,
+    },
+    /// An idle connection was removed from the pool.
+    Evicted {
+        /// The host of the connection, such as `http://hyper.rs`.
+        host: &'a str,
+        /// Why the connection was removed.
+        reason: EvictReason,
+    },
+}
+
+/// Where the connection of a [`PoolEvent::Checkout`](PoolEvent::Checkout)
+/// came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckoutOutcome {
+    /// An idle connection was taken from the pool right away.
+    Reused,
+    /// A connection in use became idle while the request was waiting, and
+    /// won the race against connecting.
+    Released,
+    /// A new connection was made.
+    Connected,
+}
+
+/// Why an idle connection was removed from the pool, in a
+/// [`PoolEvent::Evicted`](PoolEvent::Evicted).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictReason {
+    /// The connection was closed, such as by the server.
+    Closed,
+    /// The connection was idle for longer than the idle timeout.
+    Expired,
+    /// The connection was open for longer than its maximum lifetime.
+    MaxLifetime,
+    /// The connection failed a health check, or was rejected by the
+    /// health check hook.
+    Unhealthy,
+    /// The connection was closed to make room for a connection to another
+    /// host, under the pool's limits.
+    Limit,
+}
+
+// Counts the idle connections removed from the pool, for `PoolStats`, and
+// keeps them for the event hook, which is told once the lock is released.
+struct Events {
+    evicted: usize,
+    hook: Option<EventHook>,
+    pending: Vec<(String, EvictReason)>,
+}
+
+impl Events {
+    fn evicted(&mut self, host: &str, reason: EvictReason) {
+        self.evicted += 1;
+        if self.hook.is_some() {
+            self.pending.push((host.to_owned(), reason));
+        }
+    }
+}
+
+// A lock of the connections of a pool, that tells the event hook about
+// connections evicted while it was held once it's released, so the hook
+// may use the pool itself.
+struct Locked<'a, T: 'a, K: 'a> {
+    guard: Option<MutexGuard<'a, Connections<T, K>>>,
+}
+
+impl<'a, T, K> Deref for Locked<'a, T, K> {
+    type Target = Connections<T, K>;
+
+    fn deref(&self) -> &Connections<T, K> {
+        self.guard.as_ref().expect("locked")
+    }
+}
+
+impl<'a, T, K> DerefMut for Locked<'a, T, K> {
+    fn deref_mut(&mut self) -> &mut Connections<T, K> {
+        self.guard.as_mut().expect("locked")
+    }
+}
+
+impl<'a, T, K> Drop for Locked<'a, T, K> {
+    fn drop(&mut self) {
+        let (hook, evicted) = match self.guard {
+            Some(ref mut inner) => {
+                if inner.events.pending.is_empty() {
+                    return;
+                }
+                let evicted = mem::replace(&mut inner.events.pending, Vec::new());
+                (inner.events.hook.clone(), evicted)
+            },
+            None => return,
+        };
+        drop(self.guard.take());
+        if let Some(hook) = hook {
+            for (host, reason) in evicted {
+                hook(&PoolEvent::Evicted {
+                    host: &host,
+                    reason,
+                });
+            }
+        }
+    }
+}
+
+/// An idle connection in a `Client`'s pool, about to be reused.
+///
+/// This is given to the hook set with
+/// [`Builder::pool_health_check_hook`](::client::Builder::pool_health_check_hook).
+#[derive(Debug)]
+pub struct IdleConnection<'a> {
+    created_at: Instant,
+    host: &'a str,
+    idle_at: Instant,
+    is_http2: bool,
+}
+
+impl<'a> IdleConnection<'a> {
+    /// The host this connection is to, such as `http://hyper.rs`.
+    pub fn host(&self) -> &str {
+        self.host
+    }
+
+    /// Whether this is an HTTP/2 connection.
+    pub fn is_http2(&self) -> bool {
+        self.is_http2
+    }
+
+    /// How long ago this connection was opened.
+    pub fn age(&self) -> Duration {
+        self.created_at.elapsed()
+    }
+
+    /// How long this connection has been idle in the pool.
+    pub fn idle_for(&self) -> Duration {
+        self.idle_at.elapsed()
+    }
+}
+
+/// How a `Client` gets a connection for a request, when there isn't an
+/// idle one in the pool.
+///
+/// An idle connection in the pool is always used if there is one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckoutStrategy {
+    /// Start connecting right away, while also waiting for a connection in
+    /// use to become idle. Whichever is ready first is used.
+    ///
+    /// This is the default.
+    Race,
+    /// Wait for a connection in use to become idle for this long, before
+    /// also starting to connect.
+    ///
+    /// If the connections in use close instead, connecting still waits for
+    /// the delay to pass.
+    ///
+    /// This requires the `runtime` feature, and acts like `Race` without it.
+    RaceAfter(Duration),
+    /// If any connections to the host are in use, only wait for one of them
+    /// to become idle. Connect only if there are none, or they all close.
+    PreferIdle,
+    /// Always connect, never waiting for a connection in use to become idle.
+    Connect,
+}
+
+impl Default for CheckoutStrategy {
+    fn default() -> CheckoutStrategy {
+        CheckoutStrategy::Race
+    }
+}
+
+/// Which idle connection a `Client` reuses, when the pool has several for
+/// a host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReuseStrategy {
+    /// Reuse the connection that became idle most recently.
+    ///
+    /// Busy periods are served by as few connections as possible, and the
+    /// rest are left to expire.
+    ///
+    /// This is the default.
+    Lifo,
+    /// Reuse the connection that has been idle the longest.
+    ///
+    /// Requests are spread over all the idle connections, which keeps each
+    /// of them from reaching a server's idle timeout.
+    Fifo,
+}
+
+impl Default for ReuseStrategy {
+    fn default() -> ReuseStrategy {
+        ReuseStrategy::Lifo
+    }
+}
+
+/// A snapshot of the state of a `Client`'s connection pool.
+#[derive(Clone, Debug, Default)]
+pub struct PoolStats {
+    idle: HashMap<String, usize>,
+    waiting: usize,
+    evicted: usize,
+}
+
+impl PoolStats {
+    /// The total number of idle connections in the pool.
+    pub fn idle(&self) -> usize {
+        self.idle.values().sum()
+    }
+
+    /// The number of idle connections for a host, such as `http://hyper.rs`.
+    pub fn idle_for(&self, host: &str) -> usize {
+        self.idle.get(host).cloned().unwrap_or(0)
+    }
+
+    /// The number of idle connections, keyed by host.
+    pub fn idle_per_host(&self) -> &HashMap<String, usize> {
+        &self.idle
+    }
+
+    /// The number of checkouts waiting for an idle connection.
+    pub fn waiting(&self) -> usize {
+        self.waiting
+    }
+
+    /// The number of idle connections that have been evicted from the pool,
+    /// because they were closed or had expired.
+    pub fn evicted(&self) -> usize {
+        self.evicted
+    }
 }
 
 // This is because `Weak::new()` *allocates* space for `T`, even if it
 // doesn't need it!
 struct WeakOpt<T>(Option<Weak<T>>);
 
-impl<T> Pool<T> {
-    pub fn new(enabled: bool, timeout: Option<Duration>, __exec: &Exec) -> Pool<T> {
+impl<T, K: Key> Pool<T, K> {
+    /// Create a new pool.
+    ///
+    /// Connections that have been idle for longer than `idle_timeout` are
+    /// not reused. If there is a timeout, and the `runtime` feature is
+    /// enabled, a task to drop expired connections is spawned on the
+    /// default executor, once the pool has an idle connection.
+    pub fn new(idle_timeout: Option<Duration>) -> Pool<T, K> {
+        Pool::with_config(true, idle_timeout, None, Limits::default(), &Exec::Default)
+    }
+
+    pub(super) fn with_config(enabled: bool, timeout: Option<Duration>, max_lifetime: Option<Duration>, limits: Limits, __exec: &Exec) -> Pool<T, K> {
         Pool {
             inner: Arc::new(PoolInner {
                 connections: Mutex::new(Connections {
                     connecting: HashSet::new(),
+                    http1_only: HashMap::new(),
+                    h2_negotiated: HashSet::new(),
                     idle: HashMap::new(),
                     #[cfg(feature = "runtime")]
                     idle_interval_ref: None,
                     waiters: HashMap::new(),
+                    busy: HashMap::new(),
+                    refreshing: HashSet::new(),
+                    events: Events {
+                        evicted: 0,
+                        hook: None,
+                        pending: Vec::new(),
+                    },
+                    closed: false,
+                    health_hook: None,
+                    reuse: ReuseStrategy::default(),
                     #[cfg(feature = "runtime")]
                     exec: __exec.clone(),
                     timeout,
+                    max_lifetime,
+                    refresh: None,
                 }),
                 enabled,
+                limits,
+                open: Arc::new(Mutex::new(Open {
+                    per_host: HashMap::new(),
+                    requests: HashMap::new(),
+                    total: 0,
+                    closed: false,
+                    waiting: Vec::new(),
+                })),
             }),
         }
     }
@@ -113,32 +523,211 @@ impl<T> Pool<T> {
             inner.idle_interval_ref = Some(tx);
         }
     }
+
+    pub(super) fn set_health_hook(&self, hook: Option<HealthHook>) {
+        self.inner.connections.lock().unwrap()
+            .health_hook = hook;
+    }
+
+    pub(super) fn set_event_hook(&self, hook: Option<EventHook>) {
+        self.inner.connections.lock().unwrap()
+            .events.hook = hook;
+    }
+
+    pub(super) fn set_reuse_strategy(&self, reuse: ReuseStrategy) {
+        self.inner.connections.lock().unwrap()
+            .reuse = reuse;
+    }
+
+    pub(super) fn set_refresh(&self, refresh: Option<Duration>) {
+        self.inner.connections.lock().unwrap()
+            .refresh = refresh;
+    }
+
+    /// Whether connections close to their maximum lifetime are replaced
+    /// ahead of time.
+    pub(super) fn refreshes(&self) -> bool {
+        let inner = self.inner.connections.lock().unwrap();
+        self.inner.enabled && inner.refresh.is_some() && inner.max_lifetime.is_some()
+    }
+
+    /// Remember that this host does not speak HTTP/2, so that new
+    /// connections to it should use HTTP/1, for `expiry` or for as long as
+    /// the pool lives.
+    pub(super) fn set_http1_only(&self, host: &Arc<String>, expiry: Option<Duration>) {
+        trace!("remembering {:?} as HTTP/1 only for {:?}", host, expiry);
+        let now = Instant::now();
+        let expires = expiry.map(|dur| now + dur);
+        let mut inner = self.inner.connections.lock().unwrap();
+        let hosts = &mut inner.http1_only;
+        if hosts.len() >= MAX_HOSTS && !hosts.contains_key(host) {
+            // Expired hosts are forgotten first. If there are still too
+            // many, any one host is, and it just tries HTTP/2 again.
+            hosts.retain(|_, expires| expires.map_or(true, |at| at > now));
+            if hosts.len() >= MAX_HOSTS {
+                let forget = hosts.keys().next().cloned();
+                if let Some(forget) = forget {
+                    trace!("forgetting {:?} as HTTP/1 only, too many hosts", forget);
+                    hosts.remove(&forget);
+                }
+            }
+        }
+        hosts.insert(host.clone(), expires);
+    }
+
+    /// Check if this host was recently found to not speak HTTP/2.
+    pub(super) fn is_http1_only(&self, host: &Arc<String>) -> bool {
+        let mut inner = self.inner.connections.lock().unwrap();
+        let expired = match inner.http1_only.get(host) {
+            Some(&Some(expires)) => Instant::now() >= expires,
+            Some(&None) => false,
+            None => return false,
+        };
+        if expired {
+            trace!("forgetting {:?} as HTTP/1 only", host);
+            inner.http1_only.remove(host);
+        }
+        !expired
+    }
+
+    /// Remember that this host negotiated HTTP/2, so that requests to it
+    /// should share an HTTP/2 connection.
+    pub(super) fn set_h2_negotiated(&self, host: &Arc<String>) {
+        trace!("remembering {:?} as negotiating HTTP/2", host);
+        let mut inner = self.inner.connections.lock().unwrap();
+        let hosts = &mut inner.h2_negotiated;
+        if hosts.len() >= MAX_HOSTS && !hosts.contains(host) {
+            // Any one host is forgotten, and its next connection negotiates
+            // again.
+            let forget = hosts.iter().next().cloned();
+            if let Some(forget) = forget {
+                trace!("forgetting {:?} as negotiating HTTP/2, too many hosts", forget);
+                hosts.remove(&forget);
+            }
+        }
+        hosts.insert(host.clone());
+    }
+
+    /// Check if a connection to this host previously negotiated HTTP/2.
+    pub(super) fn is_h2_negotiated(&self, host: &Arc<String>) -> bool {
+        self.inner.connections.lock().unwrap()
+            .h2_negotiated.contains(host)
+    }
+
+    /// Forget that this host negotiated HTTP/2, since a connection to it
+    /// didn't, or failed, so that requests to it use their own version
+    /// again.
+    pub(super) fn clear_h2_negotiated(&self, host: &Arc<String>) {
+        if self.inner.connections.lock().unwrap().h2_negotiated.remove(host) {
+            trace!("forgetting {:?} as negotiating HTTP/2", host);
+        }
+    }
+
+    /// Stop handing out connections, and drop the idle ones.
+    ///
+    /// Connections in use are dropped once they're done, instead of being
+    /// put back, and no new connections may be opened.
+    pub(super) fn close(&self) {
+        let (idle, waiters) = {
+            let mut inner = self.inner.connections.lock().unwrap();
+            inner.closed = true;
+            #[cfg(feature = "runtime")]
+            {
+                // Stops the idle interval, there is nothing left for it.
+                inner.idle_interval_ref = None;
+            }
+            let idle = ::std::mem::replace(&mut inner.idle, HashMap::new());
+            let waiters = ::std::mem::replace(&mut inner.waiters, HashMap::new());
+            (idle, waiters)
+        };
+        let waiting = {
+            let mut open = self.inner.open.lock().unwrap();
+            open.closed = true;
+            ::std::mem::replace(&mut open.waiting, Vec::new())
+        };
+        trace!("pool closed, dropping {} idle connections", idle.values().map(Vec::len).sum::<usize>());
+        // Dropped without holding the locks, since dropping the waiters
+        // cancels their `Checkout`s, and `Permit`s that are waiting have
+        // to see that the pool is closed.
+        drop(idle);
+        drop(waiters);
+        for task in waiting {
+            task.notify();
+        }
+    }
+
+    /// Returns a future that resolves once all connections that were opened
+    /// from this pool have closed.
+    pub(super) fn closed(&self) -> Closed {
+        Closed {
+            open: self.inner.open.clone(),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.inner.connections.lock().unwrap()
+            .closed
+    }
+
+    /// Get a snapshot of the connections in the pool.
+    pub fn stats(&self) -> PoolStats {
+        let inner = self.inner.connections.lock().unwrap();
+        let mut idle = HashMap::new();
+        for (key, list) in &inner.idle {
+            *idle.entry(key.host().to_string()).or_insert(0) += list.len();
+        }
+        let waiting = inner.waiters.values()
+            .flat_map(|waiters| waiters.iter())
+            .filter(|tx| !tx.is_canceled())
+            .count();
+        PoolStats {
+            idle,
+            waiting,
+            evicted: inner.events.evicted,
+        }
+    }
 }
 
-impl<T: Poolable> Pool<T> {
+impl<T: Poolable, K: Key> Pool<T, K> {
     /// Returns a `Checkout` which is a future that resolves if an idle
     /// connection becomes available.
-    pub fn checkout(&self, key: Key) -> Checkout<T> {
+    ///
+    /// If there isn't one, the `Checkout` waits for a connection in use to
+    /// become idle, so it's usually raced with making a new connection.
+    pub fn checkout(&self, key: K) -> Checkout<T, K> {
         Checkout {
+            checking: None,
             key,
             pool: self.clone(),
+            wait: true,
             waiter: None,
         }
     }
 
-    /// Ensure that there is only ever 1 connecting task for HTTP/2
-    /// connections. This does nothing for HTTP/1.
-    pub(super) fn connecting(&self, key: &Key) -> Option<Connecting<T>> {
-        if key.1 == Ver::Http2 && self.inner.enabled {
+    /// Whether any unshared connections for this key are in use, and so
+    /// could become idle once their request is done.
+    pub(super) fn is_busy(&self, key: &K) -> bool {
+        self.inner.connections.lock().unwrap()
+            .busy.contains_key(key)
+    }
+
+    /// Start connecting a new connection for this key.
+    ///
+    /// This ensures that there is only ever 1 connecting task for a shared
+    /// key, and returns `None` if another one is already connecting. It
+    /// never returns `None` for other keys.
+    pub fn connecting(&self, key: &K) -> Option<Connecting<T, K>> {
+        if key.is_shared() && self.inner.enabled {
             let mut inner = self.inner.connections.lock().unwrap();
             if inner.connecting.insert(key.clone()) {
                 let connecting = Connecting {
                     key: key.clone(),
                     pool: WeakOpt::downgrade(&self.inner),
+                    is_pooled: true,
                 };
                 Some(connecting)
             } else {
-                trace!("HTTP/2 connecting already in progress for {:?}", key.0);
+                trace!("shared connecting already in progress for {:?}", key);
                 None
             }
         } else {
@@ -147,23 +736,94 @@ impl<T: Poolable> Pool<T> {
                 // in HTTP/1's case, there is never a lock, so we don't
                 // need to do anything in Drop.
                 pool: WeakOpt::none(),
+                is_pooled: true,
             })
         }
     }
 
-    fn take(&self, key: &Key) -> Option<Pooled<T>> {
+    /// Start connecting a connection that will not be put into the pool,
+    /// nor take the shared connecting lock.
+    pub(super) fn connecting_unpooled(&self, key: &K) -> Connecting<T, K> {
+        Connecting {
+            key: key.clone(),
+            pool: WeakOpt::none(),
+            is_pooled: false,
+        }
+    }
+
+    /// Returns a future that resolves with a `Permit` once a new connection
+    /// to this host can be opened without going over the `Limits`.
+    pub(super) fn permit(&self, host: &Arc<String>) -> AcquirePermit<T, K> {
+        AcquirePermit {
+            host: host.clone(),
+            pool: self.clone(),
+        }
+    }
+
+    /// Returns a future that resolves with a `RequestPermit` once a request
+    /// to this host can be sent without going over the `Limits`.
+    pub(super) fn request_permit(&self, host: &Arc<String>) -> AcquireRequestPermit {
+        AcquireRequestPermit {
+            fail_fast: self.inner.limits.fail_fast,
+            host: host.clone(),
+            max: self.inner.limits.requests_per_host,
+            open: self.inner.open.clone(),
+        }
+    }
+
+    /// Drop the unshared idle connection that has been idle the longest,
+    /// from any other host, to make room for a connection to `host`.
+    ///
+    /// Shared connections are skipped, since the idle entry is likely not
+    /// the only reference keeping them open.
+    fn evict_idle_except(&self, host: &Arc<String>) {
+        let evicted = {
+            let mut inner = self.inner.lock().unwrap();
+            let oldest = inner.idle.iter()
+                .filter(|&(key, _)| !key.is_shared() && key.host() != host.as_str())
+                .filter_map(|(key, list)| list.first().map(|idle| (key, idle.idle_at)))
+                .min_by_key(|&(_, idle_at)| idle_at)
+                .map(|(key, _)| key.clone());
+            let key = match oldest {
+                Some(key) => key,
+                None => return,
+            };
+            trace!("evicting idle connection for {:?} to make room for {:?}", key, host);
+            inner.events.evicted(key.host(), EvictReason::Limit);
+            let (idle, empty) = {
+                let list = inner.idle.get_mut(&key).expect("oldest key is idle");
+                (list.remove(0), list.is_empty())
+            };
+            if empty {
+                inner.idle.remove(&key);
+            }
+            idle
+        };
+        // The connection task notices once the lock isn't held anymore,
+        // and releases its `Permit` when it closes.
+        drop(evicted);
+    }
+
+    fn take(&self, key: &K) -> Option<Pooled<T, K>> {
         let entry = {
-            let mut inner = self.inner.connections.lock().unwrap();
-            let expiration = Expiration::new(inner.timeout);
+            let mut guard = self.inner.lock().unwrap();
+            let inner = &mut *guard;
+            let expiration = Expiration::new(inner.timeout, inner.max_lifetime);
+            let events = &mut inner.events;
+            let health_hook = inner.health_hook.as_ref();
+            let reuse = inner.reuse;
             let maybe_entry = inner.idle.get_mut(key)
                 .and_then(|list| {
-                    trace!("take? {:?}: expiration = {:?}", key, expiration.0);
+                    trace!("take? {:?}: expiration = {:?}", key, expiration.idle);
                     // A block to end the mutable borrow on list,
                     // so the map below can check is_empty()
                     {
                         let popper = IdlePopper {
+                            events,
+                            health_hook,
                             key,
                             list,
+                            reuse,
                         };
                         popper.pop(&expiration)
                     }
@@ -183,20 +843,63 @@ impl<T: Poolable> Pool<T> {
             entry
         };
 
-        entry.map(|e| self.reuse(key, e.value))
+        entry.map(|e| self.reuse(key, e.value, e.created_at, CheckoutOutcome::Reused))
+    }
+
+    /// Start replacing a reused connection that is close to its maximum
+    /// lifetime, unless a replacement for its key is already being made.
+    ///
+    /// The replacement is being made until the `Refresh` is dropped. Shared
+    /// connections aren't replaced, since the pool only keeps one per key.
+    pub(super) fn refresh(&self, pooled: &Pooled<T, K>) -> Option<Refresh<T, K>> {
+        if !pooled.is_reused() || pooled.key.is_shared() || !self.inner.enabled {
+            return None;
+        }
+        let mut inner = self.inner.connections.lock().unwrap();
+        let near_end = match (inner.refresh, inner.max_lifetime) {
+            (Some(refresh), Some(lifetime)) => pooled.created_at.elapsed() + refresh >= lifetime,
+            _ => false,
+        };
+        if !near_end || !inner.refreshing.insert(pooled.key.clone()) {
+            return None;
+        }
+        trace!("refreshing connection near its max lifetime for {:?}", pooled.key);
+        Some(Refresh {
+            key: pooled.key.clone(),
+            pool: WeakOpt::downgrade(&self.inner),
+        })
+    }
+
+    /// Tell the event hook that a request got this connection, after
+    /// waiting `waited` for it.
+    pub(super) fn checked_out(&self, pooled: &Pooled<T, K>, waited: Duration) {
+        let hook = self.inner.connections.lock().unwrap()
+            .events.hook.clone();
+        if let Some(hook) = hook {
+            hook(&PoolEvent::Checkout {
+                host: pooled.key.host(),
+                outcome: pooled.outcome,
+                waited,
+            });
+        }
     }
 
-    pub(super) fn pooled(&self, mut connecting: Connecting<T>, value: T) -> Pooled<T> {
-        let (value, pool_ref)  = if self.inner.enabled {
+    /// Wrap a new connection, so that it's put into the pool once the
+    /// `Pooled` is dropped.
+    ///
+    /// A shared connection is put into the pool right away, and the
+    /// `Pooled` is a second reservation of it.
+    pub fn pooled(&self, mut connecting: Connecting<T, K>, value: T) -> Pooled<T, K> {
+        let created_at = Instant::now();
+        let (value, pool_ref)  = if self.inner.enabled && connecting.is_pooled {
             match value.reserve() {
                 Reservation::Shared(to_insert, to_return) => {
-                    debug_assert_eq!(
-                        connecting.key.1,
-                        Ver::Http2,
-                        "shared reservation without Http2"
+                    debug_assert!(
+                        connecting.key.is_shared(),
+                        "shared reservation without a shared key"
                     );
-                    let mut inner = self.inner.connections.lock().unwrap();
-                    inner.put(connecting.key.clone(), to_insert, &self.inner);
+                    let mut inner = self.inner.lock().unwrap();
+                    inner.put(connecting.key.clone(), to_insert, created_at, &self.inner);
                     // Do this here instead of Drop for Connecting because we
                     // already have a lock, no need to lock the mutex twice.
                     inner.connected(&connecting.key);
@@ -211,11 +914,14 @@ impl<T: Poolable> Pool<T> {
                     // Unique reservations must take a reference to the pool
                     // since they hope to reinsert once the reservation is
                     // completed
+                    self.inner.connections.lock().unwrap()
+                        .checked_out(&connecting.key);
                     (value, WeakOpt::downgrade(&self.inner))
                 },
             }
         } else {
-            // If pool is not enabled, skip all the things...
+            // If pool is not enabled, or this connection should not be
+            // pooled, skip all the things...
 
             // The Connecting should have had no pool ref
             debug_assert!(connecting.pool.upgrade().is_none());
@@ -223,14 +929,15 @@ impl<T: Poolable> Pool<T> {
             (value, WeakOpt::none())
         };
         Pooled {
+            created_at,
             key: connecting.key.clone(),
-            is_reused: false,
+            outcome: CheckoutOutcome::Connected,
             pool: pool_ref,
             value: Some(value)
         }
     }
 
-    fn reuse(&self, key: &Key, value: T) -> Pooled<T> {
+    fn reuse(&self, key: &K, value: T, created_at: Instant, outcome: CheckoutOutcome) -> Pooled<T, K> {
         debug!("reuse idle connection for {:?}", key);
         // TODO: unhack this
         // In Pool::pooled(), which is used for inserting brand new connections,
@@ -238,44 +945,65 @@ impl<T: Poolable> Pool<T> {
         // on if the Reservation can be shared or is unique. By the time
         // reuse() is called, the reservation has already been made, and
         // we just have the final value, without knowledge of if this is
-        // unique or shared. So, the hack is to just assume Ver::Http2 means
-        // shared... :(
-        let pool_ref = if key.1 == Ver::Http2 {
+        // unique or shared. So, the hack is to just trust the key to
+        // know... :(
+        let pool_ref = if key.is_shared() {
             WeakOpt::none()
         } else {
+            self.inner.connections.lock().unwrap()
+                .checked_out(key);
             WeakOpt::downgrade(&self.inner)
         };
 
         Pooled {
-            is_reused: true,
+            created_at,
             key: key.clone(),
+            outcome,
             pool: pool_ref,
             value: Some(value),
         }
     }
 
-    fn waiter(&mut self, key: Key, tx: oneshot::Sender<T>) {
+    fn waiter(&mut self, key: K, tx: oneshot::Sender<(T, Instant)>) {
         trace!("checkout waiting for idle connection: {:?}", key);
-        self.inner.connections.lock().unwrap()
-            .waiters.entry(key)
+        let mut inner = self.inner.connections.lock().unwrap();
+        if inner.closed {
+            // Dropping the sender cancels the `Checkout`.
+            return;
+        }
+        inner.waiters.entry(key)
             .or_insert(VecDeque::new())
             .push_back(tx);
     }
 }
 
 /// Pop off this list, looking for a usable connection that hasn't expired.
-struct IdlePopper<'a, T: 'a> {
-    key: &'a Key,
+struct IdlePopper<'a, T: 'a, K: 'a> {
+    events: &'a mut Events,
+    health_hook: Option<&'a HealthHook>,
+    key: &'a K,
     list: &'a mut Vec<Idle<T>>,
+    reuse: ReuseStrategy,
 }
 
-impl<'a, T: Poolable + 'a> IdlePopper<'a, T> {
-    fn pop(self, expiration: &Expiration) -> Option<Idle<T>> {
-        while let Some(entry) = self.list.pop() {
+impl<'a, T: Poolable + 'a, K: Key> IdlePopper<'a, T, K> {
+    // The `idle` list is always pushed to the end, and so is ordered from
+    // the longest idle to the most recently idle.
+    fn next(&mut self) -> Option<Idle<T>> {
+        match self.reuse {
+            ReuseStrategy::Lifo => self.list.pop(),
+            ReuseStrategy::Fifo if self.list.is_empty() => None,
+            ReuseStrategy::Fifo => Some(self.list.remove(0)),
+        }
+    }
+
+    fn pop(mut self, expiration: &Expiration) -> Option<Idle<T>> {
+        while let Some(entry) = self.next() {
             // If the connection has been closed, or is older than our idle
             // timeout, simply drop it and keep looking...
             if !entry.value.is_open() {
                 trace!("removing closed connection for {:?}", self.key);
+                self.events.evicted(self.key.host(), EvictReason::Closed);
                 continue;
             }
             // TODO: Actually, since the `idle` list is pushed to the end always,
@@ -284,14 +1012,34 @@ impl<'a, T: Poolable + 'a> IdlePopper<'a, T> {
             //
             // In that case, we could just break out of the loop and drop the
             // whole list...
-            if expiration.expires(entry.idle_at) {
+            if expiration.expires(entry.idle_at, entry.value.idle_timeout()) {
                 trace!("removing expired connection for {:?}", self.key);
+                self.events.evicted(self.key.host(), EvictReason::Expired);
+                continue;
+            }
+            if expiration.outlived(entry.created_at) {
+                trace!("removing connection past its max lifetime for {:?}", self.key);
+                self.events.evicted(self.key.host(), EvictReason::MaxLifetime);
                 continue;
             }
+            if let Some(hook) = self.health_hook {
+                let idle = IdleConnection {
+                    created_at: entry.created_at,
+                    host: self.key.host(),
+                    idle_at: entry.idle_at,
+                    is_http2: self.key.is_shared(),
+                };
+                if !hook(&idle) {
+                    trace!("removing connection rejected by health check for {:?}", self.key);
+                    self.events.evicted(self.key.host(), EvictReason::Unhealthy);
+                    continue;
+                }
+            }
 
             let value = match entry.value.reserve() {
                 Reservation::Shared(to_reinsert, to_checkout) => {
                     self.list.push(Idle {
+                        created_at: entry.created_at,
                         idle_at: Instant::now(),
                         value: to_reinsert,
                     });
@@ -303,6 +1051,7 @@ impl<'a, T: Poolable + 'a> IdlePopper<'a, T> {
             };
 
             return Some(Idle {
+                created_at: entry.created_at,
                 idle_at: entry.idle_at,
                 value,
             });
@@ -312,10 +1061,19 @@ impl<'a, T: Poolable + 'a> IdlePopper<'a, T> {
     }
 }
 
-impl<T: Poolable> Connections<T> {
-    fn put(&mut self, key: Key, value: T, __pool_ref: &Arc<PoolInner<T>>) {
-        if key.1 == Ver::Http2 && self.idle.contains_key(&key) {
-            trace!("put; existing idle HTTP/2 connection for {:?}", key);
+impl<T: Poolable, K: Key> Connections<T, K> {
+    fn put(&mut self, key: K, value: T, created_at: Instant, __pool_ref: &Arc<PoolInner<T, K>>) {
+        if self.closed {
+            trace!("put; pool closed, dropping connection for {:?}", key);
+            return;
+        }
+        if Expiration::new(None, self.max_lifetime).outlived(created_at) {
+            trace!("put; connection past its max lifetime for {:?}", key);
+            self.events.evicted(key.host(), EvictReason::MaxLifetime);
+            return;
+        }
+        if key.is_shared() && self.idle.contains_key(&key) {
+            trace!("put; existing idle shared connection for {:?}", key);
             return;
         }
         trace!("put; add idle connection for {:?}", key);
@@ -332,7 +1090,7 @@ impl<T: Poolable> Connections<T> {
                         },
                         Reservation::Unique(uniq) => uniq,
                     };
-                    match tx.send(reserved) {
+                    match tx.send((reserved, created_at)) {
                         Ok(()) => {
                             if value.is_none() {
                                 break;
@@ -340,7 +1098,7 @@ impl<T: Poolable> Connections<T> {
                                 continue;
                             }
                         },
-                        Err(e) => {
+                        Err((e, _)) => {
                             value = Some(e);
                         }
                     }
@@ -361,6 +1119,7 @@ impl<T: Poolable> Connections<T> {
                      .or_insert(Vec::new())
                      .push(Idle {
                          value: value,
+                         created_at,
                          idle_at: Instant::now(),
                      });
 
@@ -373,9 +1132,33 @@ impl<T: Poolable> Connections<T> {
         }
     }
 
+    fn checked_out(&mut self, key: &K) {
+        *self.busy.entry(key.clone()).or_insert(0) += 1;
+    }
+
+    /// A checked out connection is done being used, either back in the
+    /// pool, or closed.
+    fn checked_in(&mut self, key: &K) {
+        let is_idle = match self.busy.get_mut(key) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            },
+            None => false,
+        };
+        if is_idle {
+            self.busy.remove(key);
+            // Waiters that are left won't be given a connection that is
+            // currently in use anymore, so let them know to stop waiting.
+            if self.waiters.remove(key).is_some() {
+                trace!("no more busy connections for {:?}, canceling waiters", key);
+            }
+        }
+    }
+
     /// A `Connecting` task is complete. Not necessarily successfully,
     /// but the lock is going away, so clean up.
-    fn connected(&mut self, key: &Key) {
+    fn connected(&mut self, key: &K) {
         let existed = self.connecting.remove(key);
         debug_assert!(
             existed,
@@ -388,7 +1171,7 @@ impl<T: Poolable> Connections<T> {
     }
 
     #[cfg(feature = "runtime")]
-    fn spawn_idle_interval(&mut self, pool_ref: &Arc<PoolInner<T>>) {
+    fn spawn_idle_interval(&mut self, pool_ref: &Arc<PoolInner<T, K>>) {
         let (dur, rx) = {
             debug_assert!(pool_ref.enabled);
 
@@ -396,7 +1179,13 @@ impl<T: Poolable> Connections<T> {
                 return;
             }
 
-            if let Some(dur) = self.timeout {
+            // Check often enough for both idle timeouts and lifetimes.
+            let dur = match (self.timeout, self.max_lifetime) {
+                (Some(timeout), Some(lifetime)) => Some(::std::cmp::min(timeout, lifetime)),
+                (timeout, lifetime) => timeout.or(lifetime),
+            };
+
+            if let Some(dur) = dur {
                 let (tx, rx) = oneshot::channel();
                 self.idle_interval_ref = Some(tx);
                 (dur, rx)
@@ -416,12 +1205,12 @@ impl<T: Poolable> Connections<T> {
     }
 }
 
-impl<T> Connections<T> {
+impl<T, K: Key> Connections<T, K> {
     /// Any `FutureResponse`s that were created will have made a `Checkout`,
     /// and possibly inserted into the pool that it is waiting for an idle
     /// connection. If a user ever dropped that future, we need to clean out
     /// those parked senders.
-    fn clean_waiters(&mut self, key: &Key) {
+    fn clean_waiters(&mut self, key: &K) {
         let mut remove_waiters = false;
         if let Some(waiters) = self.waiters.get_mut(key) {
             waiters.retain(|tx| {
@@ -436,22 +1225,27 @@ impl<T> Connections<T> {
 }
 
 #[cfg(feature = "runtime")]
-impl<T: Poolable> Connections<T> {
+impl<T: Poolable, K: Key> Connections<T, K> {
     /// This should *only* be called by the IdleInterval.
     fn clear_expired(&mut self) {
-        let dur = self.timeout.expect("interval assumes timeout");
-
-        let now = Instant::now();
-        //self.last_idle_check_at = now;
+        let expiration = Expiration::new(self.timeout, self.max_lifetime);
 
+        let events = &mut self.events;
         self.idle.retain(|key, values| {
             values.retain(|entry| {
                 if !entry.value.is_open() {
                     trace!("idle interval evicting closed for {:?}", key);
+                    events.evicted(key.host(), EvictReason::Closed);
                     return false;
                 }
-                if now - entry.idle_at > dur {
+                if expiration.expires(entry.idle_at, entry.value.idle_timeout()) {
                     trace!("idle interval evicting expired for {:?}", key);
+                    events.evicted(key.host(), EvictReason::Expired);
+                    return false;
+                }
+                if expiration.outlived(entry.created_at) {
+                    trace!("idle interval evicting past max lifetime for {:?}", key);
+                    events.evicted(key.host(), EvictReason::MaxLifetime);
                     return false;
                 }
 
@@ -465,8 +1259,15 @@ impl<T: Poolable> Connections<T> {
     }
 }
 
-impl<T> Clone for Pool<T> {
-    fn clone(&self) -> Pool<T> {
+impl<T, K: Key> fmt::Debug for Pool<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pool")
+            .finish()
+    }
+}
+
+impl<T, K: Key> Clone for Pool<T, K> {
+    fn clone(&self) -> Pool<T, K> {
         Pool {
             inner: self.inner.clone(),
         }
@@ -475,18 +1276,22 @@ impl<T> Clone for Pool<T> {
 
 /// A wrapped poolable value that tries to reinsert to the Pool on Drop.
 // Note: The bounds `T: Poolable` is needed for the Drop impl.
-pub(super) struct Pooled<T: Poolable> {
+pub struct Pooled<T: Poolable, K: Key> {
     value: Option<T>,
-    is_reused: bool,
-    key: Key,
-    pool: WeakOpt<PoolInner<T>>,
+    created_at: Instant,
+    key: K,
+    outcome: CheckoutOutcome,
+    pool: WeakOpt<PoolInner<T, K>>,
 }
 
-impl<T: Poolable> Pooled<T> {
+impl<T: Poolable, K: Key> Pooled<T, K> {
+    /// Whether this connection was checked out of the pool, instead of
+    /// being new.
     pub fn is_reused(&self) -> bool {
-        self.is_reused
+        self.outcome != CheckoutOutcome::Connected
     }
 
+    /// Whether this connection will return to the pool when dropped.
     pub fn is_pool_enabled(&self) -> bool {
         self.pool.0.is_some()
     }
@@ -500,46 +1305,45 @@ impl<T: Poolable> Pooled<T> {
     }
 }
 
-impl<T: Poolable> Deref for Pooled<T> {
+impl<T: Poolable, K: Key> Deref for Pooled<T, K> {
     type Target = T;
     fn deref(&self) -> &T {
         self.as_ref()
     }
 }
 
-impl<T: Poolable> DerefMut for Pooled<T> {
+impl<T: Poolable, K: Key> DerefMut for Pooled<T, K> {
     fn deref_mut(&mut self) -> &mut T {
         self.as_mut()
     }
 }
 
-impl<T: Poolable> Drop for Pooled<T> {
+impl<T: Poolable, K: Key> Drop for Pooled<T, K> {
     fn drop(&mut self) {
         if let Some(value) = self.value.take() {
-            if !value.is_open() {
-                // If we *already* know the connection is done here,
-                // it shouldn't be re-inserted back into the pool.
-                return;
-            }
-
             if let Some(pool) = self.pool.upgrade() {
                 // Pooled should not have had a real reference if pool is
                 // not enabled!
                 debug_assert!(pool.enabled);
 
-                if let Ok(mut inner) = pool.connections.lock() {
-                    inner.put(self.key.clone(), value, &pool);
+                if let Some(mut inner) = pool.lock() {
+                    // If we *already* know the connection is done here,
+                    // it shouldn't be re-inserted back into the pool.
+                    if value.is_open() {
+                        inner.put(self.key.clone(), value, self.created_at, &pool);
+                    }
+                    inner.checked_in(&self.key);
                 }
-            } else if self.key.1 == Ver::Http1 {
+            } else if !self.key.is_shared() && value.is_open() {
                 trace!("pool dropped, dropping pooled ({:?})", self.key);
             }
-            // Ver::Http2 is already in the Pool (or dead), so we wouldn't
-            // have an actual reference to the Pool.
+            // A shared connection is already in the Pool (or dead), so we
+            // wouldn't have an actual reference to the Pool.
         }
     }
 }
 
-impl<T: Poolable> fmt::Debug for Pooled<T> {
+impl<T: Poolable, K: Key> fmt::Debug for Pooled<T, K> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Pooled")
             .field("key", &self.key)
@@ -548,24 +1352,40 @@ impl<T: Poolable> fmt::Debug for Pooled<T> {
 }
 
 struct Idle<T> {
+    created_at: Instant,
     idle_at: Instant,
     value: T,
 }
 
-pub(super) struct Checkout<T> {
-    key: Key,
-    pool: Pool<T>,
-    waiter: Option<oneshot::Receiver<T>>,
+/// A future that resolves with an idle connection from the pool.
+///
+/// See [`Pool::checkout`](Pool::checkout).
+#[must_use = "futures do nothing unless polled"]
+pub struct Checkout<T: Poolable, K: Key> {
+    // An idle connection that is being checked before it's used.
+    checking: Option<Pooled<T, K>>,
+    key: K,
+    pool: Pool<T, K>,
+    // Whether to wait for a busy connection to become idle.
+    wait: bool,
+    waiter: Option<oneshot::Receiver<(T, Instant)>>,
 }
 
-impl<T: Poolable> Checkout<T> {
-    fn poll_waiter(&mut self) -> Poll<Option<Pooled<T>>, ::Error> {
+impl<T: Poolable, K: Key> Checkout<T, K> {
+    /// Only check out a connection that is idle now, never waiting for one
+    /// in use to become idle.
+    pub(super) fn without_waiting(mut self) -> Self {
+        self.wait = false;
+        self
+    }
+
+    fn poll_waiter(&mut self) -> Poll<Option<Pooled<T, K>>, ::Error> {
         static CANCELED: &str = "pool checkout failed";
         if let Some(mut rx) = self.waiter.take() {
             match rx.poll() {
-                Ok(Async::Ready(value)) => {
+                Ok(Async::Ready((value, created_at))) => {
                     if value.is_open() {
-                        Ok(Async::Ready(Some(self.pool.reuse(&self.key, value))))
+                        Ok(Async::Ready(Some(self.pool.reuse(&self.key, value, created_at, CheckoutOutcome::Released))))
                     } else {
                         Err(::Error::new_canceled(Some(CANCELED)))
                     }
@@ -591,8 +1411,8 @@ impl<T: Poolable> Checkout<T> {
     }
 }
 
-impl<T: Poolable> Future for Checkout<T> {
-    type Item = Pooled<T>;
+impl<T: Poolable, K: Key> Future for Checkout<T, K> {
+    type Item = Pooled<T, K>;
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
@@ -600,18 +1420,52 @@ impl<T: Poolable> Future for Checkout<T> {
             return Ok(Async::Ready(pooled));
         }
 
-        let entry = self.pool.take(&self.key);
+        loop {
+            let mut pooled = match self.checking.take() {
+                Some(pooled) => pooled,
+                None => match self.pool.take(&self.key) {
+                    Some(pooled) => pooled,
+                    None => break,
+                },
+            };
+
+            match pooled.as_mut().poll_healthy() {
+                Async::Ready(true) => return Ok(Async::Ready(pooled)),
+                Async::Ready(false) => {
+                    debug!("idle connection failed health check for {:?}", self.key);
+                    // Don't let it back into the pool.
+                    pooled.pool = WeakOpt::none();
+                    if let Some(mut inner) = self.pool.inner.lock() {
+                        inner.events.evicted(self.key.host(), EvictReason::Unhealthy);
+                        inner.checked_in(&self.key);
+                    }
+                },
+                Async::NotReady => {
+                    self.checking = Some(pooled);
+                    return Ok(Async::NotReady);
+                },
+            }
+        }
 
-        if let Some(pooled) = entry {
-            Ok(Async::Ready(pooled))
-        } else {
+        if self.wait {
+            if self.pool.is_closed() {
+                return Err(::Error::new_pool_closed());
+            }
             self.add_waiter();
-            Ok(Async::NotReady)
         }
+        Ok(Async::NotReady)
+    }
+}
+
+impl<T: Poolable, K: Key> fmt::Debug for Checkout<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Checkout")
+            .field("key", &self.key)
+            .finish()
     }
 }
 
-impl<T> Drop for Checkout<T> {
+impl<T: Poolable, K: Key> Drop for Checkout<T, K> {
     fn drop(&mut self) {
         if self.waiter.take().is_some() {
             if let Ok(mut inner) = self.pool.inner.connections.lock() {
@@ -621,20 +1475,30 @@ impl<T> Drop for Checkout<T> {
     }
 }
 
-pub(super) struct Connecting<T: Poolable> {
-    key: Key,
-    pool: WeakOpt<PoolInner<T>>,
+/// A connection being made for a key, until it's given to
+/// [`Pool::pooled`](Pool::pooled).
+pub struct Connecting<T: Poolable, K: Key> {
+    key: K,
+    pool: WeakOpt<PoolInner<T, K>>,
+    is_pooled: bool,
 }
 
-impl<T: Poolable> Drop for Connecting<T> {
+impl<T: Poolable, K: Key> fmt::Debug for Connecting<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connecting")
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+impl<T: Poolable, K: Key> Drop for Connecting<T, K> {
     fn drop(&mut self) {
         if let Some(pool) = self.pool.upgrade() {
             // No need to panic on drop, that could abort!
             if let Ok(mut inner) = pool.connections.lock() {
-                debug_assert_eq!(
-                    self.key.1,
-                    Ver::Http2,
-                    "Connecting constructed without Http2"
+                debug_assert!(
+                    self.key.is_shared(),
+                    "Connecting constructed without a shared key"
                 );
                 inner.connected(&self.key);
             }
@@ -642,25 +1506,259 @@ impl<T: Poolable> Drop for Connecting<T> {
     }
 }
 
-struct Expiration(Option<Duration>);
+/// A connection being made to replace one close to its maximum lifetime,
+/// from [`Pool::refresh`](Pool::refresh).
+pub(super) struct Refresh<T, K: Key> {
+    key: K,
+    pool: WeakOpt<PoolInner<T, K>>,
+}
+
+impl<T, K: Key> Drop for Refresh<T, K> {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.upgrade() {
+            if let Ok(mut inner) = pool.connections.lock() {
+                inner.refreshing.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// A future that resolves once a connection can be opened.
+pub(super) struct AcquirePermit<T, K> {
+    host: Arc<String>,
+    pool: Pool<T, K>,
+}
+
+impl<T: Poolable, K: Key> Future for AcquirePermit<T, K> {
+    type Item = Permit;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let limits = self.pool.inner.limits;
+        let only_total_reached = {
+            let mut open = self.pool.inner.open.lock().unwrap();
+            if open.closed {
+                debug!("pool closed, not connecting to {:?}", self.host);
+                return Err(::Error::new_pool_closed());
+            }
+            let host_reached = limits.per_host.map_or(false, |max| {
+                open.per_host.get(&self.host).cloned().unwrap_or(0) >= max
+            });
+            let total_reached = limits.total.map_or(false, |max| open.total >= max);
+
+            if !host_reached && !total_reached {
+                *open.per_host.entry(self.host.clone()).or_insert(0) += 1;
+                open.total += 1;
+                return Ok(Async::Ready(Permit {
+                    _counted: Arc::new(PermitInner {
+                        host: self.host.clone(),
+                        open: self.pool.inner.open.clone(),
+                    }),
+                }));
+            }
+
+            if limits.fail_fast {
+                debug!("connection limit reached for {:?}", self.host);
+                return Err(::Error::new_pool_full());
+            }
+
+            trace!("connection limit reached for {:?}, waiting", self.host);
+            if !open.waiting.iter().any(|task| task.will_notify_current()) {
+                open.waiting.push(task::current());
+            }
+            !host_reached
+        };
+
+        if only_total_reached {
+            self.pool.evict_idle_except(&self.host);
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+/// Counts as an open connection until dropped.
+///
+/// Clones share the same count, so that a connection that is being
+/// replaced, like when falling back from HTTP/2, can hand it over.
+#[derive(Clone)]
+pub(super) struct Permit {
+    // Only held on to, so that dropping the last clone releases it.
+    _counted: Arc<PermitInner>,
+}
+
+impl fmt::Debug for Permit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Permit")
+            .field("host", &self._counted.host)
+            .finish()
+    }
+}
+
+struct PermitInner {
+    host: Arc<String>,
+    open: Arc<Mutex<Open>>,
+}
+
+impl Drop for PermitInner {
+    fn drop(&mut self) {
+        // No need to panic on drop, that could abort!
+        if let Ok(mut open) = self.open.lock() {
+            let remove = match open.per_host.get_mut(&self.host) {
+                Some(count) => {
+                    *count -= 1;
+                    *count == 0
+                },
+                None => false,
+            };
+            if remove {
+                open.per_host.remove(&self.host);
+            }
+            open.total -= 1;
+            for task in open.waiting.drain(..) {
+                task.notify();
+            }
+        }
+    }
+}
+
+/// A future that resolves once a request can be sent.
+pub(super) struct AcquireRequestPermit {
+    fail_fast: bool,
+    host: Arc<String>,
+    max: Option<usize>,
+    open: Arc<Mutex<Open>>,
+}
+
+impl Future for AcquireRequestPermit {
+    type Item = RequestPermit;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let max = match self.max {
+            Some(max) => max,
+            None => return Ok(Async::Ready(RequestPermit(None))),
+        };
+        let mut open = self.open.lock().unwrap();
+        let in_flight = open.requests.get(&self.host).cloned().unwrap_or(0);
+        if in_flight < max {
+            *open.requests.entry(self.host.clone()).or_insert(0) += 1;
+            return Ok(Async::Ready(RequestPermit(Some(RequestPermitInner {
+                host: self.host.clone(),
+                open: self.open.clone(),
+            }))));
+        }
+
+        if self.fail_fast {
+            debug!("request limit reached for {:?}", self.host);
+            return Err(::Error::new_pool_full());
+        }
+
+        trace!("request limit reached for {:?}, waiting", self.host);
+        if !open.waiting.iter().any(|task| task.will_notify_current()) {
+            open.waiting.push(task::current());
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+/// Counts as a request in flight until dropped.
+pub(super) struct RequestPermit(Option<RequestPermitInner>);
+
+impl fmt::Debug for RequestPermit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RequestPermit")
+            .field("host", &self.0.as_ref().map(|inner| &inner.host))
+            .finish()
+    }
+}
+
+struct RequestPermitInner {
+    host: Arc<String>,
+    open: Arc<Mutex<Open>>,
+}
+
+impl Drop for RequestPermitInner {
+    fn drop(&mut self) {
+        // No need to panic on drop, that could abort!
+        if let Ok(mut open) = self.open.lock() {
+            let remove = match open.requests.get_mut(&self.host) {
+                Some(count) => {
+                    *count -= 1;
+                    *count == 0
+                },
+                None => false,
+            };
+            if remove {
+                open.requests.remove(&self.host);
+            }
+            for task in open.waiting.drain(..) {
+                task.notify();
+            }
+        }
+    }
+}
+
+/// A future that resolves once every `Permit` of the pool is released.
+pub(super) struct Closed {
+    open: Arc<Mutex<Open>>,
+}
+
+impl Future for Closed {
+    type Item = ();
+    type Error = ::common::Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut open = self.open.lock().unwrap();
+        if open.total == 0 {
+            return Ok(Async::Ready(()));
+        }
+        trace!("waiting for {} connections to close", open.total);
+        if !open.waiting.iter().any(|task| task.will_notify_current()) {
+            open.waiting.push(task::current());
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+struct Expiration {
+    idle: Option<Duration>,
+    lifetime: Option<Duration>,
+}
 
 impl Expiration {
-    fn new(dur: Option<Duration>) -> Expiration {
-        Expiration(dur)
+    fn new(idle: Option<Duration>, lifetime: Option<Duration>) -> Expiration {
+        Expiration {
+            idle,
+            lifetime,
+        }
     }
 
-    fn expires(&self, instant: Instant) -> bool {
-        match self.0 {
+    /// Whether a connection idle since `instant` has been idle too long,
+    /// given the connection's own idle timeout, if any.
+    fn expires(&self, instant: Instant, own: Option<Duration>) -> bool {
+        let timeout = match (self.idle, own) {
+            (Some(idle), Some(own)) => Some(cmp::min(idle, own)),
+            (idle, own) => idle.or(own),
+        };
+        match timeout {
             Some(timeout) => instant.elapsed() > timeout,
             None => false,
         }
     }
+
+    /// Whether a connection created at `instant` should be retired.
+    fn outlived(&self, instant: Instant) -> bool {
+        match self.lifetime {
+            Some(lifetime) => instant.elapsed() > lifetime,
+            None => false,
+        }
+    }
 }
 
 #[cfg(feature = "runtime")]
-struct IdleInterval<T> {
+struct IdleInterval<T, K> {
     interval: Interval,
-    pool: WeakOpt<PoolInner<T>>,
+    pool: WeakOpt<PoolInner<T, K>>,
     // This allows the IdleInterval to be notified as soon as the entire
     // Pool is fully dropped, and shutdown. This channel is never sent on,
     // but Err(Canceled) will be received when the Pool is dropped.
@@ -668,7 +1766,7 @@ struct IdleInterval<T> {
 }
 
 #[cfg(feature = "runtime")]
-impl<T: Poolable + 'static> Future for IdleInterval<T> {
+impl<T: Poolable + 'static, K: Key> Future for IdleInterval<T, K> {
     type Item = ();
     type Error = ();
 
@@ -691,7 +1789,7 @@ impl<T: Poolable + 'static> Future for IdleInterval<T> {
             }));
 
             if let Some(inner) = self.pool.upgrade() {
-                if let Ok(mut inner) = inner.connections.lock() {
+                if let Some(mut inner) = inner.lock() {
                     trace!("idle interval checking for expired");
                     inner.clear_expired();
                     continue;
@@ -725,7 +1823,9 @@ mod tests {
     use futures::{Async, Future};
     use futures::future;
     use common::Exec;
-    use super::{Connecting, Key, Poolable, Pool, Reservation, Ver, WeakOpt};
+    use super::{Connecting, Limits, MAX_HOSTS, Poolable, Pool, Reservation, ReuseStrategy, Ver, WeakOpt};
+
+    type Key = (Arc<String>, Ver);
 
     /// Test unique reservations.
     #[derive(Debug, PartialEq, Eq)]
@@ -741,15 +1841,16 @@ mod tests {
         }
     }
 
-    fn c<T: Poolable>(key: Key) -> Connecting<T> {
+    fn c<T: Poolable>(key: Key) -> Connecting<T, Key> {
         Connecting {
             key,
             pool: WeakOpt::none(),
+            is_pooled: true,
         }
     }
 
-    fn pool_no_timer<T>() -> Pool<T> {
-        let pool = Pool::new(true, Some(Duration::from_millis(100)), &Exec::Default);
+    fn pool_no_timer<T>() -> Pool<T, Key> {
+        let pool = Pool::with_config(true, Some(Duration::from_millis(100)), None, Limits::default(), &Exec::Default);
         pool.no_timer();
         pool
     }
@@ -768,6 +1869,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_pool_custom_key() {
+        #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+        struct Tunnel(String);
+
+        impl super::Key for Tunnel {
+            fn host(&self) -> &str {
+                &self.0
+            }
+        }
+
+        let pool = Pool::new(None);
+        let key = Tunnel("proxy:3128".to_string());
+        let connecting = pool.connecting(&key).expect("unshared key");
+        let pooled = pool.pooled(connecting, Uniq(41));
+        assert!(!pooled.is_reused());
+        drop(pooled);
+        assert_eq!(pool.stats().idle_for("proxy:3128"), 1);
+
+        match pool.checkout(key).poll().unwrap() {
+            Async::Ready(pooled) => {
+                assert_eq!(*pooled, Uniq(41));
+                assert!(pooled.is_reused());
+            },
+            _ => panic!("not ready"),
+        }
+    }
+
+    #[test]
+    fn test_pool_checkout_reuse_strategy() {
+        let pool = pool_no_timer();
+        let key = (Arc::new("foo".to_string()), Ver::Http1);
+        let first = pool.pooled(c(key.clone()), Uniq(41));
+        let second = pool.pooled(c(key.clone()), Uniq(42));
+        drop(first);
+        drop(second);
+
+        match pool.checkout(key.clone()).poll().unwrap() {
+            Async::Ready(pooled) => assert_eq!(*pooled, Uniq(42), "lifo"),
+            _ => panic!("not ready"),
+        }
+
+        pool.set_reuse_strategy(ReuseStrategy::Fifo);
+        let third = pool.pooled(c(key.clone()), Uniq(43));
+        drop(third);
+
+        match pool.checkout(key).poll().unwrap() {
+            Async::Ready(pooled) => assert_eq!(*pooled, Uniq(41), "fifo"),
+            _ => panic!("not ready"),
+        }
+    }
+
     #[test]
     fn test_pool_checkout_returns_none_if_expired() {
         future::lazy(|| {
@@ -808,7 +1961,7 @@ mod tests {
         use std::sync::Arc;
         let runtime = ::tokio::runtime::Runtime::new().unwrap();
         let executor = runtime.executor();
-        let pool = Pool::new(true, Some(Duration::from_millis(100)), &Exec::Executor(Arc::new(executor)));
+        let pool = Pool::with_config(true, Some(Duration::from_millis(100)), None, Limits::default(), &Exec::Executor(Arc::new(executor)));
 
         let key = (Arc::new("foo".to_string()), Ver::Http1);
 
@@ -894,4 +2047,336 @@ mod tests {
 
         assert!(!pool.inner.connections.lock().unwrap().idle.contains_key(&key));
     }
+
+    #[test]
+    fn checkout_canceled_when_busy_connection_closes() {
+        let pool = pool_no_timer();
+        let key = (Arc::new("localhost:12345".to_string()), Ver::Http1);
+        let pooled = pool.pooled(c(key.clone()), CanClose {
+            val: 57,
+            closed: true,
+        });
+        assert!(pool.is_busy(&key));
+
+        let mut checkout = pool.checkout(key.clone());
+        future::lazy(|| {
+            assert!(checkout.poll().unwrap().is_not_ready());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+
+        drop(pooled);
+        assert!(!pool.is_busy(&key));
+        let err = checkout.poll().unwrap_err();
+        assert!(err.is_canceled());
+    }
+
+    #[test]
+    fn checkout_without_waiting() {
+        let pool = pool_no_timer::<Uniq<i32>>();
+        let key = (Arc::new("foo".to_string()), Ver::Http1);
+
+        let mut checkout = pool.checkout(key).without_waiting();
+        future::lazy(|| {
+            assert!(checkout.poll().unwrap().is_not_ready());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+        assert_eq!(pool.stats().waiting(), 0);
+    }
+
+    #[test]
+    fn test_pool_max_lifetime() {
+        future::lazy(|| {
+            let pool = Pool::with_config(true, None, Some(Duration::from_millis(100)), Limits::default(), &Exec::Default);
+            pool.no_timer();
+            let key = (Arc::new("foo".to_string()), Ver::Http1);
+
+            let pooled = pool.pooled(c(key.clone()), Uniq(41));
+            drop(pooled);
+
+            // still young, so it can be checked out and reused...
+            let pooled = match pool.checkout(key.clone()).poll().unwrap() {
+                Async::Ready(pooled) => pooled,
+                _ => panic!("not ready"),
+            };
+            assert_eq!(*pooled, Uniq(41));
+
+            // but once its lifetime has passed, it isn't reinserted.
+            ::std::thread::sleep(Duration::from_millis(100));
+            drop(pooled);
+            assert!(pool.inner.connections.lock().unwrap().idle.get(&key).is_none());
+            assert_eq!(pool.stats().evicted(), 1);
+
+            // idle connections past their lifetime aren't checked out
+            pool.pooled(c(key.clone()), Uniq(5));
+            ::std::thread::sleep(Duration::from_millis(100));
+            assert!(pool.checkout(key).poll().unwrap().is_not_ready());
+            assert_eq!(pool.stats().evicted(), 2);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn test_pool_refresh_near_max_lifetime() {
+        future::lazy(|| {
+            let pool = Pool::with_config(true, None, Some(Duration::from_secs(10)), Limits::default(), &Exec::Default);
+            pool.no_timer();
+            pool.set_refresh(Some(Duration::from_secs(0)));
+            assert!(pool.refreshes());
+            let key = (Arc::new("foo".to_string()), Ver::Http1);
+
+            // a new connection isn't replaced...
+            let pooled = pool.pooled(c(key.clone()), Uniq(41));
+            assert!(pool.refresh(&pooled).is_none());
+            drop(pooled);
+
+            // nor a reused one that is still young...
+            let pooled = match pool.checkout(key.clone()).poll().unwrap() {
+                Async::Ready(pooled) => pooled,
+                _ => panic!("not ready"),
+            };
+            assert!(pool.refresh(&pooled).is_none());
+
+            // but once it's close to its lifetime, only one replacement
+            // is made at a time.
+            pool.set_refresh(Some(Duration::from_secs(10)));
+            let refresh = pool.refresh(&pooled).expect("refresh");
+            assert!(pool.refresh(&pooled).is_none());
+            drop(refresh);
+            assert!(pool.refresh(&pooled).is_some());
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn test_pool_event_hook_evictions() {
+        use std::sync::Mutex;
+        use super::{EvictReason, PoolEvent};
+
+        future::lazy(|| {
+            let pool = pool_no_timer();
+            let reasons = Arc::new(Mutex::new(Vec::new()));
+            let seen = reasons.clone();
+            let weak = Arc::downgrade(&pool.inner);
+            pool.set_event_hook(Some(Arc::new(move |event: &PoolEvent| {
+                if let PoolEvent::Evicted { host, reason } = *event {
+                    assert_eq!(host, "http://foo");
+                    // The pool isn't locked while the hook is called.
+                    let pool = Pool { inner: weak.upgrade().expect("pool") };
+                    seen.lock().unwrap().push((reason, pool.stats().evicted()));
+                }
+            })));
+            let key = (Arc::new("http://foo".to_string()), Ver::Http1);
+
+            pool.pooled(c(key.clone()), CanClose {
+                val: 41,
+                closed: false,
+            });
+            pool.inner.connections.lock().unwrap()
+                .idle.get_mut(&key).unwrap()[0].value.closed = true;
+            assert!(pool.checkout(key).poll().unwrap().is_not_ready());
+
+            assert_eq!(*reasons.lock().unwrap(), vec![(EvictReason::Closed, 1)]);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn test_pool_stats() {
+        future::lazy(|| {
+            let pool = pool_no_timer();
+            let foo = (Arc::new("http://foo".to_string()), Ver::Http1);
+            let bar = (Arc::new("http://bar".to_string()), Ver::Http1);
+
+            pool.pooled(c(foo.clone()), Uniq(41));
+            pool.pooled(c(foo.clone()), Uniq(5));
+            pool.pooled(c(bar.clone()), Uniq(99));
+
+            let mut checkout = pool.checkout((Arc::new("http://baz".to_string()), Ver::Http1));
+            checkout.poll().unwrap();
+
+            let stats = pool.stats();
+            assert_eq!(stats.idle(), 3);
+            assert_eq!(stats.idle_for("http://foo"), 2);
+            assert_eq!(stats.idle_for("http://bar"), 1);
+            assert_eq!(stats.idle_for("http://baz"), 0);
+            assert_eq!(stats.waiting(), 1);
+            assert_eq!(stats.evicted(), 0);
+
+            ::std::thread::sleep(pool.inner.connections.lock().unwrap().timeout.unwrap());
+            pool.checkout(foo).poll().unwrap();
+
+            let stats = pool.stats();
+            assert_eq!(stats.idle(), 1);
+            assert_eq!(stats.evicted(), 2);
+
+            drop(checkout);
+            assert_eq!(pool.stats().waiting(), 0);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn test_pool_permit_limits() {
+        let limits = Limits {
+            per_host: Some(1),
+            total: Some(2),
+            fail_fast: false,
+            ..Limits::default()
+        };
+        let pool = Pool::<Uniq<i32>, Key>::with_config(true, None, None, limits, &Exec::Default);
+        let foo = Arc::new("http://foo".to_string());
+        let bar = Arc::new("http://bar".to_string());
+        let baz = Arc::new("http://baz".to_string());
+
+        future::lazy(|| {
+            let foo1 = match pool.permit(&foo).poll().unwrap() {
+                Async::Ready(permit) => permit,
+                _ => panic!("foo should get a permit"),
+            };
+            assert!(pool.permit(&foo).poll().unwrap().is_not_ready(), "per host limit");
+            let _bar1 = pool.permit(&bar).poll().unwrap();
+
+            let mut baz1 = pool.permit(&baz);
+            assert!(baz1.poll().unwrap().is_not_ready(), "total limit");
+            drop(foo1);
+            assert!(baz1.poll().unwrap().is_ready());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn test_pool_permit_fail_fast() {
+        let limits = Limits {
+            per_host: None,
+            total: Some(1),
+            fail_fast: true,
+            ..Limits::default()
+        };
+        let pool = Pool::<Uniq<i32>, Key>::with_config(true, None, None, limits, &Exec::Default);
+        let foo = Arc::new("http://foo".to_string());
+
+        let _foo1 = pool.permit(&foo).wait().unwrap();
+        let err = pool.permit(&foo).wait().unwrap_err();
+        assert!(err.is_pool_full(), "{:?}", err);
+    }
+
+    #[test]
+    fn test_pool_request_permit_limits() {
+        let limits = Limits {
+            requests_per_host: Some(2),
+            ..Limits::default()
+        };
+        let pool = Pool::<Uniq<i32>, Key>::with_config(true, None, None, limits, &Exec::Default);
+        let foo = Arc::new("http://foo".to_string());
+        let bar = Arc::new("http://bar".to_string());
+
+        future::lazy(|| {
+            let foo1 = pool.request_permit(&foo).wait().unwrap();
+            let _foo2 = pool.request_permit(&foo).wait().unwrap();
+            let mut foo3 = pool.request_permit(&foo);
+            assert!(foo3.poll().unwrap().is_not_ready(), "per host limit");
+            assert!(pool.request_permit(&bar).poll().unwrap().is_ready());
+
+            drop(foo1);
+            assert!(foo3.poll().unwrap().is_ready());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn test_pool_permit_evicts_idle_for_other_host() {
+        let limits = Limits {
+            per_host: None,
+            total: Some(1),
+            fail_fast: false,
+            ..Limits::default()
+        };
+        let pool = Pool::with_config(true, None, None, limits, &Exec::Default);
+        pool.no_timer();
+        let foo = (Arc::new("http://foo".to_string()), Ver::Http1);
+        let bar = Arc::new("http://bar".to_string());
+
+        future::lazy(|| {
+            let _foo1 = pool.permit(&foo.0).poll().unwrap();
+            drop(pool.pooled(c(foo.clone()), Uniq(41)));
+            assert_eq!(pool.stats().idle_for("http://foo"), 1);
+
+            assert!(pool.permit(&bar).poll().unwrap().is_not_ready());
+            let stats = pool.stats();
+            assert_eq!(stats.idle(), 0);
+            assert_eq!(stats.evicted(), 1);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn pool_remembers_http1_only_hosts() {
+        let pool = pool_no_timer::<Uniq<i32>>();
+        let foo = Arc::new("http://foo".to_string());
+        let bar = Arc::new("http://bar".to_string());
+
+        assert!(!pool.is_http1_only(&foo));
+        pool.set_http1_only(&foo, None);
+        assert!(pool.is_http1_only(&foo));
+        assert!(!pool.is_http1_only(&bar));
+
+        // clones share the same memory of hosts
+        assert!(pool.clone().is_http1_only(&foo));
+    }
+
+    #[test]
+    fn pool_forgets_expired_http1_only_hosts() {
+        let pool = pool_no_timer::<Uniq<i32>>();
+        let foo = Arc::new("http://foo".to_string());
+        let bar = Arc::new("http://bar".to_string());
+
+        pool.set_http1_only(&foo, Some(Duration::from_secs(0)));
+        pool.set_http1_only(&bar, Some(Duration::from_secs(60)));
+        assert!(!pool.is_http1_only(&foo));
+        assert!(pool.is_http1_only(&bar));
+    }
+
+    #[test]
+    fn pool_forgets_h2_negotiated_hosts() {
+        let pool = pool_no_timer::<Uniq<i32>>();
+        let foo = Arc::new("http://foo".to_string());
+
+        pool.set_h2_negotiated(&foo);
+        assert!(pool.is_h2_negotiated(&foo));
+        pool.clear_h2_negotiated(&foo);
+        assert!(!pool.is_h2_negotiated(&foo));
+    }
+
+    #[test]
+    fn pool_remembers_at_most_max_hosts() {
+        let pool = pool_no_timer::<Uniq<i32>>();
+        let expired = Arc::new("http://expired".to_string());
+        pool.set_http1_only(&expired, Some(Duration::from_secs(0)));
+        for i in 1..MAX_HOSTS {
+            let host = Arc::new(format!("http://{}", i));
+            pool.set_http1_only(&host, None);
+            pool.set_h2_negotiated(&host);
+        }
+        let foo = Arc::new("http://foo".to_string());
+        pool.set_h2_negotiated(&foo);
+
+        // An expired host makes room for a new one.
+        let bar = Arc::new("http://bar".to_string());
+        pool.set_http1_only(&bar, None);
+        assert!(pool.is_http1_only(&bar));
+        assert!(pool.is_http1_only(&Arc::new("http://1".to_string())));
+
+        // Otherwise another host is forgotten.
+        pool.set_http1_only(&foo, None);
+        pool.set_h2_negotiated(&bar);
+        assert!(pool.is_http1_only(&foo));
+        assert!(pool.is_h2_negotiated(&bar));
+
+        let inner = pool.inner.connections.lock().unwrap();
+        assert_eq!(inner.http1_only.len(), MAX_HOSTS);
+        assert_eq!(inner.h2_negotiated.len(), MAX_HOSTS);
+    }
 }
@@ -0,0 +1,172 @@
+//! Rendering the bytes a request would be written as, without a connection,
+//! see `Client::debug_render`.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::{Async, Future, Poll};
+use http::{HeaderMap, Request};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use body::Payload;
+use body::internal::{FullDataArg, FullDataRet};
+use super::conn;
+
+/// A `Future` resolving to the bytes a `Client` would write for a request.
+///
+/// See [`Client::debug_render`](::Client::debug_render).
+#[must_use = "futures do nothing unless polled"]
+pub struct DebugRender {
+    inner: Box<Future<Item=Vec<u8>, Error=::Error> + Send>,
+}
+
+impl DebugRender {
+    pub(super) fn new(fut: Box<Future<Item=Vec<u8>, Error=::Error> + Send>) -> Self {
+        Self {
+            inner: fut,
+        }
+    }
+}
+
+impl fmt::Debug for DebugRender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Future<DebugRender>")
+    }
+}
+
+impl Future for DebugRender {
+    type Item = Vec<u8>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+/// Write `req` into an HTTP/1 connection made by `builder` over an IO that
+/// only keeps what is written to it.
+pub(super) fn render<B>(builder: &conn::Builder, req: Request<B>) -> Box<Future<Item=Vec<u8>, Error=::Error> + Send>
+where
+    B: Payload + Send + 'static,
+    B::Data: Send,
+{
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let streaming = Arc::new(AtomicBool::new(false));
+    let io = Capture {
+        written: written.clone(),
+    };
+    let req = req.map(|body| Watched {
+        body,
+        streaming: streaming.clone(),
+    });
+    Box::new(builder.handshake_no_upgrades(io)
+        .and_then(move |(mut tx, conn)| {
+            let res = tx.send_request(req);
+            Rendering {
+                conn,
+                res,
+                streaming,
+                written,
+            }
+        }))
+}
+
+// The connection is polled until it's waiting for the response, with the
+// whole request written.
+struct Rendering<C> {
+    conn: C,
+    res: conn::ResponseFuture,
+    streaming: Arc<AtomicBool>,
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl<C> Future for Rendering<C>
+where
+    C: Future<Item=(), Error=::Error>,
+{
+    type Item = Vec<u8>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let closed = self.conn.poll()?.is_ready();
+        // No response ever comes, but a request that couldn't be written
+        // fails with the reason.
+        self.res.poll()?;
+        if !closed && self.streaming.load(Ordering::SeqCst) {
+            return Ok(Async::NotReady);
+        }
+        Ok(Async::Ready(mem::replace(&mut *self.written.lock().unwrap(), Vec::new())))
+    }
+}
+
+// An IO that keeps everything written to it, and never has anything to read.
+struct Capture {
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Read for Capture {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for Capture {}
+
+impl AsyncWrite for Capture {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+// A body that tells whether it was started, but not finished, being
+// written.
+struct Watched<B> {
+    body: B,
+    streaming: Arc<AtomicBool>,
+}
+
+impl<B: Payload> Payload for Watched<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        let res = self.body.poll_data();
+        let streaming = match res {
+            Ok(Async::Ready(Some(_))) => !self.body.is_end_stream(),
+            Ok(Async::Ready(None)) | Err(_) => false,
+            Ok(Async::NotReady) => true,
+        };
+        self.streaming.store(streaming, Ordering::SeqCst);
+        res
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        self.body.poll_trailers()
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.body.content_length()
+    }
+
+    fn __hyper_full_data(&mut self, arg: FullDataArg) -> FullDataRet<Self::Data> {
+        self.body.__hyper_full_data(arg)
+    }
+}
@@ -0,0 +1,98 @@
+//! The settings an HTTP/2 server advertised, see `SendRequest::peer_settings`.
+
+use std::sync::{Arc, Mutex};
+
+/// The settings the server of an HTTP/2 connection advertised.
+///
+/// Settings the server didn't send have their default values from
+/// [RFC 7540](https://tools.ietf.org/html/rfc7540#section-6.5.2).
+///
+/// See [`SendRequest::peer_settings`](::client::conn::SendRequest::peer_settings).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerSettings {
+    header_table_size: u32,
+    max_concurrent_streams: Option<u32>,
+    initial_window_size: u32,
+    max_frame_size: u32,
+    max_header_list_size: Option<u32>,
+}
+
+impl PeerSettings {
+    /// The size of the header compression table the server uses to decode
+    /// requests.
+    pub fn header_table_size(&self) -> u32 {
+        self.header_table_size
+    }
+
+    /// How many streams the server allows open at once, or `None` if it
+    /// didn't limit them.
+    pub fn max_concurrent_streams(&self) -> Option<u32> {
+        self.max_concurrent_streams
+    }
+
+    /// The initial flow control window of the streams the client opens.
+    pub fn initial_window_size(&self) -> u32 {
+        self.initial_window_size
+    }
+
+    /// The largest frame payload the server accepts.
+    pub fn max_frame_size(&self) -> u32 {
+        self.max_frame_size
+    }
+
+    /// The largest header list the server accepts, or `None` if it didn't
+    /// limit it.
+    pub fn max_header_list_size(&self) -> Option<u32> {
+        self.max_header_list_size
+    }
+
+    /// Apply a setting from a `SETTINGS` frame.
+    #[cfg(feature = "http2")]
+    pub(crate) fn apply(&mut self, id: u16, value: u32) {
+        match id {
+            0x1 => self.header_table_size = value,
+            0x3 => self.max_concurrent_streams = Some(value),
+            0x4 => self.initial_window_size = value,
+            0x5 => self.max_frame_size = value,
+            0x6 => self.max_header_list_size = Some(value),
+            // ENABLE_PUSH means nothing from a server, and unknown settings
+            // are ignored.
+            _ => (),
+        }
+    }
+}
+
+impl Default for PeerSettings {
+    fn default() -> PeerSettings {
+        PeerSettings {
+            header_table_size: 4_096,
+            max_concurrent_streams: None,
+            initial_window_size: 65_535,
+            max_frame_size: 16_384,
+            max_header_list_size: None,
+        }
+    }
+}
+
+/// Where the settings of a connection are kept, once the server sent them.
+#[derive(Clone)]
+pub(crate) struct SettingsSlot {
+    inner: Arc<Mutex<Option<PeerSettings>>>,
+}
+
+impl SettingsSlot {
+    pub(crate) fn new() -> SettingsSlot {
+        SettingsSlot {
+            inner: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<PeerSettings> {
+        *self.inner.lock().unwrap()
+    }
+
+    #[cfg(feature = "http2")]
+    pub(crate) fn set(&self, settings: PeerSettings) {
+        *self.inner.lock().unwrap() = Some(settings);
+    }
+}
@@ -0,0 +1,184 @@
+#[cfg(feature = "http1")] use bytes::Bytes;
+#[cfg(feature = "http1")] use http::{HeaderMap, Method};
+#[cfg(feature = "http1")] use http::header::HOST;
+#[cfg(feature = "http1")] use http::uri::{Authority, Parts, PathAndQuery, Scheme, Uri};
+
+/// The form of the request-target written in an HTTP/1 request line.
+///
+/// By default, the `Client` sends requests in origin-form, or absolute-form
+/// through an HTTP proxy, and `conn::SendRequest` writes the `Uri` of a
+/// request as it is. A `RequestTarget` set with
+/// [`Builder::http1_request_target`](::client::Builder::http1_request_target),
+/// [`conn::Builder::http1_request_target`](::client::conn::Builder::http1_request_target),
+/// or [`RequestConfig::request_target`](::client::RequestConfig::request_target)
+/// rewrites the `Uri` into one form, no matter how it was constructed.
+///
+/// HTTP/2 requests always carry the scheme, authority and path separately,
+/// so they ignore it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestTarget {
+    /// The path and query, such as `GET /where?q=now HTTP/1.1`.
+    ///
+    /// This is the form for requests to an origin server.
+    Origin,
+    /// The whole `Uri`, such as `GET http://hyper.rs/where?q=now HTTP/1.1`.
+    ///
+    /// This is the form for requests to a forward proxy. If the `Uri` has
+    /// no authority, it's taken from the `Host` header, with the `http`
+    /// scheme.
+    Absolute,
+    /// Just the host and port, such as `CONNECT hyper.rs:443 HTTP/1.1`.
+    ///
+    /// This is the form for `CONNECT` requests. If the authority of the
+    /// `Uri`, or of the `Host` header if it has none, has no port, the
+    /// default port of the scheme is added.
+    Authority,
+    /// Just an asterisk, such as `OPTIONS * HTTP/1.1`.
+    ///
+    /// This is the form for `OPTIONS` requests about the server as a whole,
+    /// instead of one of its resources. Requests with other methods are sent
+    /// as they are.
+    ///
+    /// A server receives it as a `Uri` of just the path `*`.
+    Asterisk,
+}
+
+#[cfg(feature = "http1")]
+impl RequestTarget {
+    // Rewrite `uri` into this form. It's left alone if this form needs an
+    // authority that neither it nor the headers have.
+    pub(crate) fn apply(self, method: &Method, uri: &mut Uri, headers: &HeaderMap) {
+        let rewritten = match self {
+            RequestTarget::Origin => origin_form(uri),
+            RequestTarget::Absolute => absolute_form(uri, headers),
+            RequestTarget::Authority => authority_form(uri, headers),
+            RequestTarget::Asterisk => {
+                if *method != Method::OPTIONS {
+                    debug!("request-target {:?} is only for OPTIONS, sending {} {} as is", self, method, uri);
+                    return;
+                }
+                Some("*".parse().expect("* is valid uri"))
+            },
+        };
+        match rewritten {
+            Some(rewritten) => *uri = rewritten,
+            None => debug!("request-target {:?} needs an authority, sending {} as is", self, uri),
+        }
+    }
+}
+
+#[cfg(feature = "http1")]
+fn origin_form(uri: &Uri) -> Option<Uri> {
+    // A `Uri` of just the path `/` is only made right by parsing it.
+    path_and_query(uri).as_str().parse().ok()
+}
+
+#[cfg(feature = "http1")]
+fn absolute_form(uri: &Uri, headers: &HeaderMap) -> Option<Uri> {
+    let authority = match authority(uri, headers) {
+        Some(authority) => authority,
+        None => return None,
+    };
+    let mut parts = Parts::default();
+    parts.scheme = Some(uri.scheme_part().cloned().unwrap_or(Scheme::HTTP));
+    parts.authority = Some(authority);
+    parts.path_and_query = Some(path_and_query(uri));
+    Uri::from_parts(parts).ok()
+}
+
+#[cfg(feature = "http1")]
+fn authority_form(uri: &Uri, headers: &HeaderMap) -> Option<Uri> {
+    let authority = match authority(uri, headers) {
+        Some(authority) => authority,
+        None => return None,
+    };
+    if has_port(authority.as_str()) {
+        return authority.as_str().parse().ok();
+    }
+    let port = if uri.scheme_part() == Some(&Scheme::HTTPS) {
+        443
+    } else {
+        80
+    };
+    format!("{}:{}", authority, port).parse().ok()
+}
+
+#[cfg(feature = "http1")]
+fn authority(uri: &Uri, headers: &HeaderMap) -> Option<Authority> {
+    if let Some(authority) = uri.authority_part() {
+        return Some(authority.clone());
+    }
+    headers
+        .get(HOST)
+        .and_then(|host| Authority::from_shared(Bytes::from(host.as_bytes())).ok())
+}
+
+#[cfg(feature = "http1")]
+fn path_and_query(uri: &Uri) -> PathAndQuery {
+    match uri.path_and_query() {
+        Some(path) if !path.as_str().is_empty() => path.clone(),
+        _ => "/".parse().expect("/ is valid path"),
+    }
+}
+
+// The port of an IPv6 host comes after its closing bracket.
+#[cfg(feature = "http1")]
+fn has_port(authority: &str) -> bool {
+    match authority.rfind(':') {
+        Some(colon) => !authority[colon..].contains(']'),
+        None => false,
+    }
+}
+
+#[cfg(all(test, feature = "http1"))]
+mod tests {
+    use http::{HeaderMap, Method};
+    use http::header::HOST;
+
+    use super::RequestTarget;
+
+    fn apply(target: RequestTarget, uri: &str, host: Option<&'static str>) -> String {
+        apply_method(target, Method::GET, uri, host)
+    }
+
+    fn apply_method(target: RequestTarget, method: Method, uri: &str, host: Option<&'static str>) -> String {
+        let mut uri = uri.parse().unwrap();
+        let mut headers = HeaderMap::new();
+        if let Some(host) = host {
+            headers.insert(HOST, host.parse().unwrap());
+        }
+        target.apply(&method, &mut uri, &headers);
+        uri.to_string()
+    }
+
+    #[test]
+    fn origin_form() {
+        assert_eq!(apply(RequestTarget::Origin, "http://hyper.rs/a?b", None), "/a?b");
+        assert_eq!(apply(RequestTarget::Origin, "http://hyper.rs", None), "/");
+        assert_eq!(apply(RequestTarget::Origin, "/a", None), "/a");
+    }
+
+    #[test]
+    fn absolute_form() {
+        assert_eq!(apply(RequestTarget::Absolute, "https://hyper.rs/a?b", None), "https://hyper.rs/a?b");
+        assert_eq!(apply(RequestTarget::Absolute, "/a", Some("hyper.rs:8080")), "http://hyper.rs:8080/a");
+        assert_eq!(apply(RequestTarget::Absolute, "/a", None), "/a");
+    }
+
+    #[test]
+    fn authority_form() {
+        assert_eq!(apply(RequestTarget::Authority, "https://hyper.rs/a", None), "hyper.rs:443");
+        assert_eq!(apply(RequestTarget::Authority, "http://hyper.rs", None), "hyper.rs:80");
+        assert_eq!(apply(RequestTarget::Authority, "http://[::1]:3000/", None), "[::1]:3000");
+        assert_eq!(apply(RequestTarget::Authority, "/", Some("[::1]")), "[::1]:80");
+        assert_eq!(apply(RequestTarget::Authority, "hyper.rs:443", None), "hyper.rs:443");
+    }
+
+    #[test]
+    fn asterisk_form() {
+        assert_eq!(apply_method(RequestTarget::Asterisk, Method::OPTIONS, "http://hyper.rs/", None), "*");
+        assert_eq!(apply_method(RequestTarget::Asterisk, Method::OPTIONS, "*", None), "*");
+        assert_eq!(apply(RequestTarget::Asterisk, "http://hyper.rs/a", None), "http://hyper.rs/a");
+        assert_eq!(apply(RequestTarget::Origin, "*", None), "*");
+    }
+}
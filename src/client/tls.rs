@@ -0,0 +1,317 @@
+//! An HTTPS connector, using native-tls.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use bytes::Buf;
+use futures::{Async, Future, Poll};
+use native_tls::{self, HandshakeError, MidHandshakeTlsStream, TlsConnector, TlsStream};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::connect::{Connect, ConnectError, ConnectPhase, Connected, Destination, HttpConnector};
+
+/// A connector for the `https` and `http` schemes, using the TLS library of
+/// the platform.
+///
+/// Connects with the wrapped connector, which is an `HttpConnector` by
+/// default, and then performs a TLS handshake for `https` destinations,
+/// trusting the certificates of the operating system's trust store.
+///
+/// The handshake offers `h2` and `http/1.1` with ALPN. If the server picks
+/// `h2`, the `Connected` reports it, and the `Client` uses HTTP/2 on the
+/// connection.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// # fn main() {
+/// use hyper::Client;
+/// use hyper::client::NativeTlsConnector;
+///
+/// let connector = NativeTlsConnector::new(4).expect("TLS initialization");
+/// let client = Client::builder()
+///     .build::<_, hyper::Body>(connector);
+/// # drop(client);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct NativeTlsConnector<T> {
+    enforce_https: bool,
+    http: T,
+    tls: TlsConnector,
+}
+
+impl NativeTlsConnector<HttpConnector> {
+    /// Construct a new NativeTlsConnector, with the default TLS settings of
+    /// the platform.
+    ///
+    /// Takes number of DNS worker threads.
+    pub fn new(threads: usize) -> Result<NativeTlsConnector<HttpConnector>, native_tls::Error> {
+        let mut http = HttpConnector::new(threads);
+        http.enforce_http(false);
+        let tls = TlsConnector::builder()
+            .request_alpns(&["h2", "http/1.1"])
+            .build()?;
+        Ok(NativeTlsConnector::with_connector(http, tls))
+    }
+}
+
+impl<T> NativeTlsConnector<T> {
+    /// Construct a new NativeTlsConnector, from a connector for the
+    /// underlying transport, and a `native_tls::TlsConnector`.
+    ///
+    /// HTTP/2 is only used if the `TlsConnector` was built to offer `h2`,
+    /// with `request_alpns`. An `HttpConnector` needs `enforce_http`
+    /// disabled, to connect to `https` destinations.
+    pub fn with_connector(http: T, tls: TlsConnector) -> NativeTlsConnector<T> {
+        NativeTlsConnector {
+            enforce_https: false,
+            http,
+            tls,
+        }
+    }
+
+    /// Option to enforce all `Uri`s have the `https` scheme.
+    ///
+    /// Default is `false`, `http` destinations are connected to without TLS.
+    #[inline]
+    pub fn enforce_https(&mut self, is_enforced: bool) {
+        self.enforce_https = is_enforced;
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for NativeTlsConnector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NativeTlsConnector")
+            .field("enforce_https", &self.enforce_https)
+            .field("http", &self.http)
+            .finish()
+    }
+}
+
+impl<T> Connect for NativeTlsConnector<T>
+where
+    T: Connect,
+{
+    type Transport = MaybeNativeTlsStream<T::Transport>;
+    type Error = io::Error;
+    type Future = NativeTlsConnecting<T>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let is_https = dst.scheme() == "https";
+        if !is_https && self.enforce_https {
+            return NativeTlsConnecting::error("invalid URL, scheme must be https");
+        }
+
+        let tls = if is_https {
+            Some((self.tls.clone(), dst.host().to_owned()))
+        } else {
+            None
+        };
+
+        NativeTlsConnecting {
+            state: State::Connecting(self.http.connect(dst), tls),
+        }
+    }
+}
+
+/// A Future representing work to connect to a URL, and perform a TLS
+/// handshake.
+#[must_use = "futures do nothing unless polled"]
+pub struct NativeTlsConnecting<T: Connect> {
+    state: State<T>,
+}
+
+enum State<T: Connect> {
+    Connecting(T::Future, Option<(TlsConnector, String)>),
+    Handshaking(Option<(MidHandshakeTlsStream<T::Transport>, Connected)>),
+    Error(Option<io::Error>),
+}
+
+impl<T: Connect> NativeTlsConnecting<T> {
+    fn error(msg: &'static str) -> NativeTlsConnecting<T> {
+        NativeTlsConnecting {
+            state: State::Error(Some(io::Error::new(io::ErrorKind::InvalidInput, msg))),
+        }
+    }
+}
+
+impl<T: Connect> Future for NativeTlsConnecting<T> {
+    type Item = (MaybeNativeTlsStream<T::Transport>, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (handshake, connected) = match self.state {
+            State::Connecting(ref mut fut, ref mut tls) => {
+                let (io, connected) = try_ready!(fut.poll().map_err(into_io_error));
+                match tls.take() {
+                    Some((tls, host)) => (tls.connect(&host, io), connected),
+                    None => {
+                        let io = MaybeNativeTlsStream {
+                            inner: Inner::Http(io),
+                        };
+                        return Ok(Async::Ready((io, connected)));
+                    }
+                }
+            },
+            State::Handshaking(ref mut handshaking) => {
+                let (mid, connected) = handshaking.take().expect("polled after complete");
+                (mid.handshake(), connected)
+            },
+            State::Error(ref mut e) => return Err(e.take().expect("polled more than once")),
+        };
+
+        match handshake {
+            Ok(tls) => {
+                let connected = match tls.negotiated_alpn() {
+                    Ok(Some(ref proto)) if proto == b"h2" => connected.h2(),
+                    _ => connected,
+                };
+                let io = MaybeNativeTlsStream {
+                    inner: Inner::Https(tls),
+                };
+                Ok(Async::Ready((io, connected)))
+            },
+            Err(HandshakeError::WouldBlock(mid)) => {
+                self.state = State::Handshaking(Some((mid, connected)));
+                Ok(Async::NotReady)
+            },
+            Err(HandshakeError::Failure(err)) => {
+                Err(ConnectError::new(ConnectPhase::Tls, err).with_addr(connected.addr).into())
+            },
+        }
+    }
+}
+
+impl<T: Connect> fmt::Debug for NativeTlsConnecting<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("NativeTlsConnecting")
+    }
+}
+
+fn into_io_error<E: Into<Box<StdError + Send + Sync>>>(err: E) -> io::Error {
+    match err.into().downcast::<io::Error>() {
+        Ok(err) => *err,
+        Err(err) => io::Error::new(io::ErrorKind::Other, err),
+    }
+}
+
+/// A transport connected by a `NativeTlsConnector`, with TLS for `https`
+/// destinations.
+pub struct MaybeNativeTlsStream<T> {
+    inner: Inner<T>,
+}
+
+enum Inner<T> {
+    Http(T),
+    Https(TlsStream<T>),
+}
+
+impl<T> MaybeNativeTlsStream<T> {
+    /// Check if this transport uses TLS.
+    pub fn is_https(&self) -> bool {
+        match self.inner {
+            Inner::Http(_) => false,
+            Inner::Https(_) => true,
+        }
+    }
+}
+
+impl<T> fmt::Debug for MaybeNativeTlsStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.inner {
+            Inner::Http(_) => f.pad("Http(..)"),
+            Inner::Https(_) => f.pad("Https(..)"),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Read for MaybeNativeTlsStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner {
+            Inner::Http(ref mut io) => io.read(buf),
+            Inner::Https(ref mut tls) => tls.read(buf),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Write for MaybeNativeTlsStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner {
+            Inner::Http(ref mut io) => io.write(buf),
+            Inner::Https(ref mut tls) => tls.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner {
+            Inner::Http(ref mut io) => io.flush(),
+            Inner::Https(ref mut tls) => tls.flush(),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncRead for MaybeNativeTlsStream<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        match self.inner {
+            Inner::Http(ref io) => io.prepare_uninitialized_buffer(buf),
+            // Not every platform's TLS library promises not to read from
+            // the buffer, so it's zeroed.
+            Inner::Https(_) => {
+                for b in buf.iter_mut() {
+                    *b = 0;
+                }
+                true
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncWrite for MaybeNativeTlsStream<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self.inner {
+            Inner::Http(ref mut io) => io.shutdown(),
+            Inner::Https(ref mut tls) => {
+                try_nb!(tls.shutdown());
+                tls.get_mut().shutdown()
+            }
+        }
+    }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        match self.inner {
+            Inner::Http(ref mut io) => io.write_buf(buf),
+            Inner::Https(ref mut tls) => {
+                let n = try_nb!(tls.write(buf.bytes()));
+                buf.advance(n);
+                Ok(Async::Ready(n))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use futures::Future;
+
+    use client::connect::{Connect, Destination};
+    use super::NativeTlsConnector;
+
+    #[test]
+    fn errors_enforce_https() {
+        let uri = "http://example.domain/foo/bar?baz".parse().unwrap();
+        let dst = Destination {
+            uri,
+            addr: None,
+            early_data: false,
+        };
+        let mut connector = NativeTlsConnector::new(1).unwrap();
+        connector.enforce_https(true);
+
+        assert_eq!(connector.connect(dst).wait().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
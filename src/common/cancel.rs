@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use futures::Async;
+use futures::task::{self, Task};
+
+/// A token to abort server connections, such as all the connections of a
+/// tenant that is being evicted, or a single client request.
+///
+/// When set with [`Http::cancel_token`](::server::conn::Http::cancel_token),
+/// every connection served with it is aborted once the token is canceled,
+/// after writing out what was already buffered, instead of waiting for
+/// in-flight requests to finish. The token is also put in the extensions of
+/// every request, so that a `Service` can stop its work early, with
+/// [`poll_canceled`](CancelToken::poll_canceled).
+///
+/// When inserted into the extensions of a client `Request`, the request is
+/// aborted once the token is canceled, if its response head hasn't been
+/// received yet. Its future then resolves with an error whose
+/// `is_canceled()` is true. An HTTP/1 connection is closed, since the
+/// request can't be taken back, while only the stream of an HTTP/2 request
+/// is reset.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// # #[cfg(feature = "server")]
+/// # fn main() {
+/// # use futures::future;
+/// # use hyper::{Body, Request, Response};
+/// use hyper::server::conn::{CancelToken, Http};
+///
+/// let token = CancelToken::new();
+/// let mut http = Http::new();
+/// http.cancel_token(token.clone());
+///
+/// // In a `Service`, stop waiting on work that won't be answered anyway.
+/// let respond = |req: Request<Body>| {
+///     let token = req.extensions().get::<CancelToken>().cloned();
+///     future::poll_fn(move || {
+///         if let Some(ref token) = token {
+///             if token.poll_canceled().is_ready() {
+///                 return Err("connection canceled");
+///             }
+///         }
+///         // ... poll the actual work ...
+/// #       Ok(futures::Async::Ready(Response::new(Body::empty())))
+///     })
+/// };
+/// # drop(respond);
+///
+/// // Later, abort every connection served with `http`.
+/// token.cancel();
+/// # }
+/// # #[cfg(not(feature = "server"))]
+/// # fn main() {}
+/// ```
+pub struct CancelToken {
+    id: usize,
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    canceled: AtomicBool,
+    next_id: AtomicUsize,
+    // The task waiting on each clone of the token.
+    waiters: Mutex<HashMap<usize, Task>>,
+}
+
+impl CancelToken {
+    /// Create a new `CancelToken`, that isn't canceled.
+    pub fn new() -> CancelToken {
+        CancelToken {
+            id: 0,
+            inner: Arc::new(Inner {
+                canceled: AtomicBool::new(false),
+                next_id: AtomicUsize::new(1),
+                waiters: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Cancel this token, and every clone of it.
+    pub fn cancel(&self) {
+        if self.inner.canceled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        trace!("cancel token canceled");
+        let waiters = ::std::mem::replace(&mut *self.inner.waiters.lock().unwrap(), HashMap::new());
+        for (_, task) in waiters {
+            task.notify();
+        }
+    }
+
+    /// Check if this token was canceled.
+    pub fn is_canceled(&self) -> bool {
+        self.inner.canceled.load(Ordering::SeqCst)
+    }
+
+    /// Check if this token was canceled, and if not, notify the current task
+    /// once it is.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if called outside of a task.
+    pub fn poll_canceled(&self) -> Async<()> {
+        if self.is_canceled() {
+            return Async::Ready(());
+        }
+        self.inner.waiters.lock().unwrap().insert(self.id, task::current());
+        // It may have been canceled before the task was registered.
+        if self.is_canceled() {
+            Async::Ready(())
+        } else {
+            Async::NotReady
+        }
+    }
+}
+
+impl Clone for CancelToken {
+    fn clone(&self) -> CancelToken {
+        CancelToken {
+            id: self.inner.next_id.fetch_add(1, Ordering::Relaxed),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl Drop for CancelToken {
+    fn drop(&mut self) {
+        if let Ok(mut waiters) = self.inner.waiters.lock() {
+            waiters.remove(&self.id);
+        }
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("canceled", &self.is_canceled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Async, Future};
+
+    use super::CancelToken;
+
+    #[test]
+    fn cancel_notifies_clones() {
+        future::lazy(|| {
+            let token = CancelToken::new();
+            let clone = token.clone();
+            assert_eq!(clone.poll_canceled(), Async::NotReady);
+            assert_eq!(token.inner.waiters.lock().unwrap().len(), 1);
+
+            token.cancel();
+            assert!(clone.is_canceled());
+            assert_eq!(clone.poll_canceled(), Async::Ready(()));
+            assert!(token.inner.waiters.lock().unwrap().is_empty());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -0,0 +1,42 @@
+use futures::{Async, Poll};
+
+/// Why a connection closed.
+///
+/// A `CloseReason` is available from a `Connection`, once it has finished,
+/// whether it closed cleanly or with an error.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CloseReason {
+    #[cfg(feature = "http2")]
+    http2_error_code: Option<u32>,
+}
+
+impl CloseReason {
+    /// The reason for a connection that finished polling with `res`, or
+    /// `None` if it hasn't finished yet.
+    pub(crate) fn after(res: &Poll<(), ::Error>) -> Option<CloseReason> {
+        match *res {
+            Ok(Async::NotReady) => None,
+            Ok(Async::Ready(())) => Some(CloseReason::default()),
+            #[cfg(feature = "http2")]
+            Err(ref err) => Some(CloseReason {
+                http2_error_code: err.http2_error_code(),
+            }),
+            #[cfg(not(feature = "http2"))]
+            Err(_) => Some(CloseReason::default()),
+        }
+    }
+
+    /// Returns the HTTP/2 error code the connection closed with, if any.
+    ///
+    /// This is the code of a `GOAWAY` frame from the peer, or of one sent
+    /// because of a protocol error. A connection that closed gracefully,
+    /// with `NO_ERROR`, has no code.
+    ///
+    /// The debug data of a `GOAWAY`, such as `"too_many_pings"`, isn't
+    /// available: the version of h2 used by hyper discards it, and it needs
+    /// a newer h2.
+    #[cfg(feature = "http2")]
+    pub fn http2_error_code(&self) -> Option<u32> {
+        self.http2_error_code
+    }
+}
@@ -0,0 +1,153 @@
+use std::mem;
+
+use futures::{Async, Future, Poll, Stream};
+use futures::future::Shared;
+use futures::sync::{mpsc, oneshot};
+
+use super::Never;
+
+/// Create a channel to start draining, and wait until everything watching
+/// it has finished.
+pub(crate) fn channel() -> (Signal, Watch) {
+    let (tx, rx) = oneshot::channel();
+    let (drained_tx, drained_rx) = mpsc::channel(0);
+    (
+        Signal {
+            drained_rx,
+            tx,
+        },
+        Watch {
+            _drained_tx: drained_tx,
+            rx: rx.shared(),
+        },
+    )
+}
+
+#[derive(Debug)]
+pub(crate) struct Signal {
+    drained_rx: mpsc::Receiver<Never>,
+    tx: oneshot::Sender<()>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Draining {
+    drained_rx: mpsc::Receiver<Never>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Watch {
+    // Never sent on, only dropped, so `Draining` knows once every `Watch`
+    // is gone.
+    _drained_tx: mpsc::Sender<Never>,
+    rx: Shared<oneshot::Receiver<()>>,
+}
+
+pub(crate) struct Watching<F, FN> {
+    future: F,
+    state: State<FN>,
+    watch: Watch,
+}
+
+enum State<F> {
+    Watch(F),
+    Draining,
+}
+
+impl Signal {
+    /// Start draining, returning a future that completes once every
+    /// `Watch` has been dropped.
+    pub(crate) fn drain(self) -> Draining {
+        let _ = self.tx.send(());
+        Draining {
+            drained_rx: self.drained_rx,
+        }
+    }
+}
+
+impl Future for Draining {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match try_ready!(self.drained_rx.poll()) {
+            Some(never) => match never {},
+            None => Ok(Async::Ready(())),
+        }
+    }
+}
+
+impl Watch {
+    /// Wrap a future, calling `on_drain` with it once draining starts. The
+    /// future keeps being polled until it completes.
+    pub(crate) fn watch<F, FN>(self, future: F, on_drain: FN) -> Watching<F, FN>
+    where
+        F: Future,
+        FN: FnOnce(&mut F),
+    {
+        Watching {
+            future,
+            state: State::Watch(on_drain),
+            watch: self,
+        }
+    }
+}
+
+impl<F, FN> Future for Watching<F, FN>
+where
+    F: Future,
+    FN: FnOnce(&mut F),
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match mem::replace(&mut self.state, State::Draining) {
+                State::Watch(on_drain) => {
+                    match self.watch.rx.poll() {
+                        // A dropped `Signal` drains as well.
+                        Ok(Async::Ready(_)) | Err(_) => {
+                            on_drain(&mut self.future);
+                        },
+                        Ok(Async::NotReady) => {
+                            self.state = State::Watch(on_drain);
+                            return self.future.poll();
+                        },
+                    }
+                },
+                State::Draining => {
+                    return self.future.poll();
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Async, Future};
+
+    use super::*;
+
+    #[test]
+    fn watch_drains_on_signal() {
+        future::lazy(|| {
+            let (signal, watch) = channel();
+            let mut drained = false;
+            let mut watching = watch.clone().watch(future::empty::<(), ()>(), |_| ());
+            let mut draining = {
+                let mut other = watch.watch(future::ok::<(), ()>(()), |_| drained = true);
+                assert_eq!(other.poll(), Ok(Async::Ready(())));
+                signal.drain()
+            };
+            assert!(!drained, "completed before draining started");
+
+            // Still one `Watch` alive.
+            assert_eq!(draining.poll(), Ok(Async::NotReady));
+            assert_eq!(watching.poll(), Ok(Async::NotReady));
+            drop(watching);
+            assert_eq!(draining.poll(), Ok(Async::Ready(())));
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
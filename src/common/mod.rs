@@ -1,7 +1,20 @@
-mod buf;
-mod exec;
+#[cfg(all(feature = "http1", any(feature = "client", feature = "server")))] mod buf;
+#[cfg(any(feature = "client", feature = "server"))] mod cancel;
+#[cfg(any(feature = "client", feature = "server"))] mod close;
+#[cfg(feature = "server")] pub(crate) mod drain;
+#[cfg(any(feature = "client", feature = "server"))] mod exec;
 mod never;
+#[cfg(any(feature = "client", feature = "server"))] mod stats;
+#[cfg(feature = "client")] mod window;
+#[cfg(all(feature = "runtime", any(feature = "client", feature = "server")))] mod write_timeout;
 
-pub(crate) use self::buf::StaticBuf;
-pub(crate) use self::exec::Exec;
+#[cfg(all(feature = "http1", any(feature = "client", feature = "server")))] pub(crate) use self::buf::StaticBuf;
+#[cfg(any(feature = "client", feature = "server"))] pub use self::cancel::CancelToken;
+#[cfg(any(feature = "client", feature = "server"))] pub use self::close::CloseReason;
+#[cfg(any(feature = "client", feature = "server"))] pub(crate) use self::exec::Exec;
 pub use self::never::Never;
+#[cfg(any(feature = "client", feature = "server"))] pub use self::stats::{ConnectionStats, ExchangeStats};
+#[cfg(all(feature = "http2", any(feature = "client", feature = "server")))] pub(crate) use self::stats::StatsIo;
+#[cfg(all(feature = "http1", any(feature = "client", feature = "server")))] pub(crate) use self::stats::Transfer;
+#[cfg(feature = "client")] pub use self::window::SendWindow;
+#[cfg(all(feature = "runtime", any(feature = "client", feature = "server")))] pub(crate) use self::write_timeout::WriteTimeout;
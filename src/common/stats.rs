@@ -0,0 +1,286 @@
+use std::fmt;
+#[cfg(feature = "http2")]
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[cfg(feature = "http2")]
+use bytes::{Buf, BufMut};
+#[cfg(feature = "http2")]
+use futures::{Async, Poll};
+#[cfg(feature = "http1")]
+use http::Extensions;
+#[cfg(feature = "http2")]
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Counters of the bytes transferred, and requests exchanged, on a
+/// connection.
+///
+/// The counters are updated as the connection reads from and writes to its
+/// IO object, so they include everything, such as message heads and chunked
+/// encoding, and cost no more than an atomic add per read or write.
+///
+/// A `ConnectionStats` is available from a `Connection`, and is put in the
+/// extensions of every `Request` given to a server `Service`, and of every
+/// `Response` received by a client.
+#[derive(Clone)]
+pub struct ConnectionStats {
+    inner: Arc<Counters>,
+}
+
+/// Counters of the bytes transferred for a single request and response
+/// exchange, on an HTTP/1 connection.
+///
+/// Bytes read are counted as the message is parsed, so they don't include
+/// any bytes of a pipelined message that was read along with it. Bytes
+/// written are counted as they are written to the IO object, while the
+/// exchange is the connection's latest one.
+///
+/// An `ExchangeStats` is put in the extensions of every `Request` and
+/// `Response` read on an HTTP/1 connection, next to its `ConnectionStats`.
+/// The counters keep updating until the exchange is complete.
+#[derive(Clone)]
+pub struct ExchangeStats {
+    inner: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    read: AtomicUsize,
+    written: AtomicUsize,
+    requests: AtomicUsize,
+    // Whether the latest write to the connection's IO couldn't make
+    // progress.
+    write_blocked: AtomicBool,
+}
+
+// The counters a connection updates as it reads and writes.
+#[cfg(feature = "http1")]
+#[derive(Clone)]
+pub(crate) struct Transfer {
+    conn: ConnectionStats,
+    exchange: Option<ExchangeStats>,
+}
+
+// An IO wrapper counting the bytes read and written into `ConnectionStats`,
+// for protocols that do their own buffering, like HTTP/2.
+#[cfg(feature = "http2")]
+pub(crate) struct StatsIo<T> {
+    io: T,
+    stats: ConnectionStats,
+}
+
+// ===== impl ConnectionStats =====
+
+impl ConnectionStats {
+    pub(crate) fn new() -> ConnectionStats {
+        ConnectionStats {
+            inner: Arc::new(Counters::default()),
+        }
+    }
+
+    /// The number of bytes read from the connection.
+    pub fn bytes_read(&self) -> usize {
+        self.inner.read.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes written to the connection.
+    pub fn bytes_written(&self) -> usize {
+        self.inner.written.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests started on the connection.
+    pub fn requests(&self) -> usize {
+        self.inner.requests.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn on_read(&self, n: usize) {
+        self.inner.read.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_written(&self, n: usize) {
+        self.inner.written.fetch_add(n, Ordering::Relaxed);
+        self.set_write_blocked(false);
+    }
+
+    pub(crate) fn on_request(&self) {
+        self.inner.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_write_blocked(&self, blocked: bool) {
+        self.inner.write_blocked.store(blocked, Ordering::Relaxed);
+    }
+
+    /// Whether the connection is waiting for its IO to accept more bytes.
+    #[cfg(feature = "runtime")]
+    pub(crate) fn is_write_blocked(&self) -> bool {
+        self.inner.write_blocked.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for ConnectionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConnectionStats")
+            .field("bytes_read", &self.bytes_read())
+            .field("bytes_written", &self.bytes_written())
+            .field("requests", &self.requests())
+            .finish()
+    }
+}
+
+// ===== impl ExchangeStats =====
+
+impl ExchangeStats {
+    /// The number of bytes read for this exchange.
+    pub fn bytes_read(&self) -> usize {
+        self.inner.read.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes written for this exchange.
+    pub fn bytes_written(&self) -> usize {
+        self.inner.written.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for ExchangeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExchangeStats")
+            .field("bytes_read", &self.bytes_read())
+            .field("bytes_written", &self.bytes_written())
+            .finish()
+    }
+}
+
+// ===== impl Transfer =====
+
+#[cfg(feature = "http1")]
+impl Transfer {
+    pub(crate) fn new(conn: ConnectionStats) -> Transfer {
+        Transfer {
+            conn,
+            exchange: None,
+        }
+    }
+
+    pub(crate) fn connection(&self) -> &ConnectionStats {
+        &self.conn
+    }
+
+    /// A new request was read or written, so the following bytes belong to
+    /// a new exchange.
+    pub(crate) fn start_exchange(&mut self) {
+        self.conn.on_request();
+        self.exchange = Some(ExchangeStats {
+            inner: Arc::new(Counters::default()),
+        });
+    }
+
+    /// Bytes read from the IO, for any message.
+    pub(crate) fn on_read(&self, n: usize) {
+        self.conn.on_read(n);
+    }
+
+    /// Bytes of the current exchange's incoming message, that were parsed.
+    pub(crate) fn on_consumed(&self, n: usize) {
+        if let Some(ref exchange) = self.exchange {
+            exchange.inner.read.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn on_written(&self, n: usize) {
+        self.conn.on_written(n);
+        if let Some(ref exchange) = self.exchange {
+            exchange.inner.written.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn set_write_blocked(&self, blocked: bool) {
+        self.conn.set_write_blocked(blocked);
+    }
+
+    /// Put the stats in the extensions of the exchange's `Request` or
+    /// `Response`.
+    pub(crate) fn set(&self, ext: &mut Extensions) {
+        ext.insert(self.conn.clone());
+        if let Some(ref exchange) = self.exchange {
+            ext.insert(exchange.clone());
+        }
+    }
+}
+
+// ===== impl StatsIo =====
+
+#[cfg(feature = "http2")]
+impl<T> StatsIo<T> {
+    pub(crate) fn new(io: T, stats: ConnectionStats) -> StatsIo<T> {
+        StatsIo {
+            io,
+            stats,
+        }
+    }
+}
+
+#[cfg(feature = "http2")]
+impl<T: Read> Read for StatsIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.io.read(buf)?;
+        self.stats.on_read(n);
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "http2")]
+impl<T: Write> Write for StatsIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.io.write(buf) {
+            Ok(n) => {
+                self.stats.on_written(n);
+                Ok(n)
+            },
+            Err(e) => {
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    self.stats.set_write_blocked(true);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+#[cfg(feature = "http2")]
+impl<T: AsyncRead> AsyncRead for StatsIo<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+
+    fn read_buf<B: BufMut>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        let n = try_ready!(self.io.read_buf(buf));
+        self.stats.on_read(n);
+        Ok(n.into())
+    }
+}
+
+#[cfg(feature = "http2")]
+impl<T: AsyncWrite> AsyncWrite for StatsIo<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+
+    // Forwarded, so the IO can still use vectored writes.
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        match self.io.write_buf(buf)? {
+            Async::Ready(n) => {
+                self.stats.on_written(n);
+                Ok(n.into())
+            },
+            Async::NotReady => {
+                self.stats.set_write_blocked(true);
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::cmp;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "http2")] use bytes::Buf;
+use futures::Async;
+use futures::task::{self, Task};
+#[cfg(feature = "http2")] use h2::SendStream;
+
+/// A handle to the HTTP/2 flow control window of a request body.
+///
+/// Insert a `SendWindow` into the extensions of a `Request`, and keep a
+/// clone of it in the request's `Payload`. Once the request is sent on an
+/// HTTP/2 connection, the `Payload` can wait for the peer to allow more
+/// data with [`poll_capacity`](SendWindow::poll_capacity), and produce only
+/// that much, instead of having its data buffered in the connection.
+///
+/// HTTP/1 has no flow control, so a request sent on an HTTP/1 connection
+/// may always send the capacity it asks for.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// # use futures::{Async, Poll};
+/// # use hyper::{Chunk, Request};
+/// # use hyper::body::Payload;
+/// use hyper::client::SendWindow;
+///
+/// // Sends `len` zero bytes, as fast as the peer allows.
+/// struct Zeroes {
+///     len: usize,
+///     window: SendWindow,
+/// }
+///
+/// impl Payload for Zeroes {
+///     type Data = Chunk;
+///     type Error = hyper::Error;
+///
+///     fn poll_data(&mut self) -> Poll<Option<Chunk>, hyper::Error> {
+///         if self.len == 0 {
+///             return Ok(Async::Ready(None));
+///         }
+///         let n = match self.window.poll_capacity(self.len) {
+///             Async::Ready(Some(n)) => n,
+///             Async::Ready(None) => return Ok(Async::Ready(None)),
+///             Async::NotReady => return Ok(Async::NotReady),
+///         };
+///         self.len -= n;
+///         Ok(Async::Ready(Some(vec![0; n].into())))
+///     }
+/// }
+///
+/// # fn main() {
+/// let window = SendWindow::new();
+/// let mut req = Request::new(Zeroes {
+///     len: 1024 * 1024,
+///     window: window.clone(),
+/// });
+/// req.extensions_mut().insert(window);
+/// # drop(req);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SendWindow {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    state: State,
+    // The capacity assigned to the stream, which can be sent right away.
+    available: usize,
+    // The capacity the `Payload` asked for, and is waiting on.
+    requested: usize,
+    payload: Option<Task>,
+    pipe: Option<Task>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    // The request hasn't been sent yet.
+    Pending,
+    // Only HTTP/2 streams have a window, or close.
+    #[cfg_attr(not(feature = "http2"), allow(dead_code))]
+    Stream,
+    // Only HTTP/1 connections have no flow control.
+    #[cfg_attr(not(feature = "http1"), allow(dead_code))]
+    Unlimited,
+    #[cfg_attr(not(feature = "http2"), allow(dead_code))]
+    Closed,
+}
+
+impl SendWindow {
+    /// Create a new `SendWindow`, for a request that wasn't sent yet.
+    pub fn new() -> SendWindow {
+        SendWindow {
+            inner: Arc::new(Mutex::new(Inner {
+                state: State::Pending,
+                available: 0,
+                requested: 0,
+                payload: None,
+                pipe: None,
+            })),
+        }
+    }
+
+    /// Get the capacity that can be sent right away, without waiting.
+    pub fn capacity(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        match inner.state {
+            State::Unlimited => usize::max_value(),
+            State::Pending | State::Stream => inner.available,
+            State::Closed => 0,
+        }
+    }
+
+    /// Ask for capacity to send up to `want` bytes, and check if any is
+    /// available.
+    ///
+    /// Returns `Ready(Some(n))` once `n` bytes, at most `want`, may be sent.
+    /// Returns `Ready(None)` if the stream can't send any more data, such as
+    /// when it was reset.
+    ///
+    /// Until the capacity is sent, asking again returns the same capacity.
+    pub fn poll_capacity(&self, want: usize) -> Async<Option<usize>> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            State::Unlimited => Async::Ready(Some(want)),
+            State::Closed => Async::Ready(None),
+            State::Pending | State::Stream => {
+                if inner.available > 0 {
+                    return Async::Ready(Some(cmp::min(inner.available, want)));
+                }
+                inner.payload = Some(task::current());
+                if inner.requested != want {
+                    inner.requested = want;
+                    if let Some(pipe) = inner.pipe.take() {
+                        pipe.notify();
+                    }
+                }
+                Async::NotReady
+            }
+        }
+    }
+
+    // Called by the task sending the body on an HTTP/2 stream, to reserve
+    // the requested capacity, and to keep the available capacity up to date.
+    #[cfg(feature = "http2")]
+    pub(crate) fn poll_stream<B: Buf>(&self, tx: &mut SendStream<B>) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            State::Pending => inner.state = State::Stream,
+            State::Stream => (),
+            State::Unlimited | State::Closed => return,
+        }
+        inner.pipe = Some(task::current());
+        if inner.requested > 0 {
+            tx.reserve_capacity(inner.requested);
+        }
+
+        loop {
+            match tx.poll_capacity() {
+                Ok(Async::Ready(Some(_))) => continue,
+                Ok(Async::NotReady) => break,
+                Ok(Async::Ready(None)) => {
+                    trace!("send window closed");
+                    inner.close();
+                    return;
+                },
+                Err(e) => {
+                    trace!("send window error: {}", e);
+                    inner.close();
+                    return;
+                }
+            }
+        }
+
+        let available = tx.capacity();
+        if available != inner.available {
+            trace!("send window capacity: {}", available);
+            inner.available = available;
+            inner.notify_payload();
+        }
+    }
+
+    // Called once `sent` bytes of data were given to the stream.
+    #[cfg(feature = "http2")]
+    pub(crate) fn on_sent(&self, sent: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.available = inner.available.saturating_sub(sent);
+        inner.requested = 0;
+    }
+
+    // The request was sent on a connection without flow control.
+    #[cfg(feature = "http1")]
+    pub(crate) fn set_unlimited(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == State::Pending {
+            inner.state = State::Unlimited;
+            inner.notify_payload();
+        }
+    }
+
+    // The stream won't send any more data.
+    #[cfg(feature = "http2")]
+    pub(crate) fn close(&self) {
+        self.inner.lock().unwrap().close();
+    }
+}
+
+impl Inner {
+    #[cfg(feature = "http2")]
+    fn close(&mut self) {
+        self.state = State::Closed;
+        self.available = 0;
+        self.pipe = None;
+        self.notify_payload();
+    }
+
+    fn notify_payload(&mut self) {
+        if let Some(payload) = self.payload.take() {
+            payload.notify();
+        }
+    }
+}
+
+impl fmt::Debug for SendWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("SendWindow")
+            .field("state", &inner.state)
+            .field("available", &inner.available)
+            .field("requested", &inner.requested)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "http1", feature = "http2"))]
+mod tests {
+    use futures::{future, Async, Future};
+
+    use super::SendWindow;
+
+    #[test]
+    fn waits_for_a_connection() {
+        future::lazy(|| {
+            let window = SendWindow::new();
+            assert_eq!(window.poll_capacity(10), Async::NotReady);
+            assert_eq!(window.capacity(), 0);
+
+            window.set_unlimited();
+            assert_eq!(window.poll_capacity(10), Async::Ready(Some(10)));
+
+            window.close();
+            assert_eq!(window.poll_capacity(10), Async::Ready(None));
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
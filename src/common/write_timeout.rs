@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use tokio_timer::Delay;
+
+use super::ConnectionStats;
+
+/// Watches a connection for writes that make no progress, see
+/// `server::conn::Http::write_timeout` and
+/// `client::conn::Builder::write_timeout`.
+#[derive(Debug)]
+pub(crate) struct WriteTimeout {
+    dur: Duration,
+    // The bytes written when the connection got stuck, and when it may
+    // stay stuck until.
+    stalled: Option<(usize, Delay)>,
+}
+
+impl WriteTimeout {
+    pub(crate) fn new(dur: Duration) -> WriteTimeout {
+        WriteTimeout {
+            dur,
+            stalled: None,
+        }
+    }
+
+    /// Check on a connection that isn't ready, returning an error once it
+    /// has been stuck writing for too long.
+    pub(crate) fn poll(&mut self, stats: &ConnectionStats) -> Poll<(), ::Error> {
+        if !stats.is_write_blocked() {
+            self.stalled = None;
+            return Ok(Async::NotReady);
+        }
+        // Any bytes written since means the peer is still reading, just
+        // slowly, so the timeout starts over.
+        let written = stats.bytes_written();
+        let is_progress = match self.stalled {
+            Some((since, _)) => since != written,
+            None => true,
+        };
+        if is_progress {
+            self.stalled = Some((written, Delay::new(Instant::now() + self.dur)));
+        }
+
+        let delay = &mut self.stalled.as_mut().expect("just set").1;
+        match delay.poll() {
+            Ok(Async::Ready(())) => {
+                debug!("connection write stalled for {:?}, closing", self.dur);
+                Err(::Error::new_write_timeout())
+            },
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(timer_err) => {
+                error!("couldn't time connection writes, timer error: {}", timer_err);
+                self.stalled = None;
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! IO that owns the buffers it reads into and writes from, like completion
+//! based IO such as io_uring or IOCP.
+//!
+//! With `AsyncRead` and `AsyncWrite`, hyper lends its buffers to the IO for
+//! the length of a call. A completion based IO needs its buffers for as long
+//! as an operation is in flight, so it has to copy through buffers of its
+//! own. A [`CompletionIo`](CompletionIo) is handed hyper's buffers instead,
+//! and HTTP/1 connections read and write without those copies.
+//!
+//! These are unstable, and only available with the `unstable-completion-io`
+//! feature. They may change in any release.
+
+use std::cmp;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+
+use bytes::{BufMut, BytesMut};
+use futures::{Async, Poll};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use proto::h1::{OwnedIo, INIT_BUFFER_SIZE};
+
+/// An IO that owns the buffers of its reads and writes, until they
+/// complete.
+///
+/// At most one read and one write are in flight at a time. Each `poll_*`
+/// method registers the current task to be notified when it isn't ready.
+pub trait CompletionIo {
+    /// Start reading into the spare capacity of `buf`, after its length.
+    fn start_read(&mut self, buf: BytesMut);
+
+    /// Poll the read started last, giving back its buffer with the bytes
+    /// read appended. No bytes appended means the end of the stream.
+    fn poll_read(&mut self) -> Poll<BytesMut, io::Error>;
+
+    /// Start writing all of `buf`.
+    fn start_write(&mut self, buf: Vec<u8>);
+
+    /// Poll the write started last, giving back its buffer and how many
+    /// bytes of it were written.
+    fn poll_write(&mut self) -> Poll<(Vec<u8>, usize), io::Error>;
+
+    /// Poll flushing the completed writes.
+    ///
+    /// The default does nothing.
+    fn poll_flush(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    /// Poll shutting down the write side.
+    fn poll_shutdown(&mut self) -> Poll<(), io::Error>;
+}
+
+/// Adapts a `CompletionIo` to `AsyncRead` and `AsyncWrite`.
+///
+/// This is the IO of connections made with
+/// [`Http::serve_completion`](::server::conn::Http::serve_completion) and
+/// [`Builder::handshake_completion`](::client::conn::Builder::handshake_completion).
+/// HTTP/1 connections hand their buffers to the `CompletionIo`, while the
+/// `AsyncRead` and `AsyncWrite` impls copy, for HTTP/2 and for upgraded
+/// connections.
+pub struct Completion<C> {
+    io: C,
+    // Bytes read with `AsyncRead`, that weren't returned yet.
+    read_buf: BytesMut,
+    // The length the buffer of the read in flight had when it started.
+    reading: Option<usize>,
+    writing: bool,
+}
+
+impl<C: CompletionIo> Completion<C> {
+    /// Wrap a `CompletionIo`.
+    pub fn new(io: C) -> Completion<C> {
+        Completion {
+            io,
+            read_buf: BytesMut::new(),
+            reading: None,
+            writing: false,
+        }
+    }
+
+    /// Get a reference to the inner IO.
+    pub fn get_ref(&self) -> &C {
+        &self.io
+    }
+
+    /// Get a mutable reference to the inner IO.
+    ///
+    /// Starting reads or writes on it directly would mix them up with the
+    /// ones of the connection.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.io
+    }
+
+    pub(crate) fn owned_io() -> OwnedIo<Completion<C>> {
+        OwnedIo {
+            read: Completion::read_owned,
+            write: Completion::write_owned,
+        }
+    }
+
+    fn read_owned(&mut self, buf: &mut BytesMut) -> Poll<usize, io::Error> {
+        if !self.read_buf.is_empty() {
+            // Left over from `AsyncRead`, which only copies so much.
+            let n = self.read_buf.len();
+            buf.extend_from_slice(&self.read_buf);
+            self.read_buf.clear();
+            return Ok(Async::Ready(n));
+        }
+        if self.reading.is_none() {
+            if buf.remaining_mut() < INIT_BUFFER_SIZE {
+                buf.reserve(INIT_BUFFER_SIZE);
+            }
+            self.reading = Some(buf.len());
+            self.io.start_read(mem::replace(buf, BytesMut::new()));
+        }
+        let (read, len) = try_ready!(self.poll_read_done());
+        let n = read.len() - len;
+        if buf.is_empty() {
+            *buf = read;
+        } else {
+            // The read was started by `AsyncRead`, with a buffer of ours.
+            buf.extend_from_slice(&read);
+        }
+        Ok(Async::Ready(n))
+    }
+
+    fn write_owned(&mut self, buf: &mut Vec<u8>) -> Poll<usize, io::Error> {
+        if !self.writing {
+            if buf.is_empty() {
+                return Ok(Async::Ready(0));
+            }
+            self.writing = true;
+            self.io.start_write(mem::replace(buf, Vec::new()));
+        }
+        let (mut written, n) = try_ready!(self.poll_write_done());
+        written.drain(..n);
+        // Bytes buffered while the write was in flight go after the ones
+        // it didn't write.
+        written.extend_from_slice(buf);
+        *buf = written;
+        Ok(Async::Ready(n))
+    }
+
+    fn poll_read_done(&mut self) -> Poll<(BytesMut, usize), io::Error> {
+        let read = match self.io.poll_read() {
+            Ok(Async::Ready(read)) => read,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(err) => {
+                self.reading = None;
+                return Err(err);
+            }
+        };
+        let len = self.reading.take().expect("read in flight");
+        Ok(Async::Ready((read, len)))
+    }
+
+    fn poll_write_done(&mut self) -> Poll<(Vec<u8>, usize), io::Error> {
+        let res = self.io.poll_write();
+        if let Ok(Async::NotReady) = res {
+            return res;
+        }
+        self.writing = false;
+        res
+    }
+
+    // Complete the write in flight, writing again whatever it didn't.
+    fn poll_write_all(&mut self) -> Poll<(), io::Error> {
+        while self.writing {
+            let (mut written, n) = try_ready!(self.poll_write_done());
+            if n < written.len() {
+                if n == 0 {
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                written.drain(..n);
+                self.writing = true;
+                self.io.start_write(written);
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<C: CompletionIo> Read for Completion<C> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        if self.read_buf.is_empty() {
+            if self.reading.is_none() {
+                self.reading = Some(0);
+                self.io.start_read(BytesMut::with_capacity(INIT_BUFFER_SIZE));
+            }
+            // A read started by a connection holds the bytes it hadn't
+            // parsed yet too, so all of it is returned.
+            match self.poll_read_done()? {
+                Async::Ready((read, _)) => self.read_buf = read,
+                Async::NotReady => return Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+        let n = cmp::min(dst.len(), self.read_buf.len());
+        dst[..n].copy_from_slice(&self.read_buf.split_to(n));
+        Ok(n)
+    }
+}
+
+impl<C: CompletionIo> Write for Completion<C> {
+    fn write(&mut self, src: &[u8]) -> io::Result<usize> {
+        if let Async::NotReady = self.poll_write_all()? {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.writing = true;
+        self.io.start_write(src.to_vec());
+        Ok(src.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Async::NotReady = self.poll_write_all()? {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        match self.io.poll_flush()? {
+            Async::Ready(()) => Ok(()),
+            Async::NotReady => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<C: CompletionIo> AsyncRead for Completion<C> {}
+
+impl<C: CompletionIo> AsyncWrite for Completion<C> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        try_ready!(self.poll_write_all());
+        self.io.poll_shutdown()
+    }
+}
+
+impl<C> fmt::Debug for Completion<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Completion")
+            .field("reading", &self.reading.is_some())
+            .field("writing", &self.writing)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use bytes::{BufMut, BytesMut};
+    use futures::{task, Async, Poll};
+
+    use super::{Completion, CompletionIo};
+
+    // Reads from `input`, and writes at most `max_write` bytes at a time to
+    // `output`, completing everything at once.
+    struct Mock {
+        input: Vec<u8>,
+        output: Arc<Mutex<Vec<u8>>>,
+        max_write: usize,
+        // Like a server, don't answer until something is written.
+        read_after_write: bool,
+        stall_write: bool,
+        read: Option<BytesMut>,
+        write: Option<(Vec<u8>, usize)>,
+    }
+
+    impl Mock {
+        fn new(input: &[u8], max_write: usize) -> Mock {
+            Mock {
+                input: input.to_vec(),
+                output: Arc::new(Mutex::new(Vec::new())),
+                max_write,
+                read_after_write: false,
+                stall_write: false,
+                read: None,
+                write: None,
+            }
+        }
+    }
+
+    impl CompletionIo for Mock {
+        fn start_read(&mut self, buf: BytesMut) {
+            assert!(self.read.is_none(), "read in flight");
+            self.read = Some(buf);
+        }
+
+        fn poll_read(&mut self) -> Poll<BytesMut, ::std::io::Error> {
+            if self.read_after_write && self.output.lock().unwrap().is_empty() {
+                task::current().notify();
+                return Ok(Async::NotReady);
+            }
+            let mut buf = self.read.take().expect("no read in flight");
+            let n = ::std::cmp::min(buf.remaining_mut(), self.input.len());
+            buf.extend_from_slice(&self.input[..n]);
+            self.input.drain(..n);
+            Ok(Async::Ready(buf))
+        }
+
+        fn start_write(&mut self, buf: Vec<u8>) {
+            assert!(self.write.is_none(), "write in flight");
+            let n = ::std::cmp::min(buf.len(), self.max_write);
+            self.output.lock().unwrap().extend_from_slice(&buf[..n]);
+            self.write = Some((buf, n));
+        }
+
+        fn poll_write(&mut self) -> Poll<(Vec<u8>, usize), ::std::io::Error> {
+            if self.stall_write {
+                self.stall_write = false;
+                return Ok(Async::NotReady);
+            }
+            Ok(Async::Ready(self.write.take().expect("no write in flight")))
+        }
+
+        fn poll_shutdown(&mut self) -> Poll<(), ::std::io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[test]
+    fn owned_read_hands_over_buffer() {
+        let mut io = Completion::new(Mock::new(b"world", 1024));
+        let mut buf = BytesMut::with_capacity(64);
+        buf.extend_from_slice(b"hello ");
+        assert_eq!((Completion::owned_io().read)(&mut io, &mut buf).unwrap(), Async::Ready(5));
+        assert_eq!(&buf[..], b"hello world");
+
+        // No more input is the end of the stream.
+        assert_eq!((Completion::owned_io().read)(&mut io, &mut buf).unwrap(), Async::Ready(0));
+    }
+
+    #[test]
+    fn owned_write_keeps_unwritten_bytes_first() {
+        let mut mock = Mock::new(b"", 3);
+        mock.stall_write = true;
+        let output = mock.output.clone();
+        let mut io = Completion::new(mock);
+        let write = Completion::owned_io().write;
+
+        let mut buf = b"hello".to_vec();
+        assert_eq!(write(&mut io, &mut buf).unwrap(), Async::NotReady);
+        assert!(buf.is_empty());
+        // Buffered while the write is in flight.
+        buf.extend_from_slice(b" world");
+        assert_eq!(write(&mut io, &mut buf).unwrap(), Async::Ready(3));
+        assert_eq!(buf, b"lo world");
+        while !buf.is_empty() {
+            write(&mut io, &mut buf).unwrap();
+        }
+        assert_eq!(&output.lock().unwrap()[..], b"hello world");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn serve_completion_pipelined() {
+        use futures::Future;
+        use server::conn::Http;
+        use service::service_fn;
+        use {Body, Response};
+
+        let mock = Mock::new(b"\
+            GET /a HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            \r\n\
+            GET /b HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ", 7);
+        let output = mock.output.clone();
+        Http::new()
+            .serve_completion(mock, service_fn(|req| {
+                Ok::<_, ::Error>(Response::new(Body::from(req.uri().path().to_owned())))
+            }))
+            .wait()
+            .unwrap();
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", output);
+        assert_eq!(output.matches("HTTP/1.1 200 OK\r\n").count(), 2, "{:?}", output);
+        assert!(output.contains("\r\n\r\n/a"), "{:?}", output);
+        assert!(output.ends_with("\r\n\r\n/b"), "{:?}", output);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn handshake_completion() {
+        use futures::{Future, Stream};
+        use client::conn::Builder;
+        use {Body, Request};
+
+        let mut mock = Mock::new(b"\
+            HTTP/1.1 200 OK\r\n\
+            Content-Length: 5\r\n\
+            Connection: close\r\n\
+            \r\n\
+            hello\
+        ", 7);
+        mock.read_after_write = true;
+        let output = mock.output.clone();
+        let (mut tx, conn) = Builder::new()
+            .handshake_completion::<_, Body>(mock)
+            .wait()
+            .unwrap();
+        let req = Request::builder()
+            .uri("/a")
+            .body(Body::empty())
+            .unwrap();
+        let conn = ::std::thread::spawn(move || conn.wait());
+        let res = tx.send_request(req).wait().unwrap();
+        let body = res.into_body().concat2().wait().unwrap();
+        assert_eq!(&body[..], b"hello");
+        conn.join().unwrap().unwrap();
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("GET /a HTTP/1.1\r\n"), "{:?}", output);
+    }
+}
@@ -1,11 +1,13 @@
 //! Error and Result module.
 use std::error::Error as StdError;
 use std::fmt;
-use std::io;
+#[cfg(any(feature = "client", all(feature = "http1", feature = "server")))] use std::io;
 
 use httparse;
 use http;
 
+#[cfg(feature = "client")] use client::connect::ConnectError;
+
 /// Result type often returned from methods that can have hyper `Error`s.
 pub type Result<T> = ::std::result::Result<T, Error>;
 
@@ -21,6 +23,12 @@ struct ErrorImpl {
     cause: Option<Cause>,
 }
 
+// The `is_*` methods check for every kind, but only the kinds of the enabled
+// roles and protocols are ever created.
+#[cfg_attr(
+    not(all(feature = "client", feature = "server", feature = "http1", feature = "http2")),
+    allow(dead_code)
+)]
 #[derive(Debug, PartialEq)]
 pub(crate) enum Kind {
     Parse(Parse),
@@ -28,6 +36,9 @@ pub(crate) enum Kind {
     Incomplete,
     /// A client connection received a response when not waiting for one.
     MismatchedResponse,
+    /// A `101 Switching Protocols` response didn't accept the protocols
+    /// offered by its request.
+    Upgrade,
     /// A pending item was dropped before ever being processed.
     Canceled,
     /// Indicates a connection is closed.
@@ -36,6 +47,14 @@ pub(crate) enum Kind {
     Io,
     /// Error occurred while connecting.
     Connect,
+    /// Waited too long for a connection from the pool.
+    PoolTimeout,
+    /// A connection limit of the pool was reached.
+    PoolFull,
+    /// The pool was closed, by shutting down the `Client`.
+    PoolClosed,
+    /// The circuit breaker for a host is open.
+    CircuitOpen,
     /// Error creating a TcpListener.
     #[cfg(feature = "runtime")]
     Listen,
@@ -51,8 +70,12 @@ pub(crate) enum Kind {
     BodyWrite,
     /// Error calling user's Payload::poll_data().
     BodyUser,
+    /// A received body was larger than its limit.
+    BodyTooLarge,
     /// Error calling AsyncWrite::shutdown()
     Shutdown,
+    /// A connection couldn't write anything for too long.
+    WriteTimeout,
 
     /// A general error from h2.
     Http2,
@@ -61,8 +84,18 @@ pub(crate) enum Kind {
     UnsupportedVersion,
     /// User tried to create a CONNECT Request with the Client.
     UnsupportedRequestMethod,
+    /// User tried to send a header value containing CR, LF, or NUL.
+    InvalidHeaderValue,
+    /// User tried to take apart a connection that isn't HTTP/1.
+    UnsupportedIntoParts,
+    /// User tried to send an interim response that isn't a 1xx status.
+    InvalidInformational,
 }
 
+#[cfg_attr(
+    not(all(feature = "client", feature = "server", feature = "http1", feature = "http2")),
+    allow(dead_code)
+)]
 #[derive(Debug, PartialEq)]
 pub(crate) enum Parse {
     Method,
@@ -90,7 +123,6 @@ impl Error {
     //TODO(error): should there be these kinds of inspection methods?
     //
     // - is_io()
-    // - is_connect()
     // - is_closed()
     // - etc?
 
@@ -102,6 +134,15 @@ impl Error {
         }
     }
 
+    /// Returns true if this was an HTTP parse error caused by a message
+    /// head that was too large, or had too many headers.
+    pub fn is_parse_too_large(&self) -> bool {
+        match self.inner.kind {
+            Kind::Parse(Parse::TooLarge) => true,
+            _ => false,
+        }
+    }
+
     /// Returns true if this error was caused by user code.
     pub fn is_user(&self) -> bool {
         match self.inner.kind {
@@ -110,7 +151,10 @@ impl Error {
             Kind::Service |
             Kind::Closed |
             Kind::UnsupportedVersion |
-            Kind::UnsupportedRequestMethod => true,
+            Kind::UnsupportedRequestMethod |
+            Kind::InvalidHeaderValue |
+            Kind::UnsupportedIntoParts |
+            Kind::InvalidInformational => true,
             _ => false,
         }
     }
@@ -120,11 +164,128 @@ impl Error {
         self.inner.kind == Kind::Canceled
     }
 
+    /// Returns true if a `Client` couldn't connect to the destination of a
+    /// request.
+    ///
+    /// More details may be available from
+    /// [`connect_error`](Error::connect_error).
+    pub fn is_connect(&self) -> bool {
+        self.inner.kind == Kind::Connect
+    }
+
+    /// Returns the [`ConnectError`](::client::connect::ConnectError) of a
+    /// `Client` that couldn't connect, telling which step of connecting
+    /// failed.
+    ///
+    /// This is `None` for other errors, and for errors of a connector that
+    /// didn't return a `ConnectError`.
+    #[cfg(feature = "client")]
+    pub fn connect_error(&self) -> Option<&ConnectError> {
+        if self.inner.kind != Kind::Connect {
+            return None;
+        }
+        let cause = match self.inner.cause {
+            Some(ref cause) => cause,
+            None => return None,
+        };
+        if let Some(err) = cause.downcast_ref::<ConnectError>() {
+            return Some(err);
+        }
+        cause.downcast_ref::<io::Error>()
+            .and_then(|err| err.get_ref())
+            .and_then(|err| err.downcast_ref::<ConnectError>())
+    }
+
+    /// Returns true if a `Client` request waited too long for a pooled
+    /// or new connection.
+    pub fn is_pool_timeout(&self) -> bool {
+        self.inner.kind == Kind::PoolTimeout
+    }
+
+    /// Returns true if a `Client` request couldn't get a connection, because
+    /// a connection or request limit was reached.
+    pub fn is_pool_full(&self) -> bool {
+        self.inner.kind == Kind::PoolFull
+    }
+
+    /// Returns true if a `Client` request couldn't get a connection, because
+    /// the `Client` was shut down.
+    pub fn is_pool_closed(&self) -> bool {
+        self.inner.kind == Kind::PoolClosed
+    }
+
+    /// Returns true if a `Client` request wasn't sent, because too many
+    /// recent requests to the same host failed.
+    pub fn is_circuit_open(&self) -> bool {
+        self.inner.kind == Kind::CircuitOpen
+    }
+
+    /// Returns true if a response body was larger than the maximum size
+    /// set with `Builder::max_response_body_size`.
+    pub fn is_body_too_large(&self) -> bool {
+        self.inner.kind == Kind::BodyTooLarge
+    }
+
+    /// Returns true if a server connection was closed, because its peer
+    /// stopped reading for longer than the write timeout.
+    pub fn is_write_timeout(&self) -> bool {
+        self.inner.kind == Kind::WriteTimeout
+    }
+
     /// Returns true if a sender's channel is closed.
     pub fn is_closed(&self) -> bool {
         self.inner.kind == Kind::Closed
     }
 
+    /// Returns the HTTP/2 error code, if this error was caused by one.
+    ///
+    /// This is the code of a `GOAWAY` or `RST_STREAM` frame from the peer,
+    /// or of one sent because of a protocol error. For example, a server
+    /// closing a connection because of too many pings would use `0xb`
+    /// (`ENHANCE_YOUR_CALM`).
+    ///
+    /// The debug data of a `GOAWAY` isn't available: the version of h2 used
+    /// by hyper discards it, and it needs a newer h2.
+    #[cfg(feature = "http2")]
+    pub fn http2_error_code(&self) -> Option<u32> {
+        self.inner.cause
+            .as_ref()
+            .and_then(|cause| cause.downcast_ref::<::h2::Error>())
+            .and_then(|err| err.reason())
+            .map(u32::from)
+    }
+
+    /// Returns true if the peer asked to be sent less, with the HTTP/2
+    /// error code `ENHANCE_YOUR_CALM`.
+    #[cfg(all(feature = "http2", feature = "client"))]
+    pub(crate) fn is_enhance_your_calm(&self) -> bool {
+        self.http2_error_code() == Some(u32::from(::h2::Reason::ENHANCE_YOUR_CALM))
+    }
+
+    #[cfg(all(not(feature = "http2"), feature = "client"))]
+    pub(crate) fn is_enhance_your_calm(&self) -> bool {
+        false
+    }
+
+    /// Returns true if the connection closed before a response was received.
+    #[cfg(feature = "client")]
+    pub(crate) fn is_closed_before_response(&self) -> bool {
+        match self.inner.kind {
+            Kind::Incomplete => true,
+            Kind::Io => self.inner.cause
+                .as_ref()
+                .and_then(|cause| cause.downcast_ref::<io::Error>())
+                .map_or(false, |err| match err.kind() {
+                    io::ErrorKind::BrokenPipe |
+                    io::ErrorKind::ConnectionAborted |
+                    io::ErrorKind::ConnectionReset |
+                    io::ErrorKind::UnexpectedEof => true,
+                    _ => false,
+                }),
+            _ => false,
+        }
+    }
+
     pub(crate) fn new(kind: Kind, cause: Option<Cause>) -> Error {
         Error {
             inner: Box::new(ErrorImpl {
@@ -134,55 +295,96 @@ impl Error {
         }
     }
 
+    #[cfg(any(feature = "client", feature = "server"))]
     pub(crate) fn kind(&self) -> &Kind {
         &self.inner.kind
     }
 
+    #[cfg(feature = "client")]
     pub(crate) fn new_canceled<E: Into<Cause>>(cause: Option<E>) -> Error {
         Error::new(Kind::Canceled, cause.map(Into::into))
     }
 
+    #[cfg(any(feature = "client", all(feature = "http1", feature = "server")))]
     pub(crate) fn new_incomplete() -> Error {
         Error::new(Kind::Incomplete, None)
     }
 
+    #[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
     pub(crate) fn new_too_large() -> Error {
         Error::new(Kind::Parse(Parse::TooLarge), None)
     }
 
+    #[cfg(all(feature = "http1", feature = "server"))]
     pub(crate) fn new_header() -> Error {
         Error::new(Kind::Parse(Parse::Header), None)
     }
 
+    #[cfg(all(feature = "http1", feature = "server"))]
     pub(crate) fn new_status() -> Error {
         Error::new(Kind::Parse(Parse::Status), None)
     }
 
+    #[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
     pub(crate) fn new_version_h2() -> Error {
         Error::new(Kind::Parse(Parse::VersionH2), None)
     }
 
+    #[cfg(all(feature = "http1", feature = "client"))]
     pub(crate) fn new_mismatched_response() -> Error {
         Error::new(Kind::MismatchedResponse, None)
     }
 
+    #[cfg(all(feature = "http1", feature = "client"))]
+    pub(crate) fn new_upgrade<E: Into<Cause>>(cause: E) -> Error {
+        Error::new(Kind::Upgrade, Some(cause.into()))
+    }
+
+    #[cfg(any(feature = "client", all(feature = "http1", feature = "server")))]
     pub(crate) fn new_io(cause: io::Error) -> Error {
         Error::new(Kind::Io, Some(cause.into()))
     }
 
-    #[cfg(feature = "runtime")]
+    #[cfg(all(feature = "runtime", feature = "server"))]
     pub(crate) fn new_listen<E: Into<Cause>>(cause: E) -> Error {
         Error::new(Kind::Listen, Some(cause.into()))
     }
 
+    #[cfg(feature = "server")]
     pub(crate) fn new_accept<E: Into<Cause>>(cause: E) -> Error {
         Error::new(Kind::Accept, Some(cause.into()))
     }
 
+    #[cfg(feature = "client")]
     pub(crate) fn new_connect<E: Into<Cause>>(cause: E) -> Error {
         Error::new(Kind::Connect, Some(cause.into()))
     }
 
+    #[cfg(all(feature = "runtime", feature = "client"))]
+    pub(crate) fn new_pool_timeout() -> Error {
+        Error::new(Kind::PoolTimeout, None)
+    }
+
+    #[cfg(all(feature = "runtime", any(feature = "client", feature = "server")))]
+    pub(crate) fn new_write_timeout() -> Error {
+        Error::new(Kind::WriteTimeout, None)
+    }
+
+    #[cfg(feature = "client")]
+    pub(crate) fn new_pool_full() -> Error {
+        Error::new(Kind::PoolFull, None)
+    }
+
+    #[cfg(feature = "client")]
+    pub(crate) fn new_pool_closed() -> Error {
+        Error::new(Kind::PoolClosed, None)
+    }
+
+    #[cfg(feature = "client")]
+    pub(crate) fn new_circuit_open() -> Error {
+        Error::new(Kind::CircuitOpen, None)
+    }
+
     pub(crate) fn new_closed() -> Error {
         Error::new(Kind::Closed, None)
     }
@@ -191,34 +393,61 @@ impl Error {
         Error::new(Kind::Body, Some(cause.into()))
     }
 
+    pub(crate) fn new_body_too_large() -> Error {
+        Error::new(Kind::BodyTooLarge, None)
+    }
+
+    #[cfg(any(feature = "client", feature = "server"))]
     pub(crate) fn new_body_write<E: Into<Cause>>(cause: E) -> Error {
         Error::new(Kind::BodyWrite, Some(cause.into()))
     }
 
+    #[cfg(any(feature = "client", all(feature = "server", not(feature = "http2"))))]
     pub(crate) fn new_user_unsupported_version() -> Error {
         Error::new(Kind::UnsupportedVersion, None)
     }
 
+    #[cfg(feature = "client")]
     pub(crate) fn new_user_unsupported_request_method() -> Error {
         Error::new(Kind::UnsupportedRequestMethod, None)
     }
 
+    #[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
+    pub(crate) fn new_user_header_value() -> Error {
+        Error::new(Kind::InvalidHeaderValue, None)
+    }
+
+    #[cfg(any(feature = "client", feature = "server"))]
+    pub(crate) fn new_user_unsupported_into_parts() -> Error {
+        Error::new(Kind::UnsupportedIntoParts, None)
+    }
+
+    #[cfg(feature = "server")]
+    pub(crate) fn new_user_informational() -> Error {
+        Error::new(Kind::InvalidInformational, None)
+    }
+
+    #[cfg(feature = "server")]
     pub(crate) fn new_user_new_service<E: Into<Cause>>(cause: E) -> Error {
         Error::new(Kind::NewService, Some(cause.into()))
     }
 
+    #[cfg(feature = "server")]
     pub(crate) fn new_user_service<E: Into<Cause>>(cause: E) -> Error {
         Error::new(Kind::Service, Some(cause.into()))
     }
 
+    #[cfg(any(feature = "client", feature = "server"))]
     pub(crate) fn new_user_body<E: Into<Cause>>(cause: E) -> Error {
         Error::new(Kind::BodyUser, Some(cause.into()))
     }
 
+    #[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
     pub(crate) fn new_shutdown(cause: io::Error) -> Error {
         Error::new(Kind::Shutdown, Some(Box::new(cause)))
     }
 
+    #[cfg(feature = "http2")]
     pub(crate) fn new_h2(cause: ::h2::Error) -> Error {
         Error::new(Kind::Http2, Some(Box::new(cause)))
     }
@@ -256,8 +485,13 @@ impl StdError for Error {
             Kind::Parse(Parse::UpgradeNotSupported) => "unsupported protocol upgrade",
             Kind::Incomplete => "message is incomplete",
             Kind::MismatchedResponse => "response received without matching request",
+            Kind::Upgrade => "upgrade response didn't accept the offered protocols",
             Kind::Closed => "connection closed",
             Kind::Connect => "an error occurred trying to connect",
+            Kind::PoolTimeout => "timed out waiting for a connection from the pool",
+            Kind::PoolFull => "connection limit of the pool reached",
+            Kind::PoolClosed => "connection pool was closed",
+            Kind::CircuitOpen => "circuit breaker open after failed requests to the host",
             Kind::Canceled => "an operation was canceled internally before starting",
             #[cfg(feature = "runtime")]
             Kind::Listen => "error creating server listener",
@@ -267,10 +501,15 @@ impl StdError for Error {
             Kind::Body => "error reading a body from connection",
             Kind::BodyWrite => "error write a body to connection",
             Kind::BodyUser => "error from user's Payload stream",
+            Kind::BodyTooLarge => "body exceeded its maximum size",
             Kind::Shutdown => "error shutting down connection",
+            Kind::WriteTimeout => "timed out writing to a stalled connection",
             Kind::Http2 => "http2 general error",
             Kind::UnsupportedVersion => "request has unsupported HTTP version",
             Kind::UnsupportedRequestMethod => "request has unsupported HTTP method",
+            Kind::InvalidHeaderValue => "header value contains CR, LF, or NUL",
+            Kind::UnsupportedIntoParts => "only HTTP/1 connections can be taken apart",
+            Kind::InvalidInformational => "interim response must have a 1xx status other than 101",
 
             Kind::Io => "an IO error occurred",
         }
@@ -329,6 +568,17 @@ trait AssertSendSync: Send + Sync + 'static {}
 #[doc(hidden)]
 impl AssertSendSync for Error {}
 
-#[cfg(test)]
+#[cfg(all(test, feature = "http2", feature = "client"))]
 mod tests {
+    use super::Error;
+
+    #[test]
+    fn http2_error_code() {
+        let err = Error::new_h2(::h2::Reason::ENHANCE_YOUR_CALM.into());
+        assert_eq!(err.http2_error_code(), Some(0xb));
+        assert!(err.is_enhance_your_calm());
+
+        let err = Error::new_incomplete();
+        assert_eq!(err.http2_error_code(), None);
+    }
 }
@@ -1,22 +1,35 @@
+#[cfg(all(feature = "http1", feature = "client"))]
 use std::fmt::Write;
 
+#[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
+use bytes::Bytes;
+#[cfg(all(feature = "http1", feature = "client"))]
 use bytes::BytesMut;
+#[cfg(all(feature = "http1", feature = "client"))]
 use http::HeaderMap;
+#[cfg(all(feature = "http1", feature = "client"))]
 use http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
-use http::header::{HeaderValue, OccupiedEntry, ValueIter};
+#[cfg(any(feature = "client", all(feature = "http1", feature = "server")))]
+use http::header::HeaderValue;
+#[cfg(all(feature = "http1", feature = "client"))]
+use http::header::{OccupiedEntry, ValueIter};
 
 /// Maximum number of bytes needed to serialize a u64 into ASCII decimal.
+#[cfg(all(feature = "http1", feature = "client"))]
 const MAX_DECIMAL_U64_BYTES: usize = 20;
 
+#[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
 pub fn connection_keep_alive(value: &HeaderValue) -> bool {
     connection_has(value, "keep-alive")
 }
 
+#[cfg(any(feature = "client", all(feature = "http1", feature = "server")))]
 pub fn connection_close(value: &HeaderValue) -> bool {
     connection_has(value, "close")
 }
 
-fn connection_has(value: &HeaderValue, needle: &str) -> bool {
+#[cfg(any(feature = "client", all(feature = "http1", feature = "server")))]
+pub fn connection_has(value: &HeaderValue, needle: &str) -> bool {
     if let Ok(s) = value.to_str() {
         for val in s.split(',') {
             if eq_ascii(val.trim(), needle) {
@@ -27,6 +40,28 @@ fn connection_has(value: &HeaderValue, needle: &str) -> bool {
     false
 }
 
+/// Parse the `max` and `timeout` parameters of a legacy `Keep-Alive`
+/// header, such as `timeout=5, max=100`.
+#[cfg(feature = "client")]
+pub fn keep_alive_parse(value: &HeaderValue) -> (Option<u64>, Option<u64>) {
+    let mut max = None;
+    let mut timeout = None;
+    if let Ok(s) = value.to_str() {
+        for param in s.split(',') {
+            let mut parts = param.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let value = parts.next().and_then(|v| v.trim().parse().ok());
+            if eq_ascii(name, "max") {
+                max = value;
+            } else if eq_ascii(name, "timeout") {
+                timeout = value;
+            }
+        }
+    }
+    (max, timeout)
+}
+
+#[cfg(all(feature = "http1", feature = "server"))]
 pub fn content_length_parse(value: &HeaderValue) -> Option<u64> {
     value
         .to_str()
@@ -34,10 +69,71 @@ pub fn content_length_parse(value: &HeaderValue) -> Option<u64> {
         .and_then(|s| s.parse().ok())
 }
 
+/// Parse a `Content-Range` of `bytes first-last/complete`, where the
+/// complete length may be `*`.
+#[cfg(feature = "client")]
+pub fn content_range_parse(value: &HeaderValue) -> Option<(u64, u64, Option<u64>)> {
+    let s = match value.to_str() {
+        Ok(s) => s.trim(),
+        Err(_) => return None,
+    };
+    if !s.starts_with("bytes ") {
+        return None;
+    }
+    let mut range_len = s["bytes ".len()..].splitn(2, '/');
+    let (range, len) = match (range_len.next(), range_len.next()) {
+        (Some(range), Some(len)) => (range, len.trim()),
+        _ => return None,
+    };
+    let mut first_last = range.splitn(2, '-');
+    let (first, last) = match (first_last.next(), first_last.next()) {
+        (Some(first), Some(last)) => match (first.trim().parse(), last.trim().parse()) {
+            (Ok(first), Ok(last)) if first <= last => (first, last),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let complete_length = if len == "*" {
+        None
+    } else {
+        match len.parse() {
+            Ok(len) => Some(len),
+            Err(_) => return None,
+        }
+    };
+    Some((first, last, complete_length))
+}
+
+/// Get the `boundary` parameter of a `multipart/byteranges` content type.
+#[cfg(feature = "client")]
+pub fn multipart_byteranges_boundary(value: &HeaderValue) -> Option<String> {
+    let s = match value.to_str() {
+        Ok(s) => s,
+        Err(_) => return None,
+    };
+    let mut params = s.split(';');
+    match params.next() {
+        Some(mime) if eq_ascii(mime.trim(), "multipart/byteranges") => (),
+        _ => return None,
+    }
+    for param in params {
+        let mut kv = param.splitn(2, '=');
+        if let (Some(name), Some(value)) = (kv.next(), kv.next()) {
+            let value = value.trim().trim_matches('"');
+            if eq_ascii(name.trim(), "boundary") && !value.is_empty() {
+                return Some(value.to_owned());
+            }
+        }
+    }
+    None
+}
+
+#[cfg(all(feature = "http1", feature = "client"))]
 pub fn content_length_parse_all(headers: &HeaderMap) -> Option<u64> {
     content_length_parse_all_values(headers.get_all(CONTENT_LENGTH).into_iter())
 }
 
+#[cfg(all(feature = "http1", feature = "client"))]
 pub fn content_length_parse_all_values(values: ValueIter<HeaderValue>) -> Option<u64> {
     // If multiple Content-Length headers were sent, everything can still
     // be alright if they all contain the same value, and all parse
@@ -68,6 +164,7 @@ pub fn content_length_parse_all_values(values: ValueIter<HeaderValue>) -> Option
     }
 }
 
+#[cfg(all(feature = "http1", feature = "client"))]
 pub fn content_length_value(len: u64) -> HeaderValue {
     let mut len_buf = BytesMut::with_capacity(MAX_DECIMAL_U64_BYTES);
     write!(len_buf, "{}", len)
@@ -78,10 +175,12 @@ pub fn content_length_value(len: u64) -> HeaderValue {
     }
 }
 
+#[cfg(all(feature = "http1", feature = "client"))]
 pub fn transfer_encoding_is_chunked(headers: &HeaderMap) -> bool {
     is_chunked(headers.get_all(TRANSFER_ENCODING).into_iter())
 }
 
+#[cfg(all(feature = "http1", feature = "client"))]
 pub fn is_chunked(mut encodings: ValueIter<HeaderValue>) -> bool {
     // chunked must always be the last encoding, according to spec
     if let Some(line) = encodings.next_back() {
@@ -91,6 +190,7 @@ pub fn is_chunked(mut encodings: ValueIter<HeaderValue>) -> bool {
     false
 }
 
+#[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
 pub fn is_chunked_(value: &HeaderValue) -> bool {
     // chunked must always be the last encoding, according to spec
     if let Ok(s) = value.to_str() {
@@ -102,6 +202,7 @@ pub fn is_chunked_(value: &HeaderValue) -> bool {
     false
 }
 
+#[cfg(all(feature = "http1", feature = "client"))]
 pub fn add_chunked(mut entry: OccupiedEntry<HeaderValue>) {
     const CHUNKED: &'static str = "chunked";
 
@@ -121,7 +222,41 @@ pub fn add_chunked(mut entry: OccupiedEntry<HeaderValue>) {
     entry.insert(HeaderValue::from_static(CHUNKED));
 }
 
-fn eq_ascii(left: &str, right: &str) -> bool {
+/// Returns true if the value contains bytes that would let it break out of
+/// its header line: `CR`, `LF`, or `NUL`.
+#[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
+pub fn has_line_breaking_bytes(value: &HeaderValue) -> bool {
+    value.as_bytes().iter().any(is_line_breaking)
+}
+
+/// Replace any `CR`, `LF`, or `NUL` bytes in the value with spaces.
+#[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
+pub fn sanitize_line_breaking_bytes(value: &HeaderValue) -> HeaderValue {
+    let bytes = replace_line_breaking_bytes(value.as_bytes());
+    // safe because the only bytes changed were replaced with spaces, and
+    // the rest were already in a HeaderValue
+    let mut sanitized = unsafe {
+        HeaderValue::from_shared_unchecked(Bytes::from(bytes))
+    };
+    sanitized.set_sensitive(value.is_sensitive());
+    sanitized
+}
+
+#[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
+fn replace_line_breaking_bytes(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .map(|b| if is_line_breaking(b) { b' ' } else { *b })
+        .collect()
+}
+
+#[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
+#[inline]
+fn is_line_breaking(b: &u8) -> bool {
+    *b == b'\r' || *b == b'\n' || *b == 0
+}
+
+pub fn eq_ascii(left: &str, right: &str) -> bool {
     // As of Rust 1.23, str gained this method inherently, and so the
     // compiler says this trait is unused.
     //
@@ -134,6 +269,35 @@ fn eq_ascii(left: &str, right: &str) -> bool {
 
 #[cfg(test)]
 mod tests {
+    #[cfg(all(feature = "http1", any(feature = "client", feature = "server")))]
+    #[test]
+    fn replace_line_breaking_bytes() {
+        assert_eq!(super::replace_line_breaking_bytes(b"a\r\nb\0c"), b"a  b c");
+        assert_eq!(super::replace_line_breaking_bytes(b"a\tb"), b"a\tb");
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn content_range_parse() {
+        use http::header::HeaderValue;
+        let parse = |s| super::content_range_parse(&HeaderValue::from_static(s));
+        assert_eq!(parse("bytes 0-4/26"), Some((0, 4, Some(26))));
+        assert_eq!(parse("bytes 20-25/*"), Some((20, 25, None)));
+        assert_eq!(parse("bytes 5-4/26"), None);
+        assert_eq!(parse("bytes */26"), None);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn keep_alive_parse() {
+        use http::header::HeaderValue;
+        let parse = |s| super::keep_alive_parse(&HeaderValue::from_static(s));
+        assert_eq!(parse("timeout=5, max=100"), (Some(100), Some(5)));
+        assert_eq!(parse("Max = 3"), (Some(3), None));
+        assert_eq!(parse("timeout=soon, foo"), (None, None));
+    }
+
+    #[cfg(all(feature = "http1", feature = "client"))]
     #[test]
     fn assert_max_decimal_u64_bytes() {
         assert_eq!(
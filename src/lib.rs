@@ -15,24 +15,53 @@
 //!
 //! If just starting out, **check out the [Guides](https://hyper.rs/guides)
 //! first.**
+//!
+//! # Optional Features
+//!
+//! The client, the server, and each HTTP version can be left out of a build
+//! that doesn't need them, to compile less code and fewer dependencies. All
+//! of these features are enabled by default:
+//!
+//! - `client`: the [Client](client/index.html), and `client::conn`.
+//! - `server`: the [Server](server/index.html), and `server::conn`.
+//! - `http1`: HTTP/1 connections.
+//! - `http2`: HTTP/2 connections, with the `h2` dependency.
+//!
+//! The `client` and `server` features need at least one of `http1` and
+//! `http2`. A connection configured for an HTTP version that isn't enabled
+//! fails with an error.
+//!
+//! The `mmap` feature, which isn't enabled by default, adds
+//! [`body::MmapBody`](body/struct.MmapBody.html), to send memory-mapped
+//! files with the `memmap` dependency.
+//!
+//! The `testing` feature, which isn't enabled by default either, adds the
+//! [`testing`](testing/index.html) module, with a scripted server to test
+//! clients against, and faults to inject into connections. It needs the
+//! `runtime` feature.
 
 extern crate bytes;
 #[macro_use] extern crate futures;
 #[cfg(feature = "runtime")] extern crate futures_cpupool;
-extern crate h2;
+#[cfg(feature = "http2")] extern crate h2;
 extern crate http;
 extern crate httparse;
 extern crate iovec;
 #[macro_use] extern crate log;
+#[cfg(feature = "mmap")] extern crate memmap;
 #[cfg(feature = "runtime")] extern crate net2;
+#[cfg(feature = "https-native-tls")] extern crate native_tls;
+#[cfg(feature = "https-rustls")] extern crate rustls;
 extern crate time;
 #[cfg(feature = "runtime")] extern crate tokio;
 #[cfg(feature = "runtime")] extern crate tokio_executor;
-#[macro_use] extern crate tokio_io;
+#[cfg_attr(any(feature = "client", feature = "server"), macro_use)] extern crate tokio_io;
 #[cfg(feature = "runtime")] extern crate tokio_reactor;
 #[cfg(feature = "runtime")] extern crate tokio_tcp;
 #[cfg(feature = "runtime")] extern crate tokio_timer;
 extern crate want;
+#[cfg(feature = "https-rustls")] extern crate webpki;
+#[cfg(feature = "https-rustls")] extern crate webpki_roots;
 
 #[cfg(all(test, feature = "nightly"))]
 extern crate test;
@@ -48,20 +77,32 @@ pub use http::{
     Version,
 };
 
-pub use client::Client;
+#[cfg(feature = "client")] pub use client::Client;
 pub use error::{Result, Error};
 pub use body::{Body, Chunk};
-pub use server::Server;
+#[cfg(feature = "server")] pub use server::Server;
+
+#[cfg(all(any(feature = "client", feature = "server"), not(any(feature = "http1", feature = "http2"))))]
+compile_error!("the `client` and `server` features need `http1` or `http2` enabled");
 
 mod common;
-#[cfg(test)]
+// Test helpers for every role and protocol, so some go unused without all
+// of them.
+#[cfg(all(test, any(feature = "client", feature = "server")))]
+#[cfg_attr(not(all(feature = "client", feature = "server", feature = "http1", feature = "http2")), allow(dead_code))]
 mod mock;
 pub mod body;
 mod chunk;
-pub mod client;
+#[cfg(feature = "client")] pub mod client;
+#[cfg(all(feature = "http1", feature = "unstable-completion-io", any(feature = "client", feature = "server")))] pub mod completion;
 pub mod error;
 mod headers;
-mod proto;
-pub mod server;
+#[cfg(feature = "mmap")] mod mmap;
+#[cfg(all(feature = "http1", feature = "unstable-lowlevel"))] pub mod lowlevel;
+#[cfg(any(feature = "client", feature = "server"))] mod proto;
+#[cfg(all(feature = "client", feature = "server"))] pub mod proxy;
+#[cfg(feature = "server")] pub mod server;
 pub mod service;
 #[cfg(feature = "runtime")] pub mod rt;
+#[cfg(feature = "testing")] pub mod testing;
+pub mod upgrade;
@@ -0,0 +1,11 @@
+//! Low level pieces of hyper, for driving connections without its
+//! dispatchers, such as on runtimes with their own kind of IO.
+//!
+//! These are unstable, and only available with the `unstable-lowlevel`
+//! feature. They may change in any release.
+
+/// HTTP/1 codecs.
+pub mod h1 {
+    pub use proto::BodyLength;
+    pub use proto::h1::codec::{Client, Codec, Server};
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+use std::sync::Arc;
+
+use bytes::Buf;
+use futures::{Async, Poll};
+use memmap::Mmap;
+
+use body::Payload;
+
+// Large enough that a multi-gigabyte file is written with few chunks.
+const DEFAULT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// A `Payload` that sends the contents of a memory-mapped file.
+///
+/// The body is yielded as [`MmapChunk`](MmapChunk)s, which point into the
+/// mapped region instead of holding a copy of it. When an HTTP/1
+/// connection writes with vectored writes, which is the default for
+/// transports that support them, like a `TcpStream`, each chunk is written
+/// straight from the mapped memory, without being copied into the
+/// connection's write buffer. Otherwise it's copied there, like any other
+/// body.
+///
+/// The length of the body is known, so a `Content-Length` is sent for it,
+/// and it can be sent again by the `Client` if a connection closes before
+/// a response.
+///
+/// The mapped file should not be modified while it's being sent.
+///
+/// This type is only available with the `mmap` feature.
+pub struct MmapBody {
+    map: Arc<Mmap>,
+    pos: usize,
+    chunk_size: usize,
+}
+
+/// A piece of a [`MmapBody`](MmapBody), pointing into its mapped region.
+pub struct MmapChunk {
+    map: Arc<Mmap>,
+    pos: usize,
+    end: usize,
+}
+
+impl MmapBody {
+    /// Create a body of the whole mapped region, yielded in chunks of 4MB.
+    pub fn new(map: Mmap) -> MmapBody {
+        MmapBody::with_chunk_size(map, DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Create a body of the whole mapped region, yielded in chunks of up to
+    /// `chunk_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `chunk_size` is 0.
+    pub fn with_chunk_size(map: Mmap, chunk_size: usize) -> MmapBody {
+        assert!(chunk_size > 0, "MmapBody chunk_size cannot be 0");
+        MmapBody {
+            map: Arc::new(map),
+            pos: 0,
+            chunk_size,
+        }
+    }
+}
+
+impl Payload for MmapBody {
+    type Data = MmapChunk;
+    type Error = ::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<MmapChunk>, ::Error> {
+        if self.pos == self.map.len() {
+            return Ok(Async::Ready(None));
+        }
+        let end = ::std::cmp::min(self.pos + self.chunk_size, self.map.len());
+        let chunk = MmapChunk {
+            map: self.map.clone(),
+            pos: self.pos,
+            end,
+        };
+        self.pos = end;
+        Ok(Async::Ready(Some(chunk)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pos == self.map.len()
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        Some((self.map.len() - self.pos) as u64)
+    }
+
+    fn replay(&self) -> Option<MmapBody> {
+        Some(MmapBody {
+            map: self.map.clone(),
+            pos: self.pos,
+            chunk_size: self.chunk_size,
+        })
+    }
+}
+
+impl fmt::Debug for MmapBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MmapBody")
+            .field("len", &self.map.len())
+            .field("pos", &self.pos)
+            .field("chunk_size", &self.chunk_size)
+            .finish()
+    }
+}
+
+impl Buf for MmapChunk {
+    #[inline]
+    fn remaining(&self) -> usize {
+        self.end - self.pos
+    }
+
+    #[inline]
+    fn bytes(&self) -> &[u8] {
+        &self.map[self.pos..self.end]
+    }
+
+    #[inline]
+    fn advance(&mut self, cnt: usize) {
+        assert!(cnt <= self.remaining(), "cannot advance past the end of MmapChunk");
+        self.pos += cnt;
+    }
+}
+
+impl AsRef<[u8]> for MmapChunk {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.bytes()
+    }
+}
+
+impl fmt::Debug for MmapChunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MmapChunk")
+            .field("len", &self.remaining())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_mmap_body_chunks() {
+        let path = ::std::env::temp_dir().join("hyper-test-mmap-body");
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"hello mapped world").unwrap();
+        let map = unsafe { Mmap::map(&file).unwrap() };
+        drop(file);
+        fs::remove_file(&path).unwrap();
+
+        let mut body = MmapBody::with_chunk_size(map, 8);
+        assert_eq!(body.content_length(), Some(18));
+        let replay = body.replay().unwrap();
+
+        let mut chunks = Vec::new();
+        while let Async::Ready(Some(chunk)) = body.poll_data().unwrap() {
+            chunks.push(chunk.bytes().to_vec());
+        }
+        assert_eq!(chunks, vec![
+            b"hello ma".to_vec(),
+            b"pped wor".to_vec(),
+            b"ld".to_vec(),
+        ]);
+        assert!(body.is_end_stream());
+
+        let mut body = replay;
+        let mut chunk = match body.poll_data().unwrap() {
+            Async::Ready(Some(chunk)) => chunk,
+            other => panic!("unexpected poll_data: {:?}", other),
+        };
+        chunk.advance(6);
+        assert_eq!(chunk.remaining(), 2);
+        assert_eq!(chunk.as_ref(), b"ma");
+    }
+}
@@ -1,8 +1,8 @@
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 use std::collections::HashMap;
 use std::cmp;
 use std::io::{self, Read, Write};
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 use std::sync::{Arc, Mutex};
 
 use bytes::Buf;
@@ -10,7 +10,7 @@ use futures::{Async, Poll};
 use futures::task::{self, Task};
 use tokio_io::{AsyncRead, AsyncWrite};
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 use ::client::connect::{Connect, Connected, Destination};
 
 #[derive(Debug)]
@@ -287,26 +287,26 @@ impl ::std::ops::Deref for AsyncIo<MockCursor> {
     }
 }
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 pub struct Duplex {
     inner: Arc<Mutex<DuplexInner>>,
 }
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 struct DuplexInner {
     handle_read_task: Option<Task>,
     read: AsyncIo<MockCursor>,
     write: AsyncIo<MockCursor>,
 }
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 impl Read for Duplex {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.inner.lock().unwrap().read.read(buf)
     }
 }
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 impl Write for Duplex {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let mut inner = self.inner.lock().unwrap();
@@ -322,11 +322,11 @@ impl Write for Duplex {
     }
 }
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 impl AsyncRead for Duplex {
 }
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 impl AsyncWrite for Duplex {
     fn shutdown(&mut self) -> Poll<(), io::Error> {
         Ok(().into())
@@ -341,12 +341,12 @@ impl AsyncWrite for Duplex {
     }
 }
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 pub struct DuplexHandle {
     inner: Arc<Mutex<DuplexInner>>,
 }
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 impl DuplexHandle {
     pub fn read(&self, buf: &mut [u8]) -> Poll<usize, io::Error> {
         let mut inner = self.inner.lock().unwrap();
@@ -374,7 +374,7 @@ impl DuplexHandle {
     }
 }
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 impl Drop for DuplexHandle {
     fn drop(&mut self) {
         trace!("mock duplex handle drop");
@@ -384,12 +384,12 @@ impl Drop for DuplexHandle {
     }
 }
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 pub struct MockConnector {
     mocks: Mutex<HashMap<String, Vec<Duplex>>>,
 }
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 impl MockConnector {
     pub fn new() -> MockConnector {
         MockConnector {
@@ -425,7 +425,7 @@ impl MockConnector {
     }
 }
 
-#[cfg(feature = "runtime")]
+#[cfg(all(feature = "runtime", feature = "client"))]
 impl Connect for MockConnector {
     type Transport = Duplex;
     type Error = io::Error;
@@ -0,0 +1,445 @@
+use std::cmp;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{Async, Poll};
+use http::{HeaderMap, Method, Request, Response};
+
+use body::Chunk;
+use proto::{BodyLength, MessageHead, RequestLine};
+use super::{ClientUpgradeTransaction, Decode, Decoder, Encode, Encoder, Http1Transaction, Lenient, ParseContext, ServerTransaction};
+use super::io::MemRead;
+
+/// An HTTP/1 codec, parsing and encoding messages in buffers owned by the
+/// caller.
+///
+/// A `Codec` does no IO. It is fed the bytes read from a connection, and
+/// appends the bytes to write to it, so it can be driven by any kind of
+/// scheduler. It keeps only the state needed to frame the messages of a
+/// single connection: the body being read, the body being written, and
+/// whether the connection can be kept alive.
+///
+/// A `Codec<Server>` reads requests and writes responses, and a
+/// `Codec<Client>` writes requests and reads responses. Messages are read
+/// and written in order, it's up to the caller to answer each request
+/// before the next one, and to send a `100 Continue` if it wants one.
+pub struct Codec<R> {
+    cached_headers: Option<HeaderMap>,
+    method: Option<Method>,
+    decoder: Option<Decoder>,
+    encoder: Option<Encoder>,
+    keep_alive: bool,
+    preserve_header_case: bool,
+    preserve_header_order: bool,
+    title_case_headers: bool,
+    _role: PhantomData<fn(R)>,
+}
+
+/// The role of a `Codec` that reads requests and writes responses.
+#[derive(Debug)]
+pub enum Server {}
+
+/// The role of a `Codec` that writes requests and reads responses.
+#[derive(Debug)]
+pub enum Client {}
+
+// The bytes of a body, from a buffer owned by the caller.
+struct Buffer<'a> {
+    buf: &'a mut BytesMut,
+    is_eof: bool,
+}
+
+impl<R> Codec<R> {
+    fn new() -> Codec<R> {
+        Codec {
+            cached_headers: None,
+            method: None,
+            decoder: None,
+            encoder: None,
+            keep_alive: true,
+            preserve_header_case: false,
+            preserve_header_order: false,
+            title_case_headers: false,
+            _role: PhantomData,
+        }
+    }
+
+    /// Set whether header names are written with title case, instead of
+    /// lower case.
+    ///
+    /// Default is false.
+    pub fn set_title_case_headers(&mut self, enabled: bool) {
+        self.title_case_headers = enabled;
+    }
+
+    /// Set whether the original casing of header names is put in a
+    /// `HeaderCaseMap` in the extensions of the messages read.
+    ///
+    /// A `HeaderCaseMap` in the extensions of a message written is always
+    /// used.
+    ///
+    /// Default is false.
+    pub fn set_preserve_header_case(&mut self, enabled: bool) {
+        self.preserve_header_case = enabled;
+    }
+
+    /// Set whether the original order of the header lines is put in a
+    /// `HeaderOrder` in the extensions of the messages read.
+    ///
+    /// A `HeaderOrder` in the extensions of a message written is always
+    /// used.
+    ///
+    /// Default is false.
+    pub fn set_preserve_header_order(&mut self, enabled: bool) {
+        self.preserve_header_order = enabled;
+    }
+
+    /// Whether the connection can be used for another message, once the
+    /// current ones are read and written.
+    ///
+    /// When this is false, the connection should be closed after the
+    /// bytes written so far are flushed.
+    pub fn is_keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    /// Whether the body of the message read last has more to be read.
+    pub fn is_reading_body(&self) -> bool {
+        self.decoder.is_some()
+    }
+
+    /// Whether the body of the message written last has more to be written.
+    pub fn is_writing_body(&self) -> bool {
+        self.encoder.is_some()
+    }
+
+    /// Read the next chunk of the body of the message read last, from the
+    /// front of `buf`.
+    ///
+    /// Returns `NotReady` if more bytes need to be read into `buf` first,
+    /// and `None` once the body is done. `is_eof` tells that the connection
+    /// has nothing more to read, which ends a body delimited by the
+    /// connection closing, and is an error for any other.
+    pub fn read_body_chunk(&mut self, buf: &mut BytesMut, is_eof: bool) -> Poll<Option<Chunk>, ::Error> {
+        let bytes = match self.decoder {
+            Some(ref mut decoder) => {
+                let mut buf = Buffer {
+                    buf,
+                    is_eof,
+                };
+                try_ready!(decoder.decode(&mut buf).map_err(::Error::new_body))
+            },
+            None => return Ok(Async::Ready(None)),
+        };
+        if bytes.is_empty() {
+            self.decoder = None;
+            return Ok(Async::Ready(None));
+        }
+        Ok(Async::Ready(Some(Chunk::from(bytes))))
+    }
+
+    /// Append a chunk of the body of the message written last to `dst`.
+    ///
+    /// If the message can't have a body, such as a response to a `HEAD`
+    /// request, the chunk is discarded, as is anything past its
+    /// `Content-Length`.
+    pub fn write_body_chunk(&mut self, chunk: &[u8], dst: &mut Vec<u8>) {
+        if chunk.is_empty() {
+            return;
+        }
+        let is_eof = match self.encoder {
+            Some(ref mut encoder) => {
+                dst.put(encoder.encode(chunk));
+                encoder.is_eof()
+            },
+            None => {
+                trace!("discarding {}B of body that can't be written", chunk.len());
+                return;
+            }
+        };
+        if is_eof {
+            self.encoder = None;
+        }
+    }
+
+    /// End the body of the message written last, appending anything that
+    /// marks its end to `dst`.
+    ///
+    /// If the end of the body can only be told by closing the connection,
+    /// such as when less than its `Content-Length` was written, the
+    /// connection is no longer kept alive.
+    pub fn write_body_end(&mut self, dst: &mut Vec<u8>) {
+        if let Some(encoder) = self.encoder.take() {
+            match encoder.end::<io::Cursor<Bytes>>() {
+                Ok(end) => {
+                    if let Some(end) = end {
+                        dst.put(end);
+                    }
+                },
+                Err(_not_eof) => {
+                    trace!("body ended early, closing");
+                    self.keep_alive = false;
+                }
+            }
+        }
+    }
+
+    fn read_message<T>(&mut self, buf: &mut BytesMut) -> ::Result<Option<MessageHead<T::Incoming>>>
+    where
+        T: Http1Transaction,
+    {
+        loop {
+            let msg = match T::parse(buf, ParseContext {
+                cached_headers: &mut self.cached_headers,
+                req_method: &mut self.method,
+                max_headers: None,
+                preserve_header_case: self.preserve_header_case,
+                preserve_header_order: self.preserve_header_order,
+                lenient: Lenient::default(),
+            })? {
+                Some(msg) => msg,
+                None => return Ok(None),
+            };
+            let decoder = match msg.decode {
+                Decode::Normal(d) => d,
+                Decode::Final(d) => {
+                    trace!("final decoder, HTTP ending");
+                    self.keep_alive = false;
+                    d
+                },
+                // likely a 1xx message that we can ignore
+                Decode::Ignore => continue,
+            };
+            debug!("incoming body is {}", decoder);
+            self.keep_alive &= msg.keep_alive;
+            self.decoder = if decoder.is_eof() {
+                None
+            } else {
+                Some(decoder)
+            };
+            return Ok(Some(msg.head));
+        }
+    }
+
+    fn write_message<T>(&mut self, mut head: MessageHead<T::Outgoing>, body: Option<BodyLength>, dst: &mut Vec<u8>) -> ::Result<()>
+    where
+        T: Http1Transaction,
+    {
+        let encoder = T::encode(Encode {
+            head: &mut head,
+            body,
+            keep_alive: self.keep_alive,
+            req_method: &mut self.method,
+            title_case_headers: self.title_case_headers,
+        }, dst)?;
+        self.cached_headers = Some(head.headers);
+        if encoder.is_last() {
+            self.keep_alive = false;
+        }
+        self.encoder = if encoder.is_eof() {
+            None
+        } else {
+            Some(encoder)
+        };
+        Ok(())
+    }
+}
+
+impl Codec<Server> {
+    /// Create a codec for the server side of a connection.
+    pub fn server() -> Codec<Server> {
+        Codec::new()
+    }
+
+    /// Read the head of the next request from the front of `buf`.
+    ///
+    /// Returns `None` if `buf` doesn't hold a whole head yet. The bytes of
+    /// the head are removed from `buf`, while its body is left to
+    /// `read_body_chunk`. The size of `buf` is up to the caller, so it
+    /// should give up on a head that doesn't fit its limits.
+    pub fn read_head(&mut self, buf: &mut BytesMut) -> ::Result<Option<Request<()>>> {
+        let head = match self.read_message::<ServerTransaction>(buf)? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let mut req = Request::new(());
+        *req.method_mut() = head.subject.0;
+        *req.uri_mut() = head.subject.1;
+        *req.headers_mut() = head.headers;
+        *req.version_mut() = head.version;
+        if let Some(header_case) = head.header_case {
+            req.extensions_mut().insert(header_case);
+        }
+        if let Some(header_order) = head.header_order {
+            req.extensions_mut().insert(header_order);
+        }
+        Ok(Some(req))
+    }
+
+    /// Append the head of a response to `dst`.
+    ///
+    /// `body` is the length of the body to write after it, if any, used to
+    /// pick its `Content-Length` or chunked encoding.
+    pub fn write_head(&mut self, res: Response<()>, body: Option<BodyLength>, dst: &mut Vec<u8>) -> ::Result<()> {
+        ServerTransaction::update_date();
+        let (mut parts, ()) = res.into_parts();
+        let head = MessageHead {
+            version: parts.version,
+            subject: parts.status,
+            headers: parts.headers,
+            header_case: parts.extensions.remove(),
+            header_order: parts.extensions.remove(),
+            reason: parts.extensions.remove(),
+        };
+        self.write_message::<ServerTransaction>(head, body, dst)
+    }
+}
+
+impl Codec<Client> {
+    /// Create a codec for the client side of a connection.
+    pub fn client() -> Codec<Client> {
+        Codec::new()
+    }
+
+    /// Read the head of the response to the request written last, from the
+    /// front of `buf`.
+    ///
+    /// Returns `None` if `buf` doesn't hold a whole head yet. Informational
+    /// `1xx` responses are skipped, except for `101 Switching Protocols`,
+    /// after which the connection is no longer HTTP/1.
+    pub fn read_head(&mut self, buf: &mut BytesMut) -> ::Result<Option<Response<()>>> {
+        let head = match self.read_message::<ClientUpgradeTransaction>(buf)? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let mut res = Response::new(());
+        *res.status_mut() = head.subject;
+        *res.headers_mut() = head.headers;
+        *res.version_mut() = head.version;
+        if let Some(header_case) = head.header_case {
+            res.extensions_mut().insert(header_case);
+        }
+        if let Some(header_order) = head.header_order {
+            res.extensions_mut().insert(header_order);
+        }
+        if let Some(reason) = head.reason {
+            res.extensions_mut().insert(reason);
+        }
+        Ok(Some(res))
+    }
+
+    /// Append the head of a request to `dst`.
+    ///
+    /// `body` is the length of the body to write after it, if any, used to
+    /// pick its `Content-Length` or chunked encoding.
+    pub fn write_head(&mut self, req: Request<()>, body: Option<BodyLength>, dst: &mut Vec<u8>) -> ::Result<()> {
+        let (mut parts, ()) = req.into_parts();
+        let head = MessageHead {
+            version: parts.version,
+            subject: RequestLine(parts.method, parts.uri),
+            headers: parts.headers,
+            header_case: parts.extensions.remove(),
+            header_order: parts.extensions.remove(),
+            reason: None,
+        };
+        self.write_message::<ClientUpgradeTransaction>(head, body, dst)
+    }
+}
+
+impl<R> fmt::Debug for Codec<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Codec")
+            .field("decoder", &self.decoder)
+            .field("encoder", &self.encoder)
+            .field("keep_alive", &self.keep_alive)
+            .finish()
+    }
+}
+
+impl<'a> MemRead for Buffer<'a> {
+    fn read_mem(&mut self, len: usize) -> Poll<Bytes, io::Error> {
+        if self.buf.is_empty() && !self.is_eof {
+            return Ok(Async::NotReady);
+        }
+        let n = cmp::min(len, self.buf.len());
+        Ok(Async::Ready(self.buf.split_to(n).freeze()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use futures::Async;
+    use http::{Method, Request, Response, StatusCode};
+
+    use proto::BodyLength;
+    use super::Codec;
+
+    fn read<R>(codec: &mut Codec<R>, buf: &mut BytesMut, is_eof: bool) -> Async<Option<String>> {
+        codec.read_body_chunk(buf, is_eof)
+            .unwrap()
+            .map(|chunk| chunk.map(|chunk| String::from_utf8(chunk.to_vec()).unwrap()))
+    }
+
+    #[test]
+    fn server_reads_request_and_writes_response() {
+        let mut codec = Codec::server();
+        let mut buf = BytesMut::from(&b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel"[..]);
+
+        let req = codec.read_head(&mut buf).unwrap().expect("head");
+        assert_eq!(req.method(), Method::POST);
+        assert_eq!(req.uri(), "/a");
+        assert!(codec.is_reading_body());
+
+        assert_eq!(read(&mut codec, &mut buf, false), Async::Ready(Some("hel".into())));
+        assert_eq!(read(&mut codec, &mut buf, false), Async::NotReady);
+        buf.extend_from_slice(b"lo\r\n0\r\n\r\n");
+        assert_eq!(read(&mut codec, &mut buf, false), Async::Ready(Some("lo".into())));
+        assert_eq!(read(&mut codec, &mut buf, false), Async::Ready(None));
+        assert!(!codec.is_reading_body());
+        assert!(buf.is_empty());
+
+        let mut dst = Vec::new();
+        let mut res = Response::new(());
+        *res.status_mut() = StatusCode::CREATED;
+        codec.write_head(res, Some(BodyLength::Known(2)), &mut dst).unwrap();
+        codec.write_body_chunk(b"ok", &mut dst);
+        codec.write_body_end(&mut dst);
+        let dst = String::from_utf8(dst).unwrap();
+        assert!(dst.starts_with("HTTP/1.1 201 Created\r\ncontent-length: 2\r\n"), "{:?}", dst);
+        assert!(dst.ends_with("\r\n\r\nok"), "{:?}", dst);
+        assert!(codec.is_keep_alive());
+    }
+
+    #[test]
+    fn client_reads_response_to_head_without_body() {
+        let mut codec = Codec::client();
+        let mut req = Request::new(());
+        *req.method_mut() = Method::HEAD;
+        *req.uri_mut() = "/".parse().unwrap();
+        let mut dst = Vec::new();
+        codec.write_head(req, None, &mut dst).unwrap();
+        assert_eq!(dst, b"HEAD / HTTP/1.1\r\n\r\n");
+
+        let mut buf = BytesMut::from(&b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\n"[..]);
+        let res = codec.read_head(&mut buf).unwrap().expect("head");
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!codec.is_reading_body());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn eof_ends_close_delimited_body() {
+        let mut codec = Codec::client();
+        let mut dst = Vec::new();
+        codec.write_head(Request::new(()), None, &mut dst).unwrap();
+
+        let mut buf = BytesMut::from(&b"HTTP/1.1 200 OK\r\n\r\nhi"[..]);
+        codec.read_head(&mut buf).unwrap().expect("head");
+        assert_eq!(read(&mut codec, &mut buf, false), Async::Ready(Some("hi".into())));
+        assert_eq!(read(&mut codec, &mut buf, false), Async::NotReady);
+        assert_eq!(read(&mut codec, &mut buf, true), Async::Ready(None));
+    }
+}
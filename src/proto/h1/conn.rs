@@ -8,11 +8,16 @@ use http::{HeaderMap, Method, Version};
 use tokio_io::{AsyncRead, AsyncWrite};
 
 use ::Chunk;
+use common::Transfer;
+use headers;
 use proto::{BodyLength, MessageHead};
 use super::io::{Buffered};
-use super::{EncodedBuf, Encode, Encoder, Decode, Decoder, Http1Transaction, ParseContext};
+#[cfg(feature = "unstable-completion-io")]
+use super::io::OwnedIo;
+use super::{EncodedBuf, Encode, Encoder, Decode, Decoder, HeaderValuePolicy, Http1Transaction, Lenient, ParseContext};
 
 const H2_PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const CONTINUE: &'static [u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
 
 /// This handles a connection, which will have been established over an
 /// `AsyncRead + AsyncWrite` (like a socket), and will likely include multiple
@@ -37,12 +42,26 @@ where I: AsyncRead + AsyncWrite,
             io: Buffered::new(io),
             state: State {
                 cached_headers: None,
+                auto_continue: true,
+                chunk_extensions: false,
+                expecting_continue: false,
                 error: None,
+                half_close: true,
+                half_closed: false,
+                h2_preface: false,
+                header_value_policy: None,
+                invalid_header_values: 0,
                 keep_alive: KA::Busy,
+                legacy_keep_alive: true,
+                lenient: Lenient::default(),
+                max_headers: None,
                 method: None,
+                preserve_header_case: false,
+                preserve_header_order: false,
                 title_case_headers: false,
                 notify_read: false,
                 reading: Reading::Init,
+                rejected: None,
                 writing: Writing::Init,
                 // We assume a modern world where the remote speaks HTTP/1.1.
                 // If they tell us otherwise, we'll downgrade in `read_head`.
@@ -52,6 +71,7 @@ where I: AsyncRead + AsyncWrite,
         }
     }
 
+    #[cfg(feature = "server")]
     pub fn set_flush_pipeline(&mut self, enabled: bool) {
         self.io.set_flush_pipeline(enabled);
     }
@@ -60,14 +80,74 @@ where I: AsyncRead + AsyncWrite,
         self.io.set_max_buf_size(max);
     }
 
+    pub fn set_max_headers(&mut self, max: usize) {
+        self.state.max_headers = Some(max);
+    }
+
+    #[cfg(feature = "client")]
     pub fn set_write_strategy_flatten(&mut self) {
         self.io.set_write_strategy_flatten();
     }
 
+    #[cfg(feature = "unstable-completion-io")]
+    pub fn set_owned_io(&mut self, owned_io: OwnedIo<I>) {
+        self.io.set_owned_io(owned_io);
+    }
+
+    #[cfg(feature = "client")]
     pub fn set_title_case_headers(&mut self) {
         self.state.title_case_headers = true;
     }
 
+    pub fn set_preserve_header_case(&mut self) {
+        self.state.preserve_header_case = true;
+    }
+
+    pub fn set_preserve_header_order(&mut self) {
+        self.state.preserve_header_order = true;
+    }
+
+    #[cfg(feature = "server")]
+    pub fn disable_auto_continue(&mut self) {
+        self.state.auto_continue = false;
+    }
+
+    #[cfg(feature = "server")]
+    pub fn disable_half_close(&mut self) {
+        self.state.half_close = false;
+    }
+
+    #[cfg(feature = "server")]
+    pub fn set_h2_preface(&mut self) {
+        self.state.h2_preface = true;
+    }
+
+    pub fn set_keep_chunk_extensions(&mut self) {
+        self.state.chunk_extensions = true;
+    }
+
+    pub fn disable_legacy_keep_alive(&mut self) {
+        self.state.legacy_keep_alive = false;
+    }
+
+    pub fn set_lenient(&mut self, lenient: Lenient) {
+        self.state.lenient = lenient;
+    }
+
+    pub fn set_header_value_policy(&mut self, policy: HeaderValuePolicy) {
+        self.state.header_value_policy = Some(policy);
+    }
+
+    /// The counters of the connection, and of its latest exchange.
+    pub fn transfer(&self) -> Transfer {
+        self.io.transfer().clone()
+    }
+
+    /// The number of outgoing header values found with `CR`, `LF`, or `NUL`.
+    pub fn invalid_header_values(&self) -> usize {
+        self.state.invalid_header_values
+    }
+
     pub fn into_inner(self) -> (I, Bytes) {
         self.io.into_inner()
     }
@@ -114,14 +194,36 @@ where I: AsyncRead + AsyncWrite,
         read_buf.len() >= 24 && read_buf[..24] == *H2_PREFACE
     }
 
+    // The first bytes could be the start of the HTTP/2 preface, which
+    // HTTP/1 fails to parse before all of it is read. Wait for the rest,
+    // so it can be told apart from a request.
+    fn poll_h2_preface(&mut self) -> Poll<(), ::Error> {
+        while self.state.h2_preface {
+            let len = self.io.read_buf().len();
+            if len >= H2_PREFACE.len() || !H2_PREFACE.starts_with(self.io.read_buf()) {
+                break;
+            }
+            trace!("read {} bytes of a possible h2 preface", len);
+            if try_ready!(self.io.read_from_io().map_err(::Error::new_io)) == 0 {
+                break;
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+
     pub fn read_head(&mut self) -> Poll<Option<(MessageHead<T::Incoming>, bool)>, ::Error> {
         debug_assert!(self.can_read_head());
         trace!("Conn::read_head");
+        try_ready!(self.poll_h2_preface());
 
         loop {
             let msg = match self.io.parse::<T>(ParseContext {
                 cached_headers: &mut self.state.cached_headers,
                 req_method: &mut self.state.method,
+                max_headers: self.state.max_headers,
+                preserve_header_case: self.state.preserve_header_case,
+                preserve_header_order: self.state.preserve_header_order,
+                lenient: self.state.lenient,
             }) {
                 Ok(Async::Ready(msg)) => msg,
                 Ok(Async::NotReady) => return Ok(Async::NotReady),
@@ -146,8 +248,9 @@ where I: AsyncRead + AsyncWrite,
             };
 
             self.state.version = msg.head.version;
+            self.state.h2_preface = false;
             let head = msg.head;
-            let decoder = match msg.decode {
+            let mut decoder = match msg.decode {
                 Decode::Normal(d) => {
                     d
                 },
@@ -164,13 +267,23 @@ where I: AsyncRead + AsyncWrite,
             };
 
             debug!("incoming body is {}", decoder);
+            if self.state.chunk_extensions {
+                decoder.keep_extensions();
+            }
 
             self.state.busy();
             if msg.expect_continue {
-                let cont = b"HTTP/1.1 100 Continue\r\n\r\n";
-                self.io.headers_buf().extend_from_slice(cont);
+                if self.state.auto_continue {
+                    self.io.headers_buf().extend_from_slice(CONTINUE);
+                } else {
+                    trace!("waiting to be told to continue");
+                    self.state.expecting_continue = true;
+                }
             }
-            let wants_keep_alive = msg.keep_alive;
+            // An HTTP/1.0 peer may ask to keep the connection alive, unless
+            // that was disabled.
+            let wants_keep_alive = msg.keep_alive &&
+                (head.version != Version::HTTP_10 || self.state.legacy_keep_alive);
             self.state.keep_alive &= wants_keep_alive;
             let (body, reading) = if decoder.is_eof() {
                 (false, Reading::KeepAlive)
@@ -199,8 +312,19 @@ where I: AsyncRead + AsyncWrite,
                 match decoder.decode(&mut self.io) {
                     Ok(Async::Ready(slice)) => {
                         let (reading, chunk) = if !slice.is_empty() {
-                            return Ok(Async::Ready(Some(Chunk::from(slice))));
+                            let mut chunk = Chunk::from(slice);
+                            if let Some(ext) = decoder.take_extensions() {
+                                chunk.set_extensions(ext);
+                            }
+                            return Ok(Async::Ready(Some(chunk)));
                         } else if decoder.is_eof() {
+                            if let Some(ext) = decoder.take_extensions() {
+                                // The extensions of the last chunk come in
+                                // an empty `Chunk`, before the end.
+                                let mut chunk = Chunk::default();
+                                chunk.set_extensions(ext);
+                                return Ok(Async::Ready(Some(chunk)));
+                            }
                             debug!("incoming body completed");
                             (Reading::KeepAlive, None)
                         } else {
@@ -234,10 +358,45 @@ where I: AsyncRead + AsyncWrite,
 
         if !self.is_mid_message() {
             self.require_empty_read().map_err(::Error::new_io)?;
+        } else if T::should_read_first() {
+            self.mid_message_detect_eof()?;
         }
         Ok(())
     }
 
+    pub fn is_half_closed(&self) -> bool {
+        self.state.half_closed
+    }
+
+    // A server keeps reading while it answers a request, to notice the
+    // client closing its writing half. Anything else read is the next
+    // request, which stays buffered until its turn.
+    fn mid_message_detect_eof(&mut self) -> ::Result<()> {
+        if self.state.is_read_closed() || !self.io.read_buf().is_empty() {
+            return Ok(());
+        }
+        match self.io.read_from_io() {
+            Ok(Async::Ready(0)) => {
+                trace!("found EOF on busy connection: {:?}", self.state);
+                self.state.close_read();
+                self.state.half_closed = true;
+                if self.state.half_close {
+                    Ok(())
+                } else {
+                    debug!("client closed its writing half, closing connection");
+                    self.state.close_write();
+                    Err(::Error::new_incomplete())
+                }
+            },
+            Ok(Async::Ready(_)) |
+            Ok(Async::NotReady) => Ok(()),
+            Err(e) => {
+                self.state.close();
+                Err(::Error::new_io(e))
+            },
+        }
+    }
+
     fn is_mid_message(&self) -> bool {
         match (&self.state.reading, &self.state.writing) {
             (&Reading::Init, &Writing::Init) => false,
@@ -388,6 +547,31 @@ where I: AsyncRead + AsyncWrite,
         self.io.can_buffer()
     }
 
+    /// Write the `100 Continue` the incoming message expects, if it wasn't
+    /// already answered.
+    pub fn write_continue(&mut self) {
+        if !self.state.expecting_continue {
+            return;
+        }
+        self.state.expecting_continue = false;
+        if let Writing::Init = self.state.writing {
+            self.io.headers_buf().extend_from_slice(CONTINUE);
+        }
+    }
+
+    pub fn write_informational(&mut self, mut head: MessageHead<T::Outgoing>) {
+        // Once the final head is written, it's too late.
+        if let Writing::Init = self.state.writing {
+            if let Some(policy) = self.state.header_value_policy {
+                if self.enforce_header_values(&mut head, policy).is_err() {
+                    debug!("dropping interim response with invalid header values");
+                    return;
+                }
+            }
+            T::encode_informational(&mut head, self.io.headers_buf());
+        }
+    }
+
     pub fn write_head(&mut self, head: MessageHead<T::Outgoing>, body: Option<BodyLength>) {
         if let Some(encoder) = self.encode_head(head, body) {
             self.state.writing = if !encoder.is_eof() {
@@ -416,14 +600,49 @@ where I: AsyncRead + AsyncWrite,
         }
     }
 
-    fn encode_head(&mut self, mut head: MessageHead<T::Outgoing>, body: Option<BodyLength>) -> Option<Encoder> {
+    fn encode_head(&mut self, mut head: MessageHead<T::Outgoing>, mut body: Option<BodyLength>) -> Option<Encoder> {
         debug_assert!(self.can_write_head());
 
+        // A final response answers an `Expect: 100-continue` too.
+        self.state.expecting_continue = false;
+
+        if let Some(policy) = self.state.header_value_policy {
+            if let Err(err) = self.enforce_header_values(&mut head, policy) {
+                if policy == HeaderValuePolicy::RejectAndClose {
+                    self.state.disable_keep_alive();
+                }
+                match T::on_error(&err) {
+                    Some(msg) => {
+                        // server: send the automatic response in its place
+                        head = msg;
+                        body = None;
+                    },
+                    None if policy == HeaderValuePolicy::Reject => {
+                        // client: nothing was written, so only this
+                        // request fails
+                        self.state.rejected = Some(err);
+                        return None;
+                    },
+                    None => {
+                        // client: there's nothing to send in its place
+                        self.state.error = Some(err);
+                        self.state.close();
+                        return None;
+                    }
+                }
+            }
+        }
+
         if !T::should_read_first() {
             self.state.busy();
+            // A client's exchange starts with the request it writes.
+            self.io.start_exchange();
         }
 
         self.enforce_version(&mut head);
+        if head.version == Version::HTTP_10 && !self.state.legacy_keep_alive {
+            self.state.disable_keep_alive();
+        }
 
         let buf = self.io.headers_buf();
         match T::encode(Encode {
@@ -447,6 +666,37 @@ where I: AsyncRead + AsyncWrite,
         }
     }
 
+    // Header values built with the unchecked constructors can contain bytes
+    // that end the header line early. Look for them before they go to the
+    // wire, and either scrub them or refuse the message.
+    fn enforce_header_values(&mut self, head: &mut MessageHead<T::Outgoing>, policy: HeaderValuePolicy) -> ::Result<()> {
+        let mut found = 0;
+        for value in head.headers.values_mut() {
+            if headers::has_line_breaking_bytes(value) {
+                found += 1;
+                if policy == HeaderValuePolicy::Sanitize {
+                    *value = headers::sanitize_line_breaking_bytes(value);
+                }
+            }
+        }
+
+        if found == 0 {
+            return Ok(());
+        }
+
+        self.state.invalid_header_values += found;
+        warn!(
+            "outgoing message had {} header value(s) containing CR, LF, or NUL; policy = {:?}",
+            found,
+            policy,
+        );
+        if policy == HeaderValuePolicy::Sanitize {
+            Ok(())
+        } else {
+            Err(::Error::new_user_header_value())
+        }
+    }
+
     // If we know the remote speaks an older version, we try to fix up any messages
     // to work with our older peer.
     fn enforce_version(&mut self, head: &mut MessageHead<T::Outgoing>) {
@@ -465,14 +715,14 @@ where I: AsyncRead + AsyncWrite,
         }
     }
 
-    pub fn write_body(&mut self, chunk: B) {
+    pub fn write_body(&mut self, chunk: B, extensions: Option<Bytes>) {
         debug_assert!(self.can_write_body() && self.can_buffer_body());
         // empty chunks should be discarded at Dispatcher level
         debug_assert!(chunk.remaining() != 0);
 
         let state = match self.state.writing {
             Writing::Body(ref mut encoder) => {
-                self.io.buffer(encoder.encode(chunk));
+                self.io.buffer(encoder.encode_with_extensions(chunk, extensions));
 
                 if encoder.is_eof() {
                     if encoder.is_last() {
@@ -490,14 +740,14 @@ where I: AsyncRead + AsyncWrite,
         self.state.writing = state;
     }
 
-    pub fn write_body_and_end(&mut self, chunk: B) {
+    pub fn write_body_and_end(&mut self, chunk: B, extensions: Option<Bytes>) {
         debug_assert!(self.can_write_body() && self.can_buffer_body());
         // empty chunks should be discarded at Dispatcher level
         debug_assert!(chunk.remaining() != 0);
 
         let state = match self.state.writing {
             Writing::Body(ref encoder) => {
-                let can_keep_alive = encoder.encode_and_end(chunk, self.io.write_buf());
+                let can_keep_alive = encoder.encode_and_end(chunk, extensions, self.io.write_buf());
                 if can_keep_alive {
                     Writing::KeepAlive
                 } else {
@@ -510,6 +760,13 @@ where I: AsyncRead + AsyncWrite,
         self.state.writing = state;
     }
 
+    /// Set the extensions of the last chunk, if the body is chunked.
+    pub fn set_last_chunk_extensions(&mut self, extensions: Bytes) {
+        if let Writing::Body(ref mut encoder) = self.state.writing {
+            encoder.set_last_extensions(extensions);
+        }
+    }
+
     pub fn end_body(&mut self) {
         debug_assert!(self.can_write_body());
 
@@ -546,7 +803,7 @@ where I: AsyncRead + AsyncWrite,
 
         match self.state.writing {
             Writing::Init => {
-                if self.has_h2_prefix() {
+                if self.state.h2_preface && self.has_h2_prefix() {
                     return Err(::Error::new_version_h2())
                 }
                 if let Some(msg) = T::on_error(&err) {
@@ -591,6 +848,7 @@ where I: AsyncRead + AsyncWrite,
         self.state.close_write();
     }
 
+    #[cfg(any(feature = "server", feature = "runtime"))]
     pub fn disable_keep_alive(&mut self) {
         if self.state.is_idle() {
             self.state.close_read();
@@ -599,6 +857,12 @@ where I: AsyncRead + AsyncWrite,
         }
     }
 
+    /// Take the error of a request the header value policy refused to
+    /// write, leaving the connection able to write the next one.
+    pub fn take_rejected(&mut self) -> Option<::Error> {
+        self.state.rejected.take()
+    }
+
     pub fn take_error(&mut self) -> ::Result<()> {
         if let Some(err) = self.state.error.take() {
             Err(err)
@@ -608,7 +872,7 @@ where I: AsyncRead + AsyncWrite,
     }
 
     // Used in h1::dispatch tests
-    #[cfg(test)]
+    #[cfg(all(test, feature = "client"))]
     pub(super) fn io_mut(&mut self) -> &mut I {
         self.io.io_mut()
     }
@@ -624,24 +888,57 @@ impl<I, B: Buf, T> fmt::Debug for Conn<I, B, T> {
 }
 
 struct State {
+    /// Whether to write `100 Continue` as soon as a message expecting it is
+    /// read.
+    auto_continue: bool,
     /// Re-usable HeaderMap to reduce allocating new ones.
     cached_headers: Option<HeaderMap>,
+    /// Whether to keep the extensions of incoming chunks.
+    chunk_extensions: bool,
+    /// Whether the message being read expects a `100 Continue` that wasn't
+    /// written yet.
+    expecting_continue: bool,
     /// If an error occurs when there wasn't a direct way to return it
     /// back to the user, this is set.
     error: Option<::Error>,
+    /// Whether a response may still be written after the client closed its
+    /// writing half.
+    half_close: bool,
+    /// Whether the client closed its writing half while a message was in
+    /// flight.
+    half_closed: bool,
+    /// Whether the connection could still start with the HTTP/2 preface,
+    /// until a first message is read.
+    h2_preface: bool,
+    /// What to do with outgoing header values containing CR, LF, or NUL.
+    header_value_policy: Option<HeaderValuePolicy>,
+    /// How many such header values have been found on this connection.
+    invalid_header_values: usize,
     /// Current keep-alive status.
     keep_alive: KA,
+    /// Whether HTTP/1.0 messages may keep the connection alive.
+    legacy_keep_alive: bool,
+    /// Which malformed incoming heads are tolerated.
+    lenient: Lenient,
+    /// The most headers an incoming message may have.
+    max_headers: Option<usize>,
     /// If mid-message, the HTTP Method that started it.
     ///
     /// This is used to know things such as if the message can include
     /// a body or not.
     method: Option<Method>,
+    /// Whether to record the original casing of incoming header names.
+    preserve_header_case: bool,
+    preserve_header_order: bool,
     title_case_headers: bool,
     /// Set to true when the Dispatcher should poll read operations
     /// again. See the `maybe_notify` method for more.
     notify_read: bool,
     /// State of allowed reads
     reading: Reading,
+    /// The error of a request refused by the header value policy, for the
+    /// Dispatcher to fail it with.
+    rejected: Option<::Error>,
     /// State of allowed writes
     writing: Writing,
     /// Either HTTP/1.0 or 1.1 connection
@@ -1,5 +1,6 @@
 use std::error::Error as StdError;
 use std::fmt;
+use std::mem;
 use std::usize;
 use std::io;
 
@@ -17,6 +18,21 @@ use self::Kind::{Length, Chunked, Eof};
 #[derive(Clone, PartialEq)]
 pub struct Decoder {
     kind: Kind,
+    /// The extensions of chunks, if they are kept.
+    extensions: Option<ChunkExtensions>,
+}
+
+// The longest chunk extensions kept, so a peer can't make them use up
+// memory without sending any of the body.
+const MAX_EXTENSIONS_LEN: usize = 4096;
+
+#[derive(Clone, Default, PartialEq)]
+struct ChunkExtensions {
+    /// The extensions of the chunk size line being read.
+    line: Vec<u8>,
+    /// The extensions of the latest chunk, until its first bytes, or the
+    /// end of the body for the last chunk, are returned.
+    pending: Option<Bytes>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -41,6 +57,7 @@ enum Kind {
     /// > the final encoding, the message body length cannot be determined
     /// > reliably; the server MUST respond with the 400 (Bad Request)
     /// > status code and then close the connection.
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
     Eof(bool),
 }
 
@@ -62,19 +79,33 @@ impl Decoder {
     // constructors
 
     pub fn length(x: u64) -> Decoder {
-        Decoder { kind: Kind::Length(x) }
+        Decoder { kind: Kind::Length(x), extensions: None }
     }
 
     pub fn chunked() -> Decoder {
-        Decoder { kind: Kind::Chunked(ChunkedState::Size, 0) }
+        Decoder { kind: Kind::Chunked(ChunkedState::Size, 0), extensions: None }
     }
 
+    #[cfg(feature = "client")]
     pub fn eof() -> Decoder {
-        Decoder { kind: Kind::Eof(false) }
+        Decoder { kind: Kind::Eof(false), extensions: None }
     }
 
     // methods
 
+    /// Keep the extensions of chunks, instead of skipping them.
+    pub fn keep_extensions(&mut self) {
+        if let Kind::Chunked(..) = self.kind {
+            self.extensions = Some(ChunkExtensions::default());
+        }
+    }
+
+    /// Take the extensions of the chunk whose bytes were just returned by
+    /// `decode`, or of the last chunk once the body ended.
+    pub fn take_extensions(&mut self) -> Option<Bytes> {
+        self.extensions.as_mut().and_then(|ext| ext.pending.take())
+    }
+
     pub fn is_eof(&self) -> bool {
         match self.kind {
             Length(0) |
@@ -107,8 +138,16 @@ impl Decoder {
             Chunked(ref mut state, ref mut size) => {
                 loop {
                     let mut buf = None;
+                    let prev = *state;
                     // advances the chunked state
-                    *state = try_ready!(state.step(body, size, &mut buf));
+                    *state = try_ready!(state.step(body, size, &mut buf, self.extensions.as_mut().map(|ext| &mut ext.line)));
+                    if prev == ChunkedState::SizeLf {
+                        if let Some(ref mut ext) = self.extensions {
+                            if !ext.line.is_empty() {
+                                ext.pending = Some(Bytes::from(mem::replace(&mut ext.line, Vec::new())));
+                            }
+                        }
+                    }
                     if *state == ChunkedState::End {
                         trace!("end of chunked");
                         return Ok(Async::Ready(Bytes::new()));
@@ -167,13 +206,14 @@ impl ChunkedState {
     fn step<R: MemRead>(&self,
                         body: &mut R,
                         size: &mut u64,
-                        buf: &mut Option<Bytes>)
+                        buf: &mut Option<Bytes>,
+                        extensions: Option<&mut Vec<u8>>)
                         -> Poll<ChunkedState, io::Error> {
         use self::ChunkedState::*;
         match *self {
             Size => ChunkedState::read_size(body, size),
             SizeLws => ChunkedState::read_size_lws(body),
-            Extension => ChunkedState::read_extension(body),
+            Extension => ChunkedState::read_extension(body, extensions),
             SizeLf => ChunkedState::read_size_lf(body, *size),
             Body => ChunkedState::read_body(body, size, buf),
             BodyCr => ChunkedState::read_body_cr(body),
@@ -222,11 +262,22 @@ impl ChunkedState {
             }
         }
     }
-    fn read_extension<R: MemRead>(rdr: &mut R) -> Poll<ChunkedState, io::Error> {
+    fn read_extension<R: MemRead>(rdr: &mut R, extensions: Option<&mut Vec<u8>>) -> Poll<ChunkedState, io::Error> {
         trace!("read_extension");
         match byte!(rdr) {
             b'\r' => Ok(Async::Ready(ChunkedState::SizeLf)),
-            _ => Ok(Async::Ready(ChunkedState::Extension)), // no supported extensions
+            b => match extensions {
+                Some(extensions) => {
+                    if b == b'\n' || extensions.len() == MAX_EXTENSIONS_LEN {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                  "Invalid chunk extensions"));
+                    }
+                    extensions.push(b);
+                    Ok(Async::Ready(ChunkedState::Extension))
+                },
+                // skipped, unless they are kept
+                None => Ok(Async::Ready(ChunkedState::Extension)),
+            },
         }
     }
     fn read_size_lf<R: MemRead>(rdr: &mut R, size: u64) -> Poll<ChunkedState, io::Error> {
@@ -369,7 +420,7 @@ mod tests {
             let rdr = &mut s.as_bytes();
             let mut size = 0;
             loop {
-                let result = state.step(rdr, &mut size, &mut None);
+                let result = state.step(rdr, &mut size, &mut None, None);
                 let desc = format!("read_size failed for {:?}", s);
                 state = result.expect(desc.as_str()).unwrap();
                 if state == ChunkedState::Body || state == ChunkedState::EndCr {
@@ -384,7 +435,7 @@ mod tests {
             let rdr = &mut s.as_bytes();
             let mut size = 0;
             loop {
-                let result = state.step(rdr, &mut size, &mut None);
+                let result = state.step(rdr, &mut size, &mut None, None);
                 state = match result {
                     Ok(s) => s.unwrap(),
                     Err(e) => {
@@ -439,6 +490,33 @@ mod tests {
         assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
     }
 
+    #[test]
+    fn test_read_chunked_extensions() {
+        let mut bytes = &b"\
+            3;sig=abc\r\n\
+            foo\r\n\
+            3\r\n\
+            bar\r\n\
+            0;sig=def;last\r\n\
+            \r\n\
+        "[..];
+        let mut decoder = Decoder::chunked();
+        decoder.keep_extensions();
+        assert_eq!(decoder.decode(&mut bytes).unwrap().unwrap(), "foo");
+        assert_eq!(decoder.take_extensions().unwrap(), "sig=abc");
+        assert_eq!(decoder.decode(&mut bytes).unwrap().unwrap(), "bar");
+        assert_eq!(decoder.take_extensions(), None);
+        assert_eq!(decoder.decode(&mut bytes).unwrap().unwrap().len(), 0);
+        assert!(decoder.is_eof());
+        assert_eq!(decoder.take_extensions().unwrap(), "sig=def;last");
+
+        let mut bytes = &b"3;a\nb\r\nfoo\r\n"[..];
+        let mut decoder = Decoder::chunked();
+        decoder.keep_extensions();
+        let e = decoder.decode(&mut bytes).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+
     #[test]
     fn test_read_chunked_early_eof() {
         let mut bytes = &b"\
@@ -528,6 +606,7 @@ mod tests {
         all_async_cases(content, expected, Decoder::chunked());
     }
 
+    #[cfg(feature = "client")]
     #[test]
     fn test_read_eof_async() {
         let content = "foobar";
@@ -1,43 +1,134 @@
 use bytes::{Buf, Bytes};
-use futures::{Async, Future, Poll, Stream};
-use http::{Request, Response, StatusCode};
+use futures::{task, Async, Future, Poll};
+#[cfg(feature = "client")] use futures::Stream;
+use http::{Request, StatusCode};
+#[cfg(feature = "client")] use http::Response;
+#[cfg(feature = "server")] use http::HeaderMap;
+#[cfg(feature = "server")] use http::header::{CONNECTION, EXPECT, HOST, HeaderValue};
 use tokio_io::{AsyncRead, AsyncWrite};
 
-use body::{Body, Payload};
+use body::{Body, BufferLimit, ChunkSize, Payload};
 use body::internal::FullDataArg;
-use proto::{BodyLength, Conn, MessageHead, RequestHead, RequestLine, ResponseHead};
-use super::Http1Transaction;
-use service::Service;
+use chunk::Chunk;
+use common::{CancelToken, ConnectionStats, Transfer};
+#[cfg(feature = "client")] use common::SendWindow;
+use proto::{BodyLength, Conn, MessageHead, RequestHead};
+#[cfg(feature = "client")] use proto::{RequestLine, ResponseHead};
+use super::{h2c, Http1Transaction};
+#[cfg(feature = "unstable-completion-io")]
+use super::OwnedIo;
+#[cfg(feature = "server")] use server::conn::{ExpectContinue, Expectation, HalfClose, HostPolicy, Informational, MethodPolicy, UnknownExpectations};
+#[cfg(feature = "server")] use server::expect::unknown_expectations;
+#[cfg(feature = "server")] use server::request_id::{RequestId, RequestIds};
+#[cfg(feature = "server")] use server::upgrade::{OnRefusedUpgrade, UpgradeRefusal};
+#[cfg(feature = "server")] use http::{Method, Version};
+#[cfg(feature = "server")] use service::Service;
+#[cfg(feature = "client")] use client::{RequestConfig, RequestTarget};
+#[cfg(feature = "client")] use upgrade::Offer;
 
 pub(crate) struct Dispatcher<D, Bs: Payload, I, T> {
     conn: Conn<I, Bs::Data, T>,
     dispatch: D,
     body_tx: Option<::body::Sender>,
     body_rx: Option<Bs>,
+    body_limit: BufferLimit,
+    cancel_token: Option<CancelToken>,
+    // The rest of a chunk that didn't fit within the `body_limit`.
+    body_pending: Option<Chunk>,
+    chunk_size: ChunkSize,
     is_closing: bool,
+    // How many body bytes may be written in a single poll, before yielding
+    // to let other tasks on the executor run.
+    write_quota: Option<usize>,
+    written: usize,
 }
 
 pub(crate) trait Dispatch {
     type PollItem;
     type PollBody;
     type RecvItem;
-    fn poll_msg(&mut self) -> Poll<Option<(Self::PollItem, Self::PollBody)>, ::Error>;
-    fn recv_msg(&mut self, msg: ::Result<(Self::RecvItem, Body)>) -> ::Result<()>;
+    fn poll_msg(&mut self) -> Poll<Option<(Self::PollItem, Option<Self::PollBody>)>, ::Error>;
+    fn recv_msg(&mut self, msg: ::Result<(Self::RecvItem, Body, Transfer)>) -> ::Result<()>;
     fn poll_ready(&mut self) -> Poll<(), ()>;
     fn should_poll(&self) -> bool;
+    // Whether the message in flight was canceled, and the connection
+    // should be closed.
+    fn poll_canceled(&mut self) -> bool;
+    // Whether the message being received was told to continue, and a
+    // `100 Continue` should be written.
+    fn poll_continue(&mut self) -> bool;
+    // The next interim response to write before the message in flight.
+    fn poll_informational(&mut self) -> Option<Self::PollItem>;
+    // The peer closed its writing half while the message in flight is
+    // answered.
+    fn on_half_close(&mut self);
+    // Whether the IO goes on with another protocol once HTTP/1 is done, so
+    // it mustn't be shut down.
+    fn keeps_io(&self) -> bool;
 }
 
+#[cfg(feature = "server")]
 pub struct Server<S: Service> {
+    auto_continue: bool,
+    cancel_token: Option<CancelToken>,
+    // The status to respond with when the `Service` errors.
+    error_status: Option<StatusCode>,
+    // The handle of a request waiting to be told to continue.
+    expect_continue: Option<ExpectContinue>,
+    host_policy: Option<HostPolicy>,
+    // Tells requests if the client closed its writing half.
+    half_close: HalfClose,
+    // Whether a request can upgrade the connection to HTTP/2.
+    h2c_upgrade: bool,
+    // The request that upgraded the connection to HTTP/2, as the HTTP/2
+    // server reads it.
+    h2c_request: Option<h2c::Upgrade>,
     in_flight: Option<S::Future>,
+    // The handle the request in flight sends interim responses with.
+    informational: Option<Informational>,
+    // Whether every HTTP/1.1 request gets an `Informational`.
+    informational_enabled: bool,
+    // Whether the request in flight is a CONNECT, which a 2xx response
+    // turns into a tunnel.
+    is_connect: bool,
+    method_policy: Option<MethodPolicy>,
+    on_refused_upgrade: Option<OnRefusedUpgrade>,
+    refuse_upgrades: bool,
+    // The response to a request a policy didn't allow.
+    rejected: Option<MessageHead<StatusCode>>,
+    request_ids: Option<RequestIds>,
+    request_id: Option<RequestId>,
     pub(crate) service: S,
+    // What to do with requests expecting more than `100-continue`.
+    unknown_expectations: UnknownExpectations,
+    // Whether the connection is polled such that an upgraded IO can be
+    // taken back.
+    upgrade_claimed: bool,
 }
 
+#[cfg(feature = "client")]
 pub struct Client<B> {
-    callback: Option<::client::dispatch::Callback<Request<B>, Response<Body>>>,
+    callback: Option<ClientCallback<B>>,
+    // The `CancelToken` of the request in flight.
+    cancel_token: Option<CancelToken>,
+    // Whether the next request offers to upgrade to HTTP/2.
+    h2c_offer: bool,
+    // Whether the request in flight offered to upgrade to HTTP/2.
+    h2c_offered: bool,
+    // The callback of the request that upgraded the connection to HTTP/2,
+    // which is answered on the first stream.
+    h2c_callback: Option<ClientCallback<B>>,
+    // The form to write request-targets in, unless a request says otherwise.
+    request_target: Option<RequestTarget>,
     rx: ClientRx<B>,
+    // The protocols the request in flight offers to upgrade to.
+    upgrade_offer: Option<Offer>,
 }
 
+#[cfg(feature = "client")]
 type ClientRx<B> = ::client::dispatch::Receiver<Request<B>, Response<Body>>;
+#[cfg(feature = "client")]
+type ClientCallback<B> = ::client::dispatch::Callback<Request<B>, Response<Body>>;
 
 impl<D, Bs, I, T> Dispatcher<D, Bs, I, T>
 where
@@ -52,14 +143,63 @@ where
             dispatch: dispatch,
             body_tx: None,
             body_rx: None,
+            body_limit: BufferLimit::default(),
+            body_pending: None,
+            cancel_token: None,
+            chunk_size: ChunkSize::default(),
             is_closing: false,
+            write_quota: None,
+            written: 0,
         }
     }
 
+    #[cfg(any(feature = "server", feature = "runtime"))]
     pub fn disable_keep_alive(&mut self) {
         self.conn.disable_keep_alive()
     }
 
+    #[cfg(feature = "unstable-completion-io")]
+    pub fn set_owned_io(&mut self, owned_io: OwnedIo<I>) {
+        self.conn.set_owned_io(owned_io)
+    }
+
+    #[cfg(feature = "server")]
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel_token = Some(token);
+    }
+
+    #[cfg(feature = "server")]
+    pub fn set_write_quota(&mut self, bytes: usize) {
+        self.write_quota = Some(bytes);
+    }
+
+    pub fn set_chunk_size(&mut self, size: ChunkSize) {
+        self.chunk_size = size;
+    }
+
+    #[cfg(all(feature = "server", feature = "http2"))]
+    pub fn chunk_size(&self) -> ChunkSize {
+        self.chunk_size
+    }
+
+    #[cfg(feature = "client")]
+    pub fn set_body_buffer_limit(&mut self, limit: BufferLimit) {
+        self.body_limit = limit;
+    }
+
+    pub fn invalid_header_values(&self) -> usize {
+        self.conn.invalid_header_values()
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        self.conn.transfer().connection().clone()
+    }
+
+    #[cfg(any(feature = "server", feature = "runtime", feature = "http2"))]
+    pub fn dispatch_mut(&mut self) -> &mut D {
+        &mut self.dispatch
+    }
+
     pub fn into_inner(self) -> (I, Bytes, D) {
         let (io, buf) = self.conn.into_inner();
         (io, buf, self.dispatch)
@@ -91,8 +231,14 @@ where
 
     fn poll_inner(&mut self, should_shutdown: bool) -> Poll<(), ::Error> {
         T::update_date();
+        self.written = 0;
+        try_ready!(self.poll_close_canceled());
         loop {
             self.poll_read()?;
+            if self.dispatch.poll_continue() {
+                self.conn.write_continue();
+            }
+            self.poll_informational();
             self.poll_write()?;
             self.poll_flush()?;
 
@@ -108,9 +254,11 @@ where
                 break;
             }
         }
+        // A message written just now may have brought a new token.
+        try_ready!(self.poll_close_canceled());
 
         if self.is_done() {
-            if should_shutdown {
+            if should_shutdown && !self.dispatch.keeps_io() {
                 try_ready!(self.conn.shutdown().map_err(::Error::new_shutdown));
             }
             self.conn.take_error()?;
@@ -124,10 +272,10 @@ where
         loop {
             if self.is_closing {
                 return Ok(Async::Ready(()));
-            } else if self.conn.can_read_head() {
+            } else if self.conn.can_read_head() && self.body_pending.is_none() {
                 try_ready!(self.poll_read_head());
             } else if let Some(mut body) = self.body_tx.take() {
-                if self.conn.can_read_body() {
+                if self.conn.can_read_body() || self.body_pending.is_some() {
                     match body.poll_ready() {
                         Ok(Async::Ready(())) => (),
                         Ok(Async::NotReady) => {
@@ -137,18 +285,32 @@ where
                         Err(_canceled) => {
                             // user doesn't care about the body
                             // so we should stop reading
-                            trace!("body receiver dropped before eof, closing");
-                            self.conn.close_read();
+                            self.body_pending = None;
+                            if self.conn.can_read_body() {
+                                trace!("body receiver dropped before eof, closing");
+                                self.conn.close_read();
+                            }
                             return Ok(Async::Ready(()));
                         }
                     }
-                    match self.conn.read_body() {
-                        Ok(Async::Ready(Some(chunk))) => {
+                    let read = match self.body_pending.take() {
+                        Some(chunk) => Ok(Async::Ready(Some(chunk))),
+                        None => self.conn.read_body(),
+                    };
+                    match read {
+                        Ok(Async::Ready(Some(mut chunk))) => {
+                            match body.room() {
+                                Some(room) if chunk.len() > room => {
+                                    self.body_pending = Some(chunk.split_off(room));
+                                },
+                                _ => (),
+                            }
                             match body.send_data(chunk) {
                                 Ok(()) => {
                                     self.body_tx = Some(body);
                                 },
                                 Err(_canceled) => {
+                                    self.body_pending = None;
                                     if self.conn.can_read_body() {
                                         trace!("body receiver dropped before eof, closing");
                                         self.conn.close_read();
@@ -172,7 +334,11 @@ where
                     // just drop, the body will close automatically
                 }
             } else {
-                return self.conn.read_keep_alive().map(Async::Ready);
+                self.conn.read_keep_alive()?;
+                if self.conn.is_half_closed() {
+                    self.dispatch.on_half_close();
+                }
+                return Ok(Async::Ready(()));
             }
         }
     }
@@ -192,14 +358,15 @@ where
         match self.conn.read_head() {
             Ok(Async::Ready(Some((head, has_body)))) => {
                 let body = if has_body {
-                    let (mut tx, rx) = Body::channel();
+                    let (mut tx, mut rx) = Body::channel_with_limit(self.body_limit);
                     let _ = tx.poll_ready(); // register this task if rx is dropped
+                    rx.set_chunk_size(self.chunk_size);
                     self.body_tx = Some(tx);
                     rx
                 } else {
                     Body::empty()
                 };
-                self.dispatch.recv_msg(Ok((head, body)))?;
+                self.dispatch.recv_msg(Ok((head, body, self.conn.transfer())))?;
                 Ok(Async::Ready(()))
             },
             Ok(Async::Ready(None)) => {
@@ -218,18 +385,40 @@ where
         }
     }
 
+    fn poll_informational(&mut self) {
+        while let Some(head) = self.dispatch.poll_informational() {
+            self.conn.write_informational(head);
+        }
+    }
+
     fn poll_write(&mut self) -> Poll<(), ::Error> {
         loop {
             if self.is_closing {
                 return Ok(Async::Ready(()));
             } else if self.body_rx.is_none() && self.conn.can_write_head() && self.dispatch.should_poll() {
-                if let Some((head, mut body)) = try_ready!(self.dispatch.poll_msg()) {
+                if let Some((head, body)) = try_ready!(self.dispatch.poll_msg()) {
+                    // Interim responses sent while the service worked on
+                    // this one go first.
+                    self.poll_informational();
+                    let mut body = match body {
+                        Some(body) => body,
+                        None => {
+                            self.conn.write_head(head, None);
+                            if self.poll_rejected()? {
+                                continue;
+                            }
+                            return Ok(Async::Ready(()));
+                        }
+                    };
                     // Check if the body knows its full data immediately.
                     //
                     // If so, we can skip a bit of bookkeeping that streaming
                     // bodies need to do.
                     if let Some(full) = body.__hyper_full_data(FullDataArg(())).0 {
                         self.conn.write_full_msg(head, full);
+                        if self.poll_rejected()? {
+                            continue;
+                        }
                         return Ok(Async::Ready(()));
                     }
                     let body_type = if body.is_end_stream() {
@@ -243,12 +432,19 @@ where
                         btype
                     };
                     self.conn.write_head(head, body_type);
+                    self.poll_rejected()?;
                 } else {
                     self.close();
                     return Ok(Async::Ready(()));
                 }
             } else if !self.conn.can_buffer_body() {
                 try_ready!(self.poll_flush());
+            } else if self.body_rx.is_some() && self.is_write_quota_spent() {
+                // Other connections on this executor deserve a turn. Ask to
+                // be polled again, instead of waiting on the IO or body.
+                trace!("write quota spent, yielding");
+                task::current().notify();
+                return Ok(Async::NotReady);
             } else if let Some(mut body) = self.body_rx.take() {
                 if !self.conn.can_write_body() {
                     trace!(
@@ -259,21 +455,27 @@ where
                 }
                 match body.poll_data().map_err(::Error::new_user_body)? {
                     Async::Ready(Some(chunk)) => {
+                        self.written += chunk.remaining();
                         let eos = body.is_end_stream();
-                        if eos {
-                            if chunk.remaining() == 0 {
+                        let extensions = body.chunk_extensions(&chunk);
+                        if chunk.remaining() == 0 {
+                            if let Some(extensions) = extensions {
+                                trace!("keeping extensions of empty chunk for the last chunk");
+                                self.conn.set_last_chunk_extensions(extensions);
+                            } else {
                                 trace!("discarding empty chunk");
+                            }
+                            if eos {
                                 self.conn.end_body();
                             } else {
-                                self.conn.write_body_and_end(chunk);
+                                self.body_rx = Some(body);
+                                continue;
                             }
+                        } else if eos {
+                            self.conn.write_body_and_end(chunk, extensions);
                         } else {
                             self.body_rx = Some(body);
-                            if chunk.remaining() == 0 {
-                                trace!("discarding empty chunk");
-                                continue;
-                            }
-                            self.conn.write_body(chunk);
+                            self.conn.write_body(chunk, extensions);
                         }
                     },
                     Async::Ready(None) => {
@@ -290,6 +492,48 @@ where
         }
     }
 
+    // A request the header value policy refused to write fails on its
+    // own, and the next one may be written instead.
+    fn poll_rejected(&mut self) -> ::Result<bool> {
+        match self.conn.take_rejected() {
+            Some(err) => {
+                self.body_rx = None;
+                self.dispatch.recv_msg(Err(err))?;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    fn poll_close_canceled(&mut self) -> Poll<(), ::Error> {
+        if self.poll_canceled() {
+            // Whatever was already buffered is written out, and nothing
+            // more is read or written.
+            try_ready!(self.poll_flush());
+            debug!("connection canceled, closing");
+            self.close();
+        }
+        Ok(Async::Ready(()))
+    }
+
+    fn poll_canceled(&mut self) -> bool {
+        if self.is_closing {
+            return false;
+        }
+        let is_canceled = match self.cancel_token {
+            Some(ref token) => token.poll_canceled().is_ready(),
+            None => false,
+        };
+        is_canceled || self.dispatch.poll_canceled()
+    }
+
+    fn is_write_quota_spent(&self) -> bool {
+        match self.write_quota {
+            Some(quota) => self.written >= quota,
+            None => false,
+        }
+    }
+
     fn poll_flush(&mut self) -> Poll<(), ::Error> {
         self.conn.flush().map_err(|err| {
             debug!("error writing: {}", err);
@@ -307,6 +551,10 @@ where
         if self.is_closing {
             return true;
         }
+        if self.body_pending.is_some() {
+            // the rest of a body was already read, but not delivered yet
+            return false;
+        }
 
         let read_done = self.conn.is_read_closed();
 
@@ -338,20 +586,147 @@ where
     }
 }
 
+// The same check the request head is parsed with.
+#[cfg(feature = "server")]
+fn expects_continue(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(EXPECT)
+        .iter()
+        .last()
+        .map_or(false, |value| value.as_bytes() == b"100-continue")
+}
+
 // ===== impl Server =====
 
+#[cfg(feature = "server")]
 impl<S> Server<S> where S: Service {
     pub fn new(service: S) -> Server<S> {
         Server {
+            auto_continue: true,
+            cancel_token: None,
+            error_status: None,
+            expect_continue: None,
+            half_close: HalfClose::new(),
+            h2c_upgrade: false,
+            h2c_request: None,
+            host_policy: None,
             in_flight: None,
+            informational: None,
+            informational_enabled: false,
+            is_connect: false,
+            method_policy: None,
+            on_refused_upgrade: None,
+            refuse_upgrades: false,
+            rejected: None,
+            request_ids: None,
+            request_id: None,
             service: service,
+            unknown_expectations: UnknownExpectations::Ignore,
+            upgrade_claimed: false,
         }
     }
+
+    pub fn set_request_ids(&mut self, ids: RequestIds) {
+        self.request_ids = Some(ids);
+    }
+
     pub fn into_service(self) -> S {
         self.service
     }
+
+    pub fn set_host_policy(&mut self, policy: HostPolicy) {
+        self.host_policy = Some(policy);
+    }
+
+    pub fn set_method_policy(&mut self, policy: MethodPolicy) {
+        self.method_policy = Some(policy);
+    }
+
+    pub fn set_error_status(&mut self, status: StatusCode) {
+        self.error_status = Some(status);
+    }
+
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel_token = Some(token);
+    }
+
+    pub fn set_auto_continue(&mut self, enabled: bool) {
+        self.auto_continue = enabled;
+    }
+
+    pub fn set_informational(&mut self, enabled: bool) {
+        self.informational_enabled = enabled;
+    }
+
+    pub fn set_unknown_expectations(&mut self, expectations: UnknownExpectations) {
+        self.unknown_expectations = expectations;
+    }
+
+    pub fn set_refuse_upgrades(&mut self, enabled: bool) {
+        self.refuse_upgrades = enabled;
+    }
+
+    pub fn set_on_refused_upgrade(&mut self, hook: OnRefusedUpgrade) {
+        self.on_refused_upgrade = Some(hook);
+    }
+
+    pub fn set_upgrade_claimed(&mut self, claimed: bool) {
+        self.upgrade_claimed = claimed;
+    }
+
+    pub fn set_h2c_upgrade(&mut self, enabled: bool) {
+        self.h2c_upgrade = enabled;
+    }
+
+    #[cfg(feature = "http2")]
+    pub fn take_h2c_request(&mut self) -> Option<h2c::Upgrade> {
+        self.h2c_request.take()
+    }
+
+    #[cfg(feature = "http2")]
+    pub fn into_parts(self) -> (S, Option<RequestIds>, Option<MethodPolicy>, Option<StatusCode>, Option<CancelToken>) {
+        (self.service, self.request_ids, self.method_policy, self.error_status, self.cancel_token)
+    }
+
+    // Answers the request with `status`, instead of calling the service.
+    fn reject(&mut self, status: StatusCode, headers: HeaderMap) {
+        let mut head = MessageHead::default();
+        head.subject = status;
+        head.headers = headers;
+        if let (Some(ids), Some(id)) = (self.request_ids.as_ref(), self.request_id.take()) {
+            ids.on_response(id, &mut head.headers);
+        }
+        self.rejected = Some(head);
+    }
+
+    // Answers with a `500` instead of a `101`, or a 2xx to a CONNECT, that
+    // mustn't be sent, or a `101` that can't be, closing the connection if
+    // nothing can take the upgraded IO.
+    fn refuse_upgrade(&mut self, headers: &HeaderMap) -> Option<MessageHead<StatusCode>> {
+        let refusal = if self.refuse_upgrades {
+            UpgradeRefusal::Refused
+        } else if !self.upgrade_claimed {
+            UpgradeRefusal::Unclaimed
+        } else {
+            return None;
+        };
+        warn!("refusing 101 response to upgrade: {:?}", refusal);
+        if let Some(ref hook) = self.on_refused_upgrade {
+            hook.call(refusal, headers);
+        }
+        let mut head = MessageHead::default();
+        head.subject = StatusCode::INTERNAL_SERVER_ERROR;
+        if refusal == UpgradeRefusal::Unclaimed {
+            head.headers.insert(CONNECTION, HeaderValue::from_static("close"));
+        }
+        if let (Some(ids), Some(id)) = (self.request_ids.as_ref(), self.request_id.take()) {
+            ids.on_response(id, &mut head.headers);
+        }
+        Some(head)
+    }
 }
 
+#[cfg(feature = "server")]
 impl<S, Bs> Dispatch for Server<S>
 where
     S: Service<ReqBody=Body, ResBody=Bs>,
@@ -362,40 +737,146 @@ where
     type PollBody = Bs;
     type RecvItem = RequestHead;
 
-    fn poll_msg(&mut self) -> Poll<Option<(Self::PollItem, Self::PollBody)>, ::Error> {
-        if let Some(mut fut) = self.in_flight.take() {
-            let resp = match fut.poll().map_err(::Error::new_user_service)? {
-                Async::Ready(res) => res,
-                Async::NotReady => {
+    fn poll_msg(&mut self) -> Poll<Option<(Self::PollItem, Option<Self::PollBody>)>, ::Error> {
+        if let Some(head) = self.rejected.take() {
+            Ok(Async::Ready(Some((head, None))))
+        } else if let Some(mut fut) = self.in_flight.take() {
+            let resp = match fut.poll() {
+                Ok(Async::Ready(res)) => res,
+                Ok(Async::NotReady) => {
                     self.in_flight = Some(fut);
                     return Ok(Async::NotReady);
+                },
+                Err(e) => {
+                    let err = ::Error::new_user_service(e);
+                    let status = match self.error_status {
+                        Some(status) => status,
+                        None => return Err(err),
+                    };
+                    debug!("service error, responding with {}: {}", status, err);
+                    let mut head = MessageHead::default();
+                    head.subject = status;
+                    if let (Some(ids), Some(id)) = (self.request_ids.as_ref(), self.request_id.take()) {
+                        ids.on_response(id, &mut head.headers);
+                    }
+                    return Ok(Async::Ready(Some((head, None))));
                 }
             };
-            let (parts, body) = resp.into_parts();
+            let is_tunnel = self.is_connect && resp.status().is_success();
+            if resp.status() == StatusCode::SWITCHING_PROTOCOLS
+                || (is_tunnel && self.refuse_upgrades) {
+                if let Some(head) = self.refuse_upgrade(resp.headers()) {
+                    return Ok(Async::Ready(Some((head, None))));
+                }
+            } else if is_tunnel && !self.upgrade_claimed {
+                // The 2xx is still sent, but the connection is closed after
+                // its head, since there is no one to take the tunnel.
+                debug!("unclaimed CONNECT tunnel, closing after response");
+            }
+            let (mut parts, body) = resp.into_parts();
+            if let (Some(ids), Some(id)) = (self.request_ids.as_ref(), self.request_id.take()) {
+                ids.on_response(id, &mut parts.headers);
+            }
             let head = MessageHead {
                 version: parts.version,
                 subject: parts.status,
                 headers: parts.headers,
+                header_case: parts.extensions.remove(),
+                header_order: parts.extensions.remove(),
+                reason: parts.extensions.remove(),
             };
-            Ok(Async::Ready(Some((head, body))))
+            Ok(Async::Ready(Some((head, Some(body)))))
         } else {
             unreachable!("poll_msg shouldn't be called if no inflight");
         }
     }
 
-    fn recv_msg(&mut self, msg: ::Result<(Self::RecvItem, Body)>) -> ::Result<()> {
-        let (msg, body) = msg?;
+    fn recv_msg(&mut self, msg: ::Result<(Self::RecvItem, Body, Transfer)>) -> ::Result<()> {
+        let (msg, body, transfer) = msg?;
         let mut req = Request::new(body);
         *req.method_mut() = msg.subject.0;
         *req.uri_mut() = msg.subject.1;
         *req.headers_mut() = msg.headers;
         *req.version_mut() = msg.version;
+        if cfg!(feature = "http2") && self.h2c_upgrade && h2c::is_offer(&req) {
+            debug!("request offered h2c, upgrading");
+            // The request is answered as HTTP/2, after the `101`.
+            self.h2c_request = Some(h2c::encode_request(&req));
+            self.rejected = Some(h2c::switching_protocols());
+            return Ok(());
+        }
+        self.is_connect = *req.method() == Method::CONNECT;
+        if let Some(header_case) = msg.header_case {
+            req.extensions_mut().insert(header_case);
+        }
+        if let Some(header_order) = msg.header_order {
+            req.extensions_mut().insert(header_order);
+        }
+        transfer.set(req.extensions_mut());
+        req.extensions_mut().insert(self.half_close.clone());
+        if let Some(ref token) = self.cancel_token {
+            req.extensions_mut().insert(token.clone());
+        }
+        if !self.auto_continue && expects_continue(req.headers()) {
+            let expect = ExpectContinue::new();
+            req.extensions_mut().insert(expect.clone());
+            self.expect_continue = Some(expect);
+        }
+        if self.informational_enabled && req.version() == Version::HTTP_11 {
+            let informational = Informational::new();
+            req.extensions_mut().insert(informational.clone());
+            self.informational = Some(informational);
+        }
+        if let Some(ref ids) = self.request_ids {
+            self.request_id = Some(ids.on_request(&mut req));
+        }
+        let rejection = self.host_policy
+            .as_ref()
+            .and_then(|policy| policy.check(req.uri(), req.headers()));
+        if let Some(status) = rejection {
+            debug!("rejecting request with host not allowed: {:?}", req.headers().get(HOST));
+            self.reject(status, HeaderMap::new());
+            return Ok(());
+        }
+        let rejection = match self.method_policy {
+            Some(ref policy) if !policy.is_allowed(req.method()) => Some(policy.rejection_headers()),
+            _ => None,
+        };
+        if let Some(headers) = rejection {
+            debug!("rejecting request with method not allowed: {}", req.method());
+            self.reject(StatusCode::METHOD_NOT_ALLOWED, headers);
+            return Ok(());
+        }
+        // HTTP/1.0 doesn't define `Expect`, so it's ignored.
+        let unknown = match self.unknown_expectations {
+            UnknownExpectations::Ignore => Vec::new(),
+            _ if req.version() != Version::HTTP_11 => Vec::new(),
+            _ => unknown_expectations(req.headers()),
+        };
+        if !unknown.is_empty() {
+            if self.unknown_expectations == UnknownExpectations::Reject {
+                debug!("rejecting request with unknown expectations: {:?}", unknown);
+                self.reject(StatusCode::EXPECTATION_FAILED, HeaderMap::new());
+                return Ok(());
+            }
+            // Meeting an expectation may need interim responses, even if
+            // requests don't get them otherwise.
+            let informational = match self.informational {
+                Some(ref informational) => informational.clone(),
+                None => {
+                    let informational = Informational::new();
+                    self.informational = Some(informational.clone());
+                    informational
+                },
+            };
+            req.extensions_mut().insert(Expectation::new(unknown, informational));
+        }
         self.in_flight = Some(self.service.call(req));
         Ok(())
     }
 
     fn poll_ready(&mut self) -> Poll<(), ()> {
-        if self.in_flight.is_some() {
+        if self.should_poll() {
             Ok(Async::NotReady)
         } else {
             Ok(Async::Ready(()))
@@ -403,22 +884,97 @@ where
     }
 
     fn should_poll(&self) -> bool {
-        self.in_flight.is_some()
+        self.in_flight.is_some() || self.rejected.is_some()
+    }
+
+    fn poll_canceled(&mut self) -> bool {
+        false
+    }
+
+    fn poll_continue(&mut self) -> bool {
+        let continued = match self.expect_continue {
+            Some(ref expect) => expect.poll_continue(),
+            None => return false,
+        };
+        // Once the response is sent, it's too late to continue.
+        if continued || self.in_flight.is_none() {
+            self.expect_continue = None;
+        }
+        continued
+    }
+
+    fn poll_informational(&mut self) -> Option<Self::PollItem> {
+        let next = match self.informational {
+            Some(ref informational) => informational.poll_next(),
+            None => return None,
+        };
+        // Once the response is taken, anything sent after it is too late.
+        if next.is_none() && self.in_flight.is_none() {
+            if let Some(informational) = self.informational.take() {
+                informational.close();
+            }
+        }
+        next
+    }
+
+    fn on_half_close(&mut self) {
+        self.half_close.close();
+    }
+
+    fn keeps_io(&self) -> bool {
+        self.h2c_request.is_some()
     }
 }
 
 // ===== impl Client =====
 
 
+#[cfg(feature = "client")]
 impl<B> Client<B> {
     pub fn new(rx: ClientRx<B>) -> Client<B> {
         Client {
             callback: None,
+            cancel_token: None,
+            h2c_offer: false,
+            h2c_offered: false,
+            h2c_callback: None,
+            request_target: None,
             rx: rx,
+            upgrade_offer: None,
+        }
+    }
+
+    pub fn set_request_target(&mut self, target: RequestTarget) {
+        self.request_target = Some(target);
+    }
+
+    pub fn set_h2c_offer(&mut self) {
+        self.h2c_offer = true;
+    }
+
+    // The callback of the request the server accepted the offer to
+    // upgrade to HTTP/2 of.
+    #[cfg(feature = "http2")]
+    pub fn take_h2c_callback(&mut self) -> Option<ClientCallback<B>> {
+        self.h2c_callback.take()
+    }
+
+    #[cfg(feature = "http2")]
+    pub fn into_rx(self) -> ClientRx<B> {
+        self.rx
+    }
+
+    // Fail the request waiting for its response, with an error found
+    // outside of the dispatcher.
+    #[cfg(feature = "runtime")]
+    pub fn fail_in_flight(&mut self, err: ::Error) {
+        if let Some(cb) = self.callback.take() {
+            let _ = cb.send(Err((err, None)));
         }
     }
 }
 
+#[cfg(feature = "client")]
 impl<B> Dispatch for Client<B>
 where
     B: Payload,
@@ -427,7 +983,12 @@ where
     type PollBody = B;
     type RecvItem = ResponseHead;
 
-    fn poll_msg(&mut self) -> Poll<Option<(Self::PollItem, Self::PollBody)>, ::Error> {
+    fn poll_msg(&mut self) -> Poll<Option<(Self::PollItem, Option<Self::PollBody>)>, ::Error> {
+        if self.h2c_offered || self.h2c_callback.is_some() {
+            // Anything else is sent once it's known which protocol to
+            // send it with.
+            return Ok(Async::NotReady);
+        }
         match self.rx.poll() {
             Ok(Async::Ready(Some((req, mut cb)))) => {
                 // check that future hasn't been canceled already
@@ -437,14 +998,38 @@ where
                         Ok(Async::Ready(None))
                     },
                     Async::NotReady => {
-                        let (parts, body) = req.into_parts();
+                        let (mut parts, body) = req.into_parts();
+                        if let Some(window) = parts.extensions.get::<SendWindow>() {
+                            window.set_unlimited();
+                        }
+                        self.cancel_token = parts.extensions.get::<CancelToken>().cloned();
+                        // An invalid offer is sent as is, but a `101` can't
+                        // be checked against it.
+                        self.upgrade_offer = Offer::from_headers(&parts.headers).unwrap_or(None);
+                        if self.h2c_offer {
+                            self.h2c_offer = false;
+                            if body.is_end_stream() && h2c::can_offer(&parts.method, parts.version, &parts.headers) {
+                                h2c::write_offer(&mut parts.headers);
+                                self.h2c_offered = true;
+                            }
+                        }
+                        let target = parts.extensions
+                            .get::<RequestConfig>()
+                            .and_then(RequestConfig::target_form)
+                            .or(self.request_target);
+                        if let Some(target) = target {
+                            target.apply(&parts.method, &mut parts.uri, &parts.headers);
+                        }
                         let head = RequestHead {
                             version: parts.version,
                             subject: RequestLine(parts.method, parts.uri),
                             headers: parts.headers,
+                            header_case: parts.extensions.remove(),
+                            header_order: parts.extensions.remove(),
+                            reason: None,
                         };
                         self.callback = Some(cb);
-                        Ok(Async::Ready(Some((head, body))))
+                        Ok(Async::Ready(Some((head, Some(body)))))
                     }
                 }
             },
@@ -458,14 +1043,41 @@ where
         }
     }
 
-    fn recv_msg(&mut self, msg: ::Result<(Self::RecvItem, Body)>) -> ::Result<()> {
+    fn recv_msg(&mut self, msg: ::Result<(Self::RecvItem, Body, Transfer)>) -> ::Result<()> {
         match msg {
-            Ok((msg, body)) => {
+            Ok((msg, body, transfer)) => {
                 if let Some(cb) = self.callback.take() {
+                    let h2c_offered = self.h2c_offered;
+                    self.h2c_offered = false;
+                    if h2c_offered && msg.subject == StatusCode::SWITCHING_PROTOCOLS && h2c::is_upgrade(&msg.headers) {
+                        debug!("server accepted h2c, upgrading");
+                        // The response comes on the first HTTP/2 stream.
+                        self.h2c_callback = Some(cb);
+                        return Ok(());
+                    }
+                    if let Some(offer) = self.upgrade_offer.take() {
+                        if msg.subject == StatusCode::SWITCHING_PROTOCOLS {
+                            if let Err(err) = offer.verify_headers(&msg.headers) {
+                                debug!("101 response rejected: {}", err);
+                                let _ = cb.send(Err((::Error::new_upgrade(err), None)));
+                                return Ok(());
+                            }
+                        }
+                    }
                     let mut res = Response::new(body);
                     *res.status_mut() = msg.subject;
                     *res.headers_mut() = msg.headers;
                     *res.version_mut() = msg.version;
+                    if let Some(header_case) = msg.header_case {
+                        res.extensions_mut().insert(header_case);
+                    }
+                    if let Some(header_order) = msg.header_order {
+                        res.extensions_mut().insert(header_order);
+                    }
+                    if let Some(reason) = msg.reason {
+                        res.extensions_mut().insert(reason);
+                    }
+                    transfer.set(res.extensions_mut());
                     let _ = cb.send(Ok(res));
                     Ok(())
                 } else {
@@ -474,6 +1086,9 @@ where
             },
             Err(err) => {
                 if let Some(cb) = self.callback.take() {
+                    self.cancel_token = None;
+                    self.h2c_offered = false;
+                    self.upgrade_offer = None;
                     let _ = cb.send(Err((err, None)));
                     Ok(())
                 } else if let Ok(Async::Ready(Some((req, cb)))) = self.rx.poll() {
@@ -506,13 +1121,49 @@ where
     fn should_poll(&self) -> bool {
         self.callback.is_none()
     }
+
+    fn poll_canceled(&mut self) -> bool {
+        let is_canceled = match self.cancel_token {
+            Some(ref token) => token.poll_canceled().is_ready(),
+            None => false,
+        };
+        if !is_canceled {
+            if self.callback.is_none() {
+                // The response head was received, dropping its body is
+                // how the rest is aborted.
+                self.cancel_token = None;
+            }
+            return false;
+        }
+        if let Some(cb) = self.callback.take() {
+            debug!("request canceled, closing connection");
+            cb.send(Err((::Error::new_canceled(Some("request canceled")), None)));
+        }
+        true
+    }
+
+    fn poll_continue(&mut self) -> bool {
+        false
+    }
+
+    fn poll_informational(&mut self) -> Option<Self::PollItem> {
+        None
+    }
+
+    fn on_half_close(&mut self) {}
+
+    fn keeps_io(&self) -> bool {
+        self.h2c_callback.is_some()
+    }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client"))]
 mod tests {
     extern crate pretty_env_logger;
 
     use super::*;
+    #[cfg(feature = "server")] use std::cell::RefCell;
+    #[cfg(feature = "server")] use std::rc::Rc;
     use mock::AsyncIo;
     use proto::ClientTransaction;
 
@@ -568,4 +1219,79 @@ mod tests {
             Ok::<(), ()>(())
         }).wait().unwrap();
     }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn write_quota_yields_between_chunks() {
+        use proto::ServerTransaction;
+        use service::service_fn_ok;
+
+        let _ = pretty_env_logger::try_init();
+        ::futures::lazy(|| {
+            let peer = OneRequest::new(b"GET / HTTP/1.1\r\n\r\n");
+            let written = peer.written.clone();
+            let io = AsyncIo::new(peer, 1024);
+            let conn = Conn::<_, ::Chunk, ServerTransaction>::new(io);
+            let service = service_fn_ok(|_req| {
+                let chunks = vec![Ok::<_, ::Error>("hello"), Ok("world")];
+                ::Response::new(::Body::wrap_stream(::futures::stream::iter_result(chunks)))
+            });
+            let mut dispatcher = Dispatcher::new(Server::new(service), conn);
+            dispatcher.set_write_quota(5);
+
+            // Only the first chunk fits in the quota, even though the
+            // second one is already available.
+            assert!(dispatcher.poll().expect("poll 1").is_not_ready());
+            assert!(
+                written.borrow().ends_with(b"\r\n\r\n5\r\nhello\r\n"),
+                "first chunk written"
+            );
+
+            assert!(dispatcher.poll().expect("poll 2").is_not_ready());
+            assert!(
+                written.borrow().ends_with(b"\r\n\r\n5\r\nhello\r\n5\r\nworld\r\n"),
+                "second chunk written"
+            );
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    // An IO where the peer sends a single request, and then nothing.
+    #[cfg(feature = "server")]
+    #[derive(Debug)]
+    struct OneRequest {
+        request: &'static [u8],
+        written: Rc<RefCell<Vec<u8>>>,
+    }
+
+    #[cfg(feature = "server")]
+    impl OneRequest {
+        fn new(request: &'static [u8]) -> OneRequest {
+            OneRequest {
+                request: request,
+                written: Rc::new(RefCell::new(Vec::new())),
+            }
+        }
+    }
+
+    #[cfg(feature = "server")]
+    impl ::std::io::Read for OneRequest {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            if self.request.is_empty() {
+                return Err(::std::io::ErrorKind::WouldBlock.into());
+            }
+            self.request.read(buf)
+        }
+    }
+
+    #[cfg(feature = "server")]
+    impl ::std::io::Write for OneRequest {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.written.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
 }
@@ -1,6 +1,7 @@
 use std::fmt;
+use std::io::Cursor;
 
-use bytes::{Buf, IntoBuf};
+use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
 use bytes::buf::{Chain, Take};
 use iovec::IoVec;
 
@@ -12,6 +13,7 @@ use super::io::WriteBuf;
 pub struct Encoder {
     kind: Kind,
     is_last: bool,
+    last_extensions: Option<Bytes>,
 }
 
 #[derive(Debug)]
@@ -42,7 +44,9 @@ enum BufKind<B> {
     Exact(B),
     Limited(Take<B>),
     Chunked(Chain<Chain<ChunkSize, B>, StaticBuf>),
+    ChunkedExt(Chain<Chain<Cursor<Bytes>, B>, StaticBuf>),
     ChunkedEnd(StaticBuf),
+    ChunkedEndExt(Chain<Cursor<Bytes>, StaticBuf>),
 }
 
 impl Encoder {
@@ -50,6 +54,7 @@ impl Encoder {
         Encoder {
             kind: kind,
             is_last: false,
+            last_extensions: None,
         }
     }
     pub fn chunked() -> Encoder {
@@ -64,6 +69,14 @@ impl Encoder {
         Encoder::new(Kind::CloseDelimited)
     }
 
+    #[cfg(feature = "server")]
+    pub fn is_close_delimited(&self) -> bool {
+        match self.kind {
+            Kind::CloseDelimited => true,
+            _ => false,
+        }
+    }
+
     pub fn is_eof(&self) -> bool {
         match self.kind {
             Kind::Length(0) => true,
@@ -71,6 +84,7 @@ impl Encoder {
         }
     }
 
+    #[cfg(feature = "server")]
     pub fn set_last(mut self, is_last: bool) -> Self {
         self.is_last = is_last;
         self
@@ -80,9 +94,20 @@ impl Encoder {
         self.is_last
     }
 
+    /// Set the chunk extensions of the last chunk, if it's chunked.
+    pub fn set_last_extensions(&mut self, extensions: Bytes) {
+        self.last_extensions = Some(extensions);
+    }
+
     pub fn end<B>(&self) -> Result<Option<EncodedBuf<B>>, NotEof> {
         match self.kind {
             Kind::Length(0) => Ok(None),
+            Kind::Chunked if self.last_extensions.is_some() => Ok(Some(EncodedBuf {
+                kind: BufKind::ChunkedEndExt(
+                    chunk_size_with_extensions(0, self.last_extensions.as_ref().unwrap())
+                        .chain(StaticBuf(b"\r\n"))
+                ),
+            })),
             Kind::Chunked => Ok(Some(EncodedBuf {
                 kind: BufKind::ChunkedEnd(StaticBuf(b"0\r\n\r\n")),
             })),
@@ -90,7 +115,16 @@ impl Encoder {
         }
     }
 
+    #[cfg(any(test, feature = "unstable-lowlevel"))]
     pub fn encode<B>(&mut self, msg: B) -> EncodedBuf<B::Buf>
+    where
+        B: IntoBuf,
+    {
+        self.encode_with_extensions(msg, None)
+    }
+
+    /// Encode `msg`, with chunk extensions if it's chunked.
+    pub fn encode_with_extensions<B>(&mut self, msg: B, extensions: Option<Bytes>) -> EncodedBuf<B::Buf>
     where
         B: IntoBuf,
     {
@@ -99,6 +133,13 @@ impl Encoder {
         debug_assert!(len > 0, "encode() called with empty buf");
 
         let kind = match self.kind {
+            Kind::Chunked if extensions.is_some() => {
+                trace!("encoding chunked {}B with extensions", len);
+                let buf = chunk_size_with_extensions(len, &extensions.unwrap())
+                    .chain(msg)
+                    .chain(StaticBuf(b"\r\n"));
+                BufKind::ChunkedExt(buf)
+            },
             Kind::Chunked => {
                 trace!("encoding chunked {}B", len);
                 let buf = ChunkSize::new(len)
@@ -127,7 +168,7 @@ impl Encoder {
         }
     }
 
-    pub(super) fn encode_and_end<B>(&self, msg: B, dst: &mut WriteBuf<EncodedBuf<B::Buf>>) -> bool
+    pub(super) fn encode_and_end<B>(&self, msg: B, extensions: Option<Bytes>, dst: &mut WriteBuf<EncodedBuf<B::Buf>>) -> bool
     where
         B: IntoBuf,
     {
@@ -136,6 +177,22 @@ impl Encoder {
         debug_assert!(len > 0, "encode() called with empty buf");
 
         match self.kind {
+            Kind::Chunked if self.last_extensions.is_some() => {
+                // The last chunk has extensions of its own, so it's written
+                // after this one.
+                trace!("encoding chunked {}B before the last chunk", len);
+                dst.buffer(Encoder::chunked().encode_with_extensions(msg, extensions));
+                dst.buffer(self.end::<B::Buf>().expect("chunked end").expect("last chunk"));
+                !self.is_last
+            },
+            Kind::Chunked if extensions.is_some() => {
+                trace!("encoding chunked {}B with extensions", len);
+                let buf = chunk_size_with_extensions(len, &extensions.unwrap())
+                    .chain(msg)
+                    .chain(StaticBuf(b"\r\n0\r\n\r\n"));
+                dst.buffer(buf);
+                !self.is_last
+            },
             Kind::Chunked => {
                 trace!("encoding chunked {}B", len);
                 let buf = ChunkSize::new(len)
@@ -213,7 +270,9 @@ where
             BufKind::Exact(ref b) => b.remaining(),
             BufKind::Limited(ref b) => b.remaining(),
             BufKind::Chunked(ref b) => b.remaining(),
+            BufKind::ChunkedExt(ref b) => b.remaining(),
             BufKind::ChunkedEnd(ref b) => b.remaining(),
+            BufKind::ChunkedEndExt(ref b) => b.remaining(),
         }
     }
 
@@ -223,7 +282,9 @@ where
             BufKind::Exact(ref b) => b.bytes(),
             BufKind::Limited(ref b) => b.bytes(),
             BufKind::Chunked(ref b) => b.bytes(),
+            BufKind::ChunkedExt(ref b) => b.bytes(),
             BufKind::ChunkedEnd(ref b) => b.bytes(),
+            BufKind::ChunkedEndExt(ref b) => b.bytes(),
         }
     }
 
@@ -233,7 +294,9 @@ where
             BufKind::Exact(ref mut b) => b.advance(cnt),
             BufKind::Limited(ref mut b) => b.advance(cnt),
             BufKind::Chunked(ref mut b) => b.advance(cnt),
+            BufKind::ChunkedExt(ref mut b) => b.advance(cnt),
             BufKind::ChunkedEnd(ref mut b) => b.advance(cnt),
+            BufKind::ChunkedEndExt(ref mut b) => b.advance(cnt),
         }
     }
 
@@ -243,7 +306,9 @@ where
             BufKind::Exact(ref b) => b.bytes_vec(dst),
             BufKind::Limited(ref b) => b.bytes_vec(dst),
             BufKind::Chunked(ref b) => b.bytes_vec(dst),
+            BufKind::ChunkedExt(ref b) => b.bytes_vec(dst),
             BufKind::ChunkedEnd(ref b) => b.bytes_vec(dst),
+            BufKind::ChunkedEndExt(ref b) => b.bytes_vec(dst),
         }
     }
 }
@@ -279,6 +344,18 @@ impl ChunkSize {
     }
 }
 
+// The size line of a chunk, such as `5;name=value\r\n`.
+fn chunk_size_with_extensions(len: usize, extensions: &[u8]) -> Cursor<Bytes> {
+    let size = ChunkSize::new(len);
+    let size = &size.bytes[..size.len as usize - 2];
+    let mut line = BytesMut::with_capacity(size.len() + 1 + extensions.len() + 2);
+    line.put_slice(size);
+    line.put_u8(b';');
+    line.put_slice(extensions);
+    line.put_slice(b"\r\n");
+    line.freeze().into_buf()
+}
+
 impl Buf for ChunkSize {
     #[inline]
     fn remaining(&self) -> usize {
@@ -340,6 +417,14 @@ impl<B: Buf> From<Chain<Chain<ChunkSize, B>, StaticBuf>> for EncodedBuf<B> {
     }
 }
 
+impl<B: Buf> From<Chain<Chain<Cursor<Bytes>, B>, StaticBuf>> for EncodedBuf<B> {
+    fn from(buf: Chain<Chain<Cursor<Bytes>, B>, StaticBuf>) -> Self {
+        EncodedBuf {
+            kind: BufKind::ChunkedExt(buf),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use bytes::{BufMut};
@@ -369,6 +454,27 @@ mod tests {
         assert_eq!(dst, b"7\r\nfoo bar\r\nD\r\nbaz quux herp\r\n0\r\n\r\n".as_ref());
     }
 
+    #[test]
+    fn chunked_with_extensions() {
+        let mut encoder = Encoder::chunked();
+        let mut dst = Vec::new();
+
+        let buf = encoder.encode_with_extensions(b"foo bar".as_ref(), Some("sig=abc;n=1".into()));
+        dst.put(buf);
+        assert_eq!(dst, b"7;sig=abc;n=1\r\nfoo bar\r\n");
+
+        // As well as the last chunk.
+        encoder.set_last_extensions("sig=end".into());
+        dst.put(encoder.end::<Cursor<Vec<u8>>>().unwrap().unwrap());
+        assert_eq!(dst, b"7;sig=abc;n=1\r\nfoo bar\r\n0;sig=end\r\n\r\n".as_ref());
+
+        // Other encodings don't have chunk extensions.
+        let mut encoder = Encoder::length(3);
+        let mut dst = Vec::new();
+        dst.put(encoder.encode_with_extensions(b"foo".as_ref(), Some("sig=abc".into())));
+        assert_eq!(dst, b"foo");
+    }
+
     #[test]
     fn length() {
         let max_len = 8;
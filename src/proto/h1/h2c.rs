@@ -0,0 +1,358 @@
+//! Upgrading an HTTP/1.1 connection to HTTP/2 over cleartext, with an
+//! `Upgrade: h2c` offer.
+//!
+//! See [RFC 7540](https://tools.ietf.org/html/rfc7540#section-3.2).
+
+#[cfg(feature = "server")] use bytes::Bytes;
+use http::{HeaderMap, Method, Version};
+use http::header::{HeaderValue, CONNECTION, UPGRADE};
+#[cfg(feature = "server")] use http::header::{HeaderName, HOST, TE};
+#[cfg(feature = "server")] use http::{Request, StatusCode};
+
+#[cfg(feature = "server")] use body::{Body, Payload};
+use headers;
+#[cfg(feature = "server")] use proto::MessageHead;
+
+/// The protocol name of HTTP/2 over cleartext.
+const H2C: &str = "h2c";
+
+/// The header carrying the client's settings.
+const HTTP2_SETTINGS: &str = "http2-settings";
+
+#[cfg(feature = "server")]
+const TYPE_HEADERS: u8 = 0x1;
+#[cfg(feature = "server")]
+const TYPE_CONTINUATION: u8 = 0x9;
+#[cfg(feature = "server")]
+const FLAG_END_STREAM: u8 = 0x1;
+#[cfg(feature = "server")]
+const FLAG_END_HEADERS: u8 = 0x4;
+
+/// The largest frame payload a peer must accept, before its settings are
+/// known.
+#[cfg(feature = "server")]
+const MAX_FRAME_SIZE: usize = 16_384;
+
+/// Whether `headers` name `h2c` in their `Upgrade`.
+pub(crate) fn is_upgrade(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(UPGRADE)
+        .iter()
+        .any(|value| headers::connection_has(value, H2C))
+}
+
+// ===== server =====
+
+/// A request that upgraded a connection, as the HTTP/2 server reads it.
+#[cfg(feature = "server")]
+#[cfg_attr(not(feature = "http2"), allow(dead_code))]
+pub(crate) struct Upgrade {
+    /// The client's settings from `HTTP2-Settings`, as the payload of a
+    /// `SETTINGS` frame.
+    pub(crate) settings: Bytes,
+    /// The `HEADERS` frame, and any `CONTINUATION` frames, opening stream 1.
+    pub(crate) frames: Bytes,
+}
+
+/// Whether a request offers to upgrade to HTTP/2, such that it can be
+/// answered on the first stream of the new connection.
+///
+/// Only an HTTP/1.1 request without a body, and with exactly one valid
+/// `HTTP2-Settings`, can be.
+#[cfg(feature = "server")]
+pub(crate) fn is_offer(req: &Request<Body>) -> bool {
+    if req.version() != Version::HTTP_11
+        || *req.method() == Method::CONNECT
+        || !req.body().is_end_stream() {
+        return false;
+    }
+    let headers = req.headers();
+    let connection = headers
+        .get_all(CONNECTION)
+        .iter()
+        .any(|value| headers::connection_has(value, "upgrade"));
+    let mut settings = headers.get_all(HTTP2_SETTINGS).iter();
+    let settings = match (settings.next(), settings.next()) {
+        (Some(value), None) => decode_settings(value.as_bytes()).is_some(),
+        _ => false,
+    };
+    is_upgrade(headers) && connection && settings
+}
+
+/// The `101 Switching Protocols` accepting an offer.
+#[cfg(feature = "server")]
+pub(crate) fn switching_protocols() -> MessageHead<StatusCode> {
+    let mut head = MessageHead::default();
+    head.subject = StatusCode::SWITCHING_PROTOCOLS;
+    head.headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+    head.headers.insert(UPGRADE, HeaderValue::from_static(H2C));
+    head
+}
+
+/// Encode a request that offered to upgrade, which `is_offer` must have
+/// accepted, for the HTTP/2 server to read.
+///
+/// The header block only has literals that aren't indexed, so it doesn't
+/// change the state of the server's decoder.
+#[cfg(feature = "server")]
+pub(crate) fn encode_request(req: &Request<Body>) -> Upgrade {
+    let settings = req.headers()
+        .get(HTTP2_SETTINGS)
+        .and_then(|value| decode_settings(value.as_bytes()))
+        .expect("encode_request of a request that isn't an offer");
+    let mut block = Vec::new();
+    encode_field(&mut block, b":method", req.method().as_str().as_bytes());
+    encode_field(&mut block, b":scheme", b"http");
+    let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+    encode_field(&mut block, b":path", if path.is_empty() { b"/" } else { path.as_bytes() });
+    let authority = req.uri()
+        .authority_part()
+        .map(|authority| authority.as_str().as_bytes())
+        .or_else(|| req.headers().get(HOST).map(HeaderValue::as_bytes));
+    if let Some(authority) = authority {
+        encode_field(&mut block, b":authority", authority);
+    }
+    for (name, value) in req.headers() {
+        if is_connection_specific(name, value) {
+            continue;
+        }
+        encode_field(&mut block, name.as_str().as_bytes(), value.as_bytes());
+    }
+    Upgrade {
+        settings: Bytes::from(settings),
+        frames: frames(&block),
+    }
+}
+
+// Headers that mean nothing past the HTTP/1.1 connection, which HTTP/2
+// doesn't allow.
+#[cfg(feature = "server")]
+fn is_connection_specific(name: &HeaderName, value: &HeaderValue) -> bool {
+    match name.as_str() {
+        "connection" | "host" | "http2-settings" | "keep-alive" |
+        "proxy-connection" | "transfer-encoding" | "upgrade" => true,
+        _ => *name == TE && *value != "trailers",
+    }
+}
+
+// A literal header field without indexing, with a new name, and neither
+// string Huffman coded.
+#[cfg(feature = "server")]
+fn encode_field(dst: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    dst.push(0);
+    encode_len(dst, name.len());
+    dst.extend_from_slice(name);
+    encode_len(dst, value.len());
+    dst.extend_from_slice(value);
+}
+
+// A string length, as an integer with a 7-bit prefix.
+#[cfg(feature = "server")]
+fn encode_len(dst: &mut Vec<u8>, len: usize) {
+    if len < 0x7f {
+        dst.push(len as u8);
+        return;
+    }
+    dst.push(0x7f);
+    let mut rest = len - 0x7f;
+    while rest >= 0x80 {
+        dst.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    dst.push(rest as u8);
+}
+
+#[cfg(feature = "server")]
+fn frames(block: &[u8]) -> Bytes {
+    let mut dst = Vec::with_capacity(block.len() + 9);
+    let mut chunks = block.chunks(MAX_FRAME_SIZE).peekable();
+    let mut kind = TYPE_HEADERS;
+    let mut flags = FLAG_END_STREAM;
+    while let Some(chunk) = chunks.next() {
+        if chunks.peek().is_none() {
+            flags |= FLAG_END_HEADERS;
+        }
+        let len = chunk.len();
+        dst.extend_from_slice(&[
+            (len >> 16) as u8, (len >> 8) as u8, len as u8,
+            kind, flags,
+            0, 0, 0, 1,
+        ]);
+        dst.extend_from_slice(chunk);
+        kind = TYPE_CONTINUATION;
+        flags = 0;
+    }
+    Bytes::from(dst)
+}
+
+// The payload of a `SETTINGS` frame, from the base64url of `HTTP2-Settings`,
+// with or without its trailing `=`.
+#[cfg(feature = "server")]
+fn decode_settings(value: &[u8]) -> Option<Vec<u8>> {
+    let value = match value.iter().position(|&b| b == b'=') {
+        Some(i) if value[i..].iter().all(|&b| b == b'=') => &value[..i],
+        Some(_) => return None,
+        None => value,
+    };
+    let mut decoded = Vec::with_capacity(value.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &b in value {
+        let sextet = if b >= b'A' && b <= b'Z' {
+            b - b'A'
+        } else if b >= b'a' && b <= b'z' {
+            b - b'a' + 26
+        } else if b >= b'0' && b <= b'9' {
+            b - b'0' + 52
+        } else if b == b'-' {
+            62
+        } else if b == b'_' {
+            63
+        } else {
+            return None;
+        };
+        acc = acc << 6 | sextet as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    // Each setting is 6 bytes, an identifier and a value.
+    if bits >= 6 || decoded.len() % 6 != 0 {
+        return None;
+    }
+    Some(decoded)
+}
+
+// ===== client =====
+
+/// Whether a request can offer to upgrade, which needs it to not have a
+/// body, or an offer of its own.
+#[cfg(feature = "client")]
+pub(crate) fn can_offer(method: &Method, version: Version, headers: &HeaderMap) -> bool {
+    version == Version::HTTP_11
+        && *method != Method::CONNECT
+        && !headers.contains_key(UPGRADE)
+}
+
+/// Add an offer to upgrade to a request's headers.
+///
+/// The settings only disable server push, which isn't supported. The rest
+/// are sent again once the connection is upgraded.
+#[cfg(feature = "client")]
+pub(crate) fn write_offer(headers: &mut HeaderMap) {
+    headers.insert(UPGRADE, HeaderValue::from_static(H2C));
+    headers.append(CONNECTION, HeaderValue::from_static("Upgrade, HTTP2-Settings"));
+    // SETTINGS_ENABLE_PUSH = 0
+    headers.insert(HTTP2_SETTINGS, HeaderValue::from_static("AAIAAAAA"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "server")]
+    fn offer(settings: &[&str], body: Body) -> Request<Body> {
+        let mut req = Request::builder();
+        req.header("upgrade", "h2c");
+        req.header("connection", "Upgrade, HTTP2-Settings");
+        for value in settings {
+            req.header("http2-settings", *value);
+        }
+        req.body(body).unwrap()
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn is_offer_needs_settings() {
+        assert!(is_offer(&offer(&["AAIAAAAA"], Body::empty())));
+        assert!(!is_offer(&offer(&[], Body::empty())), "no settings");
+        assert!(!is_offer(&offer(&["AAIA AAAA"], Body::empty())), "not base64url");
+        assert!(!is_offer(&offer(&["AAIAAA"], Body::empty())), "not settings");
+        assert!(!is_offer(&offer(&["AAIAAAAA", "AAIAAAAA"], Body::empty())), "twice");
+        assert!(!is_offer(&offer(&["AAIAAAAA"], Body::from("body"))), "body");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn encode_request_literals() {
+        let req = Request::builder()
+            .uri("/a?b")
+            .header("host", "example.domain")
+            .header("upgrade", "h2c")
+            .header("http2-settings", "AAIAAAAA")
+            .header("accept", "*/*")
+            .body(Body::empty())
+            .unwrap();
+        let mut expected = vec![0, 0, 0, TYPE_HEADERS, FLAG_END_STREAM | FLAG_END_HEADERS, 0, 0, 0, 1];
+        encode_field(&mut expected, b":method", b"GET");
+        encode_field(&mut expected, b":scheme", b"http");
+        encode_field(&mut expected, b":path", b"/a?b");
+        encode_field(&mut expected, b":authority", b"example.domain");
+        encode_field(&mut expected, b"accept", b"*/*");
+        let len = expected.len() - 9;
+        expected[2] = len as u8;
+        let upgrade = encode_request(&req);
+        assert_eq!(&upgrade.frames[..], &expected[..]);
+        assert_eq!(&upgrade.settings[..], &[0, 2, 0, 0, 0, 0]);
+        assert_eq!(&expected[9..18], b"\0\x07:method");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn encode_request_continuation() {
+        let value = "a".repeat(MAX_FRAME_SIZE);
+        let req = Request::builder()
+            .header("x-big", &value[..])
+            .header("http2-settings", "")
+            .body(Body::empty())
+            .unwrap();
+        let encoded = encode_request(&req).frames;
+        assert_eq!(&encoded[..9], &[0x00, 0x40, 0x00, TYPE_HEADERS, FLAG_END_STREAM, 0, 0, 0, 1]);
+        let next = &encoded[9 + MAX_FRAME_SIZE..];
+        assert_eq!(next[3], TYPE_CONTINUATION);
+        assert_eq!(next[4], FLAG_END_HEADERS);
+        let len = (next[1] as usize) << 8 | next[2] as usize;
+        assert_eq!(next.len(), 9 + len);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn decode_settings_base64url() {
+        // SETTINGS_ENABLE_PUSH = 0
+        assert_eq!(decode_settings(b"AAIAAAAA"), Some(vec![0, 2, 0, 0, 0, 0]));
+        // SETTINGS_MAX_CONCURRENT_STREAMS = 100, SETTINGS_INITIAL_WINDOW_SIZE = 1073741823
+        let both = vec![0, 3, 0, 0, 0, 100, 0, 4, 0x3f, 0xff, 0xff, 0xff];
+        assert_eq!(decode_settings(b"AAMAAABkAAQ_____"), Some(both.clone()));
+        assert_eq!(decode_settings(b""), Some(Vec::new()));
+
+        assert_eq!(decode_settings(b"AAIAAAA"), None, "not whole bytes");
+        assert_eq!(decode_settings(b"AAIAAA=="), None, "not whole settings");
+        assert_eq!(decode_settings(b"AAIA=AAA"), None, "padding in the middle");
+        assert_eq!(decode_settings(b"AAIAAAA+"), None, "base64, not base64url");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn encode_len_prefix() {
+        let mut dst = Vec::new();
+        encode_len(&mut dst, 126);
+        encode_len(&mut dst, 127);
+        encode_len(&mut dst, 1337);
+        assert_eq!(dst, [126, 127, 0, 127, 0xba, 0x09]);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn write_offer_is_upgrade() {
+        let mut headers = HeaderMap::new();
+        assert!(can_offer(&Method::GET, Version::HTTP_11, &headers));
+        write_offer(&mut headers);
+        assert!(is_upgrade(&headers));
+        assert!(!can_offer(&Method::GET, Version::HTTP_11, &headers));
+        assert!(!can_offer(&Method::CONNECT, Version::HTTP_11, &HeaderMap::new()));
+        assert!(!can_offer(&Method::GET, Version::HTTP_10, &HeaderMap::new()));
+    }
+}
@@ -8,13 +8,13 @@ use futures::{Async, Poll};
 use iovec::IoVec;
 use tokio_io::{AsyncRead, AsyncWrite};
 
+use common::{ConnectionStats, Transfer};
 use super::{Http1Transaction, ParseContext, ParsedMessage};
 
-/// The initial buffer size allocated before trying to read from IO.
-pub(crate) const INIT_BUFFER_SIZE: usize = 8192;
+use proto::MINIMUM_MAX_BUFFER_SIZE;
 
-/// The minimum value that can be set to max buffer size.
-pub const MINIMUM_MAX_BUFFER_SIZE: usize = INIT_BUFFER_SIZE;
+/// The initial buffer size allocated before trying to read from IO.
+pub(crate) const INIT_BUFFER_SIZE: usize = MINIMUM_MAX_BUFFER_SIZE;
 
 /// The default maximum read buffer size. If the buffer gets this big and
 /// a message is still not complete, a `TooLarge` error is triggered.
@@ -32,8 +32,11 @@ pub struct Buffered<T, B> {
     flush_pipeline: bool,
     io: T,
     max_buf_size: usize,
+    #[cfg(feature = "unstable-completion-io")]
+    owned_io: Option<OwnedIo<T>>,
     read_blocked: bool,
     read_buf: BytesMut,
+    transfer: Transfer,
     write_buf: WriteBuf<B>,
 }
 
@@ -59,12 +62,24 @@ where
             flush_pipeline: false,
             io: io,
             max_buf_size: DEFAULT_MAX_BUFFER_SIZE,
+            #[cfg(feature = "unstable-completion-io")]
+            owned_io: None,
             read_buf: BytesMut::with_capacity(0),
+            transfer: Transfer::new(ConnectionStats::new()),
             write_buf: WriteBuf::new(),
             read_blocked: false,
         }
     }
 
+    pub fn transfer(&self) -> &Transfer {
+        &self.transfer
+    }
+
+    pub fn start_exchange(&mut self) {
+        self.transfer.start_exchange();
+    }
+
+    #[cfg(feature = "server")]
     pub fn set_flush_pipeline(&mut self, enabled: bool) {
         self.flush_pipeline = enabled;
         self.write_buf.set_strategy(if enabled {
@@ -83,6 +98,7 @@ where
         self.write_buf.max_buf_size = max;
     }
 
+    #[cfg(any(feature = "client", feature = "unstable-completion-io"))]
     pub fn set_write_strategy_flatten(&mut self) {
         // this should always be called only at construction time,
         // so this assert is here to catch myself
@@ -90,6 +106,16 @@ where
         self.write_buf.set_strategy(Strategy::Flatten);
     }
 
+    /// Read and write by handing the buffers to the IO.
+    ///
+    /// Everything written is flattened into the headers buffer, so it can
+    /// be handed over in one piece.
+    #[cfg(feature = "unstable-completion-io")]
+    pub fn set_owned_io(&mut self, owned_io: OwnedIo<T>) {
+        self.set_write_strategy_flatten();
+        self.owned_io = Some(owned_io);
+    }
+
     pub fn read_buf(&self) -> &[u8] {
         self.read_buf.as_ref()
     }
@@ -130,9 +156,15 @@ where
         S: Http1Transaction,
     {
         loop {
-            match try!(S::parse(&mut self.read_buf, ParseContext { cached_headers: ctx.cached_headers, req_method: ctx.req_method, })) {
+            let len = self.read_buf.len();
+            match try!(S::parse(&mut self.read_buf, ParseContext { cached_headers: ctx.cached_headers, req_method: ctx.req_method, max_headers: ctx.max_headers, preserve_header_case: ctx.preserve_header_case, preserve_header_order: ctx.preserve_header_order, lenient: ctx.lenient, })) {
                 Some(msg) => {
                     debug!("parsed {} headers", msg.head.headers.len());
+                    // A server's exchange starts with the request it reads.
+                    if S::should_read_first() {
+                        self.transfer.start_exchange();
+                    }
+                    self.transfer.on_consumed(len - self.read_buf.len());
                     return Ok(Async::Ready(msg))
                 },
                 None => {
@@ -153,15 +185,12 @@ where
     }
 
     pub fn read_from_io(&mut self) -> Poll<usize, io::Error> {
-        use bytes::BufMut;
         self.read_blocked = false;
-        if self.read_buf.remaining_mut() < INIT_BUFFER_SIZE {
-            self.read_buf.reserve(INIT_BUFFER_SIZE);
-        }
-        self.io.read_buf(&mut self.read_buf).map(|ok| {
+        self.read_io().map(|ok| {
             match ok {
                 Async::Ready(n) => {
                     debug!("read {} bytes", n);
+                    self.transfer.on_read(n);
                     Async::Ready(n)
                 },
                 Async::NotReady => {
@@ -172,6 +201,29 @@ where
         })
     }
 
+    #[cfg(feature = "unstable-completion-io")]
+    fn read_io(&mut self) -> Poll<usize, io::Error> {
+        match self.owned_io {
+            // The IO reserves room itself, since the buffer is left empty
+            // while a read is in flight.
+            Some(ref owned_io) => (owned_io.read)(&mut self.io, &mut self.read_buf),
+            None => self.read_io_borrowed(),
+        }
+    }
+
+    #[cfg(not(feature = "unstable-completion-io"))]
+    fn read_io(&mut self) -> Poll<usize, io::Error> {
+        self.read_io_borrowed()
+    }
+
+    fn read_io_borrowed(&mut self) -> Poll<usize, io::Error> {
+        use bytes::BufMut;
+        if self.read_buf.remaining_mut() < INIT_BUFFER_SIZE {
+            self.read_buf.reserve(INIT_BUFFER_SIZE);
+        }
+        self.io.read_buf(&mut self.read_buf)
+    }
+
     pub fn into_inner(self) -> (T, Bytes) {
         (self.io, self.read_buf.freeze())
     }
@@ -185,6 +237,22 @@ where
     }
 
     pub fn flush(&mut self) -> Poll<(), io::Error> {
+        let ret = self.flush_buffered();
+        match ret {
+            Ok(Async::Ready(())) => self.transfer.set_write_blocked(false),
+            Ok(Async::NotReady) => self.transfer.set_write_blocked(true),
+            Err(_) => (),
+        }
+        ret
+    }
+
+    fn flush_buffered(&mut self) -> Poll<(), io::Error> {
+        #[cfg(feature = "unstable-completion-io")]
+        {
+            if self.owned_io.is_some() && !(self.flush_pipeline && !self.read_buf.is_empty()) {
+                return self.flush_owned();
+            }
+        }
         if self.flush_pipeline && !self.read_buf.is_empty() {
             //Ok(())
         } else if self.write_buf.remaining() == 0 {
@@ -197,6 +265,7 @@ where
             loop {
                 let n = try_ready!(self.io.write_buf(&mut self.write_buf.auto()));
                 debug!("flushed {} bytes", n);
+                self.transfer.on_written(n);
                 if self.write_buf.remaining() == 0 {
                     break;
                 } else if n == 0 {
@@ -217,6 +286,7 @@ where
         loop {
             let n = try_nb!(self.io.write(self.write_buf.headers.bytes()));
             debug!("flushed {} bytes", n);
+            self.transfer.on_written(n);
             self.write_buf.headers.advance(n);
             if self.write_buf.headers.remaining() == 0 {
                 self.write_buf.headers.reset();
@@ -229,8 +299,54 @@ where
         try_nb!(self.io.flush());
         Ok(Async::Ready(()))
     }
+
+    /// Version of `flush_flattened` that hands the headers buffer to the IO.
+    ///
+    /// While a write is in flight the buffer is held by the IO, so the
+    /// IO writes until it gives back an empty buffer.
+    #[cfg(feature = "unstable-completion-io")]
+    fn flush_owned(&mut self) -> Poll<(), io::Error> {
+        let write = self.owned_io.as_ref().expect("flush_owned without owned_io").write;
+        debug_assert_eq!(self.write_buf.headers.pos, 0);
+        loop {
+            let n = try_ready!(write(&mut self.io, &mut self.write_buf.headers.bytes));
+            debug!("flushed {} bytes", n);
+            self.transfer.on_written(n);
+            if self.write_buf.headers.bytes.is_empty() {
+                break;
+            } else if n == 0 {
+                trace!("write returned zero, but {} bytes remaining", self.write_buf.remaining());
+                return Err(io::ErrorKind::WriteZero.into())
+            }
+        }
+        try_nb!(self.io.flush());
+        Ok(Async::Ready(()))
+    }
 }
 
+/// How `Buffered` reads and writes with an IO that takes ownership of the
+/// buffers, see `completion::Completion`.
+#[cfg(feature = "unstable-completion-io")]
+pub(crate) struct OwnedIo<T> {
+    /// Read into the end of the buffer, taking it while the read is in
+    /// flight, and returning how many bytes were read.
+    pub(crate) read: fn(&mut T, &mut BytesMut) -> Poll<usize, io::Error>,
+    /// Write the front of the buffer, taking it while the write is in
+    /// flight, and returning how many bytes were written. The written
+    /// bytes are removed from the buffer.
+    pub(crate) write: fn(&mut T, &mut Vec<u8>) -> Poll<usize, io::Error>,
+}
+
+#[cfg(feature = "unstable-completion-io")]
+impl<T> Clone for OwnedIo<T> {
+    fn clone(&self) -> OwnedIo<T> {
+        *self
+    }
+}
+
+#[cfg(feature = "unstable-completion-io")]
+impl<T> Copy for OwnedIo<T> {}
+
 pub trait MemRead {
     fn read_mem(&mut self, len: usize) -> Poll<Bytes, io::Error>;
 }
@@ -241,13 +357,14 @@ where
     B: Buf,
 {
     fn read_mem(&mut self, len: usize) -> Poll<Bytes, io::Error> {
-        if !self.read_buf.is_empty() {
-            let n = ::std::cmp::min(len, self.read_buf.len());
-            Ok(Async::Ready(self.read_buf.split_to(n).freeze()))
+        let n = if !self.read_buf.is_empty() {
+            ::std::cmp::min(len, self.read_buf.len())
         } else {
             let n = try_ready!(self.read_from_io());
-            Ok(Async::Ready(self.read_buf.split_to(::std::cmp::min(len, n)).freeze()))
-        }
+            ::std::cmp::min(len, n)
+        };
+        self.transfer.on_consumed(n);
+        Ok(Async::Ready(self.read_buf.split_to(n).freeze()))
     }
 }
 
@@ -570,6 +687,7 @@ mod tests {
         io_buf.flush().expect("should short-circuit flush");
     }
 
+    #[cfg(feature = "client")]
     #[test]
     fn parse_reads_until_blocked() {
         // missing last line ending
@@ -580,6 +698,10 @@ mod tests {
         let ctx = ParseContext {
             cached_headers: &mut None,
             req_method: &mut None,
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: ::proto::h1::Lenient::default(),
         };
         assert!(buffered.parse::<::proto::ClientTransaction>(ctx).unwrap().is_not_ready());
         assert!(buffered.io.blocked());
@@ -2,28 +2,37 @@ use bytes::BytesMut;
 use http::{HeaderMap, Method};
 
 use proto::{MessageHead, BodyLength};
+pub(crate) use proto::{HeaderValuePolicy, Lenient};
 
 pub(crate) use self::conn::Conn;
 pub(crate) use self::dispatch::Dispatcher;
 pub use self::decode::Decoder;
 pub use self::encode::{EncodedBuf, Encoder};
 pub use self::io::Cursor; //TODO: move out of h1::io
-pub use self::io::MINIMUM_MAX_BUFFER_SIZE;
+#[cfg(feature = "unstable-completion-io")]
+pub(crate) use self::io::{OwnedIo, INIT_BUFFER_SIZE};
 
+#[cfg(feature = "unstable-lowlevel")]
+pub(crate) mod codec;
 mod conn;
+#[cfg(feature = "server")]
 mod date;
 mod decode;
 pub(crate) mod dispatch;
 mod encode;
+pub(crate) mod h2c;
 mod io;
 mod role;
 
 
+#[cfg(feature = "server")]
 pub(crate) type ServerTransaction = self::role::Server<self::role::YesUpgrades>;
 //pub type ServerTransaction = self::role::Server<self::role::NoUpgrades>;
 //pub type ServerUpgradeTransaction = self::role::Server<self::role::YesUpgrades>;
 
+#[cfg(feature = "client")]
 pub(crate) type ClientTransaction = self::role::Client<self::role::NoUpgrades>;
+#[cfg(feature = "client")]
 pub(crate) type ClientUpgradeTransaction = self::role::Client<self::role::YesUpgrades>;
 
 pub(crate) trait Http1Transaction {
@@ -31,6 +40,7 @@ pub(crate) trait Http1Transaction {
     type Outgoing: Default;
     fn parse(bytes: &mut BytesMut, ctx: ParseContext) -> ParseResult<Self::Incoming>;
     fn encode(enc: Encode<Self::Outgoing>, dst: &mut Vec<u8>) -> ::Result<Encoder>;
+    fn encode_informational(head: &mut MessageHead<Self::Outgoing>, dst: &mut Vec<u8>);
 
     fn on_error(err: &::Error) -> Option<MessageHead<Self::Outgoing>>;
 
@@ -53,6 +63,18 @@ pub(crate) struct ParsedMessage<T> {
 pub(crate) struct ParseContext<'a> {
     cached_headers: &'a mut Option<HeaderMap>,
     req_method: &'a mut Option<Method>,
+    max_headers: Option<usize>,
+    preserve_header_case: bool,
+    preserve_header_order: bool,
+    lenient: Lenient,
+}
+
+impl Lenient {
+    fn is_enabled(&self) -> bool {
+        self.obsolete_line_folding
+            || self.spaces_in_request_target
+            || self.whitespace_around_header_names
+    }
 }
 
 /// Passed to Http1Transaction::encode
@@ -69,7 +91,9 @@ pub enum Decode {
     /// Decode normally.
     Normal(Decoder),
     /// After this decoder is done, HTTP is done.
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
     Final(Decoder),
     /// A header block that should be ignored, like unknown 1xx responses.
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
     Ignore,
 }
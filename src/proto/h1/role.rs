@@ -1,15 +1,19 @@
-use std::fmt::{self, Write};
+use std::collections::HashMap;
+#[cfg(feature = "client")] use std::fmt::{self, Write};
 use std::mem;
 
 use bytes::{BytesMut, Bytes};
-use http::header::{self, Entry, HeaderName, HeaderValue};
+use http::header::{self, HeaderName, HeaderValue, ValueIter};
+#[cfg(feature = "client")] use http::header::Entry;
 use http::{HeaderMap, Method, StatusCode, Version};
 use httparse;
 
 use error::Parse;
 use headers;
-use proto::{BodyLength, MessageHead, RequestLine, RequestHead};
-use proto::h1::{Decode, Decoder, Encode, Encoder, Http1Transaction, ParseResult, ParseContext, ParsedMessage, date};
+use proto::{BodyLength, HeaderCaseMap, HeaderOrder, MessageHead, RequestLine};
+#[cfg(feature = "client")] use proto::{ReasonPhrase, RequestHead};
+use proto::h1::{Decode, Decoder, Encode, Encoder, Http1Transaction, Lenient, ParseResult, ParseContext, ParsedMessage};
+#[cfg(feature = "server")] use proto::h1::date;
 
 const MAX_HEADERS: usize = 100;
 const AVERAGE_HEADER_SIZE: usize = 30; // totally scientific
@@ -19,10 +23,13 @@ const AVERAGE_HEADER_SIZE: usize = 30; // totally scientific
 // There is 1 modifier, OnUpgrade, which can wrap Client and Server,
 // to signal that HTTP upgrades are not supported.
 
+#[cfg(feature = "client")]
 pub(crate) struct Client<T>(T);
 
+#[cfg(feature = "server")]
 pub(crate) struct Server<T>(T);
 
+#[cfg(feature = "server")]
 impl<T> Http1Transaction for Server<T>
 where
     T: OnUpgrade,
@@ -34,15 +41,22 @@ where
         if buf.len() == 0 {
             return Ok(None);
         }
+        if ctx.lenient.is_enabled() && !lenient_head(buf, ctx.lenient, true) {
+            return Ok(None);
+        }
         // Unsafe: both headers_indices and headers are using unitialized memory,
         // but we *never* read any of it until after httparse has assigned
         // values into it. By not zeroing out the stack memory, this saves
         // a good ~5% on pipeline benchmarks.
         let mut headers_indices: [HeaderIndices; MAX_HEADERS] = unsafe { mem::uninitialized() };
+        let mut more_indices = Vec::new();
+        let max_headers = ctx.max_headers();
+        let headers_indices = headers_buf(&mut headers_indices, &mut more_indices, max_headers, HeaderIndices::EMPTY);
         let (len, subject, version, headers_len) = {
             let mut headers: [httparse::Header; MAX_HEADERS] = unsafe { mem::uninitialized() };
-            trace!("Request.parse([Header; {}], [u8; {}])", headers.len(), buf.len());
-            let mut req = httparse::Request::new(&mut headers);
+            let mut more_headers = Vec::new();
+            trace!("Request.parse([Header; {}], [u8; {}])", max_headers, buf.len());
+            let mut req = httparse::Request::new(headers_buf(&mut headers, &mut more_headers, max_headers, httparse::EMPTY_HEADER));
             let bytes = buf.as_ref();
             match req.parse(bytes)? {
                 httparse::Status::Complete(len) => {
@@ -56,7 +70,7 @@ where
                         Version::HTTP_10
                     };
 
-                    record_header_indices(bytes, &req.headers, &mut headers_indices);
+                    record_header_indices(bytes, &req.headers, headers_indices);
                     let headers_len = req.headers.len();
                     (len, subject, version, headers_len)
                 }
@@ -88,6 +102,16 @@ where
             .unwrap_or_else(HeaderMap::new);
 
         headers.reserve(headers_len);
+        let mut header_case = if ctx.preserve_header_case {
+            Some(HeaderCaseMap::new())
+        } else {
+            None
+        };
+        let mut header_order = if ctx.preserve_header_order {
+            Some(HeaderOrder::new())
+        } else {
+            None
+        };
 
         for header in &headers_indices[..headers_len] {
             let name = HeaderName::from_bytes(&slice[header.name.0..header.name.1])
@@ -156,6 +180,12 @@ where
                 _ => (),
             }
 
+            if let Some(ref mut header_case) = header_case {
+                header_case.append_bytes(&name, slice.slice(header.name.0, header.name.1));
+            }
+            if let Some(ref mut header_order) = header_order {
+                header_order.push(name.clone());
+            }
             headers.append(name, value);
         }
 
@@ -176,6 +206,9 @@ where
                 version,
                 subject,
                 headers,
+                header_case,
+                header_order,
+                reason: None,
             },
             decode: Decode::Normal(decoder),
             expect_continue,
@@ -187,11 +220,15 @@ where
         trace!("Server::encode body={:?}, method={:?}", msg.body, msg.req_method);
         debug_assert!(!msg.title_case_headers, "no server config for title case headers");
 
+        // A 2xx to a CONNECT turns the connection into a tunnel, right after
+        // the head, the same as an upgrade.
+        let is_tunnel = msg.req_method == &Some(Method::CONNECT) && msg.head.subject.is_success();
+
         // hyper currently doesn't support returning 1xx status codes as a Response
         // This is because Service only allows returning a single Response, and
         // so if you try to reply with a e.g. 100 Continue, you have no way of
         // replying with the latter status code response.
-        let (ret, mut is_last) = if StatusCode::SWITCHING_PROTOCOLS == msg.head.subject {
+        let (ret, mut is_last) = if StatusCode::SWITCHING_PROTOCOLS == msg.head.subject || is_tunnel {
             (T::on_encode_upgrade(&mut msg), true)
         } else if msg.head.subject.is_informational() {
             error!("response with 1xx status code not supported");
@@ -214,8 +251,14 @@ where
 
         let init_cap = 30 + msg.head.headers.len() * AVERAGE_HEADER_SIZE;
         dst.reserve(init_cap);
-        if msg.head.version == Version::HTTP_11 && msg.head.subject == StatusCode::OK {
-            extend(dst, b"HTTP/1.1 200 OK\r\n");
+        let reason = msg.head.reason.take();
+        let line = if reason.is_none() {
+            status_line(msg.head.version, msg.head.subject)
+        } else {
+            None
+        };
+        if let Some(line) = line {
+            extend(dst, line);
         } else {
             match msg.head.version {
                 Version::HTTP_10 => extend(dst, b"HTTP/1.0 "),
@@ -225,15 +268,24 @@ where
 
             extend(dst, msg.head.subject.as_str().as_bytes());
             extend(dst, b" ");
-            // a reason MUST be written, as many parsers will expect it.
-            extend(dst, msg.head.subject.canonical_reason().unwrap_or("<none>").as_bytes());
+            if let Some(ref reason) = reason {
+                extend(dst, reason.as_bytes());
+            } else {
+                // a reason MUST be written, as many parsers will expect it.
+                extend(dst, msg.head.subject.canonical_reason().unwrap_or("<none>").as_bytes());
+            }
             extend(dst, b"\r\n");
         }
 
+        let header_case = msg.head.header_case.take();
+        let header_order = msg.head.header_order.take();
+        let headers_start = dst.len();
         let mut encoder = Encoder::length(0);
         let mut wrote_len = false;
         let mut wrote_date = false;
+        let mut wrote_connection = false;
         'headers: for (name, mut values) in msg.head.headers.drain() {
+            let mut names = OrigNames::new(header_case.as_ref(), &name);
             match name {
                 header::CONTENT_LENGTH => {
                     if wrote_len {
@@ -241,6 +293,10 @@ where
                         rewind(dst);
                         return Err(::Error::new_header());
                     }
+                    if is_tunnel {
+                        debug!("removing content-length from response to CONNECT");
+                        continue 'headers;
+                    }
                     match msg.body {
                         Some(BodyLength::Known(known_len)) => {
                             // The Payload claims to know a length, and
@@ -276,7 +332,8 @@ where
                                         known_len,
                                         len,
                                     );
-                                    extend(dst, b"content-length: ");
+                                    extend_name(dst, names.next(), &name);
+                                    extend(dst, b": ");
                                     extend(dst, value.as_bytes());
                                     extend(dst, b"\r\n");
                                     wrote_len = true;
@@ -313,7 +370,8 @@ where
                             }
                             if let Some((len, value)) = folded {
                                 encoder = Encoder::length(len);
-                                extend(dst, b"content-length: ");
+                                extend_name(dst, names.next(), &name);
+                                extend(dst, b": ");
                                 extend(dst, value.as_bytes());
                                 extend(dst, b"\r\n");
                                 wrote_len = true;
@@ -357,7 +415,8 @@ where
                     wrote_len = true;
                     encoder = Encoder::chunked();
 
-                    extend(dst, b"transfer-encoding: ");
+                    extend_name(dst, names.next(), &name);
+                    extend(dst, b": ");
 
                     let mut saw_chunked;
                     if let Some(te) = values.next() {
@@ -381,8 +440,9 @@ where
                 },
                 header::CONNECTION => {
                     if !is_last {
+                        wrote_connection = true;
                         for value in values {
-                            extend(dst, name.as_str().as_bytes());
+                            extend_name(dst, names.next(), &name);
                             extend(dst, b": ");
                             extend(dst, value.as_bytes());
                             extend(dst, b"\r\n");
@@ -402,30 +462,38 @@ where
             //TODO: this should perhaps instead combine them into
             //single lines, as RFC7230 suggests is preferable.
             for value in values {
-                extend(dst, name.as_str().as_bytes());
+                extend_name(dst, names.next(), &name);
                 extend(dst, b": ");
                 extend(dst, value.as_bytes());
                 extend(dst, b"\r\n");
             }
         }
 
-        if !wrote_len {
+        if is_tunnel {
+            // Whatever follows the head is the tunnel, not a body.
+            encoder = Encoder::length(0);
+        } else if !wrote_len {
             encoder = match msg.body {
                 Some(BodyLength::Unknown) => {
                     if msg.head.version == Version::HTTP_10 || !Server::can_chunked(msg.req_method, msg.head.subject) {
                         Encoder::close_delimited()
                     } else {
-                        extend(dst, b"transfer-encoding: chunked\r\n");
+                        extend_added_name(dst, header_case.as_ref(), &header::TRANSFER_ENCODING);
+                        extend(dst, b": chunked\r\n");
                         Encoder::chunked()
                     }
                 },
                 None |
                 Some(BodyLength::Known(0)) => {
-                    extend(dst, b"content-length: 0\r\n");
+                    extend_added_name(dst, header_case.as_ref(), &header::CONTENT_LENGTH);
+                    extend(dst, b": 0\r\n");
                     Encoder::length(0)
                 },
                 Some(BodyLength::Known(len)) => {
-                    let _ = write!(FastWrite(dst), "content-length: {}\r\n", len);
+                    extend_added_name(dst, header_case.as_ref(), &header::CONTENT_LENGTH);
+                    extend(dst, b": ");
+                    extend_u64(dst, len);
+                    extend(dst, b"\r\n");
                     Encoder::length(len)
                 },
             };
@@ -436,19 +504,60 @@ where
             encoder = Encoder::length(0);
         }
 
+        if encoder.is_close_delimited() {
+            is_last = true;
+        } else if !is_last && !wrote_connection && msg.head.version == Version::HTTP_10 {
+            // HTTP/1.0 connections close after each response, unless told
+            // otherwise.
+            extend_added_name(dst, header_case.as_ref(), &header::CONNECTION);
+            extend(dst, b": keep-alive\r\n");
+        }
+
         // cached date is much faster than formatting every request
         if !wrote_date {
             dst.reserve(date::DATE_VALUE_LENGTH + 8);
-            extend(dst, b"date: ");
+            extend_added_name(dst, header_case.as_ref(), &header::DATE);
+            extend(dst, b": ");
             date::extend(dst);
-            extend(dst, b"\r\n\r\n");
-        } else {
             extend(dst, b"\r\n");
         }
+        if let Some(ref header_order) = header_order {
+            reorder_headers(dst, headers_start, header_order);
+        }
+        extend(dst, b"\r\n");
 
         ret.map(|()| encoder.set_last(is_last))
     }
 
+    fn encode_informational(head: &mut MessageHead<Self::Outgoing>, dst: &mut Vec<u8>) {
+        trace!("Server::encode_informational status={:?}", head.subject);
+        debug_assert!(head.subject.is_informational());
+
+        // An interim response never has a body, and doesn't change anything
+        // about the final response, so none of the framing or connection
+        // headers belong in it.
+        extend(dst, b"HTTP/1.1 ");
+        extend(dst, head.subject.as_str().as_bytes());
+        extend(dst, b" ");
+        if let Some(reason) = head.reason.take() {
+            extend(dst, reason.as_bytes());
+        } else {
+            let reason = match head.subject.as_u16() {
+                // Too new to be known by `http`.
+                103 => "Early Hints",
+                _ => head.subject.canonical_reason().unwrap_or("<none>"),
+            };
+            extend(dst, reason.as_bytes());
+        }
+        extend(dst, b"\r\n");
+
+        head.headers.remove(header::CONNECTION);
+        head.headers.remove(header::CONTENT_LENGTH);
+        head.headers.remove(header::TRANSFER_ENCODING);
+        write_headers(&head.headers, dst);
+        extend(dst, b"\r\n");
+    }
+
     fn on_error(err: &::Error) -> Option<MessageHead<Self::Outgoing>> {
         use ::error::{Kind, Parse};
         let status = match *err.kind() {
@@ -461,10 +570,13 @@ where
             Kind::Parse(Parse::TooLarge) => {
                 StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
             },
+            Kind::InvalidHeaderValue => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            },
             _ => return None,
         };
 
-        debug!("sending automatic response ({}) for parse error", status);
+        debug!("sending automatic response ({}) for error: {}", status, err);
         let mut msg = MessageHead::default();
         msg.subject = status;
         Some(msg)
@@ -483,6 +595,195 @@ where
     }
 }
 
+impl<'a> ParseContext<'a> {
+    // The most headers to parse.
+    fn max_headers(&self) -> usize {
+        self.max_headers.unwrap_or(MAX_HEADERS)
+    }
+}
+
+// The space to parse `max` headers into, which is on the stack unless more
+// headers than fit there are allowed.
+fn headers_buf<'a, T: Clone>(
+    stack: &'a mut [T; MAX_HEADERS],
+    heap: &'a mut Vec<T>,
+    max: usize,
+    empty: T,
+) -> &'a mut [T] {
+    if max <= MAX_HEADERS {
+        &mut stack[..max]
+    } else {
+        *heap = vec![empty; max];
+        &mut heap[..]
+    }
+}
+
+// The start and end of the reason phrase in the status line that httparse
+// accepted at the start of `bytes`, such as `HTTP/1.1 200 OK\r\n`.
+#[cfg(feature = "client")]
+fn reason_indices(bytes: &[u8]) -> (usize, usize) {
+    let start = bytes.iter().take_while(|&&b| b == b'\r' || b == b'\n').count();
+    let mut end = start + bytes[start..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .expect("complete status line");
+    if bytes[end - 1] == b'\r' {
+        end -= 1;
+    }
+    // `HTTP/1.1 200` is followed by a space, if there is a reason.
+    (::std::cmp::min(start + 13, end), end)
+}
+
+// Whether a response doesn't start with a status line, and so is all body,
+// as sent by HTTP/0.9 servers. `None` until there are enough bytes to tell.
+#[cfg(feature = "client")]
+fn is_http09_response(bytes: &[u8]) -> Option<bool> {
+    const PREFIX: &'static [u8] = b"HTTP/";
+    let start = bytes.iter().take_while(|&&b| b == b'\r' || b == b'\n').count();
+    let rest = &bytes[start..];
+    if rest.is_empty() {
+        None
+    } else if rest.len() < PREFIX.len() {
+        if PREFIX.starts_with(rest) {
+            None
+        } else {
+            Some(true)
+        }
+    } else {
+        Some(!rest.starts_with(PREFIX))
+    }
+}
+
+// Rewrites the head at the start of `buf` into one httparse accepts, as far
+// as the tolerances allow. Returns false if the head isn't complete yet, as
+// whether a line is folded is only known once the next one has arrived.
+//
+// Whitespace around header names is only tolerated in responses, a request
+// with it is left for httparse to reject.
+//
+// The head is read in a single pass over its lines, until the empty line
+// that ends it. Except for the request-target, lines are rewritten in place,
+// keeping the length of the head the same, and a rewritten line is left as
+// is when the head is read again after more bytes arrived.
+fn lenient_head(buf: &mut BytesMut, lenient: Lenient, is_request: bool) -> bool {
+    let start = buf.iter().take_while(|&&b| b == b'\r' || b == b'\n').count();
+    let trim_names = lenient.whitespace_around_header_names && !is_request;
+
+    {
+        let head = &mut buf[start..];
+        let mut line_start = match head.iter().position(|&b| b == b'\n') {
+            Some(i) => i + 1,
+            None => return false,
+        };
+        let mut first = true;
+        loop {
+            let line_end = match head[line_start..].iter().position(|&b| b == b'\n') {
+                Some(i) => line_start + i,
+                None => return false,
+            };
+            let len = line_end - line_start;
+            if len == 0 || (len == 1 && head[line_start] == b'\r') {
+                break;
+            }
+            if !first && lenient.obsolete_line_folding && is_whitespace(head[line_start]) {
+                // The line ending before a fold is replaced with spaces,
+                // leaving the rest of the value in the previous header.
+                let prev_end = line_start - 1;
+                head[prev_end] = b' ';
+                if head[prev_end - 1] == b'\r' {
+                    head[prev_end - 1] = b' ';
+                }
+            } else if trim_names {
+                trim_header_name(&mut head[line_start..line_end]);
+            }
+            first = false;
+            line_start = line_end + 1;
+        }
+    }
+
+    if is_request && lenient.spaces_in_request_target {
+        encode_request_target(buf, start);
+    }
+    true
+}
+
+fn is_whitespace(b: u8) -> bool {
+    b == b' ' || b == b'\t'
+}
+
+// Moves whitespace around a header name to after its colon, where it is
+// part of the whitespace before the value.
+fn trim_header_name(line: &mut [u8]) {
+    let colon = match line.iter().position(|&b| b == b':') {
+        Some(colon) => colon,
+        None => return,
+    };
+    let leading = line[..colon].iter().take_while(|&&b| is_whitespace(b)).count();
+    let trailing = line[leading..colon].iter().rev().take_while(|&&b| is_whitespace(b)).count();
+    if leading == 0 && trailing == 0 {
+        return;
+    }
+    let name = line[leading..colon - trailing].to_vec();
+    line[..name.len()].copy_from_slice(&name);
+    line[name.len()] = b':';
+    for b in &mut line[name.len() + 1..colon + 1] {
+        *b = b' ';
+    }
+}
+
+// Percent-encodes spaces inside the request-target, and drops extra ones
+// around it.
+fn encode_request_target(buf: &mut BytesMut, start: usize) {
+    let mut line_end = match buf[start..].iter().position(|&b| b == b'\n') {
+        Some(i) => start + i,
+        None => return,
+    };
+    if buf[line_end - 1] == b'\r' {
+        line_end -= 1;
+    }
+    let fixed = {
+        let line = &buf[start..line_end];
+        let method_end = match line.iter().position(|&b| b == b' ') {
+            Some(i) => i,
+            None => return,
+        };
+        let version_start = match line.iter().rposition(|&b| b == b' ') {
+            Some(i) if i > method_end && line[i + 1..].starts_with(b"HTTP/") => i + 1,
+            _ => return,
+        };
+        let target = &line[method_end + 1..version_start - 1];
+        let trimmed = {
+            let leading = target.iter().take_while(|&&b| b == b' ').count();
+            let trailing = target[leading..].iter().rev().take_while(|&&b| b == b' ').count();
+            &target[leading..target.len() - trailing]
+        };
+        if trimmed.len() == target.len() && !trimmed.contains(&b' ') {
+            return;
+        }
+
+        let mut fixed = Vec::with_capacity(line.len() + trimmed.len() * 2);
+        fixed.extend_from_slice(&line[..method_end + 1]);
+        for &b in trimmed {
+            if b == b' ' {
+                fixed.extend_from_slice(b"%20");
+            } else {
+                fixed.push(b);
+            }
+        }
+        fixed.push(b' ');
+        fixed.extend_from_slice(&line[version_start..]);
+        fixed
+    };
+    trace!("encoded request-target: {:?}", ::std::str::from_utf8(&fixed));
+
+    let mut head = BytesMut::with_capacity(buf.len() + fixed.len());
+    head.extend_from_slice(&buf[..start]);
+    head.extend_from_slice(&fixed);
+    head.extend_from_slice(&buf[line_end..]);
+    *buf = head;
+}
+
+#[cfg(feature = "server")]
 impl Server<()> {
     fn can_have_body(method: &Option<Method>, status: StatusCode) -> bool {
         Server::can_chunked(method, status)
@@ -506,6 +807,7 @@ impl Server<()> {
     }
 }
 
+#[cfg(feature = "client")]
 impl<T> Http1Transaction for Client<T>
 where
     T: OnUpgrade,
@@ -517,12 +819,42 @@ where
         if buf.len() == 0 {
             return Ok(None);
         }
+        if ctx.lenient.http09_responses {
+            match is_http09_response(buf) {
+                Some(true) => {
+                    trace!("Response.parse HTTP/0.9");
+                    let head = MessageHead {
+                        version: Version::HTTP_09,
+                        subject: StatusCode::OK,
+                        headers: ctx.cached_headers.take().unwrap_or_else(HeaderMap::new),
+                        header_case: None,
+                        header_order: None,
+                        reason: None,
+                    };
+                    return Ok(Some(ParsedMessage {
+                        head,
+                        decode: Decode::Normal(Decoder::eof()),
+                        expect_continue: false,
+                        keep_alive: false,
+                    }));
+                },
+                Some(false) => (),
+                None => return Ok(None),
+            }
+        }
+        if ctx.lenient.is_enabled() && !lenient_head(buf, ctx.lenient, false) {
+            return Ok(None);
+        }
         // Unsafe: see comment in Server Http1Transaction, above.
         let mut headers_indices: [HeaderIndices; MAX_HEADERS] = unsafe { mem::uninitialized() };
-        let (len, status, version, headers_len) = {
+        let mut more_indices = Vec::new();
+        let max_headers = ctx.max_headers();
+        let headers_indices = headers_buf(&mut headers_indices, &mut more_indices, max_headers, HeaderIndices::EMPTY);
+        let (len, status, version, headers_len, reason) = {
             let mut headers: [httparse::Header; MAX_HEADERS] = unsafe { mem::uninitialized() };
-            trace!("Response.parse([Header; {}], [u8; {}])", headers.len(), buf.len());
-            let mut res = httparse::Response::new(&mut headers);
+            let mut more_headers = Vec::new();
+            trace!("Response.parse([Header; {}], [u8; {}])", max_headers, buf.len());
+            let mut res = httparse::Response::new(headers_buf(&mut headers, &mut more_headers, max_headers, httparse::EMPTY_HEADER));
             let bytes = buf.as_ref();
             match res.parse(bytes)? {
                 httparse::Status::Complete(len) => {
@@ -533,9 +865,9 @@ where
                     } else {
                         Version::HTTP_10
                     };
-                    record_header_indices(bytes, &res.headers, &mut headers_indices);
+                    record_header_indices(bytes, &res.headers, headers_indices);
                     let headers_len = res.headers.len();
-                    (len, status, version, headers_len)
+                    (len, status, version, headers_len, reason_indices(bytes))
                 },
                 httparse::Status::Partial => return Ok(None),
             }
@@ -543,19 +875,49 @@ where
 
         let slice = buf.split_to(len).freeze();
 
+        let (reason_start, reason_end) = reason;
+        let reason = if status.canonical_reason().map(str::as_bytes) == Some(&slice[reason_start..reason_end]) {
+            None
+        } else {
+            Some(ReasonPhrase::from_shared_unchecked(slice.slice(reason_start, reason_end)))
+        };
+
         let mut headers = ctx.cached_headers
             .take()
             .unwrap_or_else(HeaderMap::new);
 
         headers.reserve(headers_len);
-        fill_headers(&mut headers, slice, &headers_indices[..headers_len]);
+        let mut header_case = if ctx.preserve_header_case {
+            Some(HeaderCaseMap::new())
+        } else {
+            None
+        };
+        let mut header_order = if ctx.preserve_header_order {
+            Some(HeaderOrder::new())
+        } else {
+            None
+        };
+        fill_headers(&mut headers, header_case.as_mut(), header_order.as_mut(), slice, &headers_indices[..headers_len]);
 
-        let keep_alive = version == Version::HTTP_11;
+        let mut keep_alive = version == Version::HTTP_11;
+        for value in headers.get_all(header::CONNECTION) {
+            // keep_alive was previously set to default for Version
+            if keep_alive {
+                // HTTP/1.1
+                keep_alive = !headers::connection_close(value);
+            } else {
+                // HTTP/1.0
+                keep_alive = headers::connection_keep_alive(value);
+            }
+        }
 
         let head = MessageHead {
             version,
             subject: status,
             headers,
+            header_case,
+            header_order,
+            reason,
         };
         let decode = Client::<T>::decoder(&head, ctx.req_method)?;
 
@@ -572,6 +934,12 @@ where
 
         *msg.req_method = Some(msg.head.subject.0.clone());
 
+        // HTTP/1.0 connections close after each response, unless told
+        // otherwise.
+        if msg.keep_alive && msg.head.version == Version::HTTP_10 && !msg.head.headers.contains_key(header::CONNECTION) {
+            msg.head.headers.insert(header::CONNECTION, HeaderValue::from_static("keep-alive"));
+        }
+
         let body = Client::set_length(msg.head, msg.body);
 
         let init_cap = 30 + msg.head.headers.len() * AVERAGE_HEADER_SIZE;
@@ -590,17 +958,27 @@ where
         }
         extend(dst, b"\r\n");
 
-        if msg.title_case_headers {
+        let headers_start = dst.len();
+        if let Some(ref header_case) = msg.head.header_case {
+            write_headers_original_case(&msg.head.headers, header_case, msg.title_case_headers, dst);
+        } else if msg.title_case_headers {
             write_headers_title_case(&msg.head.headers, dst);
         } else {
             write_headers(&msg.head.headers, dst);
         }
+        if let Some(ref header_order) = msg.head.header_order {
+            reorder_headers(dst, headers_start, header_order);
+        }
         extend(dst, b"\r\n");
         msg.head.headers.clear(); //TODO: remove when switching to drain()
 
         Ok(body)
     }
 
+    fn encode_informational(_head: &mut MessageHead<Self::Outgoing>, _dst: &mut Vec<u8>) {
+        unreachable!("clients don't send interim responses");
+    }
+
     fn on_error(_err: &::Error) -> Option<MessageHead<Self::Outgoing>> {
         // we can't tell the server about any errors it creates
         None
@@ -615,6 +993,7 @@ where
     }
 }
 
+#[cfg(feature = "client")]
 impl<T: OnUpgrade> Client<T> {
     fn decoder(inc: &MessageHead<StatusCode>, method: &mut Option<Method>) -> Result<Decode, Parse> {
         // According to https://tools.ietf.org/html/rfc7230#section-3.3.3
@@ -680,6 +1059,7 @@ impl<T: OnUpgrade> Client<T> {
     }
 }
 
+#[cfg(feature = "client")]
 impl Client<()> {
     fn set_length(head: &mut RequestHead, body: Option<BodyLength>) -> Encoder {
         if let Some(body) = body {
@@ -695,6 +1075,7 @@ impl Client<()> {
     }
 }
 
+#[cfg(feature = "client")]
 fn set_length(headers: &mut HeaderMap, body: BodyLength, can_chunked: bool) -> Encoder {
     // If the user already set specific headers, we should respect them, regardless
     // of what the Payload knows about itself. They set them for a reason.
@@ -794,6 +1175,7 @@ fn set_length(headers: &mut HeaderMap, body: BodyLength, can_chunked: bool) -> E
     }
 }
 
+#[cfg(feature = "client")]
 fn set_content_length(headers: &mut HeaderMap, len: u64) -> Encoder {
     // At this point, there should not be a valid Content-Length
     // header. However, since we'll be indexing in anyways, we can
@@ -829,19 +1211,25 @@ fn set_content_length(headers: &mut HeaderMap, len: u64) -> Encoder {
 }
 
 pub(crate) trait OnUpgrade {
+    #[cfg(feature = "server")]
+    #[cfg(feature = "server")]
     fn on_encode_upgrade(msg: &mut Encode<StatusCode>) -> ::Result<()>;
+    #[cfg(feature = "client")]
     fn on_decode_upgrade() -> Result<Decoder, Parse>;
 }
 
 pub(crate) enum YesUpgrades {}
 
+#[cfg(feature = "client")]
 pub(crate) enum NoUpgrades {}
 
 impl OnUpgrade for YesUpgrades {
+    #[cfg(feature = "server")]
     fn on_encode_upgrade(_: &mut Encode<StatusCode>) -> ::Result<()> {
         Ok(())
     }
 
+    #[cfg(feature = "client")]
     fn on_decode_upgrade() -> Result<Decoder, Parse> {
         debug!("101 response received, upgrading");
         // 101 upgrades always have no body
@@ -849,7 +1237,9 @@ impl OnUpgrade for YesUpgrades {
     }
 }
 
+#[cfg(feature = "client")]
 impl OnUpgrade for NoUpgrades {
+    #[cfg(feature = "server")]
     fn on_encode_upgrade(msg: &mut Encode<StatusCode>) -> ::Result<()> {
         error!("response with 101 status code not supported");
         *msg.head = MessageHead::default();
@@ -859,6 +1249,7 @@ impl OnUpgrade for NoUpgrades {
         Err(::Error::new_status())
     }
 
+    #[cfg(feature = "client")]
     fn on_decode_upgrade() -> Result<Decoder, Parse> {
         debug!("received 101 upgrade response, not supported");
         Err(Parse::UpgradeNotSupported)
@@ -871,6 +1262,13 @@ struct HeaderIndices {
     value: (usize, usize),
 }
 
+impl HeaderIndices {
+    const EMPTY: HeaderIndices = HeaderIndices {
+        name: (0, 0),
+        value: (0, 0),
+    };
+}
+
 fn record_header_indices(bytes: &[u8], headers: &[httparse::Header], indices: &mut [HeaderIndices]) {
     let bytes_ptr = bytes.as_ptr() as usize;
     for (header, indices) in headers.iter().zip(indices.iter_mut()) {
@@ -883,10 +1281,23 @@ fn record_header_indices(bytes: &[u8], headers: &[httparse::Header], indices: &m
     }
 }
 
-fn fill_headers(headers: &mut HeaderMap, slice: Bytes, indices: &[HeaderIndices]) {
+#[cfg(feature = "client")]
+fn fill_headers(
+    headers: &mut HeaderMap,
+    mut header_case: Option<&mut HeaderCaseMap>,
+    mut header_order: Option<&mut HeaderOrder>,
+    slice: Bytes,
+    indices: &[HeaderIndices],
+) {
     for header in indices {
         let name = HeaderName::from_bytes(&slice[header.name.0..header.name.1])
             .expect("header name already validated");
+        if let Some(ref mut header_case) = header_case {
+            header_case.append_bytes(&name, slice.slice(header.name.0, header.name.1));
+        }
+        if let Some(ref mut header_order) = header_order {
+            header_order.push(name.clone());
+        }
         let value = unsafe {
             HeaderValue::from_shared_unchecked(
                 slice.slice(header.value.0, header.value.1)
@@ -900,6 +1311,7 @@ fn fill_headers(headers: &mut HeaderMap, slice: Bytes, indices: &[HeaderIndices]
 // therefore it is trivial to convert an ASCII character from lowercase to
 // uppercase. It is as simple as XORing the lowercase character byte with
 // space.
+#[cfg(feature = "client")]
 fn title_case(dst: &mut Vec<u8>, name: &[u8]) {
     dst.reserve(name.len());
 
@@ -925,6 +1337,7 @@ fn title_case(dst: &mut Vec<u8>, name: &[u8]) {
     }
 }
 
+#[cfg(feature = "client")]
 fn write_headers_title_case(headers: &HeaderMap, dst: &mut Vec<u8>) {
     for (name, value) in headers {
         title_case(dst, name.as_str().as_bytes());
@@ -943,8 +1356,105 @@ fn write_headers(headers: &HeaderMap, dst: &mut Vec<u8>) {
     }
 }
 
+#[cfg(feature = "client")]
+fn write_headers_original_case(headers: &HeaderMap, header_case: &HeaderCaseMap, title_case_headers: bool, dst: &mut Vec<u8>) {
+    for name in headers.keys() {
+        let mut names = OrigNames::new(Some(header_case), name);
+        for value in headers.get_all(name) {
+            match names.next() {
+                Some(orig) => extend(dst, orig),
+                None if title_case_headers => title_case(dst, name.as_str().as_bytes()),
+                None => extend(dst, name.as_str().as_bytes()),
+            }
+            extend(dst, b": ");
+            extend(dst, value.as_bytes());
+            extend(dst, b"\r\n");
+        }
+    }
+}
+
+// The casings of the names of a header's values, from a `HeaderCaseMap`. A
+// value without one uses the casing of the previous value.
+struct OrigNames<'a> {
+    names: Option<ValueIter<'a, Bytes>>,
+    last: Option<&'a Bytes>,
+}
+
+impl<'a> OrigNames<'a> {
+    fn new(header_case: Option<&'a HeaderCaseMap>, name: &HeaderName) -> OrigNames<'a> {
+        OrigNames {
+            names: header_case.map(|map| map.names(name)),
+            last: None,
+        }
+    }
+
+    fn next(&mut self) -> Option<&'a Bytes> {
+        if let Some(orig) = self.names.as_mut().and_then(|names| names.next()) {
+            self.last = Some(orig);
+        }
+        self.last
+    }
+}
+
+#[cfg(feature = "server")]
+fn extend_name(dst: &mut Vec<u8>, orig: Option<&Bytes>, name: &HeaderName) {
+    match orig {
+        Some(orig) => extend(dst, orig),
+        None => extend(dst, name.as_str().as_bytes()),
+    }
+}
+
+// Write the name of a header that wasn't in the message, like the `Date`
+// added to every response.
+#[cfg(feature = "server")]
+fn extend_added_name(dst: &mut Vec<u8>, header_case: Option<&HeaderCaseMap>, name: &HeaderName) {
+    extend_name(dst, OrigNames::new(header_case, name).next(), name);
+}
+
+// Put the header lines written to `dst` since `start` in the order of a
+// `HeaderOrder`. Lines it doesn't have stay last, in the order they were
+// written.
+fn reorder_headers(dst: &mut Vec<u8>, start: usize, header_order: &HeaderOrder) {
+    let ranks = header_order
+        .iter()
+        .cloned()
+        .enumerate()
+        .map(|(rank, entry)| (entry, rank))
+        .collect::<HashMap<_, _>>();
+    let mut counts = HashMap::new();
+    let mut lines = Vec::new();
+    let mut pos = start;
+    while pos < dst.len() {
+        let end = pos + dst[pos..]
+            .windows(2)
+            .position(|crlf| crlf == b"\r\n")
+            .expect("header line ends with CRLF") + 2;
+        let colon = pos + dst[pos..end]
+            .iter()
+            .position(|&b| b == b':')
+            .expect("header line has a colon");
+        let name = HeaderName::from_bytes(&dst[pos..colon])
+            .expect("header name already validated");
+        let index = counts.entry(name.clone()).or_insert(0);
+        let rank = ranks.get(&(name, *index)).cloned().unwrap_or(::std::usize::MAX);
+        *index += 1;
+        lines.push((rank, pos..end));
+        pos = end;
+    }
+    lines.sort_by_key(|&(rank, _)| rank);
+
+    let mut reordered = Vec::with_capacity(dst.len() - start);
+    for (_, line) in lines {
+        reordered.extend_from_slice(&dst[line]);
+    }
+    dst.truncate(start);
+    dst.extend_from_slice(&reordered);
+}
+
+#[cfg(feature = "client")]
 struct FastWrite<'a>(&'a mut Vec<u8>);
 
+#[cfg(feature = "client")]
 impl<'a> fmt::Write for FastWrite<'a> {
     #[inline]
     fn write_str(&mut self, s: &str) -> fmt::Result {
@@ -963,7 +1473,54 @@ fn extend(dst: &mut Vec<u8>, data: &[u8]) {
     dst.extend_from_slice(data);
 }
 
-#[cfg(test)]
+/// Write the decimal digits of `n`, without going through `fmt`.
+#[cfg(feature = "server")]
+#[inline]
+fn extend_u64(dst: &mut Vec<u8>, mut n: u64) {
+    if n < 10 {
+        dst.push(b'0' + n as u8);
+        return;
+    }
+    // u64::MAX is 20 digits
+    let mut buf = [0u8; 20];
+    let mut pos = buf.len();
+    while n > 0 {
+        pos -= 1;
+        buf[pos] = b'0' + (n % 10) as u8;
+        n /= 10;
+    }
+    extend(dst, &buf[pos..]);
+}
+
+/// Precomputed status lines for the most commonly sent responses.
+///
+/// Anything not in this table is written piece by piece.
+#[cfg(feature = "server")]
+fn status_line(version: Version, status: StatusCode) -> Option<&'static [u8]> {
+    if version != Version::HTTP_11 {
+        return None;
+    }
+    let line: &'static [u8] = match status.as_u16() {
+        200 => b"HTTP/1.1 200 OK\r\n",
+        201 => b"HTTP/1.1 201 Created\r\n",
+        204 => b"HTTP/1.1 204 No Content\r\n",
+        206 => b"HTTP/1.1 206 Partial Content\r\n",
+        301 => b"HTTP/1.1 301 Moved Permanently\r\n",
+        302 => b"HTTP/1.1 302 Found\r\n",
+        304 => b"HTTP/1.1 304 Not Modified\r\n",
+        400 => b"HTTP/1.1 400 Bad Request\r\n",
+        401 => b"HTTP/1.1 401 Unauthorized\r\n",
+        403 => b"HTTP/1.1 403 Forbidden\r\n",
+        404 => b"HTTP/1.1 404 Not Found\r\n",
+        500 => b"HTTP/1.1 500 Internal Server Error\r\n",
+        502 => b"HTTP/1.1 502 Bad Gateway\r\n",
+        503 => b"HTTP/1.1 503 Service Unavailable\r\n",
+        _ => return None,
+    };
+    Some(line)
+}
+
+#[cfg(all(test, feature = "client", feature = "server"))]
 mod tests {
     use bytes::BytesMut;
 
@@ -982,6 +1539,10 @@ mod tests {
         let msg = Server::parse(&mut raw, ParseContext {
             cached_headers: &mut None,
             req_method: &mut method,
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: Lenient::default(),
         }).unwrap().unwrap();
         assert_eq!(raw.len(), 0);
         assert_eq!(msg.head.subject.0, ::Method::GET);
@@ -992,6 +1553,30 @@ mod tests {
         assert_eq!(method, Some(::Method::GET));
     }
 
+    #[test]
+    fn test_parse_request_asterisk_form() {
+        fn parse(s: &str) -> ParseResult<RequestLine> {
+            Server::parse(&mut BytesMut::from(s.as_bytes()), ParseContext {
+                cached_headers: &mut None,
+                req_method: &mut None,
+                max_headers: None,
+                preserve_header_case: false,
+                preserve_header_order: false,
+                lenient: Lenient::default(),
+            })
+        }
+
+        let msg = parse("OPTIONS * HTTP/1.1\r\nHost: hyper.rs\r\n\r\n").unwrap().unwrap();
+        assert_eq!(msg.head.subject.0, ::Method::OPTIONS);
+        assert_eq!(msg.head.subject.1, "*");
+
+        // Only OPTIONS is meant to be sent with it, but other methods are
+        // left for the service to answer.
+        let msg = parse("GET * HTTP/1.1\r\nHost: hyper.rs\r\n\r\n").unwrap().unwrap();
+        assert_eq!(msg.head.subject.0, ::Method::GET);
+        assert_eq!(msg.head.subject.1, "*");
+    }
+
 
     #[test]
     fn test_parse_response() {
@@ -1001,6 +1586,10 @@ mod tests {
         let ctx = ParseContext {
             cached_headers: &mut None,
             req_method: &mut Some(::Method::GET),
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: Lenient::default(),
         };
         let msg = Client::parse(&mut raw, ctx).unwrap().unwrap();
         assert_eq!(raw.len(), 0);
@@ -1010,12 +1599,205 @@ mod tests {
         assert_eq!(msg.head.headers["Content-Length"], "0");
     }
 
+    #[test]
+    fn test_parse_preserve_header_case() {
+        let mut raw = BytesMut::from(b"GET / HTTP/1.1\r\nX-Legacy-ID: 1\r\nx-legacy-id: 2\r\nHOST: hyper.rs\r\n\r\n".to_vec());
+        let msg = Server::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            max_headers: None,
+            preserve_header_case: true,
+            preserve_header_order: false,
+            lenient: Lenient::default(),
+        }).unwrap().unwrap();
+        let header_case = msg.head.header_case.expect("header_case");
+        assert_eq!(header_case.get_all(&"x-legacy-id".parse().unwrap()), vec!["X-Legacy-ID", "x-legacy-id"]);
+        assert_eq!(header_case.get(&header::HOST), Some("HOST"));
+
+        let mut raw = BytesMut::from(b"HTTP/1.1 200 OK\r\nCONTENT-length: 0\r\n\r\n".to_vec());
+        let msg = Client::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut Some(::Method::GET),
+            max_headers: None,
+            preserve_header_case: true,
+            preserve_header_order: false,
+            lenient: Lenient::default(),
+        }).unwrap().unwrap();
+        let header_case = msg.head.header_case.expect("header_case");
+        assert_eq!(header_case.get(&header::CONTENT_LENGTH), Some("CONTENT-length"));
+
+        let mut raw = BytesMut::from(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".to_vec());
+        let msg = Client::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut Some(::Method::GET),
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: Lenient::default(),
+        }).unwrap().unwrap();
+        assert_eq!(msg.head.header_case, None);
+    }
+
+    #[test]
+    fn test_parse_max_headers() {
+        let mut req = b"GET / HTTP/1.1\r\n".to_vec();
+        for i in 0..150 {
+            req.extend_from_slice(format!("x-header-{}: {}\r\n", i, i).as_bytes());
+        }
+        req.extend_from_slice(b"\r\n");
+
+        let msg = Server::parse(&mut BytesMut::from(req.clone()), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            max_headers: Some(150),
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: Lenient::default(),
+        }).unwrap().unwrap();
+        assert_eq!(msg.head.headers.len(), 150);
+        assert_eq!(msg.head.headers["x-header-149"], "149");
+
+        let err = Server::parse(&mut BytesMut::from(req.clone()), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: Lenient::default(),
+        }).unwrap_err();
+        match err {
+            ::error::Parse::TooLarge => (),
+            other => panic!("expected TooLarge, got {:?}", other),
+        }
+
+        let err = Server::parse(&mut BytesMut::from(req), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            max_headers: Some(2),
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: Lenient::default(),
+        }).unwrap_err();
+        match err {
+            ::error::Parse::TooLarge => (),
+            other => panic!("expected TooLarge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        let raw = b"GET /a b  HTTP/1.1\r\nHost: hyper.rs\r\nX-Fold: a\r\n\tb\r\n\r\n".to_vec();
+        let lenient = Lenient {
+            obsolete_line_folding: true,
+            spaces_in_request_target: true,
+            whitespace_around_header_names: true,
+            ..Lenient::default()
+        };
+
+        let msg = Server::parse(&mut BytesMut::from(raw.clone()), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: lenient,
+        }).unwrap().unwrap();
+        assert_eq!(msg.head.subject.1, "/a%20b");
+        assert_eq!(msg.head.headers["host"], "hyper.rs");
+        assert_eq!(msg.head.headers["x-fold"], "a  \tb");
+
+        Server::parse(&mut BytesMut::from(raw.clone()), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: Lenient::default(),
+        }).unwrap_err();
+
+        // Waits for the whole head, to know which lines are folded.
+        let mut partial = BytesMut::from(&raw[..raw.len() - 2]);
+        let msg = Server::parse(&mut partial, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: lenient,
+        }).unwrap();
+        assert!(msg.is_none());
+
+        // Whitespace around header names is never allowed in requests.
+        for raw in &[&b"GET / HTTP/1.1\r\nHost : hyper.rs\r\n\r\n"[..], b"GET / HTTP/1.1\r\n Host: hyper.rs\r\n\r\n"] {
+            Server::parse(&mut BytesMut::from(raw.to_vec()), ParseContext {
+                cached_headers: &mut None,
+                req_method: &mut None,
+                max_headers: None,
+                preserve_header_case: false,
+                preserve_header_order: false,
+                lenient: lenient,
+            }).unwrap_err();
+        }
+
+        let mut raw = BytesMut::from(b"HTTP/1.1 200\r\n Date: today\r\nServer : legacy\r\n\r\n".to_vec());
+        let msg = Client::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut Some(::Method::GET),
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: lenient,
+        }).unwrap().unwrap();
+        assert_eq!(msg.head.subject, ::StatusCode::OK);
+        assert_eq!(msg.head.headers["server"], "legacy");
+        assert_eq!(msg.head.headers["date"], "today");
+    }
+
+    #[test]
+    fn test_parse_preserve_header_order() {
+        let mut raw = BytesMut::from(b"GET / HTTP/1.1\r\nx-a: 1\r\nhost: hyper.rs\r\nX-A: 2\r\n\r\n".to_vec());
+        let msg = Server::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: true,
+            lenient: Lenient::default(),
+        }).unwrap().unwrap();
+        let header_order = msg.head.header_order.expect("header_order");
+        let x_a = HeaderName::from_static("x-a");
+        assert_eq!(
+            header_order.iter().cloned().collect::<Vec<_>>(),
+            vec![(x_a.clone(), 0), (header::HOST, 0), (x_a, 1)]
+        );
+
+        let mut raw = BytesMut::from(b"HTTP/1.1 200 OK\r\nserver: a\r\ncontent-length: 0\r\n\r\n".to_vec());
+        let msg = Client::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut Some(::Method::GET),
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: true,
+            lenient: Lenient::default(),
+        }).unwrap().unwrap();
+        let header_order = msg.head.header_order.expect("header_order");
+        assert_eq!(
+            header_order.iter().cloned().collect::<Vec<_>>(),
+            vec![(header::SERVER, 0), (header::CONTENT_LENGTH, 0)]
+        );
+        assert_eq!(msg.head.header_case, None);
+    }
+
     #[test]
     fn test_parse_request_errors() {
         let mut raw = BytesMut::from(b"GET htt:p// HTTP/1.1\r\nHost: hyper.rs\r\n\r\n".to_vec());
         let ctx = ParseContext {
             cached_headers: &mut None,
             req_method: &mut None,
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: Lenient::default(),
         };
         Server::parse(&mut raw, ctx).unwrap_err();
     }
@@ -1030,6 +1812,10 @@ mod tests {
             Server::parse(&mut bytes, ParseContext {
                 cached_headers: &mut None,
                 req_method: &mut None,
+                max_headers: None,
+                preserve_header_case: false,
+                preserve_header_order: false,
+                lenient: Lenient::default(),
             })
                 .expect("parse ok")
                 .expect("parse complete")
@@ -1040,6 +1826,10 @@ mod tests {
             Server::parse(&mut bytes, ParseContext {
                 cached_headers: &mut None,
                 req_method: &mut None,
+                max_headers: None,
+                preserve_header_case: false,
+                preserve_header_order: false,
+                lenient: Lenient::default(),
             })
                 .expect_err(comment)
         }
@@ -1166,6 +1956,10 @@ mod tests {
             Client::parse(&mut bytes, ParseContext {
                 cached_headers: &mut None,
                 req_method: &mut Some(m),
+                max_headers: None,
+                preserve_header_case: false,
+                preserve_header_order: false,
+                lenient: Lenient::default(),
             })
                 .expect("parse ok")
                 .expect("parse complete")
@@ -1176,6 +1970,10 @@ mod tests {
             Client::parse(&mut bytes, ParseContext {
                 cached_headers: &mut None,
                 req_method: &mut Some(Method::GET),
+                max_headers: None,
+                preserve_header_case: false,
+                preserve_header_order: false,
+                lenient: Lenient::default(),
             })
                 .expect_err("parse should err")
         }
@@ -1309,6 +2107,366 @@ mod tests {
         assert_eq!(vec, b"GET / HTTP/1.1\r\nContent-Length: 10\r\nContent-Type: application/json\r\n\r\n".to_vec());
     }
 
+    #[test]
+    fn test_client_request_encode_original_case() {
+        use http::header::HeaderValue;
+        use proto::{BodyLength, HeaderCaseMap};
+
+        let mut head = MessageHead::default();
+        head.headers.insert("content-type", HeaderValue::from_static("application/json"));
+        head.headers.append("x-legacy-id", HeaderValue::from_static("1"));
+        head.headers.append("x-legacy-id", HeaderValue::from_static("2"));
+        let mut header_case = HeaderCaseMap::new();
+        header_case.append("X-LEGACY-id").unwrap();
+        header_case.append("content-LENGTH").unwrap();
+        head.header_case = Some(header_case);
+
+        let mut vec = Vec::new();
+        Client::encode(Encode {
+            head: &mut head,
+            body: Some(BodyLength::Known(10)),
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: true,
+        }, &mut vec).unwrap();
+
+        assert_eq!(
+            vec,
+            b"GET / HTTP/1.1\r\nContent-Type: application/json\r\nX-LEGACY-id: 1\r\nX-LEGACY-id: 2\r\ncontent-LENGTH: 10\r\n\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_server_response_encode_original_case() {
+        use http::header::HeaderValue;
+        use proto::{BodyLength, HeaderCaseMap};
+
+        let mut head = MessageHead::default();
+        head.headers.insert("content-type", HeaderValue::from_static("text/plain"));
+        head.headers.insert("x-legacy-id", HeaderValue::from_static("1"));
+        let mut header_case = HeaderCaseMap::new();
+        header_case.append("Content-TYPE").unwrap();
+        header_case.append("X-Legacy-ID").unwrap();
+        header_case.append("Content-Length").unwrap();
+        header_case.append("DATE").unwrap();
+        head.header_case = Some(header_case);
+
+        let mut vec = Vec::new();
+        Server::encode(Encode {
+            head: &mut head,
+            body: Some(BodyLength::Known(5)),
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: false,
+        }, &mut vec).unwrap();
+
+        let s = ::std::str::from_utf8(&vec).unwrap();
+        assert!(s.contains("\r\nContent-TYPE: text/plain\r\n"), "{:?}", s);
+        assert!(s.contains("\r\nX-Legacy-ID: 1\r\n"), "{:?}", s);
+        assert!(s.contains("\r\nContent-Length: 5\r\n"), "{:?}", s);
+        assert!(s.contains("\r\nDATE: "), "{:?}", s);
+    }
+
+    #[test]
+    fn test_client_request_encode_original_order() {
+        use http::header::HeaderValue;
+        use proto::{BodyLength, HeaderOrder};
+
+        let mut head = MessageHead::default();
+        head.headers.append("x-a", HeaderValue::from_static("1"));
+        head.headers.append("x-a", HeaderValue::from_static("2"));
+        head.headers.insert("accept", HeaderValue::from_static("*/*"));
+        head.headers.insert("user-agent", HeaderValue::from_static("hyper"));
+        let mut header_order = HeaderOrder::new();
+        header_order.push(HeaderName::from_static("x-a"));
+        header_order.push(header::CONTENT_LENGTH);
+        header_order.push(header::ACCEPT);
+        header_order.push(HeaderName::from_static("x-a"));
+        head.header_order = Some(header_order);
+
+        let mut vec = Vec::new();
+        Client::encode(Encode {
+            head: &mut head,
+            body: Some(BodyLength::Known(10)),
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: false,
+        }, &mut vec).unwrap();
+
+        assert_eq!(
+            vec,
+            b"GET / HTTP/1.1\r\nx-a: 1\r\ncontent-length: 10\r\naccept: */*\r\nx-a: 2\r\nuser-agent: hyper\r\n\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_server_response_encode_original_order() {
+        use http::header::HeaderValue;
+        use proto::{BodyLength, HeaderCaseMap, HeaderOrder};
+
+        let mut head = MessageHead::default();
+        head.headers.insert("content-type", HeaderValue::from_static("text/plain"));
+        head.headers.insert("server", HeaderValue::from_static("hyper"));
+        let mut header_case = HeaderCaseMap::new();
+        header_case.append("Server").unwrap();
+        head.header_case = Some(header_case);
+        let mut header_order = HeaderOrder::new();
+        header_order.push(header::SERVER);
+        header_order.push(header::CONTENT_LENGTH);
+        header_order.push(header::CONTENT_TYPE);
+        head.header_order = Some(header_order);
+
+        let mut vec = Vec::new();
+        Server::encode(Encode {
+            head: &mut head,
+            body: Some(BodyLength::Known(5)),
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: false,
+        }, &mut vec).unwrap();
+
+        let s = ::std::str::from_utf8(&vec).unwrap();
+        assert!(
+            s.starts_with("HTTP/1.1 200 OK\r\nServer: hyper\r\ncontent-length: 5\r\ncontent-type: text/plain\r\ndate: "),
+            "{:?}",
+            s
+        );
+        assert!(s.ends_with(" GMT\r\n\r\n"), "{:?}", s);
+    }
+
+    #[test]
+    fn test_server_response_encode_reason_phrase() {
+        use proto::ReasonPhrase;
+
+        let mut head = MessageHead::default();
+        head.reason = Some(ReasonPhrase::from_static("Alright Then"));
+
+        let mut vec = Vec::new();
+        Server::encode(Encode {
+            head: &mut head,
+            body: None,
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: false,
+        }, &mut vec).unwrap();
+
+        let s = ::std::str::from_utf8(&vec).unwrap();
+        assert!(s.starts_with("HTTP/1.1 200 Alright Then\r\n"), "{:?}", s);
+    }
+
+    #[test]
+    fn test_server_response_encode_connect_tunnel() {
+        let mut head = MessageHead::default();
+        head.headers.insert("content-length", HeaderValue::from_static("5"));
+
+        let mut vec = Vec::new();
+        let encoder = S::<YesUpgrades>::encode(Encode {
+            head: &mut head,
+            body: Some(BodyLength::Known(5)),
+            keep_alive: true,
+            req_method: &mut Some(Method::CONNECT),
+            title_case_headers: false,
+        }, &mut vec).unwrap();
+
+        let s = ::std::str::from_utf8(&vec).unwrap();
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", s);
+        assert!(!s.contains("content-length"), "{:?}", s);
+        // The tunnel follows the head, and the connection isn't HTTP anymore.
+        assert!(encoder.is_eof());
+        assert!(encoder.is_last());
+
+        // Without upgrades, it can't become a tunnel.
+        let mut head = MessageHead::default();
+        Server::encode(Encode {
+            head: &mut head,
+            body: None,
+            keep_alive: true,
+            req_method: &mut Some(Method::CONNECT),
+            title_case_headers: false,
+        }, &mut Vec::new()).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_reason_phrase() {
+        fn parse_reason(raw: &'static [u8]) -> Option<Vec<u8>> {
+            let mut raw = BytesMut::from(raw.to_vec());
+            let msg = Client::parse(&mut raw, ParseContext {
+                cached_headers: &mut None,
+                req_method: &mut Some(::Method::GET),
+                max_headers: None,
+                preserve_header_case: false,
+                preserve_header_order: false,
+                lenient: Lenient::default(),
+            }).unwrap().unwrap();
+            assert_eq!(raw.len(), 0);
+            msg.head.reason.map(|reason| reason.as_bytes().to_vec())
+        }
+
+        assert_eq!(parse_reason(b"HTTP/1.1 200 OK\r\n\r\n"), None);
+        assert_eq!(parse_reason(b"HTTP/1.1 200 Alright Then\r\n\r\n"), Some(b"Alright Then".to_vec()));
+        assert_eq!(parse_reason(b"\r\nHTTP/1.1 404 Gone Fishing\n\n"), Some(b"Gone Fishing".to_vec()));
+        assert_eq!(parse_reason(b"HTTP/1.1 200 Caf\xe9\r\n\r\n"), Some(b"Caf\xe9".to_vec()));
+        assert_eq!(parse_reason(b"HTTP/1.1 200\r\n\r\n"), Some(b"".to_vec()));
+        assert_eq!(parse_reason(b"HTTP/1.1 200 \r\n\r\n"), Some(b"".to_vec()));
+    }
+
+    #[test]
+    fn test_parse_http09_response() {
+        let lenient = Lenient {
+            http09_responses: true,
+            ..Lenient::default()
+        };
+        fn parse(raw: &'static [u8], lenient: Lenient) -> (Option<ParsedMessage<::StatusCode>>, usize) {
+            let mut raw = BytesMut::from(raw.to_vec());
+            let msg = Client::parse(&mut raw, ParseContext {
+                cached_headers: &mut None,
+                req_method: &mut Some(::Method::GET),
+                max_headers: None,
+                preserve_header_case: false,
+                preserve_header_order: false,
+                lenient,
+            }).unwrap();
+            (msg, raw.len())
+        }
+
+        let (msg, left) = parse(b"hello world", lenient);
+        let msg = msg.unwrap();
+        assert_eq!(left, 11, "body is left in the buffer");
+        assert_eq!(msg.head.version, ::Version::HTTP_09);
+        assert_eq!(msg.head.subject, ::StatusCode::OK);
+        assert!(msg.head.headers.is_empty());
+        assert_eq!(msg.decode, Decode::Normal(Decoder::eof()));
+        assert!(!msg.keep_alive);
+
+        let (msg, _) = parse(b"\r\nHTT", lenient);
+        assert!(msg.is_none(), "could still be a status line");
+
+        let (msg, _) = parse(b"HTX", lenient);
+        assert_eq!(msg.unwrap().head.version, ::Version::HTTP_09);
+
+        let (msg, left) = parse(b"HTTP/1.1 200 OK\r\n\r\n", lenient);
+        assert_eq!(msg.unwrap().head.version, ::Version::HTTP_11);
+        assert_eq!(left, 0);
+
+        assert!(Client::parse(&mut BytesMut::from(b"hello world".to_vec()), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut Some(::Method::GET),
+            max_headers: None,
+            preserve_header_case: false,
+            preserve_header_order: false,
+            lenient: Lenient::default(),
+        }).is_err());
+    }
+
+    #[test]
+    fn test_parse_response_keep_alive() {
+        fn keep_alive(raw: &'static [u8]) -> bool {
+            let mut raw = BytesMut::from(raw.to_vec());
+            Client::parse(&mut raw, ParseContext {
+                cached_headers: &mut None,
+                req_method: &mut Some(::Method::GET),
+                max_headers: None,
+                preserve_header_case: false,
+                preserve_header_order: false,
+                lenient: Lenient::default(),
+            }).unwrap().unwrap().keep_alive
+        }
+
+        assert!(keep_alive(b"HTTP/1.1 200 OK\r\n\r\n"));
+        assert!(!keep_alive(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n"));
+        assert!(!keep_alive(b"HTTP/1.0 200 OK\r\n\r\n"));
+        assert!(keep_alive(b"HTTP/1.0 200 OK\r\nConnection: Keep-Alive\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_encode_http10_keep_alive() {
+        use proto::BodyLength;
+
+        let mut head = MessageHead::default();
+        head.version = ::Version::HTTP_10;
+        let mut vec = Vec::new();
+        let encoder = Server::encode(Encode {
+            head: &mut head,
+            body: Some(BodyLength::Known(5)),
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: false,
+        }, &mut vec).unwrap();
+        assert!(!encoder.is_last());
+        let s = ::std::str::from_utf8(&vec).unwrap();
+        assert!(s.contains("\r\nconnection: keep-alive\r\n"), "{:?}", s);
+
+        // a response without a length has to close the connection
+        let mut head = MessageHead::default();
+        head.version = ::Version::HTTP_10;
+        let mut vec = Vec::new();
+        let encoder = Server::encode(Encode {
+            head: &mut head,
+            body: Some(BodyLength::Unknown),
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: false,
+        }, &mut vec).unwrap();
+        assert!(encoder.is_last());
+        let s = ::std::str::from_utf8(&vec).unwrap();
+        assert!(!s.contains("connection"), "{:?}", s);
+
+        let mut head = MessageHead::default();
+        head.version = ::Version::HTTP_10;
+        let mut vec = Vec::new();
+        Client::encode(Encode {
+            head: &mut head,
+            body: None,
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: false,
+        }, &mut vec).unwrap();
+        let s = ::std::str::from_utf8(&vec).unwrap();
+        assert!(s.contains("\r\nconnection: keep-alive\r\n"), "{:?}", s);
+
+        let mut head = MessageHead::default();
+        head.version = ::Version::HTTP_10;
+        let mut vec = Vec::new();
+        Client::encode(Encode {
+            head: &mut head,
+            body: None,
+            keep_alive: false,
+            req_method: &mut None,
+            title_case_headers: false,
+        }, &mut vec).unwrap();
+        let s = ::std::str::from_utf8(&vec).unwrap();
+        assert!(!s.contains("connection"), "{:?}", s);
+    }
+
+    #[test]
+    fn test_status_line_table() {
+        for code in 100..600 {
+            let status = match StatusCode::from_u16(code) {
+                Ok(status) => status,
+                Err(_) => continue,
+            };
+            if let Some(line) = status_line(Version::HTTP_11, status) {
+                let expected = format!(
+                    "HTTP/1.1 {} {}\r\n",
+                    status.as_str(),
+                    status.canonical_reason().unwrap(),
+                );
+                assert_eq!(line, expected.as_bytes());
+            }
+        }
+
+        assert!(status_line(Version::HTTP_10, StatusCode::OK).is_none());
+    }
+
+    #[test]
+    fn test_extend_u64() {
+        for &n in &[0, 7, 10, 99, 1024, 65_536, ::std::u64::MAX] {
+            let mut vec = Vec::new();
+            extend_u64(&mut vec, n);
+            assert_eq!(vec, n.to_string().into_bytes());
+        }
+    }
+
     #[cfg(feature = "nightly")]
     use test::Bencher;
 
@@ -1342,6 +2500,10 @@ mod tests {
             let msg = Server::parse(&mut raw, ParseContext {
                 cached_headers: &mut headers,
                 req_method: &mut None,
+                max_headers: None,
+                preserve_header_case: false,
+                preserve_header_order: false,
+                lenient: Lenient::default(),
             }).unwrap().unwrap();
             headers = Some(msg.head.headers);
             restart(&mut raw, len);
@@ -1370,6 +2532,10 @@ mod tests {
             let msg = Server::parse(&mut raw, ParseContext {
                 cached_headers: &mut headers,
                 req_method: &mut None,
+                max_headers: None,
+                preserve_header_case: false,
+                preserve_header_order: false,
+                lenient: Lenient::default(),
             }).unwrap().unwrap();
             headers = Some(msg.head.headers);
             restart(&mut raw, len);
@@ -1413,6 +2579,35 @@ mod tests {
         })
     }
 
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn bench_server_encode_not_found_with_length(b: &mut Bencher) {
+        use http::header::HeaderValue;
+        use proto::BodyLength;
+
+        let len = 114;
+        b.bytes = len as u64;
+
+        let mut head = MessageHead::default();
+        head.subject = StatusCode::NOT_FOUND;
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+
+        b.iter(|| {
+            let mut vec = Vec::new();
+            head.headers = headers.clone();
+            Server::encode(Encode {
+                head: &mut head,
+                body: Some(BodyLength::Known(1_048_576)),
+                keep_alive: true,
+                req_method: &mut Some(Method::GET),
+                title_case_headers: false,
+            }, &mut vec).unwrap();
+            assert_eq!(vec.len(), len);
+            ::test::black_box(vec);
+        })
+    }
+
     #[cfg(feature = "nightly")]
     #[bench]
     fn bench_server_encode_no_headers(b: &mut Bencher) {
@@ -1,32 +1,52 @@
+use std::time::Instant;
+
 use bytes::IntoBuf;
+#[cfg(feature = "http1")] use bytes::Bytes;
 use futures::{Async, Future, Poll, Stream};
 use futures::future::{self, Either};
-use futures::sync::mpsc;
-use h2::client::{Builder, Handshake, SendRequest};
+use futures::sync::{mpsc, oneshot};
+use http::HeaderMap;
+use h2::{Ping, PingPong, Reason, RecvStream};
+use h2::client::{Builder, Handshake, ResponseFuture, SendRequest};
 use tokio_io::{AsyncRead, AsyncWrite};
 
-use body::Payload;
-use ::common::{Exec, Never};
+use body::{ChunkSize, Payload};
+use client::RequestConfig;
+use client::measure::PingRtt;
+use client::settings::SettingsSlot;
+use ::common::{CancelToken, ConnectionStats, Exec, Never, SendWindow, StatsIo};
 use super::{PipeToSendStream, SendBuf};
+use super::h2c::UpgradeIo;
+use super::settings::WatchSettings;
 use ::{Body, Request, Response};
 
 type ClientRx<B> = ::client::dispatch::Receiver<Request<B>, Response<Body>>;
+type Callback<B> = ::client::dispatch::Callback<Request<B>, Response<Body>>;
 /// An mpsc channel is used to help notify the `Connection` task when *all*
 /// other handles to it have been dropped, so that it can shutdown.
 type ConnDropRef = mpsc::Sender<Never>;
+/// How the connection's task finished, so that the `Connection` future
+/// finishes along with it.
+type ConnDone = oneshot::Receiver<::Result<()>>;
 
 pub struct Client<T, B>
 where
     B: Payload,
 {
+    chunk_size: ChunkSize,
     executor: Exec,
+    ping_rtt: Option<PingRtt>,
     rx: ClientRx<B>,
     state: State<T, SendBuf<B::Data>>,
+    stats: ConnectionStats,
+    // The callback of the request that upgraded the connection with `h2c`,
+    // answered on stream 1.
+    upgraded: Option<Callback<B>>,
 }
 
 enum State<T, B> where B: IntoBuf {
-    Handshaking(Handshake<T, B>),
-    Ready(SendRequest<B>, ConnDropRef),
+    Handshaking(Handshake<WatchSettings<StatsIo<UpgradeIo<T>>>, B>),
+    Ready(SendRequest<B>, ConnDropRef, ConnDone),
 }
 
 impl<T, B> Client<T, B>
@@ -34,18 +54,44 @@ where
     T: AsyncRead + AsyncWrite + Send + 'static,
     B: Payload,
 {
-    pub(crate) fn new(io: T, rx: ClientRx<B>, exec: Exec) -> Client<T, B> {
+    pub(crate) fn new(io: T, rx: ClientRx<B>, exec: Exec, chunk_size: ChunkSize, settings: Option<SettingsSlot>) -> Client<T, B> {
+        Client::with_io(UpgradeIo::new(io), rx, exec, chunk_size, settings, None)
+    }
+
+    // A connection that was upgraded from HTTP/1 with `h2c`, after the
+    // `101` to the request `cb` is waiting on.
+    #[cfg(feature = "http1")]
+    pub(crate) fn upgraded(io: T, read_buf: Bytes, rx: ClientRx<B>, exec: Exec, chunk_size: ChunkSize, settings: Option<SettingsSlot>, cb: Callback<B>) -> Client<T, B> {
+        Client::with_io(UpgradeIo::upgraded(io, read_buf), rx, exec, chunk_size, settings, Some(cb))
+    }
+
+    fn with_io(io: UpgradeIo<T>, rx: ClientRx<B>, exec: Exec, chunk_size: ChunkSize, settings: Option<SettingsSlot>, upgraded: Option<Callback<B>>) -> Client<T, B> {
+        let stats = ConnectionStats::new();
         let handshake = Builder::new()
             // we don't expose PUSH promises yet
             .enable_push(false)
-            .handshake(io);
+            .handshake(WatchSettings::new(StatsIo::new(io, stats.clone()), settings));
 
         Client {
+            chunk_size,
             executor: exec,
+            ping_rtt: None,
             rx: rx,
             state: State::Handshaking(handshake),
+            stats,
+            upgraded,
         }
     }
+
+    pub(crate) fn stats(&self) -> &ConnectionStats {
+        &self.stats
+    }
+
+    // Ping the server once the handshake is done, to sample the round-trip
+    // time.
+    pub(crate) fn set_ping_rtt(&mut self, ping_rtt: PingRtt) {
+        self.ping_rtt = Some(ping_rtt);
+    }
 }
 
 impl<T, B> Future for Client<T, B>
@@ -60,7 +106,12 @@ where
         loop {
             let next = match self.state {
                 State::Handshaking(ref mut h) => {
-                    let (request_tx, conn) = try_ready!(h.poll().map_err(::Error::new_h2));
+                    let (request_tx, mut conn) = try_ready!(h.poll().map_err(::Error::new_h2));
+                    if let Some(ping_rtt) = self.ping_rtt.take() {
+                        if let Some(ping_pong) = conn.ping_pong() {
+                            self.executor.execute(ping(ping_pong, ping_rtt));
+                        }
+                    }
                     // An mpsc channel is used entirely to detect when the
                     // 'Client' has been dropped. This is to get around a bug
                     // in h2 where dropping all SendRequests won't notify a
@@ -72,14 +123,18 @@ where
                             None => (),
                         })
                         .map_err(|_| -> Never { unreachable!("mpsc cannot error") });
+                    let (done_tx, done_rx) = oneshot::channel();
                     let fut = conn
                         .inspect(|_| trace!("connection complete"))
-                        .map_err(|e| debug!("connection error: {}", e))
                         .select2(rx)
-                        .then(|res| match res {
-                            Ok(Either::A(((), _))) |
-                            Err(Either::A(((), _))) => {
-                                // conn has finished either way
+                        .then(move |res| match res {
+                            Ok(Either::A(((), _))) => {
+                                let _ = done_tx.send(Ok(()));
+                                Either::A(future::ok(()))
+                            },
+                            Err(Either::A((e, _))) => {
+                                log_conn_error(&e);
+                                let _ = done_tx.send(Err(::Error::new_h2(e)));
                                 Either::A(future::ok(()))
                             },
                             Ok(Either::B(((), conn))) => {
@@ -87,15 +142,43 @@ where
                                 // the connection some more should start shutdown
                                 // and then close
                                 trace!("send_request dropped, starting conn shutdown");
-                                Either::B(conn)
+                                Either::B(conn.map_err(|e| log_conn_error(&e)))
                             }
                             Err(Either::B((never, _))) => match never {},
                         });
                     self.executor.execute(fut);
-                    State::Ready(request_tx, tx)
+                    State::Ready(request_tx, tx, done_rx)
                 },
-                State::Ready(ref mut tx, ref conn_dropper) => {
+                State::Ready(ref mut tx, ref conn_dropper, ref mut conn_done) => {
+                    // The connection closed, such as after a `GOAWAY` from
+                    // the server, and so no more requests can be sent.
+                    match conn_done.poll() {
+                        Ok(Async::Ready(res)) => return res.map(Async::Ready),
+                        Ok(Async::NotReady) => (),
+                        Err(_canceled) => return Ok(Async::Ready(())),
+                    }
                     try_ready!(tx.poll_ready().map_err(::Error::new_h2));
+                    if let Some(cb) = self.upgraded.take() {
+                        // The server already has the request, and only the
+                        // response is read, from the stream opened here.
+                        let req = ::http::Request::new(());
+                        let fut = match tx.send_request(req, true) {
+                            Ok((fut, _)) => fut,
+                            Err(err) => {
+                                debug!("client h2c stream error: {}", err);
+                                cb.send(Err((::Error::new_h2(err), None)));
+                                continue;
+                            }
+                        };
+                        let sender = Sender {
+                            chunk_size: self.chunk_size,
+                            conn_drop_ref: conn_dropper.clone(),
+                            executor: self.executor.clone(),
+                            stats: self.stats.clone(),
+                        };
+                        sender.respond(fut, future::empty(), cb);
+                        continue;
+                    }
                     match self.rx.poll() {
                         Ok(Async::Ready(Some((req, mut cb)))) => {
                             // check that future hasn't been canceled already
@@ -106,41 +189,24 @@ where
                             let (head, body) = req.into_parts();
                             let mut req = ::http::Request::from_parts(head, ());
                             super::strip_connection_headers(req.headers_mut());
-                            let eos = body.is_end_stream();
-                            let (fut, body_tx) = match tx.send_request(req, eos) {
-                                Ok(ok) => ok,
-                                Err(err) => {
-                                    debug!("client send request error: {}", err);
-                                    let _ = cb.send(Err((::Error::new_h2(err), None)));
-                                    continue;
-                                }
+                            let sender = Sender {
+                                chunk_size: self.chunk_size,
+                                conn_drop_ref: conn_dropper.clone(),
+                                executor: self.executor.clone(),
+                                stats: self.stats.clone(),
                             };
-                            if !eos {
-                                let conn_drop_ref = conn_dropper.clone();
-                                let pipe = PipeToSendStream::new(body, body_tx)
-                                    .map_err(|e| debug!("client request body error: {}", e))
-                                    .then(move |x| {
-                                        drop(conn_drop_ref);
-                                        x
-                                    });
-                                self.executor.execute(pipe);
-                            }
-
-                            let fut = fut
-                                .then(move |result| {
-                                    match result {
-                                        Ok(res) => {
-                                            let res = res.map(::Body::h2);
-                                            let _ = cb.send(Ok(res));
-                                        },
-                                        Err(err) => {
-                                            debug!("client response error: {}", err);
-                                            let _ = cb.send(Err((::Error::new_h2(err), None)));
-                                        }
-                                    }
-                                    Ok(())
+                            if !body.is_end_stream() && RequestConfig::get(&req).is_h2_stream_deferred() {
+                                trace!("deferring stream until body is ready");
+                                self.executor.execute(SendWhenReady {
+                                    data_done: false,
+                                    first: None,
+                                    req: Some((req, body, cb)),
+                                    sender,
+                                    tx: tx.clone(),
                                 });
-                            self.executor.execute(fut);
+                            } else {
+                                sender.send(tx, req, body, None, cb);
+                            }
                             continue;
                         },
 
@@ -158,3 +224,224 @@ where
         }
     }
 }
+
+fn log_conn_error(e: &::h2::Error) {
+    if e.reason() == Some(Reason::ENHANCE_YOUR_CALM) {
+        warn!("connection closed by peer asking to be sent less: {}", e);
+    } else {
+        debug!("connection error: {}", e);
+    }
+}
+
+// Send a PING, and pass how long its PONG took to `ping_rtt`.
+fn ping(mut ping_pong: PingPong, ping_rtt: PingRtt) -> Box<Future<Item=(), Error=()> + Send> {
+    if let Err(e) = ping_pong.send_ping(Ping::opaque()) {
+        debug!("ping error: {}", e);
+        return Box::new(future::ok(()));
+    }
+    let sent = Instant::now();
+    Box::new(future::poll_fn(move || ping_pong.poll_pong())
+        .then(move |res| {
+            match res {
+                Ok(_) => ping_rtt.call(sent.elapsed()),
+                // The connection closed before the PONG.
+                Err(e) => debug!("ping error: {}", e),
+            }
+            Ok(())
+        }))
+}
+
+// What's needed to open a stream for a request, and to wait on its response.
+struct Sender {
+    chunk_size: ChunkSize,
+    conn_drop_ref: ConnDropRef,
+    executor: Exec,
+    stats: ConnectionStats,
+}
+
+impl Sender {
+    // Opens the stream, with what was taken from the body if it was already
+    // polled, and spawns the tasks sending the body and receiving the
+    // response.
+    fn send<B>(&self, tx: &mut SendRequest<SendBuf<B::Data>>, req: ::http::Request<()>, body: B, first: Option<First<B::Data>>, cb: Callback<B>)
+    where
+        B: Payload + 'static,
+    {
+        let window = req.extensions().get::<SendWindow>().cloned();
+        let cancel_token = req.extensions().get::<CancelToken>().cloned();
+        let (eos, first, trailers) = match first {
+            Some(First::Chunk(chunk)) => (false, Some(chunk), None),
+            // A body that already ended ends the stream with the `HEADERS`,
+            // unless it has trailers to end it with.
+            Some(First::End(trailers)) => (trailers.is_none(), None, trailers),
+            None => (body.is_end_stream(), None, None),
+        };
+        let (fut, body_tx) = match tx.send_request(req, eos) {
+            Ok(ok) => ok,
+            Err(err) => {
+                debug!("client send request error: {}", err);
+                if let Some(window) = window {
+                    window.close();
+                }
+                cb.send(Err((::Error::new_h2(err), None)));
+                return;
+            }
+        };
+        let mut body_tx = if eos {
+            if let Some(window) = window {
+                window.close();
+            }
+            Some(body_tx)
+        } else if let Some(mut trailers) = trailers {
+            if let Some(window) = window {
+                window.close();
+            }
+            let mut body_tx = body_tx;
+            super::strip_connection_headers(&mut trailers);
+            if let Err(err) = body_tx.send_trailers(trailers) {
+                debug!("client request trailers error: {}", err);
+            }
+            Some(body_tx)
+        } else {
+            let conn_drop_ref = self.conn_drop_ref.clone();
+            let mut pipe = PipeToSendStream::new(body, body_tx);
+            pipe.cancel_token = cancel_token.clone();
+            pipe.first = first;
+            pipe.window = window;
+            let pipe = pipe
+                .map_err(|e| debug!("client request body error: {}", e))
+                .then(move |x| {
+                    drop(conn_drop_ref);
+                    x
+                });
+            self.executor.execute(pipe);
+            // The body's task resets the stream if it's canceled.
+            None
+        };
+        let canceled = future::poll_fn(move || {
+            if let Some(ref token) = cancel_token {
+                if token.poll_canceled().is_ready() {
+                    debug!("request canceled, resetting stream");
+                    if let Some(ref mut body_tx) = body_tx {
+                        body_tx.send_reset(Reason::CANCEL);
+                    }
+                    return Err(::Error::new_canceled(Some("request canceled")));
+                }
+            }
+            Ok(Async::NotReady)
+        });
+
+        self.respond(fut, canceled, cb);
+    }
+
+    // Spawns the task receiving the response, unless `canceled` errors
+    // first.
+    fn respond<B, C>(&self, fut: ResponseFuture, canceled: C, cb: Callback<B>)
+    where
+        B: Payload + 'static,
+        C: Future<Item=::http::Response<RecvStream>, Error=::Error> + Send + 'static,
+    {
+        self.stats.on_request();
+        let chunk_size = self.chunk_size;
+        let stats = self.stats.clone();
+        let fut = fut
+            .map_err(::Error::new_h2)
+            .select(canceled)
+            .then(move |result| {
+                match result {
+                    Ok((res, _)) => {
+                        let mut res = res.map(|recv| ::Body::h2(recv, chunk_size));
+                        res.extensions_mut().insert(stats);
+                        cb.send(Ok(res));
+                    },
+                    Err((err, _)) => {
+                        debug!("client response error: {}", err);
+                        cb.send(Err((err, None)));
+                    }
+                }
+                Ok(())
+            });
+        self.executor.execute(fut);
+    }
+}
+
+// A request waiting for its body's first chunk, before opening its stream,
+// see `RequestConfig::http2_defer_stream`.
+struct SendWhenReady<B>
+where
+    B: Payload,
+{
+    // Whether the body's data ended, and its trailers are polled.
+    data_done: bool,
+    first: Option<First<B::Data>>,
+    req: Option<(::http::Request<()>, B, Callback<B>)>,
+    sender: Sender,
+    tx: SendRequest<SendBuf<B::Data>>,
+}
+
+impl<B> Future for SendWhenReady<B>
+where
+    B: Payload + 'static,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        let ready = {
+            let (ref req, ref mut body, ref mut cb) = *self.req.as_mut().expect("polled after complete");
+            if let Async::Ready(()) = cb.poll_cancel().expect("poll_cancel cannot error") {
+                trace!("request canceled");
+                return Ok(Async::Ready(()));
+            }
+            let is_canceled = match req.extensions().get::<CancelToken>() {
+                Some(token) => token.poll_canceled().is_ready(),
+                None => false,
+            };
+            if is_canceled {
+                Err(::Error::new_canceled(Some("request canceled")))
+            } else {
+                poll_body_ready(body, &mut self.first, &mut self.data_done, &mut self.tx)
+            }
+        };
+
+        let (req, body, cb) = match ready {
+            Ok(Async::Ready(())) => self.req.take().expect("polled after complete"),
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(err) => {
+                debug!("client deferred request error: {}", err);
+                let (_, _, cb) = self.req.take().expect("polled after complete");
+                cb.send(Err((err, None)));
+                return Ok(Async::Ready(()));
+            }
+        };
+        let first = self.first.take().expect("body polled");
+        self.sender.send(&mut self.tx, req, body, Some(first), cb);
+        Ok(Async::Ready(()))
+    }
+}
+
+// What was taken from a request body before opening its stream.
+enum First<D> {
+    Chunk(D),
+    // The body had no data, only maybe trailers.
+    End(Option<HeaderMap>),
+}
+
+// Polls the body for its first chunk, or its trailers if it has no data,
+// and then the connection for room to open a stream.
+fn poll_body_ready<B>(body: &mut B, first: &mut Option<First<B::Data>>, data_done: &mut bool, tx: &mut SendRequest<SendBuf<B::Data>>) -> Poll<(), ::Error>
+where
+    B: Payload,
+{
+    if first.is_none() && !*data_done {
+        match try_ready!(body.poll_data().map_err(::Error::new_user_body)) {
+            Some(chunk) => *first = Some(First::Chunk(chunk)),
+            None => *data_done = true,
+        }
+    }
+    if first.is_none() {
+        let trailers = try_ready!(body.poll_trailers().map_err(::Error::new_user_body));
+        *first = Some(First::End(trailers));
+    }
+    tx.poll_ready().map_err(::Error::new_h2)
+}
@@ -0,0 +1,370 @@
+//! The IO of a client connection that was upgraded to HTTP/2 over
+//! cleartext.
+//!
+//! The request that offered to upgrade is answered on stream 1, which the
+//! server opened on its own. The `h2` client can't be told that, so it
+//! opens stream 1 with a `GET /` of its own, whose `HEADERS` are dropped
+//! here instead of written.
+//!
+//! Dropping them is only safe if their header block leaves the HPACK table
+//! as it was, or the server would decode the next blocks differently than
+//! they were encoded. Otherwise the connection fails with an error, before
+//! anything else is written.
+
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use bytes::{Buf, BufMut, Bytes};
+use futures::{Async, Poll};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+#[cfg(feature = "http1")]
+const PREFACE_LEN: usize = 24;
+const FRAME_HEADER_LEN: usize = 9;
+const TYPE_HEADERS: u8 = 0x1;
+const TYPE_CONTINUATION: u8 = 0x9;
+const FLAG_END_HEADERS: u8 = 0x4;
+// The number of entries of the HPACK static table.
+const STATIC_TABLE_LEN: usize = 61;
+
+pub(crate) struct UpgradeIo<T> {
+    inner: T,
+    // What was read after the `101`, before HTTP/2 started.
+    pre: Bytes,
+    // `None` once the `HEADERS` of stream 1 were dropped, or if the
+    // connection wasn't upgraded.
+    hide: Option<Hide>,
+}
+
+// Walks the frames written, to drop the ones opening stream 1.
+struct Hide {
+    // How much of the preface is left to pass on.
+    preface: usize,
+    // The header of the next frame, before it's known if it's passed on.
+    head: [u8; FRAME_HEADER_LEN],
+    head_len: usize,
+    // How much of `head` still has to be written, once it's passed on.
+    head_unsent: usize,
+    // How much of a frame's payload is left to pass on, or to drop.
+    pass: usize,
+    skip: usize,
+    // The header block dropped so far.
+    block: Vec<u8>,
+    end_headers: bool,
+}
+
+impl<T> UpgradeIo<T> {
+    pub(crate) fn new(io: T) -> UpgradeIo<T> {
+        UpgradeIo {
+            inner: io,
+            pre: Bytes::new(),
+            hide: None,
+        }
+    }
+
+    #[cfg(feature = "http1")]
+    pub(crate) fn upgraded(io: T, read_buf: Bytes) -> UpgradeIo<T> {
+        UpgradeIo {
+            inner: io,
+            pre: read_buf,
+            hide: Some(Hide {
+                preface: PREFACE_LEN,
+                head: [0; FRAME_HEADER_LEN],
+                head_len: 0,
+                head_unsent: 0,
+                pass: 0,
+                skip: 0,
+                block: Vec::new(),
+                end_headers: false,
+            }),
+        }
+    }
+}
+
+impl<T: Read> Read for UpgradeIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pre.is_empty() {
+            return self.inner.read(buf);
+        }
+        let cnt = cmp::min(buf.len(), self.pre.len());
+        buf[..cnt].copy_from_slice(&self.pre[..cnt]);
+        self.pre.advance(cnt);
+        Ok(cnt)
+    }
+}
+
+impl<T: Write> Write for UpgradeIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (cnt, done) = match self.hide {
+            Some(ref mut hide) => {
+                let cnt = hide.write(&mut self.inner, buf)?;
+                (cnt, hide.is_done())
+            },
+            None => return self.inner.write(buf),
+        };
+        if done {
+            trace!("h2c: dropped the HEADERS of stream 1");
+            self.hide = None;
+        }
+        Ok(cnt)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(ref mut hide) = self.hide {
+            hide.write_head(&mut self.inner)?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for UpgradeIo<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+
+    fn read_buf<B: BufMut>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        if self.pre.is_empty() {
+            return AsyncRead::read_buf(&mut self.inner, buf);
+        }
+        let cnt = cmp::min(buf.remaining_mut(), self.pre.len());
+        buf.put_slice(&self.pre[..cnt]);
+        self.pre.advance(cnt);
+        Ok(Async::Ready(cnt))
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for UpgradeIo<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        if let Some(ref mut hide) = self.hide {
+            try_nb!(hide.write_head(&mut self.inner));
+        }
+        self.inner.shutdown()
+    }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        if self.hide.is_none() {
+            return self.inner.write_buf(buf);
+        }
+        let cnt = try_nb!(self.write(buf.bytes()));
+        buf.advance(cnt);
+        Ok(Async::Ready(cnt))
+    }
+}
+
+impl Hide {
+    // Pass on, or drop, the start of `buf`, returning how much of it was
+    // taken.
+    fn write<W: Write>(&mut self, io: &mut W, buf: &[u8]) -> io::Result<usize> {
+        self.write_head(io)?;
+        if self.preface > 0 {
+            let cnt = io.write(&buf[..cmp::min(self.preface, buf.len())])?;
+            self.preface -= cnt;
+            return Ok(cnt);
+        }
+        if self.pass > 0 {
+            let cnt = io.write(&buf[..cmp::min(self.pass, buf.len())])?;
+            self.pass -= cnt;
+            return Ok(cnt);
+        }
+        if self.skip > 0 {
+            let cnt = cmp::min(self.skip, buf.len());
+            self.block.extend_from_slice(&buf[..cnt]);
+            self.skip -= cnt;
+            self.check_block()?;
+            return Ok(cnt);
+        }
+        let cnt = cmp::min(FRAME_HEADER_LEN - self.head_len, buf.len());
+        self.head[self.head_len..self.head_len + cnt].copy_from_slice(&buf[..cnt]);
+        self.head_len += cnt;
+        if self.head_len == FRAME_HEADER_LEN {
+            self.on_head()?;
+        }
+        Ok(cnt)
+    }
+
+    fn write_head<W: Write>(&mut self, io: &mut W) -> io::Result<()> {
+        while self.head_unsent > 0 {
+            let cnt = io.write(&self.head[FRAME_HEADER_LEN - self.head_unsent..])?;
+            if cnt == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.head_unsent -= cnt;
+        }
+        Ok(())
+    }
+
+    fn on_head(&mut self) -> io::Result<()> {
+        self.head_len = 0;
+        let len = (self.head[0] as usize) << 16 | (self.head[1] as usize) << 8 | self.head[2] as usize;
+        let kind = self.head[3];
+        let stream = ((self.head[5] as u32) << 24 | (self.head[6] as u32) << 16 |
+            (self.head[7] as u32) << 8 | self.head[8] as u32) & 0x7fff_ffff;
+        let opens = kind == TYPE_HEADERS && self.block.is_empty();
+        let continues = kind == TYPE_CONTINUATION && !self.block.is_empty();
+        if stream == 1 && (opens || continues) {
+            self.skip = len;
+            self.end_headers = self.head[4] & FLAG_END_HEADERS != 0;
+            self.block.reserve(len);
+            self.check_block()
+        } else {
+            self.head_unsent = FRAME_HEADER_LEN;
+            self.pass = len;
+            Ok(())
+        }
+    }
+
+    // Make sure dropping the request that stands in for the one the server
+    // already has doesn't put the server's HPACK decoder out of step.
+    fn check_block(&self) -> io::Result<()> {
+        if self.is_done() && !keeps_table(&self.block) {
+            debug!("h2c: header block of stream 1 changes the HPACK table: {:?}", self.block);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "h2c stream 1 headers can't be dropped"));
+        }
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        self.end_headers && self.skip == 0
+    }
+}
+
+// Whether a header block only has fields that leave the HPACK table as it
+// is: fields indexed in the static table, and literals that aren't indexed.
+fn keeps_table(mut block: &[u8]) -> bool {
+    while !block.is_empty() {
+        let first = block[0];
+        let rest = if first & 0x80 != 0 {
+            match decode_int(block, 7) {
+                Some((index, rest)) if index > 0 && index <= STATIC_TABLE_LEN => rest,
+                _ => return false,
+            }
+        } else if first & 0xe0 == 0 {
+            // A literal without indexing, or never indexed.
+            let rest = match decode_int(block, 4) {
+                Some((0, rest)) => skip_string(rest),
+                Some((index, rest)) if index <= STATIC_TABLE_LEN => Some(rest),
+                _ => None,
+            };
+            match rest.and_then(skip_string) {
+                Some(rest) => rest,
+                None => return false,
+            }
+        } else {
+            // Incrementally indexed, or a table size update.
+            return false;
+        };
+        block = rest;
+    }
+    true
+}
+
+// An integer with a prefix of `bits`, and what follows it.
+fn decode_int(buf: &[u8], bits: u32) -> Option<(usize, &[u8])> {
+    let max = (1 << bits) - 1;
+    let mut value = buf[0] as usize & max;
+    if value < max {
+        return Some((value, &buf[1..]));
+    }
+    let mut shift = 0;
+    for (i, &b) in buf[1..].iter().enumerate() {
+        if shift > 28 {
+            return None;
+        }
+        value += (b as usize & 0x7f) << shift;
+        shift += 7;
+        if b & 0x80 == 0 {
+            return Some((value, &buf[i + 2..]));
+        }
+    }
+    None
+}
+
+// Skip a string literal, Huffman coded or not.
+fn skip_string(buf: &[u8]) -> Option<&[u8]> {
+    if buf.is_empty() {
+        return None;
+    }
+    match decode_int(buf, 7) {
+        Some((len, rest)) if len <= rest.len() => Some(&rest[len..]),
+        _ => None,
+    }
+}
+
+#[cfg(all(test, feature = "http1"))]
+mod tests {
+    use super::*;
+
+    fn frame(kind: u8, flags: u8, stream: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0, 0, payload.len() as u8, kind, flags, 0, 0, 0, stream];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn write_all(io: &mut UpgradeIo<Vec<u8>>, mut buf: &[u8], step: usize) -> io::Result<()> {
+        while !buf.is_empty() {
+            let cnt = io.write(&buf[..cmp::min(step, buf.len())])?;
+            buf = &buf[cnt..];
+        }
+        io.flush()
+    }
+
+    #[test]
+    fn drops_stream_1_headers() {
+        let preface = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+        let settings = frame(0x4, 0, 0, &[0, 2, 0, 0, 0, 0]);
+        // `:method: GET`, `:scheme: http`, and `:path: /`.
+        let headers = frame(TYPE_HEADERS, 0x1 | FLAG_END_HEADERS, 1, &[0x82, 0x86, 0x84]);
+        let after = frame(TYPE_HEADERS, 0x1 | FLAG_END_HEADERS, 3, &[0x82, 0x84]);
+
+        for &step in &[1, 5, 100] {
+            let mut io = UpgradeIo::upgraded(Vec::new(), Bytes::new());
+            write_all(&mut io, preface, step).unwrap();
+            write_all(&mut io, &settings, step).unwrap();
+            write_all(&mut io, &headers, step).unwrap();
+            assert!(io.hide.is_none(), "step {}", step);
+            write_all(&mut io, &after, step).unwrap();
+
+            let mut expected = preface.to_vec();
+            expected.extend_from_slice(&settings);
+            expected.extend_from_slice(&after);
+            assert_eq!(io.inner, expected, "step {}", step);
+        }
+    }
+
+    #[test]
+    fn indexing_stream_1_headers_error() {
+        let mut io = UpgradeIo::upgraded(Vec::new(), Bytes::new());
+        write_all(&mut io, b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n", 100).unwrap();
+        // `:authority: a`, incrementally indexed.
+        let headers = frame(TYPE_HEADERS, FLAG_END_HEADERS, 1, &[0x82, 0x41, 0x01, b'a']);
+        let err = write_all(&mut io, &headers, 100).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(io.inner, b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+    }
+
+    #[test]
+    fn keeps_table_blocks() {
+        assert!(keeps_table(&[]));
+        assert!(keeps_table(&[0x82, 0x86, 0x84]));
+        // `:authority: a` without indexing, `x: y` never indexed, and
+        // `accept` with a Huffman coded value.
+        assert!(keeps_table(&[0x01, 0x01, b'a', 0x10, 0x01, b'x', 0x01, b'y', 0x0f, 0x04, 0x81, 0x1f]));
+        // The largest static index, with a multi-byte prefix: 15 + 46.
+        assert!(keeps_table(&[0x0f, 0x2e, 0x00]));
+
+        assert!(!keeps_table(&[0x80]), "index 0");
+        assert!(!keeps_table(&[0xbe]), "dynamic table index");
+        assert!(!keeps_table(&[0x41, 0x01, b'a']), "incremental indexing");
+        assert!(!keeps_table(&[0x20]), "table size update");
+        assert!(!keeps_table(&[0x01, 0x05, b'a']), "truncated value");
+        assert!(!keeps_table(&[0x0f]), "truncated index");
+    }
+
+    #[test]
+    fn reads_read_buf_first() {
+        let mut io = UpgradeIo::upgraded(&b"world"[..], Bytes::from(&b"hello "[..]));
+        let mut read = String::new();
+        io.read_to_string(&mut read).unwrap();
+        assert_eq!(read, "hello world");
+    }
+}
@@ -5,12 +5,16 @@ use http::HeaderMap;
 use http::header::{CONNECTION, TRANSFER_ENCODING};
 
 use ::body::Payload;
+#[cfg(feature = "client")] use ::common::SendWindow;
+use ::common::CancelToken;
 
-mod client;
-mod server;
+#[cfg(feature = "client")] mod client;
+#[cfg(feature = "client")] mod h2c;
+#[cfg(feature = "server")] mod server;
+#[cfg(feature = "client")] mod settings;
 
-pub(crate) use self::client::Client;
-pub(crate) use self::server::Server;
+#[cfg(feature = "client")] pub(crate) use self::client::Client;
+#[cfg(feature = "server")] pub(crate) use self::server::Server;
 
 fn strip_connection_headers(headers: &mut HeaderMap) {
     if headers.remove(TRANSFER_ENCODING).is_some() {
@@ -30,7 +34,15 @@ where
     S: Payload,
 {
     body_tx: SendStream<SendBuf<S::Data>>,
+    // Resets the stream once canceled.
+    cancel_token: Option<CancelToken>,
+    data_done: bool,
+    // A chunk already taken from the stream, sent before polling it again.
+    first: Option<S::Data>,
     stream: S,
+    // Only client requests can have a `SendWindow`.
+    #[cfg(feature = "client")]
+    window: Option<SendWindow>,
 }
 
 impl<S> PipeToSendStream<S>
@@ -40,7 +52,12 @@ where
     fn new(stream: S, tx: SendStream<SendBuf<S::Data>>) -> PipeToSendStream<S> {
         PipeToSendStream {
             body_tx: tx,
+            cancel_token: None,
+            data_done: false,
+            first: None,
             stream: stream,
+            #[cfg(feature = "client")]
+            window: None,
         }
     }
 }
@@ -53,22 +70,30 @@ where
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref token) = self.cancel_token {
+            if token.poll_canceled().is_ready() {
+                trace!("send body canceled");
+                self.body_tx.send_reset(Reason::CANCEL);
+                return Ok(Async::Ready(()));
+            }
+        }
         loop {
-            // TODO: make use of flow control on SendStream
-            // If you're looking at this and thinking of trying to fix this TODO,
-            // you may want to look at:
-            // https://docs.rs/h2/0.1.*/h2/struct.SendStream.html
-            //
-            // With that doc open, we'd want to do these things:
-            // - check self.body_tx.capacity() to see if we can send *any* data
-            // - if > 0:
-            // -   poll self.stream
-            // -   reserve chunk.len() more capacity (because its about to be used)?
-            // -   send the chunk
-            // - else:
-            // -   try reserve a smallish amount of capacity
-            // -   call self.body_tx.poll_capacity(), return if NotReady
-            match self.stream.poll_data() {
+            if self.data_done {
+                return self.poll_trailers();
+            }
+            // Without a `SendWindow`, the body isn't told about the stream's
+            // flow control, and its data is buffered until it can be sent.
+            #[cfg(feature = "client")]
+            {
+                if let Some(ref window) = self.window {
+                    window.poll_stream(&mut self.body_tx);
+                }
+            }
+            let polled = match self.first.take() {
+                Some(chunk) => Ok(Async::Ready(Some(chunk))),
+                None => self.stream.poll_data(),
+            };
+            match polled {
                 Ok(Async::Ready(Some(chunk))) => {
                     let is_eos = self.stream.is_end_stream();
                     trace!(
@@ -76,6 +101,18 @@ where
                         chunk.remaining(),
                         is_eos,
                     );
+                    // An empty DATA frame that doesn't end the stream says
+                    // nothing, and would come before trailers ending it.
+                    if !chunk.has_remaining() && !is_eos {
+                        trace!("discarding empty chunk");
+                        continue;
+                    }
+                    #[cfg(feature = "client")]
+                    {
+                        if let Some(ref window) = self.window {
+                            window.on_sent(chunk.remaining());
+                        }
+                    }
 
                     let buf = SendBuf(Some(chunk));
                     self.body_tx.send_data(buf, is_eos)
@@ -86,10 +123,7 @@ where
                     }
                 },
                 Ok(Async::Ready(None)) => {
-                    trace!("send body eos");
-                    self.body_tx.send_data(SendBuf(None), true)
-                        .map_err(::Error::new_body_write)?;
-                    return Ok(Async::Ready(()));
+                    self.data_done = true;
                 },
                 Ok(Async::NotReady) => return Ok(Async::NotReady),
                 Err(err) => {
@@ -103,6 +137,49 @@ where
     }
 }
 
+impl<S> Drop for PipeToSendStream<S>
+where
+    S: Payload,
+{
+    fn drop(&mut self) {
+        #[cfg(feature = "client")]
+        {
+            if let Some(ref window) = self.window {
+                window.close();
+            }
+        }
+    }
+}
+
+impl<S> PipeToSendStream<S>
+where
+    S: Payload,
+{
+    fn poll_trailers(&mut self) -> Poll<(), ::Error> {
+        match self.stream.poll_trailers() {
+            Ok(Async::Ready(Some(mut trailers))) => {
+                trace!("send body trailers");
+                strip_connection_headers(&mut trailers);
+                self.body_tx.send_trailers(trailers)
+                    .map_err(::Error::new_body_write)?;
+            },
+            Ok(Async::Ready(None)) => {
+                trace!("send body eos");
+                self.body_tx.send_data(SendBuf(None), true)
+                    .map_err(::Error::new_body_write)?;
+            },
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(err) => {
+                let err = ::Error::new_user_body(err);
+                trace!("send body trailers user stream error: {}", err);
+                self.body_tx.send_reset(Reason::INTERNAL_ERROR);
+                return Err(err);
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
 struct SendBuf<B>(Option<B>);
 
 impl<B: Buf> Buf for SendBuf<B> {
@@ -1,40 +1,70 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use futures::{Async, Future, Poll, Stream};
-use h2::Reason;
+use futures::sync::oneshot;
+use futures::task::AtomicTask;
+use h2::{Reason, RecvStream};
 use h2::server::{Builder, Connection, Handshake, SendResponse};
 use tokio_io::{AsyncRead, AsyncWrite};
 
-use ::body::Payload;
-use ::common::Exec;
+use ::body::{ChunkSize, Payload};
+use ::common::{CancelToken, ConnectionStats, Exec, StatsIo};
+use ::server::conn::MethodPolicy;
+use ::server::request_id::{RequestId, RequestIds};
 use ::service::Service;
 use super::{PipeToSendStream, SendBuf};
 
-use ::{Body, Response};
+use ::{Body, Response, StatusCode};
 
 pub(crate) struct Server<T, S, B>
 where
     S: Service,
     B: Payload,
 {
+    cancel_token: Option<CancelToken>,
+    chunk_size: ChunkSize,
+    error_status: Option<StatusCode>,
     exec: Exec,
+    max_pending_streams: Option<usize>,
+    method_policy: Option<MethodPolicy>,
+    request_ids: Option<RequestIds>,
     service: S,
     state: State<T, B>,
+    stats: ConnectionStats,
 }
 
 enum State<T, B>
 where
     B: Payload,
 {
-    Handshaking(Handshake<T, SendBuf<B::Data>>),
+    Handshaking(Handshake<StatsIo<T>, SendBuf<B::Data>>),
     Serving(Serving<T, B>),
+    Closed,
 }
 
 struct Serving<T, B>
 where
     B: Payload,
 {
-    conn: Connection<T, SendBuf<B::Data>>,
+    cancel_token: Option<CancelToken>,
+    conn: Connection<StatsIo<T>, SendBuf<B::Data>>,
+    max_pending_streams: Option<usize>,
+    pending_streams: Arc<PendingStreams>,
+    stats: ConnectionStats,
+}
+
+// The streams of a connection whose `Service` hasn't answered yet, so that
+// no more are accepted while there are too many.
+struct PendingStreams {
+    count: AtomicUsize,
+    // The connection task, when it stopped accepting streams.
+    task: AtomicTask,
 }
 
+// A stream counted in `PendingStreams`, until dropped.
+struct Pending(Arc<PendingStreams>);
+
 
 impl<T, S, B> Server<T, S, B>
 where
@@ -44,18 +74,54 @@ where
     S::Future: Send + 'static,
     B: Payload,
 {
-    pub(crate) fn new(io: T, service: S, exec: Exec) -> Server<T, S, B> {
+    pub(crate) fn new(io: T, service: S, exec: Exec, request_ids: Option<RequestIds>, method_policy: Option<MethodPolicy>, error_status: Option<StatusCode>, chunk_size: ChunkSize) -> Server<T, S, B> {
+        let stats = ConnectionStats::new();
+        // There's no server push: h2 0.1 can't send PUSH_PROMISE frames.
         let handshake = Builder::new()
-            .handshake(io);
+            .handshake(StatsIo::new(io, stats.clone()));
         Server {
+            cancel_token: None,
+            chunk_size,
+            error_status,
             exec,
+            max_pending_streams: None,
+            method_policy,
+            request_ids,
             state: State::Handshaking(handshake),
             service,
+            stats,
         }
     }
 
+    pub(crate) fn stats(&self) -> &ConnectionStats {
+        &self.stats
+    }
+
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel_token = Some(token);
+    }
+
+    pub fn set_max_pending_streams(&mut self, max: usize) {
+        self.max_pending_streams = Some(max);
+    }
+
     pub fn graceful_shutdown(&mut self) {
-        unimplemented!("h2 server graceful shutdown");
+        trace!("graceful_shutdown");
+        match self.state {
+            State::Handshaking(..) => {
+                // No streams yet, so just close.
+            },
+            State::Serving(ref mut srv) => {
+                // Sends a `GOAWAY`, and finishes once the streams already
+                // opened are done.
+                srv.conn.graceful_shutdown();
+                return;
+            },
+            State::Closed => {
+                return;
+            },
+        }
+        self.state = State::Closed;
     }
 }
 
@@ -74,14 +140,30 @@ where
         loop {
             let next = match self.state {
                 State::Handshaking(ref mut h) => {
+                    if let Some(ref token) = self.cancel_token {
+                        if token.poll_canceled().is_ready() {
+                            debug!("connection canceled during handshake");
+                            return Ok(Async::Ready(()));
+                        }
+                    }
                     let conn = try_ready!(h.poll().map_err(::Error::new_h2));
                     State::Serving(Serving {
+                        cancel_token: self.cancel_token.clone(),
                         conn: conn,
+                        max_pending_streams: self.max_pending_streams,
+                        pending_streams: Arc::new(PendingStreams {
+                            count: AtomicUsize::new(0),
+                            task: AtomicTask::new(),
+                        }),
+                        stats: self.stats.clone(),
                     })
                 },
                 State::Serving(ref mut srv) => {
-                    return srv.poll_server(&mut self.service, &self.exec);
-                }
+                    return srv.poll_server(&mut self.service, &self.exec, self.request_ids.as_ref(), self.method_policy.as_ref(), self.error_status, self.chunk_size);
+                },
+                State::Closed => {
+                    return Ok(Async::Ready(()));
+                },
             };
             self.state = next;
         }
@@ -93,7 +175,7 @@ where
     T: AsyncRead + AsyncWrite,
     B: Payload,
 {
-    fn poll_server<S>(&mut self, service: &mut S, exec: &Exec) -> Poll<(), ::Error>
+    fn poll_server<S>(&mut self, service: &mut S, exec: &Exec, request_ids: Option<&RequestIds>, method_policy: Option<&MethodPolicy>, error_status: Option<StatusCode>, chunk_size: ChunkSize) -> Poll<(), ::Error>
     where
         S: Service<
             ReqBody=Body,
@@ -102,10 +184,68 @@ where
         S::Error: Into<Box<::std::error::Error + Send + Sync>>,
         S::Future: Send + 'static,
     {
-        while let Some((req, respond)) = try_ready!(self.conn.poll().map_err(::Error::new_h2)) {
+        let is_canceled = match self.cancel_token {
+            Some(ref token) => token.poll_canceled().is_ready(),
+            None => false,
+        };
+        if is_canceled {
+            debug!("connection canceled, closing");
+            // No more requests are accepted after this, so the token
+            // doesn't need to be watched anymore.
+            self.cancel_token = None;
+            self.conn.abrupt_shutdown(Reason::CANCEL);
+        }
+
+        loop {
+            if let Some(max) = self.max_pending_streams {
+                // Registered before checking, so a stream answered in
+                // between still wakes this task.
+                self.pending_streams.task.register();
+                if self.pending_streams.count.load(Ordering::Acquire) >= max {
+                    // New streams wait in h2 until a `Service` answers,
+                    // while the connection still makes progress on the
+                    // streams already accepted.
+                    trace!("{} streams awaiting service, not accepting more", max);
+                    return self.conn.poll_close().map_err(::Error::new_h2);
+                }
+            }
+            let (req, mut respond) = match try_ready!(self.conn.poll().map_err(::Error::new_h2)) {
+                Some(stream) => stream,
+                None => break,
+            };
             trace!("incoming request");
-            let req = req.map(::Body::h2);
-            let fut = H2Stream::new(service.call(req), respond);
+            let (reject_tx, rejected) = oneshot::channel();
+            let mut req = req.map(|recv| {
+                let mut body = ::Body::h2(recv, chunk_size);
+                body.on_reject(reject_tx);
+                body
+            });
+            self.stats.on_request();
+            req.extensions_mut().insert(self.stats.clone());
+            if let Some(ref token) = self.cancel_token {
+                req.extensions_mut().insert(token.clone());
+            }
+            let request_id = request_ids.map(|ids| {
+                let id = ids.on_request(&mut req);
+                (ids.clone(), id)
+            });
+            if let Some(policy) = method_policy {
+                if !policy.is_allowed(req.method()) {
+                    debug!("rejecting request with method not allowed: {}", req.method());
+                    let mut res = ::http::Response::new(());
+                    *res.status_mut() = ::StatusCode::METHOD_NOT_ALLOWED;
+                    *res.headers_mut() = policy.rejection_headers();
+                    if let Some((ids, id)) = request_id {
+                        ids.on_response(id, res.headers_mut());
+                    }
+                    if let Err(e) = respond.send_response(res, true) {
+                        trace!("send response error: {}", e);
+                    }
+                    continue;
+                }
+            }
+            let pending = self.max_pending_streams.map(|_| Pending::new(&self.pending_streams));
+            let fut = H2Stream::new(service.call(req), respond, request_id, error_status, self.cancel_token.clone(), rejected, pending);
             exec.execute(fut);
         }
 
@@ -119,10 +259,23 @@ struct H2Stream<F, B>
 where
     B: Payload,
 {
+    cancel_token: Option<CancelToken>,
+    error_status: Option<StatusCode>,
+    // Counts this stream until the service answers.
+    pending: Option<Pending>,
+    // The request body, once the service rejects it.
+    rejected: Rejected,
     reply: SendResponse<SendBuf<B::Data>>,
+    request_id: Option<(RequestIds, RequestId)>,
     state: H2StreamState<F, B>,
 }
 
+enum Rejected {
+    Pending(oneshot::Receiver<RecvStream>),
+    Discarding(RecvStream),
+    Done,
+}
+
 enum H2StreamState<F, B>
 where
     B: Payload,
@@ -137,21 +290,44 @@ where
     F::Error: Into<Box<::std::error::Error + Send + Sync>>,
     B: Payload,
 {
-    fn new(fut: F, respond: SendResponse<SendBuf<B::Data>>) -> H2Stream<F, B> {
+    fn new(fut: F, respond: SendResponse<SendBuf<B::Data>>, request_id: Option<(RequestIds, RequestId)>, error_status: Option<StatusCode>, cancel_token: Option<CancelToken>, rejected: oneshot::Receiver<RecvStream>, pending: Option<Pending>) -> H2Stream<F, B> {
         H2Stream {
+            cancel_token,
+            error_status,
+            pending,
+            rejected: Rejected::Pending(rejected),
             reply: respond,
+            request_id,
             state: H2StreamState::Service(fut),
         }
     }
 
     fn poll2(&mut self) -> Poll<(), ::Error> {
+        if let Some(ref token) = self.cancel_token {
+            if token.poll_canceled().is_ready() {
+                // The connection is closing, so the `Service` future is
+                // dropped, instead of finishing a response nobody reads.
+                trace!("stream canceled");
+                self.reply.send_reset(Reason::CANCEL);
+                return Ok(Async::Ready(()));
+            }
+        }
+        self.poll_rejected();
         loop {
             let next = match self.state {
                 H2StreamState::Service(ref mut h) => {
-                    let res = try_ready!(h.poll().map_err(::Error::new_user_service));
+                    let res = match h.poll() {
+                        Ok(Async::Ready(res)) => res,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => return self.reply_error(::Error::new_user_service(e)),
+                    };
+                    self.pending = None;
                     let (head, body) = res.into_parts();
                     let mut res = ::http::Response::from_parts(head, ());
                     super::strip_connection_headers(res.headers_mut());
+                    if let Some((ids, id)) = self.request_id.take() {
+                        ids.on_response(id, res.headers_mut());
+                    }
                     macro_rules! reply {
                         ($eos:expr) => ({
                             match self.reply.send_response(res, $eos) {
@@ -181,6 +357,58 @@ where
     }
 }
 
+impl<F, B> H2Stream<F, B>
+where
+    B: Payload,
+{
+    // A rejected request body is discarded as it arrives, releasing its
+    // flow control capacity so other streams don't stall. Once the response
+    // ends, the stream is dropped, and a client still sending is reset.
+    fn poll_rejected(&mut self) {
+        loop {
+            let next = match self.rejected {
+                Rejected::Pending(ref mut rx) => match rx.poll() {
+                    Ok(Async::Ready(recv)) => {
+                        trace!("request body rejected, discarding");
+                        Rejected::Discarding(recv)
+                    },
+                    Ok(Async::NotReady) => return,
+                    Err(_dropped) => Rejected::Done,
+                },
+                Rejected::Discarding(ref mut recv) => match recv.poll() {
+                    Ok(Async::Ready(Some(bytes))) => {
+                        let _ = recv.release_capacity().release_capacity(bytes.len());
+                        continue;
+                    },
+                    Ok(Async::NotReady) => return,
+                    Ok(Async::Ready(None)) | Err(_) => Rejected::Done,
+                },
+                Rejected::Done => return,
+            };
+            self.rejected = next;
+        }
+    }
+
+    // Answer with the `error_status`, if there is one, instead of resetting
+    // the stream.
+    fn reply_error(&mut self, err: ::Error) -> Poll<(), ::Error> {
+        let status = match self.error_status {
+            Some(status) => status,
+            None => return Err(err),
+        };
+        debug!("service error, responding with {}: {}", status, err);
+        let mut res = ::http::Response::new(());
+        *res.status_mut() = status;
+        if let Some((ids, id)) = self.request_id.take() {
+            ids.on_response(id, res.headers_mut());
+        }
+        match self.reply.send_response(res, true) {
+            Ok(_) => Ok(Async::Ready(())),
+            Err(e) => Err(::Error::new_h2(e)),
+        }
+    }
+}
+
 impl<F, B> Future for H2Stream<F, B>
 where
     F: Future<Item=Response<B>>,
@@ -196,3 +424,17 @@ where
     }
 }
 
+
+impl Pending {
+    fn new(streams: &Arc<PendingStreams>) -> Pending {
+        streams.count.fetch_add(1, Ordering::AcqRel);
+        Pending(streams.clone())
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::AcqRel);
+        self.0.task.notify();
+    }
+}
@@ -0,0 +1,317 @@
+//! Reading the settings an HTTP/2 server advertised off the transport.
+//!
+//! The `h2` crate applies the server's `SETTINGS` frames, but doesn't tell
+//! what they were, so they're picked out of the bytes as they're read.
+//! They're only kept once `h2` writes the `SETTINGS` ACK of their frame,
+//! which it only does after checking them, so settings the connection
+//! fails on are never seen.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use bytes::Buf;
+use futures::{Async, Poll};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use client::conn::PeerSettings;
+use client::settings::SettingsSlot;
+
+const PREFACE_LEN: usize = 24;
+const FRAME_HEADER_LEN: usize = 9;
+const FRAME_TYPE_SETTINGS: u8 = 0x4;
+const FLAG_ACK: u8 = 0x1;
+const SETTING_LEN: usize = 6;
+
+/// Walks the frames read from a connection, collecting the settings of
+/// each `SETTINGS` frame once it was read whole.
+struct Frames {
+    head: [u8; FRAME_HEADER_LEN],
+    head_read: usize,
+    // Payload bytes of the current frame left to read.
+    remaining: usize,
+    // The settings so far, while reading a SETTINGS frame.
+    pending: Option<PeerSettings>,
+    setting: [u8; SETTING_LEN],
+    setting_read: usize,
+    // The settings of the last SETTINGS frame read, acknowledged or not.
+    settings: PeerSettings,
+    // The settings of each frame read, until it's acknowledged.
+    unacked: VecDeque<PeerSettings>,
+}
+
+/// Walks the frames written to a connection, counting `SETTINGS` ACKs.
+struct Acks {
+    // How much of the preface is left to pass over.
+    preface: usize,
+    head: [u8; FRAME_HEADER_LEN],
+    head_read: usize,
+    // Payload bytes of the current frame left to pass over.
+    remaining: usize,
+}
+
+impl Frames {
+    fn new() -> Frames {
+        Frames {
+            head: [0; FRAME_HEADER_LEN],
+            head_read: 0,
+            remaining: 0,
+            pending: None,
+            setting: [0; SETTING_LEN],
+            setting_read: 0,
+            settings: PeerSettings::default(),
+            unacked: VecDeque::new(),
+        }
+    }
+
+    fn observe(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            if self.head_read < FRAME_HEADER_LEN {
+                let n = cmp::min(buf.len(), FRAME_HEADER_LEN - self.head_read);
+                self.head[self.head_read..self.head_read + n].copy_from_slice(&buf[..n]);
+                self.head_read += n;
+                buf = &buf[n..];
+                if self.head_read == FRAME_HEADER_LEN {
+                    self.on_head();
+                }
+                continue;
+            }
+
+            let n = cmp::min(buf.len(), self.remaining);
+            if self.pending.is_some() {
+                for &byte in &buf[..n] {
+                    self.on_settings_byte(byte);
+                }
+            }
+            self.remaining -= n;
+            buf = &buf[n..];
+            if self.remaining == 0 {
+                self.on_end();
+            }
+        }
+    }
+
+    fn on_head(&mut self) {
+        self.remaining = frame_len(&self.head);
+        if is_settings(&self.head, false) {
+            self.pending = Some(self.settings);
+            self.setting_read = 0;
+        }
+        if self.remaining == 0 {
+            self.on_end();
+        }
+    }
+
+    fn on_settings_byte(&mut self, byte: u8) {
+        self.setting[self.setting_read] = byte;
+        self.setting_read += 1;
+        if self.setting_read < SETTING_LEN {
+            return;
+        }
+        self.setting_read = 0;
+        let s = &self.setting;
+        let id = (s[0] as u16) << 8 | s[1] as u16;
+        let value = (s[2] as u32) << 24 | (s[3] as u32) << 16 | (s[4] as u32) << 8 | s[5] as u32;
+        if let Some(ref mut pending) = self.pending {
+            pending.apply(id, value);
+        }
+    }
+
+    fn on_end(&mut self) {
+        self.head_read = 0;
+        if let Some(settings) = self.pending.take() {
+            trace!("peer settings read: {:?}", settings);
+            self.settings = settings;
+            self.unacked.push_back(settings);
+        }
+    }
+}
+
+impl Acks {
+    fn new() -> Acks {
+        Acks {
+            preface: PREFACE_LEN,
+            head: [0; FRAME_HEADER_LEN],
+            head_read: 0,
+            remaining: 0,
+        }
+    }
+
+    // Returns how many `SETTINGS` ACKs `buf` finished.
+    fn observe(&mut self, mut buf: &[u8]) -> usize {
+        let mut acks = 0;
+        while !buf.is_empty() {
+            if self.preface > 0 {
+                let n = cmp::min(buf.len(), self.preface);
+                self.preface -= n;
+                buf = &buf[n..];
+            } else if self.remaining > 0 {
+                let n = cmp::min(buf.len(), self.remaining);
+                self.remaining -= n;
+                buf = &buf[n..];
+            } else {
+                let n = cmp::min(buf.len(), FRAME_HEADER_LEN - self.head_read);
+                self.head[self.head_read..self.head_read + n].copy_from_slice(&buf[..n]);
+                self.head_read += n;
+                buf = &buf[n..];
+                if self.head_read == FRAME_HEADER_LEN {
+                    self.head_read = 0;
+                    self.remaining = frame_len(&self.head);
+                    if is_settings(&self.head, true) {
+                        acks += 1;
+                    }
+                }
+            }
+        }
+        acks
+    }
+}
+
+fn frame_len(head: &[u8; FRAME_HEADER_LEN]) -> usize {
+    (head[0] as usize) << 16 | (head[1] as usize) << 8 | head[2] as usize
+}
+
+/// Whether the frame header is that of a `SETTINGS` frame on the
+/// connection stream, with or without the ACK flag.
+fn is_settings(head: &[u8; FRAME_HEADER_LEN], ack: bool) -> bool {
+    head[3] == FRAME_TYPE_SETTINGS
+        && (head[4] & FLAG_ACK != 0) == ack
+        && head[5..] == [0, 0, 0, 0]
+}
+
+struct Watch {
+    acks: Acks,
+    frames: Frames,
+    slot: SettingsSlot,
+}
+
+/// An IO that keeps the settings read from it in a `SettingsSlot`, if
+/// given one, and otherwise just passes everything through.
+pub(crate) struct WatchSettings<T> {
+    io: T,
+    watch: Option<Watch>,
+}
+
+impl<T> WatchSettings<T> {
+    pub(crate) fn new(io: T, slot: Option<SettingsSlot>) -> WatchSettings<T> {
+        WatchSettings {
+            io,
+            watch: slot.map(|slot| Watch {
+                acks: Acks::new(),
+                frames: Frames::new(),
+                slot,
+            }),
+        }
+    }
+}
+
+impl Watch {
+    fn on_write(&mut self, buf: &[u8]) {
+        for _ in 0..self.acks.observe(buf) {
+            if let Some(settings) = self.frames.unacked.pop_front() {
+                trace!("peer settings acknowledged: {:?}", settings);
+                self.slot.set(settings);
+            }
+        }
+    }
+}
+
+impl<T: Read> Read for WatchSettings<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.io.read(buf)?;
+        if let Some(ref mut watch) = self.watch {
+            watch.frames.observe(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for WatchSettings<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.io.write(buf)?;
+        if let Some(ref mut watch) = self.watch {
+            watch.on_write(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for WatchSettings<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for WatchSettings<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        if self.watch.is_none() {
+            return self.io.write_buf(buf);
+        }
+        let cnt = try_nb!(self.write(buf.bytes()));
+        buf.advance(cnt);
+        Ok(Async::Ready(cnt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use client::conn::PeerSettings;
+    use super::{Acks, Frames};
+
+    #[test]
+    fn settings_split_across_reads() {
+        let mut frames = Frames::new();
+        // SETTINGS with MAX_CONCURRENT_STREAMS 100 and MAX_FRAME_SIZE 32768
+        let settings = b"\x00\x00\x0c\x04\x00\x00\x00\x00\x00\
+                         \x00\x03\x00\x00\x00\x64\
+                         \x00\x05\x00\x00\x80\x00";
+
+        frames.observe(&settings[..4]);
+        frames.observe(&settings[4..14]);
+        assert!(frames.unacked.is_empty(), "not until the whole frame is read");
+        frames.observe(&settings[14..]);
+
+        let got = frames.unacked.pop_front().expect("settings");
+        assert_eq!(got.max_concurrent_streams(), Some(100));
+        assert_eq!(got.max_frame_size(), 32_768);
+        assert_eq!(got.initial_window_size(), 65_535);
+        assert_eq!(got.max_header_list_size(), None);
+    }
+
+    #[test]
+    fn later_settings_update_earlier() {
+        let mut frames = Frames::new();
+
+        // empty SETTINGS, a DATA frame on stream 1 with a payload that
+        // looks like a setting, a SETTINGS ACK, then INITIAL_WINDOW_SIZE
+        frames.observe(b"\x00\x00\x00\x04\x00\x00\x00\x00\x00");
+        assert_eq!(frames.unacked.pop_front(), Some(PeerSettings::default()));
+        frames.observe(b"\x00\x00\x06\x00\x00\x00\x00\x00\x01\x00\x03\x00\x00\x00\x01");
+        frames.observe(b"\x00\x00\x00\x04\x01\x00\x00\x00\x00");
+        assert!(frames.unacked.is_empty());
+        frames.observe(b"\x00\x00\x06\x04\x00\x00\x00\x00\x00\x00\x04\x00\x01\x00\x00");
+
+        let got = frames.unacked.pop_front().expect("settings");
+        assert_eq!(got.max_concurrent_streams(), None);
+        assert_eq!(got.initial_window_size(), 65_536);
+    }
+
+    #[test]
+    fn acks_after_preface() {
+        let mut acks = Acks::new();
+        // the preface, then the client's SETTINGS, which isn't an ACK
+        assert_eq!(acks.observe(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n"), 0);
+        assert_eq!(acks.observe(b"\x00\x00\x06\x04\x00\x00\x00\x00\x00\x00\x02\x00\x00\x00\x00"), 0);
+        // a WINDOW_UPDATE, and an ACK split across writes
+        assert_eq!(acks.observe(b"\x00\x00\x04\x08\x00\x00\x00\x00\x00\x00\x00\x10\x00\x00\x00"), 0);
+        assert_eq!(acks.observe(b"\x00\x04\x01\x00\x00\x00\x00"), 1);
+    }
+}
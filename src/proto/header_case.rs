@@ -0,0 +1,69 @@
+use std::str;
+
+use bytes::Bytes;
+use http::HeaderMap;
+use http::header::{HeaderName, InvalidHeaderName};
+#[cfg(feature = "http1")]
+use http::header::ValueIter;
+
+/// The original casing of header names, on an HTTP/1 connection.
+///
+/// When a connection is configured to preserve header case, a
+/// `HeaderCaseMap` is put in the extensions of every `Request` or
+/// `Response` it reads, recording each header name exactly as it was
+/// received.
+///
+/// A `HeaderCaseMap` in the extensions of an outgoing `Request` or
+/// `Response` decides how its header names are written, so a proxy can
+/// forward the map it received to write the same bytes. A header value
+/// without a casing of its own uses the one of the previous value with the
+/// same name, and otherwise the name is written as it would have been.
+///
+/// Casing doesn't exist in HTTP/2, so it ignores a `HeaderCaseMap`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeaderCaseMap {
+    names: HeaderMap<Bytes>,
+}
+
+impl HeaderCaseMap {
+    /// Create an empty `HeaderCaseMap`.
+    pub fn new() -> HeaderCaseMap {
+        HeaderCaseMap::default()
+    }
+
+    /// Record the casing of the next value of a header, such as
+    /// `"X-Legacy-ID"`.
+    ///
+    /// The header is the one the name refers to regardless of case, so
+    /// casings are recorded in the order of the values in the `HeaderMap`.
+    pub fn append(&mut self, original: &str) -> Result<(), InvalidHeaderName> {
+        let name = HeaderName::from_bytes(original.as_bytes())?;
+        self.names.append(name, Bytes::from(original));
+        Ok(())
+    }
+
+    /// The casing of the first value of a header, if any.
+    pub fn get(&self, name: &HeaderName) -> Option<&str> {
+        self.names.get(name).map(to_str)
+    }
+
+    /// The casings of the values of a header, in order.
+    pub fn get_all(&self, name: &HeaderName) -> Vec<&str> {
+        self.names.get_all(name).iter().map(to_str).collect()
+    }
+
+    #[cfg(feature = "http1")]
+    pub(crate) fn append_bytes(&mut self, name: &HeaderName, original: Bytes) {
+        self.names.append(name, original);
+    }
+
+    #[cfg(feature = "http1")]
+    pub(crate) fn names<'a>(&'a self, name: &HeaderName) -> ValueIter<'a, Bytes> {
+        self.names.get_all(name).iter()
+    }
+}
+
+fn to_str(original: &Bytes) -> &str {
+    // Only valid header names are recorded, which are ASCII.
+    str::from_utf8(original).expect("header name already validated")
+}
@@ -0,0 +1,60 @@
+use std::slice;
+
+use http::header::HeaderName;
+
+/// The order of the header lines of a message, on an HTTP/1 connection.
+///
+/// A `HeaderMap` groups the values of a header together, so the order in
+/// which different headers were interleaved is lost. When a connection is
+/// configured to preserve header order, a `HeaderOrder` is put in the
+/// extensions of every `Request` or `Response` it reads, recording the
+/// header lines exactly in the order they were received.
+///
+/// A `HeaderOrder` in the extensions of an outgoing `Request` or `Response`
+/// decides the order its header lines are written in, so a proxy can
+/// forward the order it received to write the same bytes. Each entry names
+/// a header and the index of one of its values in the `HeaderMap`. Lines
+/// that aren't in the order, such as the `Date` added to responses, are
+/// written after the ones that are.
+///
+/// Order doesn't matter in HTTP/2, so it ignores a `HeaderOrder`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeaderOrder {
+    entries: Vec<(HeaderName, usize)>,
+}
+
+impl HeaderOrder {
+    /// Create an empty `HeaderOrder`.
+    pub fn new() -> HeaderOrder {
+        HeaderOrder::default()
+    }
+
+    /// Record that the next value of a header comes after the lines
+    /// recorded so far.
+    ///
+    /// The first value of each header is index `0`, the second `1`, and so
+    /// on, in the order of the values in the `HeaderMap`.
+    pub fn push(&mut self, name: HeaderName) {
+        let index = self.entries
+            .iter()
+            .filter(|&&(ref other, _)| *other == name)
+            .count();
+        self.entries.push((name, index));
+    }
+
+    /// The header lines recorded, as the name and index of each value, in
+    /// order.
+    pub fn iter<'a>(&'a self) -> slice::Iter<'a, (HeaderName, usize)> {
+        self.entries.iter()
+    }
+
+    /// The number of header lines recorded.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no header lines are recorded.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
@@ -1,13 +1,56 @@
 //! Pieces pertaining to the HTTP message protocol.
-use http::{HeaderMap, Method, StatusCode, Uri, Version};
-
-pub(crate) use self::h1::{dispatch, Conn, ClientTransaction, ClientUpgradeTransaction, ServerTransaction};
-
+#[cfg(any(feature = "http1", feature = "server"))]
+use http::{HeaderMap, Version};
+#[cfg(feature = "http1")]
+use http::{Method, Uri};
+#[cfg(all(feature = "http1", feature = "client"))]
+use http::StatusCode;
+
+pub use self::header_case::HeaderCaseMap;
+pub use self::header_order::HeaderOrder;
+pub use self::reason_phrase::{InvalidReasonPhrase, ReasonPhrase};
+#[cfg(feature = "http1")]
+pub(crate) use self::h1::Conn;
+#[cfg(all(feature = "http1", feature = "client"))]
+pub(crate) use self::h1::{ClientTransaction, ClientUpgradeTransaction};
+#[cfg(all(feature = "http1", feature = "server"))]
+pub(crate) use self::h1::ServerTransaction;
+
+#[cfg(feature = "http1")]
 pub(crate) mod h1;
+#[cfg(feature = "http2")]
 pub(crate) mod h2;
-
+mod header_case;
+mod header_order;
+mod reason_phrase;
+
+/// The minimum value that can be set to the max buffer size of HTTP/1
+/// connections.
+pub const MINIMUM_MAX_BUFFER_SIZE: usize = 8192;
+
+
+/// Tolerances for malformed incoming heads, all off by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Lenient {
+    /// Responses without a status line or headers, as sent by HTTP/0.9
+    /// servers, read as a body until the connection closes.
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
+    pub(crate) http09_responses: bool,
+    /// Header values continued on lines starting with whitespace.
+    pub(crate) obsolete_line_folding: bool,
+    /// Spaces inside the request-target, or extra ones around it.
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub(crate) spaces_in_request_target: bool,
+    /// Whitespace between a header name and its colon, or before the first
+    /// header name, of a response. Requests with it are always rejected, as
+    /// RFC 7230 section 3.2.4 requires, since a proxy might read the header
+    /// differently.
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
+    pub(crate) whitespace_around_header_names: bool,
+}
 
 /// An Incoming Message head. Includes request/status line, and headers.
+#[cfg(any(feature = "http1", feature = "server"))]
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct MessageHead<S> {
     /// HTTP version of the message.
@@ -16,15 +59,24 @@ pub struct MessageHead<S> {
     pub subject: S,
     /// Headers of the Incoming message.
     pub headers: HeaderMap,
+    /// The original casing of the header names, if it's preserved.
+    pub header_case: Option<HeaderCaseMap>,
+    /// The original order of the header lines, if it's preserved.
+    pub header_order: Option<HeaderOrder>,
+    /// The reason phrase of a response, if it isn't the canonical one.
+    pub reason: Option<ReasonPhrase>,
 }
 
 /// An incoming request message.
+#[cfg(feature = "http1")]
 pub type RequestHead = MessageHead<RequestLine>;
 
+#[cfg(feature = "http1")]
 #[derive(Debug, Default, PartialEq)]
 pub struct RequestLine(pub Method, pub Uri);
 
 /// An incoming response message.
+#[cfg(all(feature = "http1", feature = "client"))]
 pub type ResponseHead = MessageHead<StatusCode>;
 
 /*
@@ -55,6 +107,33 @@ pub fn expecting_continue(version: Version, headers: &HeaderMap) -> bool {
 }
 */
 
+/// How an HTTP/1 connection treats outgoing header values that contain `CR`,
+/// `LF`, or `NUL` bytes.
+///
+/// Such bytes allow a header value to end its line early, injecting extra
+/// headers (or a whole extra message) onto the wire. They can only get into
+/// a `HeaderValue` through the unchecked constructors, so they usually point
+/// to a bug upstream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderValuePolicy {
+    /// Replace each offending byte with a space, and send the message.
+    Sanitize,
+    /// Don't send the message.
+    ///
+    /// A server responds with an empty `500 Internal Server Error` instead,
+    /// and keeps the connection alive. A client has nothing it could send
+    /// instead, so the request fails, and the connection may still send
+    /// other requests.
+    Reject,
+    /// Don't send the message, and close the connection afterwards.
+    ///
+    /// A server closes it after the `500 Internal Server Error`, and a
+    /// client fails the request and closes it.
+    RejectAndClose,
+}
+
+/// The length of a message body, deciding how it's delimited.
+#[cfg(feature = "http1")]
 #[derive(Debug)]
 pub enum BodyLength {
     /// Content-Length
@@ -0,0 +1,101 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use bytes::Bytes;
+
+/// The reason phrase of a response, on an HTTP/1 connection.
+///
+/// HTTP/1 responses start with a status line such as `200 OK`, where `OK`
+/// is the reason phrase. A `StatusCode` only knows the canonical reason of
+/// each status, so other reason phrases are lost.
+///
+/// A response read by an HTTP/1 connection has a `ReasonPhrase` in its
+/// extensions when the reason phrase it was sent with isn't the canonical
+/// one of its status, including when it is missing.
+///
+/// A `ReasonPhrase` in the extensions of a `Response` sent by an HTTP/1
+/// server is written instead of the canonical reason.
+///
+/// Reason phrases don't exist in HTTP/2, so it ignores a `ReasonPhrase`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ReasonPhrase(Bytes);
+
+/// An error returned when bytes aren't a valid reason phrase.
+#[derive(Debug)]
+pub struct InvalidReasonPhrase {
+    _priv: (),
+}
+
+impl ReasonPhrase {
+    /// Create a `ReasonPhrase` from a static string.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the string isn't a valid reason phrase, such as if it
+    /// contains a line break.
+    pub fn from_static(reason: &'static str) -> ReasonPhrase {
+        match ReasonPhrase::from_bytes(reason.as_bytes()) {
+            Ok(reason) => reason,
+            Err(_) => panic!("invalid reason phrase: {:?}", reason),
+        }
+    }
+
+    /// Create a `ReasonPhrase` from bytes, checking that they are a valid
+    /// reason phrase.
+    ///
+    /// A reason phrase may contain tabs, spaces, visible ASCII characters,
+    /// and bytes above `0x7F`.
+    pub fn from_bytes(reason: &[u8]) -> Result<ReasonPhrase, InvalidReasonPhrase> {
+        if reason.iter().all(|&b| is_valid(b)) {
+            Ok(ReasonPhrase(Bytes::from(reason)))
+        } else {
+            Err(InvalidReasonPhrase { _priv: () })
+        }
+    }
+
+    /// The bytes of this reason phrase.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    #[cfg(all(feature = "http1", feature = "client"))]
+    pub(crate) fn from_shared_unchecked(reason: Bytes) -> ReasonPhrase {
+        ReasonPhrase(reason)
+    }
+}
+
+impl AsRef<[u8]> for ReasonPhrase {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+fn is_valid(b: u8) -> bool {
+    b == b'\t' || b == b' ' || (b >= 0x21 && b != 0x7F)
+}
+
+impl fmt::Display for InvalidReasonPhrase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid reason phrase")
+    }
+}
+
+impl StdError for InvalidReasonPhrase {
+    fn description(&self) -> &str {
+        "invalid reason phrase"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_bytes() {
+        assert_eq!(ReasonPhrase::from_bytes(b"Alright\tThen").unwrap().as_bytes(), b"Alright\tThen");
+        assert_eq!(ReasonPhrase::from_bytes(b"").unwrap().as_bytes(), b"");
+        assert_eq!(ReasonPhrase::from_bytes(b"Caf\xe9").unwrap().as_bytes(), b"Caf\xe9");
+        assert!(ReasonPhrase::from_bytes(b"OK\r\nX-Injected: 1").is_err());
+        assert!(ReasonPhrase::from_bytes(b"Bad\x7f").is_err());
+    }
+}
@@ -0,0 +1,363 @@
+//! Helpers for writing a reverse proxy.
+//!
+//! A reverse proxy serves requests by sending them on to an upstream server,
+//! and replying with the upstream's response. [`forward`](forward) does the
+//! HTTP part of that, for a request received by a `Service`, and an upstream
+//! connection made with [`client::conn`](::client::conn):
+//!
+//! - Hop-by-hop headers, like `Connection`, `Keep-Alive`, or
+//!   `Transfer-Encoding`, and any header named in `Connection`, are only
+//!   meaningful to the immediate peer, and are removed from the request and
+//!   from the response.
+//! - `Expect: 100-continue` isn't relayed. It is answered by this server,
+//!   and the `Expect` header is removed, see [`forward`](forward).
+//! - The bodies are passed along as they are, so trailers make it through
+//!   when both connections use HTTP/2. HTTP/1 bodies don't carry trailers.
+//! - `Upgrade` requests keep their `Upgrade` and `Connection: upgrade`
+//!   headers, and so does a `101 Switching Protocols` response. Once both
+//!   connections are done, [`tunnel`](tunnel) relays the new protocol
+//!   between them.
+
+use std::fmt;
+use std::io;
+
+use bytes::Bytes;
+use futures::{Async, Future, Poll};
+use http::{HeaderMap, Request, Response, StatusCode};
+use http::header::{self, HeaderName, HeaderValue};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use body::{Body, Payload};
+use client::conn::{self, SendRequest};
+use server;
+
+// The size of the buffer used for each direction of a `Tunnel`.
+const TUNNEL_BUF_SIZE: usize = 8 * 1024;
+
+/// Forward a request to an upstream connection.
+///
+/// The hop-by-hop headers of the request and of the response are removed,
+/// see the [module documentation](index.html) for the details.
+///
+/// # Expect: 100-continue
+///
+/// This is not relayed to upstream: the `Expect` header is removed. By
+/// default, this server sends `100 Continue` as soon as it reads the
+/// request head, so the client sends its body even if upstream would
+/// reject the request, such as with `413 Payload Too Large`.
+///
+/// To check a request before its body is sent, disable
+/// [`http1_auto_continue`](::server::conn::Http::http1_auto_continue), and
+/// call [`ExpectContinue::send_continue`](::server::conn::ExpectContinue::send_continue)
+/// once the `Service` has decided to forward it. Upstream still can't be
+/// asked first.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// # use futures::Future;
+/// # use hyper::{Body, Request};
+/// # use hyper::client::conn::SendRequest;
+/// # fn doc(req: Request<Body>, upstream: &mut SendRequest<Body>) {
+/// let fut = hyper::proxy::forward(req, upstream)
+///     .map(|res| {
+///         println!("upstream responded with {}", res.status());
+///         res
+///     });
+/// # drop(fut);
+/// # }
+/// # fn main() {}
+/// ```
+pub fn forward<B>(mut req: Request<B>, send_request: &mut SendRequest<B>) -> Forward
+where
+    B: Payload + 'static,
+{
+    let is_upgrade = wants_upgrade(req.headers());
+    strip_hop_by_hop(req.headers_mut(), is_upgrade);
+    if req.headers_mut().remove(header::EXPECT).is_some() {
+        trace!("removed Expect header, already handled by this server");
+    }
+    Forward {
+        inner: send_request.send_request(req),
+    }
+}
+
+/// A `Future` for the upstream response of a forwarded request.
+///
+/// See [`forward`](forward).
+#[must_use = "futures do nothing unless polled"]
+pub struct Forward {
+    inner: conn::ResponseFuture,
+}
+
+impl fmt::Debug for Forward {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Future<Forward>")
+    }
+}
+
+impl Future for Forward {
+    type Item = Response<Body>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut res = try_ready!(self.inner.poll());
+        let is_upgrade = res.status() == StatusCode::SWITCHING_PROTOCOLS;
+        strip_hop_by_hop(res.headers_mut(), is_upgrade);
+        Ok(Async::Ready(res))
+    }
+}
+
+/// Relay bytes between two connections, after an upgrade was forwarded.
+///
+/// The `downstream` parts come from the server `Connection` that received the
+/// upgrade request, and the `upstream` parts from the client `Connection` it
+/// was forwarded on. Any bytes already read on either side are passed along
+/// first.
+///
+/// The returned future resolves once both sides have reached EOF, and have
+/// been shut down for writing.
+pub fn tunnel<T, S, U>(downstream: server::conn::Parts<T, S>, upstream: conn::Parts<U>) -> Tunnel<T, U>
+where
+    T: AsyncRead + AsyncWrite,
+    U: AsyncRead + AsyncWrite,
+{
+    Tunnel {
+        downstream: downstream.io,
+        upstream: upstream.io,
+        to_upstream: Pipe::new(downstream.read_buf),
+        to_downstream: Pipe::new(upstream.read_buf),
+    }
+}
+
+/// A `Future` relaying bytes between two upgraded connections.
+///
+/// See [`tunnel`](tunnel).
+#[must_use = "futures do nothing unless polled"]
+pub struct Tunnel<T, U> {
+    downstream: T,
+    upstream: U,
+    to_upstream: Pipe,
+    to_downstream: Pipe,
+}
+
+impl<T, U> fmt::Debug for Tunnel<T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tunnel")
+            .field("to_upstream", &self.to_upstream)
+            .field("to_downstream", &self.to_downstream)
+            .finish()
+    }
+}
+
+impl<T, U> Future for Tunnel<T, U>
+where
+    T: AsyncRead + AsyncWrite,
+    U: AsyncRead + AsyncWrite,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let up = self.to_upstream.poll_pipe(&mut self.downstream, &mut self.upstream)?;
+        let down = self.to_downstream.poll_pipe(&mut self.upstream, &mut self.downstream)?;
+        if up.is_ready() && down.is_ready() {
+            trace!(
+                "tunnel closed, {} bytes upstream, {} bytes downstream",
+                self.to_upstream.total,
+                self.to_downstream.total,
+            );
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+// One direction of a `Tunnel`.
+struct Pipe {
+    buf: Vec<u8>,
+    pos: usize,
+    cap: usize,
+    total: u64,
+    eof: bool,
+    done: bool,
+}
+
+impl fmt::Debug for Pipe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pipe")
+            .field("buffered", &(self.cap - self.pos))
+            .field("total", &self.total)
+            .field("eof", &self.eof)
+            .finish()
+    }
+}
+
+impl Pipe {
+    fn new(pre: Bytes) -> Pipe {
+        let cap = pre.len();
+        let mut buf = pre.to_vec();
+        if buf.len() < TUNNEL_BUF_SIZE {
+            buf.resize(TUNNEL_BUF_SIZE, 0);
+        }
+        Pipe {
+            buf: buf,
+            pos: 0,
+            cap: cap,
+            total: 0,
+            eof: false,
+            done: false,
+        }
+    }
+
+    fn poll_pipe<R, W>(&mut self, reader: &mut R, writer: &mut W) -> Poll<(), io::Error>
+    where
+        R: AsyncRead,
+        W: AsyncWrite,
+    {
+        loop {
+            if self.done {
+                return Ok(Async::Ready(()));
+            }
+
+            if self.pos == self.cap && !self.eof {
+                match reader.poll_read(&mut self.buf)? {
+                    Async::Ready(0) => self.eof = true,
+                    Async::Ready(n) => {
+                        self.pos = 0;
+                        self.cap = n;
+                    },
+                    Async::NotReady => {
+                        // Nothing more to write for now, make sure what was
+                        // written so far gets to the other side.
+                        try_ready!(writer.poll_flush());
+                        return Ok(Async::NotReady);
+                    }
+                }
+            }
+
+            while self.pos < self.cap {
+                let n = try_ready!(writer.poll_write(&self.buf[self.pos..self.cap]));
+                if n == 0 {
+                    return Err(io::Error::new(io::ErrorKind::WriteZero, "tunnel peer closed"));
+                }
+                self.pos += n;
+                self.total += n as u64;
+            }
+
+            if self.eof {
+                try_ready!(writer.poll_flush());
+                try_ready!(writer.shutdown());
+                self.done = true;
+            }
+        }
+    }
+}
+
+// Headers that only concern a single connection, from RFC 7230.
+static HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+fn wants_upgrade(headers: &HeaderMap) -> bool {
+    headers.contains_key(header::UPGRADE) && connection_tokens(headers)
+        .iter()
+        .any(|name| *name == header::UPGRADE)
+}
+
+fn connection_tokens(headers: &HeaderMap) -> Vec<HeaderName> {
+    headers
+        .get_all(header::CONNECTION)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|token| HeaderName::from_bytes(token.trim().as_bytes()).ok())
+        .collect()
+}
+
+fn strip_hop_by_hop(headers: &mut HeaderMap, keep_upgrade: bool) {
+    for name in connection_tokens(headers) {
+        if !(keep_upgrade && name == header::UPGRADE) {
+            headers.remove(name);
+        }
+    }
+
+    // `TE: trailers` is allowed to pass, so that HTTP/2 upstreams know that
+    // trailers are understood.
+    let te_trailers = headers
+        .get(header::TE)
+        .map_or(false, |value| value == "trailers");
+
+    for name in HOP_BY_HOP {
+        if keep_upgrade && *name == "upgrade" {
+            continue;
+        }
+        headers.remove(*name);
+    }
+
+    if te_trailers {
+        headers.insert(header::TE, HeaderValue::from_static("trailers"));
+    }
+    if keep_upgrade {
+        headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderMap;
+    use http::header::{self, HeaderValue};
+
+    use super::{strip_hop_by_hop, wants_upgrade};
+
+    #[test]
+    fn strips_hop_by_hop_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, HeaderValue::from_static("keep-alive, x-hop"));
+        headers.insert("keep-alive", HeaderValue::from_static("timeout=5"));
+        headers.insert("x-hop", HeaderValue::from_static("1"));
+        headers.insert(header::TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        headers.insert(header::TE, HeaderValue::from_static("gzip"));
+        headers.insert(header::HOST, HeaderValue::from_static("example.domain"));
+
+        strip_hop_by_hop(&mut headers, false);
+
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[header::HOST], "example.domain");
+    }
+
+    #[test]
+    fn keeps_te_trailers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::TE, HeaderValue::from_static("trailers"));
+
+        strip_hop_by_hop(&mut headers, false);
+
+        assert_eq!(headers[header::TE], "trailers");
+    }
+
+    #[test]
+    fn keeps_upgrade_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, HeaderValue::from_static("Upgrade, x-hop"));
+        headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+        headers.insert("x-hop", HeaderValue::from_static("1"));
+        assert!(wants_upgrade(&headers));
+
+        strip_hop_by_hop(&mut headers, true);
+
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[header::CONNECTION], "upgrade");
+        assert_eq!(headers[header::UPGRADE], "websocket");
+    }
+}
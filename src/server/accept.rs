@@ -0,0 +1,93 @@
+//! The `Accept` trait and supporting types.
+//!
+//! A [`Server`](::Server) serves the connections it accepts from an
+//! [`Accept`](Accept). Any `Stream` of IO objects is one, such as the
+//! incoming connections of a TCP listener, and the trait can be implemented
+//! for anything else that hands out connections, such as a TLS acceptor,
+//! a unix socket, or a test harness.
+//!
+//! Context about a connection, such as the address of the peer, or the
+//! certificate it presented, travels with the connection itself, which is
+//! given to the [`MakeService`](::service::MakeService) making the
+//! `Service` that serves it.
+
+use std::fmt;
+
+use futures::{Poll, Stream};
+
+/// Asynchronously accept incoming connections.
+pub trait Accept {
+    /// The connection type that can be accepted.
+    type Conn;
+    /// The error type that can occur when accepting a connection.
+    type Error;
+
+    /// Poll to accept the next connection.
+    ///
+    /// Returns `Ready(None)` once no more connections will be accepted,
+    /// which finishes the `Server`. An error also finishes it, so an
+    /// acceptor that can recover from one should not return it.
+    fn poll_accept(&mut self) -> Poll<Option<Self::Conn>, Self::Error>;
+}
+
+impl<S: Stream> Accept for S {
+    type Conn = S::Item;
+    type Error = S::Error;
+
+    fn poll_accept(&mut self) -> Poll<Option<Self::Conn>, Self::Error> {
+        self.poll()
+    }
+}
+
+/// Create an `Accept` with a polling function.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// # use std::io::Cursor;
+/// # fn main() {
+/// use futures::Async;
+/// use hyper::server::accept;
+///
+/// // An acceptor handing out a single connection.
+/// let mut conn = Some(Cursor::new(Vec::<u8>::new()));
+/// let acceptor = accept::poll_fn(move || -> Result<_, ::std::io::Error> {
+///     Ok(Async::Ready(conn.take()))
+/// });
+/// # drop(acceptor);
+/// # }
+/// ```
+pub fn poll_fn<F, IO, E>(func: F) -> PollFn<F>
+where
+    F: FnMut() -> Poll<Option<IO>, E>,
+{
+    PollFn {
+        func,
+    }
+}
+
+/// An `Accept` created with [`poll_fn`](poll_fn).
+pub struct PollFn<F> {
+    func: F,
+}
+
+impl<F, IO, E> Accept for PollFn<F>
+where
+    F: FnMut() -> Poll<Option<IO>, E>,
+{
+    type Conn = IO;
+    type Error = E;
+
+    fn poll_accept(&mut self) -> Poll<Option<Self::Conn>, Self::Error> {
+        (self.func)()
+    }
+}
+
+impl<F> fmt::Debug for PollFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PollFn")
+            .finish()
+    }
+}
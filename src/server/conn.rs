@@ -7,26 +7,55 @@
 //!
 //! If you don't have need to manage connections yourself, consider using the
 //! higher-level [Server](super) API.
+//!
+//! # Presets
+//!
+//! [`Http::low_memory`](Http::low_memory),
+//! [`Http::high_throughput`](Http::high_throughput) and
+//! [`Http::low_latency`](Http::low_latency) set a group of related options
+//! for one goal at once. Each preset sets the same options, so the last one
+//! called wins, and options outside that group are kept. Any option can
+//! still be changed after a preset, to adjust it. The `Server` builder has
+//! the same presets.
 
 use std::fmt;
+#[cfg(not(feature = "http2"))] use std::marker::PhantomData;
 #[cfg(feature = "runtime")] use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
 #[cfg(feature = "runtime")] use std::time::Duration;
 
-use super::rewind::Rewind;
+use super::accept::Accept;
+#[cfg(feature = "http2")] use super::rewind::Rewind;
 use bytes::Bytes;
 use futures::{Async, Future, Poll, Stream};
-use futures::future::{Either, Executor};
+use futures::future::Executor;
+use http::StatusCode;
+use http::header::HeaderName;
 use tokio_io::{AsyncRead, AsyncWrite};
 #[cfg(feature = "runtime")] use tokio_reactor::Handle;
 
 use common::Exec;
+use common::drain::Watch;
+#[cfg(feature = "runtime")] use common::WriteTimeout;
+#[cfg(feature = "unstable-completion-io")] use completion::{Completion, CompletionIo};
 use proto;
-use body::{Body, Payload};
-use service::{NewService, Service};
-use error::{Kind, Parse};
+use body::{Body, ChunkSize, Payload};
+use service::{MakeServiceRef, Service};
+#[cfg(all(feature = "http1", feature = "http2"))] use error::{Kind, Parse};
+use super::request_id::RequestIds;
+use super::upgrade::OnRefusedUpgrade;
 
-#[cfg(feature = "runtime")] pub use super::tcp::AddrIncoming;
+pub use common::{CloseReason, ConnectionStats, ExchangeStats};
+pub use proto::{HeaderCaseMap, HeaderOrder, HeaderValuePolicy, InvalidReasonPhrase, ReasonPhrase};
+pub use common::CancelToken;
+pub use super::expect::{ExpectContinue, Expectation, UnknownExpectations};
+pub use super::host_policy::HostPolicy;
+pub use super::half_close::HalfClose;
+pub use super::informational::Informational;
+pub use super::method_policy::MethodPolicy;
+pub use super::request_id::RequestId;
+pub use super::upgrade::UpgradeRefusal;
+#[cfg(feature = "runtime")] pub use super::tcp::{AddrIncoming, AddrStream};
 
 /// A lower-level configuration of the HTTP protocol.
 ///
@@ -36,11 +65,70 @@ use error::{Kind, Parse};
 /// higher-level [Server](super) API.
 #[derive(Clone, Debug)]
 pub struct Http {
+    cancel_token: Option<CancelToken>,
+    chunk_size: ChunkSize,
+    error_status: Option<StatusCode>,
     exec: Exec,
+    h1_header_value_policy: Option<HeaderValuePolicy>,
+    h1_auto_continue: bool,
+    h1_chunk_extensions: bool,
+    h1_half_close: bool,
+    h1_h2c_upgrade: bool,
+    h1_informational: bool,
+    h1_legacy_keep_alive: bool,
+    h1_lenient: proto::Lenient,
+    h1_max_headers: Option<usize>,
+    h1_preserve_header_case: bool,
+    h1_preserve_header_order: bool,
+    h1_on_refused_upgrade: Option<OnRefusedUpgrade>,
+    h1_refuse_upgrades: bool,
+    h1_unknown_expectations: UnknownExpectations,
+    host_policy: Option<HostPolicy>,
     http2: bool,
+    http2_auto: bool,
+    http2_max_pending_streams: Option<usize>,
     keep_alive: bool,
     max_buf_size: Option<usize>,
+    method_policy: Option<MethodPolicy>,
     pipeline_flush: bool,
+    request_id: bool,
+    request_id_header: HeaderName,
+    shared: Option<SharedHttp>,
+    write_quota: Option<usize>,
+    #[cfg(feature = "runtime")]
+    write_timeout: Option<Duration>,
+}
+
+/// A handle to an [`Http`](Http) configuration that can be changed while
+/// serving connections.
+///
+/// Created with [`Http::shared`](Http::shared). Each connection reads the
+/// settings once, when it starts, so changes apply to the connections
+/// started afterwards, while connections already being served keep the
+/// settings they started with. A change replaces all the settings at once,
+/// so a connection never sees only some of them.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// # use hyper::server::conn::Http;
+/// # fn main() {
+/// let mut http = Http::new();
+/// let config = http.shared();
+///
+/// // later, such as when a configuration file changed...
+/// config.update(|http| {
+///     http.keep_alive(false);
+/// });
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SharedHttp {
+    inner: Arc<RwLock<Http>>,
+    // Held while an update runs, so that concurrent updates don't lose
+    // each other's changes.
+    updating: Arc<Mutex<()>>,
 }
 
 /// A stream mapping incoming IOs to new services.
@@ -56,7 +144,7 @@ pub struct Serve<I, S> {
 
 /// A future binding a `Service` to a `Connection`.
 ///
-/// Wraps the future returned from `NewService` into one that returns
+/// Wraps the future returned from `MakeService` into one that returns
 /// a `Connection`.
 #[must_use = "futures do nothing unless polled"]
 #[derive(Debug)]
@@ -72,6 +160,16 @@ pub(super) struct SpawnAll<I, S> {
     serve: Serve<I, S>,
 }
 
+// A connection spawned by `SpawnAll`, which can start a graceful shutdown
+// even before its `Service` has been made.
+enum Serving<I, F, S>
+where
+    S: Service,
+{
+    Connecting(Connecting<I, F>, bool),
+    Connected(Connection<I, S>),
+}
+
 /// A future binding a connection with a Service.
 ///
 /// Polling this future will drive HTTP forward.
@@ -80,20 +178,40 @@ pub struct Connection<T, S>
 where
     S: Service,
 {
-    pub(super) conn: Option<
-        Either<
-        proto::h1::Dispatcher<
-            proto::h1::dispatch::Server<S>,
-            S::ResBody,
-            T,
-            proto::ServerTransaction,
-        >,
-        proto::h2::Server<
-            Rewind<T>,
-            S,
-            S::ResBody,
-        >,
-    >>,
+    pub(super) conn: Option<ProtoServer<T, S::ResBody, S>>,
+    close_reason: Option<CloseReason>,
+    // For an HTTP/1 connection upgraded to HTTP/2.
+    #[cfg(all(feature = "http1", feature = "http2"))]
+    http2_max_pending_streams: Option<usize>,
+    #[cfg(feature = "runtime")]
+    write_timeout: Option<WriteTimeout>,
+}
+
+/// A future that runs a `Connection` until it is done with HTTP, and then
+/// yields its [`Parts`](Parts), without shutting down the IO object.
+///
+/// This is created by [`Connection::without_shutdown`](Connection::without_shutdown).
+#[must_use = "futures do nothing unless polled"]
+pub struct WithoutShutdown<T, S>
+where
+    S: Service,
+{
+    conn: Option<Connection<T, S>>,
+}
+
+// The protocol of a connection.
+pub(super) enum ProtoServer<T, B, S>
+where
+    S: Service,
+    B: Payload,
+{
+    #[cfg(feature = "http1")]
+    H1(proto::h1::Dispatcher<proto::h1::dispatch::Server<S>, B, T, proto::ServerTransaction>),
+    #[cfg(feature = "http2")]
+    H2(proto::h2::Server<Rewind<T>, S, B>),
+    // HTTP/2 was required, but the http2 feature is disabled.
+    #[cfg(not(feature = "http2"))]
+    Unsupported(PhantomData<fn(T, B, S)>),
 }
 
 /// Deconstructed parts of a `Connection`.
@@ -125,22 +243,339 @@ impl Http {
     /// start accepting connections.
     pub fn new() -> Http {
         Http {
+            cancel_token: None,
+            chunk_size: ChunkSize::default(),
+            error_status: None,
             exec: Exec::Default,
+            h1_header_value_policy: None,
+            h1_auto_continue: true,
+            h1_chunk_extensions: false,
+            h1_half_close: true,
+            h1_h2c_upgrade: false,
+            h1_informational: false,
+            h1_legacy_keep_alive: true,
+            h1_lenient: proto::Lenient::default(),
+            h1_max_headers: None,
+            h1_preserve_header_case: false,
+            h1_preserve_header_order: false,
+            h1_on_refused_upgrade: None,
+            h1_refuse_upgrades: false,
+            h1_unknown_expectations: UnknownExpectations::Ignore,
+            host_policy: None,
             http2: false,
+            http2_auto: true,
+            http2_max_pending_streams: None,
             keep_alive: true,
             max_buf_size: None,
+            method_policy: None,
             pipeline_flush: false,
+            request_id: false,
+            request_id_header: HeaderName::from_static("x-request-id"),
+            shared: None,
+            write_quota: None,
+            #[cfg(feature = "runtime")]
+            write_timeout: None,
         }
     }
 
+    /// Set how HTTP/1 connections treat response header values that contain
+    /// `CR`, `LF`, or `NUL` bytes.
+    ///
+    /// The number of such values found is available from
+    /// [`Connection::invalid_header_values`](Connection::invalid_header_values).
+    ///
+    /// Default is to not check header values.
+    pub fn http1_header_value_policy(&mut self, policy: HeaderValuePolicy) -> &mut Self {
+        self.h1_header_value_policy = Some(policy);
+        self
+    }
+
+    /// Set whether HTTP/1 connections send `100 Continue` as soon as they
+    /// read a request with `Expect: 100-continue`.
+    ///
+    /// When disabled, it's up to the `Service`: the request has an
+    /// [`ExpectContinue`](ExpectContinue) in its extensions, to send
+    /// `100 Continue` with once it wants the body. A final response sent
+    /// instead, such as a `417 Expectation Failed`, rejects the body
+    /// before the client sends it.
+    ///
+    /// Default is true.
+    pub fn http1_auto_continue(&mut self, enabled: bool) -> &mut Self {
+        self.h1_auto_continue = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1.1 requests can be sent interim (`1xx`) responses.
+    ///
+    /// When enabled, every HTTP/1.1 request has an
+    /// [`Informational`](Informational) in its extensions, to send them
+    /// with. HTTP/2 requests never have one, since the HTTP/2 library hyper
+    /// uses can't send more than one response head on a stream.
+    ///
+    /// An [`Expectation`](Expectation) can send interim responses whether
+    /// enabled or not.
+    ///
+    /// Default is false.
+    pub fn http1_informational_responses(&mut self, enabled: bool) -> &mut Self {
+        self.h1_informational = enabled;
+        self
+    }
+
+    /// Set what HTTP/1 connections do with a request that has an `Expect`
+    /// other than `100-continue`.
+    ///
+    /// Such expectations are for extensions of HTTP that hyper doesn't
+    /// know. They can be ignored, rejected with
+    /// `417 Expectation Failed`, or delegated to the `Service`, with an
+    /// [`Expectation`](Expectation) in the request's extensions to meet them
+    /// with. See [`UnknownExpectations`](UnknownExpectations) for details.
+    ///
+    /// Default is `UnknownExpectations::Ignore`.
+    pub fn http1_unknown_expectations(&mut self, expectations: UnknownExpectations) -> &mut Self {
+        self.h1_unknown_expectations = expectations;
+        self
+    }
+
+    /// Set whether HTTP/1 connections keep the extensions of chunks of
+    /// request bodies sent with chunked transfer-encoding.
+    ///
+    /// When enabled, they are available from the body's
+    /// [`Chunk::extensions`](::Chunk::extensions). Otherwise, they are
+    /// skipped. Extensions set on the `Chunk`s of a response body are sent
+    /// either way.
+    ///
+    /// Default is false.
+    pub fn http1_chunk_extensions(&mut self, enabled: bool) -> &mut Self {
+        self.h1_chunk_extensions = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections still write the response after the
+    /// client closed its writing half.
+    ///
+    /// Some clients shut down writing once the request is sent, and only
+    /// read afterwards. When enabled, the response is written, and then the
+    /// connection is closed. When disabled, the connection is closed as soon
+    /// as that's noticed, and the `Service` future is dropped.
+    ///
+    /// Either way, the request has a [`HalfClose`](HalfClose) in its
+    /// extensions to tell if it happened.
+    ///
+    /// Default is true.
+    pub fn http1_half_close(&mut self, enabled: bool) -> &mut Self {
+        self.h1_half_close = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections can be upgraded to HTTP/2 over
+    /// cleartext.
+    ///
+    /// When enabled, an HTTP/1.1 request without a body, that offers
+    /// `Upgrade: h2c` with an `HTTP2-Settings` header, is answered with
+    /// `101 Switching Protocols` before the `Service` sees it. The
+    /// connection then goes on as HTTP/2, with the client's settings from
+    /// `HTTP2-Settings`, and the request is served as its first stream.
+    /// Other requests offering `h2c`, such as with settings that aren't
+    /// valid, are served as HTTP/1.
+    ///
+    /// This needs the `http2` feature, and has no effect without it.
+    ///
+    /// Default is false.
+    pub fn http1_h2c_upgrade(&mut self, enabled: bool) -> &mut Self {
+        self.h1_h2c_upgrade = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1.0 requests can keep connections alive.
+    ///
+    /// An HTTP/1.0 client asks to reuse a connection by sending
+    /// `Connection: keep-alive`. When enabled, such a request gets a
+    /// response with `Connection: keep-alive`, and the connection serves
+    /// the next request, as long as the response body has a known length.
+    /// Otherwise, connections are closed after every HTTP/1.0 response.
+    ///
+    /// Default is true.
+    pub fn http1_legacy_keep_alive(&mut self, enabled: bool) -> &mut Self {
+        self.h1_legacy_keep_alive = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections keep the original casing of request
+    /// header names.
+    ///
+    /// When enabled, every `Request` has a [`HeaderCaseMap`](HeaderCaseMap)
+    /// in its extensions, with each header name as it was received.
+    ///
+    /// Whether enabled or not, a `HeaderCaseMap` in the extensions of a
+    /// `Response` sets the casing of its header names.
+    ///
+    /// Default is false.
+    pub fn http1_preserve_header_case(&mut self, enabled: bool) -> &mut Self {
+        self.h1_preserve_header_case = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections keep the original order of request
+    /// header lines.
+    ///
+    /// When enabled, every `Request` has a [`HeaderOrder`](HeaderOrder) in
+    /// its extensions, with each header line in the order it was received.
+    ///
+    /// Whether enabled or not, a `HeaderOrder` in the extensions of a
+    /// `Response` sets the order its header lines are written in.
+    ///
+    /// Default is false.
+    pub fn http1_preserve_header_order(&mut self, enabled: bool) -> &mut Self {
+        self.h1_preserve_header_order = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections accept header values folded over
+    /// several lines.
+    ///
+    /// Obsolete line folding continues a header value on a line starting
+    /// with a space or tab. When allowed, each line ending before such a
+    /// line is replaced with spaces, and the value includes the rest.
+    /// Otherwise, a request with folded headers is a parse error.
+    ///
+    /// Default is false.
+    pub fn http1_allow_obsolete_line_folding(&mut self, enabled: bool) -> &mut Self {
+        self.h1_lenient.obsolete_line_folding = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections accept spaces in request-targets.
+    ///
+    /// When allowed, a request line such as `GET /a file.txt HTTP/1.1` is
+    /// read as a request for `/a%20file.txt`, and extra spaces around the
+    /// target are ignored. Otherwise, such a request is a parse error.
+    ///
+    /// Default is false.
+    pub fn http1_allow_spaces_in_request_target(&mut self, enabled: bool) -> &mut Self {
+        self.h1_lenient.spaces_in_request_target = enabled;
+        self
+    }
+
+    /// Set whether HTTP/1 connections refuse to upgrade to other protocols.
+    ///
+    /// When enabled, a `101 Switching Protocols` response of the `Service`
+    /// is replaced with a `500 Internal Server Error`, and the connection
+    /// goes on serving HTTP/1 requests.
+    ///
+    /// A `2xx` response to a `CONNECT` request counts as an upgrade too,
+    /// since the connection becomes a tunnel right after its head. Taking
+    /// back the IO then lets the `Service` act as a forward proxy. If
+    /// nothing can take the IO, the `2xx` is still sent, and the connection
+    /// is closed after it.
+    ///
+    /// Whether enabled or not, a `101` is only sent when the connection is
+    /// polled with [`without_shutdown`](Connection::without_shutdown) or
+    /// [`poll_without_shutdown`](Connection::poll_without_shutdown), which
+    /// can give back the IO to speak the other protocol with. Otherwise it
+    /// is replaced with a `500` too, and the connection is closed.
+    ///
+    /// Default is false.
+    pub fn http1_refuse_upgrades(&mut self, enabled: bool) -> &mut Self {
+        self.h1_refuse_upgrades = enabled;
+        self
+    }
+
+    /// Set a function to call when an HTTP/1 connection doesn't send a
+    /// `101 Switching Protocols` response of the `Service`.
+    ///
+    /// The function is given why the upgrade was refused, and the headers
+    /// of the `101` response, such as its `Upgrade`.
+    pub fn http1_on_refused_upgrade<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(UpgradeRefusal, &::http::HeaderMap) + Send + Sync + 'static,
+    {
+        self.h1_on_refused_upgrade = Some(OnRefusedUpgrade::new(f));
+        self
+    }
+
+    /// Set the maximum number of headers of a request on HTTP/1
+    /// connections.
+    ///
+    /// A request with more headers is answered with a
+    /// `431 Request Header Fields Too Large`, and the connection is closed.
+    /// Up to 100 headers are parsed on the stack; a larger maximum allocates
+    /// room for them on every request.
+    ///
+    /// Default is 100.
+    pub fn http1_max_headers(&mut self, max: usize) -> &mut Self {
+        self.h1_max_headers = Some(max);
+        self
+    }
+
+    /// Set how many bytes of body an HTTP/1 connection may write each time
+    /// it is polled, before yielding to other tasks on the executor.
+    ///
+    /// This keeps a connection writing a large body from holding up other
+    /// connections, which mostly matters on a current-thread executor.
+    ///
+    /// Default is no quota.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the passed `bytes` is 0.
+    pub fn http1_write_quota(&mut self, bytes: usize) -> &mut Self {
+        assert!(bytes > 0, "the http1_write_quota cannot be 0");
+        self.write_quota = Some(bytes);
+        self
+    }
+
     /// Sets whether HTTP2 is required.
     ///
+    /// Without the `http1` feature, HTTP2 is always used. Without the
+    /// `http2` feature, connections requiring it fail with an error.
+    ///
     /// Default is false
     pub fn http2_only(&mut self, val: bool) -> &mut Self {
         self.http2 = val;
         self
     }
 
+    /// Sets whether connections starting with the HTTP/2 preface are
+    /// served with HTTP2.
+    ///
+    /// When enabled, the first bytes of a connection are checked for the
+    /// preface a client sends when it knows the server speaks HTTP2, and
+    /// the connection is served with HTTP2 if they match, or HTTP/1 if not.
+    /// This lets a cleartext port serve both, without ALPN. When disabled,
+    /// the preface is answered as a bad HTTP/1 request.
+    ///
+    /// Without the `http2` feature, connections starting with the preface
+    /// fail with an error. This has no effect with `http2_only`.
+    ///
+    /// Default is true.
+    pub fn http2_auto(&mut self, enabled: bool) -> &mut Self {
+        self.http2_auto = enabled;
+        self
+    }
+
+    /// Set the maximum number of streams of an HTTP2 connection waiting for
+    /// their `Service` to respond.
+    ///
+    /// Once reached, no more streams are accepted from the connection until
+    /// one of them has its response, so a client opening streams in bursts
+    /// can't have hyper call the `Service` for, and hold on to, an unbounded
+    /// number of requests. The streams not accepted yet wait in the HTTP2
+    /// connection, still counting towards its concurrent streams, while
+    /// the connection keeps sending responses and receiving bodies for the
+    /// streams already accepted. Streams count until their response head is
+    /// ready, not while their response body is sent.
+    ///
+    /// Default is no limit.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `max` is `0`.
+    pub fn http2_max_pending_streams(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "http2_max_pending_streams must be at least 1");
+        self.http2_max_pending_streams = Some(max);
+        self
+    }
+
     /// Enables or disables HTTP keep-alive.
     ///
     /// Default is true.
@@ -158,13 +593,44 @@ impl Http {
     /// The minimum value allowed is 8192. This method panics if the passed `max` is less than the minimum.
     pub fn max_buf_size(&mut self, max: usize) -> &mut Self {
         assert!(
-            max >= proto::h1::MINIMUM_MAX_BUFFER_SIZE,
+            max >= proto::MINIMUM_MAX_BUFFER_SIZE,
             "the max_buf_size cannot be smaller than the minimum that h1 specifies."
         );
         self.max_buf_size = Some(max);
         self
     }
 
+    /// Set the maximum size of each chunk of a request body.
+    ///
+    /// Larger reads from the connection are split into several chunks.
+    ///
+    /// Default is no maximum.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the passed `max` is 0.
+    pub fn max_chunk_size(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "the max_chunk_size cannot be 0");
+        self.chunk_size.max = Some(max);
+        self
+    }
+
+    /// Set the minimum size of each chunk of a request body.
+    ///
+    /// Smaller reads from the connection are held back, and joined with the
+    /// following ones, until there are at least `min` bytes, or the body
+    /// ends. Only the last chunk of a body can be smaller. If a maximum is
+    /// also set, and is smaller, the maximum wins.
+    ///
+    /// Note that this delays a chunk until more of the body is received, so
+    /// it shouldn't be used for bodies that are streamed interactively.
+    ///
+    /// Default is no minimum.
+    pub fn min_chunk_size(&mut self, min: usize) -> &mut Self {
+        self.chunk_size.min = Some(min);
+        self
+    }
+
     /// Aggregates flushes to better support pipelined responses.
     ///
     /// Experimental, may be have bugs.
@@ -175,6 +641,160 @@ impl Http {
         self
     }
 
+    /// Configure connections to use as little memory as possible.
+    ///
+    /// This limits the read and write buffers to the minimum `max_buf_size`,
+    /// doesn't hold back request body chunks to join them, and flushes each
+    /// response on its own. Requests with larger headers are rejected, and
+    /// large bodies take more reads and writes.
+    ///
+    /// This sets `keep_alive`, `max_buf_size`, `min_chunk_size`,
+    /// `pipeline_flush` and `http1_write_quota`, like the other
+    /// [presets](::server::conn#presets).
+    pub fn low_memory(&mut self) -> &mut Self {
+        self.keep_alive = true;
+        self.max_buf_size = Some(proto::MINIMUM_MAX_BUFFER_SIZE);
+        self.chunk_size.min = None;
+        self.pipeline_flush = false;
+        self.write_quota = None;
+        self
+    }
+
+    /// Configure connections to transfer as many bytes as possible.
+    ///
+    /// This uses the default, large buffers, joins small request body
+    /// chunks into chunks of at least 16kb, aggregates flushes of pipelined
+    /// responses, and lets a connection write for as long as it can. A
+    /// connection with a large response may delay others on the same
+    /// executor, and request bodies reach the `Service` in fewer, later
+    /// chunks.
+    ///
+    /// This sets `keep_alive`, `max_buf_size`, `min_chunk_size`,
+    /// `pipeline_flush` and `http1_write_quota`, like the other
+    /// [presets](::server::conn#presets).
+    pub fn high_throughput(&mut self) -> &mut Self {
+        self.keep_alive = true;
+        self.max_buf_size = None;
+        self.chunk_size.min = Some(16 * 1024);
+        self.pipeline_flush = true;
+        self.write_quota = None;
+        self
+    }
+
+    /// Configure connections to respond as soon as possible.
+    ///
+    /// This passes request body chunks on as soon as they're read, flushes
+    /// each response right away, and limits a connection to writing 64kb
+    /// of body each time it's polled, so that a large response doesn't
+    /// delay other connections. Large responses take more polls to write.
+    ///
+    /// This sets `keep_alive`, `max_buf_size`, `min_chunk_size`,
+    /// `pipeline_flush` and `http1_write_quota`, like the other
+    /// [presets](::server::conn#presets).
+    pub fn low_latency(&mut self) -> &mut Self {
+        self.keep_alive = true;
+        self.max_buf_size = None;
+        self.chunk_size.min = None;
+        self.pipeline_flush = false;
+        self.write_quota = Some(64 * 1024);
+        self
+    }
+
+    /// Set which authorities HTTP/1 requests may name in their `Host`
+    /// header.
+    ///
+    /// Requests without exactly one valid `Host` header are answered with a
+    /// `400 Bad Request`, and requests for an authority that isn't allowed
+    /// with a `421 Misdirected Request`, without calling the `Service`.
+    /// HTTP/2 requests are not checked.
+    ///
+    /// Default is to allow any `Host`.
+    pub fn host_policy(&mut self, policy: HostPolicy) -> &mut Self {
+        self.host_policy = Some(policy);
+        self
+    }
+
+    /// Set which request methods are allowed to reach the `Service`.
+    ///
+    /// Requests with any other method are answered with a
+    /// `405 Method Not Allowed` response, and the `Service` isn't called.
+    /// [`MethodPolicy::deny_trace`](MethodPolicy::deny_trace) keeps `TRACE`
+    /// and `TRACK` requests away from application code.
+    ///
+    /// Default is to allow all methods.
+    pub fn method_policy(&mut self, policy: MethodPolicy) -> &mut Self {
+        self.method_policy = Some(policy);
+        self
+    }
+
+    /// Set the status to respond with when a `Service` future resolves with
+    /// an error.
+    ///
+    /// By default, the error closes the HTTP/1 connection, or resets the
+    /// HTTP/2 stream, without sending a response. With a status set, the
+    /// error is logged, and a response with this status and an empty body is
+    /// sent instead, keeping the connection open.
+    pub fn service_error_status(&mut self, status: StatusCode) -> &mut Self {
+        self.error_status = Some(status);
+        self
+    }
+
+    /// Set whether each request gets a [`RequestId`](RequestId).
+    ///
+    /// If the request has a usable id in the request id header, that one is
+    /// used, otherwise a new unique one is generated. The id is put in the
+    /// request's extensions before calling the `Service`, and is sent back
+    /// in the same header of the response, unless the `Service` already
+    /// set it.
+    ///
+    /// Default is false.
+    pub fn request_id(&mut self, enabled: bool) -> &mut Self {
+        self.request_id = enabled;
+        self
+    }
+
+    /// Set the header used to receive and send request ids.
+    ///
+    /// Default is `x-request-id`.
+    pub fn request_id_header(&mut self, name: HeaderName) -> &mut Self {
+        self.request_id_header = name;
+        self
+    }
+
+    /// Set a [`CancelToken`](CancelToken) that aborts connections served
+    /// with this configuration.
+    ///
+    /// Once the token is canceled, a connection writes out what it already
+    /// buffered, and then closes, without waiting for in-flight requests
+    /// to finish. HTTP/2 connections send a `GOAWAY` with the `CANCEL`
+    /// error code. The `Connection` then resolves with `Ok(())`.
+    ///
+    /// The token is also put in the extensions of every request.
+    pub fn cancel_token(&mut self, token: CancelToken) -> &mut Self {
+        self.cancel_token = Some(token);
+        self
+    }
+
+    /// Set how long a connection may go without writing anything, while
+    /// it has bytes waiting to be written, before it is closed.
+    ///
+    /// A client that stops reading its responses fills up the connection's
+    /// buffers, and would otherwise keep the connection, its buffers, and
+    /// any `Service` futures and response bodies around indefinitely. Once
+    /// the timeout is reached, the `Connection` drops them and resolves
+    /// with an error where `Error::is_write_timeout` is true. Any bytes
+    /// written start the timeout over, so clients that read slowly aren't
+    /// affected.
+    ///
+    /// This requires the `runtime` feature.
+    ///
+    /// Default is `None`, waiting indefinitely.
+    #[cfg(feature = "runtime")]
+    pub fn write_timeout(&mut self, dur: Option<Duration>) -> &mut Self {
+        self.write_timeout = dur;
+        self
+    }
+
     /// Set the executor used to spawn background tasks.
     ///
     /// Default uses implicit default (like `tokio::spawn`).
@@ -186,6 +806,28 @@ impl Http {
         self
     }
 
+    /// Get a [`SharedHttp`](SharedHttp) handle to change this configuration
+    /// while serving connections.
+    ///
+    /// The handle starts with the current settings. From then on, every
+    /// connection served with this `Http`, or a clone of it, including the
+    /// ones accepted by a `Serve` or `Server`, uses the settings of the
+    /// handle at the time it starts. Settings changed on this `Http`
+    /// afterwards are ignored, and should be changed with the handle
+    /// instead.
+    ///
+    /// Settings used when binding a listener, such as the TCP keepalive of
+    /// `serve_addr`, and the executor spawning the accepted connections of
+    /// a `Server`, are only read once, and aren't changed by the handle.
+    pub fn shared(&mut self) -> SharedHttp {
+        if let Some(ref shared) = self.shared {
+            return shared.clone();
+        }
+        let shared = SharedHttp::new(self.clone());
+        self.shared = Some(shared.clone());
+        shared
+    }
+
     /// Bind a connection together with a [`Service`](::service::Service).
     ///
     /// This returns a Future that must be polled in order for HTTP to be
@@ -230,26 +872,177 @@ impl Http {
         Bd: Payload,
         I: AsyncRead + AsyncWrite,
     {
-        let either = if !self.http2 {
-            let mut conn = proto::Conn::new(io);
-            if !self.keep_alive {
-                conn.disable_keep_alive();
-            }
-            conn.set_flush_pipeline(self.pipeline_flush);
-            if let Some(max) = self.max_buf_size {
-                conn.set_max_buf_size(max);
-            }
-            let sd = proto::h1::dispatch::Server::new(service);
-            Either::A(proto::h1::Dispatcher::new(sd, conn))
+        if let Some(ref shared) = self.shared {
+            return shared.get().serve_connection(io, service);
+        }
+        let request_ids = if self.request_id {
+            Some(RequestIds::new(self.request_id_header.clone()))
         } else {
-            let rewind_io = Rewind::new(io);
-            let h2 = proto::h2::Server::new(rewind_io, service, self.exec.clone());
-            Either::B(h2)
+            None
+        };
+        let conn = if !self.http2 {
+            self.http1(io, service, request_ids)
+        } else {
+            self.http2(io, service, request_ids)
         };
 
         Connection {
-            conn: Some(either),
+            conn: Some(conn),
+            close_reason: None,
+            #[cfg(all(feature = "http1", feature = "http2"))]
+            http2_max_pending_streams: self.http2_max_pending_streams,
+            #[cfg(feature = "runtime")]
+            write_timeout: self.write_timeout.map(WriteTimeout::new),
+        }
+    }
+
+    /// Bind a connection on a completion based IO together with a
+    /// [`Service`](::service::Service).
+    ///
+    /// HTTP/1 connections hand their buffers to the IO, instead of lending
+    /// them, see the [`completion`](::completion) module.
+    ///
+    /// This requires the `unstable-completion-io` feature.
+    #[cfg(feature = "unstable-completion-io")]
+    pub fn serve_completion<S, C, Bd>(&self, io: C, service: S) -> Connection<Completion<C>, S>
+    where
+        S: Service<ReqBody=Body, ResBody=Bd>,
+        S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+        S::Future: Send + 'static,
+        Bd: Payload,
+        C: CompletionIo,
+    {
+        let mut conn = self.serve_connection(Completion::new(io), service);
+        if let Some(ProtoServer::H1(ref mut h1)) = conn.conn {
+            h1.set_owned_io(Completion::owned_io());
+        }
+        conn
+    }
+
+    #[cfg(feature = "http1")]
+    fn http1<S, I, Bd>(&self, io: I, service: S, request_ids: Option<RequestIds>) -> ProtoServer<I, Bd, S>
+    where
+        S: Service<ReqBody=Body, ResBody=Bd>,
+        S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+        S::Future: Send + 'static,
+        Bd: Payload,
+        I: AsyncRead + AsyncWrite,
+    {
+        let mut conn = proto::Conn::new(io);
+        if !self.keep_alive {
+            conn.disable_keep_alive();
+        }
+        if !self.h1_legacy_keep_alive {
+            conn.disable_legacy_keep_alive();
+        }
+        if !self.h1_auto_continue {
+            conn.disable_auto_continue();
+        }
+        if self.h1_chunk_extensions {
+            conn.set_keep_chunk_extensions();
+        }
+        if !self.h1_half_close {
+            conn.disable_half_close();
+        }
+        conn.set_flush_pipeline(self.pipeline_flush);
+        if let Some(max) = self.max_buf_size {
+            conn.set_max_buf_size(max);
+        }
+        if let Some(max) = self.h1_max_headers {
+            conn.set_max_headers(max);
+        }
+        if let Some(policy) = self.h1_header_value_policy {
+            conn.set_header_value_policy(policy);
+        }
+        if self.h1_preserve_header_case {
+            conn.set_preserve_header_case();
+        }
+        if self.h1_preserve_header_order {
+            conn.set_preserve_header_order();
+        }
+        conn.set_lenient(self.h1_lenient);
+        if self.http2_auto {
+            conn.set_h2_preface();
+        }
+        let mut sd = proto::h1::dispatch::Server::new(service);
+        if let Some(ids) = request_ids {
+            sd.set_request_ids(ids);
+        }
+        if let Some(ref policy) = self.host_policy {
+            sd.set_host_policy(policy.clone());
+        }
+        if let Some(ref policy) = self.method_policy {
+            sd.set_method_policy(policy.clone());
+        }
+        if let Some(status) = self.error_status {
+            sd.set_error_status(status);
+        }
+        if let Some(ref token) = self.cancel_token {
+            sd.set_cancel_token(token.clone());
+        }
+        sd.set_auto_continue(self.h1_auto_continue);
+        sd.set_informational(self.h1_informational);
+        sd.set_unknown_expectations(self.h1_unknown_expectations);
+        sd.set_h2c_upgrade(self.h1_h2c_upgrade);
+        sd.set_refuse_upgrades(self.h1_refuse_upgrades);
+        if let Some(ref hook) = self.h1_on_refused_upgrade {
+            sd.set_on_refused_upgrade(hook.clone());
+        }
+        let mut dispatcher = proto::h1::Dispatcher::new(sd, conn);
+        if let Some(ref token) = self.cancel_token {
+            dispatcher.set_cancel_token(token.clone());
         }
+        if let Some(quota) = self.write_quota {
+            dispatcher.set_write_quota(quota);
+        }
+        dispatcher.set_chunk_size(self.chunk_size);
+        ProtoServer::H1(dispatcher)
+    }
+
+    // Without HTTP/1, every connection is served with HTTP/2.
+    #[cfg(not(feature = "http1"))]
+    fn http1<S, I, Bd>(&self, io: I, service: S, request_ids: Option<RequestIds>) -> ProtoServer<I, Bd, S>
+    where
+        S: Service<ReqBody=Body, ResBody=Bd>,
+        S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+        S::Future: Send + 'static,
+        Bd: Payload,
+        I: AsyncRead + AsyncWrite,
+    {
+        self.http2(io, service, request_ids)
+    }
+
+    #[cfg(feature = "http2")]
+    fn http2<S, I, Bd>(&self, io: I, service: S, request_ids: Option<RequestIds>) -> ProtoServer<I, Bd, S>
+    where
+        S: Service<ReqBody=Body, ResBody=Bd>,
+        S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+        S::Future: Send + 'static,
+        Bd: Payload,
+        I: AsyncRead + AsyncWrite,
+    {
+        let rewind_io = Rewind::new(io);
+        let mut h2 = proto::h2::Server::new(rewind_io, service, self.exec.clone(), request_ids, self.method_policy.clone(), self.error_status, self.chunk_size);
+        if let Some(ref token) = self.cancel_token {
+            h2.set_cancel_token(token.clone());
+        }
+        if let Some(max) = self.http2_max_pending_streams {
+            h2.set_max_pending_streams(max);
+        }
+        ProtoServer::H2(h2)
+    }
+
+    #[cfg(not(feature = "http2"))]
+    fn http2<S, I, Bd>(&self, _io: I, _service: S, _request_ids: Option<RequestIds>) -> ProtoServer<I, Bd, S>
+    where
+        S: Service<ReqBody=Body, ResBody=Bd>,
+        S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+        S::Future: Send + 'static,
+        Bd: Payload,
+        I: AsyncRead + AsyncWrite,
+    {
+        debug!("HTTP/2 connection, but the http2 feature is disabled");
+        ProtoServer::Unsupported(PhantomData)
     }
 
     /// Bind the provided `addr` with the default `Handle` and return [`Serve`](Serve).
@@ -261,7 +1054,7 @@ impl Http {
     #[cfg(feature = "runtime")]
     pub fn serve_addr<S, Bd>(&self, addr: &SocketAddr, new_service: S) -> ::Result<Serve<AddrIncoming, S>>
     where
-        S: NewService<ReqBody=Body, ResBody=Bd>,
+        S: MakeServiceRef<AddrStream, ReqBody=Body, ResBody=Bd>,
         S::Error: Into<Box<::std::error::Error + Send + Sync>>,
         Bd: Payload,
     {
@@ -281,7 +1074,7 @@ impl Http {
     #[cfg(feature = "runtime")]
     pub fn serve_addr_handle<S, Bd>(&self, addr: &SocketAddr, handle: &Handle, new_service: S) -> ::Result<Serve<AddrIncoming, S>>
     where
-        S: NewService<ReqBody=Body, ResBody=Bd>,
+        S: MakeServiceRef<AddrStream, ReqBody=Body, ResBody=Bd>,
         S::Error: Into<Box<::std::error::Error + Send + Sync>>,
         Bd: Payload,
     {
@@ -292,13 +1085,16 @@ impl Http {
         Ok(self.serve_incoming(incoming, new_service))
     }
 
-    /// Bind the provided stream of incoming IO objects with a `NewService`.
+    /// Bind the provided incoming IO objects with a `MakeService`.
+    ///
+    /// These can come from any [`Accept`](super::accept::Accept), such as a
+    /// `Stream` of them.
     pub fn serve_incoming<I, S, Bd>(&self, incoming: I, new_service: S) -> Serve<I, S>
     where
-        I: Stream,
+        I: Accept,
         I::Error: Into<Box<::std::error::Error + Send + Sync>>,
-        I::Item: AsyncRead + AsyncWrite,
-        S: NewService<ReqBody=Body, ResBody=Bd>,
+        I::Conn: AsyncRead + AsyncWrite,
+        S: MakeServiceRef<I::Conn, ReqBody=Body, ResBody=Bd>,
         S::Error: Into<Box<::std::error::Error + Send + Sync>>,
         Bd: Payload,
     {
@@ -311,6 +1107,54 @@ impl Http {
 }
 
 
+// ===== impl SharedHttp =====
+
+impl SharedHttp {
+    fn new(mut http: Http) -> SharedHttp {
+        http.shared = None;
+        SharedHttp {
+            inner: Arc::new(RwLock::new(http)),
+            updating: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Get a copy of the current settings.
+    pub fn get(&self) -> Http {
+        self.inner.read().unwrap().clone()
+    }
+
+    /// Replace all the settings, for connections started afterwards.
+    pub fn set(&self, mut http: Http) {
+        let _updating = self.updating.lock().unwrap();
+        http.shared = None;
+        *self.inner.write().unwrap() = http;
+    }
+
+    /// Change some of the settings, for connections started afterwards.
+    ///
+    /// `f` changes a copy of the settings, which replaces them once it
+    /// returns. Connections keep starting with the previous settings while
+    /// `f` runs, so they either get all of its changes, or none of them.
+    pub fn update<F>(&self, f: F)
+    where
+        F: FnOnce(&mut Http),
+    {
+        let _updating = self.updating.lock().unwrap();
+        let mut http = self.get();
+        f(&mut http);
+        http.shared = None;
+        *self.inner.write().unwrap() = http;
+    }
+}
+
+impl fmt::Debug for SharedHttp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("SharedHttp")
+            .field(&*self.inner.read().unwrap())
+            .finish()
+    }
+}
+
 // ===== impl Connection =====
 
 impl<I, B, S> Connection<I, S>
@@ -327,35 +1171,83 @@ where
     /// can finish.
     pub fn graceful_shutdown(&mut self) {
         match *self.conn.as_mut().unwrap() {
-            Either::A(ref mut h1) => {
+            #[cfg(feature = "http1")]
+            ProtoServer::H1(ref mut h1) => {
                 h1.disable_keep_alive();
             },
-            Either::B(ref mut h2) => {
+            #[cfg(feature = "http2")]
+            ProtoServer::H2(ref mut h2) => {
                 h2.graceful_shutdown();
-            }
+            },
+            #[cfg(not(feature = "http2"))]
+            ProtoServer::Unsupported(_) => (),
         }
     }
 
+    /// Returns how many response header values containing `CR`, `LF`, or
+    /// `NUL` bytes have been found on this connection.
+    ///
+    /// Values are only checked if a
+    /// [`HeaderValuePolicy`](HeaderValuePolicy) was configured, and
+    /// HTTP/2 connections always return `0`.
+    pub fn invalid_header_values(&self) -> usize {
+        match *self.conn.as_ref().unwrap() {
+            #[cfg(feature = "http1")]
+            ProtoServer::H1(ref h1) => h1.invalid_header_values(),
+            #[cfg(feature = "http2")]
+            ProtoServer::H2(_) => 0,
+            #[cfg(not(feature = "http2"))]
+            ProtoServer::Unsupported(_) => 0,
+        }
+    }
+
+    /// Get the byte and request counters of this connection.
+    ///
+    /// If a connection turns out to use HTTP/2 only after reading its
+    /// preface, the counters start over for HTTP/2.
+    pub fn stats(&self) -> ConnectionStats {
+        match *self.conn.as_ref().unwrap() {
+            #[cfg(feature = "http1")]
+            ProtoServer::H1(ref h1) => h1.stats(),
+            #[cfg(feature = "http2")]
+            ProtoServer::H2(ref h2) => h2.stats().clone(),
+            #[cfg(not(feature = "http2"))]
+            ProtoServer::Unsupported(_) => ConnectionStats::new(),
+        }
+    }
+
+    /// Get why this connection closed, once it has finished.
+    ///
+    /// For an HTTP/2 connection, this includes the error code of a `GOAWAY`
+    /// from the peer.
+    pub fn close_reason(&self) -> Option<&CloseReason> {
+        self.close_reason.as_ref()
+    }
+
     /// Return the inner IO object, and additional information.
     ///
     /// If the IO object has been "rewound" the io will not contain those bytes rewound.
     /// This should only be called after `poll_without_shutdown` signals
     /// that the connection is "done". Otherwise, it may not have finished
     /// flushing all necessary HTTP bytes.
-    pub fn into_parts(self) -> Parts<I, S> {
-        let (io, read_buf, dispatch) = match self.conn.unwrap() {
-            Either::A(h1) => {
-                h1.into_inner()
+    ///
+    /// Only works for HTTP/1 connections, HTTP/2 connections return `None`.
+    pub fn into_parts(self) -> Option<Parts<I, S>> {
+        match self.conn.unwrap() {
+            #[cfg(feature = "http1")]
+            ProtoServer::H1(h1) => {
+                let (io, read_buf, dispatch) = h1.into_inner();
+                Some(Parts {
+                    io: io,
+                    read_buf: read_buf,
+                    service: dispatch.into_service(),
+                    _inner: (),
+                })
             },
-            Either::B(_h2) => {
-                panic!("h2 cannot into_inner");
-            }
-        };
-        Parts {
-            io: io,
-            read_buf: read_buf,
-            service: dispatch.into_service(),
-            _inner: (),
+            #[cfg(feature = "http2")]
+            ProtoServer::H2(_) => None,
+            #[cfg(not(feature = "http2"))]
+            ProtoServer::Unsupported(_) => None,
         }
     }
 
@@ -367,34 +1259,145 @@ where
     /// but it is not desired to actally shutdown the IO object. Instead you
     /// would take it back using `into_parts`.
     pub fn poll_without_shutdown(&mut self) -> Poll<(), ::Error> {
-        match *self.conn.as_mut().unwrap() {
-            Either::A(ref mut h1) => {
-                try_ready!(h1.poll_without_shutdown());
-                Ok(().into())
-            },
-            Either::B(ref mut h2) => h2.poll(),
+        self.claim_upgrades(true);
+        let ret = match *self.conn.as_mut().unwrap() {
+            #[cfg(feature = "http1")]
+            ProtoServer::H1(ref mut h1) => h1.poll_without_shutdown(),
+            #[cfg(feature = "http2")]
+            ProtoServer::H2(ref mut h2) => h2.poll(),
+            #[cfg(not(feature = "http2"))]
+            ProtoServer::Unsupported(_) => Err(::Error::new_user_unsupported_version()),
+        };
+        match ret? {
+            Async::Ready(()) => self.poll_h2c(),
+            Async::NotReady => self.poll_write_timeout(),
+        }
+    }
+
+    /// Run the connection until it is done with HTTP, and then yield its
+    /// `Parts`, without calling `shutdown` on the underlying IO.
+    ///
+    /// This combines `poll_without_shutdown` and `into_parts`, such as to
+    /// take back the IO object after an HTTP upgrade. The future fails
+    /// right away if the connection isn't HTTP/1.
+    pub fn without_shutdown(self) -> WithoutShutdown<I, S> {
+        WithoutShutdown {
+            conn: Some(self),
+        }
+    }
+
+    // Whether this is an HTTP/1 connection, which can be taken apart.
+    fn is_http1(&self) -> bool {
+        match self.conn {
+            #[cfg(feature = "http1")]
+            Some(ProtoServer::H1(..)) => true,
+            _ => false,
+        }
+    }
+
+    // Tell the connection whether a `101` response can be sent, which
+    // depends on whether it's polled as a `Future`.
+    #[cfg(feature = "http1")]
+    fn claim_upgrades(&mut self, claimed: bool) {
+        if let Some(ProtoServer::H1(ref mut h1)) = self.conn {
+            h1.dispatch_mut().set_upgrade_claimed(claimed);
+        }
+    }
+
+    #[cfg(not(feature = "http1"))]
+    fn claim_upgrades(&mut self, _claimed: bool) {}
+
+    #[cfg(feature = "runtime")]
+    fn poll_write_timeout(&mut self) -> Poll<(), ::Error> {
+        let stats = self.stats();
+        match self.write_timeout {
+            Some(ref mut timeout) => timeout.poll(&stats),
+            None => Ok(Async::NotReady),
+        }
+    }
+
+    #[cfg(not(feature = "runtime"))]
+    fn poll_write_timeout(&mut self) -> Poll<(), ::Error> {
+        Ok(Async::NotReady)
+    }
+
+    fn poll_inner(&mut self) -> Poll<(), ::Error> {
+        self.claim_upgrades(false);
+        match self.conn.poll() {
+            Ok(Async::Ready(_)) => self.poll_h2c(),
+            Ok(Async::NotReady) => self.poll_write_timeout(),
+            Err(e) => {
+                debug!("error polling connection protocol: {}", e);
+                match *e.kind() {
+                    #[cfg(all(feature = "http1", feature = "http2"))]
+                    Kind::Parse(Parse::VersionH2) => self.try_h2(),
+                    _ => Err(e),
+                }
+            }
         }
     }
 
+    #[cfg(all(feature = "http1", feature = "http2"))]
     fn try_h2(&mut self) -> Poll<(), ::Error> {
         trace!("Trying to upgrade connection to h2");
+        self.upgrade_h2(None)
+    }
+
+    // An HTTP/1 connection that accepted an offer to upgrade to HTTP/2
+    // goes on as HTTP/2, once the `101` was written.
+    #[cfg(all(feature = "http1", feature = "http2"))]
+    fn poll_h2c(&mut self) -> Poll<(), ::Error> {
+        let h2c_request = match self.conn {
+            Some(ProtoServer::H1(ref mut h1)) => h1.dispatch_mut().take_h2c_request(),
+            _ => None,
+        };
+        match h2c_request {
+            Some(upgrade) => {
+                trace!("upgrading connection to h2c");
+                self.upgrade_h2(Some(upgrade))
+            },
+            None => Ok(Async::Ready(())),
+        }
+    }
+
+    #[cfg(not(all(feature = "http1", feature = "http2")))]
+    fn poll_h2c(&mut self) -> Poll<(), ::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    // Serve the rest of an HTTP/1 connection as HTTP/2, with the request
+    // that upgraded it with `h2c`, if any, as the first stream.
+    #[cfg(all(feature = "http1", feature = "http2"))]
+    fn upgrade_h2(&mut self, h2c_request: Option<proto::h1::h2c::Upgrade>) -> Poll<(), ::Error> {
         let conn = self.conn.take();
 
-        let (io, read_buf, dispatch) = match conn.unwrap() {
-            Either::A(h1) => {
-                h1.into_inner()
+        let (io, read_buf, dispatch, chunk_size) = match conn.unwrap() {
+            ProtoServer::H1(h1) => {
+                let chunk_size = h1.chunk_size();
+                let (io, read_buf, dispatch) = h1.into_inner();
+                (io, read_buf, dispatch, chunk_size)
             },
-            Either::B(_h2) => {
+            ProtoServer::H2(_h2) => {
                 panic!("h2 cannot into_inner");
             }
         };
         let mut rewind_io = Rewind::new(io);
         rewind_io.rewind(read_buf);
-        let mut h2 = proto::h2::Server::new(rewind_io, dispatch.into_service(), Exec::Default);
+        if let Some(upgrade) = h2c_request {
+            rewind_io.splice(upgrade.settings, upgrade.frames);
+        }
+        let (service, request_ids, method_policy, error_status, cancel_token) = dispatch.into_parts();
+        let mut h2 = proto::h2::Server::new(rewind_io, service, Exec::Default, request_ids, method_policy, error_status, chunk_size);
+        if let Some(token) = cancel_token {
+            h2.set_cancel_token(token);
+        }
+        if let Some(max) = self.http2_max_pending_streams {
+            h2.set_max_pending_streams(max);
+        }
         let pr = h2.poll();
 
         debug_assert!(self.conn.is_none());
-        self.conn = Some(Either::B(h2));
+        self.conn = Some(ProtoServer::H2(h2));
         
         pr
     }
@@ -412,16 +1415,11 @@ where
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        match self.conn.poll() {
-            Ok(x) => Ok(x.map(|o| o.unwrap_or_else(|| ()))),
-            Err(e) => {
-                debug!("error polling connection protocol: {}", e);
-                match *e.kind() {
-                    Kind::Parse(Parse::VersionH2) => self.try_h2(),
-                    _ => Err(e),
-                }
-            }
+        let res = self.poll_inner();
+        if let Some(reason) = CloseReason::after(&res) {
+            self.close_reason = Some(reason);
         }
+        res
     }
 }
 
@@ -435,6 +1433,72 @@ where
     }
 }
 
+// ===== impl WithoutShutdown =====
+
+impl<I, B, S> Future for WithoutShutdown<I, S>
+where
+    S: Service<ReqBody=Body, ResBody=B> + 'static,
+    S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    S::Future: Send,
+    I: AsyncRead + AsyncWrite + 'static,
+    B: Payload + 'static,
+{
+    type Item = Parts<I, S>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let conn = self.conn.as_mut().expect("polled after complete");
+            // Only HTTP/1 can be taken apart, so an HTTP/2 connection fails
+            // at once, instead of being driven until it closes.
+            if !conn.is_http1() {
+                return Err(::Error::new_user_unsupported_into_parts());
+            }
+            try_ready!(conn.poll_without_shutdown());
+        }
+        let conn = self.conn.take().expect("polled after complete");
+        match conn.into_parts() {
+            Some(parts) => Ok(Async::Ready(parts)),
+            None => Err(::Error::new_user_unsupported_into_parts()),
+        }
+    }
+}
+
+impl<I, S> fmt::Debug for WithoutShutdown<I, S>
+where
+    S: Service,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WithoutShutdown")
+            .finish()
+    }
+}
+
+// ===== impl ProtoServer =====
+
+impl<I, B, S> Future for ProtoServer<I, B, S>
+where
+    S: Service<ReqBody=Body, ResBody=B> + 'static,
+    S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    S::Future: Send,
+    I: AsyncRead + AsyncWrite + 'static,
+    B: Payload + 'static,
+{
+    type Item = ();
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match *self {
+            #[cfg(feature = "http1")]
+            ProtoServer::H1(ref mut h1) => h1.poll(),
+            #[cfg(feature = "http2")]
+            ProtoServer::H2(ref mut h2) => h2.poll(),
+            #[cfg(not(feature = "http2"))]
+            ProtoServer::Unsupported(_) => Err(::Error::new_user_unsupported_version()),
+        }
+    }
+}
+
 // ===== impl Serve =====
 
 impl<I, S> Serve<I, S> {
@@ -445,13 +1509,13 @@ impl<I, S> Serve<I, S> {
         }
     }
 
-    /// Get a reference to the incoming stream.
+    /// Get a reference to the incoming connections.
     #[inline]
     pub fn incoming_ref(&self) -> &I {
         &self.incoming
     }
 
-    /// Get a mutable reference to the incoming stream.
+    /// Get a mutable reference to the incoming connections.
     #[inline]
     pub fn incoming_mut(&mut self) -> &mut I {
         &mut self.incoming
@@ -460,20 +1524,20 @@ impl<I, S> Serve<I, S> {
 
 impl<I, S, B> Stream for Serve<I, S>
 where
-    I: Stream,
-    I::Item: AsyncRead + AsyncWrite,
+    I: Accept,
+    I::Conn: AsyncRead + AsyncWrite,
     I::Error: Into<Box<::std::error::Error + Send + Sync>>,
-    S: NewService<ReqBody=Body, ResBody=B>,
+    S: MakeServiceRef<I::Conn, ReqBody=Body, ResBody=B>,
     S::Error: Into<Box<::std::error::Error + Send + Sync>>,
     <S::Service as Service>::Future: Send + 'static,
     B: Payload,
 {
-    type Item = Connecting<I::Item, S::Future>;
+    type Item = Connecting<I::Conn, S::Future>;
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        if let Some(io) = try_ready!(self.incoming.poll().map_err(::Error::new_accept)) {
-            let new_fut = self.new_service.new_service();
+        if let Some(io) = try_ready!(self.incoming.poll_accept().map_err(::Error::new_accept)) {
+            let new_fut = self.new_service.make_service_ref(&io);
             Ok(Async::Ready(Some(Connecting {
                 future: new_fut,
                 io: Some(io),
@@ -522,10 +1586,10 @@ impl<I, S> SpawnAll<I, S> {
 
 impl<I, S, B> Future for SpawnAll<I, S>
 where
-    I: Stream,
+    I: Accept,
     I::Error: Into<Box<::std::error::Error + Send + Sync>>,
-    I::Item: AsyncRead + AsyncWrite + Send + 'static,
-    S: NewService<ReqBody=Body, ResBody=B> + Send + 'static,
+    I::Conn: AsyncRead + AsyncWrite + Send + 'static,
+    S: MakeServiceRef<I::Conn, ReqBody=Body, ResBody=B> + Send + 'static,
     S::Error: Into<Box<::std::error::Error + Send + Sync>>,
     S::Service: Send,
     S::Future: Send + 'static,
@@ -536,17 +1600,92 @@ where
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.poll_watch(None)
+    }
+}
+
+impl<I, S, B> SpawnAll<I, S>
+where
+    I: Accept,
+    I::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    I::Conn: AsyncRead + AsyncWrite + Send + 'static,
+    S: MakeServiceRef<I::Conn, ReqBody=Body, ResBody=B> + Send + 'static,
+    S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    S::Service: Send,
+    S::Future: Send + 'static,
+    <S::Service as Service>::Future: Send + 'static,
+    B: Payload,
+{
+    // Spawn the incoming connections, each watching `watch` to shut down
+    // gracefully once it starts draining.
+    pub(super) fn poll_watch(&mut self, watch: Option<&Watch>) -> Poll<(), ::Error> {
         loop {
             if let Some(connecting) = try_ready!(self.serve.poll()) {
-                let fut = connecting
-                    .map_err(::Error::new_user_new_service)
-                    // flatten basically
-                    .and_then(|conn| conn)
-                    .map_err(|err| debug!("conn error: {}", err));
-                self.serve.protocol.exec.execute(fut);
+                let fut = Serving::Connecting(connecting, false);
+                match watch {
+                    Some(watch) => {
+                        let fut = watch.clone()
+                            .watch(fut, Serving::graceful_shutdown)
+                            .map_err(|err| debug!("conn error: {}", err));
+                        self.serve.protocol.exec.execute(fut);
+                    },
+                    None => {
+                        let fut = fut.map_err(|err| debug!("conn error: {}", err));
+                        self.serve.protocol.exec.execute(fut);
+                    },
+                }
             } else {
                 return Ok(Async::Ready(()))
             }
         }
     }
 }
+
+// ===== impl Serving =====
+
+impl<I, F, S, B> Serving<I, F, S>
+where
+    I: AsyncRead + AsyncWrite + 'static,
+    F: Future<Item=S>,
+    S: Service<ReqBody=Body, ResBody=B> + 'static,
+    S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    S::Future: Send,
+    B: Payload,
+{
+    fn graceful_shutdown(&mut self) {
+        match *self {
+            Serving::Connecting(_, ref mut shutdown) => *shutdown = true,
+            Serving::Connected(ref mut conn) => conn.graceful_shutdown(),
+        }
+    }
+}
+
+impl<I, F, S, B> Future for Serving<I, F, S>
+where
+    I: AsyncRead + AsyncWrite + 'static,
+    F: Future<Item=S>,
+    F::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    S: Service<ReqBody=Body, ResBody=B> + 'static,
+    S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    S::Future: Send + 'static,
+    B: Payload,
+{
+    type Item = ();
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let conn = match *self {
+                Serving::Connecting(ref mut connecting, shutdown) => {
+                    let mut conn = try_ready!(connecting.poll().map_err(::Error::new_user_new_service));
+                    if shutdown {
+                        conn.graceful_shutdown();
+                    }
+                    conn
+                },
+                Serving::Connected(ref mut conn) => return conn.poll(),
+            };
+            *self = Serving::Connected(conn);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::task::AtomicTask;
+use http::Response;
+#[cfg(feature = "http1")] use http::HeaderMap;
+use http::header::HeaderValue;
+#[cfg(feature = "http1")] use http::header::EXPECT;
+
+use super::informational::Informational;
+
+/// A handle to answer a request's `Expect: 100-continue`.
+///
+/// When [`Http::http1_auto_continue`](::server::conn::Http::http1_auto_continue)
+/// is disabled, an HTTP/1 request with `Expect: 100-continue` has one of
+/// these in its extensions, and `100 Continue` is only sent once the
+/// `Service` calls [`send_continue`](ExpectContinue::send_continue). Until
+/// then, the client holds back the body, so the `Service` can look at the
+/// request head first, and answer with a final status, such as
+/// `413 Payload Too Large` or `417 Expectation Failed`, without the body
+/// ever being sent.
+///
+/// Reading the body doesn't send `100 Continue` by itself. A client may
+/// send the body anyway after waiting a while, but most wait for the
+/// `100 Continue` first.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// # use futures::future;
+/// # use hyper::{Body, Request, Response, StatusCode};
+/// use hyper::header::CONTENT_LENGTH;
+/// use hyper::server::conn::ExpectContinue;
+///
+/// # fn main() {
+/// let upload = |req: Request<Body>| {
+///     let too_large = req.headers()
+///         .get(CONTENT_LENGTH)
+///         .and_then(|len| len.to_str().ok())
+///         .and_then(|len| len.parse::<u64>().ok())
+///         .map_or(false, |len| len > 1024 * 1024);
+///     if too_large {
+///         let mut res = Response::new(Body::empty());
+///         *res.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+///         return future::ok::<_, hyper::Error>(res);
+///     }
+///     if let Some(expect) = req.extensions().get::<ExpectContinue>() {
+///         expect.send_continue();
+///     }
+///     // ... read the body ...
+/// #   future::ok(Response::new(Body::empty()))
+/// };
+/// # drop(upload);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ExpectContinue {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    continued: AtomicBool,
+    task: AtomicTask,
+}
+
+impl ExpectContinue {
+    #[cfg(feature = "http1")]
+    pub(crate) fn new() -> ExpectContinue {
+        ExpectContinue {
+            inner: Arc::new(Inner {
+                continued: AtomicBool::new(false),
+                task: AtomicTask::new(),
+            }),
+        }
+    }
+
+    /// Send `100 Continue`, asking the client for the body.
+    ///
+    /// Nothing is sent if it was already, or if the response head was
+    /// already written.
+    pub fn send_continue(&self) {
+        if !self.inner.continued.swap(true, Ordering::SeqCst) {
+            trace!("expect continue: sending 100 Continue");
+            self.inner.task.notify();
+        }
+    }
+
+    /// Check if `send_continue` was called, and if not, notify the current
+    /// task once it is.
+    #[cfg(feature = "http1")]
+    pub(crate) fn poll_continue(&self) -> bool {
+        if self.inner.continued.load(Ordering::SeqCst) {
+            return true;
+        }
+        self.inner.task.register();
+        // It may have been continued before the task was registered.
+        self.inner.continued.load(Ordering::SeqCst)
+    }
+}
+
+impl fmt::Debug for ExpectContinue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExpectContinue")
+            .field("continued", &self.inner.continued.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+/// What HTTP/1 connections do with a request that has an `Expect` other
+/// than `100-continue`, which hyper doesn't know how to meet.
+///
+/// Set with
+/// [`Http::http1_unknown_expectations`](::server::conn::Http::http1_unknown_expectations).
+/// HTTP/1.0 requests are always served as if they had no expectations, as
+/// HTTP/1.0 doesn't define `Expect`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownExpectations {
+    /// Serve the request as if it had no expectations.
+    Ignore,
+    /// Answer with `417 Expectation Failed`, without calling the `Service`.
+    Reject,
+    /// Serve the request with an [`Expectation`](Expectation) in its
+    /// extensions, so the `Service` can meet the expectations, or answer
+    /// with a `417 Expectation Failed` itself.
+    Delegate,
+}
+
+impl Default for UnknownExpectations {
+    fn default() -> UnknownExpectations {
+        UnknownExpectations::Ignore
+    }
+}
+
+/// The expectations of a request that hyper doesn't know how to meet.
+///
+/// An HTTP/1.1 request has one of these in its extensions when
+/// [`UnknownExpectations::Delegate`](UnknownExpectations::Delegate) is set,
+/// and it has an `Expect` other than `100-continue`. A `Service` that knows
+/// the expectations can meet them, such as with interim responses sent
+/// with [`send_interim`](Expectation::send_interim), and otherwise should
+/// answer with `417 Expectation Failed`.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// # use futures::future;
+/// # use hyper::{Body, Request, Response, StatusCode};
+/// use hyper::server::conn::Expectation;
+///
+/// # fn main() {
+/// let service = |req: Request<Body>| {
+///     if let Some(expectation) = req.extensions().get::<Expectation>() {
+///         if expectation.values().iter().any(|value| value != "x-ping") {
+///             let mut res = Response::new(Body::empty());
+///             *res.status_mut() = StatusCode::EXPECTATION_FAILED;
+///             return future::ok::<_, hyper::Error>(res);
+///         }
+///         let pong = Response::builder()
+///             .status(StatusCode::from_u16(199).unwrap())
+///             .body(())
+///             .unwrap();
+///         let _ = expectation.send_interim(pong);
+///     }
+///     // ... serve the request ...
+/// #   future::ok(Response::new(Body::empty()))
+/// };
+/// # drop(service);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Expectation {
+    values: Vec<HeaderValue>,
+    informational: Informational,
+}
+
+impl Expectation {
+    #[cfg(feature = "http1")]
+    pub(crate) fn new(values: Vec<HeaderValue>, informational: Informational) -> Expectation {
+        Expectation {
+            values,
+            informational,
+        }
+    }
+
+    /// The expectations hyper doesn't know, each an element of the
+    /// request's `Expect` headers.
+    pub fn values(&self) -> &[HeaderValue] {
+        &self.values
+    }
+
+    /// Send an interim (`1xx`) response, before the final one.
+    ///
+    /// See [`Informational::send`](::server::conn::Informational::send)
+    /// for the errors.
+    pub fn send_interim(&self, res: Response<()>) -> ::Result<()> {
+        self.informational.send(res)
+    }
+}
+
+/// The elements of the `Expect` headers other than `100-continue`.
+#[cfg(feature = "http1")]
+pub(crate) fn unknown_expectations(headers: &HeaderMap) -> Vec<HeaderValue> {
+    let mut unknown = Vec::new();
+    for value in headers.get_all(EXPECT) {
+        for element in value.as_bytes().split(|&b| b == b',') {
+            let element = trim(element);
+            if element.is_empty() || element.eq_ignore_ascii_case(b"100-continue") {
+                continue;
+            }
+            if let Ok(element) = HeaderValue::from_bytes(element) {
+                unknown.push(element);
+            }
+        }
+    }
+    unknown
+}
+
+#[cfg(feature = "http1")]
+fn trim(mut bytes: &[u8]) -> &[u8] {
+    while let Some((&first, rest)) = bytes.split_first() {
+        if first != b' ' && first != b'\t' {
+            break;
+        }
+        bytes = rest;
+    }
+    while let Some((&last, rest)) = bytes.split_last() {
+        if last != b' ' && last != b'\t' {
+            break;
+        }
+        bytes = rest;
+    }
+    bytes
+}
+
+#[cfg(all(test, feature = "http1"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_expectations_skips_continue() {
+        let mut headers = HeaderMap::new();
+        assert!(unknown_expectations(&headers).is_empty());
+
+        headers.append(EXPECT, HeaderValue::from_static("100-Continue"));
+        assert!(unknown_expectations(&headers).is_empty());
+
+        headers.append(EXPECT, HeaderValue::from_static(" x-foo ,, 100-continue,x-bar=1\t"));
+        assert_eq!(unknown_expectations(&headers), ["x-foo", "x-bar=1"]);
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::Async;
+use futures::task::AtomicTask;
+
+/// A handle to tell if the client closed its writing half of an HTTP/1
+/// connection.
+///
+/// Simple clients often shut down writing once the request is sent, and
+/// only read the response afterwards. By default, the response is still
+/// written, but the connection isn't kept alive after it. With
+/// [`Http::http1_half_close`](::server::conn::Http::http1_half_close)
+/// disabled, the connection is closed instead, without a response.
+///
+/// An HTTP/1 request has one of these in its extensions, so that a
+/// `Service` can notice, such as to stop streaming a response no one will
+/// ask to continue.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// # use futures::future;
+/// # use hyper::{Body, Request, Response};
+/// use hyper::server::conn::HalfClose;
+///
+/// # fn main() {
+/// let service = |req: Request<Body>| {
+///     let closed = req.extensions()
+///         .get::<HalfClose>()
+///         .map_or(false, HalfClose::is_closed);
+///     // ...
+/// #   drop(closed);
+/// #   future::ok::<_, hyper::Error>(Response::new(Body::empty()))
+/// };
+/// # drop(service);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct HalfClose {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    closed: AtomicBool,
+    task: AtomicTask,
+}
+
+impl HalfClose {
+    #[cfg(feature = "http1")]
+    pub(crate) fn new() -> HalfClose {
+        HalfClose {
+            inner: Arc::new(Inner {
+                closed: AtomicBool::new(false),
+                task: AtomicTask::new(),
+            }),
+        }
+    }
+
+    /// Check if the client closed its writing half.
+    ///
+    /// It's only noticed once the request body was read, and while no
+    /// pipelined request is waiting behind it.
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::SeqCst)
+    }
+
+    /// Check if the client closed its writing half, and if not, notify the
+    /// current task once it does.
+    pub fn poll_closed(&self) -> Async<()> {
+        if self.is_closed() {
+            return Async::Ready(());
+        }
+        self.inner.task.register();
+        // It may have closed before the task was registered.
+        if self.is_closed() {
+            Async::Ready(())
+        } else {
+            Async::NotReady
+        }
+    }
+
+    #[cfg(feature = "http1")]
+    pub(crate) fn close(&self) {
+        if !self.inner.closed.swap(true, Ordering::SeqCst) {
+            trace!("half close: client closed its writing half");
+            self.inner.task.notify();
+        }
+    }
+}
+
+impl fmt::Debug for HalfClose {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HalfClose")
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
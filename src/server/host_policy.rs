@@ -0,0 +1,229 @@
+use std::fmt;
+use std::net::SocketAddr;
+
+#[cfg(feature = "http1")] use http::{HeaderMap, StatusCode, Uri};
+#[cfg(feature = "http1")] use http::header;
+
+use headers::eq_ascii;
+
+/// Which authorities HTTP/1 requests may name in their `Host` header.
+///
+/// When set with [`Http::host_policy`](::server::conn::Http::host_policy),
+/// a request is answered without calling the `Service` if it doesn't have
+/// exactly one valid `Host` header, with a `400 Bad Request`, or if the
+/// header names an authority that isn't allowed, with a
+/// `421 Misdirected Request`. A request target in absolute form must name
+/// an allowed authority too.
+///
+/// This keeps requests meant for other sites away from the application,
+/// such as from a DNS rebinding attack, or with a forged `Host` used to
+/// build links.
+///
+/// Hosts are compared ignoring ASCII case. An authority allowed without a
+/// port matches any port.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// # use hyper::server::conn::HostPolicy;
+/// # fn main() {
+/// let addr = ([127, 0, 0, 1], 3000).into();
+/// let policy = HostPolicy::allow(vec!["example.com", "www.example.com"])
+///     .with_addr(addr);
+/// assert!(policy.is_allowed("WWW.example.com:8080"));
+/// assert!(policy.is_allowed("127.0.0.1:3000"));
+/// assert!(!policy.is_allowed("127.0.0.1:4000"));
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct HostPolicy {
+    authorities: Vec<(String, Option<u16>)>,
+}
+
+impl HostPolicy {
+    /// Only allow requests to one of the `authorities`, each a host with
+    /// an optional port.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if an authority isn't a valid host, with an
+    /// optional port.
+    pub fn allow<I, S>(authorities: I) -> HostPolicy
+    where
+        I: IntoIterator<Item=S>,
+        S: AsRef<str>,
+    {
+        authorities
+            .into_iter()
+            .fold(HostPolicy { authorities: Vec::new() }, |policy, authority| {
+                policy.with(authority.as_ref())
+            })
+    }
+
+    /// Only allow requests to a bound address, such as the one of a server
+    /// that isn't meant to be reached with a host name.
+    pub fn for_addr(addr: SocketAddr) -> HostPolicy {
+        HostPolicy { authorities: Vec::new() }.with_addr(addr)
+    }
+
+    /// Also allow requests to an `authority`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the authority isn't a valid host, with an
+    /// optional port.
+    pub fn with(mut self, authority: &str) -> HostPolicy {
+        let (host, port) = split_authority(authority)
+            .unwrap_or_else(|| panic!("invalid authority: {:?}", authority));
+        self.authorities.push((host.to_owned(), port));
+        self
+    }
+
+    /// Also allow requests to a bound address.
+    pub fn with_addr(mut self, addr: SocketAddr) -> HostPolicy {
+        let host = match addr {
+            SocketAddr::V4(ref addr) => addr.ip().to_string(),
+            SocketAddr::V6(ref addr) => format!("[{}]", addr.ip()),
+        };
+        self.authorities.push((host, Some(addr.port())));
+        self
+    }
+
+    /// Check if a request naming this `authority` may reach the `Service`.
+    pub fn is_allowed(&self, authority: &str) -> bool {
+        let (host, port) = match split_authority(authority) {
+            Some(parts) => parts,
+            None => return false,
+        };
+        self.authorities.iter().any(|&(ref allowed, allowed_port)| {
+            eq_ascii(allowed, host) && (allowed_port.is_none() || allowed_port == port)
+        })
+    }
+
+    /// The status to reject a request with, if it shouldn't reach the
+    /// `Service`.
+    #[cfg(feature = "http1")]
+    pub(crate) fn check(&self, uri: &Uri, headers: &HeaderMap) -> Option<StatusCode> {
+        let mut hosts = headers.get_all(header::HOST).iter();
+        let host = match (hosts.next(), hosts.next()) {
+            (Some(host), None) => host,
+            _ => return Some(StatusCode::BAD_REQUEST),
+        };
+        let host = match host.to_str() {
+            Ok(host) if split_authority(host).is_some() => host,
+            _ => return Some(StatusCode::BAD_REQUEST),
+        };
+        if !self.is_allowed(host) {
+            return Some(StatusCode::MISDIRECTED_REQUEST);
+        }
+        if let Some(authority) = uri.authority_part() {
+            if !self.is_allowed(authority.as_str()) {
+                return Some(StatusCode::MISDIRECTED_REQUEST);
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Debug for HostPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let authorities = self.authorities
+            .iter()
+            .map(|&(ref host, port)| match port {
+                Some(port) => format!("{}:{}", host, port),
+                None => host.clone(),
+            })
+            .collect::<Vec<_>>();
+        f.debug_tuple("HostPolicy")
+            .field(&authorities)
+            .finish()
+    }
+}
+
+// Splits an authority into its host, and optional port, if it has no
+// userinfo, and a host made of name characters or an IP literal.
+fn split_authority(authority: &str) -> Option<(&str, Option<u16>)> {
+    let (host, port) = if authority.starts_with('[') {
+        let end = match authority.find(']') {
+            Some(end) => end + 1,
+            None => return None,
+        };
+        let (host, rest) = authority.split_at(end);
+        if !host[1..end - 1].bytes().all(|b| is_hex(b) || b == b':' || b == b'.') {
+            return None;
+        }
+        (host, rest)
+    } else {
+        let end = authority.find(':').unwrap_or(authority.len());
+        let (host, rest) = authority.split_at(end);
+        if !host.bytes().all(|b| is_alphanumeric(b) || b == b'-' || b == b'.' || b == b'_') {
+            return None;
+        }
+        (host, rest)
+    };
+    if host.is_empty() {
+        return None;
+    }
+    if port.is_empty() {
+        return Some((host, None));
+    }
+    if !port.starts_with(':') {
+        return None;
+    }
+    match port[1..].parse() {
+        Ok(port) => Some((host, Some(port))),
+        Err(_) => None,
+    }
+}
+
+fn is_alphanumeric(b: u8) -> bool {
+    (b >= b'0' && b <= b'9') || (b >= b'a' && b <= b'z') || (b >= b'A' && b <= b'Z')
+}
+
+fn is_hex(b: u8) -> bool {
+    (b >= b'0' && b <= b'9') || (b >= b'a' && b <= b'f') || (b >= b'A' && b <= b'F')
+}
+
+#[cfg(all(test, feature = "http1"))]
+mod tests {
+    use http::{HeaderMap, StatusCode, Uri};
+    use http::header::{self, HeaderValue};
+
+    use super::HostPolicy;
+
+    #[test]
+    fn allow_matches_host_and_port() {
+        let policy = HostPolicy::allow(vec!["example.com", "api.example.com:8443"])
+            .with_addr("[::1]:3000".parse().unwrap());
+        assert!(policy.is_allowed("EXAMPLE.com"));
+        assert!(policy.is_allowed("example.com:80"));
+        assert!(policy.is_allowed("api.example.com:8443"));
+        assert!(!policy.is_allowed("api.example.com"));
+        assert!(policy.is_allowed("[::1]:3000"));
+        assert!(!policy.is_allowed("[::1]"));
+        assert!(!policy.is_allowed("evil.example"));
+        assert!(!policy.is_allowed("user@example.com"));
+        assert!(!policy.is_allowed("example.com:http"));
+    }
+
+    #[test]
+    fn check_requires_one_allowed_host() {
+        let policy = HostPolicy::allow(vec!["example.com"]);
+        let uri = Uri::from_static("/");
+        let mut headers = HeaderMap::new();
+        assert_eq!(policy.check(&uri, &headers), Some(StatusCode::BAD_REQUEST));
+
+        headers.insert(header::HOST, HeaderValue::from_static("example.com"));
+        assert_eq!(policy.check(&uri, &headers), None);
+
+        let absolute = Uri::from_static("http://evil.example/");
+        assert_eq!(policy.check(&absolute, &headers), Some(StatusCode::MISDIRECTED_REQUEST));
+
+        headers.append(header::HOST, HeaderValue::from_static("example.com"));
+        assert_eq!(policy.check(&uri, &headers), Some(StatusCode::BAD_REQUEST));
+
+        headers.insert(header::HOST, HeaderValue::from_static("evil.example"));
+        assert_eq!(policy.check(&uri, &headers), Some(StatusCode::MISDIRECTED_REQUEST));
+    }
+}
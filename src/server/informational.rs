@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use futures::task::AtomicTask;
+use http::{Response, StatusCode};
+
+use proto::MessageHead;
+
+/// A handle to send interim (`1xx`) responses before the final one.
+///
+/// With [`Http::http1_informational_responses`](::server::conn::Http::http1_informational_responses)
+/// enabled, an HTTP/1.1 request has one of these in its extensions, so a
+/// `Service` can tell the client something while it works on the response,
+/// such as `103 Early Hints` with the `link`s a page will preload. Any
+/// number of interim responses can be sent, and they are written in order,
+/// before the final response head.
+///
+/// HTTP/1.0 clients don't understand interim responses, so their requests
+/// don't have one. HTTP/2 requests don't have one either, since sending
+/// interim responses over HTTP/2 isn't supported yet.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// # use futures::future;
+/// # use hyper::{Body, Request, Response, StatusCode};
+/// use hyper::server::conn::Informational;
+///
+/// # fn main() {
+/// let page = |req: Request<Body>| {
+///     if let Some(informational) = req.extensions().get::<Informational>() {
+///         let hints = Response::builder()
+///             .status(StatusCode::from_u16(103).unwrap())
+///             .header("link", "</style.css>; rel=preload; as=style")
+///             .body(())
+///             .unwrap();
+///         // It's fine if the response was already sent.
+///         let _ = informational.send(hints);
+///     }
+///     // ... render the page ...
+/// #   future::ok::<_, hyper::Error>(Response::new(Body::empty()))
+/// };
+/// # drop(page);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Informational {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    // `None` once the final response was written.
+    queue: Mutex<Option<VecDeque<MessageHead<StatusCode>>>>,
+    task: AtomicTask,
+}
+
+impl Informational {
+    #[cfg(feature = "http1")]
+    pub(crate) fn new() -> Informational {
+        Informational {
+            inner: Arc::new(Inner {
+                queue: Mutex::new(Some(VecDeque::new())),
+                task: AtomicTask::new(),
+            }),
+        }
+    }
+
+    /// Send an interim response.
+    ///
+    /// Only the status, headers, and a
+    /// [`ReasonPhrase`](::server::conn::ReasonPhrase) extension are sent.
+    ///
+    /// # Errors
+    ///
+    /// This returns an error if the status isn't `1xx`, or is
+    /// `101 Switching Protocols`, which is for upgrading the connection
+    /// instead. It also errors if the final response was already written,
+    /// in which case [`Error::is_closed`](::Error::is_closed) is true.
+    pub fn send(&self, res: Response<()>) -> ::Result<()> {
+        let (mut parts, ()) = res.into_parts();
+        if !parts.status.is_informational() || parts.status == StatusCode::SWITCHING_PROTOCOLS {
+            return Err(::Error::new_user_informational());
+        }
+        let mut queue = self.inner.queue.lock().unwrap();
+        match *queue {
+            Some(ref mut queue) => {
+                trace!("informational: queued {}", parts.status);
+                queue.push_back(MessageHead {
+                    version: parts.version,
+                    subject: parts.status,
+                    headers: parts.headers,
+                    header_case: None,
+                    header_order: None,
+                    reason: parts.extensions.remove(),
+                });
+            },
+            None => return Err(::Error::new_closed()),
+        }
+        drop(queue);
+        self.inner.task.notify();
+        Ok(())
+    }
+
+    /// Take the next interim response to write, if any, and notify the
+    /// current task once there is.
+    #[cfg(feature = "http1")]
+    pub(crate) fn poll_next(&self) -> Option<MessageHead<StatusCode>> {
+        self.inner.task.register();
+        match *self.inner.queue.lock().unwrap() {
+            Some(ref mut queue) => queue.pop_front(),
+            None => None,
+        }
+    }
+
+    /// Refuse any more interim responses, since the final one was written.
+    #[cfg(feature = "http1")]
+    pub(crate) fn close(&self) {
+        *self.inner.queue.lock().unwrap() = None;
+    }
+}
+
+impl fmt::Debug for Informational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Informational")
+            .field("closed", &self.inner.queue.lock().unwrap().is_none())
+            .finish()
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+
+use http::{HeaderMap, Method};
+use http::header::{self, HeaderValue};
+
+/// Which request methods are allowed to reach a `Service`.
+///
+/// When set with [`Http::method_policy`](::server::conn::Http::method_policy),
+/// requests with any other method are answered with a
+/// `405 Method Not Allowed`, without calling the `Service`.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// # use hyper::server::conn::MethodPolicy;
+/// # fn main() {
+/// // Also keep a custom debugging method out of the application.
+/// let debug = hyper::Method::from_bytes(b"DEBUG").unwrap();
+/// let policy = MethodPolicy::deny_trace().with(debug);
+/// # drop(policy);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct MethodPolicy {
+    mode: Mode,
+    methods: Vec<Method>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Allow,
+    Deny,
+}
+
+impl MethodPolicy {
+    /// Reject `TRACE` and `TRACK` requests, which can echo back credentials
+    /// and cookies, and allow any other method.
+    pub fn deny_trace() -> MethodPolicy {
+        let track = Method::from_bytes(b"TRACK").expect("TRACK is a valid method");
+        MethodPolicy::deny(vec![Method::TRACE, track])
+    }
+
+    /// Reject requests with any of the `methods`, and allow any other one.
+    pub fn deny<I>(methods: I) -> MethodPolicy
+    where
+        I: IntoIterator<Item=Method>,
+    {
+        MethodPolicy {
+            mode: Mode::Deny,
+            methods: methods.into_iter().collect(),
+        }
+    }
+
+    /// Only allow requests with one of the `methods`, and reject any other
+    /// one.
+    ///
+    /// The rejections list the allowed methods in their `Allow` header.
+    pub fn allow<I>(methods: I) -> MethodPolicy
+    where
+        I: IntoIterator<Item=Method>,
+    {
+        MethodPolicy {
+            mode: Mode::Allow,
+            methods: methods.into_iter().collect(),
+        }
+    }
+
+    /// Add a method to the list, rejecting it if this policy denies methods,
+    /// or allowing it if this policy allows methods.
+    pub fn with(mut self, method: Method) -> MethodPolicy {
+        if !self.methods.contains(&method) {
+            self.methods.push(method);
+        }
+        self
+    }
+
+    /// Check if a request with this method may reach the `Service`.
+    pub fn is_allowed(&self, method: &Method) -> bool {
+        let listed = self.methods.contains(method);
+        match self.mode {
+            Mode::Allow => listed,
+            Mode::Deny => !listed,
+        }
+    }
+
+    /// The headers of a `405 Method Not Allowed` response.
+    pub(crate) fn rejection_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if self.mode == Mode::Allow {
+            let allow = self.methods
+                .iter()
+                .map(Method::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+            let value = HeaderValue::from_str(&allow).expect("methods are valid header values");
+            headers.insert(header::ALLOW, value);
+        }
+        headers
+    }
+}
+
+impl fmt::Debug for MethodPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let methods = self.methods
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>();
+        match self.mode {
+            Mode::Allow => f.debug_tuple("Allow").field(&methods).finish(),
+            Mode::Deny => f.debug_tuple("Deny").field(&methods).finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use http::header;
+
+    use super::MethodPolicy;
+
+    #[test]
+    fn deny_trace() {
+        let policy = MethodPolicy::deny_trace();
+        assert!(!policy.is_allowed(&Method::TRACE));
+        assert!(!policy.is_allowed(&Method::from_bytes(b"TRACK").unwrap()));
+        assert!(policy.is_allowed(&Method::GET));
+        assert!(policy.rejection_headers().is_empty());
+    }
+
+    #[test]
+    fn allow_lists_methods() {
+        let policy = MethodPolicy::allow(vec![Method::GET, Method::HEAD]).with(Method::POST);
+        assert!(policy.is_allowed(&Method::POST));
+        assert!(!policy.is_allowed(&Method::DELETE));
+        assert_eq!(policy.rejection_headers()[header::ALLOW], "GET, HEAD, POST");
+    }
+}
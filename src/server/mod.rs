@@ -11,7 +11,7 @@
 //! # Server
 //!
 //! The [`Server`](Server) is main way to start listening for HTTP requests.
-//! It wraps a listener with a [`NewService`](::service), and then should
+//! It wraps a listener with a [`MakeService`](::service), and then should
 //! be executed to start serving requests.
 //!
 //! ## Example
@@ -48,24 +48,37 @@
 //! # fn main() {}
 //! ```
 
+pub mod accept;
 pub mod conn;
+pub(crate) mod expect;
+pub(crate) mod half_close;
 #[cfg(feature = "runtime")] mod tcp;
-mod rewind;
+pub(crate) mod host_policy;
+pub(crate) mod informational;
+pub(crate) mod method_policy;
+pub(crate) mod request_id;
+#[cfg(feature = "http2")] mod rewind;
+mod shutdown;
+pub(crate) mod upgrade;
 
 use std::fmt;
-#[cfg(feature = "runtime")] use std::net::SocketAddr;
+#[cfg(feature = "runtime")] use std::net::{SocketAddr, TcpListener as StdTcpListener};
+#[cfg(all(feature = "runtime", unix))] use std::os::unix::io::{FromRawFd, RawFd};
 #[cfg(feature = "runtime")] use std::time::Duration;
 
-use futures::{Future, Stream, Poll};
+use futures::{Future, Poll};
 use tokio_io::{AsyncRead, AsyncWrite};
 
 use body::{Body, Payload};
-use service::{NewService, Service};
+use service::{MakeServiceRef, Service};
+use self::accept::Accept;
 // Renamed `Http` as `Http_` for now so that people upgrading don't see an
 // error that `hyper::server::Http` is private...
 use self::conn::{Http as Http_, SpawnAll};
 #[cfg(feature = "runtime")] use self::tcp::{AddrIncoming};
 
+pub use self::shutdown::Graceful;
+
 /// A listening HTTP server.
 ///
 /// `Server` is a `Future` mapping a bound listener with a set of service
@@ -86,7 +99,10 @@ pub struct Builder<I> {
 // ===== impl Server =====
 
 impl<I> Server<I, ()> {
-    /// Starts a [`Builder`](Builder) with the provided incoming stream.
+    /// Starts a [`Builder`](Builder) with the provided incoming connections.
+    ///
+    /// This can be any [`Accept`](accept::Accept), such as a `Stream` of IO
+    /// objects.
     pub fn builder(incoming: I) -> Builder<I> {
         Builder {
             incoming,
@@ -116,22 +132,187 @@ impl Server<AddrIncoming, ()> {
         AddrIncoming::new(addr, None)
             .map(Server::builder)
     }
+
+    /// Binds to all the provided addresses, and returns a [`Builder`](Builder).
+    ///
+    /// The server accepts connections from all of them.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `addrs` is empty, or binding to any of the
+    /// addresses fails. For a method to bind to addresses and return a
+    /// `Result`, see `Server::try_bind_all`.
+    pub fn bind_all(addrs: &[SocketAddr]) -> Builder<AddrIncoming> {
+        let incoming = AddrIncoming::new_all(addrs, None)
+            .unwrap_or_else(|e| {
+                panic!("error binding to {:?}: {}", addrs, e);
+            });
+        Server::builder(incoming)
+    }
+
+    /// Tries to bind to all the provided addresses, and returns a
+    /// [`Builder`](Builder).
+    pub fn try_bind_all(addrs: &[SocketAddr]) -> ::Result<Builder<AddrIncoming>> {
+        AddrIncoming::new_all(addrs, None)
+            .map(Server::builder)
+    }
+
+    /// Binds to the provided address, with `IPV6_V6ONLY` set to `only_v6`,
+    /// and returns a [`Builder`](Builder).
+    ///
+    /// Whether a socket bound to an IPv6 address also accepts IPv4
+    /// connections, as IPv4-mapped addresses, otherwise depends on the OS:
+    /// Linux usually does by default, while Windows and the BSDs don't. For
+    /// an IPv4 address, `only_v6` is ignored.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if binding to the address fails. For a method
+    /// to bind to an address and return a `Result`, see
+    /// `Server::try_bind_only_v6`.
+    pub fn bind_only_v6(addr: &SocketAddr, only_v6: bool) -> Builder<AddrIncoming> {
+        let incoming = AddrIncoming::new_only_v6(addr, only_v6, None)
+            .unwrap_or_else(|e| {
+                panic!("error binding to {}: {}", addr, e);
+            });
+        Server::builder(incoming)
+    }
+
+    /// Tries to bind to the provided address, with `IPV6_V6ONLY` set to
+    /// `only_v6`, and returns a [`Builder`](Builder).
+    pub fn try_bind_only_v6(addr: &SocketAddr, only_v6: bool) -> ::Result<Builder<AddrIncoming>> {
+        AddrIncoming::new_only_v6(addr, only_v6, None)
+            .map(Server::builder)
+    }
+
+    /// Binds to a port on all IPv4 and IPv6 addresses, and returns a
+    /// [`Builder`](Builder).
+    ///
+    /// A single IPv6 socket accepting both IPv4 and IPv6 connections is
+    /// used where the OS allows it. Otherwise, an IPv4 and an IPv6 socket
+    /// are bound to the port, and if IPv6 isn't available at all, only the
+    /// IPv4 one. Either way, the server accepts connections on the port the
+    /// same way on every OS, though remote addresses of IPv4 connections to
+    /// a single socket are IPv4-mapped IPv6 addresses.
+    ///
+    /// With a port of `0`, all sockets use the same port picked by the OS.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if binding to the port fails. For a method to
+    /// bind to a port and return a `Result`, see
+    /// `Server::try_bind_dual_stack`.
+    pub fn bind_dual_stack(port: u16) -> Builder<AddrIncoming> {
+        let incoming = AddrIncoming::new_dual_stack(port, None)
+            .unwrap_or_else(|e| {
+                panic!("error binding to port {}: {}", port, e);
+            });
+        Server::builder(incoming)
+    }
+
+    /// Tries to bind to a port on all IPv4 and IPv6 addresses, and returns a
+    /// [`Builder`](Builder).
+    pub fn try_bind_dual_stack(port: u16) -> ::Result<Builder<AddrIncoming>> {
+        AddrIncoming::new_dual_stack(port, None)
+            .map(Server::builder)
+    }
+
+    /// Serves from an already bound `std::net::TcpListener`, and returns a
+    /// [`Builder`](Builder).
+    ///
+    /// This lets a listening socket be set up elsewhere, such as with
+    /// options hyper doesn't expose, or inherited from another process. The
+    /// listener is switched to non-blocking mode.
+    pub fn from_std(listener: StdTcpListener) -> ::Result<Builder<AddrIncoming>> {
+        AddrIncoming::from_std(listener, None)
+            .map(Server::builder)
+    }
+
+    /// Serves from the listening TCP socket with the file descriptor `fd`,
+    /// and returns a [`Builder`](Builder).
+    ///
+    /// This is useful for a socket passed in by the parent process, such as
+    /// with systemd socket activation, or by the previous instance of a
+    /// server restarting without dropping connections.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open socket that is bound and listening, and is not
+    /// owned by anything else, since the `Server` closes it when dropped.
+    #[cfg(unix)]
+    pub unsafe fn from_raw_fd(fd: RawFd) -> ::Result<Builder<AddrIncoming>> {
+        Server::from_std(StdTcpListener::from_raw_fd(fd))
+    }
 }
 
 #[cfg(feature = "runtime")]
 impl<S> Server<AddrIncoming, S> {
     /// Returns the local address that this server is bound to.
+    ///
+    /// If bound to several addresses, this is the first one.
     pub fn local_addr(&self) -> SocketAddr {
         self.spawn_all.local_addr()
     }
+
+    /// Returns all the local addresses that this server is bound to.
+    ///
+    /// These are the actual addresses, so binding port `0` shows the port
+    /// picked by the OS.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.spawn_all.incoming_ref().local_addrs()
+    }
+}
+
+impl<I, S> Server<I, S> {
+    /// Prepares the server to shut down gracefully once `signal` completes.
+    ///
+    /// The returned future stops accepting connections on every listener
+    /// when the signal completes, or fails, and tells every connection to
+    /// finish the requests in flight and then close, with HTTP/1 keep-alive
+    /// disabled and an HTTP/2 `GOAWAY`. It completes once all of them have
+    /// closed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate hyper;
+    /// # use futures::Future;
+    /// # fn main() {}
+    /// # #[cfg(feature = "runtime")]
+    /// # fn run() {
+    /// use futures::sync::oneshot;
+    /// use hyper::{Body, Response, Server};
+    /// use hyper::service::service_fn_ok;
+    ///
+    /// let addrs = [([0, 0, 0, 0], 80).into(), ([0, 0, 0, 0], 8080).into()];
+    /// let server = Server::bind_all(&addrs)
+    ///     .serve(|| service_fn_ok(|_req| Response::new(Body::from("Hello World"))));
+    ///
+    /// let (tx, rx) = oneshot::channel::<()>();
+    /// let graceful = server
+    ///     .with_graceful_shutdown(rx)
+    ///     .map_err(|err| eprintln!("server error: {}", err));
+    /// hyper::rt::spawn(graceful);
+    ///
+    /// // Later, stop serving both ports.
+    /// let _ = tx.send(());
+    /// # }
+    /// ```
+    pub fn with_graceful_shutdown<F>(self, signal: F) -> Graceful<I, S, F>
+    where
+        F: Future<Item=()>,
+    {
+        Graceful::new(self.spawn_all, signal)
+    }
 }
 
 impl<I, S, B> Future for Server<I, S>
 where
-    I: Stream,
+    I: Accept,
     I::Error: Into<Box<::std::error::Error + Send + Sync>>,
-    I::Item: AsyncRead + AsyncWrite + Send + 'static,
-    S: NewService<ReqBody=Body, ResBody=B> + Send + 'static,
+    I::Conn: AsyncRead + AsyncWrite + Send + 'static,
+    S: MakeServiceRef<I::Conn, ReqBody=Body, ResBody=B> + Send + 'static,
     S::Error: Into<Box<::std::error::Error + Send + Sync>>,
     S::Service: Send,
     S::Future: Send + 'static,
@@ -157,7 +338,8 @@ impl<I: fmt::Debug, S: fmt::Debug> fmt::Debug for Server<I, S> {
 // ===== impl Builder =====
 
 impl<I> Builder<I> {
-    /// Start a new builder, wrapping an incoming stream and low-level options.
+    /// Start a new builder, wrapping an [`Accept`](accept::Accept) and
+    /// low-level options.
     ///
     /// For a more convenient constructor, see [`Server::bind`](Server::bind).
     pub fn new(incoming: I, protocol: Http_) -> Self {
@@ -167,6 +349,281 @@ impl<I> Builder<I> {
         }
     }
 
+    /// Set how many bytes of body an HTTP/1 connection may write each time
+    /// it is polled, before yielding to other tasks on the executor.
+    ///
+    /// Default is no quota.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the passed `bytes` is 0.
+    pub fn http1_write_quota(mut self, bytes: usize) -> Self {
+        self.protocol.http1_write_quota(bytes);
+        self
+    }
+
+    /// Set the maximum number of headers of a request on HTTP/1
+    /// connections.
+    ///
+    /// See [`Http::http1_max_headers`](conn::Http::http1_max_headers) for
+    /// details.
+    ///
+    /// Default is 100.
+    pub fn http1_max_headers(mut self, max: usize) -> Self {
+        self.protocol.http1_max_headers(max);
+        self
+    }
+
+    /// Set whether HTTP/1 connections accept header values folded over
+    /// several lines.
+    ///
+    /// See [`Http::http1_allow_obsolete_line_folding`](conn::Http::http1_allow_obsolete_line_folding)
+    /// for details.
+    ///
+    /// Default is false.
+    pub fn http1_allow_obsolete_line_folding(mut self, enabled: bool) -> Self {
+        self.protocol.http1_allow_obsolete_line_folding(enabled);
+        self
+    }
+
+    /// Set whether HTTP/1 connections accept spaces in request-targets.
+    ///
+    /// See [`Http::http1_allow_spaces_in_request_target`](conn::Http::http1_allow_spaces_in_request_target)
+    /// for details.
+    ///
+    /// Default is false.
+    pub fn http1_allow_spaces_in_request_target(mut self, enabled: bool) -> Self {
+        self.protocol.http1_allow_spaces_in_request_target(enabled);
+        self
+    }
+
+    /// Set whether HTTP/1 connections send `100 Continue` as soon as they
+    /// read a request with `Expect: 100-continue`.
+    ///
+    /// See [`Http::http1_auto_continue`](conn::Http::http1_auto_continue)
+    /// for details.
+    ///
+    /// Default is true.
+    pub fn http1_auto_continue(mut self, enabled: bool) -> Self {
+        self.protocol.http1_auto_continue(enabled);
+        self
+    }
+
+    /// Set whether HTTP/1.1 requests can be sent interim (`1xx`) responses.
+    ///
+    /// See [`Http::http1_informational_responses`](conn::Http::http1_informational_responses)
+    /// for details.
+    ///
+    /// Default is false.
+    pub fn http1_informational_responses(mut self, enabled: bool) -> Self {
+        self.protocol.http1_informational_responses(enabled);
+        self
+    }
+
+    /// Set what HTTP/1 connections do with a request that has an `Expect`
+    /// other than `100-continue`.
+    ///
+    /// See [`Http::http1_unknown_expectations`](conn::Http::http1_unknown_expectations)
+    /// for details.
+    ///
+    /// Default is `UnknownExpectations::Ignore`.
+    pub fn http1_unknown_expectations(mut self, expectations: conn::UnknownExpectations) -> Self {
+        self.protocol.http1_unknown_expectations(expectations);
+        self
+    }
+
+    /// Set whether HTTP/1 connections still write the response after the
+    /// client closed its writing half.
+    ///
+    /// See [`Http::http1_half_close`](conn::Http::http1_half_close) for
+    /// details.
+    ///
+    /// Default is true.
+    pub fn http1_half_close(mut self, enabled: bool) -> Self {
+        self.protocol.http1_half_close(enabled);
+        self
+    }
+
+    /// Set whether HTTP/1 connections can be upgraded to HTTP/2 over
+    /// cleartext.
+    ///
+    /// See [`Http::http1_h2c_upgrade`](conn::Http::http1_h2c_upgrade) for
+    /// details.
+    ///
+    /// Default is false.
+    pub fn http1_h2c_upgrade(mut self, enabled: bool) -> Self {
+        self.protocol.http1_h2c_upgrade(enabled);
+        self
+    }
+
+    /// Set whether HTTP/1 connections keep the extensions of chunks of
+    /// request bodies.
+    ///
+    /// Default is false.
+    pub fn http1_chunk_extensions(mut self, enabled: bool) -> Self {
+        self.protocol.http1_chunk_extensions(enabled);
+        self
+    }
+
+    /// Set whether HTTP/1.0 requests can keep connections alive.
+    ///
+    /// Default is true.
+    pub fn http1_legacy_keep_alive(mut self, enabled: bool) -> Self {
+        self.protocol.http1_legacy_keep_alive(enabled);
+        self
+    }
+
+    /// Set whether HTTP/1 connections keep the original casing of request
+    /// header names.
+    ///
+    /// See [`Http::http1_preserve_header_case`](conn::Http::http1_preserve_header_case)
+    /// for details.
+    ///
+    /// Default is false.
+    pub fn http1_preserve_header_case(mut self, enabled: bool) -> Self {
+        self.protocol.http1_preserve_header_case(enabled);
+        self
+    }
+
+    /// Set whether HTTP/1 connections keep the original order of request
+    /// header lines.
+    ///
+    /// See [`Http::http1_preserve_header_order`](conn::Http::http1_preserve_header_order)
+    /// for details.
+    ///
+    /// Default is false.
+    pub fn http1_preserve_header_order(mut self, enabled: bool) -> Self {
+        self.protocol.http1_preserve_header_order(enabled);
+        self
+    }
+
+    /// Set whether HTTP/1 connections refuse to upgrade to other protocols.
+    ///
+    /// A `Server` polls its connections as `Future`s, so it never sends a
+    /// `101 Switching Protocols` response. With this enabled, the connection
+    /// goes on serving requests after refusing one, instead of closing.
+    ///
+    /// See [`Http::http1_refuse_upgrades`](conn::Http::http1_refuse_upgrades)
+    /// for details.
+    ///
+    /// Default is false.
+    pub fn http1_refuse_upgrades(mut self, enabled: bool) -> Self {
+        self.protocol.http1_refuse_upgrades(enabled);
+        self
+    }
+
+    /// Set a function to call when an HTTP/1 connection doesn't send a
+    /// `101 Switching Protocols` response of the `Service`.
+    ///
+    /// See [`Http::http1_on_refused_upgrade`](conn::Http::http1_on_refused_upgrade)
+    /// for details.
+    pub fn http1_on_refused_upgrade<F>(mut self, f: F) -> Self
+    where
+        F: Fn(conn::UpgradeRefusal, &::http::HeaderMap) + Send + Sync + 'static,
+    {
+        self.protocol.http1_on_refused_upgrade(f);
+        self
+    }
+
+    /// Configure connections to use as little memory as possible.
+    ///
+    /// See [`Http::low_memory`](conn::Http::low_memory) for details.
+    pub fn low_memory(mut self) -> Self {
+        self.protocol.low_memory();
+        self
+    }
+
+    /// Configure connections to transfer as many bytes as possible.
+    ///
+    /// See [`Http::high_throughput`](conn::Http::high_throughput) for details.
+    pub fn high_throughput(mut self) -> Self {
+        self.protocol.high_throughput();
+        self
+    }
+
+    /// Configure connections to respond as soon as possible.
+    ///
+    /// See [`Http::low_latency`](conn::Http::low_latency) for details.
+    pub fn low_latency(mut self) -> Self {
+        self.protocol.low_latency();
+        self
+    }
+
+    /// Set the maximum size of each chunk of a request body.
+    ///
+    /// See [`Http::max_chunk_size`](conn::Http::max_chunk_size) for details.
+    ///
+    /// Default is no maximum.
+    pub fn max_chunk_size(mut self, max: usize) -> Self {
+        self.protocol.max_chunk_size(max);
+        self
+    }
+
+    /// Set the minimum size of each chunk of a request body.
+    ///
+    /// See [`Http::min_chunk_size`](conn::Http::min_chunk_size) for details.
+    ///
+    /// Default is no minimum.
+    pub fn min_chunk_size(mut self, min: usize) -> Self {
+        self.protocol.min_chunk_size(min);
+        self
+    }
+
+    /// Set which authorities HTTP/1 requests may name in their `Host`
+    /// header.
+    ///
+    /// See [`Http::host_policy`](conn::Http::host_policy) for details.
+    ///
+    /// Default is to allow any `Host`.
+    pub fn host_policy(mut self, policy: conn::HostPolicy) -> Self {
+        self.protocol.host_policy(policy);
+        self
+    }
+
+    /// Set which request methods are allowed to reach the `Service`.
+    ///
+    /// See [`Http::method_policy`](conn::Http::method_policy) for details.
+    ///
+    /// Default is to allow all methods.
+    pub fn method_policy(mut self, policy: conn::MethodPolicy) -> Self {
+        self.protocol.method_policy(policy);
+        self
+    }
+
+    /// Set the status to respond with when a `Service` future resolves with
+    /// an error.
+    ///
+    /// See [`Http::service_error_status`](conn::Http::service_error_status)
+    /// for details.
+    ///
+    /// Default is to close the connection, or reset the HTTP/2 stream.
+    pub fn service_error_status(mut self, status: ::StatusCode) -> Self {
+        self.protocol.service_error_status(status);
+        self
+    }
+
+    /// Set whether each request gets a `RequestId`.
+    ///
+    /// See [`Http::request_id`](conn::Http::request_id) for details.
+    ///
+    /// Default is `false`.
+    pub fn request_id(mut self, enabled: bool) -> Self {
+        self.protocol.request_id(enabled);
+        self
+    }
+
+    /// Set how long a connection may go without writing anything, while
+    /// it has bytes waiting to be written, before it is closed.
+    ///
+    /// See [`Http::write_timeout`](conn::Http::write_timeout) for details.
+    ///
+    /// Default is `None`, waiting indefinitely.
+    #[cfg(feature = "runtime")]
+    pub fn write_timeout(mut self, dur: Option<Duration>) -> Self {
+        self.protocol.write_timeout(dur);
+        self
+    }
+
     /// Sets whether HTTP/2 is required.
     ///
     /// Default is `false`.
@@ -175,8 +632,54 @@ impl<I> Builder<I> {
         self
     }
 
+    /// Sets whether connections starting with the HTTP/2 preface are
+    /// served with HTTP/2.
+    ///
+    /// See [`Http::http2_auto`](conn::Http::http2_auto) for details.
+    ///
+    /// Default is `true`.
+    pub fn http2_auto(mut self, enabled: bool) -> Self {
+        self.protocol.http2_auto(enabled);
+        self
+    }
+
+    /// Sets the maximum number of streams of an HTTP/2 connection waiting
+    /// for their `Service` to respond.
+    ///
+    /// See [`Http::http2_max_pending_streams`](conn::Http::http2_max_pending_streams)
+    /// for details.
+    ///
+    /// Default is no limit.
+    pub fn http2_max_pending_streams(mut self, max: usize) -> Self {
+        self.protocol.http2_max_pending_streams(max);
+        self
+    }
+
+    /// Get a handle to change the HTTP settings of connections accepted
+    /// after the server started.
+    ///
+    /// See [`Http::shared`](conn::Http::shared) for details.
+    pub fn shared(&mut self) -> conn::SharedHttp {
+        self.protocol.shared()
+    }
+
+    /// Inject [`Faults`](::testing::Faults) into every accepted connection.
+    ///
+    /// This method is only available with the `testing` feature.
+    #[cfg(feature = "testing")]
+    pub fn inject_faults(self, faults: ::testing::Faults) -> Builder<::testing::FaultyIncoming<I>> {
+        Builder {
+            incoming: faults.incoming(self.incoming),
+            protocol: self.protocol,
+        }
+    }
+
     /// Consume this `Builder`, creating a [`Server`](Server).
     ///
+    /// A `Service` is made for each accepted connection. Use
+    /// [`make_service_fn`](::service::make_service_fn) to make it from the
+    /// connection's IO, such as its remote address.
+    ///
     /// # Example
     ///
     /// ```
@@ -206,10 +709,10 @@ impl<I> Builder<I> {
     /// ```
     pub fn serve<S, B>(self, new_service: S) -> Server<I, S>
     where
-        I: Stream,
+        I: Accept,
         I::Error: Into<Box<::std::error::Error + Send + Sync>>,
-        I::Item: AsyncRead + AsyncWrite + Send + 'static,
-        S: NewService<ReqBody=Body, ResBody=B> + Send + 'static,
+        I::Conn: AsyncRead + AsyncWrite + Send + 'static,
+        S: MakeServiceRef<I::Conn, ReqBody=Body, ResBody=B> + Send + 'static,
         S::Error: Into<Box<::std::error::Error + Send + Sync>>,
         S::Service: Send,
         <S::Service as Service>::Future: Send + 'static,
@@ -240,5 +743,40 @@ impl Builder<AddrIncoming> {
         self.incoming.set_nodelay(enabled);
         self
     }
+
+    /// Call `f` with each address the server is bound to.
+    ///
+    /// The addresses are bound when the builder is created, so `f` is called
+    /// right away, once per address, in the order they were given to
+    /// `Server::bind_all`. A port of `0` is reported as the port picked by
+    /// the OS.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate hyper;
+    /// # #[cfg(feature = "runtime")]
+    /// # fn main() {
+    /// use hyper::{Body, Response, Server};
+    /// use hyper::service::service_fn_ok;
+    ///
+    /// let addrs = [([127, 0, 0, 1], 0).into(), ([127, 0, 0, 1], 0).into()];
+    /// let server = Server::bind_all(&addrs)
+    ///     .on_bind(|addr| println!("listening on http://{}", addr))
+    ///     .serve(|| service_fn_ok(|_req| Response::new(Body::empty())));
+    /// # drop(server);
+    /// # }
+    /// # #[cfg(not(feature = "runtime"))]
+    /// # fn main() {}
+    /// ```
+    pub fn on_bind<F>(self, mut f: F) -> Self
+    where
+        F: FnMut(SocketAddr),
+    {
+        for addr in self.incoming.local_addrs() {
+            f(addr);
+        }
+        self
+    }
 }
 
@@ -0,0 +1,144 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use http::{HeaderMap, Request};
+use http::header::{HeaderName, HeaderValue};
+
+/// The longest inbound request id that will be accepted from a client.
+const MAX_INBOUND_LEN: usize = 128;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+static PROCESS_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// An identifier for a single request and response exchange.
+///
+/// When enabled with [`Http::request_id`](::server::conn::Http::request_id),
+/// every request given to a `Service` has a `RequestId` in its
+/// extensions, and the same id is sent back in a header of the response.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(HeaderValue);
+
+impl RequestId {
+    /// Get the id as a string slice.
+    pub fn as_str(&self) -> &str {
+        self.0.to_str().expect("RequestId is always visible ASCII")
+    }
+
+    fn generate() -> RequestId {
+        let process = process_id();
+        let next = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let id = format!("{:x}-{:x}", process, next);
+        RequestId(HeaderValue::from_str(&id).expect("hex is a valid header value"))
+    }
+
+    fn from_inbound(value: &HeaderValue) -> Option<RequestId> {
+        let bytes = value.as_bytes();
+        let is_valid = !bytes.is_empty()
+            && bytes.len() <= MAX_INBOUND_LEN
+            && bytes.iter().all(|&b| b > b' ' && b < 0x7F);
+        if is_valid {
+            Some(RequestId(value.clone()))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Debug for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RequestId")
+            .field(&self.as_str())
+            .finish()
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Distinguishes ids generated by this process from another one, such as
+// after a restart, so that ids stay unique in logs over time.
+fn process_id() -> usize {
+    let id = PROCESS_ID.load(Ordering::Relaxed);
+    if id != 0 {
+        return id;
+    }
+    let now = ::time::get_time();
+    let fresh = (now.sec as usize)
+        .wrapping_mul(1_000_000_000)
+        .wrapping_add(now.nsec as usize)
+        | 1;
+    match PROCESS_ID.compare_exchange(0, fresh, Ordering::SeqCst, Ordering::SeqCst) {
+        Ok(_) => fresh,
+        Err(current) => current,
+    }
+}
+
+/// Configuration to assign each request a `RequestId`.
+#[derive(Clone, Debug)]
+pub(crate) struct RequestIds {
+    header: HeaderName,
+}
+
+impl RequestIds {
+    pub(crate) fn new(header: HeaderName) -> RequestIds {
+        RequestIds {
+            header,
+        }
+    }
+
+    /// Use the id from the inbound header if there is a usable one, or
+    /// generate a new one, and put it in the request extensions.
+    pub(crate) fn on_request<B>(&self, req: &mut Request<B>) -> RequestId {
+        let id = req.headers()
+            .get(&self.header)
+            .and_then(RequestId::from_inbound)
+            .unwrap_or_else(RequestId::generate);
+        trace!("request id {} for {} {}", id, req.method(), req.uri());
+        req.extensions_mut().insert(id.clone());
+        id
+    }
+
+    /// Stamp the id on the response, unless the `Service` already set
+    /// the header itself.
+    pub(crate) fn on_response(&self, id: RequestId, headers: &mut HeaderMap) {
+        trace!("request id {} responding", id);
+        headers.entry(&self.header)
+            .expect("request id header is a valid HeaderName")
+            .or_insert(id.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_ids_are_unique() {
+        let a = RequestId::generate();
+        let b = RequestId::generate();
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn inbound_id_is_used_if_valid() {
+        let ids = RequestIds::new(HeaderName::from_static("x-request-id"));
+
+        let mut req = Request::new(());
+        req.headers_mut().insert("x-request-id", HeaderValue::from_static("abc-123"));
+        let id = ids.on_request(&mut req);
+        assert_eq!(id.as_str(), "abc-123");
+        assert_eq!(req.extensions().get::<RequestId>(), Some(&id));
+
+        let mut req = Request::new(());
+        req.headers_mut().insert("x-request-id", HeaderValue::from_static("has spaces"));
+        let id = ids.on_request(&mut req);
+        assert_ne!(id.as_str(), "has spaces");
+
+        let mut headers = HeaderMap::new();
+        ids.on_response(id.clone(), &mut headers);
+        assert_eq!(headers["x-request-id"], id.as_str());
+    }
+}
@@ -1,13 +1,51 @@
-use bytes::{Buf, BufMut, Bytes, IntoBuf};
+use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
 use futures::{Async, Poll};
 use std::io::{self, Read, Write};
 use std::cmp;
+use std::mem;
 use tokio_io::{AsyncRead, AsyncWrite};
 
+// The length of the HTTP/2 connection preface.
+const PREFACE_LEN: usize = 24;
+const FRAME_HEADER_LEN: usize = 9;
+const MAX_FRAME_SIZE: usize = 16_384;
+const TYPE_SETTINGS: u8 = 0x4;
+const FLAG_ACK: u8 = 0x1;
+
 #[derive(Debug)]
 pub struct Rewind<T> {
     pre: Option<Bytes>,
     inner: T,
+    splice: Option<Splice>,
+}
+
+// Settings and frames to read in the middle of what the client sent. The
+// settings are read at the start of the `SETTINGS` frame that must follow
+// the HTTP/2 preface, as if the client had sent them there, and the frames
+// right after it.
+#[derive(Debug)]
+struct Splice {
+    settings: Bytes,
+    frames: Bytes,
+    // How much of the preface and of the header of the client's first
+    // frame was read.
+    read: usize,
+    head: [u8; FRAME_HEADER_LEN],
+    // How much of the payload of the client's first frame is left to read.
+    payload: usize,
+    // What's read next, instead of what the client sent.
+    pending: Bytes,
+}
+
+// What a read does while splicing.
+enum Step {
+    // Read this much of what the client sent, at most.
+    Pass(usize),
+    // Read the rest of the header of the client's first frame.
+    Head(usize),
+    // This much was read from what's spliced.
+    Spliced(usize),
+    Done,
 }
 
 impl<T> Rewind<T> {
@@ -15,20 +53,80 @@ impl<T> Rewind<T> {
         Rewind {
             pre: None,
             inner: tcp,
+            splice: None,
         }
     }
+    #[cfg(feature = "http1")]
     pub fn rewind(&mut self, bs: Bytes) {
         debug_assert!(self.pre.is_none());
         self.pre = Some(bs);
     }
+
+    /// Read `frames` as if the client sent them after its first HTTP/2
+    /// frame, such as a request it sent before upgrading to HTTP/2, and
+    /// the payload `settings` as if they started that frame, such as the
+    /// ones it sent with its offer to upgrade.
+    #[cfg(feature = "http1")]
+    pub fn splice(&mut self, settings: Bytes, frames: Bytes) {
+        debug_assert!(self.splice.is_none());
+        self.splice = Some(Splice {
+            settings,
+            frames,
+            read: 0,
+            head: [0; FRAME_HEADER_LEN],
+            payload: 0,
+            pending: Bytes::new(),
+        });
+    }
 }
 
 impl<T> Read for Rewind<T>
 where
     T: Read,
 {
-    #[inline]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.read_rewound(buf);
+        }
+        loop {
+            let step = match self.splice {
+                Some(ref mut splice) => splice.step(buf),
+                None => return self.read_rewound(buf),
+            };
+            match step {
+                Step::Pass(max) => {
+                    let cnt = self.read_rewound(&mut buf[..max])?;
+                    if let Some(ref mut splice) = self.splice {
+                        splice.passed(cnt);
+                    }
+                    return Ok(cnt);
+                },
+                Step::Head(max) => {
+                    let mut head = [0; FRAME_HEADER_LEN];
+                    let cnt = self.read_rewound(&mut head[..max])?;
+                    if cnt == 0 {
+                        return Ok(0);
+                    }
+                    if let Some(ref mut splice) = self.splice {
+                        splice.on_head(&head[..cnt]);
+                    }
+                },
+                Step::Spliced(cnt) => return Ok(cnt),
+                Step::Done => {
+                    trace!("spliced frames were read");
+                    self.splice = None;
+                },
+            }
+        }
+    }
+}
+
+impl<T> Rewind<T>
+where
+    T: Read,
+{
+    #[inline]
+    fn read_rewound(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         if let Some(pre_bs) = self.pre.take() {
             // If there are no remaining bytes, let the bytes get dropped.
             if pre_bs.len() > 0 {
@@ -76,6 +174,18 @@ where
 
     #[inline]
     fn read_buf<B: BufMut>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        if self.splice.is_some() {
+            // The same as the default, since `read` knows where to stop.
+            unsafe {
+                let cnt = {
+                    let b = buf.bytes_mut();
+                    self.prepare_uninitialized_buffer(b);
+                    try_nb!(self.read(b))
+                };
+                buf.advance_mut(cnt);
+                return Ok(Async::Ready(cnt));
+            }
+        }
         if let Some(bs) = self.pre.take() {
             let pre_len = bs.len();
             // If there are no remaining bytes, let the bytes get dropped.
@@ -100,6 +210,71 @@ where
     }
 }
 
+impl Splice {
+    // Read into `buf` from what's spliced, or tell how much can be read of
+    // what the client sent.
+    fn step(&mut self, buf: &mut [u8]) -> Step {
+        if !self.pending.is_empty() {
+            let cnt = cmp::min(buf.len(), self.pending.len());
+            buf[..cnt].copy_from_slice(&self.pending[..cnt]);
+            self.pending.advance(cnt);
+            return Step::Spliced(cnt);
+        }
+        if self.read < PREFACE_LEN {
+            Step::Pass(cmp::min(buf.len(), PREFACE_LEN - self.read))
+        } else if self.read < PREFACE_LEN + FRAME_HEADER_LEN {
+            Step::Head(PREFACE_LEN + FRAME_HEADER_LEN - self.read)
+        } else if self.payload > 0 {
+            Step::Pass(cmp::min(buf.len(), self.payload))
+        } else {
+            Step::Done
+        }
+    }
+
+    fn passed(&mut self, cnt: usize) {
+        if self.read < PREFACE_LEN {
+            self.read += cnt;
+        } else {
+            self.payload -= cnt;
+            if self.payload == 0 {
+                self.pending = mem::replace(&mut self.frames, Bytes::new());
+            }
+        }
+    }
+
+    // Collect the header of the client's first frame, and once it's whole,
+    // read it with the settings added to its payload.
+    fn on_head(&mut self, bytes: &[u8]) {
+        let start = self.read - PREFACE_LEN;
+        self.head[start..start + bytes.len()].copy_from_slice(bytes);
+        self.read += bytes.len();
+        if self.read < PREFACE_LEN + FRAME_HEADER_LEN {
+            return;
+        }
+
+        self.payload = (self.head[0] as usize) << 16 | (self.head[1] as usize) << 8 | self.head[2] as usize;
+        let is_settings = self.head[3] == TYPE_SETTINGS && self.head[4] & FLAG_ACK == 0;
+        let len = self.payload + self.settings.len();
+        let mut pending = BytesMut::with_capacity(FRAME_HEADER_LEN + self.settings.len());
+        if is_settings && len <= MAX_FRAME_SIZE {
+            self.head[0] = (len >> 16) as u8;
+            self.head[1] = (len >> 8) as u8;
+            self.head[2] = len as u8;
+            pending.extend_from_slice(&self.head);
+            pending.extend_from_slice(&self.settings);
+        } else {
+            // The connection fails anyway without `SETTINGS` first.
+            debug!("client's first frame can't take the upgrade settings");
+            pending.extend_from_slice(&self.head);
+        }
+        if self.payload == 0 {
+            pending.extend_from_slice(&self.frames);
+            self.frames = Bytes::new();
+        }
+        self.pending = pending.freeze();
+    }
+}
+
 impl<T> AsyncWrite for Rewind<T>
 where
     T: AsyncWrite,
@@ -115,7 +290,7 @@ where
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "http1"))]
 mod tests {
     use super::*;
     extern crate tokio_mockstream;
@@ -205,4 +380,60 @@ mod tests {
         stream.read(&mut o2[cnt..]).unwrap();
         assert_eq!(&o2, &bs);
     }
+
+    fn read_spliced(sent: &[u8], settings: &[u8], step: usize) -> Vec<u8> {
+        let mut stream = Rewind::new(MockStream::new(&sent[10..]));
+        stream.rewind(Bytes::from(&sent[..10]));
+        stream.splice(Bytes::from(settings), Bytes::from(&b"spliced"[..]));
+
+        let mut read = Vec::new();
+        loop {
+            let mut chunk = Cursor::new(vec![0; step]);
+            match stream.read_buf(&mut chunk).unwrap() {
+                Async::Ready(0) => break,
+                Async::Ready(cnt) => read.extend_from_slice(&chunk.into_inner()[..cnt]),
+                Async::NotReady => panic!("should be ready"),
+            }
+        }
+        read
+    }
+
+    #[test]
+    fn splice_after_first_frame() {
+        let preface = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+        let ping = [0, 0, 8, 6, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8];
+        // SETTINGS_ENABLE_PUSH = 0
+        let upgrade_settings = [0, 2, 0, 0, 0, 0];
+
+        // SETTINGS with one setting, and a PING after it.
+        let mut sent = preface.to_vec();
+        sent.extend_from_slice(&[0, 0, 6, 4, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 100]);
+        sent.extend_from_slice(&ping);
+
+        let mut expected = preface.to_vec();
+        expected.extend_from_slice(&[0, 0, 12, 4, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&upgrade_settings);
+        expected.extend_from_slice(&[0, 3, 0, 0, 0, 100]);
+        expected.extend_from_slice(b"spliced");
+        expected.extend_from_slice(&ping);
+
+        for &step in &[1, 5, 100] {
+            assert_eq!(read_spliced(&sent, &upgrade_settings, step), expected, "step {}", step);
+        }
+
+        // An empty SETTINGS.
+        let mut sent = preface.to_vec();
+        sent.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 0, 0]);
+        sent.extend_from_slice(&ping);
+
+        let mut expected = preface.to_vec();
+        expected.extend_from_slice(&[0, 0, 6, 4, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&upgrade_settings);
+        expected.extend_from_slice(b"spliced");
+        expected.extend_from_slice(&ping);
+
+        for &step in &[1, 5, 100] {
+            assert_eq!(read_spliced(&sent, &upgrade_settings, step), expected, "step {}", step);
+        }
+    }
 }
@@ -0,0 +1,94 @@
+use std::fmt;
+
+use futures::{Async, Future, Poll};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use body::{Body, Payload};
+use common::drain::{self, Draining, Signal, Watch};
+use service::{MakeServiceRef, Service};
+use super::conn::SpawnAll;
+use super::accept::Accept;
+
+/// A future that serves until a signal, and then shuts down gracefully.
+///
+/// Made with [`Server::with_graceful_shutdown`](::Server::with_graceful_shutdown).
+#[must_use = "futures do nothing unless polled"]
+pub struct Graceful<I, S, F> {
+    state: State<I, S, F>,
+}
+
+enum State<I, S, F> {
+    Running {
+        drain: Option<(Signal, Watch)>,
+        spawn_all: SpawnAll<I, S>,
+        signal: F,
+    },
+    Draining(Draining),
+}
+
+impl<I, S, F> Graceful<I, S, F> {
+    pub(super) fn new(spawn_all: SpawnAll<I, S>, signal: F) -> Self {
+        let drain = Some(drain::channel());
+        Graceful {
+            state: State::Running {
+                drain,
+                spawn_all,
+                signal,
+            },
+        }
+    }
+}
+
+impl<I, S, B, F> Future for Graceful<I, S, F>
+where
+    I: Accept,
+    I::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    I::Conn: AsyncRead + AsyncWrite + Send + 'static,
+    S: MakeServiceRef<I::Conn, ReqBody=Body, ResBody=B> + Send + 'static,
+    S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    S::Service: Send,
+    S::Future: Send + 'static,
+    <S::Service as Service>::Future: Send + 'static,
+    B: Payload,
+    F: Future<Item=()>,
+{
+    type Item = ();
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Running {
+                    ref mut drain,
+                    ref mut spawn_all,
+                    ref mut signal,
+                } => match signal.poll() {
+                    // A failed signal shuts down as well.
+                    Ok(Async::Ready(())) | Err(_) => {
+                        debug!("signal received, starting graceful shutdown");
+                        let (signal, _watch) = drain.take().expect("drain channel");
+                        // Dropping `spawn_all` stops accepting on every
+                        // listener.
+                        State::Draining(signal.drain())
+                    },
+                    Ok(Async::NotReady) => {
+                        let watch = &drain.as_ref().expect("drain channel").1;
+                        return spawn_all.poll_watch(Some(watch));
+                    },
+                },
+                State::Draining(ref mut draining) => {
+                    return draining.poll()
+                        .map_err(|()| unreachable!("drain channel error"));
+                },
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<I, S, F> fmt::Debug for Graceful<I, S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Graceful")
+            .finish()
+    }
+}
@@ -1,20 +1,23 @@
 use std::fmt;
 use std::io;
-use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::net::{Ipv6Addr, SocketAddr, TcpListener as StdTcpListener};
 use std::time::{Duration, Instant};
 
 use futures::{Async, Future, Poll, Stream};
+use net2::TcpBuilder;
 use tokio_reactor::Handle;
-use tokio_tcp::TcpListener;
+use tokio_tcp::{TcpListener, TcpStream};
 use tokio_timer::Delay;
 
-use self::addr_stream::AddrStream;
+pub use self::addr_stream::AddrStream;
 
-/// A stream of connections from binding to an address.
+/// A stream of connections from binding to one or more addresses.
 #[must_use = "streams do nothing unless polled"]
 pub struct AddrIncoming {
-    addr: SocketAddr,
-    listener: TcpListener,
+    listeners: Vec<(SocketAddr, TcpListener)>,
+    // Which listener to accept from first, so that a busy listener doesn't
+    // starve the others.
+    next: usize,
     sleep_on_errors: bool,
     tcp_keepalive_timeout: Option<Duration>,
     tcp_nodelay: bool,
@@ -23,30 +26,104 @@ pub struct AddrIncoming {
 
 impl AddrIncoming {
     pub(super) fn new(addr: &SocketAddr, handle: Option<&Handle>) -> ::Result<AddrIncoming> {
-        let listener = if let Some(handle) = handle {
-            let std_listener = StdTcpListener::bind(addr)
-                .map_err(::Error::new_listen)?;
-            TcpListener::from_std(std_listener, handle)
-                .map_err(::Error::new_listen)?
-        } else {
-            TcpListener::bind(addr).map_err(::Error::new_listen)?
+        AddrIncoming::new_all(&[*addr], handle)
+    }
+
+    pub(super) fn new_all(addrs: &[SocketAddr], handle: Option<&Handle>) -> ::Result<AddrIncoming> {
+        if addrs.is_empty() {
+            return Err(::Error::new_listen(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no addresses to bind to",
+            )));
+        }
+
+        let mut listeners = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            let listener = if let Some(handle) = handle {
+                let std_listener = StdTcpListener::bind(addr)
+                    .map_err(::Error::new_listen)?;
+                TcpListener::from_std(std_listener, handle)
+                    .map_err(::Error::new_listen)?
+            } else {
+                TcpListener::bind(addr).map_err(::Error::new_listen)?
+            };
+
+            let addr = listener.local_addr().map_err(::Error::new_listen)?;
+            listeners.push((addr, listener));
+        }
+
+        Ok(AddrIncoming::from_listeners(listeners))
+    }
+
+    pub(super) fn new_only_v6(addr: &SocketAddr, only_v6: bool, handle: Option<&Handle>) -> ::Result<AddrIncoming> {
+        let listener = match *addr {
+            SocketAddr::V6(_) => bind_v6(addr, only_v6),
+            SocketAddr::V4(_) => StdTcpListener::bind(addr),
         };
+        let listener = listener.map_err(::Error::new_listen)?;
+        Ok(AddrIncoming::from_listeners(vec![from_std(listener, handle)?]))
+    }
 
-        let addr = listener.local_addr().map_err(::Error::new_listen)?;
+    pub(super) fn new_dual_stack(port: u16, handle: Option<&Handle>) -> ::Result<AddrIncoming> {
+        let v6 = SocketAddr::from((Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), port));
+        match bind_v6(&v6, false) {
+            Ok(listener) => {
+                return Ok(AddrIncoming::from_listeners(vec![from_std(listener, handle)?]));
+            },
+            Err(e) => {
+                if e.kind() == io::ErrorKind::AddrInUse {
+                    return Err(::Error::new_listen(e));
+                }
+                debug!("couldn't bind dual-stack socket to {}, binding IPv4 separately: {}", v6, e);
+            },
+        }
 
-        Ok(AddrIncoming {
-            addr: addr,
-            listener: listener,
+        // The OS doesn't let an IPv6 socket accept IPv4 connections, or
+        // doesn't have IPv6 at all.
+        let v4 = SocketAddr::from(([0, 0, 0, 0], port));
+        let v4 = StdTcpListener::bind(v4).map_err(::Error::new_listen)?;
+        let v4 = from_std(v4, handle)?;
+        // With a port of `0`, both listen on the port picked for IPv4.
+        let v6 = SocketAddr::from((Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), v4.0.port()));
+        let mut listeners = vec![v4];
+        match bind_v6(&v6, true) {
+            Ok(listener) => listeners.push(from_std(listener, handle)?),
+            Err(e) => debug!("couldn't bind {}, listening on IPv4 only: {}", v6, e),
+        }
+        Ok(AddrIncoming::from_listeners(listeners))
+    }
+
+    pub(super) fn from_std(listener: StdTcpListener, handle: Option<&Handle>) -> ::Result<AddrIncoming> {
+        Ok(AddrIncoming::from_listeners(vec![from_std(listener, handle)?]))
+    }
+
+    fn from_listeners(listeners: Vec<(SocketAddr, TcpListener)>) -> AddrIncoming {
+        AddrIncoming {
+            listeners: listeners,
+            next: 0,
             sleep_on_errors: true,
             tcp_keepalive_timeout: None,
             tcp_nodelay: false,
             timeout: None,
-        })
+        }
     }
 
     /// Get the local address bound to this listener.
+    ///
+    /// If bound to several addresses, this is the first one.
     pub fn local_addr(&self) -> SocketAddr {
-        self.addr
+        self.listeners[0].0
+    }
+
+    /// Get all the local addresses bound to this listener.
+    ///
+    /// These are the actual addresses, so a port of `0` was replaced with
+    /// the port that was picked by the OS.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .map(|&(addr, _)| addr)
+            .collect()
     }
 
     /// Set whether TCP keepalive messages are enabled on accepted connections.
@@ -85,6 +162,29 @@ impl AddrIncoming {
     }
 }
 
+// Bind an IPv6 socket with `IPV6_V6ONLY` set as asked, instead of left to
+// the OS default.
+fn bind_v6(addr: &SocketAddr, only_v6: bool) -> io::Result<StdTcpListener> {
+    let builder = TcpBuilder::new_v6()?;
+    builder.only_v6(only_v6)?;
+    // Like `std::net::TcpListener::bind`.
+    if cfg!(unix) {
+        builder.reuse_address(true)?;
+    }
+    builder.bind(addr)?;
+    builder.listen(128)
+}
+
+fn from_std(listener: StdTcpListener, handle: Option<&Handle>) -> ::Result<(SocketAddr, TcpListener)> {
+    let listener = match handle {
+        Some(handle) => TcpListener::from_std(listener, handle),
+        None => TcpListener::from_std(listener, &Handle::default()),
+    };
+    let listener = listener.map_err(::Error::new_listen)?;
+    let addr = listener.local_addr().map_err(::Error::new_listen)?;
+    Ok((addr, listener))
+}
+
 impl Stream for AddrIncoming {
     // currently unnameable...
     type Item = AddrStream;
@@ -103,7 +203,7 @@ impl Stream for AddrIncoming {
         }
         self.timeout = None;
         loop {
-            match self.listener.poll_accept() {
+            match self.poll_accept_any() {
                 Ok(Async::Ready((socket, addr))) => {
                     if let Some(dur) = self.tcp_keepalive_timeout {
                         if let Err(e) = socket.set_keepalive(Some(dur)) {
@@ -153,6 +253,20 @@ impl Stream for AddrIncoming {
     }
 }
 
+impl AddrIncoming {
+    fn poll_accept_any(&mut self) -> Poll<(TcpStream, SocketAddr), io::Error> {
+        let len = self.listeners.len();
+        for i in 0..len {
+            let idx = (self.next + i) % len;
+            if let Async::Ready(accepted) = self.listeners[idx].1.poll_accept()? {
+                self.next = (idx + 1) % len;
+                return Ok(Async::Ready(accepted));
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+
 /// This function defines errors that are per-connection. Which basically
 /// means that if we get this error from `accept()` system call it means
 /// next connection might be ready to be accepted.
@@ -169,7 +283,7 @@ fn is_connection_error(e: &io::Error) -> bool {
 impl fmt::Debug for AddrIncoming {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("AddrIncoming")
-            .field("addr", &self.addr)
+            .field("addrs", &self.local_addrs())
             .field("sleep_on_errors", &self.sleep_on_errors)
             .field("tcp_keepalive_timeout", &self.tcp_keepalive_timeout)
             .field("tcp_nodelay", &self.tcp_nodelay)
@@ -186,6 +300,7 @@ mod addr_stream {
     use tokio_io::{AsyncRead, AsyncWrite};
 
 
+    /// A TCP connection accepted by an `AddrIncoming`.
     #[derive(Debug)]
     pub struct AddrStream {
         inner: TcpStream,
@@ -199,6 +314,12 @@ mod addr_stream {
                 remote_addr: addr,
             }
         }
+
+        /// Returns the remote (peer) address of this connection.
+        #[inline]
+        pub fn remote_addr(&self) -> SocketAddr {
+            self.remote_addr
+        }
     }
 
     impl Read for AddrStream {
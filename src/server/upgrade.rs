@@ -0,0 +1,55 @@
+use std::fmt;
+use std::sync::Arc;
+
+use http::HeaderMap;
+
+/// Why a server connection didn't send a `101 Switching Protocols` response
+/// of its `Service`, or a `2xx` response to a `CONNECT` request when
+/// upgrades are refused.
+///
+/// An HTTP/1 connection can only be upgraded when whoever polls it can take
+/// the IO back afterwards. Otherwise the `101` is replaced with a
+/// `500 Internal Server Error`, and the hook set with
+/// [`Http::http1_on_refused_upgrade`](::server::conn::Http::http1_on_refused_upgrade)
+/// is told why.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpgradeRefusal {
+    /// The connection was polled as a `Future`, instead of with
+    /// `without_shutdown` or `poll_without_shutdown`, so nothing could take
+    /// the upgraded IO.
+    ///
+    /// The connection is closed once the `500` is written, since the client
+    /// may already be sending bytes of the other protocol.
+    Unclaimed,
+    /// Upgrades are refused with
+    /// [`Http::http1_refuse_upgrades`](::server::conn::Http::http1_refuse_upgrades).
+    ///
+    /// The connection goes on serving requests.
+    Refused,
+}
+
+// The hook set with `Http::http1_on_refused_upgrade`.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "http1"), allow(dead_code))]
+pub(crate) struct OnRefusedUpgrade(Arc<Fn(UpgradeRefusal, &HeaderMap) + Send + Sync>);
+
+impl OnRefusedUpgrade {
+    pub(crate) fn new<F>(f: F) -> OnRefusedUpgrade
+    where
+        F: Fn(UpgradeRefusal, &HeaderMap) + Send + Sync + 'static,
+    {
+        OnRefusedUpgrade(Arc::new(f))
+    }
+
+    #[cfg(feature = "http1")]
+    pub(crate) fn call(&self, refusal: UpgradeRefusal, headers: &HeaderMap) {
+        (self.0)(refusal, headers)
+    }
+}
+
+impl fmt::Debug for OnRefusedUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OnRefusedUpgrade")
+            .finish()
+    }
+}
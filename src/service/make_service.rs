@@ -0,0 +1,186 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use futures::{Future, IntoFuture};
+
+use body::Payload;
+use super::{NewService, Service};
+
+/// An asynchronous constructor of `Service`s, given some context.
+///
+/// Unlike a `NewService`, a `MakeService` is told what the `Service` is made
+/// for. A [`Server`](::Server) makes a `Service` for each connection it
+/// accepts, given a reference to the connection's IO, such as an
+/// [`AddrStream`](::server::conn::AddrStream).
+///
+/// Any `NewService` is also a `MakeService`, which ignores the context.
+pub trait MakeService<Ctx> {
+    /// The `Payload` body of the `http::Request`.
+    type ReqBody: Payload;
+
+    /// The `Payload` body of the `http::Response`.
+    type ResBody: Payload;
+
+    /// The error type that can be returned by `Service`s.
+    type Error: Into<Box<StdError + Send + Sync>>;
+
+    /// The resolved `Service` from `make_service()`.
+    type Service: Service<
+        ReqBody=Self::ReqBody,
+        ResBody=Self::ResBody,
+        Error=Self::Error,
+    >;
+
+    /// The future returned from `make_service` of a `Service`.
+    type Future: Future<Item=Self::Service, Error=Self::MakeError>;
+
+    /// The error type that can be returned when creating a new `Service`.
+    type MakeError: Into<Box<StdError + Send + Sync>>;
+
+    /// Create a new `Service`, for the given context.
+    fn make_service(&mut self, ctx: Ctx) -> Self::Future;
+}
+
+impl<N, Ctx> MakeService<Ctx> for N
+where
+    N: NewService,
+{
+    type ReqBody = N::ReqBody;
+    type ResBody = N::ResBody;
+    type Error = N::Error;
+    type Service = N::Service;
+    type Future = N::Future;
+    type MakeError = N::InitError;
+
+    fn make_service(&mut self, _ctx: Ctx) -> Self::Future {
+        self.new_service()
+    }
+}
+
+/// A `MakeService` given a reference to its context, of any lifetime.
+///
+/// This is implemented for every type that is a `MakeService<&Ctx>`, and is
+/// what a `Server` needs to make a `Service` for each connection.
+pub trait MakeServiceRef<Ctx> {
+    /// The `Payload` body of the `http::Request`.
+    type ReqBody: Payload;
+
+    /// The `Payload` body of the `http::Response`.
+    type ResBody: Payload;
+
+    /// The error type that can be returned by `Service`s.
+    type Error: Into<Box<StdError + Send + Sync>>;
+
+    /// The resolved `Service` from `make_service_ref()`.
+    type Service: Service<
+        ReqBody=Self::ReqBody,
+        ResBody=Self::ResBody,
+        Error=Self::Error,
+    >;
+
+    /// The future returned from `make_service_ref` of a `Service`.
+    type Future: Future<Item=Self::Service, Error=Self::MakeError>;
+
+    /// The error type that can be returned when creating a new `Service`.
+    type MakeError: Into<Box<StdError + Send + Sync>>;
+
+    /// Create a new `Service`, for the given context.
+    fn make_service_ref(&mut self, ctx: &Ctx) -> Self::Future;
+}
+
+impl<T, Ctx, E, ME, S, F, IB, OB> MakeServiceRef<Ctx> for T
+where
+    T: for<'a> MakeService<&'a Ctx, Error=E, MakeError=ME, Service=S, Future=F, ReqBody=IB, ResBody=OB>,
+    E: Into<Box<StdError + Send + Sync>>,
+    ME: Into<Box<StdError + Send + Sync>>,
+    S: Service<ReqBody=IB, ResBody=OB, Error=E>,
+    F: Future<Item=S, Error=ME>,
+    IB: Payload,
+    OB: Payload,
+{
+    type ReqBody = IB;
+    type ResBody = OB;
+    type Error = E;
+    type Service = S;
+    type Future = F;
+    type MakeError = ME;
+
+    fn make_service_ref(&mut self, ctx: &Ctx) -> Self::Future {
+        self.make_service(ctx)
+    }
+}
+
+/// Create a `MakeService` from a function, given a reference to the context.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate hyper;
+/// # #[cfg(all(feature = "runtime", feature = "server", feature = "http1"))]
+/// # fn main() {
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use hyper::{Body, Response, Server};
+/// use hyper::server::conn::AddrStream;
+/// use hyper::service::{make_service_fn, service_fn_ok};
+///
+/// let hits = Arc::new(AtomicUsize::new(0));
+///
+/// let make_svc = make_service_fn(move |conn: &AddrStream| {
+///     let remote = conn.remote_addr();
+///     let hits = hits.clone();
+///     service_fn_ok(move |_req| {
+///         let n = hits.fetch_add(1, Ordering::Relaxed);
+///         Response::new(Body::from(format!("hello {}, you are visitor {}", remote, n)))
+///     })
+/// });
+///
+/// let addr = ([127, 0, 0, 1], 3000).into();
+/// let server = Server::bind(&addr).serve(make_svc);
+/// # drop(server);
+/// # }
+/// # #[cfg(not(all(feature = "runtime", feature = "server", feature = "http1")))]
+/// # fn main() {}
+/// ```
+pub fn make_service_fn<F, Target, Ret>(f: F) -> MakeServiceFn<F>
+where
+    F: Fn(&Target) -> Ret,
+    Ret: IntoFuture,
+{
+    MakeServiceFn {
+        f,
+    }
+}
+
+// Not exported from crate as this will likely be replaced with `impl Service`.
+pub struct MakeServiceFn<F> {
+    f: F,
+}
+
+impl<'c, F, Target, Ret, ReqBody, ResBody> MakeService<&'c Target> for MakeServiceFn<F>
+where
+    F: Fn(&Target) -> Ret,
+    Ret: IntoFuture,
+    Ret::Item: Service<ReqBody=ReqBody, ResBody=ResBody>,
+    Ret::Error: Into<Box<StdError + Send + Sync>>,
+    ReqBody: Payload,
+    ResBody: Payload,
+{
+    type ReqBody = ReqBody;
+    type ResBody = ResBody;
+    type Error = <Ret::Item as Service>::Error;
+    type Service = Ret::Item;
+    type Future = Ret::Future;
+    type MakeError = Ret::Error;
+
+    fn make_service(&mut self, target: &'c Target) -> Self::Future {
+        (self.f)(target).into_future()
+    }
+}
+
+impl<F> fmt::Debug for MakeServiceFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MakeServiceFn")
+            .finish()
+    }
+}
@@ -28,8 +28,17 @@
 //! Resources that need to be shared by all `Service`s can be put into a
 //! `NewService`, and then passed to individual `Service`s when `new_service`
 //! is called.
+//!
+//! # MakeService
+//!
+//! A [`MakeService`](MakeService) is like a `NewService`, but is also given
+//! some context, such as the connection a `Service` is made for. The helper
+//! [`make_service_fn`](make_service_fn) creates one from a function, given
+//! a reference to the context.
+mod make_service;
 mod new_service;
 mod service;
 
+pub use self::make_service::{make_service_fn, MakeService, MakeServiceRef};
 pub use self::new_service::{NewService};
 pub use self::service::{service_fn, service_fn_ok, Service};
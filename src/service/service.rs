@@ -1,8 +1,9 @@
 use std::error::Error as StdError;
 use std::fmt;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
-use futures::{future, Future, IntoFuture};
+use futures::{future, Async, Future, IntoFuture, Poll};
 
 use body::Payload;
 use common::Never;
@@ -28,6 +29,81 @@ pub trait Service {
 
     /// Calls this `Service` with a request, returning a `Future` of the response.
     fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future;
+
+    /// Change each request with a function, before this `Service` is called.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use hyper::{Body, Request, Response};
+    /// use hyper::service::{service_fn_ok, Service};
+    ///
+    /// let service = service_fn_ok(|req: Request<Body>| {
+    ///     Response::new(Body::from(req.uri().path().to_owned()))
+    /// }).map_request(|mut req: Request<Body>| {
+    ///     *req.uri_mut() = "/rewritten".parse().unwrap();
+    ///     req
+    /// });
+    /// # drop(service);
+    /// ```
+    fn map_request<F, R>(self, f: F) -> MapRequest<Self, F, R>
+    where
+        Self: Sized,
+        F: Fn(Request<R>) -> Request<Self::ReqBody>,
+        R: Payload,
+    {
+        MapRequest {
+            service: self,
+            f,
+            _req: PhantomData,
+        }
+    }
+
+    /// Change each response of this `Service` with a function.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use hyper::{Body, Request, Response};
+    /// use hyper::header::HeaderValue;
+    /// use hyper::service::{service_fn_ok, Service};
+    ///
+    /// let service = service_fn_ok(|_req: Request<Body>| {
+    ///     Response::new(Body::from("Hello World"))
+    /// }).map_response(|mut res| {
+    ///     res.headers_mut().insert("server", HeaderValue::from_static("hyper"));
+    ///     res
+    /// });
+    /// # drop(service);
+    /// ```
+    fn map_response<F, B>(self, f: F) -> MapResponse<Self, F>
+    where
+        Self: Sized,
+        F: Fn(Response<Self::ResBody>) -> Response<B>,
+        B: Payload,
+    {
+        MapResponse {
+            service: self,
+            f: Arc::new(f),
+        }
+    }
+
+    /// Continue with a future, once this `Service` has a response.
+    ///
+    /// The function is called with each response, and the response of the
+    /// returned future is used instead.
+    fn and_then<F, Ret, B>(self, f: F) -> AndThen<Self, F>
+    where
+        Self: Sized,
+        F: Fn(Response<Self::ResBody>) -> Ret,
+        Ret: IntoFuture<Item=Response<B>, Error=Self::Error>,
+        B: Payload,
+    {
+        AndThen {
+            service: self,
+            f: Arc::new(f),
+        }
+    }
 }
 
 
@@ -163,3 +239,205 @@ impl<F, R> fmt::Debug for ServiceFnOk<F, R> {
             .finish()
     }
 }
+
+/// A `Service` changing its requests, see `Service::map_request`.
+pub struct MapRequest<S, F, R> {
+    service: S,
+    f: F,
+    _req: PhantomData<fn(R)>,
+}
+
+impl<S, F, R> Service for MapRequest<S, F, R>
+where
+    S: Service,
+    F: Fn(Request<R>) -> Request<S::ReqBody>,
+    R: Payload,
+{
+    type ReqBody = R;
+    type ResBody = S::ResBody;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
+        let req = (self.f)(req);
+        self.service.call(req)
+    }
+}
+
+impl<S, F, R> IntoFuture for MapRequest<S, F, R> {
+    type Future = future::FutureResult<Self::Item, Self::Error>;
+    type Item = Self;
+    type Error = Never;
+
+    fn into_future(self) -> Self::Future {
+        future::ok(self)
+    }
+}
+
+impl<S: fmt::Debug, F, R> fmt::Debug for MapRequest<S, F, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MapRequest")
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+/// A `Service` changing its responses, see `Service::map_response`.
+pub struct MapResponse<S, F> {
+    service: S,
+    f: Arc<F>,
+}
+
+impl<S, F, B> Service for MapResponse<S, F>
+where
+    S: Service,
+    F: Fn(Response<S::ResBody>) -> Response<B>,
+    B: Payload,
+{
+    type ReqBody = S::ReqBody;
+    type ResBody = B;
+    type Error = S::Error;
+    type Future = MapResponseFuture<S::Future, F>;
+
+    fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
+        MapResponseFuture {
+            future: self.service.call(req),
+            f: self.f.clone(),
+        }
+    }
+}
+
+impl<S, F> IntoFuture for MapResponse<S, F> {
+    type Future = future::FutureResult<Self::Item, Self::Error>;
+    type Item = Self;
+    type Error = Never;
+
+    fn into_future(self) -> Self::Future {
+        future::ok(self)
+    }
+}
+
+impl<S: fmt::Debug, F> fmt::Debug for MapResponse<S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MapResponse")
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+/// The `Future` of a `MapResponse` service.
+#[must_use = "futures do nothing unless polled"]
+pub struct MapResponseFuture<T, F> {
+    future: T,
+    f: Arc<F>,
+}
+
+impl<T, F, A, B> Future for MapResponseFuture<T, F>
+where
+    T: Future<Item=Response<A>>,
+    F: Fn(Response<A>) -> Response<B>,
+{
+    type Item = Response<B>;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let res = try_ready!(self.future.poll());
+        Ok(Async::Ready((self.f)(res)))
+    }
+}
+
+impl<T, F> fmt::Debug for MapResponseFuture<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Future<Response>")
+    }
+}
+
+/// A `Service` continuing with a future after each response, see
+/// `Service::and_then`.
+pub struct AndThen<S, F> {
+    service: S,
+    f: Arc<F>,
+}
+
+impl<S, F, Ret, B> Service for AndThen<S, F>
+where
+    S: Service,
+    F: Fn(Response<S::ResBody>) -> Ret,
+    Ret: IntoFuture<Item=Response<B>, Error=S::Error>,
+    B: Payload,
+{
+    type ReqBody = S::ReqBody;
+    type ResBody = B;
+    type Error = S::Error;
+    type Future = AndThenFuture<S::Future, F, Ret>;
+
+    fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
+        AndThenFuture {
+            state: AndThenState::First(self.service.call(req), self.f.clone()),
+        }
+    }
+}
+
+impl<S, F> IntoFuture for AndThen<S, F> {
+    type Future = future::FutureResult<Self::Item, Self::Error>;
+    type Item = Self;
+    type Error = Never;
+
+    fn into_future(self) -> Self::Future {
+        future::ok(self)
+    }
+}
+
+impl<S: fmt::Debug, F> fmt::Debug for AndThen<S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AndThen")
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+/// The `Future` of an `AndThen` service.
+#[must_use = "futures do nothing unless polled"]
+pub struct AndThenFuture<T, F, R>
+where
+    R: IntoFuture,
+{
+    state: AndThenState<T, F, R::Future>,
+}
+
+enum AndThenState<T, F, U> {
+    First(T, Arc<F>),
+    Second(U),
+}
+
+impl<T, F, R, A, B> Future for AndThenFuture<T, F, R>
+where
+    T: Future<Item=Response<A>>,
+    F: Fn(Response<A>) -> R,
+    R: IntoFuture<Item=Response<B>, Error=T::Error>,
+{
+    type Item = Response<B>;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                AndThenState::First(ref mut future, ref f) => {
+                    let res = try_ready!(future.poll());
+                    f(res).into_future()
+                },
+                AndThenState::Second(ref mut future) => return future.poll(),
+            };
+            self.state = AndThenState::Second(next);
+        }
+    }
+}
+
+impl<T, F, R> fmt::Debug for AndThenFuture<T, F, R>
+where
+    R: IntoFuture,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Future<Response>")
+    }
+}
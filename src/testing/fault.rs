@@ -0,0 +1,526 @@
+use std::cmp;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll, Stream};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Delay;
+
+#[cfg(feature = "client")] use client::connect::{Connect, Connected, Destination};
+
+/// Faults to inject into connections, for testing how an application copes
+/// with a bad network, or a bad peer.
+///
+/// Whether a read or write fails or is corrupted is decided by a random
+/// number generator seeded with the given seed, and the order of the
+/// connection among those wrapped by this `Faults` (and its clones), so
+/// the same seed injects the same faults into the same exchanges.
+///
+/// The faults only happen to the connections wrapped with it:
+///
+/// - on a `Client`, with [`Faults::connector`](Faults::connector), or
+///   [`Builder::build_with_faults`](::client::Builder::build_with_faults);
+/// - on a `Server`, with [`Faults::incoming`](Faults::incoming), or
+///   [`Builder::inject_faults`](::server::Builder::inject_faults);
+/// - or any other IO, with [`Faults::wrap`](Faults::wrap).
+///
+/// Delays are timed with the timer of the `runtime`.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// # use std::time::Duration;
+/// use hyper::Client;
+/// use hyper::client::HttpConnector;
+/// use hyper::testing::Faults;
+///
+/// # fn main() {
+/// let faults = Faults::new(42)
+///     .read_delay(Duration::from_millis(10))
+///     .reset(0.01);
+///
+/// let client = Client::builder()
+///     .build_with_faults::<_, hyper::Body>(HttpConnector::new(1), faults);
+/// # drop(client);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Faults {
+    config: Config,
+    // How many connections were wrapped, to seed each differently.
+    connections: Arc<AtomicUsize>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Config {
+    seed: u64,
+    read_delay: Option<Duration>,
+    write_delay: Option<Duration>,
+    truncate_after: Option<u64>,
+    reset: f64,
+    corrupt_framing: f64,
+}
+
+/// An IO with [`Faults`](Faults) injected into its reads and writes.
+pub struct FaultyIo<T> {
+    io: T,
+    config: Config,
+    rng: Rng,
+    read: u64,
+    written: u64,
+    is_reset: bool,
+    read_delay: DelayState,
+    write_delay: DelayState,
+    // The fate of the write being attempted, decided once so a write that
+    // has to be retried isn't decided again: whether to reset, and to
+    // corrupt.
+    write_fate: Option<(bool, bool)>,
+}
+
+/// A `Connect` whose connections have [`Faults`](Faults) injected.
+#[cfg(feature = "client")]
+#[derive(Debug)]
+pub struct FaultyConnector<C> {
+    connector: C,
+    faults: Faults,
+}
+
+/// A future connecting a [`FaultyIo`](FaultyIo).
+#[cfg(feature = "client")]
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct FaultyConnecting<F> {
+    future: F,
+    faults: Faults,
+}
+
+/// A stream of incoming connections with [`Faults`](Faults) injected.
+#[must_use = "streams do nothing unless polled"]
+#[derive(Debug)]
+pub struct FaultyIncoming<I> {
+    incoming: I,
+    faults: Faults,
+}
+
+enum DelayState {
+    // A delay is to be waited before the next operation.
+    Idle,
+    Waiting(Delay),
+    // The delay was waited, the operation can go on.
+    Passed,
+}
+
+// xorshift64*, which is plenty for picking faults.
+struct Rng(u64);
+
+// ===== impl Faults =====
+
+impl Faults {
+    /// Create `Faults` that inject nothing, yet, picking faults with this
+    /// seed.
+    pub fn new(seed: u64) -> Faults {
+        Faults {
+            config: Config {
+                seed,
+                read_delay: None,
+                write_delay: None,
+                truncate_after: None,
+                reset: 0.0,
+                corrupt_framing: 0.0,
+            },
+            connections: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Wait this long before each read.
+    pub fn read_delay(mut self, delay: Duration) -> Faults {
+        self.config.read_delay = Some(delay);
+        self
+    }
+
+    /// Wait this long before each write.
+    pub fn write_delay(mut self, delay: Duration) -> Faults {
+        self.config.write_delay = Some(delay);
+        self
+    }
+
+    /// Cut each direction of a connection short after this many bytes.
+    ///
+    /// Reads see the end of the stream once this many bytes were read, and
+    /// writes fail with `BrokenPipe` once this many bytes were written.
+    pub fn truncate_after(mut self, bytes: u64) -> Faults {
+        self.config.truncate_after = Some(bytes);
+        self
+    }
+
+    /// Reset a connection on each read or write with this probability,
+    /// from 0 to 1.
+    ///
+    /// The read or write, and every one after it, fails with
+    /// `ConnectionReset`. The peer sees the connection close once it's
+    /// dropped.
+    pub fn reset(mut self, probability: f64) -> Faults {
+        self.config.reset = probability;
+        self
+    }
+
+    /// Corrupt each read or write with this probability, from 0 to 1.
+    ///
+    /// One `CR` or `LF` of the bytes read or written, if there is any, is
+    /// replaced with another byte, breaking the framing of HTTP/1 message
+    /// heads and chunked bodies.
+    pub fn corrupt_framing(mut self, probability: f64) -> Faults {
+        self.config.corrupt_framing = probability;
+        self
+    }
+
+    /// Inject these faults into an IO.
+    pub fn wrap<T>(&self, io: T) -> FaultyIo<T> {
+        let n = self.connections.fetch_add(1, Ordering::SeqCst) as u64;
+        trace!("injecting faults into connection {}", n);
+        FaultyIo {
+            io,
+            config: self.config,
+            rng: Rng::new(self.config.seed ^ n.wrapping_mul(0x9E37_79B9_7F4A_7C15)),
+            read: 0,
+            written: 0,
+            is_reset: false,
+            read_delay: DelayState::Idle,
+            write_delay: DelayState::Idle,
+            write_fate: None,
+        }
+    }
+
+    /// Inject these faults into the connections of a `Connect`.
+    #[cfg(feature = "client")]
+    pub fn connector<C>(&self, connector: C) -> FaultyConnector<C> {
+        FaultyConnector {
+            connector,
+            faults: self.clone(),
+        }
+    }
+
+    /// Inject these faults into a stream of incoming connections.
+    pub fn incoming<I>(&self, incoming: I) -> FaultyIncoming<I> {
+        FaultyIncoming {
+            incoming,
+            faults: self.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for Faults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Faults")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+// ===== impl FaultyIo =====
+
+impl<T> FaultyIo<T> {
+    /// Get a reference to the wrapped IO.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Unwrap the IO, leaving the faults behind.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+}
+
+impl<T: Read> Read for FaultyIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.is_reset {
+            return Err(reset_error());
+        }
+        poll_delay(&mut self.read_delay, self.config.read_delay)?;
+        let mut max = buf.len();
+        if let Some(limit) = self.config.truncate_after {
+            let left = limit.saturating_sub(self.read);
+            if left == 0 {
+                trace!("faulty io: read truncated");
+                return Ok(0);
+            }
+            max = cmp::min(max, left as usize);
+        }
+        let n = self.io.read(&mut buf[..max])?;
+        self.read_delay = DelayState::Idle;
+        if n == 0 {
+            return Ok(0);
+        }
+        self.read += n as u64;
+        if self.rng.chance(self.config.reset) {
+            trace!("faulty io: reset on read");
+            self.is_reset = true;
+            return Err(reset_error());
+        }
+        if self.rng.chance(self.config.corrupt_framing) {
+            corrupt(&mut buf[..n], &mut self.rng);
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for FaultyIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.is_reset {
+            return Err(reset_error());
+        }
+        poll_delay(&mut self.write_delay, self.config.write_delay)?;
+        let mut len = buf.len();
+        if let Some(limit) = self.config.truncate_after {
+            let left = limit.saturating_sub(self.written);
+            if left == 0 {
+                trace!("faulty io: write truncated");
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "connection truncated by faults"));
+            }
+            len = cmp::min(len, left as usize);
+        }
+        let (reset, corrupted) = match self.write_fate {
+            Some(fate) => fate,
+            None => {
+                let fate = (self.rng.chance(self.config.reset), self.rng.chance(self.config.corrupt_framing));
+                self.write_fate = Some(fate);
+                fate
+            }
+        };
+        if reset {
+            trace!("faulty io: reset on write");
+            self.write_fate = None;
+            self.is_reset = true;
+            return Err(reset_error());
+        }
+        let n = if corrupted {
+            let mut bytes = buf[..len].to_vec();
+            corrupt(&mut bytes, &mut self.rng);
+            self.io.write(&bytes)?
+        } else {
+            self.io.write(&buf[..len])?
+        };
+        self.write_fate = None;
+        self.write_delay = DelayState::Idle;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.is_reset {
+            return Err(reset_error());
+        }
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for FaultyIo<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for FaultyIo<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+impl<T> fmt::Debug for FaultyIo<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FaultyIo")
+            .field("read", &self.read)
+            .field("written", &self.written)
+            .field("is_reset", &self.is_reset)
+            .finish()
+    }
+}
+
+// Waits the delay before an operation, if there is one, returning
+// `WouldBlock` until it's over.
+fn poll_delay(state: &mut DelayState, delay: Option<Duration>) -> io::Result<()> {
+    let delay = match delay {
+        Some(delay) => delay,
+        None => return Ok(()),
+    };
+    loop {
+        let next = match *state {
+            DelayState::Idle => DelayState::Waiting(Delay::new(Instant::now() + delay)),
+            DelayState::Waiting(ref mut wait) => match wait.poll() {
+                Ok(Async::Ready(())) => DelayState::Passed,
+                Ok(Async::NotReady) => return Err(io::ErrorKind::WouldBlock.into()),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+            },
+            DelayState::Passed => return Ok(()),
+        };
+        *state = next;
+    }
+}
+
+fn reset_error() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "connection reset by faults")
+}
+
+fn corrupt(bytes: &mut [u8], rng: &mut Rng) {
+    let framing = bytes
+        .iter()
+        .filter(|&&b| b == b'\r' || b == b'\n')
+        .count();
+    if framing == 0 {
+        return;
+    }
+    let nth = (rng.next() % framing as u64) as usize;
+    let pos = bytes
+        .iter()
+        .enumerate()
+        .filter(|&(_, &b)| b == b'\r' || b == b'\n')
+        .nth(nth)
+        .map(|(pos, _)| pos)
+        .expect("nth framing byte");
+    trace!("faulty io: corrupting byte {}", pos);
+    bytes[pos] = b'!';
+}
+
+// ===== impl FaultyConnector =====
+
+#[cfg(feature = "client")]
+impl<C> Connect for FaultyConnector<C>
+where
+    C: Connect,
+{
+    type Transport = FaultyIo<C::Transport>;
+    type Error = C::Error;
+    type Future = FaultyConnecting<C::Future>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        FaultyConnecting {
+            future: self.connector.connect(dst),
+            faults: self.faults.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+impl<F, T> Future for FaultyConnecting<F>
+where
+    F: Future<Item=(T, Connected)>,
+{
+    type Item = (FaultyIo<T>, Connected);
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (io, connected) = try_ready!(self.future.poll());
+        Ok(Async::Ready((self.faults.wrap(io), connected)))
+    }
+}
+
+// ===== impl FaultyIncoming =====
+
+impl<I> Stream for FaultyIncoming<I>
+where
+    I: Stream,
+{
+    type Item = FaultyIo<I::Item>;
+    type Error = I::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let io = try_ready!(self.incoming.poll());
+        Ok(Async::Ready(io.map(|io| self.faults.wrap(io))))
+    }
+}
+
+// ===== impl Rng =====
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // Mixed with splitmix64 first, so that seeds close together, like
+        // 1 and 2, still pick different faults from the start.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        // The state of xorshift can't be zero.
+        Rng(if z == 0 { 0x2545_F491_4F6C_DD1D } else { z })
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        let sample = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        sample < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use super::Faults;
+
+    #[test]
+    fn truncate_after() {
+        let faults = Faults::new(1).truncate_after(4);
+        let mut io = faults.wrap(Cursor::new(b"hello world".to_vec()));
+        let mut buf = Vec::new();
+        io.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"hell");
+
+        let mut io = faults.wrap(Vec::new());
+        assert_eq!(io.write(b"hello").unwrap(), 4);
+        assert_eq!(io.write(b"o").unwrap_err().kind(), ::std::io::ErrorKind::BrokenPipe);
+        assert_eq!(io.into_inner(), b"hell");
+    }
+
+    #[test]
+    fn reset_and_corrupt_are_seeded() {
+        fn run(seed: u64) -> (Vec<u8>, usize) {
+            let faults = Faults::new(seed).corrupt_framing(0.5).reset(0.1);
+            let mut io = faults.wrap(Vec::new());
+            let mut writes = 0;
+            while writes < 100 && io.write(b"3\r\nabc\r\n").is_ok() {
+                writes += 1;
+            }
+            (io.into_inner(), writes)
+        }
+
+        let (written, writes) = run(3);
+        assert_eq!(run(3), (written.clone(), writes));
+        assert!(writes < 100, "a reset was injected");
+        assert!(written.contains(&b'!'), "framing was corrupted");
+        assert_ne!(run(4), (written, writes));
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn client_truncated_response() {
+        use futures::{Future, Stream};
+        use http::StatusCode;
+        use tokio::runtime::Runtime;
+
+        use client::{Client, HttpConnector};
+        use testing::{expect_request, MockServer, Reply};
+
+        let server = MockServer::start(vec![
+            expect_request().respond_with(Reply::new(StatusCode::OK).body(vec![b'x'; 100])),
+        ]);
+        let client = Client::builder()
+            .build_with_faults::<_, ::Body>(HttpConnector::new(1), Faults::new(1).truncate_after(60));
+
+        let mut rt = Runtime::new().unwrap();
+        let res = rt.block_on(client.get(server.uri("/")).and_then(|res| res.into_body().concat2()));
+        res.expect_err("truncated body");
+        server.verify();
+    }
+}
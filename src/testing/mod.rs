@@ -0,0 +1,618 @@
+//! Utilities for testing HTTP clients and servers.
+//!
+//! A [`MockServer`](MockServer) is an HTTP/1 server following a script: it
+//! expects a sequence of requests, checks each one it receives against the
+//! next [`Expectation`](Expectation), and answers with its scripted
+//! [`Reply`](Reply). Replies can be any bytes at all, so a client can be
+//! tested against responses that well-behaved servers never send, like a
+//! wrong `Content-Length`, or a connection closed mid-response.
+//!
+//! [`Faults`](Faults) inject delays, resets, and corruption into the
+//! connections of a `Client` or `Server`, to test how an application copes
+//! with them.
+//!
+//! This module is only available with the `testing` feature.
+//!
+//! # Example
+//!
+//! ```
+//! # extern crate hyper;
+//! use hyper::{Client, Method, StatusCode};
+//! use hyper::rt::{self, Future, Stream};
+//! use hyper::testing::{expect_request, MockServer, Reply};
+//!
+//! # fn main() {
+//! let server = MockServer::start(vec![
+//!     expect_request()
+//!         .method(Method::GET)
+//!         .path("/hello")
+//!         .respond_with(Reply::new(StatusCode::OK).body("world")),
+//! ]);
+//!
+//! let uri = server.uri("/hello");
+//! rt::run(rt::lazy(move || {
+//!     Client::new()
+//!         .get(uri)
+//!         .and_then(|res| res.into_body().concat2())
+//!         .map(|body| assert_eq!(body.as_ref(), b"world"))
+//!         .map_err(|err| panic!("request failed: {}", err))
+//! }));
+//!
+//! server.verify();
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use bytes::Bytes;
+use http::{Method, StatusCode, Uri};
+use httparse;
+
+#[cfg(feature = "client")] pub use self::fault::FaultyConnecting;
+#[cfg(feature = "client")] pub use self::fault::FaultyConnector;
+pub use self::fault::{Faults, FaultyIncoming, FaultyIo};
+
+mod fault;
+
+// The most headers a request to a `MockServer` may have.
+const MAX_HEADERS: usize = 100;
+
+/// Start an [`Expectation`](Expectation) of a request.
+///
+/// Without any conditions, it matches any request, and without a reply, it
+/// is answered with an empty `200 OK`.
+pub fn expect_request() -> Expectation {
+    Expectation {
+        method: None,
+        path: None,
+        headers: Vec::new(),
+        body: None,
+        reply: Reply::new(StatusCode::OK),
+    }
+}
+
+/// A request a [`MockServer`](MockServer) expects, and how it replies.
+///
+/// Created with [`expect_request`](expect_request).
+#[derive(Clone, Debug)]
+pub struct Expectation {
+    method: Option<Method>,
+    path: Option<String>,
+    headers: Vec<(String, String)>,
+    body: Option<Bytes>,
+    reply: Reply,
+}
+
+/// What a [`MockServer`](MockServer) writes back for a request.
+#[derive(Clone, Debug)]
+pub struct Reply {
+    kind: ReplyKind,
+    close: bool,
+}
+
+#[derive(Clone, Debug)]
+enum ReplyKind {
+    Response {
+        status: StatusCode,
+        headers: Vec<(String, String)>,
+        body: Bytes,
+    },
+    Raw(Bytes),
+}
+
+/// An HTTP/1 server answering a script of requests, for testing clients.
+///
+/// The server runs on its own threads, listening on an ephemeral port of
+/// `127.0.0.1`, until it's dropped. Requests are matched to the
+/// expectations in the order they are received, over any number of
+/// connections.
+///
+/// A request that doesn't match its expectation, or that comes after all
+/// of them were met, is answered with a `500 Internal Server Error`
+/// describing why, and the connection is closed. The mismatches are
+/// reported by [`verify`](MockServer::verify).
+pub struct MockServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    expectations: Mutex<VecDeque<Expectation>>,
+    failures: Mutex<Vec<String>>,
+    received: Mutex<usize>,
+    shutdown: AtomicBool,
+}
+
+// A request as the `MockServer` received it.
+struct Received {
+    method: String,
+    path: String,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+}
+
+// ===== impl Expectation =====
+
+impl Expectation {
+    /// Expect the request to have this method.
+    pub fn method(mut self, method: Method) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    /// Expect the request-target to be exactly this, such as `/a?b=c`.
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_owned());
+        self
+    }
+
+    /// Expect the request to have a header with this name and value.
+    ///
+    /// The name is compared ignoring case, and the request may have other
+    /// values of it.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Expect the request body to be exactly this.
+    ///
+    /// Bodies with a `Content-Length` and chunked bodies are read either
+    /// way, and compared after decoding.
+    pub fn body<B: Into<Bytes>>(mut self, body: B) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Answer the request with this `Reply`.
+    pub fn respond_with(mut self, reply: Reply) -> Self {
+        self.reply = reply;
+        self
+    }
+
+    // Why the request doesn't match, if it doesn't.
+    fn mismatch(&self, req: &Received) -> Option<String> {
+        if let Some(ref method) = self.method {
+            if method.as_str() != req.method {
+                return Some(format!("expected method {}, got {}", method, req.method));
+            }
+        }
+        if let Some(ref path) = self.path {
+            if *path != req.path {
+                return Some(format!("expected path {:?}, got {:?}", path, req.path));
+            }
+        }
+        for &(ref name, ref value) in &self.headers {
+            let found = req.headers
+                .iter()
+                .any(|&(ref n, ref v)| n.eq_ignore_ascii_case(name) && v == value.as_bytes());
+            if !found {
+                return Some(format!("expected header {}: {:?}", name, value));
+            }
+        }
+        if let Some(ref body) = self.body {
+            if body.as_ref() != &req.body[..] {
+                return Some(format!(
+                    "expected body {:?}, got {:?}",
+                    String::from_utf8_lossy(body),
+                    String::from_utf8_lossy(&req.body),
+                ));
+            }
+        }
+        None
+    }
+}
+
+// ===== impl Reply =====
+
+impl Reply {
+    /// A response with this status, and a `Content-Length` for its body,
+    /// unless one is set with [`header`](Reply::header).
+    pub fn new(status: StatusCode) -> Reply {
+        Reply {
+            kind: ReplyKind::Response {
+                status,
+                headers: Vec::new(),
+                body: Bytes::new(),
+            },
+            close: false,
+        }
+    }
+
+    /// Write exactly these bytes, whether they make up a valid response or
+    /// not.
+    pub fn raw<B: Into<Bytes>>(bytes: B) -> Reply {
+        Reply {
+            kind: ReplyKind::Raw(bytes.into()),
+            close: false,
+        }
+    }
+
+    /// Close the connection without writing anything.
+    pub fn hang_up() -> Reply {
+        Reply::raw(Bytes::new()).close()
+    }
+
+    /// Add a header to the response.
+    ///
+    /// The name and value are written as they are, even if they aren't
+    /// valid.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the reply was created with
+    /// [`raw`](Reply::raw).
+    pub fn header(mut self, name: &str, value: &str) -> Reply {
+        match self.kind {
+            ReplyKind::Response { ref mut headers, .. } => {
+                headers.push((name.to_owned(), value.to_owned()));
+            },
+            ReplyKind::Raw(..) => panic!("Reply::header called on a raw reply"),
+        }
+        self
+    }
+
+    /// Set the body of the response.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the reply was created with
+    /// [`raw`](Reply::raw).
+    pub fn body<B: Into<Bytes>>(mut self, bytes: B) -> Reply {
+        match self.kind {
+            ReplyKind::Response { ref mut body, .. } => *body = bytes.into(),
+            ReplyKind::Raw(..) => panic!("Reply::body called on a raw reply"),
+        }
+        self
+    }
+
+    /// Close the connection after writing the reply.
+    pub fn close(mut self) -> Reply {
+        self.close = true;
+        self
+    }
+
+    fn to_bytes(&self, method: &str) -> Vec<u8> {
+        match self.kind {
+            ReplyKind::Response { status, ref headers, ref body } => {
+                let mut buf = format!(
+                    "HTTP/1.1 {} {}\r\n",
+                    status.as_u16(),
+                    status.canonical_reason().unwrap_or("<none>"),
+                ).into_bytes();
+                let mut has_length = false;
+                for &(ref name, ref value) in headers {
+                    has_length |= name.eq_ignore_ascii_case("content-length") ||
+                        name.eq_ignore_ascii_case("transfer-encoding");
+                    buf.extend_from_slice(name.as_bytes());
+                    buf.extend_from_slice(b": ");
+                    buf.extend_from_slice(value.as_bytes());
+                    buf.extend_from_slice(b"\r\n");
+                }
+                if !has_length {
+                    buf.extend_from_slice(format!("content-length: {}\r\n", body.len()).as_bytes());
+                }
+                buf.extend_from_slice(b"\r\n");
+                if method != "HEAD" {
+                    buf.extend_from_slice(body);
+                }
+                buf
+            },
+            ReplyKind::Raw(ref bytes) => bytes.to_vec(),
+        }
+    }
+}
+
+// ===== impl MockServer =====
+
+impl MockServer {
+    /// Start a server expecting these requests, in order.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if it can't listen on a port.
+    pub fn start<I>(expectations: I) -> MockServer
+    where
+        I: IntoIterator<Item=Expectation>,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("MockServer bind");
+        let addr = listener.local_addr().expect("MockServer local_addr");
+        let shared = Arc::new(Shared {
+            expectations: Mutex::new(expectations.into_iter().collect()),
+            failures: Mutex::new(Vec::new()),
+            received: Mutex::new(0),
+            shutdown: AtomicBool::new(false),
+        });
+        let accept_shared = shared.clone();
+        thread::Builder::new()
+            .name(format!("mock-server-{}", addr))
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if accept_shared.shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            debug!("mock server accept error: {}", e);
+                            continue;
+                        }
+                    };
+                    let shared = accept_shared.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve(stream, &shared) {
+                            debug!("mock server connection error: {}", e);
+                        }
+                    });
+                }
+            })
+            .expect("MockServer thread");
+        MockServer {
+            addr,
+            shared,
+        }
+    }
+
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The `Uri` of `path` on this server, such as `http://127.0.0.1:4321/a`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `path` makes an invalid `Uri`.
+    pub fn uri(&self, path: &str) -> Uri {
+        format!("http://{}{}", self.addr, path)
+            .parse()
+            .expect("MockServer::uri path")
+    }
+
+    /// The number of requests received so far.
+    pub fn received(&self) -> usize {
+        *self.shared.received.lock().unwrap()
+    }
+
+    /// Check that every expected request was received, and matched.
+    ///
+    /// # Panics
+    ///
+    /// This method panics describing every request that didn't match, and
+    /// the expectations that are still waiting for a request.
+    pub fn verify(&self) {
+        let mut problems = self.shared.failures.lock().unwrap().clone();
+        let pending = self.shared.expectations.lock().unwrap().len();
+        if pending > 0 {
+            problems.push(format!("{} expected request(s) not received", pending));
+        }
+        if !problems.is_empty() {
+            panic!("MockServer verify failed:\n  {}", problems.join("\n  "));
+        }
+    }
+}
+
+impl fmt::Debug for MockServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MockServer")
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        // Wake up the accepting thread, so it sees the shutdown.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+// Answers the requests of a connection, until it or the reply closes it.
+fn serve(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let mut buf = Vec::new();
+    loop {
+        let req = match read_request(&mut stream, &mut buf)? {
+            Some(req) => req,
+            None => return Ok(()),
+        };
+        let n = {
+            let mut received = shared.received.lock().unwrap();
+            *received += 1;
+            *received
+        };
+        trace!("mock server request {}: {} {}", n, req.method, req.path);
+        let expectation = shared.expectations.lock().unwrap().pop_front();
+        let failure = match expectation {
+            Some(expectation) => match expectation.mismatch(&req) {
+                Some(why) => Some(why),
+                None => {
+                    let reply = &expectation.reply;
+                    stream.write_all(&reply.to_bytes(&req.method))?;
+                    stream.flush()?;
+                    if reply.close {
+                        let _ = stream.shutdown(Shutdown::Both);
+                        return Ok(());
+                    }
+                    continue;
+                }
+            },
+            None => Some("no more requests were expected".to_owned()),
+        };
+        let why = format!(
+            "request {} ({} {}): {}",
+            n,
+            req.method,
+            req.path,
+            failure.expect("failure"),
+        );
+        debug!("mock server mismatch: {}", why);
+        let reply = Reply::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("connection", "close")
+            .body(why.clone());
+        shared.failures.lock().unwrap().push(why);
+        stream.write_all(&reply.to_bytes(&req.method))?;
+        let _ = stream.shutdown(Shutdown::Both);
+        return Ok(());
+    }
+}
+
+// Reads the next request, or `None` if the connection closed before one.
+fn read_request(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<Option<Received>> {
+    loop {
+        let parsed = {
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut req = httparse::Request::new(&mut headers);
+            match req.parse(buf) {
+                Ok(httparse::Status::Complete(len)) => Some((len, Received {
+                    method: req.method.unwrap_or("").to_owned(),
+                    path: req.path.unwrap_or("").to_owned(),
+                    headers: req.headers
+                        .iter()
+                        .map(|h| (h.name.to_owned(), h.value.to_vec()))
+                        .collect(),
+                    body: Vec::new(),
+                })),
+                Ok(httparse::Status::Partial) => None,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+        };
+        if let Some((len, mut req)) = parsed {
+            buf.drain(..len);
+            req.body = read_body(stream, buf, &req.headers)?;
+            return Ok(Some(req));
+        }
+        if fill(stream, buf)? == 0 {
+            return if buf.is_empty() {
+                Ok(None)
+            } else {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "eof in request head"))
+            };
+        }
+    }
+}
+
+fn read_body(stream: &mut TcpStream, buf: &mut Vec<u8>, headers: &[(String, Vec<u8>)]) -> io::Result<Vec<u8>> {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| String::from_utf8_lossy(v).trim().to_ascii_lowercase())
+    };
+    if header("transfer-encoding").map_or(false, |te| te.ends_with("chunked")) {
+        let mut body = Vec::new();
+        loop {
+            let line = read_line(stream, buf)?;
+            let size = line.split(';').next().unwrap_or("").trim();
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if size == 0 {
+                // Skip any trailers.
+                while !read_line(stream, buf)?.is_empty() {}
+                return Ok(body);
+            }
+            body.extend_from_slice(&read_exact(stream, buf, size)?);
+            read_line(stream, buf)?;
+        }
+    }
+    let len = match header("content-length") {
+        Some(len) => len.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        None => 0,
+    };
+    read_exact(stream, buf, len)
+}
+
+fn read_line(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<String> {
+    loop {
+        if let Some(pos) = buf.windows(2).position(|w| w == b"\r\n") {
+            let line = String::from_utf8_lossy(&buf[..pos]).into_owned();
+            buf.drain(..pos + 2);
+            return Ok(line);
+        }
+        if fill(stream, buf)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "eof in chunked body"));
+        }
+    }
+}
+
+fn read_exact(stream: &mut TcpStream, buf: &mut Vec<u8>, len: usize) -> io::Result<Vec<u8>> {
+    while buf.len() < len {
+        if fill(stream, buf)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "eof in request body"));
+        }
+    }
+    Ok(buf.drain(..len).collect())
+}
+
+fn fill(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut chunk = [0; 8192];
+    let n = stream.read(&mut chunk)?;
+    buf.extend_from_slice(&chunk[..n]);
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use http::{Method, StatusCode};
+
+    use super::{expect_request, MockServer, Reply};
+
+    fn exchange(server: &MockServer, req: &[u8]) -> String {
+        let mut tcp = TcpStream::connect(server.addr()).unwrap();
+        tcp.write_all(req).unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn scripted_replies() {
+        let server = MockServer::start(vec![
+            expect_request()
+                .method(Method::POST)
+                .path("/a")
+                .header("x-test", "1")
+                .body("hello")
+                .respond_with(Reply::new(StatusCode::CREATED).body("made").close()),
+            expect_request()
+                .respond_with(Reply::raw("HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nshort").close()),
+        ]);
+
+        let res = exchange(&server, b"\
+            POST /a HTTP/1.1\r\n\
+            X-Test: 1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            3\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n\
+        ");
+        assert_eq!(res, "HTTP/1.1 201 Created\r\ncontent-length: 4\r\n\r\nmade");
+
+        let res = exchange(&server, b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(res, "HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nshort");
+
+        assert_eq!(server.received(), 2);
+        server.verify();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected path \"/a\", got \"/b\"")]
+    fn verify_mismatch() {
+        let server = MockServer::start(vec![
+            expect_request().path("/a"),
+        ]);
+
+        let res = exchange(&server, b"GET /b HTTP/1.1\r\n\r\n");
+        assert!(res.starts_with("HTTP/1.1 500 Internal Server Error\r\n"), "{:?}", res);
+
+        server.verify();
+    }
+}
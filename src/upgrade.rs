@@ -0,0 +1,477 @@
+//! Negotiating HTTP/1 upgrades to other protocols.
+//!
+//! A client offers to switch a connection to other protocols with the
+//! `Upgrade` header of a request, along with `Connection: upgrade`. A server
+//! that agrees answers with a `101 Switching Protocols` response naming the
+//! one it chose, after which the connection speaks that protocol.
+//!
+//! An [`Offer`](Offer) is the list of protocols a client offers, in order of
+//! preference. A client writes it into its request, and checks the response
+//! with [`Offer::verify`](Offer::verify). A server reads it from the request,
+//! picks a protocol with [`Offer::select`](Offer::select), and answers with
+//! [`switching_protocols`](switching_protocols).
+//!
+//! Responses of a `client::conn` connection are checked the same way, so a
+//! `101` naming a protocol the request didn't offer fails the request.
+//!
+//! HTTP/2 has no upgrades. Running a protocol such as WebSockets over it
+//! takes the extended `CONNECT` of RFC 8441, with its `:protocol`
+//! pseudo-header, which the `h2` dependency doesn't support yet.
+//!
+//! # Example
+//!
+//! ```
+//! # extern crate hyper;
+//! # use hyper::{Request, Body};
+//! use hyper::upgrade::{self, Offer, Protocol};
+//!
+//! # fn main() {
+//! let mut offer = Offer::new();
+//! offer.push(Protocol::new("websocket").unwrap());
+//! offer.push_with_q(Protocol::new("h2c").unwrap(), 0.5);
+//!
+//! let mut req = Request::new(Body::empty());
+//! offer.write_headers(req.headers_mut());
+//!
+//! // On the server...
+//! let offered = Offer::from_headers(req.headers()).unwrap().expect("an offer");
+//! let chosen = offered.select(|protocol| protocol.name() == "h2c").unwrap();
+//! let res = upgrade::switching_protocols(chosen);
+//!
+//! // ...and back on the client.
+//! assert_eq!(offer.verify(&res).unwrap().name(), "h2c");
+//! # }
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::slice;
+use std::str::FromStr;
+
+use http::{HeaderMap, Response, StatusCode};
+use http::header::{self, HeaderValue};
+
+use body::Body;
+use headers::eq_ascii;
+
+/// A protocol named in an `Upgrade` header, such as `websocket` or
+/// `HTTP/2.0`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Protocol {
+    name: String,
+    version: Option<String>,
+}
+
+/// The protocols a client offers to upgrade a connection to, in order of
+/// preference.
+///
+/// Each protocol has a quality between `0` and `1`, written as a `q`
+/// parameter when it isn't `1`. Protocols of the same quality keep the
+/// order they were offered in.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Offer {
+    // Sorted by quality, in thousandths.
+    protocols: Vec<(Protocol, u16)>,
+}
+
+/// An iterator over the protocols of an [`Offer`](Offer), in order of
+/// preference.
+#[derive(Debug)]
+pub struct Protocols<'a> {
+    inner: slice::Iter<'a, (Protocol, u16)>,
+}
+
+/// An error returned when `Upgrade` headers aren't valid, or a response
+/// didn't accept an offer.
+#[derive(Debug)]
+pub struct InvalidUpgrade {
+    reason: &'static str,
+}
+
+impl Protocol {
+    /// Parse a protocol, such as `"websocket"` or `"HTTP/2.0"`.
+    ///
+    /// The name, and the version after a `/`, must be tokens.
+    pub fn new(protocol: &str) -> Result<Protocol, InvalidUpgrade> {
+        let mut parts = protocol.splitn(2, '/');
+        let name = parts.next().unwrap_or("");
+        let version = parts.next();
+        if !is_token(name) {
+            return Err(InvalidUpgrade::new("invalid protocol name"));
+        }
+        if let Some(version) = version {
+            if !is_token(version) {
+                return Err(InvalidUpgrade::new("invalid protocol version"));
+            }
+        }
+        Ok(Protocol {
+            name: name.to_owned(),
+            version: version.map(str::to_owned),
+        })
+    }
+
+    /// The name of this protocol.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The version of this protocol, if any.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_ref().map(String::as_str)
+    }
+
+    // Whether `chosen` is this protocol. Names don't depend on case, and a
+    // protocol offered without a version may be chosen with any.
+    fn accepts(&self, chosen: &Protocol) -> bool {
+        if !eq_ascii(&self.name, &chosen.name) {
+            return false;
+        }
+        match (self.version(), chosen.version()) {
+            (Some(offered), Some(chosen)) => offered == chosen,
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if let Some(ref version) = self.version {
+            write!(f, "/{}", version)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = InvalidUpgrade;
+
+    fn from_str(s: &str) -> Result<Protocol, InvalidUpgrade> {
+        Protocol::new(s)
+    }
+}
+
+impl Offer {
+    /// Create an empty `Offer`.
+    pub fn new() -> Offer {
+        Offer::default()
+    }
+
+    /// Offer a protocol, preferred over the ones of lower quality.
+    pub fn push(&mut self, protocol: Protocol) {
+        self.insert(protocol, 1000);
+    }
+
+    /// Offer a protocol with a quality between `0` and `1`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `q` isn't between `0` and `1`.
+    pub fn push_with_q(&mut self, protocol: Protocol, q: f32) {
+        assert!(q >= 0.0 && q <= 1.0, "quality must be between 0 and 1: {}", q);
+        self.insert(protocol, (q * 1000.0).round() as u16);
+    }
+
+    fn insert(&mut self, protocol: Protocol, q: u16) {
+        let at = self.protocols
+            .iter()
+            .position(|&(_, other)| other < q)
+            .unwrap_or(self.protocols.len());
+        self.protocols.insert(at, (protocol, q));
+    }
+
+    /// Read the offer of a request, from its `Upgrade` and `Connection`
+    /// headers.
+    ///
+    /// Returns `None` if the request doesn't offer to upgrade, which needs
+    /// both an `Upgrade` header and an `upgrade` option in `Connection`.
+    /// Protocols of quality `0` are left out.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Offer>, InvalidUpgrade> {
+        if !headers.contains_key(header::UPGRADE) || !connection_has_upgrade(headers) {
+            return Ok(None);
+        }
+        let mut offer = Offer::new();
+        for value in headers.get_all(header::UPGRADE) {
+            let value = value.to_str().map_err(|_| InvalidUpgrade::new("invalid Upgrade header"))?;
+            for item in value.split(',') {
+                let item = item.trim();
+                if item.is_empty() {
+                    continue;
+                }
+                let (protocol, q) = parse_item(item)?;
+                if q > 0 {
+                    offer.insert(protocol, q);
+                }
+            }
+        }
+        Ok(Some(offer))
+    }
+
+    /// Write this offer into the headers of a request, replacing any
+    /// `Upgrade` header, and adding `upgrade` to `Connection`.
+    pub fn write_headers(&self, headers: &mut HeaderMap) {
+        let mut value = String::new();
+        for &(ref protocol, q) in &self.protocols {
+            if !value.is_empty() {
+                value.push_str(", ");
+            }
+            value.push_str(&protocol.to_string());
+            if q < 1000 {
+                value.push_str(&format!(";q={}", format_q(q)));
+            }
+        }
+        let value = HeaderValue::from_str(&value).expect("protocols are tokens");
+        headers.insert(header::UPGRADE, value);
+        if !connection_has_upgrade(headers) {
+            headers.append(header::CONNECTION, HeaderValue::from_static("upgrade"));
+        }
+    }
+
+    /// The protocols offered, in order of preference.
+    pub fn iter<'a>(&'a self) -> Protocols<'a> {
+        Protocols {
+            inner: self.protocols.iter(),
+        }
+    }
+
+    /// Whether no protocols are offered.
+    pub fn is_empty(&self) -> bool {
+        self.protocols.is_empty()
+    }
+
+    /// Pick the most preferred protocol for which `accept` returns true.
+    pub fn select<F>(&self, mut accept: F) -> Option<&Protocol>
+    where
+        F: FnMut(&Protocol) -> bool,
+    {
+        self.iter().find(|protocol| accept(protocol))
+    }
+
+    /// Check that a response accepted this offer, returning the protocol it
+    /// chose.
+    ///
+    /// The response must be a `101 Switching Protocols`, with an `Upgrade`
+    /// header naming exactly one of the protocols offered. It doesn't need
+    /// `Connection: upgrade`, since the `101` already switches the
+    /// connection.
+    pub fn verify<B>(&self, res: &Response<B>) -> Result<Protocol, InvalidUpgrade> {
+        if res.status() != StatusCode::SWITCHING_PROTOCOLS {
+            return Err(InvalidUpgrade::new("response isn't 101 Switching Protocols"));
+        }
+        self.verify_headers(res.headers())
+    }
+
+    pub(crate) fn verify_headers(&self, headers: &HeaderMap) -> Result<Protocol, InvalidUpgrade> {
+        let mut values = headers.get_all(header::UPGRADE).iter();
+        let chosen = match (values.next(), values.next()) {
+            (Some(value), None) => value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<Protocol>().ok()),
+            _ => None,
+        };
+        let chosen = match chosen {
+            Some(chosen) => chosen,
+            None => return Err(InvalidUpgrade::new("response must name one protocol in Upgrade")),
+        };
+        if self.iter().any(|offered| offered.accepts(&chosen)) {
+            Ok(chosen)
+        } else {
+            Err(InvalidUpgrade::new("response chose a protocol that wasn't offered"))
+        }
+    }
+}
+
+impl<'a> Iterator for Protocols<'a> {
+    type Item = &'a Protocol;
+
+    fn next(&mut self) -> Option<&'a Protocol> {
+        self.inner.next().map(|&(ref protocol, _)| protocol)
+    }
+}
+
+/// Create a `101 Switching Protocols` response, upgrading to `protocol`.
+///
+/// The connection must be polled with `without_shutdown` or
+/// `poll_without_shutdown` for the response to be sent, and the IO taken
+/// back afterwards to speak `protocol`.
+pub fn switching_protocols(protocol: &Protocol) -> Response<Body> {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+    let value = HeaderValue::from_str(&protocol.to_string()).expect("protocols are tokens");
+    res.headers_mut().insert(header::UPGRADE, value);
+    res.headers_mut().insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+    res
+}
+
+impl InvalidUpgrade {
+    fn new(reason: &'static str) -> InvalidUpgrade {
+        InvalidUpgrade {
+            reason: reason,
+        }
+    }
+}
+
+impl fmt::Display for InvalidUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.reason)
+    }
+}
+
+impl StdError for InvalidUpgrade {
+    fn description(&self) -> &str {
+        self.reason
+    }
+}
+
+fn connection_has_upgrade(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|option| eq_ascii(option.trim(), "upgrade"))
+}
+
+// A protocol with an optional `q` parameter, such as `websocket;q=0.5`.
+fn parse_item(item: &str) -> Result<(Protocol, u16), InvalidUpgrade> {
+    let mut parts = item.split(';');
+    let protocol = parts.next().unwrap_or("").trim().parse::<Protocol>()?;
+    let mut q = 1000;
+    for param in parts {
+        let mut kv = param.splitn(2, '=');
+        let key = kv.next().unwrap_or("").trim();
+        let value = kv.next().unwrap_or("").trim();
+        if eq_ascii(key, "q") {
+            q = parse_q(value).ok_or_else(|| InvalidUpgrade::new("invalid q parameter"))?;
+        }
+    }
+    Ok((protocol, q))
+}
+
+// A quality such as `0.5`, in thousandths.
+fn parse_q(value: &str) -> Option<u16> {
+    let mut parts = value.splitn(2, '.');
+    let whole = parts.next().unwrap_or("");
+    let fraction = parts.next().unwrap_or("");
+    if fraction.len() > 3 || !fraction.bytes().all(|b| b >= b'0' && b <= b'9') {
+        return None;
+    }
+    let thousandths = format!("{:0<3}", fraction).parse::<u16>().unwrap_or(0);
+    match whole {
+        "0" => Some(thousandths),
+        "1" if thousandths == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+fn format_q(q: u16) -> String {
+    if q == 0 {
+        return "0".to_owned();
+    }
+    let mut s = format!("0.{:03}", q);
+    while s.ends_with('0') {
+        s.pop();
+    }
+    s
+}
+
+// RFC 7230 token characters.
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| {
+        (b >= b'a' && b <= b'z') ||
+        (b >= b'A' && b <= b'Z') ||
+        (b >= b'0' && b <= b'9') ||
+        b"!#$%&'*+-.^_`|~".contains(&b)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer_names(offer: &Offer) -> Vec<String> {
+        offer.iter().map(|protocol| protocol.to_string()).collect()
+    }
+
+    #[test]
+    fn protocol_new() {
+        let protocol = Protocol::new("HTTP/2.0").unwrap();
+        assert_eq!(protocol.name(), "HTTP");
+        assert_eq!(protocol.version(), Some("2.0"));
+        assert_eq!(protocol.to_string(), "HTTP/2.0");
+        assert_eq!(Protocol::new("websocket").unwrap().version(), None);
+        assert!(Protocol::new("").is_err());
+        assert!(Protocol::new("web socket").is_err());
+        assert!(Protocol::new("h2c/").is_err());
+    }
+
+    #[test]
+    fn offer_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::UPGRADE, HeaderValue::from_static("a;q=0.5, b, c;q=0, d/1;q=0.8"));
+        assert_eq!(Offer::from_headers(&headers).unwrap(), None, "without Connection: upgrade");
+
+        headers.insert(header::CONNECTION, HeaderValue::from_static("keep-alive, Upgrade"));
+        let offer = Offer::from_headers(&headers).unwrap().expect("offer");
+        assert_eq!(offer_names(&offer), vec!["b", "d/1", "a"]);
+
+        headers.insert(header::UPGRADE, HeaderValue::from_static("a;q=2"));
+        assert!(Offer::from_headers(&headers).is_err());
+    }
+
+    #[test]
+    fn offer_write_headers() {
+        let mut offer = Offer::new();
+        offer.push_with_q(Protocol::new("a").unwrap(), 0.25);
+        offer.push(Protocol::new("b/2").unwrap());
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, HeaderValue::from_static("keep-alive"));
+        offer.write_headers(&mut headers);
+        assert_eq!(headers[header::UPGRADE], "b/2, a;q=0.25");
+        assert_eq!(
+            headers.get_all(header::CONNECTION).iter().collect::<Vec<_>>(),
+            vec!["keep-alive", "upgrade"]
+        );
+        assert_eq!(Offer::from_headers(&headers).unwrap(), Some(offer));
+    }
+
+    #[test]
+    fn format_q() {
+        assert_eq!(super::format_q(0), "0");
+        assert_eq!(super::format_q(5), "0.005");
+        assert_eq!(super::format_q(250), "0.25");
+        assert_eq!(super::format_q(999), "0.999");
+    }
+
+    #[test]
+    fn offer_select_and_verify() {
+        let mut offer = Offer::new();
+        offer.push(Protocol::new("websocket").unwrap());
+        offer.push(Protocol::new("h2c/1").unwrap());
+
+        let chosen = offer.select(|protocol| protocol.name() != "websocket").unwrap();
+        assert_eq!(chosen.to_string(), "h2c/1");
+        assert!(offer.select(|_| false).is_none());
+
+        let res = switching_protocols(chosen);
+        assert_eq!(offer.verify(&res).unwrap(), *chosen);
+
+        let res = switching_protocols(&Protocol::new("WebSocket").unwrap());
+        assert_eq!(offer.verify(&res).unwrap().name(), "WebSocket");
+
+        let res = switching_protocols(&Protocol::new("h2c/2").unwrap());
+        assert!(offer.verify(&res).is_err(), "other version");
+
+        let res = switching_protocols(&Protocol::new("foo").unwrap());
+        assert!(offer.verify(&res).is_err(), "not offered");
+
+        let mut res = switching_protocols(&Protocol::new("websocket").unwrap());
+        res.headers_mut().remove(header::CONNECTION);
+        assert_eq!(offer.verify(&res).unwrap().name(), "websocket", "no Connection: upgrade");
+
+        *res.status_mut() = StatusCode::OK;
+        assert!(offer.verify(&res).is_err(), "not 101");
+    }
+}
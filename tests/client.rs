@@ -709,9 +709,9 @@ mod dispatch_impl {
     use super::*;
     use std::io::{self, Read, Write};
     use std::sync::Arc;
-    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     use futures::{self, Future};
     use futures::sync::{mpsc, oneshot};
@@ -721,6 +721,7 @@ mod dispatch_impl {
     use tokio_io::{AsyncRead, AsyncWrite};
 
     use hyper::client::connect::{Connect, Connected, Destination, HttpConnector};
+    use hyper::client::RequestConfig;
     use hyper::Client;
     use hyper;
 
@@ -768,6 +769,297 @@ mod dispatch_impl {
         closes.into_future().wait().unwrap().0.expect("closes");
     }
 
+    #[test]
+    fn max_response_body_size_closes_connection() {
+        let _ = pretty_env_logger::try_init();
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let (closes_tx, closes) = mpsc::channel(10);
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .max_response_body_size(1024)
+            .build(DebugConnector::with_http_and_closes(HttpConnector::new_with_handle(1, runtime.reactor().clone()), closes_tx));
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n").expect("write head");
+            // a chunked body that never ends
+            let chunk = vec![b'x'; 512];
+            loop {
+                if write!(sock, "{:X}\r\n", chunk.len()).is_err() ||
+                    sock.write_all(&chunk).is_err() ||
+                    sock.write_all(b"\r\n").is_err() {
+                    break;
+                }
+            }
+        });
+
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req).and_then(|res| {
+            assert_eq!(res.status(), hyper::StatusCode::OK);
+            res.into_body().concat2()
+        });
+        let err = res.wait().unwrap_err();
+        assert!(err.is_body_too_large(), "{:?}", err);
+
+        closes.into_future().wait().unwrap().0.expect("closes");
+    }
+
+    #[test]
+    fn origin_stats() {
+        let _ = pretty_env_logger::try_init();
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build(HttpConnector::new_with_handle(1, runtime.reactor().clone()));
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 65536\r\n\r\n").expect("write head");
+            sock.write_all(&[b'x'; 65536]).expect("write body");
+            let _ = sock.read(&mut buf);
+        });
+
+        let host = format!("http://{}", addr);
+        assert!(client.origin_stats().get(&host).is_none());
+
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req).and_then(|res| {
+            assert_eq!(res.status(), hyper::StatusCode::OK);
+            res.into_body().concat2()
+        });
+        let body = res.wait().unwrap();
+        assert_eq!(body.len(), 65536);
+
+        let stats = client.origin_stats();
+        let estimate = stats.get(&host).expect("origin estimate");
+        assert_eq!(estimate.rtt_samples(), 1);
+        assert!(estimate.rtt().is_some());
+        assert_eq!(estimate.throughput_samples(), 1);
+        assert!(estimate.throughput().unwrap() > 0);
+        assert!(estimate.bandwidth_delay_product().is_some());
+    }
+
+    #[test]
+    fn origin_stats_http2_ping() {
+        let _ = pretty_env_logger::try_init();
+
+        let runtime = Runtime::new().unwrap();
+        let server = hyper::Server::bind(&"127.0.0.1:0".parse().unwrap())
+            .http2_only(true)
+            .serve(|| hyper::service::service_fn_ok(|_req| hyper::Response::new(Body::empty())));
+        let addr = server.local_addr();
+        runtime.executor().spawn(server.map_err(|e| panic!("server error: {}", e)));
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .http2_only(true)
+            .build(HttpConnector::new_with_handle(1, runtime.reactor().clone()));
+
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+
+        // One sample from connecting, and one from the PING, whose PONG may
+        // still be on its way.
+        let host = format!("http://{}", addr);
+        let started = Instant::now();
+        loop {
+            let samples = client.origin_stats().get(&host).map_or(0, |estimate| estimate.rtt_samples());
+            if samples == 2 {
+                break;
+            }
+            assert!(started.elapsed() < Duration::from_secs(5), "{} rtt samples", samples);
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn origin_stats_enhance_your_calm() {
+        let _ = pretty_env_logger::try_init();
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .http2_only(true)
+            .build(HttpConnector::new_with_handle(1, runtime.reactor().clone()));
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            // Empty SETTINGS.
+            sock.write_all(b"\0\0\0\x04\0\0\0\0\0").expect("write settings");
+
+            // Read the preface, then frames until the request's HEADERS.
+            let mut buf = Vec::new();
+            let mut chunk = [0; 4096];
+            let mut pos = 24;
+            'read: loop {
+                let n = sock.read(&mut chunk).expect("read");
+                assert!(n > 0, "eof before request");
+                buf.extend_from_slice(&chunk[..n]);
+                while buf.len() >= pos + 9 {
+                    let len = (buf[pos] as usize) << 16 | (buf[pos + 1] as usize) << 8 | buf[pos + 2] as usize;
+                    if buf[pos + 3] == 0x1 {
+                        break 'read;
+                    }
+                    pos += 9 + len;
+                }
+            }
+
+            // GOAWAY, refusing every stream, with ENHANCE_YOUR_CALM.
+            sock.write_all(b"\0\0\x08\x07\0\0\0\0\0\0\0\0\0\0\0\0\x0b").expect("write goaway");
+            let _ = sock.read(&mut chunk);
+        });
+
+        let host = format!("http://{}", addr);
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Body::empty())
+            .unwrap();
+        let err = client.request(req).wait().unwrap_err();
+        assert_eq!(err.http2_error_code(), Some(0xb), "{:?}", err);
+
+        let stats = client.origin_stats();
+        let estimate = stats.get(&host).expect("origin estimate");
+        assert_eq!(estimate.enhance_your_calm(), 1);
+    }
+
+    #[test]
+    fn options_asterisk_form() {
+        let _ = pretty_env_logger::try_init();
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build(HttpConnector::new_with_handle(1, runtime.reactor().clone()));
+
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+            tx.send(String::from_utf8(buf[..n].to_vec()).unwrap()).unwrap();
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 1");
+        });
+
+        let mut req = Request::builder()
+            .method("OPTIONS")
+            .uri(&*format!("http://{}/", addr))
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut().insert(RequestConfig::new().request_target(hyper::client::RequestTarget::Asterisk));
+        let res = client.request(req).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+
+        let head = rx.recv().unwrap();
+        assert!(head.starts_with("OPTIONS * HTTP/1.1\r\n"), "{:?}", head);
+        assert!(head.contains(&format!("\r\nhost: {}\r\n", addr)), "{:?}", head);
+    }
+
+    #[test]
+    fn debug_render() {
+        let _ = pretty_env_logger::try_init();
+
+        let client = Client::builder()
+            .http1_title_case_headers(true)
+            .build_http();
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("http://example.com/orders?q=1")
+            .body(Body::from("hello"))
+            .unwrap();
+        let bytes = client.debug_render(req).wait().unwrap();
+        assert_eq!(s(&bytes), "\
+            POST /orders?q=1 HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Content-Length: 5\r\n\
+            \r\n\
+            hello\
+        ");
+
+        let chunks = futures::stream::iter_ok::<_, ::std::io::Error>(vec!["foo", "bar"]);
+        let req = Request::builder()
+            .method("PUT")
+            .uri("http://example.com:8080/")
+            .body(Body::wrap_stream(chunks))
+            .unwrap();
+        let bytes = client.debug_render(req).wait().unwrap();
+        assert_eq!(s(&bytes), "\
+            PUT / HTTP/1.1\r\n\
+            Host: example.com:8080\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            3\r\nfoo\r\n\
+            3\r\nbar\r\n\
+            0\r\n\r\n\
+        ");
+
+        // Nothing was connected to.
+        assert_eq!(client.pool_stats().idle(), 0);
+    }
+
+    #[test]
+    fn http1_max_buf_size_rejects_large_response_head() {
+        let _ = pretty_env_logger::try_init();
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .http1_max_buf_size(8192)
+            .build(HttpConnector::new_with_handle(1, runtime.reactor().clone()));
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            let value = vec![b'x'; 1024 * 64];
+            let _ = sock.write_all(b"HTTP/1.1 200 OK\r\nbig: ");
+            let _ = sock.write_all(&value);
+        });
+
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Body::empty())
+            .unwrap();
+        let err = client.request(req).wait().unwrap_err();
+        assert!(err.is_parse_too_large(), "{:?}", err);
+    }
+
     #[test]
     fn dropped_client_closes_connection() {
         // https://github.com/hyperium/hyper/issues/1353
@@ -1176,6 +1468,61 @@ mod dispatch_impl {
         runtime.shutdown_on_idle().wait().expect("rt shutdown");
     }
 
+    #[test]
+    fn client_keep_alive_header_hints() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build(connector);
+
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nKeep-Alive: timeout=5, max=1\r\n\r\n").expect("write 1");
+            tx.send(()).unwrap();
+            sock.read(&mut buf).expect("read 2");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 2");
+            tx.send(()).unwrap();
+
+            // No more requests were allowed on the first connection.
+            let mut sock2 = server.accept().unwrap().0;
+            sock2.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock2.read(&mut buf).expect("read 3");
+            sock2.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nKeep-Alive: timeout=1\r\n\r\n").expect("write 3");
+            tx.send(()).unwrap();
+
+            // The second one was too close to its timeout to be reused.
+            let mut sock3 = server.accept().unwrap().0;
+            sock3.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock3.read(&mut buf).expect("read 4");
+            sock3.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 4");
+            tx.send(()).unwrap();
+            drop((sock, sock2));
+        });
+
+        for &(path, expected_connects) in &[("/a", 1), ("/b", 1), ("/c", 2), ("/d", 3)] {
+            let req = Request::builder()
+                .uri(&*format!("http://{}{}", addr, path))
+                .body(Body::empty())
+                .unwrap();
+            client.request(req).wait().unwrap();
+            rx.recv().expect("thread panicked");
+            assert_eq!(connects.load(Ordering::SeqCst), expected_connects, "connects after {}", path);
+
+            // let the connection get back into the pool
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
     #[test]
     fn client_keep_alive_extra_body() {
         let _ = pretty_env_logger::try_init();
@@ -1277,250 +1624,2704 @@ mod dispatch_impl {
         res.join(rx).map(|r| r.0).wait().unwrap();
     }
 
+    #[test]
+    fn pool_timeout_while_connecting() {
+        let _ = pretty_env_logger::try_init();
+        let mut runtime = Runtime::new().unwrap();
+
+        let client = Client::builder()
+            .pool_timeout(Duration::from_millis(100))
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(NeverConnector);
 
-    struct DebugConnector {
-        http: HttpConnector,
-        closes: mpsc::Sender<()>,
-        connects: Arc<AtomicUsize>,
-        is_proxy: bool,
+        let res = client.get("http://hyper.local/a".parse().unwrap());
+        let err = runtime.block_on(res).unwrap_err();
+        assert!(err.is_pool_timeout(), "{:?}", err);
     }
 
-    impl DebugConnector {
-        fn new(handle: &Handle) -> DebugConnector {
-            let http = HttpConnector::new_with_handle(1, handle.clone());
-            let (tx, _) = mpsc::channel(10);
-            DebugConnector::with_http_and_closes(http, tx)
-        }
+    #[test]
+    fn request_config_pool_timeout() {
+        let _ = pretty_env_logger::try_init();
+        let mut runtime = Runtime::new().unwrap();
 
-        fn with_http_and_closes(http: HttpConnector, closes: mpsc::Sender<()>) -> DebugConnector {
-            DebugConnector {
-                http: http,
-                closes: closes,
-                connects: Arc::new(AtomicUsize::new(0)),
-                is_proxy: false,
-            }
-        }
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(NeverConnector);
 
-        fn proxy(mut self) -> Self {
-            self.is_proxy = true;
-            self
-        }
+        let mut req = Request::new(Body::empty());
+        *req.uri_mut() = "http://hyper.local/a".parse().unwrap();
+        req.extensions_mut().insert(RequestConfig::new().pool_timeout(Duration::from_millis(100)));
+        let err = runtime.block_on(client.request(req)).unwrap_err();
+        assert!(err.is_pool_timeout(), "{:?}", err);
     }
 
-    impl Connect for DebugConnector {
-        type Transport = DebugStream;
-        type Error = io::Error;
-        type Future = Box<Future<Item = (DebugStream, Connected), Error = io::Error> + Send>;
+    #[test]
+    fn request_config_bypasses_pool() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
 
-        fn connect(&self, dst: Destination) -> Self::Future {
-            self.connects.fetch_add(1, Ordering::SeqCst);
-            let closes = self.closes.clone();
-            let is_proxy = self.is_proxy;
-            Box::new(self.http.connect(dst).map(move |(s, c)| {
-                (DebugStream(s, closes), c.proxy(is_proxy))
-            }))
-        }
-    }
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
 
-    struct DebugStream(TcpStream, mpsc::Sender<()>);
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            for _ in 0..2 {
+                let mut sock = server.accept().unwrap().0;
+                sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+                let n = sock.read(&mut buf).expect("read request");
+                assert_ne!(n, 0);
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            }
+            let _ = tx1.send(());
+        });
 
-    impl Drop for DebugStream {
-        fn drop(&mut self) {
-            let _ = self.1.try_send(());
+        for _ in 0..2 {
+            let mut req = Request::new(Body::empty());
+            *req.uri_mut() = format!("http://{}/a", addr).parse().unwrap();
+            req.extensions_mut().insert(RequestConfig::new().pool(false));
+            let res = client.request(req).wait().unwrap();
+            assert_eq!(res.status(), hyper::StatusCode::OK);
         }
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(client.pool_stats().idle(), 0);
+
+        rx1.wait().expect("thread panicked");
     }
 
-    impl Write for DebugStream {
-        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-            self.0.write(buf)
+    #[test]
+    fn connection_close_request_bypasses_pool() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            // the first connection stays idle
+            let _idle = server.accept().unwrap().0;
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let n = sock.read(&mut buf).expect("read request");
+            assert_ne!(n, 0);
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            let _ = tx1.send(());
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        client.prepare(uri.clone()).wait().unwrap();
+        assert_eq!(client.pool_stats().idle(), 1);
+
+        let req = Request::builder()
+            .uri(uri)
+            .header("connection", "close")
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(client.pool_stats().idle(), 1);
+
+        rx1.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn replayable_request_retried_when_reused_connection_closes() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .retry_replayable_requests(true)
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let read_request = |sock: &mut ::std::net::TcpStream| {
+                sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+                let mut buf = [0; 4096];
+                let mut req = Vec::new();
+                while !req.ends_with(b"hello") {
+                    let n = sock.read(&mut buf).expect("read request");
+                    assert_ne!(n, 0);
+                    req.extend_from_slice(&buf[..n]);
+                }
+                String::from_utf8(req).unwrap()
+            };
+            // the reused connection closes without responding
+            let mut sock = server.accept().unwrap().0;
+            let req = read_request(&mut sock);
+            assert!(req.contains("\r\nX-Legacy-ID: 1\r\n"), "{:?}", req);
+            drop(sock);
+
+            // the extensions are kept when sending it again
+            let mut sock = server.accept().unwrap().0;
+            let req = read_request(&mut sock);
+            assert!(req.contains("\r\nX-Legacy-ID: 1\r\n"), "{:?}", req);
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            let _ = tx1.send(());
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        client.prepare(uri.clone()).wait().unwrap();
+        assert_eq!(client.pool_stats().idle(), 1);
+
+        let mut header_case = hyper::client::conn::HeaderCaseMap::new();
+        header_case.append("X-Legacy-ID").unwrap();
+        let req = Request::builder()
+            .method("PUT")
+            .uri(uri)
+            .header("x-legacy-id", "1")
+            .extension(header_case)
+            .body(Body::from("hello"))
+            .unwrap();
+        let res = client.request(req).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        rx1.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn hedge_after_sends_request_on_another_connection() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .hedge_after(Duration::from_millis(50))
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            // the first connection never responds
+            let mut slow = server.accept().unwrap().0;
+            slow.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let n = slow.read(&mut buf).expect("read request");
+            assert_ne!(n, 0);
+
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let n = sock.read(&mut buf).expect("read hedged request");
+            assert_ne!(n, 0);
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+
+            // the losing request is canceled, closing its connection
+            let n = slow.read(&mut buf).expect("read eof");
+            assert_eq!(n, 0);
+            let _ = tx1.send(());
+        });
+
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        rx1.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn coalesce_requests_shares_one_exchange() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .coalesce_requests(true)
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read request");
+            assert_ne!(n, 0);
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nshared").expect("write 200");
+            let _ = tx1.send(());
+        });
+
+        let get = || {
+            let req = Request::builder()
+                .uri(&*format!("http://{}/a", addr))
+                .body(Body::empty())
+                .unwrap();
+            client.request(req).and_then(|res| {
+                assert_eq!(res.status(), hyper::StatusCode::OK);
+                res.into_body().concat2()
+            })
+        };
+        let (a, b) = get().join(get()).wait().unwrap();
+        assert_eq!(a.as_ref(), b"shared");
+        assert_eq!(b.as_ref(), b"shared");
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+        rx1.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn resolve_connects_to_pinned_address() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+            // the Host still comes from the Uri
+            let expected = format!("host: canary.invalid:{}\r\n", addr.port());
+            assert!(s(&buf[..n]).contains(&expected), "{:?}", s(&buf[..n]));
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 1");
+            let _ = tx1.send(());
+        });
+
+        let mut req = Request::builder()
+            .uri(&*format!("http://canary.invalid:{}/a", addr.port()))
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut().insert(RequestConfig::new().resolve(addr));
+        let res = client.request(req).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+
+        rx1.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn send_window_paces_http2_body() {
+        use hyper::body::Payload;
+        use hyper::client::SendWindow;
+        use hyper::server::conn::Http;
+        use hyper::service::service_fn;
+
+        struct Zeroes {
+            len: usize,
+            window: SendWindow,
+        }
+
+        impl Payload for Zeroes {
+            type Data = hyper::Chunk;
+            type Error = hyper::Error;
+
+            fn poll_data(&mut self) -> futures::Poll<Option<hyper::Chunk>, hyper::Error> {
+                if self.len == 0 {
+                    return Ok(futures::Async::Ready(None));
+                }
+                let n = match self.window.poll_capacity(self.len) {
+                    futures::Async::Ready(Some(n)) => n,
+                    futures::Async::Ready(None) => panic!("send window closed early"),
+                    futures::Async::NotReady => return Ok(futures::Async::NotReady),
+                };
+                // never more than the initial window of the stream
+                assert!(n <= 65_535, "capacity {}", n);
+                self.len -= n;
+                Ok(futures::Async::Ready(Some(vec![0; n].into())))
+            }
+        }
+
+        let _ = pretty_env_logger::try_init();
+        let runtime = Runtime::new().unwrap();
+        let listener = ::tokio::net::TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(|(item, _incoming)| {
+                Http::new()
+                    .http2_only(true)
+                    .serve_connection(item.unwrap(), service_fn(|req: Request<Body>| {
+                        req.into_body()
+                            .concat2()
+                            .map(|body| hyper::Response::new(Body::from(body.len().to_string())))
+                    }))
+            })
+            .map_err(|e| panic!("server error: {}", e));
+        runtime.executor().spawn(server);
+
+        let client = Client::builder()
+            .http2_only(true)
+            .executor(runtime.executor())
+            .build_http::<Zeroes>();
+
+        let window = SendWindow::new();
+        let mut req = Request::new(Zeroes {
+            len: 200_000,
+            window: window.clone(),
+        });
+        *req.uri_mut() = format!("http://{}/a", addr).parse().unwrap();
+        req.extensions_mut().insert(window);
+
+        let body = client.request(req)
+            .and_then(|res| res.into_body().concat2())
+            .wait()
+            .unwrap();
+        assert_eq!(s(&body), "200000");
+    }
+
+    #[test]
+    fn http2_defer_stream_until_body_ready() {
+        use std::sync::{mpsc, Mutex};
+
+        use hyper::client::RequestConfig;
+        use hyper::server::conn::Http;
+        use hyper::service::service_fn;
+
+        let _ = pretty_env_logger::try_init();
+        let runtime = Runtime::new().unwrap();
+        let listener = ::tokio::net::TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (opened_tx, opened_rx) = mpsc::channel();
+        let opened_tx = Mutex::new(opened_tx);
+        let server = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(move |(item, _incoming)| {
+                Http::new()
+                    .http2_only(true)
+                    .serve_connection(item.unwrap(), service_fn(move |req: Request<Body>| {
+                        opened_tx.lock().unwrap().send(()).unwrap();
+                        req.into_body()
+                            .concat2()
+                            .map(|body| hyper::Response::new(Body::from(body)))
+                    }))
+            })
+            .map_err(|e| panic!("server error: {}", e));
+        runtime.executor().spawn(server);
+
+        let client = Client::builder()
+            .http2_only(true)
+            .executor(runtime.executor())
+            .build_http::<Body>();
+
+        // a headers-only request isn't deferred
+        let mut req = Request::new(Body::empty());
+        *req.uri_mut() = format!("http://{}/a", addr).parse().unwrap();
+        req.extensions_mut().insert(RequestConfig::new().http2_defer_stream(true));
+        client.request(req).wait().unwrap();
+        opened_rx.recv_timeout(Duration::from_secs(5)).expect("first stream");
+
+        let (mut body_tx, body) = Body::channel();
+        let mut req = Request::new(body);
+        *req.uri_mut() = format!("http://{}/b", addr).parse().unwrap();
+        req.extensions_mut().insert(RequestConfig::new().http2_defer_stream(true));
+        let res = client.request(req);
+        let (res_tx, res_rx) = oneshot::channel();
+        runtime.executor().spawn(res.then(|res| {
+            let _ = res_tx.send(res);
+            Ok(())
+        }));
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(opened_rx.try_recv().is_err(), "stream opened before body was ready");
+
+        body_tx.send_data("hello".into()).expect("send_data");
+        drop(body_tx);
+        let res = res_rx.wait().unwrap().unwrap();
+        opened_rx.recv_timeout(Duration::from_secs(5)).expect("second stream");
+        let body = res.into_body().concat2().wait().unwrap();
+        assert_eq!(s(&body), "hello");
+    }
+
+    #[test]
+    fn http2_trailers_only() {
+        use hyper::HeaderMap;
+        use hyper::body::Payload;
+        use hyper::server::conn::Http;
+        use hyper::service::service_fn;
+
+        // Reads all of a body, and then its trailers.
+        fn read_all(mut body: Body) -> Box<Future<Item=(Vec<u8>, Option<HeaderMap>), Error=hyper::Error> + Send> {
+            let mut data = Vec::new();
+            Box::new(futures::future::poll_fn(move || {
+                loop {
+                    match body.poll_data()? {
+                        futures::Async::Ready(Some(chunk)) => data.extend_from_slice(&chunk),
+                        futures::Async::Ready(None) => break,
+                        futures::Async::NotReady => return Ok(futures::Async::NotReady),
+                    }
+                }
+                body.poll_trailers()
+                    .map(|async| async.map(|trailers| (::std::mem::replace(&mut data, Vec::new()), trailers)))
+            }))
+        }
+
+        let _ = pretty_env_logger::try_init();
+        let runtime = Runtime::new().unwrap();
+        let listener = ::tokio::net::TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(|(item, _incoming)| {
+                Http::new()
+                    .http2_only(true)
+                    .serve_connection(item.unwrap(), service_fn(|req: Request<Body>| {
+                        assert!(!req.body().is_end_stream(), "trailers are still to come");
+                        read_all(req.into_body()).map(|(data, trailers)| {
+                            assert_eq!(data, b"");
+                            let trailers = trailers.expect("request trailers");
+                            assert_eq!(trailers["grpc-end"], "client");
+                            let mut trailers = HeaderMap::new();
+                            trailers.insert("grpc-status", "0".parse().unwrap());
+                            hyper::Response::new(Body::trailers_only(trailers))
+                        })
+                    }))
+            })
+            .map_err(|e| panic!("server error: {}", e));
+        runtime.executor().spawn(server);
+
+        let client = Client::builder()
+            .http2_only(true)
+            .executor(runtime.executor())
+            .build_http::<Body>();
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-end", "client".parse().unwrap());
+        let mut req = Request::new(Body::trailers_only(trailers));
+        *req.uri_mut() = format!("http://{}/a", addr).parse().unwrap();
+
+        let (data, trailers) = client.request(req)
+            .and_then(|res| read_all(res.into_body()))
+            .wait()
+            .unwrap();
+        assert_eq!(data, b"");
+        assert_eq!(trailers.expect("response trailers")["grpc-status"], "0");
+    }
+
+    #[test]
+    fn cancel_token_aborts_http1_request() {
+        use hyper::client::CancelToken;
+
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build(DebugConnector::new(runtime.reactor()));
+
+        let token = CancelToken::new();
+        let token2 = token.clone();
+        let (closed_tx, closed_rx) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read request");
+            // never respond, and wait for the client to give up
+            token2.cancel();
+            let n = sock.read(&mut buf).expect("read eof");
+            assert_eq!(n, 0);
+            let _ = closed_tx.send(());
+        });
+
+        let mut req = Request::new(Body::empty());
+        *req.uri_mut() = format!("http://{}/a", addr).parse().unwrap();
+        req.extensions_mut().insert(token);
+        let err = client.request(req).wait().unwrap_err();
+        assert!(err.is_canceled(), "{:?}", err);
+        closed_rx.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn cancel_token_resets_http2_stream() {
+        use futures::future::{self, Either};
+        use hyper::client::CancelToken;
+        use hyper::server::conn::Http;
+        use hyper::service::service_fn;
+
+        let _ = pretty_env_logger::try_init();
+        let runtime = Runtime::new().unwrap();
+        let listener = ::tokio::net::TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(|(item, _incoming)| {
+                Http::new()
+                    .http2_only(true)
+                    .serve_connection(item.unwrap(), service_fn(|req: Request<Body>| {
+                        if req.uri().path() == "/slow" {
+                            Either::A(future::empty())
+                        } else {
+                            Either::B(future::ok::<_, hyper::Error>(hyper::Response::new(Body::empty())))
+                        }
+                    }))
+            })
+            .map_err(|e| panic!("server error: {}", e));
+        runtime.executor().spawn(server);
+
+        let connector = DebugConnector::new(runtime.reactor()).h2();
+        let connects = connector.connects.clone();
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let token = CancelToken::new();
+        let mut req = Request::new(Body::empty());
+        *req.uri_mut() = format!("http://{}/slow", addr).parse().unwrap();
+        req.extensions_mut().insert(token.clone());
+        let res = client.request(req);
+        let (res_tx, res_rx) = oneshot::channel();
+        runtime.executor().spawn(res.then(|res| {
+            let _ = res_tx.send(res);
+            Ok(())
+        }));
+
+        thread::sleep(Duration::from_millis(100));
+        token.cancel();
+        let err = res_rx.wait().unwrap().unwrap_err();
+        assert!(err.is_canceled(), "{:?}", err);
+
+        // only the stream was reset, the connection is still used
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        client.get(uri).wait().unwrap();
+        assert_eq!(connects.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn max_requests_per_host_counts_http2_streams() {
+        use std::sync::Mutex;
+
+        use hyper::server::conn::Http;
+        use hyper::service::service_fn_ok;
+
+        let _ = pretty_env_logger::try_init();
+        let runtime = Runtime::new().unwrap();
+        let listener = ::tokio::net::TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        // the response bodies never end, until the senders are dropped
+        let senders = Arc::new(Mutex::new(Vec::new()));
+        let senders2 = senders.clone();
+        let server = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(move |(item, _incoming)| {
+                Http::new()
+                    .http2_only(true)
+                    .serve_connection(item.unwrap(), service_fn_ok(move |_req| {
+                        let (tx, body) = Body::channel();
+                        senders2.lock().unwrap().push(tx);
+                        hyper::Response::new(body)
+                    }))
+            })
+            .map_err(|e| panic!("server error: {}", e));
+        runtime.executor().spawn(server);
+
+        let client = Client::builder()
+            .http2_only(true)
+            .max_requests_per_host(1)
+            .pool_fail_fast(true)
+            .executor(runtime.executor())
+            .build_http::<Body>();
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        let res1 = client.get(uri.clone()).wait().unwrap();
+
+        let err = client.get(uri.clone()).wait().unwrap_err();
+        assert!(err.is_pool_full(), "{:?}", err);
+
+        // the first request is done once its body is
+        drop(res1);
+        client.get(uri).wait().unwrap();
+        drop(senders);
+    }
+
+    #[test]
+    fn circuit_breaker_fails_fast_after_server_errors() {
+        use hyper::server::conn::Http;
+        use hyper::service::service_fn_ok;
+
+        let _ = pretty_env_logger::try_init();
+        let runtime = Runtime::new().unwrap();
+        let listener = ::tokio::net::TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let served = Arc::new(AtomicUsize::new(0));
+        let served2 = served.clone();
+        let exec = runtime.executor();
+        let server = listener.incoming()
+            .map_err(|e| panic!("accept error: {}", e))
+            .for_each(move |sock| {
+                let served = served2.clone();
+                let conn = Http::new()
+                    .serve_connection(sock, service_fn_ok(move |_req| {
+                        served.fetch_add(1, Ordering::SeqCst);
+                        hyper::Response::builder()
+                            .status(503)
+                            .body(Body::empty())
+                            .unwrap()
+                    }))
+                    .map_err(|e| panic!("server error: {}", e));
+                exec.spawn(conn);
+                Ok(())
+            });
+        runtime.executor().spawn(server);
+
+        let client = Client::builder()
+            .circuit_breaker((2, Duration::from_secs(60)))
+            .executor(runtime.executor())
+            .build_http::<Body>();
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        for _ in 0..2 {
+            let res = client.get(uri.clone()).wait().unwrap();
+            assert_eq!(res.status(), 503);
+            res.into_body().concat2().wait().unwrap();
         }
 
-        fn flush(&mut self) -> io::Result<()> {
-            self.0.flush()
-        }
-    }
+        let err = client.get(uri).wait().unwrap_err();
+        assert!(err.is_circuit_open(), "{:?}", err);
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn connect_error_tells_phase_and_addr() {
+        use hyper::client::connect::ConnectPhase;
+
+        let _ = pretty_env_logger::try_init();
+        let runtime = Runtime::new().unwrap();
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build_http::<Body>();
+
+        // nothing listening on the port anymore
+        let closed = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let uri = format!("http://{}/a", closed).parse::<hyper::Uri>().unwrap();
+        let err = client.get(uri).wait().unwrap_err();
+        assert!(err.is_connect(), "{:?}", err);
+        let connect_err = err.connect_error().expect("connect_error");
+        assert_eq!(connect_err.phase(), ConnectPhase::Tcp);
+        assert_eq!(connect_err.addr(), Some(closed));
+    }
+
+    #[test]
+    fn connector_negotiated_h2_is_shared() {
+        use hyper::server::conn::Http;
+        use hyper::service::service_fn_ok;
+
+        let _ = pretty_env_logger::try_init();
+        let runtime = Runtime::new().unwrap();
+        let listener = ::tokio::net::TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(|(item, _incoming)| {
+                Http::new()
+                    .http2_only(true)
+                    .serve_connection(item.unwrap(), service_fn_ok(|_req| {
+                        hyper::Response::new(Body::empty())
+                    }))
+            })
+            .map_err(|e| panic!("server error: {}", e));
+        runtime.executor().spawn(server);
+
+        // The client isn't `http2_only`, but the connector says the
+        // connection speaks HTTP/2, like after ALPN.
+        let connector = DebugConnector::new(runtime.reactor()).h2();
+        let connects = connector.connects.clone();
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        let res = client.get(uri.clone()).wait().unwrap();
+        assert_eq!(res.version(), hyper::Version::HTTP_2);
+
+        // the server only accepts one connection
+        let res = client.get(uri).wait().unwrap();
+        assert_eq!(res.version(), hyper::Version::HTTP_2);
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn connector_no_longer_negotiating_h2_is_forgotten() {
+        use hyper::client::RequestConfig;
+        use hyper::server::conn::Http;
+        use hyper::service::service_fn_ok;
+
+        let _ = pretty_env_logger::try_init();
+        let runtime = Runtime::new().unwrap();
+        let listener = ::tokio::net::TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let exec = runtime.executor();
+        let server = listener.incoming()
+            .map_err(|e| panic!("accept error: {}", e))
+            .for_each(move |sock| {
+                // Serves both HTTP/1 and HTTP/2.
+                let conn = Http::new()
+                    .serve_connection(sock, service_fn_ok(|_req| {
+                        hyper::Response::new(Body::empty())
+                    }))
+                    .map_err(|e| panic!("server error: {}", e));
+                exec.spawn(conn);
+                Ok(())
+            });
+        runtime.executor().spawn(server);
+
+        let connector = DebugConnector::new(runtime.reactor()).h2();
+        let is_h2 = connector.is_h2.clone();
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        let res = client.get(uri.clone()).wait().unwrap();
+        assert_eq!(res.version(), hyper::Version::HTTP_2);
+
+        // Like ALPN picking HTTP/1.1 now, on a new connection.
+        is_h2.store(false, Ordering::SeqCst);
+        let mut req = Request::new(Body::empty());
+        *req.uri_mut() = uri.clone();
+        req.extensions_mut().insert(RequestConfig::new().pool(false));
+        client.request(req).wait().unwrap();
+
+        let res = client.get(uri).wait().unwrap();
+        assert_eq!(res.version(), hyper::Version::HTTP_11);
+    }
+
+    #[test]
+    fn pool_key_separates_connections() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        #[derive(Clone)]
+        struct Cert(&'static str);
+
+        let client = Client::builder()
+            .pool_key(|uri, extensions| {
+                let cert = extensions.get::<Cert>().map(|cert| cert.0).unwrap_or("none");
+                format!("{}#{}", uri.authority_part().unwrap(), cert)
+            })
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let mut socks = Vec::new();
+            for _ in 0..2 {
+                let sock = server.accept().unwrap().0;
+                sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+                socks.push(sock);
+            }
+            // the prepared connection is only used by the last request
+            for sock in socks.iter_mut().rev() {
+                let n = sock.read(&mut buf).expect("read request");
+                assert_ne!(n, 0);
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            }
+            let _ = tx1.send(());
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        client.prepare(uri.clone()).wait().unwrap();
+        assert_eq!(client.pool_stats().idle(), 1);
+
+        let mut req = Request::new(Body::empty());
+        *req.uri_mut() = uri.clone();
+        req.extensions_mut().insert(Cert("a"));
+        let res = client.request(req).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        let res = client.get(uri).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        rx1.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn pool_key_keeps_resolved_requests_apart() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .pool_key(|uri, _| uri.authority_part().unwrap().to_string())
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let _prepared = server.accept().unwrap().0;
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let n = sock.read(&mut buf).expect("read request");
+            assert_ne!(n, 0);
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            let _ = tx1.send(());
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        client.prepare(uri.clone()).wait().unwrap();
+        assert_eq!(client.pool_stats().idle(), 1);
+
+        // The same key, but pinned to an address, so the prepared
+        // connection isn't used.
+        let mut req = Request::new(Body::empty());
+        *req.uri_mut() = uri;
+        req.extensions_mut().insert(RequestConfig::new().resolve(addr));
+        let res = client.request(req).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        rx1.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn pool_refresh_before_lifetime_replaces_in_background() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .pool_max_connection_lifetime(Duration::from_secs(10))
+            .pool_refresh_before_lifetime(Duration::from_secs(10))
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            for _ in 0..2 {
+                let n = sock.read(&mut buf).expect("read request");
+                assert_ne!(n, 0);
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            }
+            // the replacement
+            let sock2 = server.accept().unwrap().0;
+            let _ = tx1.send((sock, sock2));
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        let res = client.get(uri.clone()).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+        // wait for the connection to get back into the pool
+        let started = Instant::now();
+        while client.pool_stats().idle() == 0 {
+            assert!(started.elapsed() < Duration::from_secs(5), "connection never went idle");
+            thread::yield_now();
+        }
+
+        // served on the old connection, while a new one is made
+        let res = client.get(uri).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        // keep both sockets open until the end of the test
+        let _socks = rx1.wait().expect("thread panicked");
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        let started = Instant::now();
+        while client.pool_stats().idle() < 2 {
+            assert!(started.elapsed() < Duration::from_secs(5), "replacement never went idle");
+            thread::yield_now();
+        }
+        assert_eq!(client.pool_stats().idle(), 2);
+    }
+
+    #[test]
+    fn prepare_parks_idle_connection() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let n = sock.read(&mut buf).expect("read request");
+            assert_ne!(n, 0);
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            let _ = tx1.send(());
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        client.prepare(uri.clone()).wait().unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+        assert_eq!(client.pool_stats().idle(), 1);
+
+        let res = client.get(uri).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+        rx1.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn http1_max_body_buffer_splits_chunks() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+
+        let client = Client::builder()
+            .http1_max_body_buffer(4096)
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        const BODY_LEN: usize = 64 * 1024;
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let n = sock.read(&mut buf).expect("read request");
+            assert_ne!(n, 0);
+            let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", BODY_LEN);
+            sock.write_all(head.as_bytes()).expect("write head");
+            sock.write_all(&[b'x'; BODY_LEN]).expect("write body");
+            let _ = tx1.send(());
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        let res = client.get(uri).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        let len = res.into_body()
+            .fold(0, |len, chunk| {
+                assert!(chunk.len() <= 4096, "chunk of {} bytes", chunk.len());
+                Ok::<_, hyper::Error>(len + chunk.len())
+            })
+            .wait()
+            .unwrap();
+        assert_eq!(len, BODY_LEN);
+
+        rx1.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn shutdown_closes_idle_connections() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let n = sock.read(&mut buf).expect("read request");
+            assert_ne!(n, 0);
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            let n = sock.read(&mut buf).expect("read eof");
+            assert_eq!(n, 0, "connection closed");
+            let _ = tx1.send(());
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        let res = client.get(uri.clone()).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        res.into_body().concat2().wait().unwrap();
+
+        client.shutdown().wait().unwrap();
+        assert_eq!(client.pool_stats().idle(), 0);
+        rx1.wait().expect("thread panicked");
+
+        let err = client.get(uri).wait().unwrap_err();
+        assert!(err.is_pool_closed(), "{:?}", err);
+    }
+
+    #[test]
+    fn pool_health_check_skips_closed_connection() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .pool_health_check(true)
+            .retry_canceled_requests(false)
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (closed_tx, closed_rx) = oneshot::channel();
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 1");
+            // close without saying so in the response
+            drop(sock);
+            let _ = closed_tx.send(());
+
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.read(&mut buf).expect("read 2");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 2");
+            let _ = tx1.send(());
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        let res = client.get(uri.clone()).join(closed_rx.map_err(|_| unreachable!())).wait().unwrap().0;
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+
+        let res = client.get(uri).join(rx1.map_err(|_| unreachable!())).wait().unwrap().0;
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn pool_health_check_hook_rejects_idle() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .pool_health_check_hook(|conn| !conn.host().starts_with("http://127.0.0.1"))
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            // keep the sockets open, so only the hook closes the first
+            let mut socks = Vec::new();
+            for _ in 0..2 {
+                let mut sock = server.accept().unwrap().0;
+                sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                sock.read(&mut buf).expect("read request");
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+                socks.push(sock);
+            }
+            let _ = tx1.send(socks);
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        client.get(uri.clone()).wait().unwrap();
+        // let the connection get back into the pool
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(client.pool_stats().idle(), 1);
+
+        client.get(uri).join(rx1.map_err(|_| unreachable!())).wait().unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(client.pool_stats().evicted(), 1);
+    }
+
+    #[test]
+    fn pool_event_hook_reports_checkouts() {
+        use std::sync::Mutex;
+        use hyper::client::{CheckoutOutcome, PoolEvent};
+
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+
+        let outcomes = Arc::new(Mutex::new(Vec::new()));
+        let seen = outcomes.clone();
+        let client = Client::builder()
+            .pool_event_hook(move |event| {
+                if let PoolEvent::Checkout { outcome, .. } = *event {
+                    seen.lock().unwrap().push(outcome);
+                }
+            })
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            for _ in 0..2 {
+                sock.read(&mut buf).expect("read request");
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            }
+            let _ = tx1.send(sock);
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        client.get(uri.clone()).wait().unwrap();
+        // wait for the connection to get back into the pool
+        let started = Instant::now();
+        while client.pool_stats().idle() == 0 {
+            assert!(started.elapsed() < Duration::from_secs(5), "connection never went idle");
+            thread::yield_now();
+        }
+
+        client.get(uri).join(rx1.map_err(|_| unreachable!())).wait().unwrap();
+        assert_eq!(
+            *outcomes.lock().unwrap(),
+            vec![CheckoutOutcome::Connected, CheckoutOutcome::Reused]
+        );
+    }
+
+    #[test]
+    fn connected_extra_in_response_extensions() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            for _ in 0..2 {
+                sock.read(&mut buf).expect("read request");
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            }
+            let _ = tx1.send(sock);
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        let res = client.get(uri.clone()).wait().unwrap();
+        assert_eq!(res.extensions().get::<NthConnection>(), Some(&NthConnection(1)));
+        // let the connection get back into the pool
+        thread::sleep(Duration::from_millis(50));
+
+        let (res, _) = client.get(uri).join(rx1.map_err(|_| unreachable!())).wait().unwrap();
+        assert_eq!(res.extensions().get::<NthConnection>(), Some(&NthConnection(1)));
+    }
+
+    #[test]
+    fn connection_stats_in_response_extensions() {
+        use hyper::client::conn::{ConnectionStats, ExchangeStats};
+
+        const RES: &'static [u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read request");
+            sock.write_all(RES).expect("write 200");
+            let _ = tx1.send(n);
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        let (res, written) = client.get(uri).join(rx1.map_err(|_| unreachable!())).wait().unwrap();
+        let conn = res.extensions().get::<ConnectionStats>().cloned().expect("connection stats");
+        let exchange = res.extensions().get::<ExchangeStats>().cloned().expect("exchange stats");
+        res.into_body().concat2().wait().unwrap();
+
+        assert_eq!(conn.requests(), 1);
+        assert_eq!(conn.bytes_written(), written);
+        assert_eq!(exchange.bytes_written(), written);
+        assert_eq!(conn.bytes_read(), RES.len());
+        assert_eq!(exchange.bytes_read(), RES.len());
+    }
+
+    #[test]
+    fn early_data_only_for_idempotent_requests() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+        let early_data = connector.early_data.clone();
+
+        let client = Client::builder()
+            .http1_early_data(true)
+            .executor(runtime.executor())
+            .build(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            for _ in 0..2 {
+                let mut sock = server.accept().unwrap().0;
+                sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                let mut buf = [0; 4096];
+                sock.read(&mut buf).expect("read request");
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").expect("write 200");
+            }
+            let _ = tx1.send(());
+        });
+
+        let uri = format!("http://{}/a", addr);
+        let req = Request::builder()
+            .method("POST")
+            .uri(&*uri)
+            .body(Body::from("hello"))
+            .unwrap();
+        client.request(req).wait().unwrap();
+        assert_eq!(early_data.load(Ordering::SeqCst), 0);
+
+        let req = Request::builder()
+            .uri(&*uri)
+            .body(Body::empty())
+            .unwrap();
+        client.request(req).join(rx1.map_err(|_| unreachable!())).wait().unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(early_data.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn checkout_strategy_prefer_idle_waits_for_busy() {
+        use hyper::client::CheckoutStrategy;
+
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .pool_checkout_strategy(CheckoutStrategy::PreferIdle)
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (read1_tx, read1_rx) = ::std::sync::mpsc::channel();
+        let (write1_tx, write1_rx) = ::std::sync::mpsc::channel::<()>();
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.read(&mut buf).expect("read 1");
+            read1_tx.send(()).unwrap();
+            write1_rx.recv().unwrap();
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 1");
+            sock.read(&mut buf).expect("read 2");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 2");
+            let _ = tx1.send(sock);
+        });
+
+        let (res1_tx, res1_rx) = oneshot::channel();
+        let res1 = client.get(format!("http://{}/a", addr).parse().unwrap());
+        runtime.spawn(res1.then(move |res| {
+            let _ = res1_tx.send(res.map(|res| res.status()));
+            Ok(())
+        }));
+        read1_rx.recv().unwrap();
+
+        // the first connection is busy, so this waits for it
+        let mut res2 = client.get(format!("http://{}/b", addr).parse().unwrap());
+        let res2 = runtime.block_on(futures::future::lazy(move || {
+            assert!(res2.poll()?.is_not_ready());
+            write1_tx.send(()).unwrap();
+            Ok::<_, hyper::Error>(res2)
+        })).unwrap();
+        let (status1, status2) = runtime.block_on(res1_rx.map_err(|_| unreachable!()).join(res2)).unwrap();
+        assert_eq!(status1.unwrap(), hyper::StatusCode::OK);
+        assert_eq!(status2.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+        rx1.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn max_connections_per_host_queues_requests() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .max_connections_per_host(1)
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            for _ in 0..2 {
+                let n = sock.read(&mut buf).expect("read request");
+                assert_ne!(n, 0);
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            }
+            let _ = tx1.send(());
+        });
+
+        let rx = rx1.expect("thread panicked");
+        let res1 = client.get(format!("http://{}/a", addr).parse().unwrap());
+        let res2 = client.get(format!("http://{}/b", addr).parse().unwrap());
+        res1.join(res2).join(rx).wait().unwrap();
+
+        assert_eq!(connects.load(Ordering::SeqCst), 1, "second request should wait for the first connection");
+    }
+
+    #[test]
+    fn max_total_connections_fail_fast() {
+        let _ = pretty_env_logger::try_init();
+        let mut runtime = Runtime::new().unwrap();
+
+        let client = Client::builder()
+            .max_total_connections(1)
+            .pool_fail_fast(true)
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(NeverConnector);
+
+        // the first request holds the only connection, which never finishes
+        // connecting
+        let res1 = client.get("http://hyper.local/a".parse().unwrap());
+        let res2 = client.get("http://hyper.local/b".parse().unwrap());
+        match runtime.block_on(res1.select2(res2)) {
+            Err(futures::future::Either::B((err, _res1))) => assert!(err.is_pool_full(), "{:?}", err),
+            Ok(_) => panic!("requests should not finish"),
+            Err(futures::future::Either::A((err, _))) => panic!("first request failed: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn http2_fallback_to_http1() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let handle = runtime.reactor();
+        let connector = DebugConnector::new(&handle);
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .http2_fallback(true)
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            // the first connection only speaks HTTP/1, and rejects the preface
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read preface");
+            assert!(s(&buf[..n]).starts_with("PRI * HTTP/2.0\r\n"));
+            sock.write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n").expect("write 400");
+            drop(sock);
+
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            for _ in 0..2 {
+                let n = sock.read(&mut buf).expect("read request");
+                let expected = format!("GET /a HTTP/1.1\r\nhost: {addr}\r\n\r\n", addr=addr);
+                assert_eq!(s(&buf[..n]), expected);
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            }
+            let _ = tx1.send(());
+        });
+
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        let res = client.get(uri.clone()).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        // the host is remembered as HTTP/1, so the idle connection is reused,
+        // once it is back in the pool
+        for _ in 0..100 {
+            if client.pool_stats().idle() == 1 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let res = client.get(uri).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        rx1.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn http2_fallback_timeout_falls_back_to_http1() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+        let handle = runtime.reactor();
+        let connector = DebugConnector::new(&handle);
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .http2_fallback(true)
+            .http2_fallback_timeout(Duration::from_millis(100))
+            .executor(runtime.executor())
+            .build::<_, hyper::Body>(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            // the first connection reads the preface, and never answers
+            let mut silent = server.accept().unwrap().0;
+            silent.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = silent.read(&mut buf).expect("read preface");
+            assert!(s(&buf[..n]).starts_with("PRI * HTTP/2.0\r\n"));
+
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let n = sock.read(&mut buf).expect("read request");
+            let expected = format!("GET /a HTTP/1.1\r\nhost: {addr}\r\n\r\n", addr=addr);
+            assert_eq!(s(&buf[..n]), expected);
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 200");
+            let _ = tx1.send(());
+            drop(silent);
+        });
+
+        // the timeout needs the timer of the runtime
+        let uri = format!("http://{}/a", addr).parse::<hyper::Uri>().unwrap();
+        let res = runtime.block_on(client.get(uri)).unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        rx1.wait().expect("thread panicked");
+    }
+
+
+    // A connector that never finishes connecting.
+    struct NeverConnector;
+
+    impl Connect for NeverConnector {
+        type Transport = TcpStream;
+        type Error = io::Error;
+        type Future = futures::future::Empty<(TcpStream, Connected), io::Error>;
+
+        fn connect(&self, _dst: Destination) -> Self::Future {
+            futures::future::empty()
+        }
+    }
+
+    struct DebugConnector {
+        http: HttpConnector,
+        closes: mpsc::Sender<()>,
+        connects: Arc<AtomicUsize>,
+        early_data: Arc<AtomicUsize>,
+        is_h2: Arc<AtomicBool>,
+        is_proxy: bool,
+    }
+
+    impl DebugConnector {
+        fn new(handle: &Handle) -> DebugConnector {
+            let http = HttpConnector::new_with_handle(1, handle.clone());
+            let (tx, _) = mpsc::channel(10);
+            DebugConnector::with_http_and_closes(http, tx)
+        }
+
+        fn with_http_and_closes(http: HttpConnector, closes: mpsc::Sender<()>) -> DebugConnector {
+            DebugConnector {
+                http: http,
+                closes: closes,
+                connects: Arc::new(AtomicUsize::new(0)),
+                early_data: Arc::new(AtomicUsize::new(0)),
+                is_h2: Arc::new(AtomicBool::new(false)),
+                is_proxy: false,
+            }
+        }
+
+        fn h2(self) -> Self {
+            self.is_h2.store(true, Ordering::SeqCst);
+            self
+        }
+
+        fn proxy(mut self) -> Self {
+            self.is_proxy = true;
+            self
+        }
+    }
+
+    impl Connect for DebugConnector {
+        type Transport = DebugStream;
+        type Error = io::Error;
+        type Future = Box<Future<Item = (DebugStream, Connected), Error = io::Error> + Send>;
+
+        fn connect(&self, dst: Destination) -> Self::Future {
+            let nth = self.connects.fetch_add(1, Ordering::SeqCst) + 1;
+            if dst.early_data() {
+                self.early_data.fetch_add(1, Ordering::SeqCst);
+            }
+            let closes = self.closes.clone();
+            let is_h2 = self.is_h2.load(Ordering::SeqCst);
+            let is_proxy = self.is_proxy;
+            Box::new(self.http.connect(dst).map(move |(s, c)| {
+                let c = if is_h2 { c.h2() } else { c };
+                (DebugStream(s, closes), c.proxy(is_proxy).extra(NthConnection(nth)))
+            }))
+        }
+    }
+
+    // Put in the extensions of responses, with `Connected::extra`.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct NthConnection(usize);
+
+    struct DebugStream(TcpStream, mpsc::Sender<()>);
+
+    impl Drop for DebugStream {
+        fn drop(&mut self) {
+            let _ = self.1.try_send(());
+        }
+    }
+
+    impl Write for DebugStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl AsyncWrite for DebugStream {
+        fn shutdown(&mut self) -> futures::Poll<(), io::Error> {
+            AsyncWrite::shutdown(&mut self.0)
+        }
+
+        fn write_buf<B: ::bytes::Buf>(&mut self, buf: &mut B) -> futures::Poll<usize, io::Error> {
+            self.0.write_buf(buf)
+        }
+    }
+
+    impl Read for DebugStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl AsyncRead for DebugStream {}
+}
+
+mod conn {
+    use std::io::{self, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::{Async, Future, Poll, Stream};
+    use futures::future::poll_fn;
+    use futures::sync::oneshot;
+    use futures_timer::Delay;
+    use tokio::runtime::Runtime;
+    use tokio::net::TcpStream;
+    use tokio_io::{AsyncRead, AsyncWrite};
+
+    use hyper::{self, Request};
+    use hyper::client::conn;
+    use hyper::client::RequestConfig;
+
+    use super::{s, tcp_connect, FutureHyperExt};
+
+    #[test]
+    fn get() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx1, rx1) = oneshot::channel();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+
+            // Notably:
+            // - Just a path, since just a path was set
+            // - No host, since no host was set
+            let expected = "GET /a HTTP/1.1\r\n\r\n";
+            assert_eq!(s(&buf[..n]), expected);
+
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+            let _ = tx1.send(());
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::handshake(tcp).wait().unwrap();
+
+        runtime.spawn(conn.map(|_| ()).map_err(|e| panic!("conn error: {}", e)));
+
+        let req = Request::builder()
+            .uri("/a")
+            .body(Default::default())
+            .unwrap();
+        let res = client.send_request(req).and_then(move |res| {
+            assert_eq!(res.status(), hyper::StatusCode::OK);
+            res.into_body().concat2()
+        });
+        let rx = rx1.expect("thread panicked");
+
+        let timeout = Delay::new(Duration::from_millis(200));
+        let rx = rx.and_then(move |_| timeout.expect("timeout"));
+        res.join(rx).map(|r| r.0).wait().unwrap();
+    }
+
+    #[test]
+    fn send_request_parts_returns_body_if_not_ready() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        // The connection is never polled, so after the first request is
+        // buffered, it cannot accept another one.
+        let (mut client, _conn) = conn::handshake(tcp).wait().unwrap();
+
+        let (head, body) = Request::builder()
+            .uri("/a")
+            .body(hyper::Body::from("first"))
+            .unwrap()
+            .into_parts();
+        let _res = client.send_request_parts(head, body).expect("first request");
+
+        let (head, body) = Request::builder()
+            .uri("/b")
+            .body(hyper::Body::from("second"))
+            .unwrap()
+            .into_parts();
+        let (err, head, body) = client.send_request_parts(head, body).unwrap_err();
+        assert!(err.is_canceled());
+        assert_eq!(head.uri, "/b");
+        assert_eq!(body.concat2().wait().unwrap().as_ref(), b"second");
+    }
+
+    #[test]
+    fn try_send_request_returns_request_if_closed() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::handshake(tcp).wait().unwrap();
+        drop(conn);
+
+        let req = Request::builder()
+            .uri("/a")
+            .body(hyper::Body::from("first"))
+            .unwrap();
+        let (err, req) = client.try_send_request(req).wait().unwrap_err();
+        assert!(err.is_canceled());
+        let req = req.expect("request given back");
+        assert_eq!(req.uri(), "/a");
+        assert_eq!(req.into_body().concat2().wait().unwrap().as_ref(), b"first");
+    }
+
+    #[test]
+    fn uri_absolute_form() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx1, rx1) = oneshot::channel();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+
+            // Notably:
+            // - Still no Host header, since it wasn't set
+            let expected = "GET http://hyper.local/a HTTP/1.1\r\n\r\n";
+            assert_eq!(s(&buf[..n]), expected);
+
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+            let _ = tx1.send(());
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::handshake(tcp).wait().unwrap();
+
+        runtime.spawn(conn.map(|_| ()).map_err(|e| panic!("conn error: {}", e)));
+
+        let req = Request::builder()
+            .uri("http://hyper.local/a")
+            .body(Default::default())
+            .unwrap();
+
+        let res = client.send_request(req).and_then(move |res| {
+            assert_eq!(res.status(), hyper::StatusCode::OK);
+            res.into_body().concat2()
+        });
+        let rx = rx1.expect("thread panicked");
+
+        let timeout = Delay::new(Duration::from_millis(200));
+        let rx = rx.and_then(move |_| timeout.expect("timeout"));
+        res.join(rx).map(|r| r.0).wait().unwrap();
+    }
+
+    #[test]
+    fn pipeline() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx1, rx1) = oneshot::channel();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+
+            let _ = tx1.send(());
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::handshake(tcp).wait().unwrap();
+
+        runtime.spawn(conn.map(|_| ()).map_err(|e| panic!("conn error: {}", e)));
+
+        let req = Request::builder()
+            .uri("/a")
+            .body(Default::default())
+            .unwrap();
+        let res1 = client.send_request(req).and_then(move |res| {
+            assert_eq!(res.status(), hyper::StatusCode::OK);
+            res.into_body().concat2()
+        });
+
+        // pipelined request will hit NotReady, and thus should return an Error::Cancel
+        let req = Request::builder()
+            .uri("/b")
+            .body(Default::default())
+            .unwrap();
+        let res2 = client.send_request(req)
+            .then(|result| {
+                let err = result.expect_err("res2");
+                assert!(err.is_canceled(), "err not canceled, {:?}", err);
+                Ok(())
+            });
+
+        let rx = rx1.expect("thread panicked");
+
+        let timeout = Delay::new(Duration::from_millis(200));
+        let rx = rx.and_then(move |_| timeout.expect("timeout"));
+        res1.join(res2).join(rx).map(|r| r.0).wait().unwrap();
+    }
+
+    #[test]
+    fn http1_queue_depth() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx1, rx1) = oneshot::channel();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            for path in &["/a", "/b"] {
+                // the queued request is only written after the response
+                let n = sock.read(&mut buf).expect("read");
+                let expected = format!("GET {} HTTP/1.1\r\n\r\n", path);
+                assert_eq!(s(&buf[..n]), expected);
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+            }
+            let _ = tx1.send(());
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::Builder::new()
+            .http1_queue_depth(2)
+            .handshake::<_, hyper::Body>(tcp)
+            .wait()
+            .unwrap();
+
+        runtime.spawn(conn.map(|_| ()).map_err(|e| panic!("conn error: {}", e)));
+
+        let req = Request::builder()
+            .uri("/a")
+            .body(Default::default())
+            .unwrap();
+        let res1 = client.send_request(req).and_then(move |res| {
+            assert_eq!(res.status(), hyper::StatusCode::OK);
+            res.into_body().concat2()
+        });
+
+        // queued behind the first request, instead of being canceled
+        let req = Request::builder()
+            .uri("/b")
+            .body(Default::default())
+            .unwrap();
+        let res2 = client.send_request(req).and_then(move |res| {
+            assert_eq!(res.status(), hyper::StatusCode::OK);
+            res.into_body().concat2()
+        });
+
+        let rx = rx1.expect("thread panicked");
+
+        let timeout = Delay::new(Duration::from_millis(200));
+        let rx = rx.and_then(move |_| timeout.expect("timeout"));
+        res1.join(res2).join(rx).map(|r| r.0).wait().unwrap();
+    }
+
+    #[test]
+    fn ready_waits_until_request_can_be_sent() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx1, rx1) = oneshot::channel();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            for path in &["/a", "/b"] {
+                let n = sock.read(&mut buf).expect("read");
+                let expected = format!("GET {} HTTP/1.1\r\n\r\n", path);
+                assert_eq!(s(&buf[..n]), expected);
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+            }
+            let _ = tx1.send(());
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::handshake(tcp).wait().unwrap();
+
+        runtime.spawn(conn.map(|_| ()).map_err(|e| panic!("conn error: {}", e)));
+
+        let req = Request::builder()
+            .uri("/a")
+            .body(Default::default())
+            .unwrap();
+        let res1 = client.send_request(req).and_then(move |res| {
+            assert_eq!(res.status(), hyper::StatusCode::OK);
+            res.into_body().concat2()
+        });
+
+        // the connection isn't ready until the first response is received
+        let req = Request::builder()
+            .uri("/b")
+            .body(Default::default())
+            .unwrap();
+        let res2 = client.ready()
+            .and_then(move |mut client| client.send_request(req))
+            .and_then(move |res| {
+                assert_eq!(res.status(), hyper::StatusCode::OK);
+                res.into_body().concat2()
+            });
+
+        let rx = rx1.expect("thread panicked");
+
+        let timeout = Delay::new(Duration::from_millis(200));
+        let rx = rx.and_then(move |_| timeout.expect("timeout"));
+        res1.join(res2).join(rx).map(|r| r.0).wait().unwrap();
+    }
+
+    #[test]
+    fn idle_timeout_closes_unused_connection() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx1, rx1) = oneshot::channel();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+            assert_ne!(n, 0);
+            // waiting on a response longer than the timeout doesn't count
+            thread::sleep(Duration::from_millis(200));
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+            let n = sock.read(&mut buf).expect("read eof");
+            assert_eq!(n, 0);
+            let _ = tx1.send(());
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::Builder::new()
+            .idle_timeout(Some(Duration::from_millis(100)))
+            .handshake(tcp)
+            .wait()
+            .unwrap();
+
+        let (done_tx, done_rx) = oneshot::channel();
+        runtime.spawn(conn.map(move |()| {
+            let _ = done_tx.send(());
+        }).map_err(|e| panic!("conn error: {}", e)));
+
+        let req = Request::builder()
+            .uri("/a")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let res = client.send_request(req).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+
+        done_rx.wait().expect("connection resolved");
+        rx1.wait().expect("thread panicked");
+    }
+
+    #[test]
+    fn write_timeout_fails_stalled_request() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx1, rx1) = oneshot::channel::<()>();
+
+        thread::spawn(move || {
+            // never reads, until the client gives up
+            let _sock = server.accept().unwrap().0;
+            let _ = rx1.wait();
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::Builder::new()
+            .write_timeout(Some(Duration::from_millis(100)))
+            .handshake(tcp)
+            .wait()
+            .unwrap();
+
+        let (done_tx, done_rx) = oneshot::channel();
+        runtime.spawn(conn.then(move |res| {
+            let _ = done_tx.send(res.map_err(|e| e.is_write_timeout()));
+            Ok(())
+        }));
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/a")
+            .body(hyper::Body::from(vec![b'x'; 32 * 1024 * 1024]))
+            .unwrap();
+        let err = client.send_request(req).wait().unwrap_err();
+        assert!(err.is_write_timeout(), "{:?}", err);
+
+        assert_eq!(done_rx.wait().expect("connection resolved"), Err(true));
+        drop(tx1);
+    }
+
+    #[test]
+    fn http1_max_headers_rejects_response() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"\
+                HTTP/1.1 200 OK\r\n\
+                a: 1\r\n\
+                b: 2\r\n\
+                content-length: 0\r\n\
+                \r\n\
+            ").unwrap();
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::Builder::new()
+            .http1_max_headers(2)
+            .handshake(tcp)
+            .wait()
+            .unwrap();
+        runtime.spawn(conn.map(|_| ()).map_err(|_| ()));
+
+        let req = Request::builder()
+            .uri("/a")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let err = client.send_request(req).wait().unwrap_err();
+        assert!(err.is_parse_too_large(), "{:?}", err);
+    }
+
+    #[test]
+    fn http1_preserve_header_case() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+            let req = String::from_utf8_lossy(&buf[..n]).into_owned();
+            sock.write_all(b"\
+                HTTP/1.1 200 OK\r\n\
+                X-Legacy-ID: 2\r\n\
+                CONTENT-LENGTH: 0\r\n\
+                \r\n\
+            ").unwrap();
+            let _ = tx1.send(req);
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
 
-    impl AsyncWrite for DebugStream {
-        fn shutdown(&mut self) -> futures::Poll<(), io::Error> {
-            AsyncWrite::shutdown(&mut self.0)
-        }
+        let (mut client, conn) = conn::Builder::new()
+            .http1_preserve_header_case(true)
+            .handshake(tcp)
+            .wait()
+            .unwrap();
+        runtime.spawn(conn.map(|_| ()).map_err(|_| ()));
 
-        fn write_buf<B: ::bytes::Buf>(&mut self, buf: &mut B) -> futures::Poll<usize, io::Error> {
-            self.0.write_buf(buf)
-        }
+        let mut header_case = conn::HeaderCaseMap::new();
+        header_case.append("X-Legacy-ID").unwrap();
+        let req = Request::builder()
+            .uri("/a")
+            .header("x-legacy-id", "1")
+            .extension(header_case)
+            .body(hyper::Body::empty())
+            .unwrap();
+        let res = client.send_request(req).wait().unwrap();
+        let header_case = res.extensions().get::<conn::HeaderCaseMap>().expect("HeaderCaseMap");
+        assert_eq!(header_case.get(&"x-legacy-id".parse().unwrap()), Some("X-Legacy-ID"));
+        assert_eq!(header_case.get(&hyper::header::CONTENT_LENGTH), Some("CONTENT-LENGTH"));
+
+        let req = rx1.wait().expect("thread panicked");
+        assert!(req.contains("\r\nX-Legacy-ID: 1\r\n"), "{:?}", req);
     }
 
-    impl Read for DebugStream {
-        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-            self.0.read(buf)
-        }
+    #[test]
+    fn http1_preserve_header_order() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+            let req = String::from_utf8_lossy(&buf[..n]).into_owned();
+            sock.write_all(b"\
+                HTTP/1.1 200 OK\r\n\
+                x-a: 1\r\n\
+                content-length: 0\r\n\
+                x-a: 2\r\n\
+                \r\n\
+            ").unwrap();
+            let _ = tx1.send(req);
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::Builder::new()
+            .http1_preserve_header_order(true)
+            .handshake(tcp)
+            .wait()
+            .unwrap();
+        runtime.spawn(conn.map(|_| ()).map_err(|_| ()));
+
+        let mut header_order = conn::HeaderOrder::new();
+        header_order.push("x-b".parse().unwrap());
+        header_order.push(hyper::header::HOST);
+        header_order.push("x-a".parse().unwrap());
+        let req = Request::builder()
+            .uri("/a")
+            .header("host", "example.com")
+            .header("x-a", "1")
+            .header("x-b", "2")
+            .extension(header_order)
+            .body(hyper::Body::empty())
+            .unwrap();
+        let res = client.send_request(req).wait().unwrap();
+        let header_order = res.extensions().get::<conn::HeaderOrder>().expect("HeaderOrder");
+        assert_eq!(
+            header_order.iter().map(|&(ref name, index)| (name.as_str(), index)).collect::<Vec<_>>(),
+            vec![("x-a", 0), ("content-length", 0), ("x-a", 1)]
+        );
+
+        let req = rx1.wait().expect("thread panicked");
+        assert!(req.contains("\r\nx-b: 2\r\nhost: example.com\r\nx-a: 1\r\n"), "{:?}", req);
     }
 
-    impl AsyncRead for DebugStream {}
-}
+    #[test]
+    fn http1_reason_phrase() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
 
-mod conn {
-    use std::io::{self, Read, Write};
-    use std::net::TcpListener;
-    use std::thread;
-    use std::time::Duration;
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"\
+                HTTP/1.1 200 Alright Then\r\n\
+                content-length: 0\r\n\
+                \r\n\
+            ").unwrap();
+            sock.read(&mut buf).expect("read 2");
+            sock.write_all(b"\
+                HTTP/1.1 200 OK\r\n\
+                content-length: 0\r\n\
+                \r\n\
+            ").unwrap();
+        });
 
-    use futures::{Async, Future, Poll, Stream};
-    use futures::future::poll_fn;
-    use futures::sync::oneshot;
-    use futures_timer::Delay;
-    use tokio::runtime::Runtime;
-    use tokio::net::TcpStream;
-    use tokio_io::{AsyncRead, AsyncWrite};
+        let tcp = tcp_connect(&addr).wait().unwrap();
 
-    use hyper::{self, Request};
-    use hyper::client::conn;
+        let (mut client, conn) = conn::Builder::new()
+            .handshake(tcp)
+            .wait()
+            .unwrap();
+        runtime.spawn(conn.map(|_| ()).map_err(|_| ()));
 
-    use super::{s, tcp_connect, FutureHyperExt};
+        let req = Request::builder()
+            .uri("/a")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let res = client.send_request(req).wait().unwrap();
+        let reason = res.extensions().get::<conn::ReasonPhrase>().expect("ReasonPhrase");
+        assert_eq!(reason.as_bytes(), b"Alright Then");
+
+        let req = Request::builder()
+            .uri("/b")
+            .body(hyper::Body::empty())
+            .unwrap();
+        // The response can arrive before the connection is ready for
+        // another request.
+        let mut client = client.ready().wait().unwrap();
+        let res = client.send_request(req).wait().unwrap();
+        assert!(res.extensions().get::<conn::ReasonPhrase>().is_none(), "canonical reason");
+    }
 
     #[test]
-    fn get() {
+    fn http1_request_target() {
         let server = TcpListener::bind("127.0.0.1:0").unwrap();
         let addr = server.local_addr().unwrap();
         let mut runtime = Runtime::new().unwrap();
 
-        let (tx1, rx1) = oneshot::channel();
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            for _ in 0..2 {
+                let n = sock.read(&mut buf).expect("read");
+                let req = String::from_utf8(buf[..n].to_vec()).unwrap();
+                tx.send(req.lines().next().unwrap().to_owned()).unwrap();
+                sock.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").unwrap();
+            }
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (client, conn) = conn::Builder::new()
+            .http1_request_target(conn::RequestTarget::Absolute)
+            .handshake(tcp)
+            .wait()
+            .unwrap();
+        runtime.spawn(conn.map(|_| ()).map_err(|_| ()));
+
+        let req = Request::builder()
+            .uri("/a?b")
+            .header("host", "hyper.rs")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let mut client = client.ready().wait().unwrap();
+        client.send_request(req).wait().unwrap();
+        assert_eq!(rx.recv().unwrap(), "GET http://hyper.rs/a?b HTTP/1.1");
+
+        let mut req = Request::builder()
+            .method("CONNECT")
+            .uri("https://hyper.rs/")
+            .body(hyper::Body::empty())
+            .unwrap();
+        req.extensions_mut().insert(RequestConfig::new().request_target(conn::RequestTarget::Authority));
+        let mut client = client.ready().wait().unwrap();
+        client.send_request(req).wait().unwrap();
+        assert_eq!(rx.recv().unwrap(), "CONNECT hyper.rs:443 HTTP/1.1");
+    }
+
+    #[test]
+    fn http1_allow_http09_responses() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
 
         thread::spawn(move || {
             let mut sock = server.accept().unwrap().0;
             sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
             sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
             let mut buf = [0; 4096];
-            let n = sock.read(&mut buf).expect("read 1");
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"<html>ancient</html>").unwrap();
+        });
 
-            // Notably:
-            // - Just a path, since just a path was set
-            // - No host, since no host was set
-            let expected = "GET /a HTTP/1.1\r\n\r\n";
-            assert_eq!(s(&buf[..n]), expected);
+        let tcp = tcp_connect(&addr).wait().unwrap();
 
-            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
-            let _ = tx1.send(());
+        let (mut client, conn) = conn::Builder::new()
+            .http1_allow_http09_responses(true)
+            .handshake(tcp)
+            .wait()
+            .unwrap();
+        runtime.spawn(conn.map(|_| ()).map_err(|_| ()));
+
+        let req = Request::builder()
+            .uri("/a")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let res = client.send_request(req).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert_eq!(res.version(), hyper::Version::HTTP_09);
+        assert!(res.headers().is_empty());
+        let body = res.into_body().concat2().wait().unwrap();
+        assert_eq!(body.as_ref(), b"<html>ancient</html>");
+    }
+
+    #[test]
+    fn http1_legacy_keep_alive() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"\
+                HTTP/1.0 200 OK\r\n\
+                connection: keep-alive\r\n\
+                content-length: 0\r\n\
+                \r\n\
+            ").unwrap();
+            let n = sock.read(&mut buf).expect("read 2");
+            tx.send(String::from_utf8(buf[..n].to_vec()).unwrap()).unwrap();
+            sock.write_all(b"\
+                HTTP/1.0 200 OK\r\n\
+                content-length: 0\r\n\
+                \r\n\
+            ").unwrap();
         });
 
         let tcp = tcp_connect(&addr).wait().unwrap();
 
         let (mut client, conn) = conn::handshake(tcp).wait().unwrap();
-
-        runtime.spawn(conn.map(|_| ()).map_err(|e| panic!("conn error: {}", e)));
+        runtime.spawn(conn.map(|_| ()).map_err(|_| ()));
 
         let req = Request::builder()
             .uri("/a")
-            .body(Default::default())
+            .body(hyper::Body::empty())
             .unwrap();
-        let res = client.send_request(req).and_then(move |res| {
-            assert_eq!(res.status(), hyper::StatusCode::OK);
-            res.into_body().concat2()
-        });
-        let rx = rx1.expect("thread panicked");
+        let res = client.send_request(req).wait().unwrap();
+        assert_eq!(res.version(), hyper::Version::HTTP_10);
 
-        let timeout = Delay::new(Duration::from_millis(200));
-        let rx = rx.and_then(move |_| timeout.expect("timeout"));
-        res.join(rx).map(|r| r.0).wait().unwrap();
+        let req = Request::builder()
+            .uri("/b")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let mut client = client.ready().wait().unwrap();
+        client.send_request(req).wait().unwrap();
+
+        let sent = rx.recv().unwrap();
+        assert!(sent.starts_with("GET /b HTTP/1.0\r\n"), "{:?}", sent);
+        assert!(sent.contains("\r\nconnection: keep-alive\r\n"), "{:?}", sent);
+    }
+
+    // A request with a header value with a CRLF in it.
+    //
+    // `HeaderValue::from_shared_unchecked` checks the bytes when debug
+    // assertions are enabled, so these only run without them, such as with
+    // `cargo test --release`.
+    #[cfg(not(debug_assertions))]
+    fn bad_header_value_request() -> Request<hyper::Body> {
+        let bad = hyper::Chunk::from("a\r\ninjected: 1").into_bytes();
+        Request::builder()
+            .uri("/bad")
+            .header("x-bad", unsafe { hyper::header::HeaderValue::from_shared_unchecked(bad) })
+            .body(Default::default())
+            .unwrap()
     }
 
+    #[cfg(not(debug_assertions))]
     #[test]
-    fn uri_absolute_form() {
+    fn http1_header_value_policy_reject_keeps_connection() {
         let server = TcpListener::bind("127.0.0.1:0").unwrap();
         let addr = server.local_addr().unwrap();
         let mut runtime = Runtime::new().unwrap();
 
-        let (tx1, rx1) = oneshot::channel();
-
         thread::spawn(move || {
             let mut sock = server.accept().unwrap().0;
             sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
             sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
             let mut buf = [0; 4096];
             let n = sock.read(&mut buf).expect("read 1");
-
-            // Notably:
-            // - Still no Host header, since it wasn't set
-            let expected = "GET http://hyper.local/a HTTP/1.1\r\n\r\n";
-            assert_eq!(s(&buf[..n]), expected);
-
+            assert_eq!(s(&buf[..n]), "GET /good HTTP/1.1\r\n\r\n");
             sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
-            let _ = tx1.send(());
         });
 
         let tcp = tcp_connect(&addr).wait().unwrap();
 
-        let (mut client, conn) = conn::handshake(tcp).wait().unwrap();
+        let (mut client, conn) = conn::Builder::new()
+            .http1_header_value_policy(conn::HeaderValuePolicy::Reject)
+            .handshake(tcp)
+            .wait()
+            .unwrap();
 
         runtime.spawn(conn.map(|_| ()).map_err(|e| panic!("conn error: {}", e)));
 
+        let err = runtime.block_on(client.send_request(bad_header_value_request())).unwrap_err();
+        assert!(err.is_user(), "{:?}", err);
+
+        // Nothing was written, so the connection can send the next request.
         let req = Request::builder()
-            .uri("http://hyper.local/a")
+            .uri("/good")
             .body(Default::default())
             .unwrap();
+        let res = runtime.block_on(client.send_request(req)).unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+    }
 
-        let res = client.send_request(req).and_then(move |res| {
-            assert_eq!(res.status(), hyper::StatusCode::OK);
-            res.into_body().concat2()
+    #[cfg(not(debug_assertions))]
+    #[test]
+    fn http1_header_value_policy_reject_and_close() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read eof");
+            assert_eq!(n, 0, "{:?}", s(&buf[..n]));
         });
-        let rx = rx1.expect("thread panicked");
 
-        let timeout = Delay::new(Duration::from_millis(200));
-        let rx = rx.and_then(move |_| timeout.expect("timeout"));
-        res.join(rx).map(|r| r.0).wait().unwrap();
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::Builder::new()
+            .http1_header_value_policy(conn::HeaderValuePolicy::RejectAndClose)
+            .handshake(tcp)
+            .wait()
+            .unwrap();
+
+        let (done_tx, done_rx) = oneshot::channel();
+        runtime.spawn(conn.then(move |res| {
+            let _ = done_tx.send(res.is_ok());
+            Ok(())
+        }));
+
+        let err = runtime.block_on(client.send_request(bad_header_value_request())).unwrap_err();
+        assert!(err.is_user(), "{:?}", err);
+
+        // The connection closes instead.
+        assert!(runtime.block_on(done_rx).unwrap());
+        let err = runtime.block_on(client.ready()).unwrap_err();
+        assert!(err.is_closed(), "{:?}", err);
     }
 
     #[test]
-    fn pipeline() {
+    fn ready_errors_if_connection_closed() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (client, conn) = conn::handshake(tcp).wait().unwrap();
+        drop(conn);
+
+        let err = client.ready().wait().unwrap_err();
+        assert!(err.is_closed(), "{:?}", err);
+    }
+
+    #[test]
+    fn http2_peer_settings() {
+        let _ = ::pretty_env_logger::try_init();
         let server = TcpListener::bind("127.0.0.1:0").unwrap();
         let addr = server.local_addr().unwrap();
         let mut runtime = Runtime::new().unwrap();
 
-        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            // SETTINGS, with MAX_CONCURRENT_STREAMS 10 and INITIAL_WINDOW_SIZE 1024
+            sock.write_all(b"\0\0\x0c\x04\0\0\0\0\0\0\x03\0\0\0\x0a\0\x04\0\0\x04\0").expect("write settings");
+
+            // Read the preface, then frames until the request's HEADERS,
+            // and the ACK of the settings, which they're kept after.
+            let mut buf = Vec::new();
+            let mut chunk = [0; 4096];
+            let mut pos = 24;
+            let (mut headers, mut ack) = (false, false);
+            while !(headers && ack) {
+                let n = sock.read(&mut chunk).expect("read");
+                assert!(n > 0, "eof before request");
+                buf.extend_from_slice(&chunk[..n]);
+                while buf.len() >= pos + 9 {
+                    let len = (buf[pos] as usize) << 16 | (buf[pos + 1] as usize) << 8 | buf[pos + 2] as usize;
+                    headers |= buf[pos + 3] == 0x1;
+                    ack |= buf[pos + 3] == 0x4 && buf[pos + 4] == 0x1;
+                    pos += 9 + len;
+                }
+            }
+
+            // HEADERS on stream 1, ending it, with just `:status: 200`.
+            sock.write_all(b"\0\0\x01\x01\x05\0\0\0\x01\x88").expect("write headers");
+            let _ = sock.read(&mut chunk);
+        });
 
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::Builder::new()
+            .http2_only(true)
+            .http2_peer_settings(true)
+            .handshake::<_, hyper::Body>(tcp)
+            .wait()
+            .unwrap();
+
+        runtime.spawn(conn.map_err(|e| panic!("conn error: {}", e)));
+
+        let req = Request::builder()
+            .uri("http://localhost/a")
+            .body(Default::default())
+            .unwrap();
+        let res = runtime.block_on(client.send_request(req)).unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+
+        let settings = client.peer_settings().expect("peer settings");
+        assert_eq!(settings.max_concurrent_streams(), Some(10));
+        assert_eq!(settings.initial_window_size(), 1024);
+        assert_eq!(settings.max_frame_size(), 16_384);
+    }
+
+    #[test]
+    fn http2_deferred_empty_body_ends_stream_with_headers() {
+        use hyper::client::RequestConfig;
+
+        let _ = ::pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (flags_tx, flags_rx) = oneshot::channel();
         thread::spawn(move || {
             let mut sock = server.accept().unwrap().0;
             sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
             sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
-            let mut buf = [0; 4096];
-            sock.read(&mut buf).expect("read 1");
-            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+            sock.write_all(b"\0\0\0\x04\0\0\0\0\0").expect("write settings");
+
+            // Read the preface, then frames until the request's HEADERS.
+            let mut buf = Vec::new();
+            let mut chunk = [0; 4096];
+            let mut pos = 24;
+            let flags = 'read: loop {
+                let n = sock.read(&mut chunk).expect("read");
+                assert!(n > 0, "eof before request");
+                buf.extend_from_slice(&chunk[..n]);
+                while buf.len() >= pos + 9 {
+                    let len = (buf[pos] as usize) << 16 | (buf[pos + 1] as usize) << 8 | buf[pos + 2] as usize;
+                    if buf[pos + 3] == 0x1 {
+                        break 'read buf[pos + 4];
+                    }
+                    pos += 9 + len;
+                }
+            };
+            let _ = flags_tx.send(flags);
+
+            // HEADERS on stream 1, ending it, with just `:status: 200`.
+            sock.write_all(b"\0\0\x01\x01\x05\0\0\0\x01\x88").expect("write headers");
+            let _ = sock.read(&mut chunk);
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+        let (mut client, conn) = conn::Builder::new()
+            .http2_only(true)
+            .handshake::<_, hyper::Body>(tcp)
+            .wait()
+            .unwrap();
+        runtime.spawn(conn.map_err(|e| panic!("conn error: {}", e)));
+
+        // a streaming body that ends without any data
+        let (body_tx, body) = hyper::Body::channel();
+        drop(body_tx);
+        let mut req = Request::builder()
+            .uri("http://localhost/a")
+            .body(body)
+            .unwrap();
+        req.extensions_mut().insert(RequestConfig::new().http2_defer_stream(true));
+        let res = runtime.block_on(client.send_request(req)).unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+
+        let flags = flags_rx.wait().expect("server thread");
+        assert_eq!(flags & 0x1, 0x1, "HEADERS should have END_STREAM");
+    }
+
+    #[test]
+    fn http1_h2c_upgrade() {
+        use hyper::server::conn::Http;
+        use hyper::service::service_fn;
 
-            let _ = tx1.send(());
-        });
+        let _ = ::pretty_env_logger::try_init();
+        let mut runtime = Runtime::new().unwrap();
+        let listener = ::tokio::net::TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(|(item, _incoming)| {
+                Http::new()
+                    .http1_h2c_upgrade(true)
+                    .serve_connection(item.unwrap(), service_fn(|req: Request<hyper::Body>| {
+                        let body = format!("{:?} {}", req.version(), req.uri().path());
+                        Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from(body)))
+                    }))
+            })
+            .map_err(|e| panic!("server error: {}", e));
+        runtime.spawn(server);
 
         let tcp = tcp_connect(&addr).wait().unwrap();
+        let (mut client, conn) = conn::Builder::new()
+            .http1_h2c_upgrade(true)
+            .http2_peer_settings(true)
+            .handshake::<_, hyper::Body>(tcp)
+            .wait()
+            .unwrap();
+        runtime.spawn(conn.map_err(|e| panic!("conn error: {}", e)));
 
-        let (mut client, conn) = conn::handshake(tcp).wait().unwrap();
+        // The first request offers to upgrade, and its response is read
+        // from the first HTTP/2 stream. The next one is sent as HTTP/2.
+        for path in &["/first", "/second"] {
+            poll_fn(|| client.poll_ready()).wait().unwrap();
+            let req = Request::builder()
+                .uri(*path)
+                .header("host", "localhost")
+                .body(hyper::Body::empty())
+                .unwrap();
+            let res = runtime.block_on(client.send_request(req)).unwrap();
+            assert_eq!(res.version(), hyper::Version::HTTP_2);
+            let body = runtime.block_on(res.into_body().concat2()).unwrap();
+            assert_eq!(s(&body), format!("HTTP/2.0 {}", path));
+        }
+        assert!(client.peer_settings().is_some());
+    }
 
-        runtime.spawn(conn.map(|_| ()).map_err(|e| panic!("conn error: {}", e)));
+    #[test]
+    fn http1_has_no_peer_settings() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
 
-        let req = Request::builder()
-            .uri("/a")
-            .body(Default::default())
-            .unwrap();
-        let res1 = client.send_request(req).and_then(move |res| {
-            assert_eq!(res.status(), hyper::StatusCode::OK);
-            res.into_body().concat2()
-        });
+        let tcp = tcp_connect(&addr).wait().unwrap();
 
-        // pipelined request will hit NotReady, and thus should return an Error::Cancel
-        let req = Request::builder()
-            .uri("/b")
-            .body(Default::default())
+        let (client, _conn) = conn::Builder::new()
+            .handshake::<_, hyper::Body>(tcp)
+            .wait()
             .unwrap();
-        let res2 = client.send_request(req)
-            .then(|result| {
-                let err = result.expect_err("res2");
-                assert!(err.is_canceled(), "err not canceled, {:?}", err);
-                Ok(())
-            });
-
-        let rx = rx1.expect("thread panicked");
-
-        let timeout = Delay::new(Duration::from_millis(200));
-        let rx = rx.and_then(move |_| timeout.expect("timeout"));
-        res1.join(res2).join(rx).map(|r| r.0).wait().unwrap();
+        assert!(client.peer_settings().is_none());
     }
 
     #[test]
@@ -1590,7 +4391,7 @@ mod conn {
             }).wait().unwrap();
         }
 
-        let parts = conn.into_parts();
+        let parts = conn.into_parts().expect("http1 parts");
         let io = parts.io;
         let buf = parts.read_buf;
 
@@ -1603,6 +4404,168 @@ mod conn {
         assert_eq!(vec, b"bar=foo");
     }
 
+    #[test]
+    fn upgrade_without_shutdown() {
+        use tokio_io::io::{read_to_end, write_all};
+        let _ = ::pretty_env_logger::try_init();
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let _runtime = Runtime::new().unwrap();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"\
+                HTTP/1.1 101 Switching Protocols\r\n\
+                Upgrade: foobar\r\n\
+                \r\n\
+                foobar=ready\
+            ").unwrap();
+
+            let n = sock.read(&mut buf).expect("read 2");
+            assert_eq!(&buf[..n], b"foo=bar");
+            sock.write_all(b"bar=foo").expect("write 2");
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let io = DebugStream {
+            tcp: tcp,
+            shutdown_called: false,
+        };
+
+        let (mut client, conn) = conn::handshake(io).wait().unwrap();
+
+        let req = Request::builder()
+            .uri("/a")
+            .body(Default::default())
+            .unwrap();
+        let res = client.send_request(req).and_then(move |res| {
+            assert_eq!(res.status(), hyper::StatusCode::SWITCHING_PROTOCOLS);
+            res.into_body().concat2()
+        });
+
+        let (parts, _) = conn.without_shutdown().join(res).wait().unwrap();
+        assert_eq!(parts.read_buf, b"foobar=ready"[..]);
+        assert!(!parts.io.shutdown_called, "upgrade shouldn't shutdown AsyncWrite");
+
+        let io = write_all(parts.io, b"foo=bar").wait().unwrap().0;
+        let vec = read_to_end(io, vec![]).wait().unwrap().1;
+        assert_eq!(vec, b"bar=foo");
+    }
+
+    #[test]
+    fn http2_without_shutdown_fails() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let _runtime = Runtime::new().unwrap();
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+        let (_client, conn) = conn::Builder::new()
+            .http2_only(true)
+            .handshake::<_, hyper::Body>(tcp)
+            .wait()
+            .unwrap();
+
+        // Fails without waiting for the connection to close.
+        let err = conn.without_shutdown().wait().unwrap_err();
+        assert!(err.is_user(), "{:?}", err);
+        drop(server);
+    }
+
+    #[cfg(feature = "http2")]
+    #[test]
+    fn http2_close_reason_goaway() {
+        let _ = ::pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            // Empty SETTINGS.
+            sock.write_all(b"\0\0\0\x04\0\0\0\0\0").expect("write settings");
+            // GOAWAY with ENHANCE_YOUR_CALM.
+            sock.write_all(b"\0\0\x08\x07\0\0\0\0\0\0\0\0\0\0\0\0\x0b").expect("write goaway");
+            let mut buf = [0; 4096];
+            while sock.read(&mut buf).unwrap_or(0) > 0 {}
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+        let (_client, mut conn) = conn::Builder::new()
+            .http2_only(true)
+            .handshake::<_, hyper::Body>(tcp)
+            .wait()
+            .unwrap();
+        assert!(conn.close_reason().is_none());
+
+        let mut conn = Some(conn);
+        let (err, conn) = runtime.block_on(poll_fn(move || {
+            let err = match conn.as_mut().unwrap().poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => panic!("connection closed without error"),
+                Err(err) => err,
+            };
+            Ok::<_, ()>(Async::Ready((err, conn.take().unwrap())))
+        })).unwrap();
+        assert_eq!(err.http2_error_code(), Some(0xb), "{:?}", err);
+        let reason = conn.close_reason().expect("close reason");
+        assert_eq!(reason.http2_error_code(), Some(0xb));
+    }
+
+    #[test]
+    fn upgrade_not_offered() {
+        use hyper::upgrade::{Offer, Protocol};
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+            let req = String::from_utf8_lossy(&buf[..n]).into_owned();
+            sock.write_all(b"\
+                HTTP/1.1 101 Switching Protocols\r\n\
+                Upgrade: foobar\r\n\
+                Connection: upgrade\r\n\
+                \r\n\
+            ").unwrap();
+            let _ = tx1.send(req);
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::handshake(tcp).wait().unwrap();
+        runtime.spawn(conn.map(|_| ()).map_err(|_| ()));
+
+        let mut offer = Offer::new();
+        offer.push(Protocol::new("websocket").unwrap());
+        offer.push_with_q(Protocol::new("h2c").unwrap(), 0.5);
+        let mut req = Request::builder()
+            .uri("/a")
+            .body(hyper::Body::empty())
+            .unwrap();
+        offer.write_headers(req.headers_mut());
+
+        let err = client.send_request(req).wait().unwrap_err();
+        assert!(err.to_string().contains("wasn't offered"), "{}", err);
+
+        let req = rx1.wait().expect("thread panicked");
+        assert!(req.contains("\r\nupgrade: websocket, h2c;q=0.5\r\n"), "{:?}", req);
+        assert!(req.contains("\r\nconnection: upgrade\r\n"), "{:?}", req);
+    }
+
     #[test]
     fn connect_method() {
         use tokio_io::io::{read_to_end, write_all};
@@ -1673,7 +4636,7 @@ mod conn {
             }).wait().unwrap();
         }
 
-        let parts = conn.into_parts();
+        let parts = conn.into_parts().expect("http1 parts");
         let io = parts.io;
         let buf = parts.read_buf;
 
@@ -1730,3 +4693,241 @@ where
         Box::new(self.map_err(move |e| panic!("expect: {}; error={:?}", msg, e)))
     }
 }
+
+#[cfg(feature = "https-rustls")]
+mod https {
+    extern crate rustls;
+
+    use std::io::{BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::{Future, Stream};
+    use tokio::runtime::Runtime;
+
+    use hyper::{Body, Client, StatusCode};
+    use hyper::client::{HttpConnector, HttpsConnector};
+
+    use self::rustls::{ClientConfig, NoClientAuth, ServerConfig, ServerSession, StreamOwned};
+    use self::rustls::internal::pemfile;
+
+    use super::s;
+
+    // A self-signed certificate for `localhost`.
+    static CERT: &[u8] = include_bytes!("fixtures/localhost.crt");
+    static KEY: &[u8] = include_bytes!("fixtures/localhost.key");
+
+    #[test]
+    fn get() {
+        let _ = ::pretty_env_logger::try_init();
+        let certs = pemfile::certs(&mut BufReader::new(CERT)).unwrap();
+        let key = pemfile::pkcs8_private_keys(&mut BufReader::new(KEY)).unwrap().remove(0);
+        let mut server_config = ServerConfig::new(NoClientAuth::new());
+        server_config.set_single_cert(certs.clone(), key).unwrap();
+        server_config.set_protocols(&[b"http/1.1".to_vec()]);
+        let server_config = Arc::new(server_config);
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut tls = StreamOwned::new(ServerSession::new(&server_config), sock);
+            let mut buf = [0; 4096];
+            let n = tls.read(&mut buf).expect("read 1");
+            assert!(s(&buf[..n]).starts_with("GET /a HTTP/1.1\r\n"), "{:?}", s(&buf[..n]));
+            tls.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").expect("write 1");
+            tls.flush().expect("flush 1");
+        });
+
+        let runtime = Runtime::new().unwrap();
+        let mut http = HttpConnector::new_with_handle(1, runtime.reactor().clone());
+        http.enforce_http(false);
+        let mut tls = ClientConfig::new();
+        tls.root_store.add(&certs[0]).unwrap();
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, Body>(HttpsConnector::with_connector(http, tls));
+
+        let uri = format!("https://localhost:{}/a", port).parse().unwrap();
+        let body = client.get(uri)
+            .and_then(|res| {
+                assert_eq!(res.status(), StatusCode::OK);
+                res.into_body().concat2()
+            })
+            .wait()
+            .unwrap();
+        assert_eq!(s(&body), "hello");
+    }
+
+    #[test]
+    fn early_data_without_server_support() {
+        let _ = ::pretty_env_logger::try_init();
+        let certs = pemfile::certs(&mut BufReader::new(CERT)).unwrap();
+        let key = pemfile::pkcs8_private_keys(&mut BufReader::new(KEY)).unwrap().remove(0);
+        let mut server_config = ServerConfig::new(NoClientAuth::new());
+        server_config.set_single_cert(certs.clone(), key).unwrap();
+        server_config.set_protocols(&[b"http/1.1".to_vec()]);
+        let server_config = Arc::new(server_config);
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || {
+            // The second connection resumes the session of the first.
+            for _ in 0..2 {
+                let sock = server.accept().unwrap().0;
+                sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+                let mut tls = StreamOwned::new(ServerSession::new(&server_config), sock);
+                let mut buf = [0; 4096];
+                let n = tls.read(&mut buf).expect("read");
+                assert!(s(&buf[..n]).starts_with("GET /a HTTP/1.1\r\n"), "{:?}", s(&buf[..n]));
+                tls.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello").expect("write");
+                tls.flush().expect("flush");
+            }
+        });
+
+        let runtime = Runtime::new().unwrap();
+        let mut http = HttpConnector::new_with_handle(1, runtime.reactor().clone());
+        http.enforce_http(false);
+        let mut tls = ClientConfig::new();
+        tls.root_store.add(&certs[0]).unwrap();
+        tls.set_protocols(&[b"http/1.1".to_vec()]);
+        tls.enable_early_data = true;
+        let client = Client::builder()
+            .http1_early_data(true)
+            .executor(runtime.executor())
+            .build::<_, Body>(HttpsConnector::with_connector(http, tls));
+
+        for _ in 0..2 {
+            let uri = format!("https://localhost:{}/a", port).parse().unwrap();
+            let body = client.get(uri)
+                .and_then(|res| {
+                    assert_eq!(res.status(), StatusCode::OK);
+                    res.into_body().concat2()
+                })
+                .wait()
+                .unwrap();
+            assert_eq!(s(&body), "hello");
+        }
+    }
+}
+
+#[cfg(feature = "https-native-tls")]
+mod native_tls {
+    extern crate native_tls;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::{Future, Stream};
+    use tokio::runtime::Runtime;
+
+    use hyper::{Body, Client, StatusCode};
+    use hyper::client::{HttpConnector, NativeTlsConnector};
+
+    use self::native_tls::{Certificate, Identity, TlsAcceptor, TlsConnector};
+
+    use super::s;
+
+    // A self-signed certificate for `localhost`.
+    static CERT: &[u8] = include_bytes!("fixtures/localhost.crt");
+    static KEY: &[u8] = include_bytes!("fixtures/localhost.key");
+
+    #[test]
+    fn get() {
+        let _ = ::pretty_env_logger::try_init();
+        let identity = Identity::from_pkcs8(CERT, KEY).unwrap();
+        let acceptor = TlsAcceptor::new(identity).unwrap();
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut tls = acceptor.accept(sock).expect("handshake");
+            let mut buf = [0; 4096];
+            let n = tls.read(&mut buf).expect("read 1");
+            assert!(s(&buf[..n]).starts_with("GET /a HTTP/1.1\r\n"), "{:?}", s(&buf[..n]));
+            tls.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").expect("write 1");
+            tls.flush().expect("flush 1");
+        });
+
+        let runtime = Runtime::new().unwrap();
+        let mut http = HttpConnector::new_with_handle(1, runtime.reactor().clone());
+        http.enforce_http(false);
+        let tls = TlsConnector::builder()
+            .add_root_certificate(Certificate::from_pem(CERT).unwrap())
+            .build()
+            .unwrap();
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, Body>(NativeTlsConnector::with_connector(http, tls));
+
+        let uri = format!("https://localhost:{}/a", port).parse().unwrap();
+        let body = client.get(uri)
+            .and_then(|res| {
+                assert_eq!(res.status(), StatusCode::OK);
+                res.into_body().concat2()
+            })
+            .wait()
+            .unwrap();
+        assert_eq!(s(&body), "hello");
+    }
+}
+
+#[cfg(feature = "blocking")]
+mod blocking {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use hyper::{Body, Request, StatusCode};
+    use hyper::client::blocking::Client;
+
+    use super::s;
+
+    #[test]
+    fn get_and_read_body() {
+        let _ = ::pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            for _ in 0..2 {
+                let n = sock.read(&mut buf).expect("read request");
+                assert!(s(&buf[..n]).starts_with("GET /a HTTP/1.1\r\n"), "{:?}", s(&buf[..n]));
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").expect("write");
+            }
+        });
+
+        let client = Client::new().unwrap();
+        let uri = format!("http://{}/a", addr);
+        let res = client.get(&uri).unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(s(res.body()), "hello");
+
+        let req = Request::builder()
+            .uri(&*uri)
+            .body(Body::empty())
+            .unwrap();
+        let mut body = String::new();
+        client.send(req).unwrap().into_body().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello");
+    }
+
+    #[test]
+    fn get_invalid_uri() {
+        let client = Client::new().unwrap();
+        assert!(client.get("not a uri").unwrap_err().is_parse());
+    }
+}
@@ -12,7 +12,7 @@ extern crate tokio_io;
 
 use std::net::{TcpStream, Shutdown, SocketAddr};
 use std::io::{self, Read, Write};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::net::{TcpListener as StdTcpListener};
@@ -32,7 +32,7 @@ use tokio_io::{AsyncRead, AsyncWrite};
 
 use hyper::{Body, Request, Response, StatusCode};
 use hyper::client::Client;
-use hyper::server::conn::Http;
+use hyper::server::conn::{HeaderCaseMap, HeaderOrder, Http, ReasonPhrase, UpgradeRefusal};
 use hyper::service::{service_fn, Service};
 
 fn tcp_bind(addr: &SocketAddr, handle: &Handle) -> ::tokio::io::Result<TcpListener> {
@@ -1047,6 +1047,285 @@ fn nonempty_parse_eof_returns_error() {
     fut.wait().unwrap_err();
 }
 
+#[test]
+fn server_bind_all_reports_local_addrs() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let addrs = [
+        "127.0.0.1:0".parse().unwrap(),
+        "127.0.0.1:0".parse().unwrap(),
+    ];
+
+    let mut bound = Vec::new();
+    let server = hyper::Server::bind_all(&addrs)
+        .on_bind(|addr| bound.push(addr))
+        .serve(|| hyper::service::service_fn_ok(|_req| Response::new(Body::empty())));
+
+    let local_addrs = server.local_addrs();
+    assert_eq!(local_addrs, bound);
+    assert_eq!(local_addrs.len(), 2);
+    assert_ne!(local_addrs[0].port(), 0);
+    assert_ne!(local_addrs[0], local_addrs[1]);
+    assert_eq!(server.local_addr(), local_addrs[0]);
+
+    runtime.executor().spawn(server.map_err(|e| panic!("server error: {}", e)));
+
+    for addr in &local_addrs {
+        let mut tcp = connect(addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        tcp.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", response);
+    }
+}
+
+#[test]
+fn server_bind_dual_stack() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+
+    let server = hyper::Server::bind_dual_stack(0)
+        .serve(|| hyper::service::service_fn_ok(|_req| Response::new(Body::empty())));
+
+    let local_addrs = server.local_addrs();
+    let port = local_addrs[0].port();
+    assert_ne!(port, 0);
+    assert!(local_addrs.iter().all(|addr| addr.port() == port), "{:?}", local_addrs);
+
+    runtime.executor().spawn(server.map_err(|e| panic!("server error: {}", e)));
+
+    // IPv4 is always accepted, IPv6 only if the sandbox has it.
+    let mut targets = vec![SocketAddr::from(([127, 0, 0, 1], port))];
+    if local_addrs.iter().any(|addr| addr.is_ipv6()) {
+        targets.push(format!("[::1]:{}", port).parse().unwrap());
+    }
+    for addr in &targets {
+        let mut tcp = connect(addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        tcp.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", response);
+    }
+}
+
+#[test]
+fn server_bind_all_graceful_shutdown() {
+    use hyper::service::service_fn;
+
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let addrs = [
+        "127.0.0.1:0".parse().unwrap(),
+        "127.0.0.1:0".parse().unwrap(),
+    ];
+
+    // The first request is answered only once `reply_tx` is sent on.
+    let (reply_tx, reply_rx) = oneshot::channel::<()>();
+    let reply_rx = Arc::new(Mutex::new(Some(reply_rx)));
+    let server = hyper::Server::bind_all(&addrs)
+        .serve(move || {
+            let reply_rx = reply_rx.clone();
+            service_fn(move |_req| {
+                let reply = reply_rx.lock().unwrap().take();
+                reply
+                    .expect("one request")
+                    .map(|()| Response::new(Body::from("drained")))
+            })
+        });
+    let local_addrs = server.local_addrs();
+
+    let (signal_tx, signal_rx) = oneshot::channel::<()>();
+    let (done_tx, done_rx) = oneshot::channel();
+    let graceful = server
+        .with_graceful_shutdown(signal_rx)
+        .map(move |()| {
+            let _ = done_tx.send(());
+        });
+    runtime.executor().spawn(graceful.map_err(|e| panic!("server error: {}", e)));
+
+    let mut tcp = connect(&local_addrs[1]);
+    tcp.write_all(b"GET / HTTP/1.1\r\nHost: example.domain\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    signal_tx.send(()).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    for addr in &local_addrs {
+        assert!(TcpStream::connect(addr).is_err(), "still listening on {}", addr);
+    }
+
+    // The request in flight is still answered, and then the keep-alive
+    // connection is closed.
+    reply_tx.send(()).unwrap();
+    let mut response = String::new();
+    tcp.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", response);
+    assert!(response.ends_with("\r\n\r\ndrained"), "{:?}", response);
+
+    done_rx.wait().expect("server finished");
+}
+
+#[test]
+fn server_graceful_shutdown_h2() {
+    use hyper::service::service_fn_ok;
+
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+
+    let server = hyper::Server::bind(&"127.0.0.1:0".parse().unwrap())
+        .http2_only(true)
+        .serve(|| service_fn_ok(|_req| Response::new(Body::from("h2"))));
+    let addr = server.local_addr();
+
+    let (signal_tx, signal_rx) = oneshot::channel::<()>();
+    let (done_tx, done_rx) = oneshot::channel();
+    let graceful = server
+        .with_graceful_shutdown(signal_rx)
+        .map(move |()| {
+            let _ = done_tx.send(());
+        });
+    runtime.spawn(graceful.map_err(|e| panic!("server error: {}", e)));
+
+    // Keeps an idle HTTP/2 connection in its pool.
+    let client: Client<_, hyper::Body> = Client::builder()
+        .http2_only(true)
+        .executor(runtime.executor())
+        .build_http();
+    let uri = format!("http://{}/", addr).parse::<hyper::Uri>().unwrap();
+    let body = runtime.block_on(client.get(uri).and_then(|res| {
+        assert_eq!(res.status(), StatusCode::OK);
+        res.into_body().concat2()
+    })).unwrap();
+    assert_eq!(body.as_ref(), b"h2");
+
+    signal_tx.send(()).unwrap();
+    done_rx.wait().expect("server finished");
+}
+
+#[test]
+fn make_service_fn_gets_connection() {
+    use hyper::server::conn::AddrStream;
+    use hyper::service::{make_service_fn, service_fn_ok};
+
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+
+    let server = hyper::Server::bind(&"127.0.0.1:0".parse().unwrap())
+        .serve(make_service_fn(|conn: &AddrStream| {
+            let remote = conn.remote_addr();
+            service_fn_ok(move |_req| Response::new(Body::from(remote.to_string())))
+                .map_request(|mut req: Request<Body>| {
+                    req.headers_mut().remove("x-secret");
+                    req
+                })
+                .map_response(|mut res| {
+                    res.headers_mut().insert("x-mapped", HeaderValue::from_static("1"));
+                    res
+                })
+        }));
+    let addr = server.local_addr();
+    runtime.executor().spawn(server.map_err(|e| panic!("server error: {}", e)));
+
+    let mut tcp = connect(&addr);
+    let local = tcp.local_addr().unwrap();
+    tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    tcp.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", response);
+    assert!(response.contains("x-mapped: 1\r\n"), "{:?}", response);
+    assert!(response.ends_with(&format!("\r\n\r\n{}", local)), "{:?}", response);
+}
+
+#[test]
+fn server_serves_from_accept_poll_fn() {
+    use hyper::server::accept;
+    use hyper::service::service_fn_ok;
+
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Accepts a single connection, and then finishes.
+    let mut incoming = Some(listener.incoming());
+    let acceptor = accept::poll_fn(move || -> futures::Poll<_, io::Error> {
+        let conn = match incoming {
+            Some(ref mut incoming) => match incoming.poll()? {
+                futures::Async::Ready(conn) => conn,
+                futures::Async::NotReady => return Ok(futures::Async::NotReady),
+            },
+            None => return Ok(futures::Async::Ready(None)),
+        };
+        incoming = None;
+        Ok(futures::Async::Ready(conn))
+    });
+
+    let (done_tx, done_rx) = oneshot::channel();
+    let server = hyper::Server::builder(acceptor)
+        .serve(|| service_fn_ok(|_req| Response::new(Body::from("accepted"))))
+        .map(move |()| {
+            let _ = done_tx.send(());
+        });
+    runtime.executor().spawn(server.map_err(|e| panic!("server error: {}", e)));
+
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    tcp.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", response);
+    assert!(response.ends_with("\r\n\r\naccepted"), "{:?}", response);
+
+    done_rx.wait().expect("server finished");
+}
+
+#[test]
+fn server_from_std_listener() {
+    use hyper::service::service_fn_ok;
+
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = hyper::Server::from_std(listener)
+        .expect("from_std")
+        .serve(|| service_fn_ok(|_req| Response::new(Body::from("inherited"))));
+    assert_eq!(server.local_addr(), addr);
+    runtime.executor().spawn(server.map_err(|e| panic!("server error: {}", e)));
+
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    tcp.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", response);
+    assert!(response.ends_with("\r\n\r\ninherited"), "{:?}", response);
+}
+
+#[cfg(unix)]
+#[test]
+fn server_from_raw_fd() {
+    use std::os::unix::io::IntoRawFd;
+    use hyper::service::service_fn_ok;
+
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fd = listener.into_raw_fd();
+
+    let server = unsafe { hyper::Server::from_raw_fd(fd) }
+        .expect("from_raw_fd")
+        .serve(|| service_fn_ok(|_req| Response::new(Body::from("activated"))));
+    assert_eq!(server.local_addr(), addr);
+    runtime.executor().spawn(server.map_err(|e| panic!("server error: {}", e)));
+
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    tcp.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", response);
+    assert!(response.ends_with("\r\n\r\nactivated"), "{:?}", response);
+}
+
 #[test]
 fn returning_1xx_response_is_error() {
     let runtime = Runtime::new().unwrap();
@@ -1138,7 +1417,7 @@ fn upgrades() {
     // wait so that we don't write until other side saw 101 response
     rx.wait().unwrap();
 
-    let parts = conn.into_parts();
+    let parts = conn.into_parts().expect("http1 parts");
     let io = parts.io;
     assert_eq!(parts.read_buf, "eagerly optimistic");
 
@@ -1148,47 +1427,146 @@ fn upgrades() {
 }
 
 #[test]
-fn parse_errors_send_4xx_response() {
+fn upgrades_without_shutdown() {
+    use tokio_io::io::{read_to_end, write_all};
+    let _ = pretty_env_logger::try_init();
     let runtime = Runtime::new().unwrap();
     let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
     let addr = listener.local_addr().unwrap();
+    let (tx, rx) = oneshot::channel();
 
     thread::spawn(move || {
         let mut tcp = connect(&addr);
-        tcp.write_all(b"GE T / HTTP/1.1\r\n\r\n").unwrap();
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Upgrade: foobar\r\n\
+            Connection: upgrade\r\n\
+            \r\n\
+            eagerly optimistic\
+        ").expect("write 1");
         let mut buf = [0; 256];
-        tcp.read(&mut buf).unwrap();
+        tcp.read(&mut buf).expect("read 1");
 
-        let expected = "HTTP/1.1 400 ";
+        let expected = "HTTP/1.1 101 Switching Protocols\r\n";
         assert_eq!(s(&buf[..expected.len()]), expected);
+        let _ = tx.send(());
+
+        let n = tcp.read(&mut buf).expect("read 2");
+        assert_eq!(s(&buf[..n]), "foo=bar");
+        tcp.write_all(b"bar=foo").expect("write 2");
     });
 
     let fut = listener.incoming()
         .into_future()
-        .map_err(|_| unreachable!())
+        .map_err(|_| -> hyper::Error { unreachable!() })
         .and_then(|(item, _incoming)| {
             let socket = item.unwrap();
-            Http::new()
-                .serve_connection(socket, HelloWorld)
+            let conn = Http::new()
+                .serve_connection(socket, service_fn(|_| {
+                    let res = Response::builder()
+                        .status(101)
+                        .header("upgrade", "foobar")
+                        .body(hyper::Body::empty())
+                        .unwrap();
+                    Ok::<_, hyper::Error>(res)
+                }));
+
+            conn.without_shutdown()
         });
 
-    fut.wait().unwrap_err();
+    let parts = fut.wait().unwrap();
+
+    // wait so that we don't write until other side saw 101 response
+    rx.wait().unwrap();
+
+    let io = parts.io;
+    assert_eq!(parts.read_buf, "eagerly optimistic");
+
+    let io = write_all(io, b"foo=bar").wait().unwrap().0;
+    let vec = read_to_end(io, vec![]).wait().unwrap().1;
+    assert_eq!(vec, b"bar=foo");
 }
 
 #[test]
-fn illegal_request_length_returns_400_response() {
+fn connect_tunnel() {
+    use tokio_io::io::{read_to_end, write_all};
+    let _ = pretty_env_logger::try_init();
     let runtime = Runtime::new().unwrap();
     let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
     let addr = listener.local_addr().unwrap();
+    let (tx, rx) = oneshot::channel();
 
     thread::spawn(move || {
         let mut tcp = connect(&addr);
-        tcp.write_all(b"POST / HTTP/1.1\r\nContent-Length: foo\r\n\r\n").unwrap();
+        tcp.write_all(b"\
+            CONNECT hyper.rs:443 HTTP/1.1\r\n\
+            Host: hyper.rs:443\r\n\
+            \r\n\
+            eagerly optimistic\
+        ").expect("write 1");
         let mut buf = [0; 256];
-        tcp.read(&mut buf).unwrap();
+        let n = tcp.read(&mut buf).expect("read 1");
+        let head = s(&buf[..n]);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", head);
+        assert!(!head.contains("content-length"), "{:?}", head);
+        assert!(head.ends_with("\r\n\r\n"), "{:?}", head);
+        let _ = tx.send(());
 
-        let expected = "HTTP/1.1 400 ";
-        assert_eq!(s(&buf[..expected.len()]), expected);
+        let n = tcp.read(&mut buf).expect("read 2");
+        assert_eq!(s(&buf[..n]), "foo=bar");
+        tcp.write_all(b"bar=foo").expect("write 2");
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| -> hyper::Error { unreachable!() })
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            let conn = Http::new()
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    assert_eq!(req.method(), &hyper::Method::CONNECT);
+                    assert_eq!(req.uri(), "hyper.rs:443");
+                    // The body is ignored, it would be the tunnel.
+                    let res = Response::builder()
+                        .header("content-length", "5")
+                        .body(hyper::Body::from("hello"))
+                        .unwrap();
+                    Ok::<_, hyper::Error>(res)
+                }));
+
+            conn.without_shutdown()
+        });
+
+    let parts = fut.wait().unwrap();
+
+    // wait so that we don't write until other side saw the 200 response
+    rx.wait().unwrap();
+
+    let io = parts.io;
+    assert_eq!(parts.read_buf, "eagerly optimistic");
+
+    let io = write_all(io, b"foo=bar").wait().unwrap().0;
+    let vec = read_to_end(io, vec![]).wait().unwrap().1;
+    assert_eq!(vec, b"bar=foo");
+}
+
+#[test]
+fn connect_tunnel_unclaimed_is_closed() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            CONNECT hyper.rs:443 HTTP/1.1\r\n\
+            Host: hyper.rs:443\r\n\
+            \r\n\
+        ").expect("write 1");
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).expect("read 1");
+        tx.send(s(&buf).to_owned()).unwrap();
     });
 
     let fut = listener.incoming()
@@ -1197,10 +1575,1768 @@ fn illegal_request_length_returns_400_response() {
         .and_then(|(item, _incoming)| {
             let socket = item.unwrap();
             Http::new()
-                .serve_connection(socket, HelloWorld)
+                .serve_connection(socket, service_fn(|_| {
+                    Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                }))
         });
 
-    fut.wait().unwrap_err();
+    fut.wait().unwrap();
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn upgrade_unclaimed_is_refused() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Upgrade: foobar\r\n\
+            Connection: upgrade\r\n\
+            \r\n\
+        ").expect("write 1");
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).expect("read 1");
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let refused = Arc::new(Mutex::new(Vec::new()));
+    let refused2 = refused.clone();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| -> hyper::Error { unreachable!() })
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            // Polled as a `Future`, nothing can take the upgraded IO.
+            Http::new()
+                .http1_on_refused_upgrade(move |refusal, headers| {
+                    refused2.lock().unwrap().push((refusal, headers["upgrade"].clone()));
+                })
+                .serve_connection(socket, service_fn(|_| {
+                    let res = Response::builder()
+                        .status(101)
+                        .header("upgrade", "foobar")
+                        .body(hyper::Body::empty())
+                        .unwrap();
+                    Ok::<_, hyper::Error>(res)
+                }))
+        });
+
+    fut.wait().unwrap();
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 500 Internal Server Error\r\n"), "{:?}", resp);
+    assert!(resp.contains("\r\nconnection: close\r\n"), "{:?}", resp);
+    assert_eq!(*refused.lock().unwrap(), vec![(UpgradeRefusal::Unclaimed, "foobar".parse().unwrap())]);
+}
+
+#[test]
+fn http1_refuse_upgrades_keeps_serving() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET /ws HTTP/1.1\r\n\
+            Upgrade: foobar\r\n\
+            Connection: upgrade\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").expect("write 1");
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).expect("read 1");
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| -> hyper::Error { unreachable!() })
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_refuse_upgrades(true)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    let status = if req.uri().path() == "/ws" { 101 } else { 200 };
+                    let res = Response::builder()
+                        .status(status)
+                        .header("upgrade", "foobar")
+                        .body(hyper::Body::empty())
+                        .unwrap();
+                    Ok::<_, hyper::Error>(res)
+                }))
+                .without_shutdown()
+        });
+
+    // Even though the IO could be taken back, the upgrade is refused.
+    let parts = fut.wait().unwrap();
+    drop(parts);
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 500 Internal Server Error\r\n"), "{:?}", resp);
+    assert!(resp.contains("\r\n\r\nHTTP/1.1 200 OK\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn http2_connection_has_no_parts() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let _tcp = connect(&addr);
+
+    let (socket, _incoming) = listener.incoming()
+        .into_future()
+        .map_err(|(e, _)| e)
+        .wait()
+        .unwrap();
+    let conn = Http::new()
+        .http2_only(true)
+        .serve_connection(socket.unwrap(), service_fn(|_| {
+            Ok::<_, hyper::Error>(Response::new(Body::empty()))
+        }));
+    assert!(conn.into_parts().is_none());
+}
+
+// Serves a request for `/bad`, whose response has a header value with a
+// CRLF in it, and then one for `/good`, returning what the client read.
+//
+// `HeaderValue::from_shared_unchecked` checks the bytes when debug
+// assertions are enabled, so these only run without them, such as with
+// `cargo test --release`.
+#[cfg(not(debug_assertions))]
+fn serve_header_value_policy(policy: hyper::server::conn::HeaderValuePolicy) -> String {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET /bad HTTP/1.1\r\n\
+            \r\n\
+            GET /good HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_header_value_policy(policy)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    let mut res = Response::new(Body::empty());
+                    if req.uri().path() == "/bad" {
+                        let bad = hyper::Chunk::from("a\r\ninjected: 1").into_bytes();
+                        res.headers_mut().insert("x-bad", unsafe {
+                            HeaderValue::from_shared_unchecked(bad)
+                        });
+                    }
+                    Ok::<_, hyper::Error>(res)
+                }))
+        });
+
+    fut.wait().unwrap();
+    rx.recv().unwrap()
+}
+
+#[cfg(not(debug_assertions))]
+#[test]
+fn http1_header_value_policy_sanitize() {
+    let resp = serve_header_value_policy(hyper::server::conn::HeaderValuePolicy::Sanitize);
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(has_header(&resp, "x-bad: a  injected: 1"), "{:?}", resp);
+    assert!(!resp.contains("\r\ninjected"), "{:?}", resp);
+}
+
+#[cfg(not(debug_assertions))]
+#[test]
+fn http1_header_value_policy_reject_keeps_connection() {
+    let resp = serve_header_value_policy(hyper::server::conn::HeaderValuePolicy::Reject);
+    assert!(resp.starts_with("HTTP/1.1 500 Internal Server Error\r\n"), "{:?}", resp);
+    assert!(!resp.contains("injected"), "{:?}", resp);
+    assert!(resp.contains("\r\n\r\nHTTP/1.1 200 OK\r\n"), "{:?}", resp);
+}
+
+#[cfg(not(debug_assertions))]
+#[test]
+fn http1_header_value_policy_reject_and_close() {
+    let resp = serve_header_value_policy(hyper::server::conn::HeaderValuePolicy::RejectAndClose);
+    assert!(resp.starts_with("HTTP/1.1 500 Internal Server Error\r\n"), "{:?}", resp);
+    assert!(!resp.contains("injected"), "{:?}", resp);
+    // The connection was closed before the second request was read.
+    assert!(!resp.contains("200 OK"), "{:?}", resp);
+}
+
+#[test]
+fn http2_without_shutdown_fails() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let _tcp = connect(&addr);
+
+    let (socket, _incoming) = listener.incoming()
+        .into_future()
+        .map_err(|(e, _)| e)
+        .wait()
+        .unwrap();
+    let conn = Http::new()
+        .http2_only(true)
+        .serve_connection(socket.unwrap(), service_fn(|_| {
+            Ok::<_, hyper::Error>(Response::new(Body::empty()))
+        }));
+
+    // Fails without waiting for the connection to close.
+    let err = conn.without_shutdown().wait().unwrap_err();
+    assert!(err.is_user(), "{:?}", err);
+}
+
+#[cfg(feature = "http2")]
+#[test]
+fn http2_close_reason_goaway() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").expect("write preface");
+        // Empty SETTINGS.
+        tcp.write_all(b"\0\0\0\x04\0\0\0\0\0").expect("write settings");
+        // GOAWAY with ENHANCE_YOUR_CALM.
+        tcp.write_all(b"\0\0\x08\x07\0\0\0\0\0\0\0\0\0\0\0\0\x0b").expect("write goaway");
+        let mut buf = [0; 4096];
+        while tcp.read(&mut buf).unwrap_or(0) > 0 {}
+    });
+
+    let (socket, _incoming) = listener.incoming()
+        .into_future()
+        .map_err(|(e, _)| e)
+        .wait()
+        .unwrap();
+    let mut conn = Http::new()
+        .http2_only(true)
+        .serve_connection(socket.unwrap(), service_fn(|_| {
+            Ok::<_, hyper::Error>(Response::new(Body::empty()))
+        }));
+    assert!(conn.close_reason().is_none());
+
+    let err = future::poll_fn(|| conn.poll()).wait().unwrap_err();
+    assert_eq!(err.http2_error_code(), Some(0xb), "{:?}", err);
+    let reason = conn.close_reason().expect("close reason");
+    assert_eq!(reason.http2_error_code(), Some(0xb));
+}
+
+#[test]
+fn rejected_body_closes_connection() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            POST / HTTP/1.1\r\n\
+            Content-Length: 1000000\r\n\
+            \r\n\
+            0123456789\
+        ").unwrap();
+        // The rest of the body is never sent, yet the response arrives and
+        // the connection is closed.
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        let resp = s(&buf);
+        assert!(resp.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{:?}", resp);
+        assert!(has_header(resp, "connection: close"), "{:?}", resp);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    req.into_body().reject();
+                    let mut res = Response::new(Body::empty());
+                    *res.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+                    Ok::<_, hyper::Error>(res)
+                }))
+        });
+
+    fut.wait().unwrap();
+}
+
+#[test]
+fn http1_preserve_header_case() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            X-Legacy-ID: 1\r\n\
+            CONNECTION: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        let resp = s(&buf);
+        assert!(resp.contains("\r\nX-Legacy-ID: 1\r\n"), "{:?}", resp);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_preserve_header_case(true)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    let header_case = req.extensions()
+                        .get::<HeaderCaseMap>()
+                        .expect("HeaderCaseMap")
+                        .clone();
+                    assert_eq!(header_case.get(&hyper::header::CONNECTION), Some("CONNECTION"));
+                    // Echo the header back, in the casing it was received.
+                    let mut res = Response::new(Body::empty());
+                    res.headers_mut().insert("x-legacy-id", req.headers()["x-legacy-id"].clone());
+                    res.extensions_mut().insert(header_case);
+                    Ok::<_, hyper::Error>(res)
+                }))
+        });
+
+    fut.wait().unwrap();
+}
+
+#[test]
+fn http1_preserve_header_order() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            x-a: 1\r\n\
+            x-b: 2\r\n\
+            x-a: 3\r\n\
+            connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_preserve_header_order(true)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    let header_order = req.extensions()
+                        .get::<HeaderOrder>()
+                        .expect("HeaderOrder")
+                        .clone();
+                    assert_eq!(header_order.len(), 4);
+                    // Echo the headers back, in the order they were received.
+                    let mut res = Response::new(Body::empty());
+                    *res.headers_mut() = req.headers().clone();
+                    res.extensions_mut().insert(header_order);
+                    Ok::<_, hyper::Error>(res)
+                }))
+        });
+
+    fut.wait().unwrap();
+    let resp = rx.recv().unwrap();
+    assert!(resp.contains("\r\nx-a: 1\r\nx-b: 2\r\nx-a: 3\r\nconnection: close\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn http1_reason_phrase() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(|_req: Request<Body>| {
+                    let mut res = Response::new(Body::empty());
+                    res.extensions_mut().insert(ReasonPhrase::from_static("Alright Then"));
+                    Ok::<_, hyper::Error>(res)
+                }))
+        });
+
+    fut.wait().unwrap();
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 Alright Then\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn http1_legacy_keep_alive() {
+    fn serve(legacy_keep_alive: bool) -> String {
+        let runtime = Runtime::new().unwrap();
+        let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut tcp = connect(&addr);
+            tcp.write_all(b"\
+                GET /a HTTP/1.0\r\n\
+                Connection: keep-alive\r\n\
+                \r\n\
+                GET /b HTTP/1.0\r\n\
+                \r\n\
+            ").unwrap();
+            let mut buf = Vec::new();
+            tcp.read_to_end(&mut buf).unwrap();
+            tx.send(s(&buf).to_owned()).unwrap();
+        });
+
+        let fut = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(move |(item, _incoming)| {
+                let socket = item.unwrap();
+                Http::new()
+                    .http1_legacy_keep_alive(legacy_keep_alive)
+                    .serve_connection(socket, service_fn(|req: Request<Body>| {
+                        Ok::<_, hyper::Error>(Response::new(Body::from(req.uri().path().to_owned())))
+                    }))
+            });
+
+        fut.wait().unwrap();
+        rx.recv().unwrap()
+    }
+
+    let resp = serve(true);
+    assert!(resp.starts_with("HTTP/1.0 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.contains("\r\nconnection: keep-alive\r\n"), "{:?}", resp);
+    assert_eq!(resp.matches("HTTP/1.0 200 OK").count(), 2, "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\n/b"), "{:?}", resp);
+
+    let resp = serve(false);
+    assert!(!resp.contains("connection"), "{:?}", resp);
+    assert_eq!(resp.matches("HTTP/1.0 200 OK").count(), 1, "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\n/a"), "{:?}", resp);
+}
+
+#[test]
+fn http1_options_asterisk_form() {
+    fn serve(req: &'static [u8]) -> String {
+        let runtime = Runtime::new().unwrap();
+        let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut tcp = connect(&addr);
+            tcp.write_all(req).unwrap();
+            let mut buf = Vec::new();
+            tcp.read_to_end(&mut buf).unwrap();
+            tx.send(s(&buf).to_owned()).unwrap();
+        });
+
+        let fut = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(move |(item, _incoming)| {
+                let socket = item.unwrap();
+                Http::new()
+                    .serve_connection(socket, service_fn(|req: Request<Body>| {
+                        let body = format!("{} {}", req.method(), req.uri());
+                        Ok::<_, hyper::Error>(Response::new(Body::from(body)))
+                    }))
+            });
+
+        let _ = fut.wait();
+        rx.recv().unwrap()
+    }
+
+    let resp = serve(b"OPTIONS * HTTP/1.1\r\nHost: hyper.rs\r\nConnection: close\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\nOPTIONS *"), "{:?}", resp);
+
+    // Only OPTIONS is meant to be sent with it, but the service decides how
+    // to answer other methods.
+    let resp = serve(b"GET * HTTP/1.1\r\nHost: hyper.rs\r\nConnection: close\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\nGET *"), "{:?}", resp);
+}
+
+#[test]
+fn http1_chunk_extensions() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            Connection: close\r\n\
+            \r\n\
+            3;sig=abc\r\n\
+            foo\r\n\
+            3\r\n\
+            bar\r\n\
+            0;sig=end\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_chunk_extensions(true)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    req.into_body()
+                        .map(|chunk| {
+                            let ext = s(chunk.extensions().unwrap_or(b"-")).to_owned();
+                            format!("{}:{}", s(&chunk), ext)
+                        })
+                        .collect()
+                        .map(|seen| {
+                            let chunks = vec![
+                                hyper::Chunk::from(seen.join(",")).with_extensions("n=1"),
+                                hyper::Chunk::default().with_extensions("end=1"),
+                            ];
+                            Response::new(Body::wrap_stream(futures::stream::iter_ok::<_, hyper::Error>(chunks)))
+                        })
+                }))
+        });
+
+    fut.wait().unwrap();
+    let resp = rx.recv().unwrap();
+    assert!(resp.contains("\r\ntransfer-encoding: chunked\r\n"), "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\n1A;n=1\r\nfoo:sig=abc,bar:-,:sig=end\r\n0;end=1\r\n\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn http1_manual_continue() {
+    use hyper::server::conn::ExpectContinue;
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            POST /upload HTTP/1.1\r\n\
+            Expect: 100-continue\r\n\
+            Content-Length: 5\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let cont = b"HTTP/1.1 100 Continue\r\n\r\n";
+        let mut buf = vec![0; cont.len()];
+        tcp.read_exact(&mut buf).unwrap();
+        assert_eq!(s(&buf), s(cont));
+        tcp.write_all(b"hello").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+
+        // Rejected before the body is sent.
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            POST /too-large HTTP/1.1\r\n\
+            Expect: 100-continue\r\n\
+            Content-Length: 5000000\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .take(2)
+        .map_err(|_| unreachable!())
+        .for_each(|socket| {
+            Http::new()
+                .http1_auto_continue(false)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    let expect = req.extensions().get::<ExpectContinue>().cloned().expect("ExpectContinue");
+                    if req.uri().path() == "/too-large" {
+                        let res = Response::builder()
+                            .status(StatusCode::PAYLOAD_TOO_LARGE)
+                            .body(Body::empty())
+                            .unwrap();
+                        return Box::new(future::ok(res)) as Box<Future<Item=_, Error=_> + Send>;
+                    }
+                    expect.send_continue();
+                    Box::new(req.into_body()
+                        .concat2()
+                        .map(|body| Response::new(Body::from(body))))
+                }))
+        });
+
+    fut.wait().unwrap();
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\nhello"), "{:?}", resp);
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{:?}", resp);
+    assert!(!resp.contains("100 Continue"), "{:?}", resp);
+}
+
+#[test]
+fn http1_unknown_expectations_delegated() {
+    use hyper::server::conn::{Expectation, UnknownExpectations};
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Expect: x-ping\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            Http::new()
+                .http1_unknown_expectations(UnknownExpectations::Delegate)
+                .serve_connection(item.unwrap(), service_fn(|req: Request<Body>| {
+                    let expectation = req.extensions().get::<Expectation>().expect("Expectation");
+                    assert_eq!(expectation.values(), ["x-ping"]);
+                    // interim responses aren't enabled for every request
+                    assert!(req.extensions().get::<hyper::server::conn::Informational>().is_none());
+                    let pong = Response::builder()
+                        .status(StatusCode::PROCESSING)
+                        .header("x-pong", "1")
+                        .body(())
+                        .unwrap();
+                    expectation.send_interim(pong).unwrap();
+                    Ok::<_, hyper::Error>(Response::new(Body::from("pinged")))
+                }))
+        });
+
+    fut.wait().unwrap();
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 102 Processing\r\nx-pong: 1\r\n\r\nHTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\npinged"), "{:?}", resp);
+}
+
+#[test]
+fn http1_unknown_expectations_rejected() {
+    use hyper::server::conn::UnknownExpectations;
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Expect: 100-continue, x-ping\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            Http::new()
+                .http1_unknown_expectations(UnknownExpectations::Reject)
+                .serve_connection(item.unwrap(), service_fn(|_req: Request<Body>| -> Result<Response<Body>, hyper::Error> {
+                    panic!("service called with unknown expectation");
+                }))
+        });
+
+    fut.wait().unwrap();
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 417 Expectation Failed\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn http1_informational_responses() {
+    use std::sync::{Arc, Mutex};
+    use hyper::server::conn::Informational;
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let kept = Arc::new(Mutex::new(None));
+    let kept2 = kept.clone();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_informational_responses(true)
+                .serve_connection(socket, service_fn(move |req: Request<Body>| {
+                    let informational = req.extensions().get::<Informational>().cloned().expect("Informational");
+                    let hints = Response::builder()
+                        .status(103)
+                        .header("link", "</style.css>; rel=preload; as=style")
+                        .header("content-length", "5")
+                        .body(())
+                        .unwrap();
+                    informational.send(hints).unwrap();
+                    let err = informational.send(Response::new(())).unwrap_err();
+                    assert!(err.is_user(), "{:?}", err);
+                    *kept2.lock().unwrap() = Some(informational.clone());
+                    future::lazy(move || {
+                        let processing = Response::builder()
+                            .status(102)
+                            .body(())
+                            .unwrap();
+                        informational.send(processing).unwrap();
+                        future::ok::<_, hyper::Error>(Response::new(Body::from("hello")))
+                    })
+                }))
+        });
+
+    fut.wait().unwrap();
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("\
+        HTTP/1.1 103 Early Hints\r\n\
+        link: </style.css>; rel=preload; as=style\r\n\
+        \r\n\
+        HTTP/1.1 102 Processing\r\n\
+        \r\n\
+        HTTP/1.1 200 OK\r\n\
+    "), "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\nhello"), "{:?}", resp);
+
+    let informational = kept.lock().unwrap().take().unwrap();
+    let err = informational.send(Response::builder().status(103).body(()).unwrap()).unwrap_err();
+    assert!(err.is_closed(), "{:?}", err);
+}
+
+#[test]
+fn http1_half_close_writes_response() {
+    use hyper::server::conn::HalfClose;
+
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            \r\n\
+        ").unwrap();
+        tcp.shutdown(Shutdown::Write).expect("shutdown write");
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(move |req: Request<Body>| {
+                    let half_close = req.extensions().get::<HalfClose>().cloned().expect("HalfClose");
+                    assert!(!half_close.is_closed());
+                    // Only respond once the client's FIN was noticed.
+                    future::poll_fn(move || {
+                        Ok::<_, hyper::Error>(half_close.poll_closed().map(|()| {
+                            Response::new(Body::from("half closed"))
+                        }))
+                    })
+                }))
+        });
+
+    fut.wait().unwrap();
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\nhalf closed"), "{:?}", resp);
+}
+
+#[test]
+fn http1_h2c_upgrade_serves_request_as_stream_1() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            Http::new()
+                .http1_h2c_upgrade(true)
+                .serve_connection(item.unwrap(), service_fn(|req: Request<Body>| {
+                    assert_eq!(req.version(), hyper::Version::HTTP_2);
+                    assert!(req.headers().get("upgrade").is_none());
+                    let body = format!("{} {}", req.uri().path(), req.uri().host().unwrap_or(""));
+                    Ok::<_, hyper::Error>(Response::new(Body::from(body)))
+                }))
+        })
+        .map_err(|e| panic!("server error: {}", e));
+    runtime.executor().spawn(server);
+
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"\
+        GET /upgrade HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: Upgrade, HTTP2-Settings\r\n\
+        Upgrade: h2c\r\n\
+        HTTP2-Settings: AAIAAAAA\r\n\
+        \r\n\
+    ").unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0; 1];
+        tcp.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    let head = s(&head);
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"), "{:?}", head);
+    assert!(has_header(head, "upgrade: h2c"), "{:?}", head);
+
+    // The preface, and an empty SETTINGS.
+    tcp.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0").unwrap();
+    let mut data = Vec::new();
+    loop {
+        let mut frame_head = [0; 9];
+        tcp.read_exact(&mut frame_head).unwrap();
+        let len = (frame_head[0] as usize) << 16 | (frame_head[1] as usize) << 8 | frame_head[2] as usize;
+        let mut payload = vec![0; len];
+        tcp.read_exact(&mut payload).unwrap();
+        let stream = frame_head[8];
+        // DATA on stream 1, until END_STREAM.
+        if frame_head[3] == 0x0 && stream == 1 {
+            data.extend_from_slice(&payload);
+            if frame_head[4] & 0x1 != 0 {
+                break;
+            }
+        }
+    }
+    assert_eq!(s(&data), "/upgrade example.domain");
+}
+
+#[test]
+fn http1_h2c_upgrade_applies_http2_settings() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            Http::new()
+                .http1_h2c_upgrade(true)
+                .serve_connection(item.unwrap(), service_fn(|_req: Request<Body>| {
+                    Ok::<_, hyper::Error>(Response::new(Body::from("hello world")))
+                }))
+        })
+        .map_err(|e| panic!("server error: {}", e));
+    runtime.executor().spawn(server);
+
+    // SETTINGS_INITIAL_WINDOW_SIZE = 4
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: Upgrade, HTTP2-Settings\r\n\
+        Upgrade: h2c\r\n\
+        HTTP2-Settings: AAQAAAAE\r\n\
+        \r\n\
+    ").unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0; 1];
+        tcp.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    assert!(s(&head).starts_with("HTTP/1.1 101 Switching Protocols\r\n"), "{:?}", s(&head));
+
+    // The preface, and an empty SETTINGS, so the window is only from
+    // `HTTP2-Settings`.
+    tcp.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0").unwrap();
+    loop {
+        let mut frame_head = [0; 9];
+        tcp.read_exact(&mut frame_head).unwrap();
+        let len = (frame_head[0] as usize) << 16 | (frame_head[1] as usize) << 8 | frame_head[2] as usize;
+        let mut payload = vec![0; len];
+        tcp.read_exact(&mut payload).unwrap();
+        if frame_head[3] == 0x0 && frame_head[8] == 1 {
+            assert_eq!(s(&payload), "hell");
+            assert_eq!(frame_head[4] & 0x1, 0, "END_STREAM");
+            break;
+        }
+    }
+}
+
+#[test]
+fn http1_h2c_upgrade_disabled_serves_http1() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Connection: Upgrade, HTTP2-Settings, close\r\n\
+            Upgrade: h2c\r\n\
+            HTTP2-Settings: AAIAAAAA\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            Http::new()
+                .serve_connection(item.unwrap(), service_fn(|req: Request<Body>| {
+                    assert_eq!(req.version(), hyper::Version::HTTP_11);
+                    Ok::<_, hyper::Error>(Response::new(Body::from("http/1.1")))
+                }))
+        });
+
+    fut.wait().unwrap();
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\nhttp/1.1"), "{:?}", resp);
+}
+
+#[test]
+fn http2_auto_waits_for_whole_preface() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            Http::new()
+                .serve_connection(item.unwrap(), service_fn(|_req: Request<Body>| {
+                    Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                }))
+        })
+        .map_err(|e| panic!("server error: {}", e));
+    runtime.executor().spawn(server);
+
+    // The first line alone fails to parse as HTTP/1.
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"PRI * HTTP/2.0\r\n").unwrap();
+    thread::sleep(Duration::from_millis(50));
+    tcp.write_all(b"\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0").unwrap();
+
+    // The server's SETTINGS.
+    let mut frame_head = [0; 9];
+    tcp.read_exact(&mut frame_head).unwrap();
+    assert_eq!(frame_head[3], 0x4, "{:?}", frame_head);
+}
+
+#[test]
+fn http2_auto_disabled_refuses_preface() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0").unwrap();
+        let mut buf = [0; 256];
+        let n = tcp.read(&mut buf).unwrap();
+        tx.send(s(&buf[..n]).to_string()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            Http::new()
+                .http2_auto(false)
+                .serve_connection(item.unwrap(), service_fn(|_req: Request<Body>| {
+                    Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                }))
+        });
+
+    fut.wait().unwrap_err();
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn http1_half_close_disabled_closes_connection() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            \r\n\
+        ").unwrap();
+        tcp.shutdown(Shutdown::Write).expect("shutdown write");
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(buf).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_half_close(false)
+                .serve_connection(socket, service_fn(|_req: Request<Body>| {
+                    future::empty::<Response<Body>, hyper::Error>()
+                }))
+        });
+
+    let err = fut.wait().unwrap_err();
+    assert_eq!(err.to_string(), "message is incomplete");
+    assert_eq!(rx.recv().unwrap(), b"");
+}
+
+#[test]
+fn http1_lenient_parsing() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET /a file.txt HTTP/1.1\r\n\
+            x-folded: one\r\n \
+            two\r\n\
+            connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_allow_obsolete_line_folding(true)
+                .http1_allow_spaces_in_request_target(true)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    let body = format!(
+                        "{} {:?}",
+                        req.uri(),
+                        req.headers()["x-folded"],
+                    );
+                    Ok::<_, hyper::Error>(Response::new(Body::from(body)))
+                }))
+        });
+
+    fut.wait().unwrap();
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\n/a%20file.txt \"one   two\""), "{:?}", resp);
+}
+
+#[test]
+fn http1_max_headers() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            x-a: 1\r\n\
+            x-b: 2\r\n\
+            x-c: 3\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        tx.send(s(&buf).to_owned()).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_max_headers(2)
+                .serve_connection(socket, service_fn(|_req: Request<Body>| {
+                    Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                }))
+        });
+
+    let err = fut.wait().unwrap_err();
+    assert!(err.is_parse_too_large(), "{:?}", err);
+    let resp = rx.recv().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 431 "), "{:?}", resp);
+}
+
+#[test]
+fn http2_rejected_body_keeps_connection_flowing() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (hold_tx, hold_rx) = mpsc::channel();
+    let hold_tx = Mutex::new(hold_tx);
+    let server = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            Http::new()
+                .http2_only(true)
+                .serve_connection(item.unwrap(), service_fn(move |req: Request<Body>| {
+                    if req.uri().path() == "/reject" {
+                        req.into_body().reject();
+                        // The response stays open, so the stream does too.
+                        let (body_tx, body) = Body::channel();
+                        hold_tx.lock().unwrap().send(body_tx).unwrap();
+                        let mut res = Response::new(body);
+                        *res.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+                        Either::A(future::ok(res))
+                    } else {
+                        Either::B(req.into_body()
+                            .concat2()
+                            .map(|body| Response::new(Body::from(body.len().to_string()))))
+                    }
+                }))
+        })
+        .map_err(|e| panic!("server error: {}", e));
+    runtime.executor().spawn(server);
+
+    let client = Client::builder()
+        .http2_only(true)
+        .executor(runtime.executor())
+        .build_http::<Body>();
+    let post = |path, len| {
+        let mut req = Request::new(Body::from(vec![0; len]));
+        *req.method_mut() = hyper::Method::POST;
+        *req.uri_mut() = format!("http://{}{}", addr, path).parse().unwrap();
+        client.request(req)
+    };
+
+    // More than the connection's whole flow control window is sent for the
+    // rejected body, which would stall the other streams if it were kept.
+    let rejected = post("/reject", 1_000_000).wait().unwrap();
+    assert_eq!(rejected.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let _body_tx = hold_rx.recv().unwrap();
+
+    let res = post("/echo", 200_000)
+        .and_then(|res| res.into_body().concat2())
+        .wait()
+        .unwrap();
+    assert_eq!(s(&res), "200000");
+}
+
+#[test]
+fn http2_max_pending_streams_holds_back_streams() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Requests to `/slow` are answered once `slow_tx` is sent on.
+    let calls = Arc::new(AtomicUsize::new(0));
+    let (slow_tx, slow_rx) = oneshot::channel::<()>();
+    let slow_rx = Mutex::new(Some(slow_rx));
+    let server_calls = calls.clone();
+    let server = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            Http::new()
+                .http2_only(true)
+                .http2_max_pending_streams(1)
+                .serve_connection(item.unwrap(), service_fn(move |req: Request<Body>| {
+                    server_calls.fetch_add(1, Ordering::SeqCst);
+                    if req.uri().path() == "/slow" {
+                        let slow_rx = slow_rx.lock().unwrap().take().expect("one slow request");
+                        Either::A(slow_rx
+                            .map(|()| Response::new(Body::from("slow")))
+                            .map_err(|_| "slow_tx dropped"))
+                    } else {
+                        Either::B(future::ok(Response::new(Body::from("fast"))))
+                    }
+                }))
+        })
+        .map_err(|e| panic!("server error: {}", e));
+    runtime.executor().spawn(server);
+
+    let client = Client::builder()
+        .http2_only(true)
+        .executor(runtime.executor())
+        .build_http::<Body>();
+    let get = |path| {
+        let uri = format!("http://{}{}", addr, path).parse::<hyper::Uri>().unwrap();
+        client.get(uri).and_then(|res| res.into_body().concat2())
+    };
+
+    let slow = get("/slow");
+    let (slow_done_tx, slow_done_rx) = mpsc::channel();
+    runtime.executor().spawn(slow.then(move |res| {
+        slow_done_tx.send(res.map(|body| body.to_vec())).unwrap();
+        Ok(())
+    }));
+    while calls.load(Ordering::SeqCst) == 0 {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // The fast request is held back by h2 until the slow one is answered.
+    let (fast_done_tx, fast_done_rx) = mpsc::channel();
+    runtime.executor().spawn(get("/fast").then(move |res| {
+        fast_done_tx.send(res.map(|body| body.to_vec())).unwrap();
+        Ok(())
+    }));
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert!(fast_done_rx.try_recv().is_err(), "fast request answered early");
+
+    slow_tx.send(()).unwrap();
+    assert_eq!(s(&slow_done_rx.recv().unwrap().unwrap()), "slow");
+    assert_eq!(s(&fast_done_rx.recv().unwrap().unwrap()), "fast");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn parse_errors_send_4xx_response() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GE T / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 400 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, HelloWorld)
+        });
+
+    fut.wait().unwrap_err();
+}
+
+#[test]
+fn illegal_request_length_returns_400_response() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"POST / HTTP/1.1\r\nContent-Length: foo\r\n\r\n").unwrap();
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 400 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, HelloWorld)
+        });
+
+    fut.wait().unwrap_err();
+}
+
+#[test]
+fn request_id_is_echoed_in_response() {
+    use hyper::server::conn::RequestId;
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            X-Request-Id: abc-123\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        let resp = s(&buf).to_lowercase();
+        assert!(resp.contains("x-request-id: abc-123\r\n"), "{:?}", resp);
+        assert!(resp.ends_with("\r\n\r\nabc-123"), "{:?}", resp);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .request_id(true)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    let id = req.extensions()
+                        .get::<RequestId>()
+                        .expect("request id extension")
+                        .to_string();
+                    Ok::<_, hyper::Error>(Response::new(Body::from(id)))
+                }))
+        });
+
+    fut.wait().unwrap();
+}
+
+#[test]
+fn shared_config_applies_to_new_connections() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+
+    let mut builder = hyper::Server::bind(&"127.0.0.1:0".parse().unwrap());
+    let config = builder.shared();
+    let server = builder
+        .serve(|| hyper::service::service_fn_ok(|_req| Response::new(Body::empty())));
+    let addr = server.local_addr();
+    runtime.executor().spawn(server.map_err(|e| panic!("server error: {}", e)));
+
+    let get = || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            X-Request-Id: abc-123\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        s(&buf).to_lowercase()
+    };
+
+    let resp = get();
+    assert!(!resp.contains("x-request-id"), "{:?}", resp);
+
+    config.update(|http| {
+        http.request_id(true);
+        // connections still start, without the change
+        let resp = get();
+        assert!(!resp.contains("x-request-id"), "{:?}", resp);
+    });
+    let resp = get();
+    assert!(resp.contains("x-request-id: abc-123\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn method_policy_rejects_before_service() {
+    use hyper::server::conn::MethodPolicy;
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            TRACE / HTTP/1.1\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        let resp = s(&buf);
+        assert!(resp.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{:?}", resp);
+        assert!(resp.contains("\r\n\r\nHTTP/1.1 200 OK\r\n"), "{:?}", resp);
+        assert!(resp.ends_with("\r\n\r\nGET"), "{:?}", resp);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .method_policy(MethodPolicy::deny_trace())
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    assert_ne!(req.method(), &hyper::Method::TRACE);
+                    Ok::<_, hyper::Error>(Response::new(Body::from(req.method().to_string())))
+                }))
+        });
+
+    fut.wait().unwrap();
+}
+
+#[test]
+fn host_policy_rejects_before_service() {
+    use hyper::server::conn::HostPolicy;
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+            Host: attacker.example\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+            Host: Example.com:8080\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        let resp = s(&buf);
+        assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{:?}", resp);
+        assert!(resp.contains("\r\n\r\nHTTP/1.1 421 Misdirected Request\r\n"), "{:?}", resp);
+        assert!(resp.contains("\r\n\r\nHTTP/1.1 200 OK\r\n"), "{:?}", resp);
+        assert!(resp.ends_with("\r\n\r\nExample.com:8080"), "{:?}", resp);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .host_policy(HostPolicy::allow(vec!["example.com"]))
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    let host = req.headers()["host"].to_str().unwrap().to_owned();
+                    Ok::<_, hyper::Error>(Response::new(Body::from(host)))
+                }))
+        });
+
+    fut.wait().unwrap();
+}
+
+#[test]
+fn write_timeout_closes_stalled_connection() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").unwrap();
+        // never read the response, until the server gave up on it
+        let _ = done_rx.recv();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .write_timeout(Some(Duration::from_millis(100)))
+                .serve_connection(socket, service_fn(|_req| {
+                    // more than the socket buffers can take
+                    let body = vec![b'x'; 32 * 1024 * 1024];
+                    Ok::<_, hyper::Error>(Response::new(Body::from(body)))
+                }))
+        });
+
+    let (tx, rx) = oneshot::channel();
+    runtime.executor().spawn(fut.then(move |res| {
+        let _ = tx.send(res);
+        Ok(())
+    }));
+    let err = rx.wait().unwrap().unwrap_err();
+    assert!(err.is_write_timeout(), "{:?}", err);
+    drop(done_tx);
+}
+
+#[test]
+fn service_error_status_keeps_connection() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET /fail HTTP/1.1\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        let resp = s(&buf);
+        assert!(resp.starts_with("HTTP/1.1 500 Internal Server Error\r\n"), "{:?}", resp);
+        assert!(resp.contains("\r\n\r\nHTTP/1.1 200 OK\r\n"), "{:?}", resp);
+        assert!(resp.ends_with("\r\n\r\nhello"), "{:?}", resp);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .service_error_status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    if req.uri().path() == "/fail" {
+                        Err("service failed")
+                    } else {
+                        Ok(Response::new(Body::from("hello")))
+                    }
+                }))
+        });
+
+    fut.wait().unwrap();
+}
+
+#[test]
+fn cancel_token_aborts_connection() {
+    use hyper::server::conn::CancelToken;
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        assert_eq!(s(&buf), "");
+    });
+
+    let token = CancelToken::new();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .cancel_token(token)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    // The service evicts its own connection, and never
+                    // answers.
+                    req.extensions()
+                        .get::<CancelToken>()
+                        .expect("cancel token extension")
+                        .cancel();
+                    future::empty::<Response<Body>, hyper::Error>()
+                }))
+        });
+
+    fut.wait().unwrap();
+}
+
+#[test]
+fn connection_stats_in_request_extensions() {
+    use hyper::server::conn::{ConnectionStats, ExchangeStats};
+
+    const REQ: &'static [u8] = b"\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ";
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(REQ).unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        assert!(s(&buf).ends_with("\r\n\r\nhello"), "{:?}", s(&buf));
+    });
+
+    let stats = Arc::new(Mutex::new(None));
+    let stats2 = stats.clone();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(move |req: Request<Body>| {
+                    let conn = req.extensions().get::<ConnectionStats>().cloned()
+                        .expect("connection stats extension");
+                    let exchange = req.extensions().get::<ExchangeStats>().cloned()
+                        .expect("exchange stats extension");
+                    *stats2.lock().unwrap() = Some((conn, exchange));
+                    future::ok::<_, hyper::Error>(Response::new(Body::from("hello")))
+                }))
+        });
+
+    fut.wait().unwrap();
+
+    let (conn, exchange) = stats.lock().unwrap().take().unwrap();
+    assert_eq!(conn.requests(), 1);
+    assert_eq!(conn.bytes_read(), REQ.len());
+    assert_eq!(exchange.bytes_read(), REQ.len());
+    assert!(conn.bytes_written() > "hello".len());
+    assert_eq!(exchange.bytes_written(), conn.bytes_written());
+}
+
+#[test]
+fn max_chunk_size_splits_request_body() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            POST / HTTP/1.1\r\n\
+            Content-Length: 10\r\n\
+            Connection: close\r\n\
+            \r\n\
+            0123456789\
+        ").unwrap();
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).unwrap();
+        let resp = s(&buf);
+        assert!(resp.ends_with("\r\n\r\n4,4,2"), "{:?}", resp);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .max_chunk_size(4)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    req.into_body()
+                        .map(|chunk| chunk.len().to_string())
+                        .collect()
+                        .map(|lens| Response::new(Body::from(lens.join(","))))
+                }))
+        });
+
+    fut.wait().unwrap();
 }
 
 #[test]